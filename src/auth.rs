@@ -0,0 +1,859 @@
+//! Checks for the `Authorization` header cargo sends along with its requests.
+//!
+//! Cargo treats a `401 Unauthorized` carrying a `WWW-Authenticate: Cargo`
+//! challenge as a signal that it should (re)send credentials, and will point
+//! the user at the `login_url` given in the challenge if it has none.
+//!
+//! <https://doc.rust-lang.org/cargo/reference/registry-web-api.html#authentication>
+
+use crate::database::{self, Token};
+use crate::errors::EstuaryError;
+use crate::Settings;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
+use serde_json::json;
+
+pub mod asymmetric;
+pub mod backend;
+pub mod oidc;
+pub mod trusted;
+
+/// The cookie holding someone's web UI session id.
+pub const SESSION_COOKIE: &str = "estuary_session";
+
+/// How long (in seconds) a web UI session lasts.
+pub const SESSION_MAX_AGE: i64 = 12 * 60 * 60;
+
+/**
+ * Compare two objects for equality without revealing information about the objects (other than size) through timing sidechannels.
+ */
+pub(crate) trait SecureEq {
+    /**
+     * Compare 2 objects for equality.
+     */
+    fn secure_eq(&self, other: &Self) -> bool;
+}
+
+impl SecureEq for &str {
+    fn secure_eq(&self, other: &Self) -> bool {
+        // Revealing length is okay.
+        if self.len() != other.len() {
+            return false;
+        }
+
+        self.bytes()
+            .zip(other.bytes())
+            .fold(true, |x, (a, b)| x && a == b)
+    }
+}
+
+/// The kinds of operation a token can be allowed to perform.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// Fetching the index, downloading crates, and searching (only checked
+    /// when auth is required for reads).
+    Read,
+    /// Publishing new crates and versions.
+    Publish,
+    /// Yanking and unyanking versions.
+    Yank,
+    /// Changing who owns a crate.
+    Owners,
+    /// Managing the registry itself, like revoking other tokens. Not given
+    /// out unless asked for.
+    Admin,
+}
+
+impl Scope {
+    /// Every scope.
+    pub const ALL: &'static [Scope] = &[
+        Scope::Read,
+        Scope::Publish,
+        Scope::Yank,
+        Scope::Owners,
+        Scope::Admin,
+    ];
+
+    /// What tokens are given unless they ask for something else: every scope
+    /// but `admin`.
+    pub const DEFAULT: &'static [Scope] =
+        &[Scope::Read, Scope::Publish, Scope::Yank, Scope::Owners];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Publish => "publish",
+            Self::Yank => "yank",
+            Self::Owners => "owners",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::ALL
+            .iter()
+            .find(|scope| scope.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown token scope `{}`", s))
+    }
+}
+
+/// The parts of the site that can be put behind HTTP Basic auth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BasicAuthArea {
+    /// The web UI, including the `/me` page.
+    Web,
+    /// The git index.
+    Git,
+}
+
+impl std::str::FromStr for BasicAuthArea {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "web" => Ok(Self::Web),
+            "git" => Ok(Self::Git),
+            _ => Err(format!("unknown basic auth area `{}`", s)),
+        }
+    }
+}
+
+/// Hash a user's password for storage, as an argon2 PHC string.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+
+    let salt = SaltString::generate(&mut OsRng);
+    argon2::Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    use argon2::password_hash::{PasswordHash, PasswordVerifier};
+
+    PasswordHash::new(hash)
+        .and_then(|hash| argon2::Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+/// Make up a random string that's hard enough to guess to use as a secret.
+pub fn generate_secret() -> String {
+    use rand::distributions::Alphanumeric;
+    use rand::Rng;
+
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Make up a new token for issuing to someone.
+pub fn generate_token() -> String {
+    format!("est{}", generate_secret())
+}
+
+/// The reasons a request can fail to authenticate.
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    /// No `Authorization` header was sent.
+    Missing,
+    /// A token was sent, but it isn't one we accept.
+    Invalid,
+    /// The token is valid, but isn't allowed to do this.
+    MissingScope(Scope),
+    /// The token store couldn't be read, so we can't say either way.
+    Unavailable,
+    /// A CI job's ID token was valid, but no trusted publisher rule allows it
+    /// to do this.
+    NotTrusted(String),
+    /// The token is valid, but hasn't been granted access to this crate.
+    NotGranted(String),
+    /// The token's user doesn't own this crate.
+    NotOwner(String),
+    /// Nobody owns this crate yet, but it's been reserved for someone else.
+    Reserved(String),
+}
+
+impl AuthError {
+    fn detail(&self) -> String {
+        match self {
+            Self::Missing => "This registry requires a token. Try `cargo login`.".to_string(),
+            Self::Invalid => "The supplied token is not valid for this registry.".to_string(),
+            Self::MissingScope(scope) => format!(
+                "The supplied token does not have the `{}` scope.",
+                scope.as_str()
+            ),
+            Self::Unavailable => "Unable to check tokens right now. Try again later.".to_string(),
+            Self::NotTrusted(reason) => reason.clone(),
+            Self::NotGranted(crate_name) => format!(
+                "The supplied token has not been granted access to `{}`.",
+                crate_name
+            ),
+            // Worded like crates.io, which cargo users will recognise.
+            Self::NotOwner(crate_name) => format!(
+                "crate `{}` exists, but you don't seem to be an owner.",
+                crate_name
+            ),
+            Self::Reserved(crate_name) => format!(
+                "The crate name `{}` is reserved. Ask the registry's admins if it should be yours.",
+                crate_name
+            ),
+        }
+    }
+}
+
+/// The raw `Authorization` header value.
+fn get_authorization(request: &HttpRequest) -> Result<&str, AuthError> {
+    match request.headers().get(header::AUTHORIZATION) {
+        Some(value) => value.to_str().map_err(|_| AuthError::Invalid),
+        None => Err(AuthError::Missing),
+    }
+}
+
+/// Decode the username and password from a `Basic` `Authorization` header.
+///
+/// Gives `None` for other kinds of `Authorization` header.
+fn get_basic_credentials(value: &str) -> Result<Option<(String, String)>, AuthError> {
+    match value.strip_prefix("Basic ") {
+        Some(credentials) => {
+            let decoded = base64::decode(credentials.trim()).map_err(|_| AuthError::Invalid)?;
+            let decoded = String::from_utf8(decoded).map_err(|_| AuthError::Invalid)?;
+            let (user, password) = decoded.split_once(':').ok_or(AuthError::Invalid)?;
+            Ok(Some((user.to_string(), password.to_string())))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Pull the token out of the `Authorization` header, if there is one.
+///
+/// Cargo sends the token as the whole header value. Git clients and browsers
+/// can only do HTTP Basic auth, so for those the token is taken from the
+/// password (the username is ignored).
+fn get_token(request: &HttpRequest) -> Result<String, AuthError> {
+    let value = get_authorization(request)?;
+    match get_basic_credentials(value)? {
+        Some((_user, password)) => Ok(password),
+        None => Ok(value.to_string()),
+    }
+}
+
+/// What's needed from a request to check the token it was sent with, taken
+/// out of it so the check can be made on the blocking thread pool.
+struct Sent {
+    token: Result<String, AuthError>,
+    expected: asymmetric::Expected,
+    /// Whether anything goes before the first token is issued, which it
+    /// never does once auth is required.
+    open_until_tokens: bool,
+}
+
+impl Sent {
+    fn new(request: &HttpRequest, settings: &Settings) -> Sent {
+        Sent {
+            token: get_token(request),
+            expected: asymmetric::Expected::new(request, settings),
+            open_until_tokens: !settings.auth_required,
+        }
+    }
+}
+
+/// Validate the token sent with the request against the tokens in the
+/// database, and make sure it carries the `required` scope.
+///
+/// The token may either be a secret we issued, or an asymmetric token signed
+/// by a registered key (see [`asymmetric`]).
+///
+/// Until the first token has been issued, every request is allowed (unless
+/// auth is required). Revoking every token doesn't allow them again.
+pub async fn check(
+    request: &HttpRequest,
+    settings: &Settings,
+    required: Scope,
+) -> Result<(), AuthError> {
+    let sent = Sent::new(request, settings);
+    with_db(settings, move |conn| authenticate(sent, conn, required))
+        .await
+        .map(|_| ())
+}
+
+/// Like [`check`], but the token must also have been granted access to
+/// `crate_name`, once any grants have been made (see `estuary grant`).
+///
+/// Tokens issued to a user must also belong to one of the crate's owners,
+/// once it has any, unless they carry the `admin` scope (or the user is an
+/// admin). Tokens issued from the shell aren't tied to anyone, so are only
+/// limited by grants.
+///
+/// Until the crate has owners, its name may have been reserved for someone
+/// else (see `estuary reserve`), which only admins can get past.
+///
+/// Gives back the token, like [`authorize_token`].
+pub async fn check_crate(
+    request: &HttpRequest,
+    settings: &Settings,
+    required: Scope,
+    crate_name: &str,
+    version: Option<&str>,
+) -> Result<Option<Token>, AuthError> {
+    let mut sent = Sent::new(request, settings);
+    sent.expected = sent.expected.for_crate(crate_name, version);
+    let crate_name = crate_name.to_string();
+    with_db(settings, move |conn| {
+        check_crate_token(sent, conn, required, &crate_name)
+    })
+    .await
+}
+
+fn check_crate_token(
+    sent: Sent,
+    conn: &database::Connection,
+    required: Scope,
+    crate_name: &str,
+) -> Result<Option<Token>, AuthError> {
+    let token = match authenticate(sent, conn, required)? {
+        Some(token) => token,
+        None => return Ok(None),
+    };
+    if database::count_grants(conn).map_err(|e| unavailable(e.into()))? > 0
+        && !database::is_granted(conn, &token, crate_name).map_err(|e| unavailable(e.into()))?
+    {
+        return Err(AuthError::NotGranted(crate_name.to_string()));
+    }
+    // Admins (and `admin` tokens) act on any crate, but only with the scopes
+    // their token was issued with.
+    let is_admin = match token.user_id {
+        Some(user_id) => database::is_admin(conn, user_id).map_err(|e| unavailable(e.into()))?,
+        None => false,
+    };
+    if is_admin || token.scopes.contains(&Scope::Admin) {
+        return Ok(Some(token));
+    }
+    if database::has_owners(conn, crate_name).map_err(|e| unavailable(e.into()))? {
+        if let Some(user_id) = token.user_id {
+            if !database::is_crate_owner(conn, crate_name, user_id)
+                .map_err(|e| unavailable(e.into()))?
+            {
+                return Err(AuthError::NotOwner(crate_name.to_string()));
+            }
+        }
+    } else if database::is_reserved(conn, crate_name, token.user_id)
+        .map_err(|e| unavailable(e.into()))?
+    {
+        return Err(AuthError::Reserved(crate_name.to_string()));
+    }
+    Ok(Some(token))
+}
+
+fn unavailable(e: EstuaryError) -> AuthError {
+    log::error!("Failed to read tokens: {}", e);
+    AuthError::Unavailable
+}
+
+/// Run a check against the database with [`Settings::with_db`].
+async fn with_db<T, F>(settings: &Settings, f: F) -> Result<T, AuthError>
+where
+    F: FnOnce(&database::Connection) -> Result<T, AuthError> + Send + 'static,
+    T: Send + 'static,
+{
+    settings
+        .with_db(move |conn| Ok::<_, EstuaryError>(f(conn)))
+        .await
+        .map_err(unavailable)?
+}
+
+/// The token the request was made with, or `None` when no token has been
+/// issued yet, so anyone is allowed.
+fn authenticate(
+    sent: Sent,
+    conn: &database::Connection,
+    required: Scope,
+) -> Result<Option<Token>, AuthError> {
+    if sent.open_until_tokens
+        && !database::tokens_issued(conn).map_err(|e| unavailable(e.into()))?
+    {
+        return Ok(None);
+    }
+    check_token(sent, conn, required).map(Some)
+}
+
+/// Like [`check`], but with no exception for when there are no tokens yet.
+fn check_token(
+    sent: Sent,
+    conn: &database::Connection,
+    required: Scope,
+) -> Result<Token, AuthError> {
+    let token = sent.token?;
+    let found = if asymmetric::is_asymmetric(&token) {
+        asymmetric::verify(&token, &sent.expected, conn, required)?
+    } else {
+        find_secret_token(conn, &token).map_err(|e| unavailable(e.into()))??
+    };
+    if !found.scopes.contains(&required) {
+        return Err(AuthError::MissingScope(required));
+    }
+    log::debug!("Request authorized by token `{}`", found.name);
+    Ok(found)
+}
+
+/// Find the token matching a secret sent by a client.
+fn find_secret_token(
+    conn: &database::Connection,
+    sent: &str,
+) -> Result<Result<Token, AuthError>, database::Error> {
+    let sent = database::hash_token(sent);
+    // Check every token, even after finding a match, so the time taken
+    // doesn't hint at which (if any) token was close.
+    Ok(database::list_token_hashes(conn)?
+        .into_iter()
+        .fold(None, |found, (token, hash)| {
+            if hash.as_str().secure_eq(&sent.as_str()) {
+                Some(token)
+            } else {
+                found
+            }
+        })
+        .ok_or(AuthError::Invalid))
+}
+
+/// Build the `401` response cargo expects when credentials are missing or bad.
+pub fn challenge(err: &AuthError, settings: &Settings) -> HttpResponse {
+    let status = match err {
+        AuthError::MissingScope(_)
+        | AuthError::NotTrusted(_)
+        | AuthError::NotGranted(_)
+        | AuthError::NotOwner(_)
+        | AuthError::Reserved(_) => StatusCode::FORBIDDEN,
+        AuthError::Unavailable => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::UNAUTHORIZED,
+    };
+    HttpResponse::build(status)
+        // Offered for git and browsers, neither of which speak cargo's scheme.
+        .header(header::WWW_AUTHENTICATE, r#"Basic realm="Estuary""#)
+        .header(
+            header::WWW_AUTHENTICATE,
+            format!(r#"Cargo login_url="{}/me""#, settings.base_url),
+        )
+        .json(json!({"errors": [{ "detail": err.detail() }]}))
+}
+
+/// Guard for endpoints that always require a valid token (publish, yank, etc).
+pub async fn authorize(
+    request: &HttpRequest,
+    settings: &Settings,
+    required: Scope,
+) -> Result<(), HttpResponse> {
+    check(request, settings, required)
+        .await
+        .map_err(|e| challenge(&e, settings))
+}
+
+/// Like [`authorize`], but gives back the token the request was made with
+/// (`None` when there are no tokens yet).
+pub async fn authorize_token(
+    request: &HttpRequest,
+    settings: &Settings,
+    required: Scope,
+) -> Result<Option<Token>, HttpResponse> {
+    let sent = Sent::new(request, settings);
+    with_db(settings, move |conn| authenticate(sent, conn, required))
+        .await
+        .map_err(|e| challenge(&e, settings))
+}
+
+/// Guard for endpoints acting on a particular crate (yank, unyank, and
+/// owners), which also need the token to have been granted access to it.
+pub async fn authorize_crate(
+    request: &HttpRequest,
+    settings: &Settings,
+    required: Scope,
+    crate_name: &str,
+) -> Result<Option<Token>, HttpResponse> {
+    check_crate(request, settings, required, crate_name, None)
+        .await
+        .map_err(|e| challenge(&e, settings))
+}
+
+/// Like [`authorize_crate`], for publishing `version` of `crate_name` (which
+/// come from the publish's body, rather than its path).
+pub async fn authorize_publish(
+    request: &HttpRequest,
+    settings: &Settings,
+    crate_name: &str,
+    version: &semver::Version,
+) -> Result<Option<Token>, HttpResponse> {
+    let version = version.to_string();
+    check_crate(
+        request,
+        settings,
+        Scope::Publish,
+        crate_name,
+        Some(&version),
+    )
+    .await
+    .map_err(|e| challenge(&e, settings))
+}
+
+/// Guard for the endpoints cargo uses to *read* from the registry (the index,
+/// crate downloads, and search).
+///
+/// These are only protected when the registry is configured to require auth,
+/// in which case the index `config.json` also carries `auth-required` so cargo
+/// knows to send its token along.
+pub async fn authorize_read(
+    request: &HttpRequest,
+    settings: &Settings,
+) -> Result<(), HttpResponse> {
+    if settings.auth_required {
+        authorize(request, settings, Scope::Read).await
+    } else {
+        Ok(())
+    }
+}
+
+/// Check a request made to an area protected by `--basic-auth`.
+///
+/// The request needs either a user's login and password, or a token with the
+/// `read` scope (sent as the password).
+async fn check_basic(request: &HttpRequest, settings: &Settings) -> Result<(), AuthError> {
+    let unavailable = |e: EstuaryError| {
+        log::error!("Failed to check credentials: {}", e);
+        AuthError::Unavailable
+    };
+    let credentials = get_basic_credentials(get_authorization(request)?)?;
+    let sent = Sent::new(request, settings);
+    let backend = settings.password_backend();
+    with_db(settings, move |conn| {
+        // Tokens are checked first since it doesn't involve a trip to the
+        // directory server, when there is one.
+        match check_token(sent, conn, Scope::Read) {
+            Err(AuthError::Invalid) => {}
+            checked => return checked.map(|_| ()),
+        }
+        if let Some((login, password)) = credentials {
+            if backend.verify(&login, &password).map_err(unavailable)? {
+                return Ok(());
+            }
+        }
+        Err(AuthError::Invalid)
+    })
+    .await
+}
+
+/// The cookie that keeps someone logged in to the web UI.
+///
+/// `SameSite=Lax` keeps the browser from sending it along with forms posted
+/// from other sites.
+pub fn session_cookie(settings: &Settings, session: String) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE, session)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(settings.base_url.starts_with("https://"))
+        .finish()
+}
+
+/// The user logged in to the web UI, if any.
+pub async fn session_user(request: &HttpRequest, settings: &Settings) -> Option<database::User> {
+    let session = request.cookie(SESSION_COOKIE)?.value().to_string();
+    let found = settings
+        .with_db(move |conn| database::find_session_user(conn, &session, SESSION_MAX_AGE))
+        .await;
+    match found {
+        Ok(user) => user,
+        Err(e) => {
+            log::error!("Failed to read sessions: {}", e);
+            None
+        }
+    }
+}
+
+/// Guard for an area protected by `--basic-auth`.
+async fn authorize_basic(request: &HttpRequest, settings: &Settings) -> Result<(), HttpResponse> {
+    check_basic(request, settings)
+        .await
+        .map_err(|e| challenge(&e, settings))
+}
+
+/// Guard for the web UI, which needs a login with `--basic-auth=web`, or a
+/// token in private mode.
+///
+/// Anyone logged in through single sign-on is let in either way.
+pub async fn authorize_frontend(
+    request: &HttpRequest,
+    settings: &Settings,
+) -> Result<(), HttpResponse> {
+    if session_user(request, settings).await.is_some() {
+        Ok(())
+    } else if settings.basic_auth.contains(&BasicAuthArea::Web) {
+        authorize_basic(request, settings).await
+    } else if settings.private {
+        authorize(request, settings, Scope::Read).await
+    } else {
+        Ok(())
+    }
+}
+
+/// Guard for the `/me` page, which stays open (even in private mode) so people
+/// can find out how to get a token, unless `--basic-auth=web` is set.
+pub async fn authorize_login_page(
+    request: &HttpRequest,
+    settings: &Settings,
+) -> Result<(), HttpResponse> {
+    if session_user(request, settings).await.is_none()
+        && settings.basic_auth.contains(&BasicAuthArea::Web)
+    {
+        authorize_basic(request, settings).await
+    } else {
+        Ok(())
+    }
+}
+
+/// Guard for the git index, which needs a login with `--basic-auth=git`, and
+/// is otherwise treated like the rest of the read endpoints.
+pub async fn authorize_git(request: &HttpRequest, settings: &Settings) -> Result<(), HttpResponse> {
+    if settings.basic_auth.contains(&BasicAuthArea::Git) {
+        authorize_basic(request, settings).await
+    } else {
+        authorize_read(request, settings).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+    use actix_web::test;
+
+    #[test]
+    fn test_secure_eq() {
+        assert!("abc".secure_eq(&"abc"));
+        assert!(!"abc".secure_eq(&"abd"));
+        assert!(!"abc".secure_eq(&"abcd"));
+    }
+
+    #[actix_rt::test]
+    async fn test_check_no_tokens_allows_anything() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let req = test::TestRequest::default().to_http_request();
+        assert_eq!(Ok(()), check(&req, &settings, Scope::Publish).await);
+    }
+
+    #[actix_rt::test]
+    async fn test_check_tokens_revoked() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        test_helpers::add_token(&settings, "ci", "secret");
+        let conn = settings.get_db().unwrap();
+        assert!(database::revoke_token(&conn, "ci", "shell", None).unwrap());
+
+        // Still closed, with nothing left to let anyone in.
+        let req = test::TestRequest::default()
+            .header("authorization", "secret")
+            .to_http_request();
+        assert_eq!(
+            Err(AuthError::Invalid),
+            check(&req, &settings, Scope::Publish).await
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_check_auth_required_no_tokens() {
+        let data_root = test_helpers::get_data_root();
+        let settings = Settings {
+            auth_required: true,
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        };
+        let req = test::TestRequest::default().to_http_request();
+        assert_eq!(
+            Err(AuthError::Missing),
+            check(&req, &settings, Scope::Read).await
+        );
+        assert!(authorize_read(&req, &settings).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_check_with_tokens() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        test_helpers::add_token(&settings, "ci", "secret");
+        test_helpers::add_token(&settings, "alice", "other-secret");
+
+        let req = test::TestRequest::default().to_http_request();
+        assert_eq!(
+            Err(AuthError::Missing),
+            check(&req, &settings, Scope::Publish).await
+        );
+
+        let req = test::TestRequest::default()
+            .header("authorization", "nope")
+            .to_http_request();
+        assert_eq!(
+            Err(AuthError::Invalid),
+            check(&req, &settings, Scope::Publish).await
+        );
+
+        for token in &["secret", "other-secret"] {
+            let req = test::TestRequest::default()
+                .header("authorization", *token)
+                .to_http_request();
+            assert_eq!(Ok(()), check(&req, &settings, Scope::Publish).await);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_check_scopes() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        database::set_token(
+            &settings.get_db().unwrap(),
+            "ci",
+            "secret",
+            &[Scope::Read, Scope::Publish],
+        )
+        .unwrap();
+
+        let req = test::TestRequest::default()
+            .header("authorization", "secret")
+            .to_http_request();
+        assert_eq!(Ok(()), check(&req, &settings, Scope::Publish).await);
+        assert_eq!(
+            Err(AuthError::MissingScope(Scope::Yank)),
+            check(&req, &settings, Scope::Yank).await
+        );
+        assert_eq!(
+            StatusCode::FORBIDDEN,
+            challenge(&AuthError::MissingScope(Scope::Yank), &settings).status()
+        );
+    }
+
+    #[test]
+    fn test_generate_token() {
+        let token = generate_token();
+        assert_eq!(35, token.len());
+        assert!(token.starts_with("est"));
+        assert_ne!(token, generate_token());
+    }
+
+    #[test]
+    fn test_scope_from_str() {
+        for scope in Scope::ALL {
+            assert_eq!(Ok(*scope), scope.as_str().parse());
+        }
+        assert!("superuser".parse::<Scope>().is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_check_basic_auth() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        test_helpers::add_token(&settings, "ci", "secret");
+
+        let basic = |credentials: &str| format!("Basic {}", base64::encode(credentials));
+        let req = test::TestRequest::default()
+            .header("authorization", basic("anyone:secret"))
+            .to_http_request();
+        assert_eq!(Ok(()), check(&req, &settings, Scope::Read).await);
+
+        let req = test::TestRequest::default()
+            .header("authorization", basic("ci:nope"))
+            .to_http_request();
+        assert_eq!(
+            Err(AuthError::Invalid),
+            check(&req, &settings, Scope::Read).await
+        );
+
+        let req = test::TestRequest::default()
+            .header("authorization", "Basic !!!")
+            .to_http_request();
+        assert_eq!(
+            Err(AuthError::Invalid),
+            check(&req, &settings, Scope::Read).await
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_check_basic() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        database::set_user_password(
+            &settings.get_db().unwrap(),
+            "alice",
+            &hash_password("hunter2").unwrap(),
+        )
+        .unwrap();
+
+        let basic = |credentials: &str| {
+            test::TestRequest::default()
+                .header(
+                    "authorization",
+                    format!("Basic {}", base64::encode(credentials)),
+                )
+                .to_http_request()
+        };
+        assert_eq!(
+            Ok(()),
+            check_basic(&basic("alice:hunter2"), &settings).await
+        );
+        assert_eq!(
+            Err(AuthError::Invalid),
+            check_basic(&basic("alice:wrong"), &settings).await
+        );
+        assert_eq!(
+            Err(AuthError::Missing),
+            check_basic(&test::TestRequest::default().to_http_request(), &settings).await
+        );
+
+        // Tokens work as the password too.
+        test_helpers::add_token(&settings, "ci", "secret");
+        assert_eq!(Ok(()), check_basic(&basic("git:secret"), &settings).await);
+    }
+
+    #[actix_rt::test]
+    async fn test_session_lets_into_private_frontend() {
+        let data_root = test_helpers::get_data_root();
+        let settings = Settings {
+            private: true,
+            auth_required: true,
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        };
+        test_helpers::add_token(&settings, "ci", "secret");
+
+        let req = test::TestRequest::default().to_http_request();
+        assert!(authorize_frontend(&req, &settings).await.is_err());
+
+        let session = test_helpers::add_session(&settings, "alice");
+        let req = test::TestRequest::default()
+            .cookie(actix_web::cookie::Cookie::new(SESSION_COOKIE, session))
+            .to_http_request();
+        assert_eq!("alice", session_user(&req, &settings).await.unwrap().login);
+        assert!(authorize_frontend(&req, &settings).await.is_ok());
+        // Cargo's endpoints still want a token.
+        assert!(authorize_read(&req, &settings).await.is_err());
+    }
+
+    #[test]
+    fn test_challenge_has_login_url() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let resp = challenge(&AuthError::Missing, &settings);
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        assert_eq!(
+            r#"Cargo login_url="http://localhost:7878/me""#,
+            resp.headers().get(header::WWW_AUTHENTICATE).unwrap()
+        );
+    }
+}
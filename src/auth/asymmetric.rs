@@ -0,0 +1,337 @@
+//! Cargo's asymmetric tokens ([RFC 3231]), as sent by the `cargo:paseto`
+//! credential provider.
+//!
+//! Rather than a shared secret, cargo sends a short-lived PASETO (`v3.public`)
+//! signed with a private key that never leaves the client. The footer says
+//! which key signed it (by PASERK id) and which registry it was meant for; the
+//! payload says what the request is for (ex: publishing `foo 1.0.0`).
+//!
+//! [RFC 3231]: https://rust-lang.github.io/rfcs/3231-cargo-asymmetric-tokens.html
+
+use super::{AuthError, Scope};
+use crate::database::{self, Connection, Token};
+use crate::Settings;
+use actix_web::HttpRequest;
+use pasetors::keys::AsymmetricPublicKey;
+use pasetors::paserk::{FormatAsPaserk, Id};
+use pasetors::token::UntrustedToken;
+use pasetors::version3::{PublicToken, V3};
+use pasetors::Public;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+
+/// How far a token's `iat` may be from our own clock before we refuse it.
+const MAX_CLOCK_SKEW: Duration = Duration::minutes(15);
+
+/// Does this look like an asymmetric token (as opposed to a plain secret)?
+pub fn is_asymmetric(token: &str) -> bool {
+    token.starts_with(PublicToken::HEADER)
+}
+
+/// Get the PASERK id (`k3.pid.…`) for a PASERK public key (`k3.public.…`).
+///
+/// Cargo identifies the key it signed with by this id.
+pub fn key_id(public_key: &str) -> Result<String, String> {
+    let key = AsymmetricPublicKey::<V3>::try_from(public_key)
+        .map_err(|_| "expected a `k3.public.` PASERK public key".to_string())?;
+    let mut id = String::new();
+    Id::from(&key)
+        .fmt(&mut id)
+        .map_err(|_| "failed to compute the key id".to_string())?;
+    Ok(id)
+}
+
+#[derive(Deserialize)]
+struct Footer {
+    url: String,
+    kid: String,
+}
+
+/// The claims cargo makes about the request a token was issued for.
+#[derive(Deserialize)]
+struct Message {
+    iat: String,
+    /// One of `publish`, `yank`, `unyank`, or `owners`. Absent for reads.
+    mutation: Option<String>,
+    name: Option<String>,
+    vers: Option<String>,
+}
+
+/// What a token has to have been signed for, taken from the request it came
+/// with (so it can be checked away from the request, off the worker thread).
+pub struct Expected {
+    index_urls: Vec<String>,
+    /// Whether the request is to unyank, rather than yank, for tokens needing
+    /// the `yank` scope.
+    unyank: bool,
+    crate_name: Option<String>,
+    version: Option<String>,
+}
+
+impl Expected {
+    pub fn new(request: &HttpRequest, settings: &Settings) -> Expected {
+        let path = request.match_info();
+        Expected {
+            // Cargo puts the url of the index it was configured with in the
+            // footer.
+            index_urls: settings.index_urls(),
+            unyank: request.path().ends_with("/unyank"),
+            crate_name: path.get("crate_name").map(str::to_string),
+            version: path.get("version").map(str::to_string),
+        }
+    }
+
+    /// Expect a token for `crate_name` (and `version`) when the request's
+    /// path doesn't name them, as for publishes, which name them in the
+    /// body.
+    pub fn for_crate(self, crate_name: &str, version: Option<&str>) -> Expected {
+        Expected {
+            crate_name: Some(crate_name.to_string()),
+            version: version.map(str::to_string).or(self.version),
+            ..self
+        }
+    }
+
+    /// The mutation a token has to have been signed for to be used with the
+    /// `required` scope, or `None` for reads.
+    fn mutation(&self, required: Scope) -> Result<Option<&'static str>, AuthError> {
+        match required {
+            Scope::Read => Ok(None),
+            Scope::Publish => Ok(Some("publish")),
+            Scope::Yank if self.unyank => Ok(Some("unyank")),
+            Scope::Yank => Ok(Some("yank")),
+            Scope::Owners => Ok(Some("owners")),
+            // Cargo never signs anything but its own requests.
+            Scope::Admin => Err(AuthError::Invalid),
+        }
+    }
+}
+
+/// Verify an asymmetric token, giving back the key's owner if it checks out.
+///
+/// Beyond the signature, the token has to be meant for this registry, be
+/// recent, and be for exactly the operation being attempted. Once the crate
+/// (and version) being changed is known, the token has to name the same one.
+pub fn verify(
+    token: &str,
+    expected: &Expected,
+    conn: &Connection,
+    required: Scope,
+) -> Result<Token, AuthError> {
+    let untrusted =
+        UntrustedToken::<Public, V3>::try_from(token).map_err(|_| AuthError::Invalid)?;
+    let footer: Footer =
+        serde_json::from_slice(untrusted.untrusted_footer()).map_err(|_| AuthError::Invalid)?;
+
+    let (found, public_key) = database::find_public_key(conn, &footer.kid)
+        .map_err(|e| {
+            log::error!("Failed to read public keys: {}", e);
+            AuthError::Unavailable
+        })?
+        .ok_or(AuthError::Invalid)?;
+    let public_key =
+        AsymmetricPublicKey::<V3>::try_from(public_key.as_str()).map_err(|_| AuthError::Invalid)?;
+
+    let trusted =
+        PublicToken::verify(&public_key, &untrusted, None, None).map_err(|_| AuthError::Invalid)?;
+    let message: Message =
+        serde_json::from_str(trusted.payload()).map_err(|_| AuthError::Invalid)?;
+
+    if !expected.index_urls.contains(&footer.url) {
+        log::debug!("Asymmetric token was issued for `{}`", footer.url);
+        return Err(AuthError::Invalid);
+    }
+
+    let iat = OffsetDateTime::parse(&message.iat, &Rfc3339).map_err(|_| AuthError::Invalid)?;
+    if (OffsetDateTime::now_utc() - iat).abs() > MAX_CLOCK_SKEW {
+        return Err(AuthError::Invalid);
+    }
+
+    let mutation = expected.mutation(required)?;
+    if message.mutation.as_deref() != mutation {
+        return Err(AuthError::Invalid);
+    }
+    // Reads aren't signed for any crate in particular.
+    if mutation.is_some() {
+        if expected.crate_name.is_some() && message.name != expected.crate_name {
+            return Err(AuthError::Invalid);
+        }
+        if expected.version.is_some() && message.vers != expected.version {
+            return Err(AuthError::Invalid);
+        }
+    }
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+    use actix_web::test;
+    use pasetors::keys::{AsymmetricKeyPair, Generate};
+    use serde_json::json;
+
+    struct Signer {
+        keys: AsymmetricKeyPair<V3>,
+        public_key: String,
+        kid: String,
+    }
+
+    impl Signer {
+        fn new() -> Self {
+            let keys = AsymmetricKeyPair::<V3>::generate().unwrap();
+            let mut public_key = String::new();
+            keys.public.fmt(&mut public_key).unwrap();
+            let kid = key_id(&public_key).unwrap();
+            Self {
+                keys,
+                public_key,
+                kid,
+            }
+        }
+
+        fn sign(&self, url: &str, message: serde_json::Value) -> String {
+            let footer = json!({ "url": url, "kid": self.kid });
+            PublicToken::sign(
+                &self.keys.secret,
+                message.to_string().as_bytes(),
+                Some(footer.to_string().as_bytes()),
+                None,
+            )
+            .unwrap()
+        }
+    }
+
+    fn now() -> String {
+        OffsetDateTime::now_utc().format(&Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn test_key_id() {
+        let signer = Signer::new();
+        assert!(signer.kid.starts_with("k3.pid."));
+        assert!(key_id("k3.public.nope").is_err());
+        assert!(key_id("secret").is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        let signer = Signer::new();
+        database::add_public_key(&conn, "ci", &signer.public_key, &signer.kid, Scope::ALL).unwrap();
+        let req = test::TestRequest::default().to_http_request();
+        let url = "http://localhost:7878/git/index";
+
+        let token = signer.sign(url, json!({ "iat": now(), "mutation": "publish" }));
+        assert!(is_asymmetric(&token));
+        let found = verify(
+            &token,
+            &Expected::new(&req, &settings),
+            &conn,
+            Scope::Publish,
+        )
+        .unwrap();
+        assert_eq!("ci", found.name);
+
+        // Signed for a different operation.
+        assert_eq!(
+            Err(AuthError::Invalid),
+            verify(&token, &Expected::new(&req, &settings), &conn, Scope::Yank).map(|_| ())
+        );
+
+        // Reads have no mutation, and the sparse url works too.
+        let token = signer.sign(
+            "sparse+http://localhost:7878/index/",
+            json!({ "iat": now() }),
+        );
+        assert!(verify(&token, &Expected::new(&req, &settings), &conn, Scope::Read).is_ok());
+
+        // Meant for some other registry.
+        let token = signer.sign(
+            "https://elsewhere.example.com/index",
+            json!({ "iat": now() }),
+        );
+        assert!(verify(&token, &Expected::new(&req, &settings), &conn, Scope::Read).is_err());
+
+        // Stale.
+        let token = signer.sign(url, json!({ "iat": "2020-01-01T00:00:00Z" }));
+        assert!(verify(&token, &Expected::new(&req, &settings), &conn, Scope::Read).is_err());
+
+        // Signed by a key we don't know.
+        let token = Signer::new().sign(url, json!({ "iat": now() }));
+        assert!(verify(&token, &Expected::new(&req, &settings), &conn, Scope::Read).is_err());
+
+        // Signed for yanking a different crate than the one in the path.
+        let token = signer.sign(
+            url,
+            json!({ "iat": now(), "mutation": "yank", "name": "my-crate", "vers": "0.1.0" }),
+        );
+        let req = test::TestRequest::default()
+            .param("crate_name", "my-crate")
+            .param("version", "0.1.0")
+            .to_http_request();
+        assert!(verify(&token, &Expected::new(&req, &settings), &conn, Scope::Yank).is_ok());
+        let req = test::TestRequest::default()
+            .param("crate_name", "other-crate")
+            .param("version", "0.1.0")
+            .to_http_request();
+        assert!(verify(&token, &Expected::new(&req, &settings), &conn, Scope::Yank).is_err());
+
+        // Or for yanking it, when it's being unyanked.
+        let req = test::TestRequest::with_uri("/api/v1/crates/my-crate/0.1.0/unyank")
+            .param("crate_name", "my-crate")
+            .param("version", "0.1.0")
+            .to_http_request();
+        assert!(verify(&token, &Expected::new(&req, &settings), &conn, Scope::Yank).is_err());
+
+        // Not signed for any crate, once that's known.
+        let token = signer.sign(url, json!({ "iat": now(), "mutation": "yank" }));
+        assert!(verify(&token, &Expected::new(&req, &settings), &conn, Scope::Yank).is_err());
+    }
+
+    #[test]
+    fn test_verify_publish() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        let signer = Signer::new();
+        database::add_public_key(&conn, "ci", &signer.public_key, &signer.kid, Scope::ALL).unwrap();
+        let url = "http://localhost:7878/git/index";
+        let token = signer.sign(
+            url,
+            json!({ "iat": now(), "mutation": "publish", "name": "my-crate", "vers": "0.1.0" }),
+        );
+        let req = test::TestRequest::with_uri("/api/v1/crates/new").to_http_request();
+        let expected = |crate_name, version| {
+            Expected::new(&req, &settings).for_crate(crate_name, Some(version))
+        };
+
+        assert!(verify(
+            &token,
+            &expected("my-crate", "0.1.0"),
+            &conn,
+            Scope::Publish
+        )
+        .is_ok());
+        // The publish body names a different crate, or version.
+        assert!(verify(
+            &token,
+            &expected("other-crate", "0.1.0"),
+            &conn,
+            Scope::Publish
+        )
+        .is_err());
+        assert!(verify(
+            &token,
+            &expected("my-crate", "0.2.0"),
+            &conn,
+            Scope::Publish
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Where logins and passwords are checked.
+//!
+//! By default that's the users added with `estuary user add`, but a directory
+//! server can be used instead (see `--ldap-url`), in which case it's the only
+//! place passwords are checked.
+
+use crate::database;
+use crate::errors::EstuaryError;
+use ldap3::{LdapConn, LdapConnSettings, Scope as LdapScope, SearchEntry};
+use std::fmt;
+use std::time::Duration;
+
+/// The LDAP result code for a bind with the wrong password (or an unknown DN).
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// How long to wait for the directory server before giving up.
+const LDAP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Something that can tell whether a user's password is right.
+///
+/// This may block on the network, so call it from `web::block` where
+/// possible.
+pub trait PasswordBackend: Send {
+    fn verify(&self, login: &str, password: &str) -> Result<bool, EstuaryError>;
+}
+
+/// Users kept in our own database, with argon2 password hashes.
+pub struct LocalUsers {
+    pub db: database::Pool,
+}
+
+impl PasswordBackend for LocalUsers {
+    fn verify(&self, login: &str, password: &str) -> Result<bool, EstuaryError> {
+        let conn = self.db.get()?;
+        Ok(match database::find_user_password_hash(&conn, login)? {
+            Some(hash) => super::verify_password(password, &hash),
+            None => false,
+        })
+    }
+}
+
+/// How to find users in a directory server.
+#[derive(Clone)]
+pub struct LdapConfig {
+    /// ex: `ldaps://ldap.example.com`
+    pub url: String,
+    /// A template for users' DNs, with `{login}` standing in for the login,
+    /// ex: `uid={login},ou=people,dc=example,dc=com`.
+    ///
+    /// When set, users are bound as directly, with no search.
+    pub user_dn: Option<String>,
+    /// Where to search for users when there's no `user_dn` template.
+    pub base_dn: Option<String>,
+    /// The filter to search with, with `{login}` standing in for the login.
+    pub user_filter: String,
+    /// Who to bind as to search, when anonymous searches aren't allowed.
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+}
+
+// Keeps the bind password out of the logs.
+impl fmt::Debug for LdapConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LdapConfig")
+            .field("url", &self.url)
+            .field("user_dn", &self.user_dn)
+            .field("base_dn", &self.base_dn)
+            .field("user_filter", &self.user_filter)
+            .field("bind_dn", &self.bind_dn)
+            .finish()
+    }
+}
+
+impl LdapConfig {
+    /// The DN to bind as for `login`, when using a `user_dn` template.
+    fn user_dn_for(&self, login: &str) -> Option<String> {
+        self.user_dn
+            .as_ref()
+            .map(|template| template.replace("{login}", &ldap3::dn_escape(login)))
+    }
+
+    /// The search filter that finds `login`.
+    fn user_filter_for(&self, login: &str) -> String {
+        self.user_filter
+            .replace("{login}", &ldap3::ldap_escape(login))
+    }
+
+    fn connect(&self) -> Result<LdapConn, EstuaryError> {
+        let settings = LdapConnSettings::new().set_conn_timeout(LDAP_TIMEOUT);
+        Ok(LdapConn::with_settings(settings, &self.url)?)
+    }
+
+    /// Find the DN for `login` by searching for it.
+    ///
+    /// Gives `None` unless exactly one entry matches.
+    fn search_user_dn(
+        &self,
+        conn: &mut LdapConn,
+        login: &str,
+    ) -> Result<Option<String>, EstuaryError> {
+        if let (Some(dn), Some(password)) = (&self.bind_dn, &self.bind_password) {
+            conn.simple_bind(dn, password)?.success()?;
+        }
+        let base_dn = self.base_dn.as_deref().unwrap_or_default();
+        // `1.1` asks for no attributes, since only the DN is needed.
+        let (mut entries, _) = conn
+            .search(
+                base_dn,
+                LdapScope::Subtree,
+                &self.user_filter_for(login),
+                vec!["1.1"],
+            )?
+            .success()?;
+        Ok(if entries.len() == 1 {
+            Some(SearchEntry::construct(entries.remove(0)).dn)
+        } else {
+            None
+        })
+    }
+}
+
+impl PasswordBackend for LdapConfig {
+    fn verify(&self, login: &str, password: &str) -> Result<bool, EstuaryError> {
+        // An empty password would be an "unauthenticated" bind, which servers
+        // tend to allow for any DN.
+        if login.is_empty() || password.is_empty() {
+            return Ok(false);
+        }
+        let mut conn = self.connect()?;
+        let dn = match self.user_dn_for(login) {
+            Some(dn) => dn,
+            None => match self.search_user_dn(&mut conn, login)? {
+                Some(dn) => dn,
+                None => return Ok(false),
+            },
+        };
+        let result = conn.simple_bind(&dn, password)?;
+        // Errors unbinding don't change the answer.
+        let _ = conn.unbind();
+        match result.rc {
+            0 => Ok(true),
+            INVALID_CREDENTIALS => Ok(false),
+            _ => Err(result.success().unwrap_err().into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    fn ldap_config() -> LdapConfig {
+        LdapConfig {
+            // Nothing listens here.
+            url: "ldap://127.0.0.1:1".to_string(),
+            user_dn: Some("uid={login},ou=people,dc=example,dc=com".to_string()),
+            base_dn: None,
+            user_filter: "(uid={login})".to_string(),
+            bind_dn: None,
+            bind_password: Some("shh".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_local_users() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        database::set_user_password(
+            &settings.get_db().unwrap(),
+            "alice",
+            &crate::auth::hash_password("hunter2").unwrap(),
+        )
+        .unwrap();
+        let backend = LocalUsers {
+            db: settings.db.clone(),
+        };
+        assert!(backend.verify("alice", "hunter2").unwrap());
+        assert!(!backend.verify("alice", "wrong").unwrap());
+        assert!(!backend.verify("bob", "hunter2").unwrap());
+    }
+
+    #[test]
+    fn test_ldap_escapes_logins() {
+        let config = ldap_config();
+        assert_eq!(
+            Some(r"uid=a\2cou\3dadmins,ou=people,dc=example,dc=com".to_string()),
+            config.user_dn_for("a,ou=admins")
+        );
+        assert_eq!(r"(uid=\2a)", config.user_filter_for("*"));
+    }
+
+    #[test]
+    fn test_ldap_empty_password_is_refused() {
+        // Refused without even trying to reach the server.
+        assert!(!ldap_config().verify("alice", "").unwrap());
+        assert!(ldap_config().verify("alice", "hunter2").is_err());
+    }
+
+    #[test]
+    fn test_ldap_debug_hides_password() {
+        assert!(!format!("{:?}", ldap_config()).contains("shh"));
+    }
+}
@@ -0,0 +1,547 @@
+//! Logging in to the web UI through an OpenID Connect identity provider.
+//!
+//! This is the authorization code flow: the `/me` page sends people off to
+//! the provider, which sends them back to our callback with a code. The code
+//! is traded (along with our client secret) for an ID token, which says who
+//! they are once its signature has been checked against the provider's keys.
+//!
+//! <https://openid.net/specs/openid-connect-core-1_0.html#CodeFlowAuth>
+
+use crate::errors::OidcError;
+use crate::Settings;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Where the provider sends people back to after they log in.
+pub const CALLBACK_PATH: &str = "/me/oidc/callback";
+
+/// How long (in seconds) someone has to finish logging in with the provider.
+pub const LOGIN_MAX_AGE: i64 = 10 * 60;
+
+/// The cookie holding the `state` a browser was sent off to log in with, so
+/// the callback only finishes logins started in the same browser.
+pub const STATE_COOKIE: &str = "estuary_oidc_state";
+
+/// Signing algorithms accepted for ID tokens.
+///
+/// Only public key algorithms are allowed, since the `HS*` family would have
+/// the provider signing with our client secret.
+const ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// How to reach the identity provider.
+#[derive(Clone)]
+pub struct OidcConfig {
+    /// The provider's issuer url, ex: `https://accounts.google.com`.
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Who may log in (see [`Claims::is_allowed`]). Anyone the provider
+    /// vouches for, when empty.
+    pub allowed: Vec<String>,
+}
+
+// Keeps the secret out of the logs.
+impl fmt::Debug for OidcConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OidcConfig")
+            .field("issuer", &self.issuer)
+            .field("client_id", &self.client_id)
+            .field("allowed", &self.allowed)
+            .finish()
+    }
+}
+
+/// The parts of the provider's metadata we need.
+///
+/// <https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata>
+#[derive(Debug, Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// The claims we look at in an ID token.
+///
+/// `iss`, `aud`, and `exp` are checked while decoding.
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub preferred_username: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    /// Not a standard claim, but the one most providers put groups in.
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+impl Claims {
+    /// The name to give the user when they first log in.
+    pub fn login(&self) -> &str {
+        self.preferred_username
+            .as_deref()
+            .or(self.email.as_deref())
+            .unwrap_or(&self.sub)
+    }
+
+    /// Whether any of the `allowed` entries lets this user in: an email
+    /// address (ex: `alice@example.com`), an email domain (ex:
+    /// `@example.com`), or a group (ex: `group:developers`).
+    ///
+    /// Emails only count once the provider has verified them.
+    pub fn is_allowed(&self, allowed: &[String]) -> bool {
+        if allowed.is_empty() {
+            return true;
+        }
+        let email = self
+            .email
+            .as_deref()
+            .filter(|_| self.email_verified == Some(true))
+            .map(str::to_lowercase);
+        allowed.iter().any(|entry| {
+            if let Some(group) = entry.strip_prefix("group:") {
+                self.groups.iter().any(|g| g == group)
+            } else if let Some(email) = &email {
+                let entry = entry.to_lowercase();
+                if entry.starts_with('@') {
+                    email.ends_with(&entry)
+                } else {
+                    *email == entry
+                }
+            } else {
+                false
+            }
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The url the provider should send people back to.
+pub fn redirect_uri(settings: &Settings) -> String {
+    format!("{}{}", settings.base_url, CALLBACK_PATH)
+}
+
+async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T, OidcError> {
+    let mut resp = awc::Client::default()
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| OidcError::Provider(format!("{}: {}", url, e)))?;
+    if !resp.status().is_success() {
+        return Err(OidcError::Provider(format!("{}: {}", url, resp.status())));
+    }
+    resp.json()
+        .await
+        .map_err(|e| OidcError::Provider(format!("{}: {}", url, e)))
+}
+
+/// Fetch the metadata for the provider at `issuer`.
+pub async fn discover(issuer: &str) -> Result<Discovery, OidcError> {
+    get_json(&format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    ))
+    .await
+}
+
+/// Fetch the keys the provider signs ID tokens with.
+pub async fn fetch_jwks(discovery: &Discovery) -> Result<JwkSet, OidcError> {
+    get_json(&discovery.jwks_uri).await
+}
+
+/// How long a provider's keys are kept before they're fetched again.
+pub const JWKS_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How soon keys may be fetched again when a token can't be checked with the
+/// ones kept, in case the provider has rotated them.
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+/// The keys each issuer signs ID tokens with, kept for [`JWKS_TTL`], so
+/// trusted publishing doesn't ask the provider for them on every publish.
+#[derive(Debug, Default)]
+pub struct JwksCache {
+    entries: Mutex<HashMap<String, (Instant, JwkSet)>>,
+}
+
+impl JwksCache {
+    /// The keys `issuer` signs with, fetched if the ones kept are stale (or
+    /// there aren't any).
+    ///
+    /// The cache isn't locked while fetching, so a slow provider doesn't hold
+    /// up checks against other issuers.
+    pub async fn get(&self, issuer: &str) -> Result<JwkSet, OidcError> {
+        match self.kept(issuer, JWKS_TTL) {
+            Some(jwks) => Ok(jwks),
+            None => self.fetch(issuer).await,
+        }
+    }
+
+    /// Like [`JwksCache::get`], but fetches the keys again unless they were
+    /// fetched in the last minute, for when a token was signed with a key we
+    /// don't have.
+    pub async fn refresh(&self, issuer: &str) -> Result<JwkSet, OidcError> {
+        match self.kept(issuer, JWKS_MIN_REFRESH) {
+            Some(jwks) => Ok(jwks),
+            None => self.fetch(issuer).await,
+        }
+    }
+
+    fn kept(&self, issuer: &str, max_age: Duration) -> Option<JwkSet> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(issuer)
+            .filter(|(at, _)| at.elapsed() < max_age)
+            .map(|(_, jwks)| jwks.clone())
+    }
+
+    /// Keep `jwks` as the keys `issuer` signs with.
+    pub fn insert(&self, issuer: &str, jwks: JwkSet) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(issuer.to_string(), (Instant::now(), jwks));
+    }
+
+    async fn fetch(&self, issuer: &str) -> Result<JwkSet, OidcError> {
+        let jwks = fetch_jwks(&discover(issuer).await?).await?;
+        self.insert(issuer, jwks.clone());
+        Ok(jwks)
+    }
+}
+
+/// Where to send someone to log in.
+pub fn authorization_url(
+    discovery: &Discovery,
+    config: &OidcConfig,
+    redirect_uri: &str,
+    state: &str,
+    nonce: &str,
+) -> String {
+    let query = serde_urlencoded::to_string([
+        ("response_type", "code"),
+        ("client_id", &config.client_id),
+        ("redirect_uri", redirect_uri),
+        ("scope", "openid profile email"),
+        ("state", state),
+        ("nonce", nonce),
+    ])
+    // Only fails for things that can't be represented as pairs.
+    .unwrap();
+    let separator = if discovery.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    format!("{}{}{}", discovery.authorization_endpoint, separator, query)
+}
+
+/// Trade the code the provider sent back for an ID token.
+pub async fn exchange_code(
+    discovery: &Discovery,
+    config: &OidcConfig,
+    redirect_uri: &str,
+    code: &str,
+) -> Result<String, OidcError> {
+    let url = &discovery.token_endpoint;
+    let mut resp = awc::Client::default()
+        .post(url)
+        .basic_auth(&config.client_id, Some(&config.client_secret))
+        .send_form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+        ])
+        .await
+        .map_err(|e| OidcError::Provider(format!("{}: {}", url, e)))?;
+    if !resp.status().is_success() {
+        return Err(OidcError::Provider(format!("{}: {}", url, resp.status())));
+    }
+    let body: TokenResponse = resp
+        .json()
+        .await
+        .map_err(|e| OidcError::Provider(format!("{}: {}", url, e)))?;
+    Ok(body.id_token)
+}
+
+/// Check the ID token's signature and claims, including that it carries the
+/// `nonce` we sent the user off with.
+pub fn verify_id_token(
+    id_token: &str,
+    jwks: &JwkSet,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+) -> Result<Claims, OidcError> {
+    let claims: Claims = decode_id_token(id_token, jwks, issuer, client_id)?;
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(OidcError::InvalidToken("nonce mismatch".to_string()));
+    }
+    Ok(claims)
+}
+
+/// Check an ID token's signature against the provider's keys, and that it's
+/// current and was meant for `audience`.
+///
+/// Gives whatever claims `C` picks out.
+pub fn decode_id_token<C: DeserializeOwned>(
+    id_token: &str,
+    jwks: &JwkSet,
+    issuer: &str,
+    audience: &str,
+) -> Result<C, OidcError> {
+    let invalid = |e: jsonwebtoken::errors::Error| OidcError::InvalidToken(e.to_string());
+    let header = jsonwebtoken::decode_header(id_token).map_err(invalid)?;
+    if !ALGORITHMS.contains(&header.alg) {
+        return Err(OidcError::InvalidToken(format!(
+            "unsupported algorithm `{:?}`",
+            header.alg
+        )));
+    }
+    let jwk = match (&header.kid, jwks.keys.as_slice()) {
+        (Some(kid), _) => jwks.find(kid),
+        // Without a key id, it's only clear which key to use if there's one.
+        (None, [jwk]) => Some(jwk),
+        (None, _) => None,
+    }
+    .ok_or_else(|| OidcError::InvalidToken("signed with an unknown key".to_string()))?;
+    let key = DecodingKey::from_jwk(jwk).map_err(invalid)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[audience]);
+    Ok(jsonwebtoken::decode::<C>(id_token, &key, &validation)
+        .map_err(invalid)?
+        .claims)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use jsonwebtoken::{EncodingKey, Header};
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use serde_json::json;
+
+    pub const ISSUER: &str = "https://id.example.com";
+    pub const CLIENT_ID: &str = "estuary";
+
+    /// Plays the identity provider, signing ID tokens with a P-256 key.
+    pub struct Provider {
+        key: EncodingKey,
+        pub jwks: JwkSet,
+    }
+
+    impl Provider {
+        pub fn new() -> Self {
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(
+                &ECDSA_P256_SHA256_FIXED_SIGNING,
+                &SystemRandom::new(),
+            )
+            .unwrap();
+            let pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+            // An uncompressed point: 0x04, then x, then y.
+            let point = pair.public_key().as_ref();
+            let coord = |c: &[u8]| base64::encode_config(c, base64::URL_SAFE_NO_PAD);
+            let jwks = serde_json::from_value(json!({
+                "keys": [{
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "kid": "key-1",
+                    "use": "sig",
+                    "alg": "ES256",
+                    "x": coord(&point[1..33]),
+                    "y": coord(&point[33..]),
+                }]
+            }))
+            .unwrap();
+            Self {
+                key: EncodingKey::from_ec_der(pkcs8.as_ref()),
+                jwks,
+            }
+        }
+
+        pub fn sign(&self, claims: serde_json::Value) -> String {
+            let mut header = Header::new(Algorithm::ES256);
+            header.kid = Some("key-1".to_string());
+            jsonwebtoken::encode(&header, &claims, &self.key).unwrap()
+        }
+
+        /// Claims for a token that should pass [`verify_id_token`].
+        pub fn claims(nonce: &str) -> serde_json::Value {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            json!({
+                "iss": ISSUER,
+                "aud": CLIENT_ID,
+                "sub": "248289761001",
+                "exp": now + 300,
+                "iat": now,
+                "nonce": nonce,
+                "preferred_username": "alice",
+            })
+        }
+    }
+
+    #[test]
+    fn test_verify_id_token() {
+        let provider = Provider::new();
+        let verify = |token: &str| verify_id_token(token, &provider.jwks, ISSUER, CLIENT_ID, "n");
+
+        let claims = verify(&provider.sign(Provider::claims("n"))).unwrap();
+        assert_eq!("248289761001", claims.sub);
+        assert_eq!("alice", claims.login());
+
+        assert!(verify(&provider.sign(Provider::claims("other"))).is_err());
+
+        let mut wrong_aud = Provider::claims("n");
+        wrong_aud["aud"] = json!("someone-else");
+        assert!(verify(&provider.sign(wrong_aud)).is_err());
+
+        let mut wrong_iss = Provider::claims("n");
+        wrong_iss["iss"] = json!("https://evil.example.com");
+        assert!(verify(&provider.sign(wrong_iss)).is_err());
+
+        let mut expired = Provider::claims("n");
+        expired["exp"] = json!(1);
+        assert!(verify(&provider.sign(expired)).is_err());
+
+        // Signed by someone else's key.
+        let other = Provider::new();
+        assert!(verify(&other.sign(Provider::claims("n"))).is_err());
+
+        // A shared secret algorithm.
+        let hs256 = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &Provider::claims("n"),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(verify(&hs256).is_err());
+    }
+
+    #[test]
+    fn test_login_falls_back_to_email_then_sub() {
+        let claims = Claims {
+            sub: "123".to_string(),
+            nonce: None,
+            preferred_username: None,
+            email: Some("alice@example.com".to_string()),
+            email_verified: None,
+            groups: vec![],
+        };
+        assert_eq!("alice@example.com", claims.login());
+        let claims = Claims {
+            email: None,
+            ..claims
+        };
+        assert_eq!("123", claims.login());
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let claims = Claims {
+            sub: "123".to_string(),
+            nonce: None,
+            preferred_username: Some("alice".to_string()),
+            email: Some("Alice@Example.com".to_string()),
+            email_verified: Some(true),
+            groups: vec!["developers".to_string()],
+        };
+        let allowed = |entries: &[&str]| {
+            claims.is_allowed(&entries.iter().map(|e| e.to_string()).collect::<Vec<_>>())
+        };
+        assert!(allowed(&[]));
+        assert!(allowed(&["alice@example.com"]));
+        assert!(allowed(&["bob@example.com", "@example.com"]));
+        assert!(allowed(&["group:developers"]));
+        assert!(!allowed(&["bob@example.com"]));
+        assert!(!allowed(&["@elsewhere.example.com"]));
+        // The `@` keeps other domains ending the same way out.
+        assert!(!allowed(&["@ample.com"]));
+        assert!(!allowed(&["group:admins"]));
+        // Nor by someone's login.
+        assert!(!allowed(&["alice"]));
+
+        let unverified = Claims {
+            email_verified: None,
+            ..claims
+        };
+        assert!(!unverified.is_allowed(&["@example.com".to_string()]));
+    }
+
+    #[test]
+    fn test_authorization_url() {
+        let discovery = Discovery {
+            issuer: ISSUER.to_string(),
+            authorization_endpoint: format!("{}/authorize", ISSUER),
+            token_endpoint: format!("{}/token", ISSUER),
+            jwks_uri: format!("{}/jwks", ISSUER),
+        };
+        let config = OidcConfig {
+            issuer: ISSUER.to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: "shh".to_string(),
+            allowed: vec![],
+        };
+        let url = authorization_url(
+            &discovery,
+            &config,
+            "http://localhost:7878/me/oidc/callback",
+            "s",
+            "n",
+        );
+        assert_eq!(
+            "https://id.example.com/authorize?response_type=code&client_id=estuary\
+            &redirect_uri=http%3A%2F%2Flocalhost%3A7878%2Fme%2Foidc%2Fcallback\
+            &scope=openid+profile+email&state=s&nonce=n",
+            url
+        );
+        assert!(!format!("{:?}", config).contains("shh"));
+    }
+
+    #[test]
+    fn test_jwks_cache() {
+        let cache = JwksCache::default();
+        let jwks = Provider::new().jwks;
+        assert!(cache.kept(ISSUER, JWKS_TTL).is_none());
+
+        cache.insert(ISSUER, jwks.clone());
+        assert_eq!(Some(jwks.clone()), cache.kept(ISSUER, JWKS_TTL));
+        assert!(cache.kept("https://other.example.com", JWKS_TTL).is_none());
+
+        // Keys older than the TTL are fetched again.
+        let stale = Instant::now() - JWKS_TTL - Duration::from_secs(1);
+        cache
+            .entries
+            .lock()
+            .unwrap()
+            .insert(ISSUER.to_string(), (stale, jwks));
+        assert!(cache.kept(ISSUER, JWKS_TTL).is_none());
+    }
+}
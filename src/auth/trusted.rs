@@ -0,0 +1,448 @@
+//! Trusted publishing: letting CI publish a crate with the short-lived OIDC ID
+//! token its provider issues to each job, instead of a long-lived token kept
+//! in the CI's secrets.
+//!
+//! Each crate can have any number of trusted publisher rules, naming the
+//! repository (and optionally the workflow and branch) allowed to publish it.
+//! The ID token is sent in place of a token, and is expected to have been
+//! requested with the registry's base url as its audience.
+
+use super::{oidc, AuthError};
+use crate::database::{self, TrustedPublisher};
+use crate::errors::EstuaryError;
+use crate::Settings;
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use jsonwebtoken::jwk::JwkSet;
+use serde::Deserialize;
+
+/// The CI systems whose ID tokens we understand.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Provider {
+    GitHub,
+    GitLab,
+}
+
+impl Provider {
+    pub const ALL: &'static [Provider] = &[Provider::GitHub, Provider::GitLab];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::GitHub => "github",
+            Self::GitLab => "gitlab",
+        }
+    }
+
+    /// Who issues the ID tokens, unless the provider is self-hosted.
+    pub fn default_issuer(&self) -> &'static str {
+        match self {
+            Self::GitHub => "https://token.actions.githubusercontent.com",
+            Self::GitLab => "https://gitlab.com",
+        }
+    }
+}
+
+impl std::str::FromStr for Provider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|provider| provider.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown provider `{}`", s))
+    }
+}
+
+/// The claims we look at, from either provider.
+///
+/// - <https://docs.github.com/en/actions/deployment/security-hardening-your-deployments/about-security-hardening-with-openid-connect#understanding-the-oidc-token>
+/// - <https://docs.gitlab.com/ee/ci/secrets/id_token_authentication.html#token-payload>
+#[derive(Debug, Default, Deserialize)]
+struct RawClaims {
+    /// GitHub: `owner/repo`
+    repository: Option<String>,
+    /// GitHub: `owner/repo/.github/workflows/release.yml@refs/heads/main`
+    job_workflow_ref: Option<String>,
+    /// GitLab: `group/project`
+    project_path: Option<String>,
+    /// GitLab: `gitlab.com/group/project//.gitlab-ci.yml@refs/heads/main`
+    ci_config_ref_uri: Option<String>,
+    /// GitHub: `refs/heads/main`, GitLab: `main`
+    #[serde(rename = "ref")]
+    git_ref: Option<String>,
+    /// GitLab: `branch` or `tag`
+    ref_type: Option<String>,
+}
+
+/// What a CI job is, as far as the rules are concerned.
+#[derive(Debug, PartialEq)]
+pub struct Job {
+    pub repository: String,
+    /// The file name of the workflow (or pipeline config) being run, if it's
+    /// in the job's own repository. One in another (a reusable workflow, say)
+    /// could be called from any workflow at all.
+    pub workflow: Option<String>,
+    pub branch: Option<String>,
+}
+
+impl RawClaims {
+    fn into_job(self, provider: Provider) -> Option<Job> {
+        match provider {
+            Provider::GitHub => {
+                let repository = self.repository?;
+                let workflows = format!("{}/.github/workflows/", repository);
+                let workflow = self
+                    .job_workflow_ref
+                    .as_deref()
+                    .and_then(|path| strip_prefix_ignore_case(config_path(path), &workflows))
+                    .filter(|file| !file.is_empty() && !file.contains('/'))
+                    .map(str::to_string);
+                Some(Job {
+                    repository,
+                    workflow,
+                    branch: self
+                        .git_ref
+                        .as_deref()
+                        .and_then(|r| r.strip_prefix("refs/heads/"))
+                        .map(str::to_string),
+                })
+            }
+            Provider::GitLab => {
+                let repository = self.project_path?;
+                // After the GitLab instance's host.
+                let project = format!("{}//", repository);
+                let workflow = self
+                    .ci_config_ref_uri
+                    .as_deref()
+                    .and_then(|uri| config_path(uri).split_once('/'))
+                    .and_then(|(_host, path)| strip_prefix_ignore_case(path, &project))
+                    .and_then(|path| path.rsplit('/').next())
+                    .filter(|file| !file.is_empty())
+                    .map(str::to_string);
+                Some(Job {
+                    repository,
+                    workflow,
+                    branch: match self.ref_type.as_deref() {
+                        Some("branch") => self.git_ref,
+                        _ => None,
+                    },
+                })
+            }
+        }
+    }
+}
+
+/// The config file's path, from the claim giving it followed by `@<ref>`.
+fn config_path(claim: &str) -> &str {
+    claim.split('@').next().unwrap_or_default()
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = s.get(..prefix.len())?;
+    if head.eq_ignore_ascii_case(prefix) {
+        Some(&s[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+impl TrustedPublisher {
+    /// Whether this rule allows `job` to publish.
+    fn allows(&self, job: &Job) -> bool {
+        // Repository names are case insensitive on both providers.
+        self.repository.eq_ignore_ascii_case(&job.repository)
+            && (self.workflow.is_none() || self.workflow == job.workflow)
+            && (self.branch.is_none() || self.branch == job.branch)
+    }
+}
+
+/// Whether the request carries what looks like a JWT, rather than one of our
+/// own tokens.
+pub fn is_id_token(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("eyJ") && value.matches('.').count() == 2)
+}
+
+/// Read the `iss` claim, without checking anything, so we know where to get
+/// the keys to check it with.
+fn unverified_issuer(id_token: &str) -> Option<String> {
+    #[derive(Deserialize)]
+    struct Issuer {
+        iss: String,
+    }
+    let payload = id_token.split('.').nth(1)?;
+    let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    serde_json::from_slice::<Issuer>(&payload)
+        .ok()
+        .map(|claims| claims.iss)
+}
+
+/// An ID token whose signature has been checked, but not yet against any
+/// crate's trusted publishers.
+pub struct IdToken {
+    issuer: String,
+    claims: RawClaims,
+}
+
+/// Check the ID token against the issuer's keys.
+fn decode(
+    id_token: &str,
+    jwks: &JwkSet,
+    issuer: &str,
+    audience: &str,
+) -> Result<IdToken, AuthError> {
+    let claims = oidc::decode_id_token(id_token, jwks, issuer, audience).map_err(|e| {
+        log::info!("Rejected ID token from `{}`: {}", issuer, e);
+        AuthError::Invalid
+    })?;
+    Ok(IdToken {
+        issuer: issuer.to_string(),
+        claims,
+    })
+}
+
+/// Check a decoded ID token against `rules` (which should all be for the same
+/// issuer as it).
+fn verify(
+    id_token: IdToken,
+    crate_name: &str,
+    rules: &[TrustedPublisher],
+) -> Result<(), AuthError> {
+    // Rules with the same issuer are (practically) for the same provider.
+    let provider = rules.first().ok_or(AuthError::Invalid)?.provider;
+    let job = id_token
+        .claims
+        .into_job(provider)
+        .ok_or(AuthError::Invalid)?;
+    if rules
+        .iter()
+        .any(|rule| rule.provider == provider && rule.allows(&job))
+    {
+        return Ok(());
+    }
+    Err(AuthError::NotTrusted(format!(
+        "No trusted publisher for `{}` matches repository `{}`, workflow `{}`, branch `{}`.",
+        crate_name,
+        job.repository,
+        job.workflow.as_deref().unwrap_or("-"),
+        job.branch.as_deref().unwrap_or("-"),
+    )))
+}
+
+fn unavailable(settings: &Settings) -> impl Fn(EstuaryError) -> HttpResponse + '_ {
+    move |e| {
+        log::error!("Failed to check trusted publishers: {}", e);
+        super::challenge(&AuthError::Unavailable, settings)
+    }
+}
+
+/// Check the ID token a publish was sent with, before its body is read.
+///
+/// All that's known yet is that it has to be signed by an issuer that some
+/// crate trusts; [`authorize`] checks it against the crate's own rules once
+/// the metadata has been read. The issuer's keys are kept for a while (see
+/// [`oidc::JwksCache`]).
+pub async fn authenticate(
+    request: &HttpRequest,
+    settings: &Settings,
+) -> Result<IdToken, HttpResponse> {
+    let challenge = |e: AuthError| super::challenge(&e, settings);
+    let unavailable = unavailable(settings);
+    let id_token = super::get_token(request).map_err(challenge)?;
+    let issuer = unverified_issuer(&id_token).ok_or_else(|| challenge(AuthError::Invalid))?;
+    let rules = settings
+        .with_db(|conn| database::list_trusted_publishers(conn, None))
+        .await
+        .map_err(&unavailable)?;
+    if !rules.iter().any(|rule| rule.issuer == issuer) {
+        return Err(challenge(AuthError::NotTrusted(format!(
+            "No crate has trusted publishers from `{}`.",
+            issuer
+        ))));
+    }
+
+    let jwks = settings
+        .jwks_cache
+        .get(&issuer)
+        .await
+        .map_err(|e| unavailable(e.into()))?;
+    match decode(&id_token, &jwks, &issuer, &settings.base_url) {
+        Ok(id_token) => Ok(id_token),
+        // The provider may have moved on to keys we don't have yet.
+        Err(_) => {
+            let jwks = settings
+                .jwks_cache
+                .refresh(&issuer)
+                .await
+                .map_err(|e| unavailable(e.into()))?;
+            decode(&id_token, &jwks, &issuer, &settings.base_url).map_err(challenge)
+        }
+    }
+}
+
+/// Guard for publishing `crate_name` with an ID token, once [`authenticate`]
+/// has checked it.
+///
+/// Only trusted publisher rules for `crate_name` are considered, so this has
+/// to wait until the crate's metadata has been read.
+pub async fn authorize(
+    settings: &Settings,
+    id_token: IdToken,
+    crate_name: &str,
+) -> Result<(), HttpResponse> {
+    let challenge = |e: AuthError| super::challenge(&e, settings);
+    let owned = crate_name.to_string();
+    let rules: Vec<_> = settings
+        .with_db(move |conn| database::list_trusted_publishers(conn, Some(&owned)))
+        .await
+        .map_err(unavailable(settings))?
+        .into_iter()
+        .filter(|rule| rule.issuer == id_token.issuer)
+        .collect();
+    if rules.is_empty() {
+        return Err(challenge(AuthError::NotTrusted(format!(
+            "`{}` has no trusted publishers from `{}`.",
+            crate_name, id_token.issuer
+        ))));
+    }
+    verify(id_token, crate_name, &rules).map_err(challenge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::oidc::tests::Provider as TestProvider;
+    use serde_json::json;
+
+    const AUDIENCE: &str = "http://localhost:7878";
+
+    fn rule(workflow: Option<&str>, branch: Option<&str>) -> TrustedPublisher {
+        TrustedPublisher {
+            id: 1,
+            crate_name: "my-crate".to_string(),
+            provider: Provider::GitHub,
+            issuer: Provider::GitHub.default_issuer().to_string(),
+            repository: "Example/my-crate".to_string(),
+            workflow: workflow.map(str::to_string),
+            branch: branch.map(str::to_string),
+        }
+    }
+
+    fn github_claims() -> serde_json::Value {
+        let mut claims = TestProvider::claims("");
+        claims["iss"] = json!(Provider::GitHub.default_issuer());
+        claims["aud"] = json!(AUDIENCE);
+        claims["repository"] = json!("example/my-crate");
+        claims["job_workflow_ref"] =
+            json!("example/my-crate/.github/workflows/release.yml@refs/heads/main");
+        claims["ref"] = json!("refs/heads/main");
+        claims
+    }
+
+    #[test]
+    fn test_jobs_from_claims() {
+        let github: RawClaims = serde_json::from_value(github_claims()).unwrap();
+        assert_eq!(
+            Some(Job {
+                repository: "example/my-crate".to_string(),
+                workflow: Some("release.yml".to_string()),
+                branch: Some("main".to_string()),
+            }),
+            github.into_job(Provider::GitHub)
+        );
+
+        let gitlab: RawClaims = serde_json::from_value(json!({
+            "project_path": "group/my-crate",
+            "ci_config_ref_uri": "gitlab.com/group/my-crate//.gitlab-ci.yml@refs/tags/v1.0.0",
+            "ref": "v1.0.0",
+            "ref_type": "tag",
+        }))
+        .unwrap();
+        assert_eq!(
+            Some(Job {
+                repository: "group/my-crate".to_string(),
+                workflow: Some(".gitlab-ci.yml".to_string()),
+                branch: None,
+            }),
+            gitlab.into_job(Provider::GitLab)
+        );
+
+        // A workflow file from another repository isn't this one's to vouch
+        // for, however it's named.
+        let mut claims = github_claims();
+        claims["job_workflow_ref"] =
+            json!("someone/else/.github/workflows/release.yml@refs/heads/main");
+        let reusable: RawClaims = serde_json::from_value(claims).unwrap();
+        assert_eq!(None, reusable.into_job(Provider::GitHub).unwrap().workflow);
+        let gitlab: RawClaims = serde_json::from_value(json!({
+            "project_path": "group/my-crate",
+            "ci_config_ref_uri": "gitlab.com/someone/else//.gitlab-ci.yml@refs/heads/main",
+        }))
+        .unwrap();
+        assert_eq!(None, gitlab.into_job(Provider::GitLab).unwrap().workflow);
+
+        // GitHub claims don't make a GitLab job.
+        let github: RawClaims = serde_json::from_value(github_claims()).unwrap();
+        assert_eq!(None, github.into_job(Provider::GitLab));
+    }
+
+    #[test]
+    fn test_verify() {
+        let provider = TestProvider::new();
+        let issuer = Provider::GitHub.default_issuer();
+        let check = |claims: serde_json::Value, rules: &[TrustedPublisher]| {
+            let id_token = decode(&provider.sign(claims), &provider.jwks, issuer, AUDIENCE)?;
+            verify(id_token, "my-crate", rules)
+        };
+
+        assert_eq!(Ok(()), check(github_claims(), &[rule(None, None)]));
+        assert_eq!(
+            Ok(()),
+            check(github_claims(), &[rule(Some("release.yml"), Some("main"))])
+        );
+        assert!(matches!(
+            check(github_claims(), &[rule(Some("ci.yml"), None)]),
+            Err(AuthError::NotTrusted(_))
+        ));
+        assert!(matches!(
+            check(github_claims(), &[rule(None, Some("develop"))]),
+            Err(AuthError::NotTrusted(_))
+        ));
+        let mut reusable = github_claims();
+        reusable["job_workflow_ref"] =
+            json!("someone/else/.github/workflows/release.yml@refs/heads/main");
+        assert!(matches!(
+            check(reusable, &[rule(Some("release.yml"), None)]),
+            Err(AuthError::NotTrusted(_))
+        ));
+
+        let mut wrong_audience = github_claims();
+        wrong_audience["aud"] = json!("https://crates.io");
+        assert_eq!(
+            Err(AuthError::Invalid),
+            check(wrong_audience, &[rule(None, None)])
+        );
+    }
+
+    #[test]
+    fn test_is_id_token() {
+        let provider = TestProvider::new();
+        let with = |value: &str| {
+            actix_web::test::TestRequest::default()
+                .header("authorization", value)
+                .to_http_request()
+        };
+        let id_token = provider.sign(github_claims());
+        assert!(is_id_token(&with(&id_token)));
+        assert!(!is_id_token(&with("estAX0anCUEKIxPKyPRZaG8z8T8mCXBszqf")));
+        assert!(!is_id_token(&with("v3.public.eyJ...")));
+        assert_eq!(
+            Some(Provider::GitHub.default_issuer().to_string()),
+            unverified_issuer(&id_token)
+        );
+    }
+}
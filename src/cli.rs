@@ -1,15 +1,108 @@
 //! Some of the fields on `Opt` require careful handling currently managed
 //! through getters. In order to restrict direct access to those
 //! getter-accessed fields, we tuck it away in this module.
-use std::path::PathBuf;
+use crate::auth::backend::LdapConfig;
+use crate::auth::oidc::OidcConfig;
+use crate::auth::trusted::Provider;
+use crate::auth::{BasicAuthArea, Scope};
+use crate::database::{DatabaseUrl, JournalMode, Synchronous};
+use crate::errors::EstuaryError;
+use crate::handlers::frontend::Branding;
+use crate::package_index::mirror::Mirror;
+use crate::package_index::signing::SigningKey;
+use crate::package_index::{self, Author, Committer, Config, IndexProtocol};
+use crate::publish_policy::{self, PublishPolicy};
+use crate::storage::azure::AzureConfig;
+use crate::storage::encrypted::EncryptedStore;
+use crate::storage::gcs::{GcsConfig, GcsStore};
+use crate::storage::s3::S3Config;
+use crate::storage::{self, CrateStore, Layout, LocalStore};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use structopt::clap::Shell;
 use structopt::StructOpt;
 
+/// The name the `--publish-key` token is stored under in the database.
+pub const PUBLISH_KEY_TOKEN_NAME: &str = "publish-key";
+
 /// Something about the macros used by `structopt` mean the return from
 /// `from_args()` is <unknown> in code editors without a type ascription or some
 /// other
 /// hint. This function provides such a hint.
-pub fn parse_args() -> Opt {
-    Opt::from_args()
+pub fn parse_args() -> Command {
+    Command::from_args()
+}
+
+// Only ever made once, while starting up.
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt)]
+pub enum Command {
+    /// Run the registry server.
+    Run(RunOpt),
+    /// Manage the API tokens cargo uses to authenticate.
+    Token(TokenOpt),
+    /// Manage the users who can log in with HTTP Basic auth (see `--basic-auth`).
+    User(UserOpt),
+    /// Manage which CI jobs may publish crates with their OIDC ID tokens.
+    Trust(TrustOpt),
+    /// Manage which tokens and users may publish and yank which crates.
+    Grant(GrantOpt),
+    /// Manage the teams that can own crates together.
+    Team(TeamOpt),
+    /// Manage the crate names set aside for users and teams.
+    Reserve(ReserveOpt),
+    /// Manage the `.crate` files in the crate dir.
+    Storage(StorageOpt),
+    /// Check the index, the database, and the crate files against each other,
+    /// given the same options as `run`.
+    Verify(Opt),
+    /// Copy the database to a new file, while the server carries on using it.
+    BackupDb(BackupDbOpt),
+    /// Report how big the registry is, for capacity planning.
+    Stats(StatsOpt),
+    /// Change crates directly, rather than over http, given the same options
+    /// as `run`.
+    Admin(AdminCommand),
+    /// Check the index repo over, given the same options as `run`.
+    Fsck(FsckOpt),
+    /// Squash the index history into a single commit, given the same options
+    /// as `run`. The history before is kept under `refs/snapshots/`.
+    SquashIndex(Opt),
+    /// Check the server's configuration, given the same options as `run`:
+    /// that it answers at the base url, and can reach and write to everything
+    /// it needs to.
+    Doctor(Opt),
+    /// Bundle the index repo, the crate files, and the database into one
+    /// archive, given the same options as `run`.
+    Export(ArchiveOpt),
+    /// Set up a new instance from an archive made by `export`, given the
+    /// options it'll be run with.
+    Import(ArchiveOpt),
+    /// Write a new index repo from the database and the crate files, given
+    /// the same options as `run`, for when the index has been lost.
+    RebuildIndex(Opt),
+    /// Publish every `.crate` file in a directory, given the same options as
+    /// `run`.
+    ImportDir(ImportDirOpt),
+    /// Print a script for a shell to complete estuary's subcommands and
+    /// options with.
+    Completions(CompletionsOpt),
+}
+
+#[derive(StructOpt)]
+pub struct RunOpt {
+    #[structopt(flatten)]
+    pub opt: Opt,
+
+    #[structopt(
+        long,
+        help = "Run a throwaway registry, for trying estuary out or working on the web UI: \
+        everything is kept in a temporary directory (with a few sample crates, and a token \
+        to publish more with) that's deleted once the server stops. `--base-url`, \
+        `--index-dir`, `--crate-dir`, and where the database and crate files are kept \
+        are all ignored."
+    )]
+    pub dev: bool,
 }
 
 #[derive(StructOpt)]
@@ -17,6 +110,8 @@ pub struct Opt {
     #[structopt(
         long,
         env = "ESTUARY_BASE_URL",
+        required_unless = "dev",
+        default_value_if("dev", None, ""),
         help = "The public url for the service."
     )]
     base_url: String,
@@ -25,6 +120,8 @@ pub struct Opt {
         long,
         parse(from_os_str),
         env = "ESTUARY_INDEX_DIR",
+        required_unless = "dev",
+        default_value_if("dev", None, ""),
         help = "A directory to store the package index git repo."
     )]
     pub index_dir: PathBuf,
@@ -33,96 +130,1875 @@ pub struct Opt {
         long,
         parse(from_os_str),
         env = "ESTUARY_CRATE_DIR",
+        required_unless = "dev",
+        default_value_if("dev", None, ""),
         help = "A directory to store `.crate` files."
     )]
     pub crate_dir: PathBuf,
 
+    #[structopt(
+        long,
+        env = "ESTUARY_CRATE_DIR_LAYOUT",
+        default_value = "flat",
+        possible_values = &["flat", "sharded"],
+        help = "How to arrange the `--crate-dir`. `sharded` spreads files over more \
+        directories, for registries with a lot of crates. Files still in the other layout \
+        are found all the same, until `estuary storage relocate` moves them."
+    )]
+    pub crate_dir_layout: Layout,
+
     #[structopt(
         long,
         env = "ESTUARY_DOWNLOAD_URL",
+        visible_alias = "dl-url",
         help = "The url template cargo will use when downloading crates from the registry. \
         Defaults to `<base_url>/api/v1/crates/{crate}/{version}/download`."
     )]
     download_url: Option<String>,
 
+    #[structopt(
+        long,
+        env = "ESTUARY_API_URL",
+        help = "The url cargo will use for the web API (publish, yank, search...). \
+        Defaults to `<base_url>`."
+    )]
+    api_url: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_INDEX_URL",
+        help = "The url cargo should use to fetch the package index. \
+        Defaults to `<base_url>/git/index`, or `sparse+<base_url>/index/` when \
+        only the sparse protocol is enabled."
+    )]
+    index_url: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_REGISTRY_NAME",
+        default_value = "estuary",
+        help = "The name users give the registry in their cargo config, as used in the \
+        snippets shown in the web UI."
+    )]
+    pub registry_name: String,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_SITE_TITLE",
+        default_value = "Estuary",
+        help = "What the web UI calls the registry, at the top of every page and in their titles."
+    )]
+    site_title: String,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_SITE_LOGO_URL",
+        help = "The url of an image to show beside the title in the web UI."
+    )]
+    site_logo_url: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_SITE_FOOTER",
+        help = "Text for the footer of every page in the web UI, ex: who to ask for help."
+    )]
+    site_footer: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_SITE_BANNER",
+        help = "An announcement to show at the top of every page in the web UI, ex: planned \
+        downtime."
+    )]
+    site_banner: Option<String>,
+
     #[structopt(long, default_value = "0.0.0.0", env = "ESTUARY_HTTP_HOST")]
     pub http_host: String,
 
     #[structopt(long, default_value = "7878", env = "ESTUARY_HTTP_PORT")]
     pub http_port: u16,
 
+    /// No longer used, since the index is served without running `git`. Still
+    /// accepted so existing configurations keep working.
+    #[structopt(long, parse(from_os_str), env = "ESTUARY_GIT_BIN", hidden = true)]
+    pub git_bin: Option<PathBuf>,
+
     #[structopt(
         long,
         parse(from_os_str),
-        env = "ESTUARY_GIT_BIN",
-        default_value = "git",
-        help = "Path to `git`."
+        env = "ESTUARY_DB_PATH",
+        help = "Path to the SQLite database used to store tokens and users. \
+        Defaults to `<crate_dir>/estuary.db`."
     )]
-    pub git_bin: PathBuf,
+    db_path: Option<PathBuf>,
 
-    #[structopt(long, env = "ESTUARY_PUBLISH_KEY")]
-    pub publish_key: Option<String>
-}
+    #[structopt(
+        long,
+        env = "ESTUARY_DATABASE_URL",
+        hide_env_values = true,
+        help = "The database to use in place of the SQLite file at `--db-path`, ex: \
+        `postgres://estuary@db/estuary`, so several servers can share one."
+    )]
+    database_url: Option<DatabaseUrl>,
 
-impl Opt {
-    /// Public getter for the `base_url` field.
-    ///
-    /// Mainly this just ensures there are no trailing slashes in there.
-    pub fn base_url(&self) -> &str {
-        self.base_url.trim_end_matches('/')
-    }
+    #[structopt(
+        long,
+        env = "ESTUARY_DB_POOL_SIZE",
+        default_value = "8",
+        help = "The most database connections to have open at once."
+    )]
+    pub db_pool_size: u32,
 
-    /// Returns the value of the `download_url` field verbatim when set.
-    ///
-    /// When left `download_url` is left unset, the path to the download handler
-    /// is built based on the value of the base url.
-    pub fn download_url(&self) -> String {
-        self.download_url.clone().unwrap_or_else(|| {
-            format!(
-                "{}/api/v1/crates/{{crate}}/{{version}}/download",
-                self.base_url()
-            )
-        })
-    }
-}
+    #[structopt(
+        long,
+        env = "ESTUARY_DB_BUSY_TIMEOUT",
+        default_value = "5000",
+        help = "How long (in milliseconds) to wait on another connection's write to the \
+        database before giving up."
+    )]
+    pub db_busy_timeout: u64,
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[structopt(
+        long,
+        env = "ESTUARY_DB_JOURNAL_MODE",
+        default_value = "wal",
+        possible_values = &["delete", "truncate", "persist", "wal"],
+        help = "How SQLite keeps its journal. `wal` lets reads carry on during a publish, \
+        but doesn't work with the database on a network filesystem."
+    )]
+    pub db_journal_mode: JournalMode,
 
-    #[test]
-    fn test_base_url_trims_trailing_slashes() {
-        let opt = Opt {
-            // weird
-            base_url: "http://example.com/////".to_string(),
-            index_dir: Default::default(),
-            crate_dir: Default::default(),
-            download_url: None,
-            http_host: "".to_string(),
-            http_port: 0,
-            git_bin: Default::default(),
-            publish_key: Default::default()
-        };
+    #[structopt(
+        long,
+        env = "ESTUARY_DB_SYNCHRONOUS",
+        default_value = "normal",
+        possible_values = &["off", "normal", "full", "extra"],
+        help = "How often SQLite waits for writes to reach the disk. `normal` is safe from \
+        corruption in `wal` mode; `full` also keeps every commit through a power cut."
+    )]
+    pub db_synchronous: Synchronous,
 
-        assert_eq!("http://example.com", opt.base_url());
-    }
+    #[structopt(
+        long,
+        env = "ESTUARY_PUBLISH_KEY",
+        help = "A token to issue at startup (under the name `publish-key`). \
+        Mostly useful for bootstrapping, and for deployments predating the token database."
+    )]
+    pub publish_key: Option<String>,
 
-    #[test]
-    fn test_download_url_default() {
-        let opt = Opt {
-            base_url: "http://example.com".to_string(),
-            index_dir: Default::default(),
-            crate_dir: Default::default(),
-            download_url: None,
-            http_host: "".to_string(),
-            http_port: 0,
-            git_bin: Default::default(),
-            publish_key: Default::default()
-        };
+    #[structopt(
+        long,
+        env = "ESTUARY_AUTH_REQUIRED",
+        parse(try_from_str),
+        default_value = "false",
+        help = "Require a token for index reads and crate downloads, too."
+    )]
+    pub auth_required: bool,
 
-        assert_eq!(
-            "http://example.com/api/v1/crates/{crate}/{version}/download",
-            opt.download_url()
+    #[structopt(
+        long,
+        env = "ESTUARY_PRIVATE",
+        parse(try_from_str),
+        default_value = "false",
+        help = "Require a token for everything: the index (git and sparse), downloads, search, \
+        and the web UI. Implies `--auth-required`."
+    )]
+    pub private: bool,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_BASIC_AUTH",
+        use_delimiter = true,
+        possible_values = &["web", "git"],
+        help = "Require a login (see `estuary user`) for the web UI, the git index, or both. \
+        Tokens are also accepted as the password."
+    )]
+    pub basic_auth: Vec<BasicAuthArea>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_WEB_TOKENS",
+        parse(try_from_str),
+        default_value = "false",
+        help = "Let visitors to the `/me` page issue themselves a token. \
+        Only enable this when the web UI is reachable by trusted users alone."
+    )]
+    pub web_tokens: bool,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_SIGNUP",
+        parse(try_from_str),
+        default_value = "false",
+        help = "Let visitors to the `/me` page sign up for an account, with which they can \
+        issue themselves tokens."
+    )]
+    pub signup: bool,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_RATE_LIMIT",
+        help = "How many publishes, yanks, and unyanks each token may make per minute. \
+        Unlimited by default."
+    )]
+    pub rate_limit: Option<usize>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_OIDC_ISSUER",
+        requires_all = &["oidc-client-id", "oidc-client-secret"],
+        help = "An OpenID Connect provider to log in to the web UI with, ex: \
+        `https://accounts.google.com`. Logged in users can issue themselves tokens from \
+        the `/me` page. Register `<base-url>/me/oidc/callback` as the redirect url."
+    )]
+    oidc_issuer: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_OIDC_CLIENT_ID",
+        requires = "oidc-issuer",
+        help = "The client id Estuary is registered with at the `--oidc-issuer`."
+    )]
+    oidc_client_id: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_OIDC_CLIENT_SECRET",
+        hide_env_values = true,
+        requires = "oidc-issuer",
+        help = "The client secret that goes with `--oidc-client-id`."
+    )]
+    oidc_client_secret: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_OIDC_ALLOW",
+        use_delimiter = true,
+        requires = "oidc-issuer",
+        help = "Who may log in through the `--oidc-issuer`: email addresses (ex: \
+        `alice@example.com`), email domains (ex: `@example.com`), or groups from the ID \
+        token's `groups` claim (ex: `group:developers`). Anyone the provider knows may log in \
+        when left out, so it's needed with `--private`."
+    )]
+    oidc_allow: Vec<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_LDAP_URL",
+        help = "A directory server to check passwords against (for `--basic-auth` and the \
+        `/me` page) instead of the users added with `estuary user`, ex: \
+        `ldaps://ldap.example.com`. Needs `--ldap-user-dn` or `--ldap-base-dn`."
+    )]
+    ldap_url: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_LDAP_USER_DN",
+        requires = "ldap-url",
+        help = "Users' DNs, with `{login}` standing in for the login, ex: \
+        `uid={login},ou=people,dc=example,dc=com`. Users are bound as directly."
+    )]
+    ldap_user_dn: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_LDAP_BASE_DN",
+        requires = "ldap-url",
+        help = "Where to search for users (with `--ldap-user-filter`) when their DN can't be \
+        worked out from their login, ex: `dc=example,dc=com`."
+    )]
+    ldap_base_dn: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_LDAP_USER_FILTER",
+        default_value = "(uid={login})",
+        help = "The filter to search for users with. For Active Directory, try \
+        `(sAMAccountName={login})`."
+    )]
+    ldap_user_filter: String,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_LDAP_BIND_DN",
+        requires_all = &["ldap-url", "ldap-bind-password"],
+        help = "Who to bind as to search for users, when anonymous searches aren't allowed."
+    )]
+    ldap_bind_dn: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_LDAP_BIND_PASSWORD",
+        hide_env_values = true,
+        requires = "ldap-bind-dn",
+        help = "The password for `--ldap-bind-dn`."
+    )]
+    ldap_bind_password: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_S3_BUCKET",
+        requires_all = &["s3-access-key-id", "s3-secret-access-key"],
+        help = "Keep crate files in this S3-compatible bucket instead of the `--crate-dir`."
+    )]
+    s3_bucket: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_S3_ENDPOINT",
+        requires = "s3-bucket",
+        help = "Where the `--s3-bucket` is, ex: `http://localhost:9000` for a local MinIO. \
+        Defaults to AWS, for the `--s3-region`."
+    )]
+    s3_endpoint: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_S3_REGION",
+        default_value = "us-east-1",
+        help = "The region the `--s3-bucket` is in."
+    )]
+    s3_region: String,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_S3_ACCESS_KEY_ID",
+        requires = "s3-bucket",
+        help = "The access key to sign requests to the `--s3-bucket` with."
+    )]
+    s3_access_key_id: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_S3_SECRET_ACCESS_KEY",
+        hide_env_values = true,
+        requires = "s3-bucket",
+        help = "The secret that goes with `--s3-access-key-id`."
+    )]
+    s3_secret_access_key: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_GCS_BUCKET",
+        requires = "gcs-credentials",
+        conflicts_with = "s3-bucket",
+        help = "Keep crate files in this Google Cloud Storage bucket instead of the `--crate-dir`."
+    )]
+    gcs_bucket: Option<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = "ESTUARY_GCS_CREDENTIALS",
+        requires = "gcs-bucket",
+        help = "The JSON key file of the service account to use the `--gcs-bucket` as."
+    )]
+    gcs_credentials: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_AZURE_CONTAINER",
+        requires_all = &["azure-account", "azure-access-key"],
+        conflicts_with_all = &["s3-bucket", "gcs-bucket"],
+        help = "Keep crate files in this Azure Blob Storage container instead of the `--crate-dir`."
+    )]
+    azure_container: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_AZURE_ACCOUNT",
+        requires = "azure-container",
+        help = "The storage account the `--azure-container` is in."
+    )]
+    azure_account: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_AZURE_ACCESS_KEY",
+        hide_env_values = true,
+        requires = "azure-container",
+        help = "An access key for the `--azure-account`."
+    )]
+    azure_access_key: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_AZURE_ENDPOINT",
+        requires = "azure-container",
+        help = "Where the `--azure-account` is, ex: `http://127.0.0.1:10000/devstoreaccount1` \
+        for Azurite. Defaults to `https://<account>.blob.core.windows.net`."
+    )]
+    azure_endpoint: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_VERIFY_DOWNLOADS",
+        parse(try_from_str),
+        default_value = "false",
+        help = "Check each crate file against its checksum before serving it, refusing \
+        (with a `500`) any that don't match."
+    )]
+    pub verify_downloads: bool,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_ENCRYPTION_KEY",
+        hide_env_values = true,
+        help = "Encrypt crate files before storing them (wherever they're stored) with this \
+        key: 32 random bytes, base64 encoded, ex: from `openssl rand -base64 32`. Files stored \
+        before there was a key are still served as they are."
+    )]
+    pub encryption_key: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_SCAN_INTERVAL",
+        help = "Every how many hours to check all the stored crate files against their \
+        checksums in the background, logging any that don't match. Off by default."
+    )]
+    pub scan_interval: Option<u64>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_SQUASH_INDEX_INTERVAL",
+        help = "Every how many days to squash the index history into a single commit in the \
+        background, as `estuary squash-index` does. Off by default."
+    )]
+    pub squash_index_interval: Option<u64>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_INDEX_COMMIT_WINDOW",
+        help = "Gather the index changes made within this many milliseconds into a single \
+        commit, rather than making one for each publish or yank. Changes show up in the sparse \
+        index straight away, and over git once they're committed. Off by default."
+    )]
+    pub index_commit_window: Option<u64>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_FALLBACK_AUTHOR",
+        help = "Who to credit in the index history for changes with no publisher to credit \
+        (publishes without a token, and `estuary admin` commands), ex: \
+        `Registry Admins <registry@example.com>`. Defaults to the `--git-author-name` and \
+        `--git-author-email`."
+    )]
+    pub fallback_author: Option<Author>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_GIT_AUTHOR_NAME",
+        help = "The name index commits are made under. Defaults to `estuary`."
+    )]
+    pub git_author_name: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_GIT_AUTHOR_EMAIL",
+        help = "The email address index commits are made under, ex: `registry@example.com`. \
+        Defaults to `admin@localhost`."
+    )]
+    pub git_author_email: Option<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = "ESTUARY_SIGNING_KEY",
+        help = "An ssh or OpenPGP private key file to sign every index commit with, so the \
+        history can be checked with `git log --show-signature`. Off by default."
+    )]
+    pub signing_key: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = "ESTUARY_SIGNING_KEY_PASSPHRASE_FILE",
+        requires = "signing-key",
+        help = "A file holding the passphrase `--signing-key` is encrypted with, if it is. Or \
+        give the passphrase itself in `ESTUARY_SIGNING_KEY_PASSPHRASE` (it can't be given on \
+        the command line, where anyone can see it)."
+    )]
+    pub signing_key_passphrase_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_MAX_CRATE_SIZE",
+        parse(try_from_str = parse_size),
+        help = "The biggest crate file that can be published, ex: `10M`. Bigger ones are \
+        refused before any of them is stored. There's no limit by default, besides what can be \
+        read back from an object store."
+    )]
+    max_crate_size: Option<u64>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_DENY_WILDCARD_DEPS",
+        parse(try_from_str),
+        default_value = "false",
+        help = "Refuse to publish crates with dependencies that have no upper bound on their \
+        version, like `*` or `>= 1`. Stricter than crates.io, which only refuses `*`."
+    )]
+    pub deny_wildcard_deps: bool,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_DEP_REGISTRIES",
+        use_delimiter = true,
+        help = "The index urls of the only registries (besides this one) published crates' \
+        dependencies may come from, ex: `https://github.com/rust-lang/crates.io-index` for \
+        crates.io. Any registry will do by default."
+    )]
+    pub dep_registries: Vec<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_CATEGORIES_FILE",
+        help = "A file listing the categories crates may be in, a slug per line (ex: \
+        `development-tools::testing`). Others are left out when crates are published, with a \
+        warning. Any category will do by default."
+    )]
+    pub categories_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_ALLOWED_NAMES",
+        use_delimiter = true,
+        help = "Glob patterns (ex: `acme-*`), or regexes between slashes (ex: `/^acme-[a-z]+$/`), \
+        for the only names new crates may be published under. `-` and `_` count the same, as \
+        they do to cargo. Crates already in the index can still be published to. Any name will \
+        do by default."
+    )]
+    pub allowed_names: Vec<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_DENIED_NAMES",
+        use_delimiter = true,
+        help = "Glob patterns (or regexes, between slashes) for names new crates can't be \
+        published under, even if they're allowed."
+    )]
+    pub denied_names: Vec<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_DENIED_NAMES_FILE",
+        help = "A file of more `--denied-names`, a pattern per line, ex: the names of the most \
+        popular crates on crates.io, so they can't be mistaken for them."
+    )]
+    pub denied_names_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_CRATE_QUOTA",
+        parse(try_from_str = parse_size),
+        help = "The most crate files any one crate may have, over all its versions, ex: \
+        `500M`. Publishes that would go over are refused. Unlimited by default."
+    )]
+    pub crate_quota: Option<u64>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_REGISTRY_QUOTA",
+        parse(try_from_str = parse_size),
+        help = "The most crate files the registry may have, all told, ex: `20G`. Publishes \
+        that would go over are refused. Unlimited by default."
+    )]
+    pub registry_quota: Option<u64>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_INDEX_PROTOCOL",
+        default_value = "git",
+        possible_values = &["git", "sparse", "both"],
+        help = "The protocol(s) cargo can use to fetch the package index."
+    )]
+    pub index_protocol: IndexProtocol,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_INDEX_MIRROR_URL",
+        help = "A git remote to push a copy of the index to after every change, ex: \
+        `https://github.com/me/index.git`. Its history is overwritten to match. Off by default."
+    )]
+    pub index_mirror_url: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_INDEX_MIRROR_USERNAME",
+        help = "The username to log in to `--index-mirror-url` with, when the url doesn't \
+        have one. Defaults to `git`."
+    )]
+    pub index_mirror_username: Option<String>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_INDEX_MIRROR_PASSWORD",
+        hide_env_values = true,
+        help = "The password (or access token) to log in to an https `--index-mirror-url` \
+        with. Over ssh, the keys from the ssh agent are used."
+    )]
+    pub index_mirror_password: Option<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = "ESTUARY_TLS_CERT",
+        requires = "tls-key",
+        help = "A PEM file with the certificate chain to serve https with."
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = "ESTUARY_TLS_KEY",
+        requires = "tls-cert",
+        help = "A PEM file with the private key for `--tls-cert`."
+    )]
+    pub tls_key: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_H2C",
+        parse(try_from_str),
+        default_value = "false",
+        help = "Also accept cleartext HTTP/2 (prior knowledge) connections. \
+        Useful behind a proxy that speaks HTTP/2 to its upstreams. \
+        Ignored when serving https, where HTTP/2 is always available via ALPN."
+    )]
+    pub h2c: bool,
+}
+
+impl Opt {
+    /// These options, for `run --dev` with everything kept under `dir`.
+    pub fn dev(self, dir: &Path) -> Opt {
+        let scheme = if self.tls_cert.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        let crate_dir = dir.join("crates");
+        Opt {
+            base_url: format!("{}://localhost:{}", scheme, self.http_port),
+            index_dir: dir.join("index"),
+            crate_dir: crate_dir.clone(),
+            download_url: None,
+            api_url: None,
+            index_url: None,
+            db_path: Some(crate_dir.join("estuary.db")),
+            database_url: None,
+            s3_bucket: None,
+            gcs_bucket: None,
+            azure_container: None,
+            encryption_key: None,
+            index_mirror_url: None,
+            ..self
+        }
+    }
+
+    /// Public getter for the `base_url` field.
+    ///
+    /// Mainly this just ensures there are no trailing slashes in there.
+    pub fn base_url(&self) -> &str {
+        self.base_url.trim_end_matches('/')
+    }
+
+    /// Returns the value of the `download_url` field verbatim when set.
+    ///
+    /// When left `download_url` is left unset, the path to the download handler
+    /// is built based on the value of the base url.
+    pub fn download_url(&self) -> String {
+        self.download_url.clone().unwrap_or_else(|| {
+            format!(
+                "{}/api/v1/crates/{{crate}}/{{version}}/download",
+                self.base_url()
+            )
+        })
+    }
+
+    /// Returns the value of the `db_path` field verbatim when set, otherwise
+    /// a path inside the crate dir.
+    pub fn db_path(&self) -> PathBuf {
+        db_path_or_default(&self.db_path, &self.crate_dir)
+    }
+
+    /// The database to use: `--database-url` when it's set, otherwise the
+    /// SQLite file at [`db_path`](Self::db_path).
+    pub fn database_url(&self) -> DatabaseUrl {
+        self.database_url
+            .clone()
+            .unwrap_or_else(|| DatabaseUrl::Sqlite(self.db_path()))
+    }
+
+    /// The root url for the web API, without trailing slashes.
+    ///
+    /// Falls back to the base url when `api_url` is unset.
+    pub fn api_url(&self) -> &str {
+        self.api_url
+            .as_deref()
+            .unwrap_or(&self.base_url)
+            .trim_end_matches('/')
+    }
+
+    /// What the index's `config.json` should have.
+    pub fn index_config(&self) -> Config {
+        Config {
+            dl: self.download_url(),
+            api: self.api_url().to_string(),
+            auth_required: self.auth_required || self.private,
+        }
+    }
+
+    /// What the web UI shows to tell which registry it is. Blank text is
+    /// treated as unset, so it can be cleared from the environment.
+    pub fn branding(&self) -> Branding {
+        let text = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        Branding {
+            title: self.site_title.clone(),
+            logo_url: text(&self.site_logo_url),
+            footer: text(&self.site_footer),
+            banner: text(&self.site_banner),
+        }
+    }
+
+    /// The identity provider settings, when `--oidc-issuer` is set.
+    pub fn oidc(&self) -> Option<OidcConfig> {
+        // The client id and secret are required along with the issuer.
+        Some(OidcConfig {
+            issuer: self.oidc_issuer.clone()?,
+            client_id: self.oidc_client_id.clone()?,
+            client_secret: self.oidc_client_secret.clone()?,
+            allowed: self.oidc_allow.clone(),
+        })
+    }
+
+    /// Who index commits are made by.
+    pub fn git_identity(&self) -> Author {
+        let system = Author::default();
+        Author {
+            name: self.git_author_name.clone().unwrap_or(system.name),
+            email: self.git_author_email.clone().unwrap_or(system.email),
+        }
+    }
+
+    /// Who index commits are made by, and what they're signed with, reading
+    /// the `--signing-key` when there is one.
+    pub fn committer(&self) -> Result<Committer, EstuaryError> {
+        let signing_key = match &self.signing_key {
+            Some(path) => {
+                let passphrase = self.signing_key_passphrase()?;
+                Some(Arc::new(SigningKey::read(path, passphrase.as_deref())?))
+            }
+            None => None,
+        };
+        Ok(Committer {
+            identity: self.git_identity(),
+            signing_key,
+        })
+    }
+
+    /// The passphrase the `--signing-key` is encrypted with, from the
+    /// `--signing-key-passphrase-file` (its last line break left off) or else
+    /// `ESTUARY_SIGNING_KEY_PASSPHRASE`.
+    fn signing_key_passphrase(&self) -> Result<Option<String>, EstuaryError> {
+        match self.signing_key_passphrase_file {
+            Some(ref path) => {
+                let passphrase = std::fs::read_to_string(path).map_err(|e| {
+                    EstuaryError::Config(format!("can't read `{}`: {}", path.display(), e))
+                })?;
+                let passphrase = passphrase.strip_suffix('\n').unwrap_or(&passphrase);
+                Ok(Some(
+                    passphrase
+                        .strip_suffix('\r')
+                        .unwrap_or(passphrase)
+                        .to_string(),
+                ))
+            }
+            None => Ok(std::env::var("ESTUARY_SIGNING_KEY_PASSPHRASE").ok()),
+        }
+    }
+
+    /// Who index changes are credited to when nobody in particular made them.
+    pub fn fallback_author(&self) -> Author {
+        self.fallback_author
+            .clone()
+            .unwrap_or_else(|| self.git_identity())
+    }
+
+    /// The rules publishes are checked against, with the categories and
+    /// names from the files listing them.
+    pub fn publish_policy(&self) -> Result<PublishPolicy, EstuaryError> {
+        let read = |path: &Path| {
+            publish_policy::read_list(path).map_err(|e| {
+                EstuaryError::Config(format!("can't read `{}`: {}", path.display(), e))
+            })
+        };
+        let categories = match self.categories_file {
+            Some(ref path) => Some(read(path)?),
+            None => None,
+        };
+        let denied_names_file = match self.denied_names_file {
+            Some(ref path) => read(path)?,
+            None => Default::default(),
+        };
+        Ok(PublishPolicy {
+            deny_wildcard_deps: self.deny_wildcard_deps,
+            dep_registries: self.dep_registries.clone(),
+            categories,
+            allowed_names: name_patterns(&self.allowed_names)?,
+            denied_names: name_patterns(self.denied_names.iter().chain(&denied_names_file))?,
+        })
+    }
+
+    /// The biggest crate file that can be published, if there's a limit.
+    ///
+    /// Crate files in an object store are read back (when they're checked,
+    /// or archived) a whole file at a time, so they're held to what can be.
+    pub fn max_crate_size(&self) -> Result<Option<u64>, EstuaryError> {
+        if self.s3().is_none() && self.gcs().is_none() && self.azure().is_none() {
+            return Ok(self.max_crate_size);
+        }
+        let limit = storage::MAX_OBJECT_SIZE as u64;
+        match self.max_crate_size {
+            Some(size) if size > limit => Err(EstuaryError::Config(format!(
+                "`--max-crate-size` can't be over {} bytes with an object store, since that's the \
+                 most that's read back from one at once.",
+                limit
+            ))),
+            Some(size) => Ok(Some(size)),
+            None => Ok(Some(limit)),
+        }
+    }
+
+    /// Where to push a copy of the index, when `--index-mirror-url` is set.
+    pub fn index_mirror(&self) -> Option<Mirror> {
+        Some(Mirror {
+            url: self.index_mirror_url.clone()?,
+            username: self.index_mirror_username.clone(),
+            password: self.index_mirror_password.clone(),
+        })
+    }
+
+    /// The directory server settings, when `--ldap-url` is set.
+    pub fn ldap(&self) -> Option<LdapConfig> {
+        Some(LdapConfig {
+            url: self.ldap_url.clone()?,
+            user_dn: self.ldap_user_dn.clone(),
+            base_dn: self.ldap_base_dn.clone(),
+            user_filter: self.ldap_user_filter.clone(),
+            bind_dn: self.ldap_bind_dn.clone(),
+            bind_password: self.ldap_bind_password.clone(),
+        })
+    }
+
+    /// The bucket to keep crate files in, when `--s3-bucket` is set.
+    pub fn s3(&self) -> Option<S3Config> {
+        let endpoint = self
+            .s3_endpoint
+            .clone()
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", self.s3_region));
+        Some(S3Config {
+            endpoint,
+            bucket: self.s3_bucket.clone()?,
+            region: self.s3_region.clone(),
+            access_key_id: self.s3_access_key_id.clone()?,
+            secret_access_key: self.s3_secret_access_key.clone()?,
+        })
+    }
+
+    /// Where crate files are kept: the object store configured, if any,
+    /// otherwise the `--crate-dir`, encrypted when there's a key.
+    pub fn crate_store(&self) -> Result<Arc<dyn CrateStore>, EstuaryError> {
+        let mut store: Arc<dyn CrateStore> = match (self.s3(), self.gcs(), self.azure()) {
+            (Some(s3), _, _) => Arc::new(s3),
+            (_, Some(gcs), _) => Arc::new(GcsStore::new(gcs)?),
+            (_, _, Some(azure)) => Arc::new(azure),
+            _ => Arc::new(LocalStore {
+                root: self.crate_dir.clone(),
+                layout: self.crate_dir_layout,
+            }),
+        };
+        if let Some(ref key) = self.encryption_key {
+            store = Arc::new(EncryptedStore::new(store, key)?);
+        }
+        Ok(store)
+    }
+
+    /// The bucket to keep crate files in, when `--gcs-bucket` is set.
+    pub fn gcs(&self) -> Option<GcsConfig> {
+        Some(GcsConfig {
+            bucket: self.gcs_bucket.clone()?,
+            credentials: self.gcs_credentials.clone()?,
+        })
+    }
+
+    /// The container to keep crate files in, when `--azure-container` is set.
+    pub fn azure(&self) -> Option<AzureConfig> {
+        Some(AzureConfig {
+            account: self.azure_account.clone()?,
+            access_key: self.azure_access_key.clone()?,
+            container: self.azure_container.clone()?,
+            endpoint: self.azure_endpoint.clone(),
+        })
+    }
+
+    /// Returns the value of the `index_url` field verbatim when set.
+    ///
+    /// Otherwise the url is built from the base url, pointing at whichever
+    /// index protocol is being served (preferring git when both are).
+    pub fn index_url(&self) -> String {
+        self.index_url.clone().unwrap_or_else(|| {
+            if self.index_protocol.git_enabled() {
+                format!("{}/git/index", self.base_url())
+            } else {
+                format!("sparse+{}/index/", self.base_url())
+            }
+        })
+    }
+
+    /// Every url cargo may know this registry's index by, the same as the
+    /// server's [`Settings::index_urls`](crate::Settings::index_urls).
+    pub fn index_urls(&self) -> Vec<String> {
+        package_index::index_urls(&self.index_url(), self.base_url(), self.index_protocol)
+    }
+}
+
+#[derive(StructOpt)]
+pub struct UserOpt {
+    #[structopt(flatten)]
+    pub db: DbOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: UserCommand,
+}
+
+#[derive(StructOpt)]
+pub enum UserCommand {
+    /// Add a user, or change their password. The password is read from stdin.
+    Add {
+        /// The name to log in with.
+        login: String,
+    },
+    /// List the users.
+    List,
+    /// Remove a user.
+    Remove {
+        /// The name the user logs in with.
+        login: String,
+    },
+    /// Make a user an admin. Their tokens can then act on any crate, and use
+    /// the endpoints that need the `admin` scope.
+    Promote {
+        /// The name the user logs in with.
+        login: String,
+    },
+    /// Stop a user being an admin.
+    Demote {
+        /// The name the user logs in with.
+        login: String,
+    },
+    /// Set the most the tokens a user issues themselves (from the `/me` page)
+    /// may do. Users start out with `read` alone, unless they log in through
+    /// single sign-on.
+    Scopes {
+        /// The name the user logs in with.
+        login: String,
+        #[structopt(
+            use_delimiter = true,
+            possible_values = &["read", "publish", "yank"],
+        )]
+        scopes: Vec<Scope>,
+    },
+}
+
+fn db_path_or_default(db_path: &Option<PathBuf>, crate_dir: &Path) -> PathBuf {
+    db_path
+        .clone()
+        .unwrap_or_else(|| crate_dir.join("estuary.db"))
+}
+
+/// Parse each of `patterns` with [publish_policy::name_pattern].
+fn name_patterns<'a>(
+    patterns: impl IntoIterator<Item = &'a String>,
+) -> Result<Vec<publish_policy::NamePattern>, EstuaryError> {
+    patterns
+        .into_iter()
+        .map(|pattern| {
+            publish_policy::name_pattern(pattern)
+                .map_err(|e| EstuaryError::Config(format!("`{}` isn't a pattern: {}", pattern, e)))
+        })
+        .collect()
+}
+
+/// A number of bytes, optionally in `K`, `M`, `G`, or `T` (powers of 1024).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&s[..i], 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&s[..i], 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&s[..i], 30),
+        Some((i, 'T')) | Some((i, 't')) => (&s[..i], 40),
+        _ => (s, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("`{}` isn't a size, ex: `512M`", s))
+}
+
+/// Where the shell subcommands find the database.
+#[derive(StructOpt)]
+pub struct DbOpt {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = "ESTUARY_CRATE_DIR",
+        required_unless_one = &["db-path", "database-url"],
+        help = "The server's crate dir, used to find the database when `--db-path` \
+        (or `--database-url`) isn't given."
+    )]
+    crate_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = "ESTUARY_DB_PATH",
+        help = "Path to the SQLite database used to store tokens and users. \
+        Defaults to `<crate_dir>/estuary.db`."
+    )]
+    db_path: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        env = "ESTUARY_DATABASE_URL",
+        hide_env_values = true,
+        help = "The database to use in place of the SQLite file at `--db-path`, ex: \
+        `postgres://estuary@db/estuary`."
+    )]
+    database_url: Option<DatabaseUrl>,
+}
+
+impl DbOpt {
+    /// The database to operate on, found the same way the server finds it.
+    pub fn db_path(&self) -> PathBuf {
+        // `crate_dir` is required when `db_path` is missing.
+        db_path_or_default(
+            &self.db_path,
+            self.crate_dir.as_deref().unwrap_or(Path::new("")),
+        )
+    }
+
+    /// The database to operate on, found the same way the server finds it.
+    pub fn database_url(&self) -> DatabaseUrl {
+        self.database_url
+            .clone()
+            .unwrap_or_else(|| DatabaseUrl::Sqlite(self.db_path()))
+    }
+}
+
+#[derive(StructOpt)]
+pub struct TokenOpt {
+    #[structopt(flatten)]
+    pub db: DbOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: TokenCommand,
+}
+
+#[derive(StructOpt)]
+pub enum TokenCommand {
+    /// Issue a new token. The token is printed once, and only a hash is kept.
+    Create {
+        /// Who (or what) the token is for, ex: `alice` or `ci-deploy`.
+        name: String,
+        #[structopt(
+            long,
+            use_delimiter = true,
+            default_value = "read,publish,yank,owners",
+            possible_values = &["read", "publish", "yank", "owners", "admin"],
+            help = "What the token may be used for."
+        )]
+        scopes: Vec<Scope>,
+    },
+    /// Register a public key for cargo's asymmetric (`cargo:paseto`) tokens.
+    AddKey {
+        /// Who (or what) the key belongs to.
+        name: String,
+        /// The PASERK public key cargo printed, ex: `k3.public.AmDw...`.
+        public_key: String,
+        #[structopt(
+            long,
+            use_delimiter = true,
+            default_value = "read,publish,yank,owners",
+            possible_values = &["read", "publish", "yank", "owners", "admin"],
+            help = "What requests signed by the key may be used for."
+        )]
+        scopes: Vec<Scope>,
+    },
+    /// List the tokens and keys that have been issued.
+    List,
+    /// Revoke a token, so it can no longer be used.
+    Revoke {
+        /// The name the token was issued under.
+        name: String,
+        #[structopt(long, help = "Why, for the record kept of revocations.")]
+        reason: Option<String>,
+    },
+    /// Remove a public key, so requests signed by it are refused.
+    RemoveKey {
+        /// The key's PASERK id, as shown by `estuary token list`, ex: `k3.pid.Ryx...`.
+        key_id: String,
+        #[structopt(long, help = "Why, for the record kept of revocations.")]
+        reason: Option<String>,
+    },
+    /// List the tokens (and keys) that have been revoked, and by whom.
+    Revocations,
+}
+
+#[derive(StructOpt)]
+pub struct TrustOpt {
+    #[structopt(flatten)]
+    pub db: DbOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: TrustCommand,
+}
+
+#[derive(StructOpt)]
+pub enum TrustCommand {
+    /// Let CI jobs from a repository publish a crate.
+    Add {
+        /// The crate to publish.
+        crate_name: String,
+        /// Where the CI jobs run.
+        #[structopt(possible_values = &["github", "gitlab"])]
+        provider: Provider,
+        /// The repository the jobs run for, ex: `owner/repo` (GitHub) or
+        /// `group/project` (GitLab).
+        repository: String,
+        #[structopt(
+            long,
+            help = "Only allow this workflow (or pipeline config) file, ex: `release.yml`."
+        )]
+        workflow: Option<String>,
+        #[structopt(long, help = "Only allow jobs run for this branch, ex: `main`.")]
+        branch: Option<String>,
+        #[structopt(
+            long,
+            help = "Who issues the ID tokens, for self-hosted GitLab, ex: `https://gitlab.example.com`."
+        )]
+        issuer: Option<String>,
+    },
+    /// List the trusted publishers.
+    List {
+        /// Only list those for this crate.
+        crate_name: Option<String>,
+    },
+    /// Stop trusting a publisher.
+    Remove {
+        /// The id shown by `estuary trust list`.
+        id: i64,
+    },
+}
+
+#[derive(StructOpt)]
+pub struct GrantOpt {
+    #[structopt(flatten)]
+    pub db: DbOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: GrantCommand,
+}
+
+#[derive(StructOpt)]
+pub enum GrantCommand {
+    /// Let a token or user publish and yank crates. Once any grant has been
+    /// made, tokens can only act on the crates they've been granted.
+    Add {
+        /// A crate name, or a pattern like `team-a-*`.
+        crate_pattern: String,
+        #[structopt(
+            long,
+            required_unless = "user",
+            conflicts_with = "user",
+            help = "The name the token (or key) was issued under."
+        )]
+        token: Option<String>,
+        #[structopt(long, help = "A user, whose tokens from the web UI are all granted.")]
+        user: Option<String>,
+    },
+    /// List the grants.
+    List,
+    /// Take back a grant.
+    Remove {
+        /// The id shown by `estuary grant list`.
+        id: i64,
+    },
+}
+
+#[derive(StructOpt)]
+pub struct TeamOpt {
+    #[structopt(flatten)]
+    pub db: DbOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: TeamCommand,
+}
+
+#[derive(StructOpt)]
+pub enum TeamCommand {
+    /// Add a team, which can then be made an owner with
+    /// `cargo owner --add @name`.
+    Add {
+        /// The team's name, with or without the `@`.
+        name: String,
+    },
+    /// List the teams and their members.
+    List,
+    /// Delete a team, taking away the crates it owns.
+    Remove { name: String },
+    /// Add a user to a team, so they can act on all of its crates.
+    AddMember { team: String, login: String },
+    /// Take a user out of a team.
+    RemoveMember { team: String, login: String },
+}
+
+#[derive(StructOpt)]
+pub struct ReserveOpt {
+    #[structopt(flatten)]
+    pub db: DbOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: ReserveCommand,
+}
+
+#[derive(StructOpt)]
+pub enum ReserveCommand {
+    /// Set aside crate names for a user or team. Until a reserved crate has
+    /// owners, nobody else (bar admins) may publish it.
+    Add {
+        /// A crate name, or a pattern like `acme-*`.
+        crate_pattern: String,
+        /// A user's login, or a team as `@name`.
+        holder: String,
+    },
+    /// List the reservations.
+    List,
+    /// Lift a reservation.
+    Remove {
+        /// The id shown by `estuary reserve list`.
+        id: i64,
+    },
+}
+
+#[derive(StructOpt)]
+pub struct BackupDbOpt {
+    #[structopt(flatten)]
+    pub db: DbOpt,
+
+    /// Where to write the copy, which mustn't already exist.
+    #[structopt(parse(from_os_str))]
+    pub path: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct StatsOpt {
+    #[structopt(flatten)]
+    pub db: DbOpt,
+
+    /// How many of the largest crates to list.
+    #[structopt(long, default_value = "10")]
+    pub top: usize,
+}
+
+#[derive(StructOpt)]
+pub struct CompletionsOpt {
+    /// The shell to complete for.
+    #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+    pub shell: Shell,
+}
+
+#[derive(StructOpt)]
+pub struct StorageOpt {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        env = "ESTUARY_CRATE_DIR",
+        help = "The server's crate dir."
+    )]
+    pub crate_dir: PathBuf,
+
+    #[structopt(subcommand)]
+    pub cmd: StorageCommand,
+}
+
+#[derive(StructOpt)]
+pub enum StorageCommand {
+    /// Move the crate files into a layout (see `estuary run --crate-dir-layout`).
+    /// Best done with the server stopped, or already using the new layout.
+    Relocate {
+        #[structopt(possible_values = &["flat", "sharded"])]
+        layout: Layout,
+    },
+}
+
+#[derive(StructOpt)]
+pub struct ArchiveOpt {
+    #[structopt(flatten)]
+    pub opt: Opt,
+
+    /// The archive (a `.tar.gz`) to write, or read.
+    #[structopt(parse(from_os_str))]
+    pub path: PathBuf,
+}
+
+#[derive(StructOpt)]
+pub struct ImportDirOpt {
+    #[structopt(flatten)]
+    pub opt: Opt,
+
+    /// The directory to look for `.crate` files in (and in its
+    /// subdirectories).
+    #[structopt(parse(from_os_str))]
+    pub path: PathBuf,
+
+    /// The index url of the registry the crate files came from, so their
+    /// dependencies on its crates are on this registry's instead. May be
+    /// given more than once.
+    #[structopt(long = "from-index")]
+    pub from_index: Vec<String>,
+
+    /// The login of the user to own the crates that are new to the registry.
+    /// Names reserved for someone else are skipped.
+    #[structopt(long)]
+    pub owner: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub struct FsckOpt {
+    #[structopt(flatten)]
+    pub opt: Opt,
+
+    /// Put right what can be (ex: an out of date `config.json`, or changes
+    /// that weren't committed), with a new commit.
+    #[structopt(long)]
+    pub repair: bool,
+}
+
+#[derive(StructOpt)]
+pub enum AdminCommand {
+    /// Delete a version outright: from the index (with a commit), the
+    /// database, and the crate store. Unlike yanking it, this leaves nothing
+    /// to download, and can't be undone. The same version can't be published
+    /// again afterwards.
+    DeleteVersion(AdminDeleteOpt),
+    /// Yank a version, in the index and the database, without needing the
+    /// server (or a token).
+    Yank(AdminVersionOpt),
+    /// Undo a yank, the same way.
+    Unyank(AdminVersionOpt),
+}
+
+#[derive(StructOpt)]
+pub struct AdminVersionOpt {
+    #[structopt(flatten)]
+    pub opt: Opt,
+    pub crate_name: String,
+    pub version: semver::Version,
+}
+
+#[derive(StructOpt)]
+pub struct AdminDeleteOpt {
+    #[structopt(flatten)]
+    pub version: AdminVersionOpt,
+    #[structopt(
+        long,
+        help = "Why the version's being deleted, which is kept, and given to anyone who tries \
+        publishing it again."
+    )]
+    pub reason: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An `Opt` with everything zeroed out, for tests to override as needed.
+    fn test_opt() -> Opt {
+        Opt {
+            base_url: "http://example.com".to_string(),
+            index_dir: Default::default(),
+            crate_dir: Default::default(),
+            download_url: None,
+            api_url: None,
+            index_url: None,
+            registry_name: "estuary".to_string(),
+            site_title: "Estuary".to_string(),
+            site_logo_url: None,
+            site_footer: None,
+            site_banner: None,
+            crate_dir_layout: Layout::Flat,
+            http_host: "".to_string(),
+            http_port: 0,
+            git_bin: None,
+            db_path: None,
+            database_url: None,
+            db_pool_size: 8,
+            db_busy_timeout: 5000,
+            db_journal_mode: JournalMode::Wal,
+            db_synchronous: Synchronous::Normal,
+            publish_key: Default::default(),
+            auth_required: false,
+            private: false,
+            basic_auth: vec![],
+            web_tokens: false,
+            signup: false,
+            rate_limit: None,
+            oidc_issuer: None,
+            oidc_client_id: None,
+            oidc_client_secret: None,
+            oidc_allow: vec![],
+            ldap_url: None,
+            ldap_user_dn: None,
+            ldap_base_dn: None,
+            ldap_user_filter: "(uid={login})".to_string(),
+            ldap_bind_dn: None,
+            ldap_bind_password: None,
+            s3_bucket: None,
+            s3_endpoint: None,
+            s3_region: "us-east-1".to_string(),
+            s3_access_key_id: None,
+            s3_secret_access_key: None,
+            gcs_bucket: None,
+            gcs_credentials: None,
+            azure_container: None,
+            azure_account: None,
+            azure_access_key: None,
+            azure_endpoint: None,
+            verify_downloads: false,
+            encryption_key: None,
+            scan_interval: None,
+            squash_index_interval: None,
+            max_crate_size: None,
+            deny_wildcard_deps: false,
+            dep_registries: vec![],
+            categories_file: None,
+            allowed_names: vec![],
+            denied_names: vec![],
+            denied_names_file: None,
+            crate_quota: None,
+            registry_quota: None,
+            index_protocol: IndexProtocol::Git,
+            index_commit_window: None,
+            fallback_author: None,
+            git_author_name: None,
+            git_author_email: None,
+            signing_key: None,
+            signing_key_passphrase_file: None,
+            index_mirror_url: None,
+            index_mirror_username: None,
+            index_mirror_password: None,
+            tls_cert: None,
+            tls_key: None,
+            h2c: false,
+        }
+    }
+
+    #[test]
+    fn test_base_url_trims_trailing_slashes() {
+        let opt = Opt {
+            // weird
+            base_url: "http://example.com/////".to_string(),
+            ..test_opt()
+        };
+
+        assert_eq!("http://example.com", opt.base_url());
+    }
+
+    #[test]
+    fn test_download_url_default() {
+        let opt = test_opt();
+
+        assert_eq!(
+            "http://example.com/api/v1/crates/{crate}/{version}/download",
+            opt.download_url()
+        );
+    }
+
+    #[test]
+    fn test_api_url() {
+        assert_eq!("http://example.com", test_opt().api_url());
+
+        let opt = Opt {
+            api_url: Some("https://api.example.com/".to_string()),
+            ..test_opt()
+        };
+        assert_eq!("https://api.example.com", opt.api_url());
+    }
+
+    #[test]
+    fn test_token_db_path_matches_server() {
+        let opt = Opt {
+            crate_dir: PathBuf::from("/var/lib/estuary/crates"),
+            ..test_opt()
+        };
+        let token_opt =
+            TokenOpt::from_iter(&["token", "--crate-dir", "/var/lib/estuary/crates", "list"]);
+        assert_eq!(opt.db_path(), token_opt.db.db_path());
+
+        let token_opt = TokenOpt::from_iter(&["token", "--db-path", "/tmp/estuary.db", "list"]);
+        assert_eq!(PathBuf::from("/tmp/estuary.db"), token_opt.db.db_path());
+    }
+
+    #[test]
+    fn test_token_create_scopes() {
+        let token_opt = TokenOpt::from_iter(&[
+            "token",
+            "--db-path",
+            "estuary.db",
+            "create",
+            "ci",
+            "--scopes",
+            "read,publish",
+        ]);
+        match token_opt.cmd {
+            TokenCommand::Create { name, scopes } => {
+                assert_eq!("ci", name);
+                assert_eq!(vec![Scope::Read, Scope::Publish], scopes);
+            }
+            _ => panic!("expected create"),
+        }
+    }
+
+    #[test]
+    fn test_basic_auth_areas() {
+        let args = [
+            "estuary",
+            "run",
+            "--base-url=http://example.com",
+            "--index-dir=index",
+            "--crate-dir=crates",
+            "--basic-auth=web,git",
+        ];
+        match Command::from_iter(&args) {
+            Command::Run(RunOpt { opt, .. }) => {
+                assert_eq!(vec![BasicAuthArea::Web, BasicAuthArea::Git], opt.basic_auth)
+            }
+            _ => panic!("expected run"),
+        }
+    }
+
+    #[test]
+    fn test_oidc_needs_client() {
+        let args = [
+            "estuary",
+            "run",
+            "--base-url=http://example.com",
+            "--index-dir=index",
+            "--crate-dir=crates",
+            "--oidc-issuer=https://id.example.com",
+        ];
+        assert!(Command::from_iter_safe(&args).is_err());
+
+        let args = [
+            &args[..],
+            &["--oidc-client-id=estuary", "--oidc-client-secret=shh"],
+        ]
+        .concat();
+        match Command::from_iter(&args) {
+            Command::Run(RunOpt { opt, .. }) => {
+                let config = opt.oidc().unwrap();
+                assert_eq!("https://id.example.com", config.issuer);
+                assert_eq!("estuary", config.client_id);
+                assert_eq!("shh", config.client_secret);
+            }
+            _ => panic!("expected run"),
+        }
+        assert!(test_opt().oidc().is_none());
+    }
+
+    #[test]
+    fn test_git_identity() {
+        let opt = test_opt();
+        assert_eq!(Author::default(), opt.git_identity());
+        assert_eq!(Author::default(), opt.fallback_author());
+
+        let args = [
+            "estuary",
+            "run",
+            "--base-url=http://example.com",
+            "--index-dir=index",
+            "--crate-dir=crates",
+            "--git-author-email=registry@example.com",
+        ];
+        match Command::from_iter(&args) {
+            Command::Run(RunOpt { opt, .. }) => {
+                let identity = opt.git_identity();
+                assert_eq!("estuary", identity.name);
+                assert_eq!("registry@example.com", identity.email);
+                // Changes nobody made are the registry's own.
+                assert_eq!(identity, opt.fallback_author());
+            }
+            _ => panic!("expected run"),
+        }
+    }
+
+    #[test]
+    fn test_signing_key_passphrase() {
+        let base = [
+            "estuary",
+            "run",
+            "--base-url=http://example.com",
+            "--index-dir=index",
+            "--crate-dir=crates",
+            "--signing-key=key",
+        ];
+        // Not on the command line, where `ps` would show it.
+        let flag = base
+            .iter()
+            .copied()
+            .chain(Some("--signing-key-passphrase=hunter2"));
+        assert!(Command::from_iter_safe(flag).is_err());
+
+        let dir = tempfile::Builder::new()
+            .prefix("test_passphrase")
+            .tempdir()
+            .unwrap();
+        let path = dir.path().join("passphrase");
+        std::fs::write(&path, "hunter2\n").unwrap();
+        let file = format!("--signing-key-passphrase-file={}", path.display());
+        match Command::from_iter(base.iter().copied().chain(Some(file.as_str()))) {
+            Command::Run(RunOpt { opt, .. }) => assert_eq!(
+                Some("hunter2".to_string()),
+                opt.signing_key_passphrase().unwrap()
+            ),
+            _ => panic!("expected run"),
+        }
+    }
+
+    #[test]
+    fn test_branding() {
+        let branding = test_opt().branding();
+        assert_eq!("Estuary", branding.title);
+        assert_eq!(None, branding.banner);
+
+        let args = [
+            "estuary",
+            "run",
+            "--base-url=http://example.com",
+            "--index-dir=index",
+            "--crate-dir=crates",
+            "--site-title=Acme Crates",
+            "--site-logo-url=/logo.png",
+            "--site-banner=  ",
+        ];
+        match Command::from_iter(&args) {
+            Command::Run(RunOpt { opt, .. }) => {
+                let branding = opt.branding();
+                assert_eq!("Acme Crates", branding.title);
+                assert_eq!(Some("/logo.png"), branding.logo_url.as_deref());
+                assert_eq!(None, branding.footer);
+                assert_eq!(None, branding.banner);
+            }
+            _ => panic!("expected run"),
+        }
+    }
+
+    #[test]
+    fn test_ldap() {
+        assert!(test_opt().ldap().is_none());
+
+        let args = [
+            "estuary",
+            "run",
+            "--base-url=http://example.com",
+            "--index-dir=index",
+            "--crate-dir=crates",
+            "--ldap-url=ldap://ldap.example.com",
+            "--ldap-base-dn=dc=example,dc=com",
+        ];
+        match Command::from_iter(&args) {
+            Command::Run(RunOpt { opt, .. }) => {
+                let config = opt.ldap().unwrap();
+                assert_eq!("ldap://ldap.example.com", config.url);
+                assert_eq!(Some("dc=example,dc=com"), config.base_dn.as_deref());
+                assert_eq!("(uid={login})", config.user_filter);
+            }
+            _ => panic!("expected run"),
+        }
+
+        // A bind DN is no good without its password.
+        let args = [&args[..], &["--ldap-bind-dn=cn=estuary,dc=example,dc=com"]].concat();
+        assert!(Command::from_iter_safe(&args).is_err());
+    }
+
+    #[test]
+    fn test_s3() {
+        assert!(test_opt().s3().is_none());
+
+        let args = [
+            "estuary",
+            "run",
+            "--base-url=http://example.com",
+            "--index-dir=index",
+            "--crate-dir=crates",
+            "--s3-bucket=crates",
+        ];
+        // Not without credentials.
+        assert!(Command::from_iter_safe(&args).is_err());
+
+        let args = [
+            &args[..],
+            &[
+                "--s3-access-key-id=minio",
+                "--s3-secret-access-key=minio123",
+            ],
+        ]
+        .concat();
+        match Command::from_iter(&args) {
+            Command::Run(RunOpt { opt, .. }) => {
+                let config = opt.s3().unwrap();
+                assert_eq!("https://s3.us-east-1.amazonaws.com", config.endpoint);
+                assert_eq!("crates", config.bucket);
+            }
+            _ => panic!("expected run"),
+        }
+
+        let args = [&args[..], &["--s3-endpoint=http://localhost:9000"]].concat();
+        match Command::from_iter(&args) {
+            Command::Run(RunOpt { opt, .. }) => {
+                assert_eq!("http://localhost:9000", opt.s3().unwrap().endpoint)
+            }
+            _ => panic!("expected run"),
+        }
+
+        // One bucket at a time.
+        let args = [
+            &args[..],
+            &["--gcs-bucket=crates", "--gcs-credentials=key.json"],
+        ]
+        .concat();
+        assert!(Command::from_iter_safe(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(Ok(1000), parse_size("1000"));
+        assert_eq!(Ok(512 * 1024 * 1024), parse_size("512M"));
+        assert_eq!(Ok(2 << 30), parse_size("2g"));
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("99999999T").is_err());
+    }
+
+    #[test]
+    fn test_max_crate_size() {
+        assert_eq!(None, test_opt().max_crate_size().unwrap());
+
+        let mut opt = Opt {
+            max_crate_size: Some(100 << 20),
+            ..test_opt()
+        };
+        assert_eq!(Some(100 << 20), opt.max_crate_size().unwrap());
+        // More than can be read back from a bucket.
+        opt.s3_bucket = Some("crates".to_string());
+        opt.s3_access_key_id = Some("minio".to_string());
+        opt.s3_secret_access_key = Some("minio123".to_string());
+        assert!(opt.max_crate_size().is_err());
+        opt.max_crate_size = None;
+        assert_eq!(
+            Some(storage::MAX_OBJECT_SIZE as u64),
+            opt.max_crate_size().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_azure() {
+        assert!(test_opt().azure().is_none());
+
+        let args = [
+            "estuary",
+            "run",
+            "--base-url=http://example.com",
+            "--index-dir=index",
+            "--crate-dir=crates",
+            "--azure-container=crates",
+            "--azure-account=estuary",
+        ];
+        // Not without a key.
+        assert!(Command::from_iter_safe(&args).is_err());
+
+        let args = [&args[..], &["--azure-access-key=c2ho"]].concat();
+        match Command::from_iter(&args) {
+            Command::Run(RunOpt { opt, .. }) => {
+                let config = opt.azure().unwrap();
+                assert_eq!("estuary", config.account);
+                assert_eq!(None, config.endpoint);
+            }
+            _ => panic!("expected run"),
+        }
+    }
+
+    #[test]
+    fn test_grant_needs_token_or_user() {
+        let args = [
+            "estuary",
+            "grant",
+            "--db-path=estuary.db",
+            "add",
+            "my-crate",
+        ];
+        assert!(Command::from_iter_safe(&args).is_err());
+        let both = [&args[..], &["--token=ci", "--user=alice"]].concat();
+        assert!(Command::from_iter_safe(&both).is_err());
+        let token = [&args[..], &["--token=ci"]].concat();
+        assert!(Command::from_iter_safe(&token).is_ok());
+    }
+
+    #[test]
+    fn test_index_url_default() {
+        assert_eq!("http://example.com/git/index", test_opt().index_url());
+
+        let opt = Opt {
+            index_protocol: IndexProtocol::Sparse,
+            ..test_opt()
+        };
+        assert_eq!("sparse+http://example.com/index/", opt.index_url());
+
+        let opt = Opt {
+            index_url: Some("https://index.example.com/git/index".to_string()),
+            ..test_opt()
+        };
+        assert_eq!("https://index.example.com/git/index", opt.index_url());
+        // Only the protocols being served.
+        assert_eq!(
+            vec![
+                "https://index.example.com/git/index",
+                "http://example.com/git/index",
+            ],
+            opt.index_urls()
+        );
+        assert_eq!(
+            vec!["http://example.com/git/index"],
+            test_opt().index_urls()
+        );
+        let opt = Opt {
+            index_protocol: IndexProtocol::Both,
+            ..test_opt()
+        };
+        assert_eq!(
+            vec![
+                "http://example.com/git/index",
+                "sparse+http://example.com/index/"
+            ],
+            opt.index_urls()
         );
     }
 }
@@ -0,0 +1,21 @@
+//! Subcommands that manage the registry from the shell, rather than over http.
+
+pub mod admin;
+pub mod archive;
+pub mod backup_db;
+pub mod completions;
+pub mod dev;
+pub mod doctor;
+pub mod fsck;
+pub mod grant;
+pub mod import_dir;
+pub mod rebuild_index;
+pub mod reserve;
+pub mod squash_index;
+pub mod stats;
+pub mod storage;
+pub mod team;
+pub mod token;
+pub mod trust;
+pub mod user;
+pub mod verify;
@@ -0,0 +1,262 @@
+//! `estuary admin delete-version|yank|unyank`
+
+use crate::cli::{AdminCommand, AdminDeleteOpt, AdminVersionOpt};
+use crate::database::{self, Connection};
+use crate::deletion;
+use crate::errors::EstuaryError;
+use crate::package_index::{lock, Author, PackageIndex};
+use crate::storage::CrateStore;
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub async fn run(cmd: AdminCommand) -> Result<()> {
+    let (args, yanked, reason) = match cmd {
+        AdminCommand::DeleteVersion(AdminDeleteOpt { version, reason }) => (version, None, reason),
+        AdminCommand::Yank(args) => (args, Some(true), None),
+        AdminCommand::Unyank(args) => (args, Some(false), None),
+    };
+    let AdminVersionOpt {
+        opt,
+        crate_name,
+        version,
+    } = args;
+    let conn = Connection::open(&opt.database_url())?;
+    database::init(&conn)?;
+    let index = PackageIndex::open(&opt.index_dir)?
+        .with_committer(opt.committer()?)
+        .with_mirror(opt.index_mirror());
+    let _lock = lock::acquire(&opt.index_dir)?;
+    let author = opt.fallback_author();
+    let stdout = std::io::stdout();
+    match yanked {
+        Some(yanked) => set_yanked(
+            &index,
+            &conn,
+            &crate_name,
+            &version,
+            yanked,
+            &author,
+            &mut stdout.lock(),
+        ),
+        None => {
+            let store = opt.crate_store()?;
+            delete_version(
+                &index,
+                &conn,
+                store.as_ref(),
+                &crate_name,
+                &version,
+                reason.as_deref(),
+                &author,
+                &mut stdout.lock(),
+            )
+            .await
+        }
+    }
+}
+
+/// Yank (or unyank) a version the same way `cargo yank` does, crediting the
+/// index commit to `author`.
+fn set_yanked(
+    index: &PackageIndex,
+    conn: &Connection,
+    crate_name: &str,
+    version: &semver::Version,
+    yanked: bool,
+    author: &Author,
+    out: &mut impl Write,
+) -> Result<()> {
+    let name = index
+        .find_crate_name(crate_name)?
+        .ok_or(EstuaryError::NotFound)?;
+    if !index
+        .get_package_versions(&name)?
+        .iter()
+        .any(|pkg| &pkg.vers == version)
+    {
+        return Err(EstuaryError::NotFound);
+    }
+    index.set_yanked(&name, version, yanked, author)?;
+    database::set_yanked(conn, &name, &version.to_string(), yanked)?;
+    let done = if yanked { "Yanked" } else { "Unyanked" };
+    writeln!(out, "{} `{}` {}.", done, name, version)?;
+    Ok(())
+}
+
+/// Delete a version the same way the admin page does, for the `reason`
+/// given (if any), crediting the index commit to `author`.
+#[allow(clippy::too_many_arguments)]
+async fn delete_version(
+    index: &PackageIndex,
+    conn: &Connection,
+    store: &dyn CrateStore,
+    crate_name: &str,
+    version: &semver::Version,
+    reason: Option<&str>,
+    author: &Author,
+    out: &mut impl Write,
+) -> Result<()> {
+    let removed = deletion::remove_version(index, conn, crate_name, version, reason, author)?
+        .ok_or(EstuaryError::NotFound)?;
+    deletion::delete_files(store, &removed).await?;
+    writeln!(out, "Deleted `{}` {}.", removed.pkg.name, removed.pkg.vers)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_index::PackageVersion;
+    use crate::storage::{self, Layout, LocalStore, TempFile};
+    use crate::test_helpers;
+
+    #[test]
+    fn test_set_yanked() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        test_helpers::get_test_package_index(&settings.index_dir);
+        let index = PackageIndex::open(&settings.index_dir).unwrap();
+        let pkg = PackageVersion {
+            name: "My-Crate".to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+        index.publish(&pkg, &Author::default()).unwrap();
+        database::record_publish(&conn, "My-Crate", "0.1.0", None, None).unwrap();
+        let yanked = || {
+            (
+                index.get_package_versions("My-Crate").unwrap()[0].yanked,
+                database::list_versions(&conn).unwrap()[0]
+                    .yanked_at
+                    .is_some(),
+            )
+        };
+
+        let admins: Author = "Registry Admins <registry@example.com>".parse().unwrap();
+
+        let mut out = vec![];
+        set_yanked(
+            &index, &conn, "my-crate", &pkg.vers, true, &admins, &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            "Yanked `My-Crate` 0.1.0.\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!((true, true), yanked());
+        let repo = git2::Repository::open(&settings.index_dir).unwrap();
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(Some("registry@example.com"), commit.author().email());
+        assert_eq!(Some("estuary"), commit.committer().name());
+        let mut out = vec![];
+        set_yanked(
+            &index, &conn, "my-crate", &pkg.vers, false, &admins, &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            "Unyanked `My-Crate` 0.1.0.\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!((false, false), yanked());
+
+        for (name, version) in &[("my-crate", "0.2.0"), ("other-crate", "0.1.0")] {
+            assert!(matches!(
+                set_yanked(
+                    &index,
+                    &conn,
+                    name,
+                    &version.parse().unwrap(),
+                    true,
+                    &admins,
+                    &mut vec![]
+                ),
+                Err(EstuaryError::NotFound)
+            ));
+        }
+        assert_eq!((false, false), yanked());
+    }
+
+    #[actix_rt::test]
+    async fn test_delete_version() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        test_helpers::get_test_package_index(&settings.index_dir);
+        let index = PackageIndex::open(&settings.index_dir).unwrap();
+        let store = LocalStore {
+            root: settings.crate_dir.clone(),
+            layout: Layout::Flat,
+        };
+
+        let digest = storage::sha256(b"crate");
+        let pkg = PackageVersion {
+            name: "My-Crate".to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: vec![],
+            cksum: digest.clone(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+        index.publish(&pkg, &Author::default()).unwrap();
+        database::record_publish(&conn, "My-Crate", "0.1.0", None, None).unwrap();
+        database::record_crate_file(&conn, "My-Crate", "0.1.0", &digest, 5).unwrap();
+        let key = storage::get_blob_key(&digest);
+        let file = TempFile::create(data_root.path()).unwrap();
+        std::fs::write(file.path(), b"crate").unwrap();
+        store.put(&key, file.path()).await.unwrap();
+
+        let version = pkg.vers.clone();
+        let mut out = vec![];
+        delete_version(
+            &index,
+            &conn,
+            &store,
+            "my-crate",
+            &version,
+            Some("published by mistake"),
+            &Author::default(),
+            &mut out,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            "Deleted `My-Crate` 0.1.0.\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert!(index.list_crates().unwrap().is_empty());
+        assert_eq!(
+            None,
+            database::find_publish(&conn, "My-Crate", "0.1.0").unwrap()
+        );
+        assert!(!store.exists(&key).await.unwrap());
+        let tombstone = database::find_tombstone(&conn, "my-crate", "0.1.0")
+            .unwrap()
+            .unwrap();
+        assert_eq!(digest, tombstone.cksum);
+        assert_eq!(Some("published by mistake"), tombstone.reason.as_deref());
+
+        // There's nothing left to delete.
+        assert!(matches!(
+            delete_version(
+                &index,
+                &conn,
+                &store,
+                "my-crate",
+                &version,
+                None,
+                &Author::default(),
+                &mut vec![]
+            )
+            .await,
+            Err(EstuaryError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,544 @@
+//! `estuary export` and `estuary import`
+//!
+//! Archives are gzipped tarballs of the database (as `estuary.db`), every
+//! file of the index repo (under `index/`, `.git` and all), and every crate
+//! file by its key (under `crates/`), for `estuary import` to restore.
+
+use crate::cli::ArchiveOpt;
+use crate::database::{self, Connection, DatabaseUrl};
+use crate::errors::EstuaryError;
+use crate::package_index::lock;
+use crate::storage::{CrateStore, TempFile};
+use crate::tarball;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+/// Where the database is in an archive.
+const DATABASE: &str = "estuary.db";
+/// What the index repo's files are under.
+const INDEX: &str = "index/";
+/// What the crate files' keys are under.
+const CRATES: &str = "crates/";
+
+#[cfg(not(tarpaulin_include))]
+pub async fn run_export(opt: ArchiveOpt) -> Result<()> {
+    let conn = Connection::open(&opt.opt.database_url())?;
+    // A publish landing midway could leave the index half copied.
+    let _lock = lock::acquire(&opt.opt.index_dir)?;
+    let store = opt.opt.crate_store()?;
+    let stdout = std::io::stdout();
+    export(
+        &conn,
+        &opt.opt.index_dir,
+        &opt.opt.crate_dir,
+        store.as_ref(),
+        &opt.path,
+        &mut stdout.lock(),
+    )
+    .await
+}
+
+async fn export(
+    conn: &Connection,
+    index_dir: &Path,
+    crate_dir: &Path,
+    store: &dyn CrateStore,
+    path: &Path,
+    out: &mut impl Write,
+) -> Result<()> {
+    // Writing over another archive (or anything else) would be hard to undo.
+    if path.exists() {
+        return Err(EstuaryError::Config(format!(
+            "`{}` already exists.",
+            path.display()
+        )));
+    }
+    let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    let crates = match write_archive(conn, index_dir, crate_dir, store, file).await {
+        Ok(crates) => crates,
+        Err(e) => {
+            // Half an archive is no good to anyone.
+            fs::remove_file(path)?;
+            return Err(e);
+        }
+    };
+    writeln!(
+        out,
+        "Exported the database, the index, and {} crate files to `{}`.",
+        crates,
+        path.display()
+    )?;
+    Ok(())
+}
+
+/// Handing back how many crate files went in.
+async fn write_archive(
+    conn: &Connection,
+    index_dir: &Path,
+    crate_dir: &Path,
+    store: &dyn CrateStore,
+    file: File,
+) -> Result<usize> {
+    let mut archive = tarball::builder(file);
+
+    // A copy, so it's consistent even with the server writing to it.
+    let copy = TempFile::create(&crate_dir.join("backups"))?;
+    conn.backup(copy.path())?;
+    archive.append_path_with_name(copy.path(), DATABASE)?;
+
+    for relative in index_files(index_dir)? {
+        let name = format!("{}{}", INDEX, relative.to_string_lossy().replace('\\', "/"));
+        archive.append_path_with_name(index_dir.join(&relative), name)?;
+    }
+
+    let mut keys = store.list("").await?;
+    keys.sort();
+    let mut crates = 0;
+    for key in &keys {
+        // Listed, but gone by now.
+        let body = match store.get(key).await? {
+            Some(body) => body,
+            None => continue,
+        };
+        tarball::append(&mut archive, &format!("{}{}", CRATES, key), &body)?;
+        crates += 1;
+    }
+    tarball::finish(archive)?.sync_all()?;
+    Ok(crates)
+}
+
+/// Every file under `index_dir`, relative to it.
+fn index_files(index_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(index_dir.join(&dir))? {
+            let entry = entry?;
+            let path = dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else if path != Path::new(".git").join(lock::LOCK_FILE) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(not(tarpaulin_include))]
+pub async fn run_import(opt: ArchiveOpt) -> Result<()> {
+    let store = opt.opt.crate_store()?;
+    let stdout = std::io::stdout();
+    import(
+        &opt.path,
+        &opt.opt.database_url(),
+        &opt.opt.index_dir,
+        &opt.opt.crate_dir,
+        store.as_ref(),
+        &mut stdout.lock(),
+    )
+    .await
+}
+
+async fn import(
+    path: &Path,
+    database_url: &DatabaseUrl,
+    index_dir: &Path,
+    crate_dir: &Path,
+    store: &dyn CrateStore,
+    out: &mut impl Write,
+) -> Result<()> {
+    let db_path = match database_url {
+        DatabaseUrl::Sqlite(path) => path,
+        DatabaseUrl::Postgres(_) => {
+            return Err(EstuaryError::Config(
+                "Archives can only be imported into a SQLite database.".to_string(),
+            ))
+        }
+    };
+    // Only into a fresh instance, rather than mixing two together.
+    if db_path.exists() {
+        return Err(EstuaryError::Config(format!(
+            "`{}` already exists.",
+            db_path.display()
+        )));
+    }
+    let index_is_empty = match fs::read_dir(index_dir) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+        Err(e) => return Err(e.into()),
+    };
+    if !index_is_empty {
+        return Err(EstuaryError::Config(format!(
+            "`{}` isn't empty.",
+            index_dir.display()
+        )));
+    }
+
+    // Half an instance is no good to anyone, and would keep the import from
+    // being tried again.
+    let mut stored = vec![];
+    let imported = unpack(
+        path,
+        database_url,
+        db_path,
+        index_dir,
+        crate_dir,
+        store,
+        &mut stored,
+    )
+    .await;
+    let crates = match imported {
+        Ok(crates) => crates,
+        Err(e) => {
+            if db_path.exists() {
+                fs::remove_file(db_path)?;
+            }
+            if index_dir.exists() {
+                for entry in fs::read_dir(index_dir)? {
+                    let path = entry?.path();
+                    if path.is_dir() {
+                        fs::remove_dir_all(path)?;
+                    } else {
+                        fs::remove_file(path)?;
+                    }
+                }
+            }
+            for key in &stored {
+                store.delete(key).await?;
+            }
+            return Err(e);
+        }
+    };
+    writeln!(
+        out,
+        "Imported the database, the index, and {} crate files.",
+        crates
+    )?;
+    Ok(())
+}
+
+/// Unpack the archive at `path`, handing back how many crate files were in
+/// it, with the keys of those that weren't already in the `store` added to
+/// `stored` as they're stored.
+async fn unpack(
+    path: &Path,
+    database_url: &DatabaseUrl,
+    db_path: &Path,
+    index_dir: &Path,
+    crate_dir: &Path,
+    store: &dyn CrateStore,
+    stored: &mut Vec<String>,
+) -> Result<usize> {
+    // Crate files are stored once they're all out, since that can't be done
+    // while reading the archive.
+    let uploads = crate_dir.join("uploads");
+    let mut crate_files = vec![];
+    let mut found_db = false;
+    tarball::each_file(File::open(path)?, |name, _, contents| {
+        let (dest, mut file) = if name == DATABASE {
+            found_db = true;
+            if let Some(dir) = db_path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            (None, File::create(db_path)?)
+        } else if let Some(relative) = name.strip_prefix(INDEX) {
+            let dest = index_dir.join(relative_path(relative)?);
+            fs::create_dir_all(dest.parent().unwrap())?;
+            (None, File::create(dest)?)
+        } else if let Some(key) = name.strip_prefix(CRATES) {
+            relative_path(key)?;
+            let temp = TempFile::create(&uploads)?;
+            let file = File::create(temp.path())?;
+            (Some((key.to_string(), temp)), file)
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("`{}` doesn't belong in an archive", name),
+            ));
+        };
+        io::copy(contents, &mut file)?;
+        crate_files.extend(dest);
+        Ok(true)
+    })?;
+    if !found_db {
+        return Err(EstuaryError::Config(format!(
+            "`{}` has no database in it.",
+            path.display()
+        )));
+    }
+
+    for (key, file) in &crate_files {
+        if !store.exists(key).await? {
+            store.put(key, file.path()).await?;
+            stored.push(key.clone());
+        }
+    }
+    // Archives from older versions are brought up to date now, rather than
+    // when the server starts.
+    database::init(&Connection::open(database_url)?)?;
+    Ok(crate_files.len())
+}
+
+/// A path from an archive, refused if it'd end up outside where it's being
+/// unpacked to.
+fn relative_path(path: &str) -> io::Result<PathBuf> {
+    let path = PathBuf::from(path);
+    let normal = path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !normal {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("`{}` isn't a relative path", path.display()),
+        ));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_index::{Author, PackageIndex, PackageVersion};
+    use crate::storage::{Layout, LocalStore};
+    use crate::test_helpers;
+
+    #[actix_rt::test]
+    async fn test_export() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        test_helpers::get_test_package_index(&settings.index_dir);
+        let store = LocalStore {
+            root: settings.crate_dir.clone(),
+            layout: Layout::Flat,
+        };
+        let mut file = TempFile::create(data_root.path()).unwrap();
+        file.write_all(b"crate").unwrap();
+        store
+            .put("my-crate/my-crate-0.1.0.crate", file.path())
+            .await
+            .unwrap();
+        // As `run_export` holds it, which isn't part of the index.
+        let _lock = lock::acquire(&settings.index_dir).unwrap();
+
+        let path = data_root.path().join("export.tar.gz");
+        let mut out = vec![];
+        export(
+            &conn,
+            &settings.index_dir,
+            &settings.crate_dir,
+            &store,
+            &path,
+            &mut out,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            format!(
+                "Exported the database, the index, and 1 crate files to `{}`.\n",
+                path.display()
+            ),
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut names = vec![];
+        let mut crate_file = vec![];
+        tarball::each_file(File::open(&path).unwrap(), |name, _, contents| {
+            if name.starts_with(CRATES) {
+                contents.read_to_end(&mut crate_file)?;
+            }
+            names.push(name);
+            Ok(true)
+        })
+        .unwrap();
+        assert_eq!(DATABASE, names[0]);
+        assert!(names.contains(&"index/config.json".to_string()));
+        assert!(names.contains(&"index/.git/HEAD".to_string()));
+        assert!(!names.contains(&"index/.git/estuary.lock".to_string()));
+        assert_eq!(
+            Some(&"crates/my-crate/my-crate-0.1.0.crate".to_string()),
+            names.last()
+        );
+        assert_eq!(b"crate", &crate_file[..]);
+        // The copy of the database isn't left behind.
+        assert_eq!(
+            0,
+            fs::read_dir(settings.crate_dir.join("backups"))
+                .unwrap()
+                .count()
+        );
+
+        let result = export(
+            &conn,
+            &settings.index_dir,
+            &settings.crate_dir,
+            &store,
+            &path,
+            &mut vec![],
+        )
+        .await;
+        assert!(matches!(result, Err(EstuaryError::Config(_))));
+    }
+
+    #[actix_rt::test]
+    async fn test_export_and_import() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        test_helpers::add_token(&settings, "ci", "secret");
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let pkg = PackageVersion {
+            name: "my-crate".to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+        package_index
+            .lock()
+            .unwrap()
+            .publish(&pkg, &Author::default())
+            .unwrap();
+        let store = LocalStore {
+            root: settings.crate_dir.clone(),
+            layout: Layout::Flat,
+        };
+        let mut file = TempFile::create(data_root.path()).unwrap();
+        file.write_all(b"crate").unwrap();
+        store.put("sha256/ab/abc.crate", file.path()).await.unwrap();
+        drop(file);
+
+        let archive = data_root.path().join("export.tar.gz");
+        export(
+            &conn,
+            &settings.index_dir,
+            &settings.crate_dir,
+            &store,
+            &archive,
+            &mut vec![],
+        )
+        .await
+        .unwrap();
+
+        let elsewhere = data_root.path().join("elsewhere");
+        let database_url = DatabaseUrl::Sqlite(elsewhere.join("estuary.db"));
+        let new_store = LocalStore {
+            root: elsewhere.join("crates"),
+            layout: Layout::Sharded,
+        };
+        let mut out = vec![];
+        import(
+            &archive,
+            &database_url,
+            &elsewhere.join("index"),
+            &elsewhere.join("crates"),
+            &new_store,
+            &mut out,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            "Imported the database, the index, and 1 crate files.\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let copy = Connection::open(&database_url).unwrap();
+        assert_eq!(1, database::count_tokens(&copy).unwrap());
+        let index = PackageIndex::open(elsewhere.join("index")).unwrap();
+        assert_eq!(vec![pkg], index.get_package_versions("my-crate").unwrap());
+        assert!(index
+            .fsck(&index.read_config().unwrap())
+            .unwrap()
+            .problems
+            .is_empty());
+        assert_eq!(
+            Some(&b"crate"[..]),
+            new_store
+                .get("sha256/ab/abc.crate")
+                .await
+                .unwrap()
+                .as_deref()
+        );
+        assert_eq!(
+            0,
+            fs::read_dir(elsewhere.join("crates/uploads"))
+                .unwrap()
+                .count()
+        );
+
+        // Only into a fresh instance.
+        let result = import(
+            &archive,
+            &database_url,
+            &elsewhere.join("other-index"),
+            &elsewhere.join("crates"),
+            &new_store,
+            &mut vec![],
+        )
+        .await;
+        assert!(matches!(result, Err(EstuaryError::Config(_))));
+        let result = import(
+            &archive,
+            &DatabaseUrl::Sqlite(elsewhere.join("other.db")),
+            &elsewhere.join("index"),
+            &elsewhere.join("crates"),
+            &new_store,
+            &mut vec![],
+        )
+        .await;
+        assert!(matches!(result, Err(EstuaryError::Config(_))));
+    }
+
+    #[actix_rt::test]
+    async fn test_failed_import_is_cleaned_up() {
+        let data_root = test_helpers::get_data_root();
+        let mut tarball = tarball::builder(vec![]);
+        tarball::append(&mut tarball, DATABASE, b"not a database").unwrap();
+        tarball::append(&mut tarball, "index/config.json", b"{}").unwrap();
+        tarball::append(&mut tarball, "crates/sha256/ab/abc.crate", b"crate").unwrap();
+        let archive = data_root.path().join("export.tar.gz");
+        fs::write(&archive, tarball::finish(tarball).unwrap()).unwrap();
+        let store = LocalStore {
+            root: data_root.path().join("store"),
+            layout: Layout::Flat,
+        };
+        let db_path = data_root.path().join("estuary.db");
+        let index_dir = data_root.path().join("index");
+        fs::create_dir_all(&index_dir).unwrap();
+
+        let result = import(
+            &archive,
+            &DatabaseUrl::Sqlite(db_path.clone()),
+            &index_dir,
+            &data_root.path().join("crates"),
+            &store,
+            &mut vec![],
+        )
+        .await;
+        // Once the crate file has been stored.
+        assert!(matches!(result, Err(EstuaryError::Database(_))));
+        // So it can be tried again.
+        assert!(!db_path.exists());
+        assert_eq!(0, fs::read_dir(&index_dir).unwrap().count());
+        assert!(store.list("").await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            PathBuf::from("sha256/ab/abc.crate"),
+            relative_path("sha256/ab/abc.crate").unwrap()
+        );
+        for path in &["", "/etc/passwd", "../config.json", "a/../../b"] {
+            assert!(relative_path(path).is_err(), "{}", path);
+        }
+    }
+}
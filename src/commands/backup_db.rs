@@ -0,0 +1,57 @@
+//! `estuary backup-db`
+
+use crate::cli::BackupDbOpt;
+use crate::database::Connection;
+use crate::errors::EstuaryError;
+use std::io::Write;
+use std::path::Path;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: BackupDbOpt) -> Result<()> {
+    let conn = Connection::open(&opt.db.database_url())?;
+    let stdout = std::io::stdout();
+    execute(&conn, &opt.path, &mut stdout.lock())
+}
+
+fn execute(conn: &Connection, path: &Path, out: &mut impl Write) -> Result<()> {
+    // Backing up over the database itself (or an older backup) would be
+    // hard to undo.
+    if path.exists() {
+        return Err(EstuaryError::Config(format!(
+            "`{}` already exists.",
+            path.display()
+        )));
+    }
+    conn.backup(path)?;
+    writeln!(out, "Backed up the database to `{}`.", path.display())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_backup_db() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        test_helpers::add_token(&settings, "ci", "secret");
+        let path = data_root.path().join("backup.db");
+
+        let mut out = vec![];
+        execute(&conn, &path, &mut out).unwrap();
+        assert_eq!(
+            format!("Backed up the database to `{}`.\n", path.display()),
+            String::from_utf8(out).unwrap()
+        );
+        let copy = Connection::open(&database::DatabaseUrl::Sqlite(path.clone())).unwrap();
+        assert_eq!(1, database::count_tokens(&copy).unwrap());
+
+        assert!(execute(&conn, &path, &mut vec![]).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+//! `estuary completions`
+
+use crate::cli::{Command, CompletionsOpt};
+use crate::errors::EstuaryError;
+use std::io::Write;
+use structopt::clap::Shell;
+use structopt::StructOpt;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: CompletionsOpt) -> Result<()> {
+    let stdout = std::io::stdout();
+    execute(opt.shell, &mut stdout.lock())
+}
+
+fn execute(shell: Shell, out: &mut impl Write) -> Result<()> {
+    Command::clap().gen_completions_to(env!("CARGO_PKG_NAME"), shell, out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completions() {
+        for shell in &Shell::variants() {
+            let mut out = vec![];
+            execute(shell.parse().unwrap(), &mut out).unwrap();
+            let out = String::from_utf8(out).unwrap();
+            assert!(out.contains("rebuild-index"), "{}", shell);
+            assert!(out.contains("squash-index-interval"), "{}", shell);
+        }
+    }
+}
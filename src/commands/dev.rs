@@ -0,0 +1,197 @@
+//! `estuary run --dev`
+//!
+//! A throwaway registry, for trying estuary out or working on the web UI
+//! without setting one up first. Everything is kept in a temporary directory,
+//! seeded with a few sample crates and a token to publish more with.
+
+use crate::auth::{self, Scope};
+use crate::cli::Opt;
+use crate::commands::import_dir;
+use crate::database::{self, Connection};
+use crate::errors::EstuaryError;
+use crate::package_index::PackageIndex;
+use crate::tarball;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+/// What the token printed for publishing is called.
+const TOKEN_NAME: &str = "dev";
+
+/// A sample crate to publish.
+struct Sample {
+    name: &'static str,
+    vers: &'static str,
+    description: &'static str,
+    /// On the other samples, as (name, requirement).
+    deps: &'static [(&'static str, &'static str)],
+}
+
+const SAMPLES: &[Sample] = &[
+    Sample {
+        name: "hello-estuary",
+        vers: "0.1.0",
+        description: "Says hello.",
+        deps: &[],
+    },
+    Sample {
+        name: "hello-estuary",
+        vers: "0.2.0",
+        description: "Says hello, politely.",
+        deps: &[],
+    },
+    Sample {
+        name: "estuary-greeter",
+        vers: "1.0.0",
+        description: "Greets people, with `hello-estuary`.",
+        deps: &[("hello-estuary", "0.2")],
+    },
+];
+
+/// The options to run the server with, for a registry under `dir` that's
+/// been seeded with the sample crates.
+#[cfg(not(tarpaulin_include))]
+pub async fn setup(opt: Opt, dir: &Path) -> Result<Opt> {
+    let opt = opt.dev(dir);
+    let stdout = std::io::stdout();
+    execute(&opt, dir, &mut stdout.lock()).await?;
+    Ok(opt)
+}
+
+async fn execute(opt: &Opt, dir: &Path, out: &mut impl Write) -> Result<()> {
+    fs::create_dir_all(&opt.crate_dir)?;
+    let conn = Connection::open(&opt.database_url())?;
+    database::init(&conn)?;
+    let index = PackageIndex::init(&opt.index_dir, &opt.index_config())?;
+    let store = opt.crate_store()?;
+
+    let samples = dir.join("samples");
+    fs::create_dir_all(&samples)?;
+    for sample in SAMPLES {
+        let crate_file = sample_crate(sample, &opt.index_url())?;
+        let path = samples.join(format!("{}-{}.crate", sample.name, sample.vers));
+        fs::write(path, crate_file)?;
+    }
+    import_dir::execute(
+        &index,
+        &conn,
+        store.as_ref(),
+        &samples,
+        &opt.index_urls(),
+        &opt.publish_policy()?,
+        None,
+        &mut io::sink(),
+    )
+    .await?;
+
+    let token = auth::generate_token();
+    database::set_token(&conn, TOKEN_NAME, &token, Scope::DEFAULT)?;
+    writeln!(
+        out,
+        "Running a throwaway registry in `{}`, which is deleted once the server stops.\n\
+        \n\
+        To use it, add this to your `.cargo/config.toml`:\n\
+        \n\
+        [registries.{}]\n\
+        index = \"{}\"\n\
+        \n\
+        And publish to it with the token `{}`.",
+        dir.display(),
+        opt.registry_name,
+        opt.index_url(),
+        token
+    )?;
+    Ok(())
+}
+
+/// The crate file for `sample`, as cargo would package it.
+fn sample_crate(sample: &Sample, index_url: &str) -> io::Result<Vec<u8>> {
+    let Sample {
+        name,
+        vers,
+        description,
+        deps,
+    } = sample;
+    let mut manifest = format!(
+        "[package]\n\
+        edition = \"2018\"\n\
+        name = \"{}\"\n\
+        version = \"{}\"\n\
+        authors = [\"Estuary <estuary@example.com>\"]\n\
+        description = \"{}\"\n\
+        readme = \"README.md\"\n\
+        keywords = [\"sample\"]\n\
+        license = \"MIT OR Apache-2.0\"\n",
+        name, vers, description
+    );
+    for (dep, req) in deps.iter() {
+        manifest.push_str(&format!(
+            "\n[dependencies.{}]\nversion = \"{}\"\nregistry-index = \"{}\"\n",
+            dep, req, index_url
+        ));
+    }
+    let readme = format!(
+        "# {}\n\n{} A sample crate, from `estuary run --dev`.\n",
+        name, description
+    );
+    let lib = "pub fn hello() -> &'static str {\n    \"Hello!\"\n}\n";
+
+    let mut tarball = tarball::builder(vec![]);
+    for (path, contents) in [
+        ("Cargo.toml", manifest.as_str()),
+        ("README.md", &readme),
+        ("src/lib.rs", lib),
+    ] {
+        tarball::append(
+            &mut tarball,
+            &format!("{}-{}/{}", name, vers, path),
+            contents.as_bytes(),
+        )?;
+    }
+    tarball::finish(tarball)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::{Command, RunOpt};
+    use crate::test_helpers;
+    use structopt::StructOpt;
+
+    #[actix_rt::test]
+    async fn test_dev() {
+        let data_root = test_helpers::get_data_root();
+        let opt = match Command::from_iter(&["estuary", "run", "--dev", "--http-port=9999"]) {
+            Command::Run(RunOpt { opt, dev: true }) => opt.dev(data_root.path()),
+            _ => panic!("expected run --dev"),
+        };
+        assert_eq!("http://localhost:9999", opt.base_url());
+        assert_eq!(data_root.path().join("index"), opt.index_dir);
+
+        let mut out = vec![];
+        execute(&opt, data_root.path(), &mut out).await.unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(
+            "[registries.estuary]\n\
+            index = \"http://localhost:9999/git/index\"\n"
+        ));
+
+        let index = PackageIndex::open(&opt.index_dir).unwrap();
+        assert_eq!(
+            2,
+            index.get_package_versions("hello-estuary").unwrap().len()
+        );
+        let greeter = index.get_package_versions("estuary-greeter").unwrap();
+        assert_eq!("hello-estuary", greeter[0].deps[0].name);
+        assert_eq!(None, greeter[0].deps[0].registry);
+
+        let conn = Connection::open(&opt.database_url()).unwrap();
+        let token = out.rsplit('`').nth(1).unwrap();
+        assert!(database::list_token_hashes(&conn)
+            .unwrap()
+            .iter()
+            .any(|(found, hash)| found.name == TOKEN_NAME && *hash == database::hash_token(token)));
+    }
+}
@@ -0,0 +1,377 @@
+//! `estuary doctor`
+//!
+//! Most trouble running a registry comes down to how it's configured: a base
+//! url clients can't reach (or that something else answers at), an index repo
+//! that's missing, a database that isn't there (or is out of date), or a
+//! crate dir the server can't write to. Each is checked, with a hint for
+//! whatever isn't right.
+//!
+//! Nothing's changed along the way: the database is only read from.
+
+use crate::cli::Opt;
+use crate::database::{self, Connection};
+use crate::errors::EstuaryError;
+use crate::package_index::{Config, PackageIndex};
+use crate::storage::TempFile;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+/// How long to wait on the server to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a check found.
+#[derive(Debug, PartialEq)]
+enum Finding {
+    Ok(String),
+    /// Something that isn't right, with a hint as to how to put it right.
+    Problem {
+        what: String,
+        hint: String,
+    },
+}
+
+impl Finding {
+    fn problem(what: String, hint: &str) -> Self {
+        Finding::Problem {
+            what,
+            hint: hint.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::Ok(what) => write!(f, "ok: {}", what),
+            Finding::Problem { what, hint } => write!(f, "problem: {}\n  hint: {}", what, hint),
+        }
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+pub async fn run(opt: Opt) -> Result<()> {
+    let stdout = std::io::stdout();
+    let problems = execute(&opt, &mut stdout.lock()).await?;
+    // So scripts (and health checks) can tell something's wrong.
+    if problems > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Print what each check found, handing back how many problems there were.
+#[cfg(not(tarpaulin_include))]
+async fn execute(opt: &Opt, out: &mut impl Write) -> Result<usize> {
+    let config = opt.index_config();
+    let mut findings = vec![check_base_url(opt, &config).await];
+    findings.push(check_index(&opt.index_dir, &config));
+    match Connection::open_read_only(&opt.database_url()) {
+        Ok(conn) => {
+            findings.push(check_db(&conn));
+            findings.push(check_tokens(&conn, opt.publish_key.is_some()));
+        }
+        Err(e) => findings.push(Finding::problem(
+            format!("The database couldn't be opened: {}", e),
+            "Check `--database-url` (or `--db-path`, or `--crate-dir`, which the SQLite \
+            database is in by default), and that the server can reach it.",
+        )),
+    }
+    findings.push(check_crate_dir(&opt.crate_dir));
+
+    let mut problems = 0;
+    for finding in &findings {
+        writeln!(out, "{}", finding)?;
+        if let Finding::Problem { .. } = finding {
+            problems += 1;
+        }
+    }
+    writeln!(out, "{} problems found.", problems)?;
+    Ok(problems)
+}
+
+/// Whether the server answers at the base url, as this registry should.
+#[cfg(not(tarpaulin_include))]
+async fn check_base_url(opt: &Opt, config: &Config) -> Finding {
+    let (url, expected) = if opt.index_protocol.sparse_enabled() {
+        (
+            format!("{}/index/config.json", opt.base_url()),
+            Some(config),
+        )
+    } else {
+        (
+            format!(
+                "{}/git/index/info/refs?service=git-upload-pack",
+                opt.base_url()
+            ),
+            None,
+        )
+    };
+    let resp = awc::Client::default()
+        .get(&url)
+        .timeout(TIMEOUT)
+        .send()
+        .await;
+    match resp {
+        Err(e) => Finding::problem(
+            format!("Nothing answered at `{}`: {}", url, e),
+            "Check the server is running, and that `--base-url` is the address clients \
+            reach it at, port and all.",
+        ),
+        Ok(mut resp) => {
+            let body = resp.body().await.unwrap_or_default();
+            check_response(&url, resp.status().as_u16(), &body, expected)
+        }
+    }
+}
+
+/// Whether what the server answered at `url` with is what this registry
+/// would, when it's `expected` to be the index's `config.json`.
+fn check_response(url: &str, status: u16, body: &[u8], expected: Option<&Config>) -> Finding {
+    const ELSEWHERE: &str = "Something other than Estuary may be answering at `--base-url`: \
+        another service on the same port (GitLab's, say), or a proxy sending requests \
+        elsewhere. Check `--http-port`, and what's listening on it.";
+    match status {
+        // Reads are behind a token, which there's no knowing.
+        401 | 403 => return Finding::Ok(format!("`{}` answers, asking for credentials", url)),
+        200..=299 => (),
+        _ => return Finding::problem(format!("`{}` answered with a {}", url, status), ELSEWHERE),
+    }
+    let expected = match expected {
+        Some(expected) => expected,
+        None => return Finding::Ok(format!("`{}` answers", url)),
+    };
+    match serde_json::from_slice::<Config>(body) {
+        Err(_) => Finding::problem(format!("`{}` isn't an index `config.json`", url), ELSEWHERE),
+        Ok(ref found) if found != expected => Finding::problem(
+            format!(
+                "`{}` has `dl` as `{}` and `api` as `{}`, not `{}` and `{}`",
+                url, found.dl, found.api, expected.dl, expected.api
+            ),
+            "The server may be running with other options than these. Run `estuary doctor` \
+            with the server's options (and environment), or restart the server with these.",
+        ),
+        Ok(_) => Finding::Ok(format!("`{}` answers with this registry's config", url)),
+    }
+}
+
+/// Whether the index repo is there, and in good shape.
+fn check_index(index_dir: &Path, config: &Config) -> Finding {
+    let index = match PackageIndex::open(index_dir) {
+        Ok(index) => index,
+        Err(e) => {
+            return Finding::problem(
+                format!("`{}` isn't an index repo: {}", index_dir.display(), e),
+                "The server sets one up the first time it starts. Check `--index-dir` is the \
+                index's working tree (not its `.git`), and that it hasn't been moved.",
+            )
+        }
+    };
+    match index.fsck(config) {
+        Ok(report) if report.problems.is_empty() => Finding::Ok(format!(
+            "The index repo at `{}` is healthy, with {} package files",
+            index_dir.display(),
+            report.files
+        )),
+        Ok(report) => Finding::problem(
+            format!(
+                "The index repo at `{}` has {} problems",
+                index_dir.display(),
+                report.problems.len()
+            ),
+            "Run `estuary fsck` to see them, and `estuary fsck --repair` to put right what \
+            can be.",
+        ),
+        Err(e) => Finding::problem(
+            format!(
+                "The index repo at `{}` couldn't be checked: {}",
+                index_dir.display(),
+                e
+            ),
+            "Check the server can read (and write) everything in `--index-dir`.",
+        ),
+    }
+}
+
+/// Which version the database's schema is at, and whether that's the one
+/// this version of Estuary expects.
+fn check_db(conn: &Connection) -> Finding {
+    match database::schema_version(conn) {
+        Ok(version) if version == database::SCHEMA_VERSION => Finding::Ok(format!(
+            "The database's schema is up to date (version {})",
+            version
+        )),
+        Ok(version) if version < database::SCHEMA_VERSION => Finding::problem(
+            format!(
+                "The database's schema is at version {}, behind this version of Estuary's {}",
+                version,
+                database::SCHEMA_VERSION
+            ),
+            "The server brings it up to date when it starts. For SQLite, it needs to be able \
+            to write to the database file and the directory it's in. For Postgres, its user \
+            needs to own the tables.",
+        ),
+        Ok(version) => Finding::problem(
+            format!(
+                "The database's schema is at version {}, newer than this version of Estuary's {}",
+                version,
+                database::SCHEMA_VERSION
+            ),
+            "Run the version of Estuary that last migrated it; going back isn't supported.",
+        ),
+        Err(e) => Finding::problem(
+            format!("The database's schema version couldn't be read: {}", e),
+            "Check the database is one Estuary set up. A new one is set up when the server \
+            first starts.",
+        ),
+    }
+}
+
+/// Whether there's anything (a token, say) standing in the way of anyone
+/// publishing.
+fn check_tokens(conn: &Connection, has_publish_key: bool) -> Finding {
+    match database::count_tokens(conn) {
+        Ok(0) if !has_publish_key => Finding::problem(
+            "There are no tokens, so anyone can publish".to_string(),
+            "Create one with `estuary token create <name>`, and the registry will want one \
+            for publishing from then on.",
+        ),
+        Ok(count) => Finding::Ok(format!(
+            "There are {} tokens{}",
+            count,
+            if has_publish_key {
+                ", and a `--publish-key`"
+            } else {
+                ""
+            }
+        )),
+        Err(e) => Finding::problem(
+            format!("The tokens couldn't be counted: {}", e),
+            "Check the database is one Estuary set up.",
+        ),
+    }
+}
+
+/// Whether publishes can stage their crate files in the crate dir.
+fn check_crate_dir(crate_dir: &Path) -> Finding {
+    let uploads = crate_dir.join("uploads");
+    match TempFile::create(&uploads).and_then(|mut file| file.write_all(b"estuary doctor")) {
+        Ok(()) => Finding::Ok(format!("`{}` can be written to", uploads.display())),
+        Err(e) => Finding::problem(
+            format!("`{}` can't be written to: {}", uploads.display(), e),
+            "Publishes stage crate files under `--crate-dir` (where the SQLite database is \
+            too, by default), so the server needs to be able to write to it.",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    fn config() -> Config {
+        Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        }
+    }
+
+    fn is_ok(finding: &Finding) -> bool {
+        matches!(finding, Finding::Ok(_))
+    }
+
+    #[test]
+    fn test_check_response() {
+        let url = "http://localhost/index/config.json";
+        let body = serde_json::to_vec(&config()).unwrap();
+        assert_eq!(
+            Finding::Ok(format!("`{}` answers with this registry's config", url)),
+            check_response(url, 200, &body, Some(&config()))
+        );
+        assert!(is_ok(&check_response(url, 401, b"", Some(&config()))));
+        assert!(is_ok(&check_response(url, 200, b"", None)));
+
+        // GitLab, say.
+        let finding = check_response(url, 302, b"", Some(&config()));
+        assert!(finding.to_string().contains("GitLab"));
+        assert!(!is_ok(&check_response(
+            url,
+            200,
+            b"<html>",
+            Some(&config())
+        )));
+        let other = Config {
+            api: String::from("http://localhost:8080/api"),
+            ..config()
+        };
+        let finding = check_response(url, 200, &body, Some(&other));
+        assert_eq!(
+            format!(
+                "problem: `{}` has `dl` as `http://localhost/dl` and `api` as \
+                `http://localhost/api`, not `http://localhost/dl` and \
+                `http://localhost:8080/api`",
+                url
+            ),
+            finding.to_string().lines().next().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_checks() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+
+        assert!(!is_ok(&check_index(&settings.index_dir, &config())));
+        PackageIndex::init(&settings.index_dir, &config()).unwrap();
+        assert!(is_ok(&check_index(&settings.index_dir, &config())));
+        let other = Config {
+            auth_required: true,
+            ..config()
+        };
+        assert!(!is_ok(&check_index(&settings.index_dir, &other)));
+
+        assert_eq!(
+            Finding::Ok(format!(
+                "The database's schema is up to date (version {})",
+                database::SCHEMA_VERSION
+            )),
+            check_db(&conn)
+        );
+        // Which is all it looks at: nothing is migrated.
+        let empty = Connection::open_in_memory().unwrap();
+        assert!(!is_ok(&check_db(&empty)));
+        empty
+            .execute_batch(
+                "CREATE TABLE schema_version (version INTEGER PRIMARY KEY);
+                INSERT INTO schema_version (version) VALUES (1);",
+            )
+            .unwrap();
+        assert!(!is_ok(&check_db(&empty)));
+        assert_eq!(1, database::schema_version(&empty).unwrap());
+        assert!(!is_ok(&check_tokens(&conn, false)));
+        assert!(is_ok(&check_tokens(&conn, true)));
+        test_helpers::add_token(&settings, "ci", "secret");
+        assert_eq!(
+            Finding::Ok("There are 1 tokens".to_string()),
+            check_tokens(&conn, false)
+        );
+
+        assert!(is_ok(&check_crate_dir(&settings.crate_dir)));
+        // Nothing's left behind.
+        assert_eq!(
+            0,
+            std::fs::read_dir(settings.crate_dir.join("uploads"))
+                .unwrap()
+                .count()
+        );
+        let file = data_root.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert!(!is_ok(&check_crate_dir(&file)));
+    }
+}
@@ -0,0 +1,103 @@
+//! `estuary fsck`
+
+use crate::cli::FsckOpt;
+use crate::errors::EstuaryError;
+use crate::package_index::{lock, Config, PackageIndex};
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: FsckOpt) -> Result<()> {
+    let index = PackageIndex::open(&opt.opt.index_dir)?
+        .with_committer(opt.opt.committer()?)
+        .with_mirror(opt.opt.index_mirror());
+    // Only repairs change the index, which the server mustn't be doing too.
+    let _lock = if opt.repair {
+        Some(lock::acquire(&opt.opt.index_dir)?)
+    } else {
+        None
+    };
+    let stdout = std::io::stdout();
+    let problems = execute(
+        &index,
+        &opt.opt.index_config(),
+        opt.repair,
+        &mut stdout.lock(),
+    )?;
+    // So scripts (and cron) can tell something's wrong.
+    if problems > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Print each problem found (and repair what can be, when asked to), handing
+/// back how many are left.
+fn execute(
+    index: &PackageIndex,
+    config: &Config,
+    repair: bool,
+    out: &mut impl Write,
+) -> Result<usize> {
+    let report = index.fsck(config)?;
+    for problem in &report.problems {
+        writeln!(out, "{}", problem)?;
+    }
+    writeln!(
+        out,
+        "Checked {} package files, {} problems found.",
+        report.files,
+        report.problems.len()
+    )?;
+    if !repair || report.problems.is_empty() {
+        return Ok(report.problems.len());
+    }
+    let repaired = index.repair(&report.problems, config)?;
+    writeln!(out, "Repaired {} problems.", repaired)?;
+    Ok(index.fsck(config)?.problems.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_fsck() {
+        let root = test_helpers::get_data_root();
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+        let index = PackageIndex::init(&root, &config).unwrap();
+
+        let mut out = vec![];
+        assert_eq!(0, execute(&index, &config, false, &mut out).unwrap());
+        assert_eq!(
+            "Checked 0 package files, 0 problems found.\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let config = Config {
+            auth_required: true,
+            ..config
+        };
+        let mut out = vec![];
+        assert_eq!(1, execute(&index, &config, false, &mut out).unwrap());
+        assert_eq!(
+            "`config.json` doesn't match the settings\n\
+            Checked 0 package files, 1 problems found.\n",
+            String::from_utf8(out).unwrap()
+        );
+        let mut out = vec![];
+        assert_eq!(0, execute(&index, &config, true, &mut out).unwrap());
+        assert_eq!(
+            "`config.json` doesn't match the settings\n\
+            Checked 0 package files, 1 problems found.\n\
+            Repaired 1 problems.\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! `estuary grant add|list|remove`
+
+use crate::cli::{GrantCommand, GrantOpt};
+use crate::database::{self, Connection, Grantee};
+use crate::errors::EstuaryError;
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: GrantOpt) -> Result<()> {
+    let conn = Connection::open(&opt.db.database_url())?;
+    database::init(&conn)?;
+    let stdout = std::io::stdout();
+    execute(&conn, opt.cmd, &mut stdout.lock())
+}
+
+fn execute(conn: &Connection, cmd: GrantCommand, out: &mut impl Write) -> Result<()> {
+    match cmd {
+        GrantCommand::Add {
+            crate_pattern,
+            token,
+            user,
+        } => {
+            // structopt makes sure exactly one of them is given.
+            let grantee = match (token, user) {
+                (Some(name), _) => Grantee::Token(name),
+                (None, Some(login)) => Grantee::User(login),
+                (None, None) => unreachable!(),
+            };
+            let id = database::add_grant(conn, &crate_pattern, &grantee)?;
+            writeln!(out, "Added grant {} for `{}`.", id, crate_pattern)?;
+        }
+        GrantCommand::List => {
+            for grant in database::list_grants(conn)? {
+                let grantee = match grant.grantee {
+                    Grantee::Token(name) => format!("token:{}", name),
+                    Grantee::User(login) => format!("user:{}", login),
+                };
+                writeln!(out, "{}\t{}\t{}", grant.id, grant.crate_pattern, grantee)?;
+            }
+        }
+        GrantCommand::Remove { id } => {
+            if !database::remove_grant(conn, id)? {
+                return Err(EstuaryError::NotFound);
+            }
+            writeln!(out, "Removed grant {}.", id)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        database::init(&conn).unwrap();
+        conn
+    }
+
+    fn execute_to_string(conn: &Connection, cmd: GrantCommand) -> Result<String> {
+        let mut out = vec![];
+        execute(conn, cmd, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_add_list_remove() {
+        let conn = get_conn();
+        execute_to_string(
+            &conn,
+            GrantCommand::Add {
+                crate_pattern: "team-a-*".to_string(),
+                token: Some("ci".to_string()),
+                user: None,
+            },
+        )
+        .unwrap();
+        execute_to_string(
+            &conn,
+            GrantCommand::Add {
+                crate_pattern: "my-crate".to_string(),
+                token: None,
+                user: Some("alice".to_string()),
+            },
+        )
+        .unwrap();
+
+        let listed = execute_to_string(&conn, GrantCommand::List).unwrap();
+        assert_eq!("1\tteam-a-*\ttoken:ci\n2\tmy-crate\tuser:alice\n", listed);
+
+        execute_to_string(&conn, GrantCommand::Remove { id: 1 }).unwrap();
+        assert!(matches!(
+            execute_to_string(&conn, GrantCommand::Remove { id: 1 }),
+            Err(EstuaryError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,480 @@
+//! `estuary import-dir`
+//!
+//! Each `.crate` file is published as its `Cargo.toml` describes it, with
+//! crates going before those that depend on them (from this registry), so the
+//! index never has a version depending on a crate it doesn't have yet.
+//!
+//! Crate files are held to the same rules as publishes: the crate's name,
+//! reservations, the registry's publish policy, and deleted versions.
+
+use crate::cli::ImportDirOpt;
+use crate::database::{self, Connection, User};
+use crate::errors::EstuaryError;
+use crate::manifest::{self, Manifest};
+use crate::package_index::{self, lock, DependencyKind, PackageIndex, PackageVersion};
+use crate::publish_policy::PublishPolicy;
+use crate::storage::{self, CrateStore};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub async fn run(opt: ImportDirOpt) -> Result<()> {
+    let conn = Connection::open(&opt.opt.database_url())?;
+    database::init(&conn)?;
+    let index = PackageIndex::init_as(
+        &opt.opt.index_dir,
+        &opt.opt.index_config(),
+        &opt.opt.committer()?,
+    )?;
+    let _lock = lock::acquire(&opt.opt.index_dir)?;
+    let store = opt.opt.crate_store()?;
+    let policy = opt.opt.publish_policy()?;
+    let owner =
+        match opt.owner {
+            Some(ref login) => Some(database::find_user(&conn, login)?.ok_or_else(|| {
+                EstuaryError::Config(format!("There's no user called `{}`.", login))
+            })?),
+            None => None,
+        };
+    let mut index_urls = opt.opt.index_urls();
+    index_urls.extend(opt.from_index);
+    let stdout = std::io::stdout();
+    let skipped = execute(
+        &index,
+        &conn,
+        store.as_ref(),
+        &opt.path,
+        &index_urls,
+        &policy,
+        owner.as_ref(),
+        &mut stdout.lock(),
+    )
+    .await?;
+    // So scripts can tell not everything was imported.
+    if skipped > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// A crate file to publish.
+struct Found {
+    path: PathBuf,
+    manifest: Manifest,
+    cksum: String,
+    size: u64,
+}
+
+/// Publish each crate file in `dir` that isn't already, printing what
+/// happened to each, and handing back how many couldn't be.
+///
+/// New crates are owned by `owner`, if there is one, and are only imported
+/// under a reserved name if it's reserved for them.
+#[allow(clippy::too_many_arguments)]
+pub async fn execute(
+    index: &PackageIndex,
+    conn: &Connection,
+    store: &dyn CrateStore,
+    dir: &Path,
+    index_urls: &[String],
+    policy: &PublishPolicy,
+    owner: Option<&User>,
+    out: &mut impl Write,
+) -> Result<usize> {
+    let mut found = vec![];
+    let mut skipped = 0;
+    for path in crate_files(dir)? {
+        let read = fs::read(&path).and_then(|crate_file| {
+            Ok(Found {
+                manifest: manifest::read(&crate_file, index_urls)?,
+                cksum: storage::sha256(&crate_file),
+                size: crate_file.len() as u64,
+                path: path.clone(),
+            })
+        });
+        match read {
+            Ok(crate_file) => found.push(crate_file),
+            Err(e) => {
+                writeln!(out, "`{}` was skipped: {}", path.display(), e)?;
+                skipped += 1;
+            }
+        }
+    }
+
+    let mut published = 0;
+    for i in order(&found.iter().map(|f| &f.manifest).collect::<Vec<_>>()) {
+        let Found {
+            path,
+            manifest,
+            cksum,
+            size,
+        } = &found[i];
+        let (name, vers) = (&manifest.name, manifest.vers.to_string());
+        let existing = index.find_crate_name(name)?;
+        match existing {
+            Some(ref existing) if existing != name => {
+                writeln!(
+                    out,
+                    "`{}` was skipped: the index has the crate as `{}`",
+                    path.display(),
+                    existing
+                )?;
+                skipped += 1;
+                continue;
+            }
+            Some(_)
+                if index
+                    .get_package_versions(name)?
+                    .iter()
+                    .any(|pkg| pkg.vers == manifest.vers) =>
+            {
+                writeln!(out, "`{}` {} is already published.", name, vers)?;
+                continue;
+            }
+            _ => (),
+        }
+        let pkg = manifest.package_version(cksum);
+        if let Some(reason) = refusal(conn, policy, owner, &pkg, existing.is_none())? {
+            writeln!(out, "`{}` was skipped: {}", path.display(), reason)?;
+            skipped += 1;
+            continue;
+        }
+
+        // As with publishes, the crate file is stored and the version's rows
+        // written before the index has the version, and they're only
+        // committed once it does.
+        let key = storage::get_blob_key(cksum);
+        if !store.exists(&key).await? {
+            store.put(&key, path).await?;
+        }
+        let tx = conn.transaction()?;
+        database::record_crate_file(&tx, name, &vers, cksum, *size)?;
+        database::record_publish(&tx, name, &vers, None, None)?;
+        let metadata = database::CrateMetadata {
+            categories: policy.categories(&manifest.metadata.categories),
+            ..manifest.metadata.clone()
+        };
+        database::record_metadata(&tx, name, &vers, &metadata)?;
+        database::update_search(&tx, name)?;
+        if let Some(owner) = owner {
+            if existing.is_none() && !database::has_owners(&tx, name)? {
+                database::add_crate_owner(&tx, name, owner.id)?;
+            }
+        }
+        index.publish(&pkg, index.identity())?;
+        tx.commit()?;
+        writeln!(out, "Published `{}` {}.", name, vers)?;
+        published += 1;
+    }
+    writeln!(
+        out,
+        "Published {} versions, {} crate files skipped.",
+        published, skipped
+    )?;
+    Ok(skipped)
+}
+
+/// Why `pkg` can't be published, if it can't be. Only a crate that's `new`
+/// to the index is held to the rules for names.
+fn refusal(
+    conn: &Connection,
+    policy: &PublishPolicy,
+    owner: Option<&User>,
+    pkg: &PackageVersion,
+    new: bool,
+) -> Result<Option<String>> {
+    if let Err(e) = package_index::validate_package_name(&pkg.name) {
+        return Ok(Some(e.to_string()));
+    }
+    let named = if new {
+        policy.check_name(&pkg.name)
+    } else {
+        Ok(())
+    };
+    if let Err(reason) = named.and_then(|()| policy.check(pkg)) {
+        return Ok(Some(reason));
+    }
+    if !database::has_owners(conn, &pkg.name)?
+        && database::is_reserved(conn, &pkg.name, owner.map(|user| user.id))?
+    {
+        return Ok(Some(format!("the crate name `{}` is reserved", pkg.name)));
+    }
+    let vers = pkg.vers.to_string();
+    if let Some(tombstone) = database::find_tombstone(conn, &pkg.name, &vers)? {
+        return Ok(Some(format!(
+            "`{}` {} was deleted on {} and can't be published again",
+            pkg.name, vers, tombstone.deleted_at
+        )));
+    }
+    Ok(None)
+}
+
+/// Every `.crate` file in `dir` and its subdirectories, by path.
+fn crate_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            files.extend(crate_files(&path)?);
+        } else if path.extension().is_some_and(|ext| ext == "crate") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The order to publish `manifests` in, as indices into it: crates by name,
+/// except that each goes after those it depends on, with their versions
+/// lowest first.
+///
+/// Dev-dependencies don't count, since crates may well depend on each other
+/// that way. Nor do dependencies in a cycle besides, which there's no
+/// publishing in order anyway.
+fn order(manifests: &[&Manifest]) -> Vec<usize> {
+    let mut crates = BTreeMap::<String, Vec<usize>>::new();
+    for (i, manifest) in manifests.iter().enumerate() {
+        crates
+            .entry(manifest.name.to_lowercase())
+            .or_default()
+            .push(i);
+    }
+
+    fn visit(
+        name: &str,
+        manifests: &[&Manifest],
+        crates: &BTreeMap<String, Vec<usize>>,
+        seen: &mut HashSet<String>,
+        order: &mut Vec<usize>,
+    ) {
+        let versions = match crates.get(name) {
+            Some(versions) if seen.insert(name.to_string()) => versions,
+            _ => return,
+        };
+        for &i in versions {
+            for dep in &manifests[i].deps {
+                if dep.kind != DependencyKind::Dev && dep.registry.is_none() {
+                    let dep_name = dep.package.as_ref().unwrap_or(&dep.name);
+                    visit(&dep_name.to_lowercase(), manifests, crates, seen, order);
+                }
+            }
+        }
+        let mut versions = versions.clone();
+        versions.sort_by_key(|&i| &manifests[i].vers);
+        order.extend(versions);
+    }
+
+    let mut seen = HashSet::new();
+    let mut order = vec![];
+    for name in crates.keys() {
+        visit(name, manifests, &crates, &mut seen, &mut order);
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_index::Dependency;
+    use crate::test_helpers;
+
+    fn manifest(name: &str, vers: &str, deps: &[(&str, DependencyKind)]) -> Manifest {
+        let crate_file = test_helpers::crate_file(test_helpers::MY_CRATE_0_1_0);
+        Manifest {
+            name: name.to_string(),
+            vers: vers.parse().unwrap(),
+            deps: deps
+                .iter()
+                .map(|(dep, kind)| Dependency {
+                    name: dep.to_string(),
+                    req: "*".to_string(),
+                    features: vec![],
+                    optional: false,
+                    default_features: true,
+                    target: None,
+                    kind: kind.clone(),
+                    registry: None,
+                    package: None,
+                })
+                .collect(),
+            ..manifest::read(&crate_file, &[]).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_order() {
+        let manifests = [
+            manifest("a", "0.2.0", &[("b", DependencyKind::Normal)]),
+            manifest("a", "0.1.0", &[]),
+            manifest("c", "1.0.0", &[("a", DependencyKind::Dev)]),
+            manifest("b", "1.0.0", &[("c", DependencyKind::Build)]),
+            // Depends on itself.
+            manifest("d", "1.0.0", &[("d", DependencyKind::Normal)]),
+            manifest("serde", "1.0.0", &[]),
+        ];
+        let mut manifests = manifests.iter().collect::<Vec<_>>();
+        assert_eq!(vec![2, 3, 1, 0, 4, 5], order(&manifests));
+        manifests.truncate(2);
+        assert_eq!(vec![1, 0], order(&manifests));
+    }
+
+    #[actix_rt::test]
+    async fn test_import_dir() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        test_helpers::get_test_package_index(&settings.index_dir);
+        let index = PackageIndex::open(&settings.index_dir).unwrap();
+        let dir = data_root.path().join("vendor");
+        let crate_file = test_helpers::crate_file(test_helpers::MY_CRATE_0_1_0);
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/my-crate-0.1.0.crate"), &crate_file).unwrap();
+        fs::write(dir.join("bad.crate"), b"nope").unwrap();
+        fs::write(dir.join("README.md"), b"not a crate").unwrap();
+        let urls = vec![settings.index_url.clone()];
+
+        let mut out = vec![];
+        let skipped = execute(
+            &index,
+            &conn,
+            settings.crate_store.as_ref(),
+            &dir,
+            &urls,
+            &PublishPolicy::default(),
+            None,
+            &mut out,
+        )
+        .await
+        .unwrap();
+        assert_eq!(1, skipped);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with(&format!(
+            "`{}` was skipped: ",
+            dir.join("bad.crate").display()
+        )));
+        assert!(out.ends_with(
+            "Published `my-crate` 0.1.0.\n\
+            Published 1 versions, 1 crate files skipped.\n"
+        ));
+
+        let cksum = storage::sha256(&crate_file);
+        let versions = index.get_package_versions("my-crate").unwrap();
+        assert_eq!(1, versions.len());
+        assert_eq!(cksum, versions[0].cksum);
+        assert!(database::find_publish(&conn, "my-crate", "0.1.0")
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            Some(crate_file.into()),
+            settings
+                .crate_store
+                .get(&storage::get_blob_key(&cksum))
+                .await
+                .unwrap()
+        );
+
+        // Again, there's nothing left to do.
+        fs::remove_file(dir.join("bad.crate")).unwrap();
+        let mut out = vec![];
+        let skipped = execute(
+            &index,
+            &conn,
+            settings.crate_store.as_ref(),
+            &dir,
+            &urls,
+            &PublishPolicy::default(),
+            None,
+            &mut out,
+        )
+        .await
+        .unwrap();
+        assert_eq!(0, skipped);
+        assert_eq!(
+            "`my-crate` 0.1.0 is already published.\n\
+            Published 0 versions, 0 crate files skipped.\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_import_dir_checks() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        test_helpers::get_test_package_index(&settings.index_dir);
+        let index = PackageIndex::open(&settings.index_dir).unwrap();
+        let dir = data_root.path().join("vendor");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("my-crate-0.1.0.crate"),
+            test_helpers::crate_file(test_helpers::MY_CRATE_0_1_0),
+        )
+        .unwrap();
+        let urls = vec![settings.index_url.clone()];
+        let alice = database::create_user(&conn, "alice", "").unwrap();
+        let bob = database::create_user(&conn, "bob", "").unwrap();
+        database::add_reservation(&conn, "my-*", &database::Holder::User("bob".into())).unwrap();
+        let policy = PublishPolicy::default();
+
+        // The name is reserved for someone else (or anyone, without an owner),
+        // until it's whoever it's reserved for, who then owns it.
+        for (owner, skipped) in &[(None, 1), (Some(&alice), 1), (Some(&bob), 0)] {
+            let mut out = vec![];
+            let store = settings.crate_store.as_ref();
+            let found = execute(&index, &conn, store, &dir, &urls, &policy, *owner, &mut out)
+                .await
+                .unwrap();
+            assert_eq!(*skipped, found);
+            if *skipped > 0 {
+                assert_eq!(
+                    format!(
+                        "`{}` was skipped: the crate name `my-crate` is reserved\n\
+                        Published 0 versions, 1 crate files skipped.\n",
+                        dir.join("my-crate-0.1.0.crate").display()
+                    ),
+                    String::from_utf8(out).unwrap()
+                );
+                assert!(database::find_publish(&conn, "my-crate", "0.1.0")
+                    .unwrap()
+                    .is_none());
+            }
+        }
+        assert_eq!(1, index.get_package_versions("my-crate").unwrap().len());
+        assert_eq!(
+            vec!["bob"],
+            database::list_crate_owners(&conn, "my-crate")
+                .unwrap()
+                .into_iter()
+                .map(|user| user.login)
+                .collect::<Vec<_>>()
+        );
+
+        // Deleted versions stay deleted, whatever their build metadata.
+        let index_dir = data_root.path().join("other-index");
+        test_helpers::get_test_package_index(&index_dir);
+        let index = PackageIndex::open(&index_dir).unwrap();
+        database::add_tombstone(&conn, "my-crate", "0.1.0+old", "abc", None).unwrap();
+        let mut out = vec![];
+        let skipped = execute(
+            &index,
+            &conn,
+            settings.crate_store.as_ref(),
+            &dir,
+            &urls,
+            &policy,
+            Some(&bob),
+            &mut out,
+        )
+        .await
+        .unwrap();
+        assert_eq!(1, skipped);
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("`my-crate` 0.1.0 was deleted on "));
+    }
+}
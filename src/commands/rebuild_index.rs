@@ -0,0 +1,237 @@
+//! `estuary rebuild-index`
+//!
+//! Each version the database has a record of is written to a new index, as
+//! its stored crate file describes it. The checksums are taken from the crate
+//! files themselves, and versions are yanked as the database has them
+//! (versions yanked before it kept track of that are copied over from the
+//! index when the server or `estuary verify` is first run with it).
+
+use crate::cli::Opt;
+use crate::database::{self, Connection, RecordedVersion};
+use crate::errors::EstuaryError;
+use crate::manifest;
+use crate::package_index::{Committer, Config, PackageIndex, PackageVersion};
+use crate::storage::{self, CrateStore};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub async fn run(args: Opt) -> Result<()> {
+    let conn = Connection::open(&args.database_url())?;
+    database::init(&conn)?;
+    let store = args.crate_store()?;
+    let stdout = std::io::stdout();
+    let skipped = execute(
+        &conn,
+        store.as_ref(),
+        &args.index_dir,
+        &args.index_config(),
+        &args.committer()?,
+        &args.index_urls(),
+        &mut stdout.lock(),
+    )
+    .await?;
+    // So scripts can tell the index isn't everything it should be.
+    if skipped > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Write a new index at `index_dir`, printing each version that couldn't be
+/// written to it, and handing back how many there were.
+async fn execute(
+    conn: &Connection,
+    store: &dyn CrateStore,
+    index_dir: &Path,
+    config: &Config,
+    committer: &Committer,
+    index_urls: &[String],
+    out: &mut impl Write,
+) -> Result<usize> {
+    // Whatever's left of the old index is for someone to move out of the way
+    // first, rather than be mixed in with the new one.
+    let index_is_empty = match fs::read_dir(index_dir) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+        Err(e) => return Err(e.into()),
+    };
+    if !index_is_empty {
+        return Err(EstuaryError::Config(format!(
+            "`{}` isn't empty.",
+            index_dir.display()
+        )));
+    }
+
+    // Which `estuary run` (or `verify`) would have done, with the old index.
+    if database::has_backfill(conn, database::BACKFILL_YANKS)? {
+        writeln!(
+            out,
+            "The database has no record of versions yanked before it kept track of yanks, \
+            so they won't be in the new index."
+        )?;
+    }
+    let mut versions = database::list_versions(conn)?;
+    versions.sort_by_cached_key(|version| {
+        (
+            version.crate_name.to_lowercase(),
+            semver::Version::parse(&version.version).ok(),
+        )
+    });
+    let index = PackageIndex::init_as(index_dir, config, committer)?;
+    let mut crates = HashSet::new();
+    let mut skipped = 0;
+    for recorded in &versions {
+        match package_version(store, recorded, index_urls).await {
+            Ok(pkg) => {
+                index.publish(&pkg, index.identity())?;
+                crates.insert(pkg.name.to_lowercase());
+            }
+            Err(reason) => {
+                writeln!(
+                    out,
+                    "`{}` {} was skipped: {}",
+                    recorded.crate_name, recorded.version, reason
+                )?;
+                skipped += 1;
+            }
+        }
+    }
+    writeln!(
+        out,
+        "Rebuilt the index with {} versions of {} crates, {} skipped.",
+        versions.len() - skipped,
+        crates.len(),
+        skipped
+    )?;
+    Ok(skipped)
+}
+
+/// The index's line for `recorded`, or why there can't be one.
+async fn package_version(
+    store: &dyn CrateStore,
+    recorded: &RecordedVersion,
+    index_urls: &[String],
+) -> std::result::Result<PackageVersion, String> {
+    let key = match recorded.digest {
+        Some(ref digest) => storage::get_blob_key(digest),
+        None => {
+            let vers = semver::Version::parse(&recorded.version).map_err(|e| e.to_string())?;
+            storage::get_crate_file_key(&recorded.crate_name, &vers)
+        }
+    };
+    let crate_file = match store.get(&key).await {
+        Ok(Some(crate_file)) => crate_file,
+        Ok(None) => return Err(format!("there's no crate file (`{}`)", key)),
+        Err(e) => return Err(e.to_string()),
+    };
+    let cksum = storage::sha256(&crate_file);
+    if let Some(ref digest) = recorded.digest {
+        if &cksum != digest {
+            return Err(format!(
+                "its crate file (`{}`) has the SHA-256 `{}`",
+                key, cksum
+            ));
+        }
+    }
+    let manifest = manifest::read(&crate_file, index_urls).map_err(|e| e.to_string())?;
+    if !manifest.name.eq_ignore_ascii_case(&recorded.crate_name)
+        || manifest.vers.to_string() != recorded.version
+    {
+        return Err(format!(
+            "its crate file is for `{}` {}",
+            manifest.name, manifest.vers
+        ));
+    }
+    Ok(PackageVersion {
+        yanked: recorded.yanked_at.is_some(),
+        ..manifest.package_version(&cksum)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    #[actix_rt::test]
+    async fn test_rebuild_index() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+        let urls = vec![settings.index_url.clone()];
+
+        let crate_file = test_helpers::crate_file(test_helpers::MY_CRATE_0_1_0);
+        let cksum = storage::sha256(&crate_file);
+        let path = data_root.path().join("my-crate.crate");
+        fs::write(&path, &crate_file).unwrap();
+        settings
+            .crate_store
+            .put(&storage::get_blob_key(&cksum), &path)
+            .await
+            .unwrap();
+        database::record_publish(&conn, "my-crate", "0.1.0", None, None).unwrap();
+        database::record_crate_file(&conn, "my-crate", "0.1.0", &cksum, 10).unwrap();
+        database::set_yanked(&conn, "my-crate", "0.1.0", true).unwrap();
+        // Its crate file is gone.
+        database::record_publish(&conn, "my-crate", "0.2.0", None, None).unwrap();
+
+        let mut out = vec![];
+        let index_dir = data_root.path().join("new-index");
+        assert_eq!(
+            1,
+            execute(
+                &conn,
+                settings.crate_store.as_ref(),
+                &index_dir,
+                &config,
+                &Committer::default(),
+                &urls,
+                &mut out
+            )
+            .await
+            .unwrap()
+        );
+        assert_eq!(
+            "`my-crate` 0.2.0 was skipped: there's no crate file \
+            (`my-crate/my-crate-0.2.0.crate`)\n\
+            Rebuilt the index with 1 versions of 1 crates, 1 skipped.\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        let index = PackageIndex::open(&index_dir).unwrap();
+        let expected = PackageVersion {
+            yanked: true,
+            ..manifest::read(&crate_file, &urls)
+                .unwrap()
+                .package_version(&cksum)
+        };
+        assert_eq!(
+            vec![expected],
+            index.get_package_versions("my-crate").unwrap()
+        );
+        assert_eq!(config, index.read_config().unwrap());
+
+        // Not over an index that's there already.
+        assert!(execute(
+            &conn,
+            settings.crate_store.as_ref(),
+            &index_dir,
+            &config,
+            &Committer::default(),
+            &urls,
+            &mut vec![]
+        )
+        .await
+        .is_err());
+    }
+}
@@ -0,0 +1,90 @@
+//! `estuary reserve add|list|remove`
+
+use crate::cli::{ReserveCommand, ReserveOpt};
+use crate::database::{self, Connection, Holder};
+use crate::errors::EstuaryError;
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: ReserveOpt) -> Result<()> {
+    let conn = Connection::open(&opt.db.database_url())?;
+    database::init(&conn)?;
+    let stdout = std::io::stdout();
+    execute(&conn, opt.cmd, &mut stdout.lock())
+}
+
+fn execute(conn: &Connection, cmd: ReserveCommand, out: &mut impl Write) -> Result<()> {
+    match cmd {
+        ReserveCommand::Add {
+            crate_pattern,
+            holder,
+        } => {
+            let holder = Holder::parse(&holder);
+            let id = database::add_reservation(conn, &crate_pattern, &holder)?;
+            writeln!(
+                out,
+                "Added reservation {} of `{}` for `{}`.",
+                id, crate_pattern, holder
+            )?;
+        }
+        ReserveCommand::List => {
+            for reservation in database::list_reservations(conn)? {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}",
+                    reservation.id, reservation.crate_pattern, reservation.holder
+                )?;
+            }
+        }
+        ReserveCommand::Remove { id } => {
+            if !database::remove_reservation(conn, id)? {
+                return Err(EstuaryError::NotFound);
+            }
+            writeln!(out, "Removed reservation {}.", id)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        database::init(&conn).unwrap();
+        conn
+    }
+
+    fn execute_to_string(conn: &Connection, cmd: ReserveCommand) -> Result<String> {
+        let mut out = vec![];
+        execute(conn, cmd, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_add_list_remove() {
+        let conn = get_conn();
+        for (crate_pattern, holder) in &[("acme-*", "@backend"), ("alices-crate", "alice")] {
+            execute_to_string(
+                &conn,
+                ReserveCommand::Add {
+                    crate_pattern: crate_pattern.to_string(),
+                    holder: holder.to_string(),
+                },
+            )
+            .unwrap();
+        }
+
+        let listed = execute_to_string(&conn, ReserveCommand::List).unwrap();
+        assert_eq!("1\tacme-*\t@backend\n2\talices-crate\talice\n", listed);
+
+        execute_to_string(&conn, ReserveCommand::Remove { id: 1 }).unwrap();
+        assert!(matches!(
+            execute_to_string(&conn, ReserveCommand::Remove { id: 1 }),
+            Err(EstuaryError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,63 @@
+//! `estuary squash-index`
+
+use crate::cli::Opt;
+use crate::errors::EstuaryError;
+use crate::package_index::{lock, PackageIndex};
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(args: Opt) -> Result<()> {
+    let index = PackageIndex::open(&args.index_dir)?.with_committer(args.committer()?);
+    // Squashing underneath the server would lose whatever it commits
+    // meanwhile.
+    let _lock = lock::acquire(&args.index_dir)?;
+    let stdout = std::io::stdout();
+    execute(&index, &mut stdout.lock())
+}
+
+fn execute(index: &PackageIndex, out: &mut impl Write) -> Result<()> {
+    match index.squash()? {
+        Some(id) => writeln!(out, "Squashed the index history into `{}`.", id)?,
+        None => writeln!(out, "The index history is already a single commit.")?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_index::{Author, PackageVersion};
+    use crate::test_helpers;
+
+    #[test]
+    fn test_squash_index() {
+        let data_root = test_helpers::get_data_root();
+        test_helpers::get_test_package_index(data_root.path());
+        let index = PackageIndex::open(data_root.path()).unwrap();
+        let pkg = PackageVersion {
+            name: "my-crate".to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+        index.publish(&pkg, &Author::default()).unwrap();
+
+        let mut out = vec![];
+        execute(&index, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("Squashed the index history into `"));
+
+        let mut out = vec![];
+        execute(&index, &mut out).unwrap();
+        assert_eq!(
+            "The index history is already a single commit.\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(vec![pkg], index.get_package_versions("my-crate").unwrap());
+    }
+}
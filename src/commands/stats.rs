@@ -0,0 +1,108 @@
+//! `estuary stats`
+
+use crate::cli::StatsOpt;
+use crate::database::{self, Connection};
+use crate::errors::EstuaryError;
+use std::collections::HashSet;
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+/// How far back publishes count as recent ones.
+const RECENT_PUBLISH_DAYS: i64 = 30;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: StatsOpt) -> Result<()> {
+    let conn = Connection::open(&opt.db.database_url())?;
+    database::init(&conn)?;
+    let stdout = std::io::stdout();
+    execute(&conn, opt.top, &mut stdout.lock())
+}
+
+fn execute(conn: &Connection, top: usize, out: &mut impl Write) -> Result<()> {
+    let versions = database::list_versions(conn)?;
+    let crates = versions
+        .iter()
+        .map(|version| version.crate_name.to_lowercase())
+        .collect::<HashSet<_>>();
+    writeln!(out, "Crates: {}", crates.len())?;
+    writeln!(out, "Versions: {}", versions.len())?;
+    writeln!(
+        out,
+        "Published in the last {} days: {}",
+        RECENT_PUBLISH_DAYS,
+        database::count_recent_publishes(conn, RECENT_PUBLISH_DAYS)?
+    )?;
+    writeln!(
+        out,
+        "Crate files: {} bytes",
+        database::registry_usage(conn)?
+    )?;
+    writeln!(out, "Database: {} bytes", database::database_size(conn)?)?;
+
+    let largest = database::list_crate_usage(conn)?;
+    if top > 0 && !largest.is_empty() {
+        writeln!(out, "Largest crates:")?;
+        for usage in largest.iter().take(top) {
+            writeln!(
+                out,
+                "  {} ({} versions): {} bytes",
+                usage.crate_name, usage.versions, usage.bytes
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_stats() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        let size = database::database_size(&conn).unwrap();
+
+        let mut out = vec![];
+        execute(&conn, 10, &mut out).unwrap();
+        assert_eq!(
+            format!(
+                "Crates: 0\n\
+                Versions: 0\n\
+                Published in the last 30 days: 0\n\
+                Crate files: 0 bytes\n\
+                Database: {} bytes\n",
+                size
+            ),
+            String::from_utf8(out).unwrap()
+        );
+
+        for (name, version, digest, size) in &[
+            ("my-crate", "0.1.0", "abc", 10),
+            ("my-crate", "0.2.0", "def", 20),
+            ("other-crate", "1.0.0", "ghi", 5),
+        ] {
+            database::record_publish(&conn, name, version, None, None).unwrap();
+            database::record_crate_file(&conn, name, version, digest, *size).unwrap();
+        }
+        let size = database::database_size(&conn).unwrap();
+        let mut out = vec![];
+        execute(&conn, 1, &mut out).unwrap();
+        assert_eq!(
+            format!(
+                "Crates: 2\n\
+                Versions: 3\n\
+                Published in the last 30 days: 3\n\
+                Crate files: 35 bytes\n\
+                Database: {} bytes\n\
+                Largest crates:\n  \
+                my-crate (2 versions): 30 bytes\n",
+                size
+            ),
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
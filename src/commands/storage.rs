@@ -0,0 +1,58 @@
+//! `estuary storage relocate`
+
+use crate::cli::{StorageCommand, StorageOpt};
+use crate::errors::EstuaryError;
+use crate::storage::LocalStore;
+use std::io::Write;
+use std::path::Path;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: StorageOpt) -> Result<()> {
+    let stdout = std::io::stdout();
+    execute(&opt.crate_dir, opt.cmd, &mut stdout.lock())
+}
+
+fn execute(crate_dir: &Path, cmd: StorageCommand, out: &mut impl Write) -> Result<()> {
+    match cmd {
+        StorageCommand::Relocate { layout } => {
+            let store = LocalStore {
+                root: crate_dir.to_path_buf(),
+                layout,
+            };
+            let moved = store.relocate()?;
+            writeln!(out, "Moved {} crate files.", moved)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Layout;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_relocate() {
+        let data_root = test_helpers::get_data_root();
+        let crate_dir = data_root.path();
+        std::fs::create_dir_all(crate_dir.join("my-crate")).unwrap();
+        std::fs::write(crate_dir.join("my-crate/my-crate-0.1.0.crate"), b"").unwrap();
+
+        let mut out = vec![];
+        execute(
+            crate_dir,
+            StorageCommand::Relocate {
+                layout: Layout::Sharded,
+            },
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!("Moved 1 crate files.\n", String::from_utf8(out).unwrap());
+        assert!(crate_dir
+            .join("my/-c/my-crate/my-crate-0.1.0.crate")
+            .is_file());
+    }
+}
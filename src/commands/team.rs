@@ -0,0 +1,144 @@
+//! `estuary team add|list|remove|add-member|remove-member`
+
+use crate::cli::{TeamCommand, TeamOpt};
+use crate::database::{self, Connection, Team, User};
+use crate::errors::EstuaryError;
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: TeamOpt) -> Result<()> {
+    let conn = Connection::open(&opt.db.database_url())?;
+    database::init(&conn)?;
+    let stdout = std::io::stdout();
+    execute(&conn, opt.cmd, &mut stdout.lock())
+}
+
+/// Teams are written as `@name` in `cargo owner`, so take them either way.
+fn team_name(name: &str) -> &str {
+    name.strip_prefix('@').unwrap_or(name)
+}
+
+fn find_team(conn: &Connection, name: &str) -> Result<Team> {
+    database::find_team(conn, team_name(name))?.ok_or(EstuaryError::NotFound)
+}
+
+fn find_user(conn: &Connection, login: &str) -> Result<User> {
+    database::find_user(conn, login)?.ok_or(EstuaryError::NotFound)
+}
+
+fn execute(conn: &Connection, cmd: TeamCommand, out: &mut impl Write) -> Result<()> {
+    match cmd {
+        TeamCommand::Add { name } => {
+            let name = team_name(&name);
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(EstuaryError::Config(
+                    "Team names can't be empty or contain spaces.".to_string(),
+                ));
+            }
+            database::create_team(conn, name)?;
+            writeln!(out, "Added team `@{}`.", name)?;
+        }
+        TeamCommand::List => {
+            for (team, members) in database::list_teams(conn)? {
+                writeln!(out, "@{}\t{}", team.name, members.join(" "))?;
+            }
+        }
+        TeamCommand::Remove { name } => {
+            if !database::remove_team(conn, team_name(&name))? {
+                return Err(EstuaryError::NotFound);
+            }
+            writeln!(out, "Removed team `@{}`.", team_name(&name))?;
+        }
+        TeamCommand::AddMember { team, login } => {
+            let team = find_team(conn, &team)?;
+            let user = find_user(conn, &login)?;
+            database::add_team_member(conn, team.id, user.id)?;
+            writeln!(out, "Added `{}` to `@{}`.", user.login, team.name)?;
+        }
+        TeamCommand::RemoveMember { team, login } => {
+            let team = find_team(conn, &team)?;
+            let user = find_user(conn, &login)?;
+            if !database::remove_team_member(conn, team.id, user.id)? {
+                return Err(EstuaryError::NotFound);
+            }
+            writeln!(out, "Removed `{}` from `@{}`.", user.login, team.name)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        database::init(&conn).unwrap();
+        conn
+    }
+
+    fn execute_to_string(conn: &Connection, cmd: TeamCommand) -> Result<String> {
+        let mut out = vec![];
+        execute(conn, cmd, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_teams() {
+        let conn = get_conn();
+        database::find_or_create_user(&conn, "alice").unwrap();
+        database::find_or_create_user(&conn, "bob").unwrap();
+        execute_to_string(
+            &conn,
+            TeamCommand::Add {
+                name: "@backend".to_string(),
+            },
+        )
+        .unwrap();
+        for login in &["bob", "alice"] {
+            execute_to_string(
+                &conn,
+                TeamCommand::AddMember {
+                    team: "backend".to_string(),
+                    login: login.to_string(),
+                },
+            )
+            .unwrap();
+        }
+        assert!(matches!(
+            execute_to_string(
+                &conn,
+                TeamCommand::AddMember {
+                    team: "backend".to_string(),
+                    login: "carol".to_string(),
+                },
+            ),
+            Err(EstuaryError::NotFound)
+        ));
+
+        let listed = execute_to_string(&conn, TeamCommand::List).unwrap();
+        assert_eq!("@backend\talice bob\n", listed);
+
+        execute_to_string(
+            &conn,
+            TeamCommand::RemoveMember {
+                team: "@backend".to_string(),
+                login: "bob".to_string(),
+            },
+        )
+        .unwrap();
+        let listed = execute_to_string(&conn, TeamCommand::List).unwrap();
+        assert_eq!("@backend\talice\n", listed);
+
+        execute_to_string(
+            &conn,
+            TeamCommand::Remove {
+                name: "backend".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!("", execute_to_string(&conn, TeamCommand::List).unwrap());
+    }
+}
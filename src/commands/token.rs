@@ -0,0 +1,248 @@
+//! `estuary token create|add-key|list|revoke|remove-key|revocations`
+
+use crate::auth::{self, asymmetric};
+use crate::cli::{TokenCommand, TokenOpt};
+use crate::database::{self, Connection};
+use crate::errors::EstuaryError;
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: TokenOpt) -> Result<()> {
+    let conn = Connection::open(&opt.db.database_url())?;
+    database::init(&conn)?;
+    let stdout = std::io::stdout();
+    execute(&conn, opt.cmd, &mut stdout.lock())
+}
+
+fn execute(conn: &Connection, cmd: TokenCommand, out: &mut impl Write) -> Result<()> {
+    match cmd {
+        TokenCommand::Create { name, scopes } => {
+            let token = auth::generate_token();
+            database::create_token(conn, &name, &token, &scopes, None).map_err(|e| {
+                if database::is_unique_violation(&e) {
+                    EstuaryError::Config(format!("A token named `{}` already exists.", name))
+                } else {
+                    e.into()
+                }
+            })?;
+            writeln!(
+                out,
+                "Created token `{}` ({}). It won't be shown again:",
+                name,
+                database::join_scopes(&scopes)
+            )?;
+            writeln!(out, "{}", token)?;
+        }
+        TokenCommand::AddKey {
+            name,
+            public_key,
+            scopes,
+        } => {
+            let key_id = asymmetric::key_id(&public_key).map_err(EstuaryError::Config)?;
+            database::add_public_key(conn, &name, &public_key, &key_id, &scopes).map_err(|e| {
+                if database::is_unique_violation(&e) {
+                    EstuaryError::Config(format!(
+                        "A key named `{}` (or the same key) already exists.",
+                        name
+                    ))
+                } else {
+                    e.into()
+                }
+            })?;
+            writeln!(
+                out,
+                "Added key `{}` for `{}` ({}).",
+                key_id,
+                name,
+                database::join_scopes(&scopes)
+            )?;
+        }
+        TokenCommand::List => {
+            for token in database::list_tokens(conn)? {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    token.id,
+                    token.name,
+                    token.created_at,
+                    database::join_scopes(&token.scopes)
+                )?;
+            }
+            for (token, key_id) in database::list_public_keys(conn)? {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    key_id,
+                    token.name,
+                    token.created_at,
+                    database::join_scopes(&token.scopes)
+                )?;
+            }
+        }
+        TokenCommand::Revoke { name, reason } => {
+            if !database::revoke_token(conn, &name, "shell", reason.as_deref())? {
+                return Err(EstuaryError::NotFound);
+            }
+            writeln!(out, "Revoked token `{}`.", name)?;
+            if database::count_tokens(conn)? == 0 {
+                writeln!(
+                    out,
+                    "No tokens remain, so requests that need one will be refused until another \
+                    is issued."
+                )?;
+            }
+        }
+        TokenCommand::RemoveKey { key_id, reason } => {
+            let name = database::remove_public_key(conn, &key_id, "shell", reason.as_deref())?
+                .ok_or(EstuaryError::NotFound)?;
+            writeln!(out, "Removed key `{}` for `{}`.", key_id, name)?;
+        }
+        TokenCommand::Revocations => {
+            for revocation in database::list_revocations(conn)? {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}",
+                    revocation.revoked_at,
+                    revocation.token_name,
+                    revocation.revoked_by,
+                    revocation.reason.as_deref().unwrap_or("-")
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+
+    fn get_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        database::init(&conn).unwrap();
+        conn
+    }
+
+    fn execute_to_string(conn: &Connection, cmd: TokenCommand) -> Result<String> {
+        let mut out = vec![];
+        execute(conn, cmd, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_create_list_revoke() {
+        let conn = get_conn();
+        let created = execute_to_string(
+            &conn,
+            TokenCommand::Create {
+                name: "ci".to_string(),
+                scopes: vec![Scope::Publish],
+            },
+        )
+        .unwrap();
+        let token = created.lines().nth(1).unwrap();
+        let (found, hash) = database::list_token_hashes(&conn).unwrap().remove(0);
+        assert_eq!("ci", found.name);
+        assert_eq!(database::hash_token(token), hash);
+
+        let listed = execute_to_string(&conn, TokenCommand::List).unwrap();
+        assert!(listed.starts_with("1\tci\t"));
+        assert!(listed.trim_end().ends_with("\tpublish"));
+        assert!(!listed.contains(token));
+
+        let revoked = execute_to_string(
+            &conn,
+            TokenCommand::Revoke {
+                name: "ci".to_string(),
+                reason: Some("leaked".to_string()),
+            },
+        )
+        .unwrap();
+        assert!(revoked.contains("No tokens remain"));
+        assert_eq!("", execute_to_string(&conn, TokenCommand::List).unwrap());
+
+        let revocations = execute_to_string(&conn, TokenCommand::Revocations).unwrap();
+        assert!(revocations.trim_end().ends_with("\tci\tshell\tleaked"));
+    }
+
+    #[test]
+    fn test_add_key() {
+        let conn = get_conn();
+        // A public key from the PASERK test vectors.
+        let public_key =
+            "k3.public.AmDwjcy0ICGf8YuNytH9n9Y5TkFRofUVgOQNvWZV2yaGfRErDZhM3P5ZcFuiyBeMvQ";
+        let added = execute_to_string(
+            &conn,
+            TokenCommand::AddKey {
+                name: "ci".to_string(),
+                public_key: public_key.to_string(),
+                scopes: vec![Scope::Publish],
+            },
+        )
+        .unwrap();
+        assert!(added.starts_with("Added key `k3.pid."));
+
+        let listed = execute_to_string(&conn, TokenCommand::List).unwrap();
+        assert!(listed.starts_with("k3.pid."));
+        assert!(listed.trim_end().ends_with("\tpublish"));
+
+        assert!(matches!(
+            execute_to_string(
+                &conn,
+                TokenCommand::AddKey {
+                    name: "bad".to_string(),
+                    public_key: "k3.public.nope".to_string(),
+                    scopes: vec![Scope::Publish],
+                },
+            ),
+            Err(EstuaryError::Config(_))
+        ));
+
+        let key_id = listed.split('\t').next().unwrap().to_string();
+        let remove = || TokenCommand::RemoveKey {
+            key_id: key_id.clone(),
+            reason: Some("rotated".to_string()),
+        };
+        let removed = execute_to_string(&conn, remove()).unwrap();
+        assert_eq!(format!("Removed key `{}` for `ci`.\n", key_id), removed);
+        assert_eq!("", execute_to_string(&conn, TokenCommand::List).unwrap());
+        assert!(matches!(
+            execute_to_string(&conn, remove()),
+            Err(EstuaryError::NotFound)
+        ));
+        let revocations = execute_to_string(&conn, TokenCommand::Revocations).unwrap();
+        assert!(revocations.trim_end().ends_with("\tci\tshell\trotated"));
+    }
+
+    #[test]
+    fn test_create_duplicate_is_err() {
+        let conn = get_conn();
+        let create = || TokenCommand::Create {
+            name: "ci".to_string(),
+            scopes: Scope::ALL.to_vec(),
+        };
+        execute_to_string(&conn, create()).unwrap();
+        assert!(matches!(
+            execute_to_string(&conn, create()),
+            Err(EstuaryError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_revoke_missing_is_not_found() {
+        let conn = get_conn();
+        assert!(matches!(
+            execute_to_string(
+                &conn,
+                TokenCommand::Revoke {
+                    name: "nope".to_string(),
+                    reason: None,
+                }
+            ),
+            Err(EstuaryError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,134 @@
+//! `estuary trust add|list|remove`
+
+use crate::cli::{TrustCommand, TrustOpt};
+use crate::database::{self, Connection, TrustedPublisher};
+use crate::errors::EstuaryError;
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: TrustOpt) -> Result<()> {
+    let conn = Connection::open(&opt.db.database_url())?;
+    database::init(&conn)?;
+    let stdout = std::io::stdout();
+    execute(&conn, opt.cmd, &mut stdout.lock())
+}
+
+fn execute(conn: &Connection, cmd: TrustCommand, out: &mut impl Write) -> Result<()> {
+    match cmd {
+        TrustCommand::Add {
+            crate_name,
+            provider,
+            repository,
+            workflow,
+            branch,
+            issuer,
+        } => {
+            let rule = TrustedPublisher {
+                id: 0,
+                crate_name,
+                provider,
+                issuer: issuer.unwrap_or_else(|| provider.default_issuer().to_string()),
+                repository,
+                workflow,
+                branch,
+            };
+            let id = database::add_trusted_publisher(conn, &rule)?;
+            writeln!(
+                out,
+                "Added trusted publisher {} for `{}`.",
+                id, rule.crate_name
+            )?;
+        }
+        TrustCommand::List { crate_name } => {
+            for rule in database::list_trusted_publishers(conn, crate_name.as_deref())? {
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    rule.id,
+                    rule.crate_name,
+                    rule.issuer,
+                    rule.repository,
+                    rule.workflow.as_deref().unwrap_or("*"),
+                    rule.branch.as_deref().unwrap_or("*"),
+                )?;
+            }
+        }
+        TrustCommand::Remove { id } => {
+            if !database::remove_trusted_publisher(conn, id)? {
+                return Err(EstuaryError::NotFound);
+            }
+            writeln!(out, "Removed trusted publisher {}.", id)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::trusted::Provider;
+
+    fn get_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        database::init(&conn).unwrap();
+        conn
+    }
+
+    fn execute_to_string(conn: &Connection, cmd: TrustCommand) -> Result<String> {
+        let mut out = vec![];
+        execute(conn, cmd, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_add_list_remove() {
+        let conn = get_conn();
+        execute_to_string(
+            &conn,
+            TrustCommand::Add {
+                crate_name: "my-crate".to_string(),
+                provider: Provider::GitHub,
+                repository: "example/my-crate".to_string(),
+                workflow: Some("release.yml".to_string()),
+                branch: None,
+                issuer: None,
+            },
+        )
+        .unwrap();
+        execute_to_string(
+            &conn,
+            TrustCommand::Add {
+                crate_name: "other-crate".to_string(),
+                provider: Provider::GitLab,
+                repository: "group/other-crate".to_string(),
+                workflow: None,
+                branch: Some("main".to_string()),
+                issuer: Some("https://gitlab.example.com".to_string()),
+            },
+        )
+        .unwrap();
+
+        let listed = execute_to_string(
+            &conn,
+            TrustCommand::List {
+                crate_name: Some("my-crate".to_string()),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            "1\tmy-crate\thttps://token.actions.githubusercontent.com\texample/my-crate\
+            \trelease.yml\t*\n",
+            listed
+        );
+        let listed = execute_to_string(&conn, TrustCommand::List { crate_name: None }).unwrap();
+        assert!(listed.contains("\thttps://gitlab.example.com\t"));
+
+        execute_to_string(&conn, TrustCommand::Remove { id: 1 }).unwrap();
+        assert!(matches!(
+            execute_to_string(&conn, TrustCommand::Remove { id: 1 }),
+            Err(EstuaryError::NotFound)
+        ));
+    }
+}
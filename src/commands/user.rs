@@ -0,0 +1,200 @@
+//! `estuary user add|list|remove|promote|demote|scopes`
+
+use crate::auth;
+use crate::cli::{UserCommand, UserOpt};
+use crate::database::{self, Connection};
+use crate::errors::EstuaryError;
+use std::io::{BufRead, Write};
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub fn run(opt: UserOpt) -> Result<()> {
+    let conn = Connection::open(&opt.db.database_url())?;
+    database::init(&conn)?;
+    let stdin = std::io::stdin();
+    let stdout = std::io::stdout();
+    execute(&conn, opt.cmd, &mut stdin.lock(), &mut stdout.lock())
+}
+
+fn execute(
+    conn: &Connection,
+    cmd: UserCommand,
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> Result<()> {
+    match cmd {
+        UserCommand::Add { login } => {
+            if login.is_empty() || login.contains(':') {
+                return Err(EstuaryError::Config(
+                    "Logins can't be empty or contain `:`.".to_string(),
+                ));
+            }
+            let mut password = String::new();
+            input.read_line(&mut password)?;
+            let password = password.trim_end_matches(&['\r', '\n'][..]);
+            if password.is_empty() {
+                return Err(EstuaryError::Config(
+                    "Expected a password on stdin.".to_string(),
+                ));
+            }
+            let hash = auth::hash_password(password).map_err(EstuaryError::Config)?;
+            database::set_user_password(conn, &login, &hash)?;
+            writeln!(out, "Saved user `{}`.", login)?;
+        }
+        UserCommand::List => {
+            let admins = database::list_admins(conn)?;
+            for login in database::list_users(conn)? {
+                if admins.contains(&login) {
+                    writeln!(out, "{}\tadmin", login)?;
+                } else {
+                    writeln!(out, "{}", login)?;
+                }
+            }
+        }
+        UserCommand::Remove { login } => {
+            if !database::remove_user(conn, &login)? {
+                return Err(EstuaryError::NotFound);
+            }
+            writeln!(out, "Removed user `{}`.", login)?;
+        }
+        UserCommand::Promote { login } => {
+            if !database::set_admin(conn, &login, true)? {
+                return Err(EstuaryError::NotFound);
+            }
+            writeln!(out, "`{}` is now an admin.", login)?;
+        }
+        UserCommand::Demote { login } => {
+            if !database::set_admin(conn, &login, false)? {
+                return Err(EstuaryError::NotFound);
+            }
+            writeln!(out, "`{}` is no longer an admin.", login)?;
+        }
+        UserCommand::Scopes { login, scopes } => {
+            if !database::set_user_scopes(conn, &login, &scopes)? {
+                return Err(EstuaryError::NotFound);
+            }
+            writeln!(
+                out,
+                "`{}` can now issue tokens for `{}`.",
+                login,
+                database::join_scopes(&scopes)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+
+    fn get_conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        database::init(&conn).unwrap();
+        conn
+    }
+
+    fn execute_to_string(conn: &Connection, cmd: UserCommand, input: &str) -> Result<String> {
+        let mut out = vec![];
+        execute(conn, cmd, &mut input.as_bytes(), &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_add_list_remove() {
+        let conn = get_conn();
+        let add = UserCommand::Add {
+            login: "alice".to_string(),
+        };
+        execute_to_string(&conn, add, "hunter2\n").unwrap();
+        let hash = database::find_user_password_hash(&conn, "alice")
+            .unwrap()
+            .unwrap();
+        assert!(!hash.contains("hunter2"));
+
+        assert_eq!(
+            "alice\n",
+            execute_to_string(&conn, UserCommand::List, "").unwrap()
+        );
+
+        let remove = || UserCommand::Remove {
+            login: "alice".to_string(),
+        };
+        execute_to_string(&conn, remove(), "").unwrap();
+        assert_eq!("", execute_to_string(&conn, UserCommand::List, "").unwrap());
+        assert!(matches!(
+            execute_to_string(&conn, remove(), ""),
+            Err(EstuaryError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_promote_demote() {
+        let conn = get_conn();
+        database::find_or_create_user(&conn, "alice").unwrap();
+        database::find_or_create_user(&conn, "bob").unwrap();
+        let promote = || UserCommand::Promote {
+            login: "alice".to_string(),
+        };
+        execute_to_string(&conn, promote(), "").unwrap();
+        assert_eq!(
+            "alice\tadmin\nbob\n",
+            execute_to_string(&conn, UserCommand::List, "").unwrap()
+        );
+
+        let demote = UserCommand::Demote {
+            login: "alice".to_string(),
+        };
+        execute_to_string(&conn, demote, "").unwrap();
+        assert_eq!(
+            "alice\nbob\n",
+            execute_to_string(&conn, UserCommand::List, "").unwrap()
+        );
+        assert!(matches!(
+            execute_to_string(
+                &conn,
+                UserCommand::Promote {
+                    login: "carol".to_string()
+                },
+                ""
+            ),
+            Err(EstuaryError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_scopes() {
+        let conn = get_conn();
+        let alice = database::find_or_create_user(&conn, "alice").unwrap();
+        let scopes = |login: &str| UserCommand::Scopes {
+            login: login.to_string(),
+            scopes: vec![Scope::Read, Scope::Publish],
+        };
+        assert_eq!(
+            "`alice` can now issue tokens for `read,publish`.\n",
+            execute_to_string(&conn, scopes("alice"), "").unwrap()
+        );
+        assert_eq!(
+            vec![Scope::Read, Scope::Publish],
+            database::find_user_scopes(&conn, alice.id).unwrap()
+        );
+        assert!(matches!(
+            execute_to_string(&conn, scopes("bob"), ""),
+            Err(EstuaryError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_add_needs_password() {
+        let conn = get_conn();
+        let add = UserCommand::Add {
+            login: "alice".to_string(),
+        };
+        assert!(matches!(
+            execute_to_string(&conn, add, "\n"),
+            Err(EstuaryError::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+//! `estuary verify`
+
+use crate::cli::Opt;
+use crate::database::{self, Connection};
+use crate::errors::EstuaryError;
+use crate::package_index::PackageIndex;
+use crate::storage::CrateStore;
+use crate::verify;
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[cfg(not(tarpaulin_include))]
+pub async fn run(args: Opt) -> Result<()> {
+    let conn = Connection::open(&args.database_url())?;
+    database::init(&conn)?;
+    let index = PackageIndex::open(&args.index_dir)?;
+    verify::backfill_yanks(&index, &conn)?;
+    let store = args.crate_store()?;
+    let stdout = std::io::stdout();
+    let problems = execute(&index, &conn, store.as_ref(), &mut stdout.lock()).await?;
+    // So scripts (and cron) can tell something's wrong.
+    if problems > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Print each problem found, handing back how many there were.
+async fn execute(
+    index: &PackageIndex,
+    conn: &Connection,
+    store: &dyn CrateStore,
+    out: &mut impl Write,
+) -> Result<usize> {
+    let report = verify::check(
+        &verify::index_versions(index)?,
+        &database::list_versions(conn)?,
+        store,
+    )
+    .await;
+    for problem in &report.problems {
+        writeln!(out, "{}", problem)?;
+    }
+    writeln!(
+        out,
+        "Checked {} versions, {} problems found.",
+        report.versions,
+        report.problems.len()
+    )?;
+    Ok(report.problems.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Layout, LocalStore};
+    use crate::test_helpers;
+
+    #[actix_rt::test]
+    async fn test_verify() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        test_helpers::get_test_package_index(&settings.index_dir);
+        let index = PackageIndex::open(&settings.index_dir).unwrap();
+        let store = LocalStore {
+            root: settings.crate_dir.clone(),
+            layout: Layout::Flat,
+        };
+
+        let mut out = vec![];
+        assert_eq!(0, execute(&index, &conn, &store, &mut out).await.unwrap());
+        assert_eq!(
+            "Checked 0 versions, 0 problems found.\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        database::record_publish(&conn, "my-crate", "0.1.0", None, None).unwrap();
+        let mut out = vec![];
+        assert_eq!(1, execute(&index, &conn, &store, &mut out).await.unwrap());
+        assert_eq!(
+            "`my-crate` 0.1.0 is in the database, but not the index\n\
+            Checked 1 versions, 1 problems found.\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
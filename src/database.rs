@@ -0,0 +1,4179 @@
+//! Registry state that doesn't belong in the package index, kept in SQLite
+//! or Postgres.
+//!
+//! The database is a SQLite file alongside the crate files by default (see
+//! `--db-path`), or whatever `--database-url` points at, so that several
+//! servers can share one. The server keeps a [Pool] of connections, which
+//! handlers borrow from via [`Settings::with_db`](crate::Settings::with_db).
+//!
+//! Everything here blocks, so handlers only call it through `with_db`, which
+//! runs it on the blocking thread pool rather than on a worker.
+
+mod connection;
+
+use crate::auth::trusted::Provider;
+use crate::auth::Scope;
+use crate::package_index::normalize_name;
+pub(crate) use connection::params;
+pub use connection::{
+    Connection, DatabaseUrl, Error, JournalMode, Manager, OptionalExtension, Pragmas, Synchronous,
+    ToSql,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+type Result<T> = std::result::Result<T, Error>;
+
+pub type Pool = r2d2::Pool<Manager>;
+
+/// A connection borrowed from a [Pool], which goes back to it when dropped.
+pub type PooledConnection = r2d2::PooledConnection<Manager>;
+
+/// Connections to the database at `url`, at most `size` of them at once.
+///
+/// Each waits up to `busy_timeout` for another's lock before giving up, and
+/// SQLite connections are set up with `pragmas` as they're opened.
+pub fn pool(
+    url: &DatabaseUrl,
+    size: u32,
+    busy_timeout: Duration,
+    pragmas: Pragmas,
+) -> std::result::Result<Pool, r2d2::Error> {
+    let manager = Manager {
+        url: url.clone(),
+        busy_timeout,
+        pragmas,
+    };
+    r2d2::Pool::builder().max_size(size).build(manager)
+}
+
+/// How the schema has changed, oldest first.
+///
+/// Each migration is run once (in a transaction), and `schema_version` keeps
+/// track of which have been. Changes to the schema go in a new migration on
+/// the end; ones that have been released are left as they are.
+///
+/// Migrations are written for both SQLite and Postgres, and leave the two
+/// schemas equivalent: `COLLATE NOCASE` columns are `CITEXT`, integers are
+/// `BIGINT`s, and timestamps are seconds since the epoch in either.
+const MIGRATIONS: &[fn(&Connection) -> Result<()>] = &[
+    baseline,
+    add_crate_metadata,
+    add_downloads,
+    add_search,
+    add_yanked_at,
+    add_tombstones,
+    add_user_backends,
+    add_backfills,
+];
+
+/// Bring the schema up to date, running whichever migrations haven't been.
+pub fn init(conn: &Connection) -> Result<()> {
+    if conn.is_postgres() {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS schema_version (
+                version BIGINT PRIMARY KEY,
+                applied_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT)
+            );",
+        )?;
+    } else {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER PRIMARY KEY,
+                applied_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            );",
+        )?;
+    }
+    let current = schema_version(conn)?;
+    if current > MIGRATIONS.len() {
+        log::warn!(
+            "The database's schema (version {}) is newer than this version of Estuary knows \
+            (version {}). Carrying on, but going back to an older version isn't supported.",
+            current,
+            MIGRATIONS.len()
+        );
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        // Taking the lock up front means only one process migrates.
+        let tx = conn.exclusive_transaction()?;
+        if schema_version(&tx)? > i {
+            continue;
+        }
+        migration(&tx)?;
+        tx.execute(
+            "INSERT INTO schema_version (version) VALUES (?1)",
+            params![i + 1],
+        )?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// What [schema_version] is once every migration has been run.
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+/// How many migrations have been run.
+pub fn schema_version(conn: &Connection) -> Result<usize> {
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+        params![],
+        |row| row.get(0),
+    )
+}
+
+/// The schema as it was before there were migrations.
+///
+/// Databases from back then could be in any state, so this only creates the
+/// tables (and columns) that don't already exist. Postgres came later, so its
+/// databases start out as the last of those states.
+fn baseline(conn: &Connection) -> Result<()> {
+    if conn.is_postgres() {
+        return postgres_baseline(conn);
+    }
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tokens (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            token TEXT NOT NULL UNIQUE,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );",
+    )?;
+    // Tokens issued before scopes existed could do anything, so they get
+    // every scope there is by default (`admin` is only ever asked for).
+    if !has_column(conn, "tokens", "scopes")? {
+        conn.execute(
+            &format!(
+                "ALTER TABLE tokens ADD COLUMN scopes TEXT NOT NULL DEFAULT '{}'",
+                join_scopes(Scope::DEFAULT)
+            ),
+            params![],
+        )?;
+    }
+    if !has_column(conn, "tokens", "token_hash")? {
+        hash_stored_tokens(conn)?;
+    }
+    conn.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS public_keys (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            public_key TEXT NOT NULL UNIQUE,
+            key_id TEXT NOT NULL UNIQUE,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            scopes TEXT NOT NULL DEFAULT '{}'
+        );",
+        join_scopes(Scope::DEFAULT)
+    ))?;
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY,
+            login TEXT NOT NULL UNIQUE,
+            password_hash TEXT,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );",
+    )?;
+    // Users who log in through an identity provider are found by its subject
+    // identifier, which (unlike their username) isn't supposed to change.
+    if !has_column(conn, "users", "oidc_subject")? {
+        conn.execute_batch(
+            "ALTER TABLE users ADD COLUMN oidc_subject TEXT;
+            CREATE UNIQUE INDEX users_oidc_subject ON users (oidc_subject);",
+        )?;
+    }
+    if !has_column(conn, "users", "is_admin")? {
+        conn.execute(
+            "ALTER TABLE users ADD COLUMN is_admin INTEGER NOT NULL DEFAULT 0",
+            params![],
+        )?;
+    }
+    if !has_column(conn, "tokens", "user_id")? {
+        conn.execute(
+            "ALTER TABLE tokens ADD COLUMN user_id INTEGER REFERENCES users (id)",
+            params![],
+        )?;
+    }
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            session_hash TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL REFERENCES users (id),
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );
+        CREATE TABLE IF NOT EXISTS trusted_publishers (
+            id INTEGER PRIMARY KEY,
+            crate_name TEXT NOT NULL,
+            provider TEXT NOT NULL,
+            issuer TEXT NOT NULL,
+            repository TEXT NOT NULL,
+            workflow TEXT,
+            branch TEXT,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );
+        CREATE TABLE IF NOT EXISTS crate_grants (
+            id INTEGER PRIMARY KEY,
+            crate_pattern TEXT NOT NULL,
+            token_name TEXT,
+            user_login TEXT,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            CHECK ((token_name IS NULL) <> (user_login IS NULL))
+        );
+        CREATE TABLE IF NOT EXISTS reservations (
+            id INTEGER PRIMARY KEY,
+            crate_pattern TEXT NOT NULL,
+            user_login TEXT,
+            team_name TEXT COLLATE NOCASE,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            CHECK ((user_login IS NULL) <> (team_name IS NULL))
+        );
+        CREATE TABLE IF NOT EXISTS revocations (
+            id INTEGER PRIMARY KEY,
+            token_name TEXT NOT NULL,
+            revoked_by TEXT NOT NULL,
+            reason TEXT,
+            revoked_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );
+        CREATE TABLE IF NOT EXISTS crate_owners (
+            crate_name TEXT NOT NULL COLLATE NOCASE,
+            user_id INTEGER NOT NULL REFERENCES users (id),
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (crate_name, user_id)
+        );
+        CREATE TABLE IF NOT EXISTS owner_invitations (
+            crate_name TEXT NOT NULL COLLATE NOCASE,
+            invitee_id INTEGER NOT NULL REFERENCES users (id),
+            inviter_id INTEGER REFERENCES users (id),
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (crate_name, invitee_id)
+        );
+        CREATE TABLE IF NOT EXISTS teams (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );
+        CREATE TABLE IF NOT EXISTS team_members (
+            team_id INTEGER NOT NULL REFERENCES teams (id),
+            user_id INTEGER NOT NULL REFERENCES users (id),
+            PRIMARY KEY (team_id, user_id)
+        );
+        CREATE TABLE IF NOT EXISTS crate_team_owners (
+            crate_name TEXT NOT NULL COLLATE NOCASE,
+            team_id INTEGER NOT NULL REFERENCES teams (id),
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (crate_name, team_id)
+        );
+        CREATE TABLE IF NOT EXISTS crate_versions (
+            crate_name TEXT NOT NULL COLLATE NOCASE,
+            version TEXT NOT NULL,
+            user_id INTEGER REFERENCES users (id),
+            token_name TEXT,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            PRIMARY KEY (crate_name, version)
+        );
+        CREATE TABLE IF NOT EXISTS crate_files (
+            crate_name TEXT NOT NULL COLLATE NOCASE,
+            version TEXT NOT NULL,
+            digest TEXT NOT NULL,
+            PRIMARY KEY (crate_name, version)
+        );
+        CREATE INDEX IF NOT EXISTS crate_files_digest ON crate_files (digest);
+        CREATE TABLE IF NOT EXISTS login_states (
+            state TEXT PRIMARY KEY,
+            nonce TEXT NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );",
+    )?;
+    // Sizes weren't kept at first, so those files don't count toward quotas.
+    if !has_column(conn, "crate_files", "size")? {
+        conn.execute(
+            "ALTER TABLE crate_files ADD COLUMN size INTEGER NOT NULL DEFAULT 0",
+            params![],
+        )?;
+    }
+    Ok(())
+}
+
+fn postgres_baseline(conn: &Connection) -> Result<()> {
+    conn.execute_batch(&format!(
+        "CREATE EXTENSION IF NOT EXISTS citext;
+        CREATE TABLE users (
+            id BIGSERIAL PRIMARY KEY,
+            login TEXT NOT NULL UNIQUE,
+            password_hash TEXT,
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT),
+            oidc_subject TEXT,
+            is_admin BOOLEAN NOT NULL DEFAULT FALSE
+        );
+        CREATE UNIQUE INDEX users_oidc_subject ON users (oidc_subject);
+        CREATE TABLE tokens (
+            id BIGSERIAL PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            token_hash TEXT NOT NULL UNIQUE,
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT),
+            scopes TEXT NOT NULL DEFAULT '{scopes}',
+            user_id BIGINT REFERENCES users (id)
+        );
+        CREATE TABLE public_keys (
+            id BIGSERIAL PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            public_key TEXT NOT NULL UNIQUE,
+            key_id TEXT NOT NULL UNIQUE,
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT),
+            scopes TEXT NOT NULL DEFAULT '{scopes}'
+        );
+        CREATE TABLE sessions (
+            session_hash TEXT PRIMARY KEY,
+            user_id BIGINT NOT NULL REFERENCES users (id),
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT)
+        );
+        CREATE TABLE trusted_publishers (
+            id BIGSERIAL PRIMARY KEY,
+            crate_name TEXT NOT NULL,
+            provider TEXT NOT NULL,
+            issuer TEXT NOT NULL,
+            repository TEXT NOT NULL,
+            workflow TEXT,
+            branch TEXT,
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT)
+        );
+        CREATE TABLE crate_grants (
+            id BIGSERIAL PRIMARY KEY,
+            crate_pattern TEXT NOT NULL,
+            token_name TEXT,
+            user_login TEXT,
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT),
+            CHECK ((token_name IS NULL) <> (user_login IS NULL))
+        );
+        CREATE TABLE reservations (
+            id BIGSERIAL PRIMARY KEY,
+            crate_pattern TEXT NOT NULL,
+            user_login TEXT,
+            team_name CITEXT,
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT),
+            CHECK ((user_login IS NULL) <> (team_name IS NULL))
+        );
+        CREATE TABLE revocations (
+            id BIGSERIAL PRIMARY KEY,
+            token_name TEXT NOT NULL,
+            revoked_by TEXT NOT NULL,
+            reason TEXT,
+            revoked_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT)
+        );
+        CREATE TABLE crate_owners (
+            crate_name CITEXT NOT NULL,
+            user_id BIGINT NOT NULL REFERENCES users (id),
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT),
+            PRIMARY KEY (crate_name, user_id)
+        );
+        CREATE TABLE owner_invitations (
+            crate_name CITEXT NOT NULL,
+            invitee_id BIGINT NOT NULL REFERENCES users (id),
+            inviter_id BIGINT REFERENCES users (id),
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT),
+            PRIMARY KEY (crate_name, invitee_id)
+        );
+        CREATE TABLE teams (
+            id BIGSERIAL PRIMARY KEY,
+            name CITEXT NOT NULL UNIQUE,
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT)
+        );
+        CREATE TABLE team_members (
+            team_id BIGINT NOT NULL REFERENCES teams (id),
+            user_id BIGINT NOT NULL REFERENCES users (id),
+            PRIMARY KEY (team_id, user_id)
+        );
+        CREATE TABLE crate_team_owners (
+            crate_name CITEXT NOT NULL,
+            team_id BIGINT NOT NULL REFERENCES teams (id),
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT),
+            PRIMARY KEY (crate_name, team_id)
+        );
+        -- SQLite gives every table a `rowid`, which is what publishes made in
+        -- the same second are ordered by.
+        CREATE TABLE crate_versions (
+            rowid BIGSERIAL,
+            crate_name CITEXT NOT NULL,
+            version TEXT NOT NULL,
+            user_id BIGINT REFERENCES users (id),
+            token_name TEXT,
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT),
+            PRIMARY KEY (crate_name, version)
+        );
+        CREATE TABLE crate_files (
+            crate_name CITEXT NOT NULL,
+            version TEXT NOT NULL,
+            digest TEXT NOT NULL,
+            size BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (crate_name, version)
+        );
+        CREATE INDEX crate_files_digest ON crate_files (digest);
+        CREATE TABLE login_states (
+            state TEXT PRIMARY KEY,
+            nonce TEXT NOT NULL,
+            created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT)
+        );",
+        scopes = join_scopes(Scope::DEFAULT)
+    ))
+}
+
+/// What cargo says about each version when it's published, beyond what the
+/// index has.
+fn add_crate_metadata(conn: &Connection) -> Result<()> {
+    if conn.is_postgres() {
+        return conn.execute_batch(
+            "ALTER TABLE crate_versions
+                ADD COLUMN description TEXT,
+                ADD COLUMN documentation TEXT,
+                ADD COLUMN homepage TEXT,
+                ADD COLUMN readme TEXT,
+                ADD COLUMN readme_file TEXT,
+                ADD COLUMN license TEXT,
+                ADD COLUMN license_file TEXT,
+                ADD COLUMN repository TEXT;
+            CREATE TABLE crate_keywords (
+                crate_name CITEXT NOT NULL,
+                version TEXT NOT NULL,
+                keyword CITEXT NOT NULL,
+                PRIMARY KEY (crate_name, version, keyword)
+            );
+            CREATE INDEX crate_keywords_keyword ON crate_keywords (keyword);
+            CREATE TABLE crate_categories (
+                crate_name CITEXT NOT NULL,
+                version TEXT NOT NULL,
+                category CITEXT NOT NULL,
+                PRIMARY KEY (crate_name, version, category)
+            );
+            CREATE INDEX crate_categories_category ON crate_categories (category);
+            CREATE TABLE crate_authors (
+                crate_name CITEXT NOT NULL,
+                version TEXT NOT NULL,
+                position BIGINT NOT NULL,
+                author TEXT NOT NULL,
+                PRIMARY KEY (crate_name, version, position)
+            );",
+        );
+    }
+    conn.execute_batch(
+        "ALTER TABLE crate_versions ADD COLUMN description TEXT;
+        ALTER TABLE crate_versions ADD COLUMN documentation TEXT;
+        ALTER TABLE crate_versions ADD COLUMN homepage TEXT;
+        ALTER TABLE crate_versions ADD COLUMN readme TEXT;
+        ALTER TABLE crate_versions ADD COLUMN readme_file TEXT;
+        ALTER TABLE crate_versions ADD COLUMN license TEXT;
+        ALTER TABLE crate_versions ADD COLUMN license_file TEXT;
+        ALTER TABLE crate_versions ADD COLUMN repository TEXT;
+        CREATE TABLE crate_keywords (
+            crate_name TEXT NOT NULL COLLATE NOCASE,
+            version TEXT NOT NULL,
+            keyword TEXT NOT NULL COLLATE NOCASE,
+            PRIMARY KEY (crate_name, version, keyword)
+        );
+        CREATE INDEX crate_keywords_keyword ON crate_keywords (keyword);
+        CREATE TABLE crate_categories (
+            crate_name TEXT NOT NULL COLLATE NOCASE,
+            version TEXT NOT NULL,
+            category TEXT NOT NULL COLLATE NOCASE,
+            PRIMARY KEY (crate_name, version, category)
+        );
+        CREATE INDEX crate_categories_category ON crate_categories (category);
+        CREATE TABLE crate_authors (
+            crate_name TEXT NOT NULL COLLATE NOCASE,
+            version TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            author TEXT NOT NULL,
+            PRIMARY KEY (crate_name, version, position)
+        );",
+    )
+}
+
+fn add_downloads(conn: &Connection) -> Result<()> {
+    let (name, count) = if conn.is_postgres() {
+        ("CITEXT NOT NULL", "BIGINT")
+    } else {
+        ("TEXT NOT NULL COLLATE NOCASE", "INTEGER")
+    };
+    conn.execute_batch(&format!(
+        "CREATE TABLE downloads (
+            crate_name {},
+            version TEXT NOT NULL,
+            date TEXT NOT NULL,
+            count {} NOT NULL,
+            PRIMARY KEY (crate_name, version, date)
+        );",
+        name, count
+    ))
+}
+
+/// What search looks through: each crate's name, and the keywords,
+/// description, and readme of its newest version. SQLite keeps it in an FTS5
+/// table; Postgres searches a plain one with its own full-text functions.
+fn add_search(conn: &Connection) -> Result<()> {
+    if conn.is_postgres() {
+        conn.execute_batch(
+            "CREATE TABLE crate_search (
+                crate_name CITEXT PRIMARY KEY,
+                keywords TEXT NOT NULL,
+                description TEXT NOT NULL,
+                readme TEXT NOT NULL
+            );",
+        )?;
+    } else {
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE crate_search USING fts5(
+                crate_name, keywords, description, readme
+            );",
+        )?;
+    }
+    let crate_names: Vec<String> = conn.query_map(
+        "SELECT DISTINCT crate_name FROM crate_versions",
+        params![],
+        |row| row.get(0),
+    )?;
+    for crate_name in crate_names {
+        write_search_entry(conn, &crate_name)?;
+    }
+    Ok(())
+}
+
+/// When each version was yanked, if it is, kept alongside the index.
+/// Versions yanked before then aren't marked.
+fn add_yanked_at(conn: &Connection) -> Result<()> {
+    let timestamp = if conn.is_postgres() {
+        "BIGINT"
+    } else {
+        "INTEGER"
+    };
+    conn.execute_batch(&format!(
+        "ALTER TABLE crate_versions ADD COLUMN yanked_at {};",
+        timestamp
+    ))
+}
+
+/// What's left of deleted versions: enough to refuse the same version being
+/// published again.
+fn add_tombstones(conn: &Connection) -> Result<()> {
+    let (name, timestamp) = if conn.is_postgres() {
+        ("CITEXT NOT NULL", "BIGINT")
+    } else {
+        ("TEXT NOT NULL COLLATE NOCASE", "INTEGER")
+    };
+    conn.execute_batch(&format!(
+        "CREATE TABLE tombstones (
+            crate_name {},
+            version TEXT NOT NULL,
+            cksum TEXT NOT NULL,
+            reason TEXT,
+            deleted_at {} NOT NULL,
+            PRIMARY KEY (crate_name, version)
+        );",
+        name, timestamp
+    ))
+}
+
+/// Which kind of account each user is, so accounts from different places
+/// with the same login aren't mixed up, and the most their web tokens may do.
+///
+/// Users from before then without a password or a subject could only have
+/// come from the directory server. Only those from the identity provider
+/// could issue themselves more than `read` tokens without being asked.
+fn add_user_backends(conn: &Connection) -> Result<()> {
+    conn.execute_batch(&format!(
+        "ALTER TABLE users ADD COLUMN backend TEXT NOT NULL DEFAULT 'local';
+        ALTER TABLE users ADD COLUMN scopes TEXT NOT NULL DEFAULT 'read';
+        UPDATE users SET backend = 'oidc', scopes = '{}' WHERE oidc_subject IS NOT NULL;
+        UPDATE users SET backend = 'ldap' WHERE oidc_subject IS NULL AND password_hash IS NULL;",
+        join_scopes(OIDC_USER_SCOPES)
+    ))
+}
+
+/// Data the schema should have had all along, which can only be filled in
+/// from elsewhere once the index is open, and still has to be.
+///
+/// Versions yanked before `yanked_at` was added are still marked as yanked in
+/// the index, and only there.
+fn add_backfills(conn: &Connection) -> Result<()> {
+    conn.execute_batch(&format!(
+        "CREATE TABLE backfills (name TEXT PRIMARY KEY);
+        INSERT INTO backfills (name) SELECT '{}' WHERE EXISTS (SELECT 1 FROM crate_versions);",
+        BACKFILL_YANKS
+    ))
+}
+
+/// Swap the plaintext `token` column for a `token_hash` column.
+///
+/// SQLite can't drop a `UNIQUE` column, so the table is rebuilt (inside the
+/// migration's transaction).
+fn hash_stored_tokens(tx: &Connection) -> Result<()> {
+    tx.execute_batch(&format!(
+        "CREATE TABLE tokens_hashed (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            token_hash TEXT NOT NULL UNIQUE,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            scopes TEXT NOT NULL DEFAULT '{}'
+        );",
+        join_scopes(Scope::DEFAULT)
+    ))?;
+    let tokens = tx.query_map(
+        "SELECT id, name, token, created_at, scopes FROM tokens",
+        params![],
+        |row| {
+            Ok((
+                row.get::<i64>(0)?,
+                row.get::<String>(1)?,
+                row.get::<String>(2)?,
+                row.get::<i64>(3)?,
+                row.get::<String>(4)?,
+            ))
+        },
+    )?;
+    for (id, name, token, created_at, scopes) in tokens {
+        tx.execute(
+            "INSERT INTO tokens_hashed (id, name, token_hash, created_at, scopes)
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![id, name, hash_token(&token), created_at, scopes],
+        )?;
+    }
+    tx.execute_batch(
+        "DROP TABLE tokens;
+        ALTER TABLE tokens_hashed RENAME TO tokens;",
+    )
+}
+
+/// Tokens are only stored as a SHA-256 hash so that reading the database
+/// isn't enough to impersonate anyone.
+///
+/// The tokens we hand out are long random strings, so there's no need for a
+/// (slow) password hash or a salt.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let names = conn.query_map(&format!("PRAGMA table_info({})", table), params![], |row| {
+        row.get::<String>(1)
+    })?;
+    Ok(names.iter().any(|name| name == column))
+}
+
+/// Scopes are stored as a comma separated list.
+pub fn join_scopes(scopes: &[Scope]) -> String {
+    scopes
+        .iter()
+        .map(Scope::as_str)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parse a stored scope list, skipping any we don't recognize.
+fn split_scopes(scopes: &str) -> Vec<Scope> {
+    scopes
+        .split(',')
+        .filter_map(|scope| scope.parse().ok())
+        .collect()
+}
+
+/// An API token, as issued to a person or CI pipeline.
+#[derive(Debug, PartialEq)]
+pub struct Token {
+    /// The token's id. Public keys are numbered separately, and go by their
+    /// PASERK id instead.
+    pub id: i64,
+    /// A label for who (or what) the token was issued to.
+    pub name: String,
+    /// What the token may be used for.
+    pub scopes: Vec<Scope>,
+    /// When the token was issued, as `YYYY-MM-DD HH:MM:SS` (UTC).
+    pub created_at: String,
+    /// The user the token was issued to, for tokens issued from the web UI.
+    pub user_id: Option<i64>,
+}
+
+/// A rule allowing CI jobs to publish a crate (see [`crate::auth::trusted`]).
+#[derive(Debug, PartialEq)]
+pub struct TrustedPublisher {
+    pub id: i64,
+    pub crate_name: String,
+    pub provider: Provider,
+    /// Who issues the CI's ID tokens.
+    pub issuer: String,
+    /// ex: `owner/repo` on GitHub, or `group/project` on GitLab.
+    pub repository: String,
+    /// Only allow this workflow (or pipeline config) file, ex: `release.yml`.
+    pub workflow: Option<String>,
+    /// Only allow jobs run for this branch.
+    pub branch: Option<String>,
+}
+
+/// Access to the crates matching a pattern, for publishing and yanking (see
+/// [`crate::auth::check_crate`]).
+#[derive(Debug, PartialEq)]
+pub struct Grant {
+    pub id: i64,
+    /// A crate name, or a glob like `team-a-*` (as understood by SQLite's
+    /// `GLOB`, ignoring case).
+    pub crate_pattern: String,
+    pub grantee: Grantee,
+}
+
+/// Who a [`Grant`] is for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Grantee {
+    /// The token (or public key) issued under this name.
+    Token(String),
+    /// Every token issued to the user with this login.
+    User(String),
+}
+
+/// Crate names set aside for a user or team, so nobody else can claim them
+/// first (see [`crate::auth::check_crate`]).
+#[derive(Debug, PartialEq)]
+pub struct Reservation {
+    pub id: i64,
+    /// A crate name, or a glob like `acme-*`, as for a [`Grant`].
+    pub crate_pattern: String,
+    pub holder: Holder,
+}
+
+/// Who a [`Reservation`] is for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Holder {
+    /// The user with this login.
+    User(String),
+    /// Every member of the team with this name.
+    Team(String),
+}
+
+impl Holder {
+    /// Read a login, or a team written as `@name` (like `cargo owner`).
+    pub fn parse(holder: &str) -> Self {
+        match holder.strip_prefix('@') {
+            Some(name) => Self::Team(name.to_string()),
+            None => Self::User(holder.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Holder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::User(login) => write!(f, "{}", login),
+            Self::Team(name) => write!(f, "@{}", name),
+        }
+    }
+}
+
+/// A group of users, who can own crates together.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Team {
+    pub id: i64,
+    /// Written as `@name` when adding the team as an owner.
+    pub name: String,
+}
+
+/// Someone who can log in to the web UI.
+#[derive(Clone, Debug, PartialEq)]
+pub struct User {
+    pub id: i64,
+    pub login: String,
+}
+
+/// Where a user's account comes from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Backend {
+    /// Added with `estuary user add` (or signed up), with a password kept
+    /// here.
+    Local,
+    /// Found in the directory server, which checks their password.
+    Ldap,
+    /// Logged in through the identity provider, and known by its subject
+    /// identifier.
+    Oidc,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::Ldap => "ldap",
+            Self::Oidc => "oidc",
+        }
+    }
+}
+
+/// The most the tokens people issue themselves after logging in through the
+/// identity provider may do, to begin with. Other users start out with
+/// `read` alone.
+pub const OIDC_USER_SCOPES: &[Scope] = &[Scope::Read, Scope::Publish, Scope::Yank];
+
+/// Store a token under `name`, replacing any token previously issued with
+/// that name.
+///
+/// Only a hash of the token is kept.
+pub fn set_token(conn: &Connection, name: &str, token: &str, scopes: &[Scope]) -> Result<()> {
+    conn.execute(
+        "INSERT INTO tokens (name, token_hash, scopes) VALUES (?1, ?2, ?3)
+        ON CONFLICT(name) DO UPDATE SET token_hash = excluded.token_hash, scopes = excluded.scopes",
+        params![name, hash_token(token), join_scopes(scopes)],
+    )?;
+    Ok(())
+}
+
+/// Store a new token under `name`, optionally on behalf of a user.
+///
+/// Unlike [`set_token`], this fails if `name` is already taken.
+pub fn create_token(
+    conn: &Connection,
+    name: &str,
+    token: &str,
+    scopes: &[Scope],
+    user_id: Option<i64>,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO tokens (name, token_hash, scopes, user_id) VALUES (?1, ?2, ?3, ?4)",
+        params![name, hash_token(token), join_scopes(scopes), user_id],
+    )?;
+    Ok(())
+}
+
+/// Whether an error came from trying to reuse a name (or other value) that
+/// must be unique.
+pub fn is_unique_violation(err: &Error) -> bool {
+    err.is_unique_violation()
+}
+
+/// A time stored as seconds since the epoch, as `YYYY-MM-DD HH:MM:SS` (UTC).
+fn datetime(timestamp: i64) -> String {
+    let format = time::macros::format_description!("[year]-[month]-[day] [hour]:[minute]:[second]");
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .ok()
+        .and_then(|time| time.format(&format).ok())
+        .unwrap_or_default()
+}
+
+/// Seconds since the epoch.
+fn now() -> i64 {
+    OffsetDateTime::now_utc().unix_timestamp()
+}
+
+/// Refuse to save a grant or reservation whose pattern would never match.
+fn check_pattern(pattern: &str) -> Result<()> {
+    glob::Pattern::new(&pattern.to_lowercase())
+        .map(|_| ())
+        .map_err(|e| Error::InvalidPattern(pattern.to_string(), e.msg.to_string()))
+}
+
+/// Whether `crate_name` matches a grant or reservation's pattern, the way
+/// SQLite's `GLOB` would match them both normalized, so `-` and `_` count the
+/// same, as they do to cargo.
+fn matches_pattern(pattern: &str, crate_name: &str) -> bool {
+    glob::Pattern::new(&normalize_name(pattern))
+        .map(|pattern| pattern.matches(&normalize_name(crate_name)))
+        .unwrap_or(false)
+}
+
+/// A version having been published, and who by.
+#[derive(Debug, PartialEq)]
+pub struct Publish {
+    pub crate_name: String,
+    pub version: String,
+    /// The user the publishing token was issued to, if any.
+    pub user_id: Option<i64>,
+    /// The token it was published with, if any (there's none for trusted
+    /// publishers, or before any tokens were issued).
+    pub token_name: Option<String>,
+    /// As `YYYY-MM-DD HH:MM:SS` (UTC).
+    pub published_at: String,
+}
+
+/// Record who published a version.
+pub fn record_publish(
+    conn: &Connection,
+    crate_name: &str,
+    version: &str,
+    user_id: Option<i64>,
+    token_name: Option<&str>,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO crate_versions (crate_name, version, user_id, token_name)
+        VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (crate_name, version)
+        DO UPDATE SET user_id = excluded.user_id, token_name = excluded.token_name",
+        params![crate_name, version, user_id, token_name],
+    )?;
+    Ok(())
+}
+
+/// Who published a version, and when, if the database knows.
+pub fn find_publish(conn: &Connection, crate_name: &str, version: &str) -> Result<Option<Publish>> {
+    conn.query_row(
+        "SELECT crate_name, version, user_id, token_name, created_at
+        FROM crate_versions WHERE crate_name = ?1 AND version = ?2",
+        params![crate_name, version],
+        |row| {
+            Ok(Publish {
+                crate_name: row.get(0)?,
+                version: row.get(1)?,
+                user_id: row.get(2)?,
+                token_name: row.get(3)?,
+                published_at: datetime(row.get(4)?),
+            })
+        },
+    )
+    .optional()
+}
+
+/// Record a version being yanked (or unyanked), as it was in the index.
+///
+/// Yanking a version that already is keeps when it first was.
+pub fn set_yanked(conn: &Connection, crate_name: &str, version: &str, yanked: bool) -> Result<()> {
+    if yanked {
+        conn.execute(
+            "UPDATE crate_versions SET yanked_at = ?3
+            WHERE crate_name = ?1 AND version = ?2 AND yanked_at IS NULL",
+            params![crate_name, version, now()],
+        )?;
+    } else {
+        conn.execute(
+            "UPDATE crate_versions SET yanked_at = NULL WHERE crate_name = ?1 AND version = ?2",
+            params![crate_name, version],
+        )?;
+    }
+    Ok(())
+}
+
+/// The backfill of versions yanked before the database kept track of them.
+pub const BACKFILL_YANKS: &str = "yanks";
+
+/// Whether the backfill `name` has yet to be done.
+pub fn has_backfill(conn: &Connection, name: &str) -> Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM backfills WHERE name = ?1",
+        params![name],
+        |row| row.get::<i64>(0),
+    )
+    .map(|count| count > 0)
+}
+
+/// Record the backfill `name` as done.
+pub fn finish_backfill(conn: &Connection, name: &str) -> Result<()> {
+    conn.execute("DELETE FROM backfills WHERE name = ?1", params![name])?;
+    Ok(())
+}
+
+/// Forget a version altogether: its publish (and metadata), crate file, and
+/// downloads.
+///
+/// Gives back the digest its crate file was stored under, when no other
+/// version's is stored under it too (so the file can go).
+pub fn delete_version(
+    conn: &Connection,
+    crate_name: &str,
+    version: &str,
+) -> Result<Option<String>> {
+    let tx = conn.transaction()?;
+    let digest = find_crate_file(&tx, crate_name, version)?;
+    for table in &[
+        "crate_versions",
+        "crate_keywords",
+        "crate_categories",
+        "crate_authors",
+        "crate_files",
+        "downloads",
+    ] {
+        tx.execute(
+            &format!(
+                "DELETE FROM {} WHERE crate_name = ?1 AND version = ?2",
+                table
+            ),
+            params![crate_name, version],
+        )?;
+    }
+    let digest = match digest {
+        Some(digest) => {
+            let shared: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM crate_files WHERE digest = ?1",
+                params![digest],
+                |row| row.get(0),
+            )?;
+            Some(digest).filter(|_| shared == 0)
+        }
+        None => None,
+    };
+    tx.commit()?;
+    Ok(digest)
+}
+
+/// A version that was deleted, kept so a different crate file can't be
+/// published as the same version later.
+#[derive(Debug, PartialEq)]
+pub struct Tombstone {
+    pub crate_name: String,
+    pub version: String,
+    /// The SHA-256 of the deleted crate file.
+    pub cksum: String,
+    /// Why it was deleted, if anyone said.
+    pub reason: Option<String>,
+    /// As `YYYY-MM-DD HH:MM:SS` (UTC).
+    pub deleted_at: String,
+}
+
+/// Record a version having been deleted.
+pub fn add_tombstone(
+    conn: &Connection,
+    crate_name: &str,
+    version: &str,
+    cksum: &str,
+    reason: Option<&str>,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO tombstones (crate_name, version, cksum, reason, deleted_at)
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT (crate_name, version)
+        DO UPDATE SET cksum = excluded.cksum, reason = excluded.reason,
+            deleted_at = excluded.deleted_at",
+        params![crate_name, version, cksum, reason, now()],
+    )?;
+    Ok(())
+}
+
+/// The tombstone of a deleted version, if it was deleted.
+///
+/// Versions that only differ in their build metadata (`1.2.3+build`) are the
+/// same version to cargo, so they share a tombstone.
+pub fn find_tombstone(
+    conn: &Connection,
+    crate_name: &str,
+    version: &str,
+) -> Result<Option<Tombstone>> {
+    let tombstones: Vec<Tombstone> = conn.query_map(
+        "SELECT crate_name, version, cksum, reason, deleted_at
+        FROM tombstones WHERE crate_name = ?1 ORDER BY deleted_at DESC",
+        params![crate_name],
+        |row| {
+            Ok(Tombstone {
+                crate_name: row.get(0)?,
+                version: row.get(1)?,
+                cksum: row.get(2)?,
+                reason: row.get(3)?,
+                deleted_at: datetime(row.get(4)?),
+            })
+        },
+    )?;
+    let version = without_build(version);
+    Ok(tombstones
+        .into_iter()
+        .find(|tombstone| without_build(&tombstone.version) == version))
+}
+
+/// `version`, less its build metadata.
+fn without_build(version: &str) -> &str {
+    version.split('+').next().unwrap_or(version)
+}
+
+/// The parts of a version's `Cargo.toml` that cargo sends along when
+/// publishing, which aren't in the index.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CrateMetadata {
+    pub description: Option<String>,
+    pub documentation: Option<String>,
+    pub homepage: Option<String>,
+    /// The readme's contents.
+    pub readme: Option<String>,
+    /// Where the readme is in the crate, ex: `README.md`.
+    pub readme_file: Option<String>,
+    /// An SPDX expression, ex: `MIT OR Apache-2.0`.
+    pub license: Option<String>,
+    pub license_file: Option<String>,
+    pub repository: Option<String>,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+    pub authors: Vec<String>,
+}
+
+/// Record a version's metadata, replacing whatever was recorded for it
+/// before. The version needs to have been recorded with [record_publish].
+pub fn record_metadata(
+    conn: &Connection,
+    crate_name: &str,
+    version: &str,
+    metadata: &CrateMetadata,
+) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute(
+        "UPDATE crate_versions SET description = ?3, documentation = ?4, homepage = ?5,
+            readme = ?6, readme_file = ?7, license = ?8, license_file = ?9, repository = ?10
+        WHERE crate_name = ?1 AND version = ?2",
+        params![
+            crate_name,
+            version,
+            metadata.description,
+            metadata.documentation,
+            metadata.homepage,
+            metadata.readme,
+            metadata.readme_file,
+            metadata.license,
+            metadata.license_file,
+            metadata.repository,
+        ],
+    )?;
+    for table in &["crate_keywords", "crate_categories", "crate_authors"] {
+        tx.execute(
+            &format!(
+                "DELETE FROM {} WHERE crate_name = ?1 AND version = ?2",
+                table
+            ),
+            params![crate_name, version],
+        )?;
+    }
+    for keyword in &metadata.keywords {
+        tx.execute(
+            "INSERT INTO crate_keywords (crate_name, version, keyword) VALUES (?1, ?2, ?3)
+            ON CONFLICT DO NOTHING",
+            params![crate_name, version, keyword],
+        )?;
+    }
+    for category in &metadata.categories {
+        tx.execute(
+            "INSERT INTO crate_categories (crate_name, version, category) VALUES (?1, ?2, ?3)
+            ON CONFLICT DO NOTHING",
+            params![crate_name, version, category],
+        )?;
+    }
+    for (position, author) in metadata.authors.iter().enumerate() {
+        tx.execute(
+            "INSERT INTO crate_authors (crate_name, version, position, author)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![crate_name, version, position, author],
+        )?;
+    }
+    tx.commit()
+}
+
+/// A version's metadata, or `None` when the version wasn't published here
+/// (or was published before metadata was kept, in which case it's all
+/// empty).
+pub fn find_metadata(
+    conn: &Connection,
+    crate_name: &str,
+    version: &str,
+) -> Result<Option<CrateMetadata>> {
+    let metadata = conn
+        .query_row(
+            "SELECT description, documentation, homepage, readme, readme_file, license,
+                license_file, repository
+            FROM crate_versions WHERE crate_name = ?1 AND version = ?2",
+            params![crate_name, version],
+            |row| {
+                Ok(CrateMetadata {
+                    description: row.get(0)?,
+                    documentation: row.get(1)?,
+                    homepage: row.get(2)?,
+                    readme: row.get(3)?,
+                    readme_file: row.get(4)?,
+                    license: row.get(5)?,
+                    license_file: row.get(6)?,
+                    repository: row.get(7)?,
+                    ..Default::default()
+                })
+            },
+        )
+        .optional()?;
+    let mut metadata = match metadata {
+        Some(metadata) => metadata,
+        None => return Ok(None),
+    };
+    let list = |sql: &str| conn.query_map(sql, params![crate_name, version], |row| row.get(0));
+    metadata.keywords = list(
+        "SELECT keyword FROM crate_keywords WHERE crate_name = ?1 AND version = ?2
+        ORDER BY keyword",
+    )?;
+    metadata.categories = list(
+        "SELECT category FROM crate_categories WHERE crate_name = ?1 AND version = ?2
+        ORDER BY category",
+    )?;
+    metadata.authors = list(
+        "SELECT author FROM crate_authors WHERE crate_name = ?1 AND version = ?2
+        ORDER BY position",
+    )?;
+    Ok(Some(metadata))
+}
+
+/// Bring `crate_name`'s search entry up to date with its newest version (the
+/// metadata of older ones isn't searched).
+pub fn update_search(conn: &Connection, crate_name: &str) -> Result<()> {
+    let tx = conn.transaction()?;
+    write_search_entry(&tx, crate_name)?;
+    tx.commit()
+}
+
+fn write_search_entry(conn: &Connection, crate_name: &str) -> Result<()> {
+    let versions: Vec<String> = conn.query_map(
+        "SELECT version FROM crate_versions WHERE crate_name = ?1",
+        params![crate_name],
+        |row| row.get(0),
+    )?;
+    let newest = versions
+        .iter()
+        .filter_map(|version| Some((semver::Version::parse(version).ok()?, version)))
+        .max()
+        .map(|(_, version)| version);
+    let metadata = match newest {
+        Some(version) => find_metadata(conn, crate_name, version)?.unwrap_or_default(),
+        None => CrateMetadata::default(),
+    };
+    conn.execute(
+        "DELETE FROM crate_search WHERE crate_name = ?1",
+        params![crate_name],
+    )?;
+    conn.execute(
+        "INSERT INTO crate_search (crate_name, keywords, description, readme)
+        VALUES (?1, ?2, ?3, ?4)",
+        params![
+            crate_name,
+            metadata.keywords.join(" "),
+            metadata.description.unwrap_or_default(),
+            metadata.readme.unwrap_or_default(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// The newest version of `crate_name` that isn't yanked, as far as the
+/// database knows, along with the name as it was published.
+pub fn find_latest_version(
+    conn: &Connection,
+    crate_name: &str,
+) -> Result<Option<(String, semver::Version)>> {
+    let versions: Vec<(String, String)> = conn.query_map(
+        "SELECT crate_name, version FROM crate_versions
+        WHERE crate_name = ?1 AND yanked_at IS NULL",
+        params![crate_name],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok(versions
+        .into_iter()
+        .filter_map(|(name, version)| Some((semver::Version::parse(&version).ok()?, name)))
+        .max()
+        .map(|(version, name)| (name, version)))
+}
+
+/// When `crate_name` was last published to, as far as the database knows.
+pub fn find_updated_at(conn: &Connection, crate_name: &str) -> Result<Option<String>> {
+    let at: Option<i64> = conn.query_row(
+        "SELECT MAX(created_at) FROM crate_versions WHERE crate_name = ?1",
+        params![crate_name],
+        |row| row.get(0),
+    )?;
+    Ok(at.map(datetime))
+}
+
+/// Give crates that aren't in the search yet (those published before it
+/// existed, or before the database knew of them) an entry, so they can at
+/// least be found by name.
+pub fn add_to_search(conn: &Connection, crate_names: &[String]) -> Result<()> {
+    let known: HashSet<String> = conn
+        .query_map("SELECT crate_name FROM crate_search", params![], |row| {
+            row.get::<String>(0)
+        })?
+        .into_iter()
+        .map(|name| name.to_lowercase())
+        .collect();
+    let tx = conn.transaction()?;
+    for crate_name in crate_names {
+        if !known.contains(&crate_name.to_lowercase()) {
+            write_search_entry(&tx, crate_name)?;
+        }
+    }
+    tx.commit()
+}
+
+/// How many days of downloads count as recent ones: towards a crate's
+/// standing in search, and as shown in the web UI.
+pub const RECENT_DOWNLOAD_DAYS: i64 = 90;
+
+/// The first day downloads count as recent on, as `YYYY-MM-DD`.
+fn recent_downloads_since() -> String {
+    (OffsetDateTime::now_utc() - time::Duration::days(RECENT_DOWNLOAD_DAYS))
+        .date()
+        .to_string()
+}
+
+/// How a crate's name matches a search, best first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum NameMatch {
+    /// Somewhere other than the name's words: inside one, or in the
+    /// keywords, description, or readme.
+    Elsewhere,
+    /// One of the name's words (split on `-` and `_`) starts with one of the
+    /// query's.
+    Word,
+    /// The name starts with the query's first word.
+    Prefix,
+    /// The name is the query, taking `-` and `_` to be the same, as cargo
+    /// does.
+    Exact,
+}
+
+fn name_match(crate_name: &str, query: &str, words: &[String]) -> NameMatch {
+    let name = crate_name.to_lowercase();
+    if normalize_name(&name) == normalize_name(query) {
+        NameMatch::Exact
+    } else if name.starts_with(&words[0]) {
+        NameMatch::Prefix
+    } else if name
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|part| words.iter().any(|word| part.starts_with(word.as_str())))
+    {
+        NameMatch::Word
+    } else {
+        NameMatch::Elsewhere
+    }
+}
+
+/// The crates matching the words in `query`, best match first.
+///
+/// Each word matches words starting with it anywhere in a crate's name,
+/// keywords, description, or readme (or anywhere at all in its name). They're
+/// ranked by how their names match first: the crate named in the query, then
+/// those whose names start with it, then those with one of its words in their
+/// names, then the rest. Within each of those, how well the text matches
+/// (counting for more in the name than the keywords, then the description,
+/// then the readme) is weighed along with recent downloads and how recently
+/// the crate was published.
+pub fn search_crates(conn: &Connection, query: &str) -> Result<Vec<String>> {
+    // Only letters and numbers are kept, so the query can't be taken for
+    // search syntax.
+    let words: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    if words.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut matches = match_text(conn, &words)?;
+    let mut seen: HashSet<String> = matches.iter().map(|name| name.to_lowercase()).collect();
+    for word in &words {
+        let inside: Vec<String> = conn.query_map(
+            "SELECT crate_name FROM crate_search WHERE crate_name LIKE ?1 ORDER BY crate_name",
+            params![format!("%{}%", word)],
+            |row| row.get(0),
+        )?;
+        for name in inside {
+            if seen.insert(name.to_lowercase()) {
+                matches.push(name);
+            }
+        }
+    }
+
+    let since = recent_downloads_since();
+    let downloads: HashMap<String, u64> = conn
+        .query_map(
+            "SELECT crate_name, CAST(SUM(count) AS BIGINT) FROM downloads
+            WHERE date >= ?1 GROUP BY crate_name",
+            params![since],
+            |row| Ok((row.get::<String>(0)?.to_lowercase(), row.get(1)?)),
+        )?
+        .into_iter()
+        .collect();
+    let published: HashMap<String, i64> = conn
+        .query_map(
+            "SELECT crate_name, MAX(created_at) FROM crate_versions GROUP BY crate_name",
+            params![],
+            |row| Ok((row.get::<String>(0)?.to_lowercase(), row.get(1)?)),
+        )?
+        .into_iter()
+        .collect();
+    let most_downloads = downloads.values().copied().max().unwrap_or(0);
+    let now = now();
+
+    let count = matches.len();
+    let mut ranked: Vec<(NameMatch, f64, String)> = matches
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| {
+            let key = name.to_lowercase();
+            // Each part is between 0 and 1.
+            let text = 1.0 - i as f64 / count as f64;
+            let popularity = match downloads.get(&key) {
+                Some(&n) if most_downloads > 0 => {
+                    (1.0 + n as f64).ln() / (1.0 + most_downloads as f64).ln()
+                }
+                _ => 0.0,
+            };
+            let recency = match published.get(&key) {
+                Some(&at) => 1.0 / (1.0 + (now - at).max(0) as f64 / (365.0 * 24.0 * 60.0 * 60.0)),
+                None => 0.0,
+            };
+            let score = 0.5 * text + 0.3 * popularity + 0.2 * recency;
+            (name_match(&name, query, &words), score, name)
+        })
+        .collect();
+    ranked.sort_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then(b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal))
+            .then_with(|| a.2.cmp(&b.2))
+    });
+    Ok(ranked.into_iter().map(|(_, _, name)| name).collect())
+}
+
+/// How search results can be ordered.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SearchSort {
+    /// Best match first, as [search_crates] has them.
+    #[default]
+    Relevance,
+    /// Most recently first published first.
+    Newest,
+    /// Most recently published (any version) first.
+    RecentlyUpdated,
+    /// Most downloaded, all time, first.
+    Downloads,
+    /// By name.
+    Alphabetical,
+}
+
+impl SearchSort {
+    pub const ALL: &'static [SearchSort] = &[
+        SearchSort::Relevance,
+        SearchSort::Newest,
+        SearchSort::RecentlyUpdated,
+        SearchSort::Downloads,
+        SearchSort::Alphabetical,
+    ];
+
+    /// The name used for it in query strings.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SearchSort::Relevance => "relevance",
+            SearchSort::Newest => "newest",
+            SearchSort::RecentlyUpdated => "recently-updated",
+            SearchSort::Downloads => "downloads",
+            SearchSort::Alphabetical => "alphabetical",
+        }
+    }
+}
+
+/// Put the crates found by a search in the order asked for, keeping them in
+/// the order they're in where they're level (and for [SearchSort::Relevance]).
+///
+/// Crates published before the database kept track of them count as the
+/// oldest and least downloaded.
+pub fn sort_crates(conn: &Connection, names: &mut [String], sort: SearchSort) -> Result<()> {
+    let sql = match sort {
+        SearchSort::Relevance => return Ok(()),
+        SearchSort::Alphabetical => {
+            names.sort_by_key(|name| name.to_lowercase());
+            return Ok(());
+        }
+        SearchSort::Newest => {
+            "SELECT crate_name, MIN(created_at) FROM crate_versions GROUP BY crate_name"
+        }
+        SearchSort::RecentlyUpdated => {
+            "SELECT crate_name, MAX(created_at) FROM crate_versions GROUP BY crate_name"
+        }
+        SearchSort::Downloads => {
+            "SELECT crate_name, CAST(SUM(count) AS BIGINT) FROM downloads GROUP BY crate_name"
+        }
+    };
+    let keys: HashMap<String, i64> = conn
+        .query_map(sql, params![], |row| {
+            Ok((row.get::<String>(0)?.to_lowercase(), row.get(1)?))
+        })?
+        .into_iter()
+        .collect();
+    names.sort_by_key(|name| Reverse(keys.get(&name.to_lowercase()).copied()));
+    Ok(())
+}
+
+/// A page of the crates in the search, in the order asked for, along with how
+/// many there are in all. With a `prefix`, only those whose names start with
+/// it (ignoring case) are counted.
+///
+/// Sorts the same as [sort_crates], taking [SearchSort::Relevance] to be by
+/// name.
+pub fn list_crates_page(
+    conn: &Connection,
+    prefix: Option<&str>,
+    sort: SearchSort,
+    offset: usize,
+    limit: usize,
+) -> Result<(Vec<String>, usize)> {
+    let key = match sort {
+        SearchSort::Relevance | SearchSort::Alphabetical => None,
+        SearchSort::Newest => Some(("crate_versions", "MIN(created_at)")),
+        SearchSort::RecentlyUpdated => Some(("crate_versions", "MAX(created_at)")),
+        SearchSort::Downloads => Some(("downloads", "CAST(SUM(count) AS BIGINT)")),
+    };
+    let prefix = prefix.map(str::to_lowercase);
+    let filter = match prefix {
+        Some(ref prefix) => format!(
+            "WHERE SUBSTR(LOWER(s.crate_name), 1, {}) = ?1",
+            prefix.chars().count()
+        ),
+        None => String::new(),
+    };
+    let mut args: Vec<&dyn ToSql> = prefix.iter().map(|p| p as &dyn ToSql).collect();
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM crate_search AS s {}", filter),
+        &args,
+        |row| row.get(0),
+    )?;
+
+    let (join, order) = match key {
+        Some((table, aggregate)) => (
+            format!(
+                "LEFT JOIN (
+                    SELECT LOWER(crate_name) AS name, {} AS sort_key FROM {}
+                    GROUP BY LOWER(crate_name)
+                ) AS k ON k.name = LOWER(s.crate_name)",
+                aggregate, table
+            ),
+            "k.sort_key DESC NULLS LAST, ",
+        ),
+        None => (String::new(), ""),
+    };
+    let offset = offset.min(i64::MAX as usize) as i64;
+    let limit = limit.min(i64::MAX as usize) as i64;
+    args.push(&limit);
+    args.push(&offset);
+    let names = conn.query_map(
+        &format!(
+            "SELECT s.crate_name FROM crate_search AS s {} {}
+            ORDER BY {}LOWER(s.crate_name), s.crate_name LIMIT ?{} OFFSET ?{}",
+            join,
+            filter,
+            order,
+            args.len() - 1,
+            args.len()
+        ),
+        &args,
+        |row| row.get(0),
+    )?;
+    Ok((names, total as usize))
+}
+
+/// The letters the names of the crates in the search start with, lowercased.
+pub fn list_crate_letters(conn: &Connection) -> Result<Vec<String>> {
+    conn.query_map(
+        "SELECT DISTINCT LOWER(SUBSTR(crate_name, 1, 1)) FROM crate_search ORDER BY 1",
+        params![],
+        |row| row.get(0),
+    )
+}
+
+/// Take `crate_name` out of the search, for when its last version is deleted.
+pub fn remove_from_search(conn: &Connection, crate_name: &str) -> Result<()> {
+    conn.execute(
+        "DELETE FROM crate_search WHERE crate_name = ?1",
+        params![crate_name],
+    )?;
+    Ok(())
+}
+
+/// The crates with a version tagged with `keyword` and in `category`
+/// (whichever of the two are given), by name.
+pub fn list_tagged_crates(
+    conn: &Connection,
+    keyword: Option<&str>,
+    category: Option<&str>,
+) -> Result<Vec<String>> {
+    let mut names: Option<Vec<String>> = None;
+    for (table, column, tag) in &[
+        ("crate_keywords", "keyword", keyword),
+        ("crate_categories", "category", category),
+    ] {
+        let tag = match tag {
+            Some(tag) => tag,
+            None => continue,
+        };
+        let tagged: Vec<String> = conn.query_map(
+            &format!(
+                "SELECT DISTINCT crate_name FROM {} WHERE {} = ?1 ORDER BY crate_name",
+                table, column
+            ),
+            params![tag],
+            |row| row.get(0),
+        )?;
+        names = Some(match names {
+            None => tagged,
+            Some(names) => {
+                let tagged: HashSet<String> =
+                    tagged.iter().map(|name| name.to_lowercase()).collect();
+                names
+                    .into_iter()
+                    .filter(|name| tagged.contains(&name.to_lowercase()))
+                    .collect()
+            }
+        });
+    }
+    Ok(names.unwrap_or_default())
+}
+
+/// The crates whose text matches any of `words`, by full-text search, best
+/// match first.
+fn match_text(conn: &Connection, words: &[String]) -> Result<Vec<String>> {
+    if conn.is_postgres() {
+        let query = words
+            .iter()
+            .map(|word| format!("{}:*", word))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        conn.query_map(
+            "SELECT crate_name FROM (
+                SELECT crate_name,
+                    setweight(to_tsvector('simple', crate_name::TEXT), 'A')
+                    || setweight(to_tsvector('simple', keywords), 'B')
+                    || setweight(to_tsvector('simple', description), 'C')
+                    || setweight(to_tsvector('simple', readme), 'D') AS document
+                FROM crate_search
+            ) AS documents, to_tsquery('simple', ?1) AS query
+            WHERE document @@ query
+            ORDER BY ts_rank(document, query) DESC, crate_name",
+            params![query],
+            |row| row.get(0),
+        )
+    } else {
+        let query = words
+            .iter()
+            .map(|word| format!("\"{}\"*", word))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        conn.query_map(
+            "SELECT crate_name FROM crate_search WHERE crate_search MATCH ?1
+            ORDER BY bm25(crate_search, 10.0, 5.0, 2.0, 1.0), crate_name",
+            params![query],
+            |row| row.get(0),
+        )
+    }
+}
+
+/// How many times a version was downloaded on a day.
+#[derive(Debug, PartialEq)]
+pub struct Downloads {
+    pub crate_name: String,
+    pub version: String,
+    /// As `YYYY-MM-DD` (UTC).
+    pub date: String,
+    pub count: u64,
+}
+
+/// Add to the download counts, all at once.
+pub fn record_downloads(conn: &Connection, downloads: &[Downloads]) -> Result<()> {
+    let tx = conn.transaction()?;
+    for downloads in downloads {
+        tx.execute(
+            "INSERT INTO downloads (crate_name, version, date, count) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (crate_name, version, date)
+            DO UPDATE SET count = downloads.count + excluded.count",
+            params![
+                downloads.crate_name,
+                downloads.version,
+                downloads.date,
+                downloads.count
+            ],
+        )?;
+    }
+    tx.commit()
+}
+
+/// How many times a crate (or just the one version of it) has been
+/// downloaded, all told.
+pub fn count_downloads(conn: &Connection, crate_name: &str, version: Option<&str>) -> Result<u64> {
+    conn.query_row(
+        "SELECT CAST(COALESCE(SUM(count), 0) AS BIGINT) FROM downloads
+        WHERE crate_name = ?1 AND (version = ?2 OR ?2 IS NULL)",
+        params![crate_name, version],
+        |row| row.get(0),
+    )
+}
+
+/// How many times any version of `crate_name` was downloaded in the last
+/// [RECENT_DOWNLOAD_DAYS].
+pub fn count_recent_downloads(conn: &Connection, crate_name: &str) -> Result<u64> {
+    conn.query_row(
+        "SELECT CAST(COALESCE(SUM(count), 0) AS BIGINT) FROM downloads
+        WHERE crate_name = ?1 AND date >= ?2",
+        params![crate_name, recent_downloads_since()],
+        |row| row.get(0),
+    )
+}
+
+/// The `limit` most downloaded crates of all time, with their downloads.
+pub fn list_most_downloaded(conn: &Connection, limit: usize) -> Result<Vec<(String, u64)>> {
+    conn.query_map(
+        "SELECT crate_name, CAST(SUM(count) AS BIGINT) AS total FROM downloads
+        GROUP BY crate_name ORDER BY total DESC, crate_name LIMIT ?1",
+        params![limit as i64],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+}
+
+/// Each day `crate_name` was downloaded on (as `YYYY-MM-DD`), oldest first,
+/// with how many times it was.
+pub fn list_daily_downloads(conn: &Connection, crate_name: &str) -> Result<Vec<(String, u64)>> {
+    conn.query_map(
+        "SELECT date, CAST(SUM(count) AS BIGINT) FROM downloads WHERE crate_name = ?1
+        GROUP BY date ORDER BY date",
+        params![crate_name],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+}
+
+/// What the stats page shows about a version.
+#[derive(Debug, PartialEq)]
+pub struct VersionStats {
+    pub version: String,
+    pub published_at: String,
+    /// The size of its crate file, in bytes, unless it was stored before
+    /// sizes were kept.
+    pub size: Option<u64>,
+    pub downloads: u64,
+}
+
+/// Each version of `crate_name` the database has a record of, oldest first.
+pub fn list_version_stats(conn: &Connection, crate_name: &str) -> Result<Vec<VersionStats>> {
+    conn.query_map(
+        "SELECT v.version, v.created_at, f.size,
+            (SELECT CAST(COALESCE(SUM(d.count), 0) AS BIGINT) FROM downloads d
+            WHERE d.crate_name = v.crate_name AND d.version = v.version)
+        FROM crate_versions v
+        LEFT JOIN crate_files f ON f.crate_name = v.crate_name AND f.version = v.version
+        WHERE v.crate_name = ?1 ORDER BY v.created_at, v.rowid",
+        params![crate_name],
+        |row| {
+            Ok(VersionStats {
+                version: row.get(0)?,
+                published_at: datetime(row.get(1)?),
+                size: row.get::<Option<u64>>(2)?.filter(|&size| size > 0),
+                downloads: row.get(3)?,
+            })
+        },
+    )
+}
+
+/// Record that a version's crate file is the one stored under `digest` (its
+/// SHA-256, as in the index's `cksum`), and is `size` bytes.
+pub fn record_crate_file(
+    conn: &Connection,
+    crate_name: &str,
+    version: &str,
+    digest: &str,
+    size: u64,
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO crate_files (crate_name, version, digest, size) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT (crate_name, version)
+        DO UPDATE SET digest = excluded.digest, size = excluded.size",
+        params![crate_name, version, digest, size],
+    )?;
+    Ok(())
+}
+
+/// The digest a version's crate file is stored under, or `None` for files
+/// stored before they were kept by digest.
+pub fn find_crate_file(
+    conn: &Connection,
+    crate_name: &str,
+    version: &str,
+) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT digest FROM crate_files WHERE crate_name = ?1 AND version = ?2",
+        params![crate_name, version],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// How much storage a crate's files take up.
+#[derive(Debug, PartialEq)]
+pub struct CrateUsage {
+    pub crate_name: String,
+    pub versions: u64,
+    pub bytes: u64,
+}
+
+/// The total size of a crate's files, in bytes.
+pub fn crate_usage(conn: &Connection, crate_name: &str) -> Result<u64> {
+    conn.query_row(
+        "SELECT CAST(COALESCE(SUM(size), 0) AS BIGINT) FROM crate_files WHERE crate_name = ?1",
+        params![crate_name],
+        |row| row.get(0),
+    )
+}
+
+/// The total size of every stored crate file, in bytes.
+///
+/// Files stored under the same digest are only counted once, since they're
+/// only stored once.
+pub fn registry_usage(conn: &Connection) -> Result<u64> {
+    conn.query_row(
+        "SELECT CAST(COALESCE(SUM(size), 0) AS BIGINT)
+        FROM (SELECT MAX(size) AS size FROM crate_files GROUP BY digest) AS files",
+        params![],
+        |row| row.get(0),
+    )
+}
+
+/// How much each crate takes up, biggest first.
+pub fn list_crate_usage(conn: &Connection) -> Result<Vec<CrateUsage>> {
+    conn.query_map(
+        "SELECT crate_name, COUNT(*), CAST(SUM(size) AS BIGINT) AS bytes FROM crate_files
+        GROUP BY crate_name ORDER BY bytes DESC, crate_name",
+        params![],
+        |row| {
+            Ok(CrateUsage {
+                crate_name: row.get(0)?,
+                versions: row.get(1)?,
+                bytes: row.get(2)?,
+            })
+        },
+    )
+}
+
+/// How many versions have been published in the last `days` days.
+pub fn count_recent_publishes(conn: &Connection, days: i64) -> Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM crate_versions WHERE created_at >= ?1",
+        params![now() - days * 24 * 60 * 60],
+        |row| row.get(0),
+    )
+}
+
+/// How much room the database itself takes up, in bytes.
+pub fn database_size(conn: &Connection) -> Result<u64> {
+    let sql = if conn.is_postgres() {
+        "SELECT pg_database_size(current_database())"
+    } else {
+        "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()"
+    };
+    conn.query_row(sql, params![], |row| row.get(0))
+}
+
+/// Every digest crate files are stored under.
+pub fn list_crate_file_digests(conn: &Connection) -> Result<Vec<String>> {
+    conn.query_map(
+        "SELECT DISTINCT digest FROM crate_files ORDER BY digest",
+        params![],
+        |row| row.get(0),
+    )
+}
+
+/// A version the database has a record of publishing.
+#[derive(Debug, PartialEq)]
+pub struct RecordedVersion {
+    pub crate_name: String,
+    pub version: String,
+    /// What its crate file is stored under, if that was recorded.
+    pub digest: Option<String>,
+    /// When it was yanked (as `YYYY-MM-DD HH:MM:SS`, UTC), if it is.
+    pub yanked_at: Option<String>,
+}
+
+/// Every version the database has a record of publishing, by crate and
+/// version.
+pub fn list_versions(conn: &Connection) -> Result<Vec<RecordedVersion>> {
+    conn.query_map(
+        "SELECT v.crate_name, v.version, f.digest, v.yanked_at FROM crate_versions v
+        LEFT JOIN crate_files f ON f.crate_name = v.crate_name AND f.version = v.version
+        ORDER BY v.crate_name, v.version",
+        params![],
+        |row| {
+            Ok(RecordedVersion {
+                crate_name: row.get(0)?,
+                version: row.get(1)?,
+                digest: row.get(2)?,
+                yanked_at: row.get::<Option<i64>>(3)?.map(datetime),
+            })
+        },
+    )
+}
+
+/// Everything published with tokens issued to `user_id`, newest first.
+pub fn list_user_publishes(conn: &Connection, user_id: i64) -> Result<Vec<Publish>> {
+    conn.query_map(
+        "SELECT crate_name, version, user_id, token_name, created_at
+        FROM crate_versions WHERE user_id = ?1 ORDER BY created_at DESC, rowid DESC",
+        params![user_id],
+        |row| {
+            Ok(Publish {
+                crate_name: row.get(0)?,
+                version: row.get(1)?,
+                user_id: row.get(2)?,
+                token_name: row.get(3)?,
+                published_at: datetime(row.get(4)?),
+            })
+        },
+    )
+}
+
+/// The `limit` most recent publishes to the registry, newest first, each with
+/// the login of the user who published it (if it was a user).
+pub fn list_recent_publishes(
+    conn: &Connection,
+    limit: usize,
+) -> Result<Vec<(Publish, Option<String>)>> {
+    conn.query_map(
+        "SELECT crate_name, version, user_id, token_name, crate_versions.created_at, login
+        FROM crate_versions LEFT JOIN users ON users.id = crate_versions.user_id
+        ORDER BY crate_versions.created_at DESC, crate_versions.rowid DESC LIMIT ?1",
+        params![limit as i64],
+        |row| {
+            Ok((
+                Publish {
+                    crate_name: row.get(0)?,
+                    version: row.get(1)?,
+                    user_id: row.get(2)?,
+                    token_name: row.get(3)?,
+                    published_at: datetime(row.get(4)?),
+                },
+                row.get(5)?,
+            ))
+        },
+    )
+}
+
+/// The crates `user_id` owns, by name, directly or through their teams.
+pub fn list_owned_crates(conn: &Connection, user_id: i64) -> Result<Vec<String>> {
+    conn.query_map(
+        "SELECT crate_name FROM crate_owners WHERE user_id = ?1
+        UNION
+        SELECT crate_name FROM crate_team_owners
+        JOIN team_members USING (team_id) WHERE team_members.user_id = ?1
+        ORDER BY crate_name",
+        params![user_id],
+        |row| row.get(0),
+    )
+}
+
+/// A token having been revoked, kept for later review.
+#[derive(Debug, PartialEq)]
+pub struct Revocation {
+    pub token_name: String,
+    /// The token used to revoke it, or `shell` for `estuary token revoke`.
+    pub revoked_by: String,
+    pub reason: Option<String>,
+    /// As `YYYY-MM-DD HH:MM:SS` (UTC).
+    pub revoked_at: String,
+}
+
+/// Delete the token issued under `name`, and record who revoked it.
+///
+/// Public keys are registered under names of their own, so one sharing the
+/// name is left alone (see [`remove_public_key`]). Grants to the name go once
+/// neither is left.
+///
+/// Gives `false` if there was no such token.
+pub fn revoke_token(
+    conn: &Connection,
+    name: &str,
+    revoked_by: &str,
+    reason: Option<&str>,
+) -> Result<bool> {
+    let tx = conn.transaction()?;
+    let revoked = tx.execute("DELETE FROM tokens WHERE name = ?1", params![name])? > 0;
+    if revoked {
+        record_revocation(&tx, name, revoked_by, reason)?;
+    }
+    tx.commit()?;
+    Ok(revoked)
+}
+
+/// Delete the public key with PASERK id `key_id`, and record who revoked it.
+///
+/// Gives the name the key was registered under, or `None` if there was no
+/// such key.
+pub fn remove_public_key(
+    conn: &Connection,
+    key_id: &str,
+    revoked_by: &str,
+    reason: Option<&str>,
+) -> Result<Option<String>> {
+    let tx = conn.transaction()?;
+    let name: Option<String> = tx
+        .query_row(
+            "SELECT name FROM public_keys WHERE key_id = ?1",
+            params![key_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(name) = &name {
+        tx.execute("DELETE FROM public_keys WHERE key_id = ?1", params![key_id])?;
+        record_revocation(&tx, name, revoked_by, reason)?;
+    }
+    tx.commit()?;
+    Ok(name)
+}
+
+fn record_revocation(
+    tx: &Connection,
+    name: &str,
+    revoked_by: &str,
+    reason: Option<&str>,
+) -> Result<()> {
+    tx.execute(
+        "INSERT INTO revocations (token_name, revoked_by, reason) VALUES (?1, ?2, ?3)",
+        params![name, revoked_by, reason],
+    )?;
+    tx.execute(
+        "DELETE FROM crate_grants WHERE token_name = ?1
+            AND NOT EXISTS (SELECT 1 FROM tokens WHERE name = ?1)
+            AND NOT EXISTS (SELECT 1 FROM public_keys WHERE name = ?1)",
+        params![name],
+    )?;
+    Ok(())
+}
+
+/// The name of the token with `id`.
+pub fn find_token_name(conn: &Connection, id: i64) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT name FROM tokens WHERE id = ?1",
+        params![id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Every revocation, oldest first.
+pub fn list_revocations(conn: &Connection) -> Result<Vec<Revocation>> {
+    conn.query_map(
+        "SELECT token_name, revoked_by, reason, revoked_at
+        FROM revocations ORDER BY id",
+        params![],
+        |row| {
+            Ok(Revocation {
+                token_name: row.get(0)?,
+                revoked_by: row.get(1)?,
+                reason: row.get(2)?,
+                revoked_at: datetime(row.get(3)?),
+            })
+        },
+    )
+}
+
+/// Register a public key that `name` will sign requests with, in place of
+/// sending a secret token.
+///
+/// `key_id` is the key's PASERK id, which is what requests refer to it by.
+pub fn add_public_key(
+    conn: &Connection,
+    name: &str,
+    public_key: &str,
+    key_id: &str,
+    scopes: &[Scope],
+) -> Result<()> {
+    conn.execute(
+        "INSERT INTO public_keys (name, public_key, key_id, scopes) VALUES (?1, ?2, ?3, ?4)",
+        params![name, public_key, key_id, join_scopes(scopes)],
+    )?;
+    Ok(())
+}
+
+/// Look up a public key by its PASERK id.
+pub fn find_public_key(conn: &Connection, key_id: &str) -> Result<Option<(Token, String)>> {
+    conn.query_row(
+        "SELECT name, scopes, created_at, public_key, id
+        FROM public_keys WHERE key_id = ?1",
+        params![key_id],
+        |row| {
+            let token = Token {
+                id: row.get(4)?,
+                name: row.get(0)?,
+                scopes: split_scopes(&row.get::<String>(1)?),
+                created_at: datetime(row.get(2)?),
+                user_id: None,
+            };
+            Ok((token, row.get(3)?))
+        },
+    )
+    .optional()
+}
+
+/// Every registered public key, along with its PASERK id, oldest first.
+pub fn list_public_keys(conn: &Connection) -> Result<Vec<(Token, String)>> {
+    conn.query_map(
+        "SELECT name, scopes, created_at, key_id, id
+        FROM public_keys ORDER BY id",
+        params![],
+        |row| {
+            let token = Token {
+                id: row.get(4)?,
+                name: row.get(0)?,
+                scopes: split_scopes(&row.get::<String>(1)?),
+                created_at: datetime(row.get(2)?),
+                user_id: None,
+            };
+            Ok((token, row.get(3)?))
+        },
+    )
+}
+
+/// Every issued token, oldest first.
+pub fn list_tokens(conn: &Connection) -> Result<Vec<Token>> {
+    Ok(list_token_hashes(conn)?
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect())
+}
+
+/// Every issued token, along with the hash of its secret.
+///
+/// Comparing the hashes is left to the caller so it can be done in constant
+/// time.
+pub fn list_token_hashes(conn: &Connection) -> Result<Vec<(Token, String)>> {
+    conn.query_map(
+        "SELECT name, scopes, created_at, token_hash, user_id, id
+        FROM tokens ORDER BY id",
+        params![],
+        |row| {
+            let token = Token {
+                id: row.get(5)?,
+                name: row.get(0)?,
+                scopes: split_scopes(&row.get::<String>(1)?),
+                created_at: datetime(row.get(2)?),
+                user_id: row.get(4)?,
+            };
+            Ok((token, row.get(3)?))
+        },
+    )
+}
+
+/// Add a user, or update the password of an existing one.
+///
+/// `password_hash` should come from [`auth::hash_password`](crate::auth::hash_password).
+pub fn set_user_password(conn: &Connection, login: &str, password_hash: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO users (login, password_hash) VALUES (?1, ?2)
+        ON CONFLICT(login) DO UPDATE SET password_hash = excluded.password_hash",
+        params![login, password_hash],
+    )?;
+    Ok(())
+}
+
+/// Get the password hash for `login`, if there's such a user and they have a
+/// password.
+pub fn find_user_password_hash(conn: &Connection, login: &str) -> Result<Option<String>> {
+    Ok(conn
+        .query_row(
+            "SELECT password_hash FROM users WHERE login = ?1",
+            params![login],
+            |row| row.get::<Option<String>>(0),
+        )
+        .optional()?
+        .flatten())
+}
+
+/// Every user's login, oldest first.
+pub fn list_users(conn: &Connection) -> Result<Vec<String>> {
+    conn.query_map("SELECT login FROM users ORDER BY id", params![], |row| {
+        row.get(0)
+    })
+}
+
+/// Make the user with `login` an admin (or not).
+///
+/// Gives `false` if there was no such user.
+pub fn set_admin(conn: &Connection, login: &str, is_admin: bool) -> Result<bool> {
+    Ok(conn.execute(
+        "UPDATE users SET is_admin = ?2 WHERE login = ?1",
+        params![login, is_admin],
+    )? > 0)
+}
+
+/// Whether `user_id` is an admin, who may act on any crate.
+pub fn is_admin(conn: &Connection, user_id: i64) -> Result<bool> {
+    Ok(conn
+        .query_row(
+            "SELECT is_admin FROM users WHERE id = ?1",
+            params![user_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(false))
+}
+
+/// The logins of the admins, oldest first.
+pub fn list_admins(conn: &Connection) -> Result<Vec<String>> {
+    conn.query_map(
+        "SELECT login FROM users WHERE is_admin ORDER BY id",
+        params![],
+        |row| row.get(0),
+    )
+}
+
+/// Delete the user with `login`, along with their sessions, grants, crate
+/// ownerships and invitations, team memberships, and the tokens issued to
+/// them.
+///
+/// Gives `false` if there was no such user.
+pub fn remove_user(conn: &Connection, login: &str) -> Result<bool> {
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM sessions WHERE user_id IN (SELECT id FROM users WHERE login = ?1)",
+        params![login],
+    )?;
+    tx.execute(
+        "DELETE FROM tokens WHERE user_id IN (SELECT id FROM users WHERE login = ?1)",
+        params![login],
+    )?;
+    tx.execute(
+        "DELETE FROM crate_grants WHERE user_login = ?1",
+        params![login],
+    )?;
+    tx.execute(
+        "DELETE FROM reservations WHERE user_login = ?1",
+        params![login],
+    )?;
+    tx.execute(
+        "DELETE FROM crate_owners WHERE user_id IN (SELECT id FROM users WHERE login = ?1)",
+        params![login],
+    )?;
+    tx.execute(
+        "DELETE FROM team_members WHERE user_id IN (SELECT id FROM users WHERE login = ?1)",
+        params![login],
+    )?;
+    tx.execute(
+        "DELETE FROM owner_invitations
+        WHERE invitee_id IN (SELECT id FROM users WHERE login = ?1)",
+        params![login],
+    )?;
+    tx.execute(
+        "UPDATE owner_invitations SET inviter_id = NULL
+        WHERE inviter_id IN (SELECT id FROM users WHERE login = ?1)",
+        params![login],
+    )?;
+    // What they published stays on record, under the token's name.
+    tx.execute(
+        "UPDATE crate_versions SET user_id = NULL
+        WHERE user_id IN (SELECT id FROM users WHERE login = ?1)",
+        params![login],
+    )?;
+    let removed = tx.execute("DELETE FROM users WHERE login = ?1", params![login])? > 0;
+    tx.commit()?;
+    Ok(removed)
+}
+
+/// Find the user with `login`, adding them if they're new, wherever they
+/// come from.
+///
+/// For tests, which don't care where users come from (see
+/// [`find_or_create_password_user`] otherwise).
+#[cfg(test)]
+pub fn find_or_create_user(conn: &Connection, login: &str) -> Result<User> {
+    conn.execute(
+        "INSERT INTO users (login) VALUES (?1) ON CONFLICT(login) DO NOTHING",
+        params![login],
+    )?;
+    conn.query_row(
+        "SELECT id, login FROM users WHERE login = ?1",
+        params![login],
+        |row| {
+            Ok(User {
+                id: row.get(0)?,
+                login: row.get(1)?,
+            })
+        },
+    )
+}
+
+/// Find the user who logs in as `login` with a password checked by `backend`,
+/// adding them the first time they show up (for the directory server, where
+/// they're added to).
+///
+/// Gives `None` if `login` belongs to another kind of account, since the same
+/// login elsewhere needn't be the same person.
+pub fn find_or_create_password_user(
+    conn: &Connection,
+    backend: Backend,
+    login: &str,
+) -> Result<Option<User>> {
+    conn.execute(
+        "INSERT INTO users (login, backend) VALUES (?1, ?2) ON CONFLICT(login) DO NOTHING",
+        params![login, backend.as_str()],
+    )?;
+    conn.query_row(
+        "SELECT id, login FROM users WHERE login = ?1 AND backend = ?2",
+        params![login, backend.as_str()],
+        |row| {
+            Ok(User {
+                id: row.get(0)?,
+                login: row.get(1)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// The most the tokens `user_id` issues themselves may do.
+pub fn find_user_scopes(conn: &Connection, user_id: i64) -> Result<Vec<Scope>> {
+    Ok(conn
+        .query_row(
+            "SELECT scopes FROM users WHERE id = ?1",
+            params![user_id],
+            |row| row.get::<String>(0),
+        )
+        .optional()?
+        .map(|scopes| split_scopes(&scopes))
+        .unwrap_or_default())
+}
+
+/// Change the most `login`'s own tokens may do, giving `false` if there's no
+/// such user.
+pub fn set_user_scopes(conn: &Connection, login: &str, scopes: &[Scope]) -> Result<bool> {
+    Ok(conn.execute(
+        "UPDATE users SET scopes = ?2 WHERE login = ?1",
+        params![login, join_scopes(scopes)],
+    )? > 0)
+}
+
+/// Add a user who signed up with a password.
+///
+/// Unlike [`set_user_password`], this fails with a unique violation if `login`
+/// is already taken.
+pub fn create_user(conn: &Connection, login: &str, password_hash: &str) -> Result<User> {
+    Ok(User {
+        id: conn.query_row(
+            "INSERT INTO users (login, password_hash) VALUES (?1, ?2) RETURNING id",
+            params![login, password_hash],
+            |row| row.get(0),
+        )?,
+        login: login.to_string(),
+    })
+}
+
+/// Look up a user by their login.
+pub fn find_user(conn: &Connection, login: &str) -> Result<Option<User>> {
+    conn.query_row(
+        "SELECT id, login FROM users WHERE login = ?1",
+        params![login],
+        |row| {
+            Ok(User {
+                id: row.get(0)?,
+                login: row.get(1)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Look up a user by their id.
+pub fn find_user_by_id(conn: &Connection, user_id: i64) -> Result<Option<User>> {
+    conn.query_row(
+        "SELECT id, login FROM users WHERE id = ?1",
+        params![user_id],
+        |row| {
+            Ok(User {
+                id: row.get(0)?,
+                login: row.get(1)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Find the user an identity provider knows as `subject`, adding them (as
+/// `login`) the first time they show up.
+///
+/// Fails with a unique violation if `login` is already taken by someone else.
+pub fn find_or_create_oidc_user(conn: &Connection, subject: &str, login: &str) -> Result<User> {
+    conn.execute(
+        "INSERT INTO users (login, oidc_subject, backend, scopes) VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(oidc_subject) DO NOTHING",
+        params![
+            login,
+            subject,
+            Backend::Oidc.as_str(),
+            join_scopes(OIDC_USER_SCOPES)
+        ],
+    )?;
+    conn.query_row(
+        "SELECT id, login FROM users WHERE oidc_subject = ?1",
+        params![subject],
+        |row| {
+            Ok(User {
+                id: row.get(0)?,
+                login: row.get(1)?,
+            })
+        },
+    )
+}
+
+/// Remember a web UI session for `user_id`.
+///
+/// Like tokens, only a hash of the session id is stored.
+pub fn create_session(conn: &Connection, session: &str, user_id: i64) -> Result<()> {
+    conn.execute(
+        "INSERT INTO sessions (session_hash, user_id) VALUES (?1, ?2)",
+        params![hash_token(session), user_id],
+    )?;
+    Ok(())
+}
+
+/// Find who a session belongs to, so long as it's less than `max_age` seconds
+/// old.
+pub fn find_session_user(conn: &Connection, session: &str, max_age: i64) -> Result<Option<User>> {
+    conn.query_row(
+        "SELECT users.id, users.login FROM sessions JOIN users ON users.id = sessions.user_id
+        WHERE session_hash = ?1 AND sessions.created_at > ?2",
+        params![hash_token(session), now() - max_age],
+        |row| {
+            Ok(User {
+                id: row.get(0)?,
+                login: row.get(1)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Forget a session, ie: log out.
+pub fn delete_session(conn: &Connection, session: &str) -> Result<()> {
+    conn.execute(
+        "DELETE FROM sessions WHERE session_hash = ?1",
+        params![hash_token(session)],
+    )?;
+    Ok(())
+}
+
+/// Remember the `nonce` sent to the identity provider along with `state`,
+/// while we wait for the user to come back from logging in.
+pub fn create_login_state(conn: &Connection, state: &str, nonce: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO login_states (state, nonce) VALUES (?1, ?2)",
+        params![state, nonce],
+    )?;
+    Ok(())
+}
+
+/// Get (and forget) the nonce stored for `state`, if it was stored less than
+/// `max_age` seconds ago.
+///
+/// Expired states are cleaned up along the way.
+pub fn take_login_state(conn: &Connection, state: &str, max_age: i64) -> Result<Option<String>> {
+    conn.execute(
+        "DELETE FROM login_states WHERE created_at <= ?1",
+        params![now() - max_age],
+    )?;
+    conn.query_row(
+        "DELETE FROM login_states WHERE state = ?1 RETURNING nonce",
+        params![state],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Add a trusted publisher rule, giving its id.
+///
+/// The `id` of `rule` is ignored.
+pub fn add_trusted_publisher(conn: &Connection, rule: &TrustedPublisher) -> Result<i64> {
+    conn.query_row(
+        "INSERT INTO trusted_publishers (crate_name, provider, issuer, repository, workflow, branch)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6) RETURNING id",
+        params![
+            rule.crate_name,
+            rule.provider.as_str(),
+            rule.issuer,
+            rule.repository,
+            rule.workflow,
+            rule.branch
+        ],
+        |row| row.get(0),
+    )
+}
+
+/// Every trusted publisher rule (for `crate_name`, when given), oldest first.
+pub fn list_trusted_publishers(
+    conn: &Connection,
+    crate_name: Option<&str>,
+) -> Result<Vec<TrustedPublisher>> {
+    conn.query_map(
+        "SELECT id, crate_name, provider, issuer, repository, workflow, branch
+        FROM trusted_publishers WHERE crate_name = ?1 OR ?1 IS NULL ORDER BY id",
+        params![crate_name],
+        |row| {
+            let provider: String = row.get(2)?;
+            Ok(TrustedPublisher {
+                id: row.get(0)?,
+                crate_name: row.get(1)?,
+                provider: provider.parse().map_err(|e| Error::Conversion(2, e))?,
+                issuer: row.get(3)?,
+                repository: row.get(4)?,
+                workflow: row.get(5)?,
+                branch: row.get(6)?,
+            })
+        },
+    )
+}
+
+/// Delete the trusted publisher rule with `id`.
+///
+/// Gives `false` if there was no such rule.
+pub fn remove_trusted_publisher(conn: &Connection, id: i64) -> Result<bool> {
+    Ok(conn.execute("DELETE FROM trusted_publishers WHERE id = ?1", params![id])? > 0)
+}
+
+/// The owners of `crate_name`, in the order they were added.
+pub fn list_crate_owners(conn: &Connection, crate_name: &str) -> Result<Vec<User>> {
+    conn.query_map(
+        "SELECT users.id, users.login FROM crate_owners
+        JOIN users ON users.id = crate_owners.user_id
+        WHERE crate_owners.crate_name = ?1
+        ORDER BY crate_owners.created_at, users.id",
+        params![crate_name],
+        |row| {
+            Ok(User {
+                id: row.get(0)?,
+                login: row.get(1)?,
+            })
+        },
+    )
+}
+
+/// Make `user_id` an owner of `crate_name`.
+///
+/// Gives `false` if they already were.
+pub fn add_crate_owner(conn: &Connection, crate_name: &str, user_id: i64) -> Result<bool> {
+    Ok(conn.execute(
+        "INSERT INTO crate_owners (crate_name, user_id) VALUES (?1, ?2)
+        ON CONFLICT DO NOTHING",
+        params![crate_name, user_id],
+    )? > 0)
+}
+
+/// Stop `user_id` owning `crate_name`.
+///
+/// Gives `false` if they weren't an owner.
+pub fn remove_crate_owner(conn: &Connection, crate_name: &str, user_id: i64) -> Result<bool> {
+    Ok(conn.execute(
+        "DELETE FROM crate_owners WHERE crate_name = ?1 AND user_id = ?2",
+        params![crate_name, user_id],
+    )? > 0)
+}
+
+/// An invitation to become an owner of a crate, which does nothing until the
+/// invitee accepts it.
+#[derive(Debug, PartialEq)]
+pub struct Invitation {
+    pub crate_name: String,
+    pub invitee_id: i64,
+    /// The login of whoever sent the invitation, if it was sent by a user.
+    pub inviter: Option<String>,
+    /// When it was sent, as `YYYY-MM-DD HH:MM:SS` (UTC).
+    pub created_at: String,
+}
+
+/// Invite `invitee_id` to become an owner of `crate_name`.
+///
+/// Gives `false` if they'd already been invited.
+pub fn invite_crate_owner(
+    conn: &Connection,
+    crate_name: &str,
+    invitee_id: i64,
+    inviter_id: Option<i64>,
+) -> Result<bool> {
+    Ok(conn.execute(
+        "INSERT INTO owner_invitations (crate_name, invitee_id, inviter_id) VALUES (?1, ?2, ?3)
+        ON CONFLICT DO NOTHING",
+        params![crate_name, invitee_id, inviter_id],
+    )? > 0)
+}
+
+/// The invitations waiting for `user_id` to answer, oldest first.
+pub fn list_invitations(conn: &Connection, user_id: i64) -> Result<Vec<Invitation>> {
+    conn.query_map(
+        "SELECT i.crate_name, i.invitee_id, users.login, i.created_at
+        FROM owner_invitations AS i LEFT JOIN users ON users.id = i.inviter_id
+        WHERE i.invitee_id = ?1 ORDER BY i.created_at, i.crate_name",
+        params![user_id],
+        |row| {
+            Ok(Invitation {
+                crate_name: row.get(0)?,
+                invitee_id: row.get(1)?,
+                inviter: row.get(2)?,
+                created_at: datetime(row.get(3)?),
+            })
+        },
+    )
+}
+
+/// Accept (making them an owner) or decline `user_id`'s invitation to own
+/// `crate_name`.
+///
+/// Gives `false` if there was no such invitation.
+pub fn answer_invitation(
+    conn: &Connection,
+    crate_name: &str,
+    user_id: i64,
+    accepted: bool,
+) -> Result<bool> {
+    let tx = conn.transaction()?;
+    let found = withdraw_invitation(&tx, crate_name, user_id)?;
+    if found && accepted {
+        add_crate_owner(&tx, crate_name, user_id)?;
+    }
+    tx.commit()?;
+    Ok(found)
+}
+
+/// Take back `user_id`'s invitation to own `crate_name`, before they've
+/// answered it.
+///
+/// Gives `false` if there was no such invitation.
+pub fn withdraw_invitation(conn: &Connection, crate_name: &str, user_id: i64) -> Result<bool> {
+    Ok(conn.execute(
+        "DELETE FROM owner_invitations WHERE crate_name = ?1 AND invitee_id = ?2",
+        params![crate_name, user_id],
+    )? > 0)
+}
+
+/// Add a team, failing with a unique violation if `name` is taken.
+pub fn create_team(conn: &Connection, name: &str) -> Result<Team> {
+    Ok(Team {
+        id: conn.query_row(
+            "INSERT INTO teams (name) VALUES (?1) RETURNING id",
+            params![name],
+            |row| row.get(0),
+        )?,
+        name: name.to_string(),
+    })
+}
+
+/// Look up a team by name.
+pub fn find_team(conn: &Connection, name: &str) -> Result<Option<Team>> {
+    conn.query_row(
+        "SELECT id, name FROM teams WHERE name = ?1",
+        params![name],
+        |row| {
+            Ok(Team {
+                id: row.get(0)?,
+                name: row.get(1)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Every team, along with the logins of its members.
+pub fn list_teams(conn: &Connection) -> Result<Vec<(Team, Vec<String>)>> {
+    let rows = conn.query_map(
+        "SELECT teams.id, teams.name, users.login FROM teams
+        LEFT JOIN team_members ON team_members.team_id = teams.id
+        LEFT JOIN users ON users.id = team_members.user_id
+        ORDER BY teams.name, teams.id",
+        params![],
+        |row| {
+            Ok((
+                Team {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                },
+                row.get::<Option<String>>(2)?,
+            ))
+        },
+    )?;
+    let mut teams: Vec<(Team, Vec<String>)> = vec![];
+    for (team, member) in rows {
+        match teams.last_mut() {
+            Some((last, members)) if last.id == team.id => members.extend(member),
+            _ => teams.push((team, member.into_iter().collect())),
+        }
+    }
+    for (_, members) in &mut teams {
+        members.sort();
+    }
+    Ok(teams)
+}
+
+/// Delete the team called `name`, along with its memberships, reservations,
+/// and the crates it owns.
+///
+/// Gives `false` if there was no such team.
+pub fn remove_team(conn: &Connection, name: &str) -> Result<bool> {
+    let team = match find_team(conn, name)? {
+        Some(team) => team,
+        None => return Ok(false),
+    };
+    let tx = conn.transaction()?;
+    tx.execute(
+        "DELETE FROM team_members WHERE team_id = ?1",
+        params![team.id],
+    )?;
+    tx.execute(
+        "DELETE FROM crate_team_owners WHERE team_id = ?1",
+        params![team.id],
+    )?;
+    tx.execute(
+        "DELETE FROM reservations WHERE team_name = ?1",
+        params![team.name],
+    )?;
+    tx.execute("DELETE FROM teams WHERE id = ?1", params![team.id])?;
+    tx.commit()?;
+    Ok(true)
+}
+
+/// Add `user_id` to a team.
+///
+/// Gives `false` if they were already a member.
+pub fn add_team_member(conn: &Connection, team_id: i64, user_id: i64) -> Result<bool> {
+    Ok(conn.execute(
+        "INSERT INTO team_members (team_id, user_id) VALUES (?1, ?2) ON CONFLICT DO NOTHING",
+        params![team_id, user_id],
+    )? > 0)
+}
+
+/// Take `user_id` out of a team.
+///
+/// Gives `false` if they weren't a member.
+pub fn remove_team_member(conn: &Connection, team_id: i64, user_id: i64) -> Result<bool> {
+    Ok(conn.execute(
+        "DELETE FROM team_members WHERE team_id = ?1 AND user_id = ?2",
+        params![team_id, user_id],
+    )? > 0)
+}
+
+/// The teams owning `crate_name`, in the order they were added.
+pub fn list_crate_owner_teams(conn: &Connection, crate_name: &str) -> Result<Vec<Team>> {
+    conn.query_map(
+        "SELECT teams.id, teams.name FROM crate_team_owners
+        JOIN teams ON teams.id = crate_team_owners.team_id
+        WHERE crate_team_owners.crate_name = ?1
+        ORDER BY crate_team_owners.created_at, teams.id",
+        params![crate_name],
+        |row| {
+            Ok(Team {
+                id: row.get(0)?,
+                name: row.get(1)?,
+            })
+        },
+    )
+}
+
+/// Make a team an owner of `crate_name`.
+///
+/// Gives `false` if it already was.
+pub fn add_crate_owner_team(conn: &Connection, crate_name: &str, team_id: i64) -> Result<bool> {
+    Ok(conn.execute(
+        "INSERT INTO crate_team_owners (crate_name, team_id) VALUES (?1, ?2)
+        ON CONFLICT DO NOTHING",
+        params![crate_name, team_id],
+    )? > 0)
+}
+
+/// Stop a team owning `crate_name`.
+///
+/// Gives `false` if it wasn't an owner.
+pub fn remove_crate_owner_team(conn: &Connection, crate_name: &str, team_id: i64) -> Result<bool> {
+    Ok(conn.execute(
+        "DELETE FROM crate_team_owners WHERE crate_name = ?1 AND team_id = ?2",
+        params![crate_name, team_id],
+    )? > 0)
+}
+
+/// Grant access to the crates matching `crate_pattern`, giving the grant's id.
+pub fn add_grant(conn: &Connection, crate_pattern: &str, grantee: &Grantee) -> Result<i64> {
+    check_pattern(crate_pattern)?;
+    let (token_name, user_login) = match grantee {
+        Grantee::Token(name) => (Some(name), None),
+        Grantee::User(login) => (None, Some(login)),
+    };
+    conn.query_row(
+        "INSERT INTO crate_grants (crate_pattern, token_name, user_login) VALUES (?1, ?2, ?3)
+        RETURNING id",
+        params![crate_pattern, token_name, user_login],
+        |row| row.get(0),
+    )
+}
+
+/// Every grant, oldest first.
+pub fn list_grants(conn: &Connection) -> Result<Vec<Grant>> {
+    conn.query_map(
+        "SELECT id, crate_pattern, token_name, user_login FROM crate_grants ORDER BY id",
+        params![],
+        |row| {
+            let token_name: Option<String> = row.get(2)?;
+            Ok(Grant {
+                id: row.get(0)?,
+                crate_pattern: row.get(1)?,
+                grantee: match token_name {
+                    Some(name) => Grantee::Token(name),
+                    None => Grantee::User(row.get(3)?),
+                },
+            })
+        },
+    )
+}
+
+/// Delete the grant with `id`.
+///
+/// Gives `false` if there was no such grant.
+pub fn remove_grant(conn: &Connection, id: i64) -> Result<bool> {
+    Ok(conn.execute("DELETE FROM crate_grants WHERE id = ?1", params![id])? > 0)
+}
+
+/// How many grants have been made.
+///
+/// Until there's at least one, tokens can act on any crate.
+pub fn count_grants(conn: &Connection) -> Result<i64> {
+    conn.query_row("SELECT COUNT(*) FROM crate_grants", params![], |row| {
+        row.get(0)
+    })
+}
+
+/// Whether `token` (or the user it was issued to) has been granted access to
+/// `crate_name`.
+pub fn is_granted(conn: &Connection, token: &Token, crate_name: &str) -> Result<bool> {
+    let patterns: Vec<String> = conn.query_map(
+        "SELECT crate_pattern FROM crate_grants
+        WHERE token_name = ?1 OR user_login = (SELECT login FROM users WHERE id = ?2)",
+        params![token.name, token.user_id],
+        |row| row.get(0),
+    )?;
+    Ok(patterns
+        .iter()
+        .any(|pattern| matches_pattern(pattern, crate_name)))
+}
+
+/// Set aside the crates matching `crate_pattern` for `holder`, giving the new
+/// reservation's id.
+pub fn add_reservation(conn: &Connection, crate_pattern: &str, holder: &Holder) -> Result<i64> {
+    check_pattern(crate_pattern)?;
+    let (user_login, team_name) = match holder {
+        Holder::User(login) => (Some(login), None),
+        Holder::Team(name) => (None, Some(name)),
+    };
+    conn.query_row(
+        "INSERT INTO reservations (crate_pattern, user_login, team_name) VALUES (?1, ?2, ?3)
+        RETURNING id",
+        params![crate_pattern, user_login, team_name],
+        |row| row.get(0),
+    )
+}
+
+/// Every reservation, oldest first.
+pub fn list_reservations(conn: &Connection) -> Result<Vec<Reservation>> {
+    conn.query_map(
+        "SELECT id, crate_pattern, user_login, team_name FROM reservations ORDER BY id",
+        params![],
+        |row| {
+            let user_login: Option<String> = row.get(2)?;
+            Ok(Reservation {
+                id: row.get(0)?,
+                crate_pattern: row.get(1)?,
+                holder: match user_login {
+                    Some(login) => Holder::User(login),
+                    None => Holder::Team(row.get(3)?),
+                },
+            })
+        },
+    )
+}
+
+/// Delete the reservation with `id`.
+///
+/// Gives `false` if there was no such reservation.
+pub fn remove_reservation(conn: &Connection, id: i64) -> Result<bool> {
+    Ok(conn.execute("DELETE FROM reservations WHERE id = ?1", params![id])? > 0)
+}
+
+/// Whether `crate_name` has been reserved for someone other than `user_id`
+/// (or one of their teams).
+pub fn is_reserved(conn: &Connection, crate_name: &str, user_id: Option<i64>) -> Result<bool> {
+    // Each reservation's pattern, and whether it's held by `user_id`.
+    let reservations: Vec<(String, bool)> = conn.query_map(
+        "SELECT crate_pattern,
+            user_login = (SELECT login FROM users WHERE id = ?1)
+            OR team_name IN (
+                SELECT teams.name FROM teams
+                JOIN team_members ON team_members.team_id = teams.id
+                WHERE team_members.user_id = ?1
+            )
+        FROM reservations",
+        params![user_id],
+        |row| Ok((row.get(0)?, row.get::<Option<bool>>(1)?.unwrap_or(false))),
+    )?;
+    let matching = reservations
+        .iter()
+        .filter(|(pattern, _)| matches_pattern(pattern, crate_name))
+        .collect::<Vec<_>>();
+    Ok(!matching.is_empty() && !matching.iter().any(|(_, held)| *held))
+}
+
+/// Whether anyone (a user or a team) owns `crate_name` yet.
+pub fn has_owners(conn: &Connection, crate_name: &str) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM crate_owners WHERE crate_name = ?1)
+            OR EXISTS (SELECT 1 FROM crate_team_owners WHERE crate_name = ?1)",
+        params![crate_name],
+        |row| row.get(0),
+    )
+}
+
+/// Whether `user_id` owns `crate_name`, themselves or through one of their
+/// teams.
+pub fn is_crate_owner(conn: &Connection, crate_name: &str, user_id: i64) -> Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM crate_owners WHERE crate_name = ?1 AND user_id = ?2)
+            OR EXISTS (
+                SELECT 1 FROM crate_team_owners JOIN team_members USING (team_id)
+                WHERE crate_team_owners.crate_name = ?1 AND team_members.user_id = ?2
+            )",
+        params![crate_name, user_id],
+        |row| row.get(0),
+    )
+}
+
+/// How many tokens (and public keys, and trusted publishers) have been
+/// issued, and not revoked.
+pub fn count_tokens(conn: &Connection) -> Result<i64> {
+    conn.query_row(
+        "SELECT (SELECT COUNT(*) FROM tokens)
+            + (SELECT COUNT(*) FROM public_keys)
+            + (SELECT COUNT(*) FROM trusted_publishers)",
+        params![],
+        |row| row.get(0),
+    )
+}
+
+/// Whether a token has ever been issued, even if it's since been revoked.
+///
+/// Until one has, the registry is open to anyone. Revoking the last token
+/// doesn't open it up again.
+pub fn tokens_issued(conn: &Connection) -> Result<bool> {
+    conn.query_row(
+        "SELECT (SELECT COUNT(*) FROM tokens)
+            + (SELECT COUNT(*) FROM public_keys)
+            + (SELECT COUNT(*) FROM trusted_publishers)
+            + (SELECT COUNT(*) FROM revocations) > 0",
+        params![],
+        |row| row.get(0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh database, in memory. Or when `ESTUARY_TEST_DATABASE_URL`
+    /// points at a Postgres server, a schema of its own there (which is left
+    /// behind afterwards).
+    fn get_conn() -> Connection {
+        let conn = match std::env::var("ESTUARY_TEST_DATABASE_URL") {
+            Ok(url) => {
+                let conn = Connection::open(&url.parse().unwrap()).unwrap();
+                // Tests running at the same time may race to create it.
+                conn.execute_batch("CREATE EXTENSION IF NOT EXISTS citext SCHEMA public")
+                    .ok();
+                let schema = format!("test_{}", rand::random::<u32>());
+                conn.execute_batch(&format!(
+                    "CREATE SCHEMA {0}; SET search_path TO {0}, public",
+                    schema
+                ))
+                .unwrap();
+                conn
+            }
+            Err(_) => Connection::open_in_memory().unwrap(),
+        };
+        init(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_init_is_idempotent() {
+        let conn = get_conn();
+        init(&conn).unwrap();
+        assert_eq!(MIGRATIONS.len(), schema_version(&conn).unwrap());
+    }
+
+    #[test]
+    fn test_pool() {
+        let data_root = crate::test_helpers::get_data_root();
+        let pool = pool(
+            &DatabaseUrl::Sqlite(data_root.path().join("estuary.db")),
+            2,
+            Duration::from_secs(1),
+            Pragmas::default(),
+        )
+        .unwrap();
+        let first = pool.get().unwrap();
+        init(&first).unwrap();
+        // Both connections see the same database.
+        let second = pool.get().unwrap();
+        set_token(&second, "ci", "abc", Scope::ALL).unwrap();
+        assert_eq!(1, count_tokens(&first).unwrap());
+        let timeout: i64 = first
+            .query_row("PRAGMA busy_timeout", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(1000, timeout);
+        let journal_mode: String = first
+            .query_row("PRAGMA journal_mode", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!("wal", journal_mode);
+        // NORMAL
+        let synchronous: i64 = second
+            .query_row("PRAGMA synchronous", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(1, synchronous);
+    }
+
+    #[test]
+    fn test_migrations_run_once() {
+        let conn = get_conn();
+        let applied: usize = conn
+            .query_row("SELECT COUNT(*) FROM schema_version", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(MIGRATIONS.len(), applied);
+
+        // A database a newer version has been at still opens.
+        conn.execute(
+            "INSERT INTO schema_version (version) VALUES (?1)",
+            params![MIGRATIONS.len() + 1],
+        )
+        .unwrap();
+        init(&conn).unwrap();
+        assert_eq!(MIGRATIONS.len() + 1, schema_version(&conn).unwrap());
+    }
+
+    #[test]
+    fn test_list_token_hashes() {
+        let conn = get_conn();
+        assert_eq!(0, count_tokens(&conn).unwrap());
+        set_token(&conn, "ci", "abc", Scope::ALL).unwrap();
+        set_token(&conn, "alice", "def", &[Scope::Publish]).unwrap();
+        assert_eq!(2, count_tokens(&conn).unwrap());
+
+        let tokens = list_token_hashes(&conn).unwrap();
+        assert_eq!(2, tokens.len());
+        assert_eq!("ci", tokens[0].0.name);
+        assert_eq!(Scope::ALL.to_vec(), tokens[0].0.scopes);
+        assert_eq!(hash_token("abc"), tokens[0].1);
+        assert_eq!("alice", tokens[1].0.name);
+        assert_eq!(vec![Scope::Publish], tokens[1].0.scopes);
+        assert_eq!(hash_token("def"), tokens[1].1);
+    }
+
+    #[test]
+    fn test_create_and_revoke_token() {
+        let conn = get_conn();
+        create_token(&conn, "ci", "abc", &[Scope::Publish], None).unwrap();
+        assert!(create_token(&conn, "ci", "def", &[Scope::Publish], None).is_err());
+
+        let tokens = list_tokens(&conn).unwrap();
+        assert_eq!(1, tokens.len());
+        assert_eq!("ci", tokens[0].name);
+        assert_eq!(19, tokens[0].created_at.len());
+        assert_eq!(
+            Some("ci".to_string()),
+            find_token_name(&conn, tokens[0].id).unwrap()
+        );
+
+        assert!(revoke_token(&conn, "ci", "admin", Some("leaked")).unwrap());
+        assert!(!revoke_token(&conn, "ci", "shell", None).unwrap());
+        assert_eq!(0, count_tokens(&conn).unwrap());
+        assert!(tokens_issued(&conn).unwrap());
+        assert_eq!(None, find_token_name(&conn, tokens[0].id).unwrap());
+
+        // Only the revocation that did something is recorded.
+        let revocations = list_revocations(&conn).unwrap();
+        assert_eq!(1, revocations.len());
+        assert_eq!("ci", revocations[0].token_name);
+        assert_eq!("admin", revocations[0].revoked_by);
+        assert_eq!(Some("leaked".to_string()), revocations[0].reason);
+    }
+
+    #[test]
+    fn test_public_keys() {
+        let conn = get_conn();
+        add_public_key(
+            &conn,
+            "ci",
+            "k3.public.abc",
+            "k3.pid.abc",
+            &[Scope::Publish],
+        )
+        .unwrap();
+        assert_eq!(1, count_tokens(&conn).unwrap());
+
+        let (token, public_key) = find_public_key(&conn, "k3.pid.abc").unwrap().unwrap();
+        assert_eq!("ci", token.name);
+        assert_eq!(vec![Scope::Publish], token.scopes);
+        assert_eq!("k3.public.abc", public_key);
+        assert_eq!(None, find_public_key(&conn, "k3.pid.nope").unwrap());
+
+        let keys = list_public_keys(&conn).unwrap();
+        assert_eq!("k3.pid.abc", keys[0].1);
+
+        // Revoking a token doesn't touch a key that shares its name.
+        set_token(&conn, "ci", "abc", &[Scope::Publish]).unwrap();
+        assert!(revoke_token(&conn, "ci", "shell", None).unwrap());
+        assert!(find_public_key(&conn, "k3.pid.abc").unwrap().is_some());
+
+        assert_eq!(
+            Some("ci".to_string()),
+            remove_public_key(&conn, "k3.pid.abc", "shell", Some("lost")).unwrap()
+        );
+        assert_eq!(
+            None,
+            remove_public_key(&conn, "k3.pid.abc", "shell", None).unwrap()
+        );
+        assert_eq!(None, find_public_key(&conn, "k3.pid.abc").unwrap());
+        assert_eq!(2, list_revocations(&conn).unwrap().len());
+    }
+
+    #[test]
+    fn test_users() {
+        let conn = get_conn();
+        set_user_password(&conn, "alice", "hash1").unwrap();
+        set_user_password(&conn, "bob", "hash2").unwrap();
+        set_user_password(&conn, "alice", "hash3").unwrap();
+        assert_eq!(vec!["alice", "bob"], list_users(&conn).unwrap());
+        assert_eq!(
+            Some("hash3".to_string()),
+            find_user_password_hash(&conn, "alice").unwrap()
+        );
+        assert_eq!(None, find_user_password_hash(&conn, "carol").unwrap());
+
+        assert!(remove_user(&conn, "alice").unwrap());
+        assert!(!remove_user(&conn, "alice").unwrap());
+        assert_eq!(vec!["bob"], list_users(&conn).unwrap());
+    }
+
+    #[test]
+    fn test_users_by_backend() {
+        let conn = get_conn();
+        let alice = find_or_create_oidc_user(&conn, "sub-1", "alice").unwrap();
+        set_user_password(&conn, "bob", "hash").unwrap();
+
+        // Someone in the directory with the same login isn't the same person.
+        assert_eq!(
+            None,
+            find_or_create_password_user(&conn, Backend::Ldap, "alice").unwrap()
+        );
+        assert_eq!(
+            None,
+            find_or_create_password_user(&conn, Backend::Ldap, "bob").unwrap()
+        );
+        let bob = find_or_create_password_user(&conn, Backend::Local, "bob")
+            .unwrap()
+            .unwrap();
+        let carol = find_or_create_password_user(&conn, Backend::Ldap, "carol")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Some(&carol),
+            find_or_create_password_user(&conn, Backend::Ldap, "carol")
+                .unwrap()
+                .as_ref()
+        );
+
+        assert_eq!(
+            OIDC_USER_SCOPES,
+            &find_user_scopes(&conn, alice.id).unwrap()[..]
+        );
+        assert_eq!(vec![Scope::Read], find_user_scopes(&conn, bob.id).unwrap());
+        assert!(set_user_scopes(&conn, "bob", &[Scope::Read, Scope::Publish]).unwrap());
+        assert_eq!(
+            vec![Scope::Read, Scope::Publish],
+            find_user_scopes(&conn, bob.id).unwrap()
+        );
+        assert!(!set_user_scopes(&conn, "nobody", &[Scope::Read]).unwrap());
+        assert!(find_user_scopes(&conn, 9999).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_oidc_users_and_sessions() {
+        let conn = get_conn();
+        let user = find_or_create_oidc_user(&conn, "sub-1", "alice").unwrap();
+        assert_eq!("alice", user.login);
+        // The login is only used the first time around.
+        assert_eq!(
+            user,
+            find_or_create_oidc_user(&conn, "sub-1", "alice2").unwrap()
+        );
+        assert!(is_unique_violation(
+            &find_or_create_oidc_user(&conn, "sub-2", "alice").unwrap_err()
+        ));
+        // They don't get a password.
+        assert_eq!(None, find_user_password_hash(&conn, "alice").unwrap());
+
+        create_session(&conn, "session", user.id).unwrap();
+        assert_eq!(
+            Some(&user),
+            find_session_user(&conn, "session", 60).unwrap().as_ref()
+        );
+        assert_eq!(None, find_session_user(&conn, "session", -1).unwrap());
+        assert_eq!(None, find_session_user(&conn, "other", 60).unwrap());
+
+        create_token(&conn, "alice/laptop", "secret", Scope::ALL, Some(user.id)).unwrap();
+        assert_eq!(Some(user.id), list_tokens(&conn).unwrap()[0].user_id);
+
+        assert!(remove_user(&conn, "alice").unwrap());
+        assert_eq!(None, find_session_user(&conn, "session", 60).unwrap());
+        assert!(list_tokens(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_find_or_create_user() {
+        let conn = get_conn();
+        set_user_password(&conn, "alice", "hash").unwrap();
+        let alice = find_or_create_user(&conn, "alice").unwrap();
+        let bob = find_or_create_user(&conn, "bob").unwrap();
+        assert_ne!(alice.id, bob.id);
+        assert_eq!(bob, find_or_create_user(&conn, "bob").unwrap());
+        // Existing passwords are left alone.
+        assert_eq!(
+            Some("hash".to_string()),
+            find_user_password_hash(&conn, "alice").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_trusted_publishers() {
+        let conn = get_conn();
+        let rule = |crate_name: &str| TrustedPublisher {
+            id: 0,
+            crate_name: crate_name.to_string(),
+            provider: Provider::GitHub,
+            issuer: Provider::GitHub.default_issuer().to_string(),
+            repository: "example/repo".to_string(),
+            workflow: Some("release.yml".to_string()),
+            branch: None,
+        };
+        let first = add_trusted_publisher(&conn, &rule("a")).unwrap();
+        add_trusted_publisher(&conn, &rule("b")).unwrap();
+        assert_eq!(2, count_tokens(&conn).unwrap());
+
+        let found = list_trusted_publishers(&conn, Some("a")).unwrap();
+        assert_eq!(
+            vec![TrustedPublisher {
+                id: first,
+                ..rule("a")
+            }],
+            found
+        );
+        assert_eq!(2, list_trusted_publishers(&conn, None).unwrap().len());
+
+        assert!(remove_trusted_publisher(&conn, first).unwrap());
+        assert!(!remove_trusted_publisher(&conn, first).unwrap());
+        assert!(list_trusted_publishers(&conn, Some("a"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_grants() {
+        let conn = get_conn();
+        let alice = find_or_create_user(&conn, "alice").unwrap();
+        create_token(&conn, "ci", "estci", Scope::ALL, None).unwrap();
+        create_token(
+            &conn,
+            "alice/laptop",
+            "estlaptop",
+            Scope::ALL,
+            Some(alice.id),
+        )
+        .unwrap();
+        let tokens = list_tokens(&conn).unwrap();
+        let token = |name: &str| tokens.iter().find(|t| t.name == name).unwrap();
+
+        let ci = add_grant(&conn, "team-a-*", &Grantee::Token("ci".to_string())).unwrap();
+        add_grant(&conn, "my-crate", &Grantee::User("alice".to_string())).unwrap();
+        assert_eq!(2, count_grants(&conn).unwrap());
+        assert_eq!(
+            Grant {
+                id: ci,
+                crate_pattern: "team-a-*".to_string(),
+                grantee: Grantee::Token("ci".to_string()),
+            },
+            list_grants(&conn).unwrap().remove(0)
+        );
+
+        assert!(is_granted(&conn, token("ci"), "team-a-core").unwrap());
+        assert!(is_granted(&conn, token("ci"), "Team-A-Core").unwrap());
+        assert!(!is_granted(&conn, token("ci"), "team-b-core").unwrap());
+        assert!(!is_granted(&conn, token("ci"), "my-crate").unwrap());
+        assert!(is_granted(&conn, token("alice/laptop"), "my-crate").unwrap());
+        assert!(!is_granted(&conn, token("alice/laptop"), "team-a-core").unwrap());
+
+        // Grants go along with whoever they were for.
+        revoke_token(&conn, "ci", "shell", None).unwrap();
+        remove_user(&conn, "alice").unwrap();
+        assert_eq!(0, count_grants(&conn).unwrap());
+        assert!(!remove_grant(&conn, ci).unwrap());
+    }
+
+    #[test]
+    fn test_crate_owners() {
+        let conn = get_conn();
+        let alice = find_or_create_user(&conn, "alice").unwrap();
+        let bob = find_or_create_user(&conn, "bob").unwrap();
+        assert_eq!(Some(bob.clone()), find_user(&conn, "bob").unwrap());
+        assert_eq!(None, find_user(&conn, "carol").unwrap());
+
+        assert!(add_crate_owner(&conn, "my-crate", alice.id).unwrap());
+        assert!(!add_crate_owner(&conn, "my-crate", alice.id).unwrap());
+        assert!(add_crate_owner(&conn, "My-Crate", bob.id).unwrap());
+        assert_eq!(
+            vec![alice.clone(), bob.clone()],
+            list_crate_owners(&conn, "my-crate").unwrap()
+        );
+        assert!(list_crate_owners(&conn, "other-crate").unwrap().is_empty());
+
+        assert!(remove_crate_owner(&conn, "my-crate", alice.id).unwrap());
+        assert!(!remove_crate_owner(&conn, "my-crate", alice.id).unwrap());
+        remove_user(&conn, "bob").unwrap();
+        assert!(list_crate_owners(&conn, "my-crate").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_publishes() {
+        let conn = get_conn();
+        let alice = create_user(&conn, "alice", "hash").unwrap();
+        assert!(is_unique_violation(
+            &create_user(&conn, "alice", "hash").unwrap_err()
+        ));
+        record_publish(
+            &conn,
+            "my-crate",
+            "0.1.0",
+            Some(alice.id),
+            Some("alice/laptop"),
+        )
+        .unwrap();
+        record_publish(
+            &conn,
+            "my-crate",
+            "0.2.0",
+            Some(alice.id),
+            Some("alice/laptop"),
+        )
+        .unwrap();
+        record_publish(&conn, "other-crate", "1.0.0", None, None).unwrap();
+        add_crate_owner(&conn, "my-crate", alice.id).unwrap();
+
+        let publishes = list_user_publishes(&conn, alice.id).unwrap();
+        let versions: Vec<_> = publishes.iter().map(|p| p.version.as_str()).collect();
+        assert_eq!(vec!["0.2.0", "0.1.0"], versions);
+        assert_eq!(Some("alice/laptop".to_string()), publishes[0].token_name);
+        assert_eq!(
+            Some(&publishes[0]),
+            find_publish(&conn, "my-crate", "0.2.0").unwrap().as_ref()
+        );
+        assert_eq!(None, find_publish(&conn, "my-crate", "9.9.9").unwrap());
+        assert_eq!(3, count_recent_publishes(&conn, 30).unwrap());
+        conn.execute(
+            "UPDATE crate_versions SET created_at = ?1 WHERE version = '0.1.0'",
+            params![now() - 31 * 24 * 60 * 60],
+        )
+        .unwrap();
+        assert_eq!(2, count_recent_publishes(&conn, 30).unwrap());
+        let recent = list_recent_publishes(&conn, 2).unwrap();
+        assert_eq!(
+            vec![
+                ("other-crate", None),
+                ("my-crate", Some("alice".to_string()))
+            ],
+            recent
+                .iter()
+                .map(|(publish, login)| (publish.crate_name.as_str(), login.clone()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["my-crate".to_string()],
+            list_owned_crates(&conn, alice.id).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_crate_files() {
+        let conn = get_conn();
+        assert_eq!(None, find_crate_file(&conn, "my-crate", "0.1.0").unwrap());
+        record_crate_file(&conn, "my-crate", "0.1.0", "abc", 10).unwrap();
+        // Names are case-insensitive, as they are for owners.
+        record_crate_file(&conn, "My-Crate", "0.1.0", "def", 20).unwrap();
+        assert_eq!(
+            Some("def".to_string()),
+            find_crate_file(&conn, "my-crate", "0.1.0").unwrap()
+        );
+        assert_eq!(None, find_crate_file(&conn, "my-crate", "0.2.0").unwrap());
+        record_crate_file(&conn, "other-crate", "0.1.0", "def", 20).unwrap();
+        assert_eq!(vec!["def"], list_crate_file_digests(&conn).unwrap());
+
+        // Only published versions are listed, with their files if recorded.
+        record_publish(&conn, "my-crate", "0.1.0", None, None).unwrap();
+        record_publish(&conn, "my-crate", "0.2.0", None, None).unwrap();
+        assert_eq!(
+            vec![
+                RecordedVersion {
+                    crate_name: "my-crate".to_string(),
+                    version: "0.1.0".to_string(),
+                    digest: Some("def".to_string()),
+                    yanked_at: None,
+                },
+                RecordedVersion {
+                    crate_name: "my-crate".to_string(),
+                    version: "0.2.0".to_string(),
+                    digest: None,
+                    yanked_at: None,
+                },
+            ],
+            list_versions(&conn).unwrap()
+        );
+
+        // Yanks are recorded from when they first were, until unyanked.
+        set_yanked(&conn, "My-Crate", "0.2.0", true).unwrap();
+        conn.execute(
+            "UPDATE crate_versions SET yanked_at = 0 WHERE version = '0.2.0'",
+            params![],
+        )
+        .unwrap();
+        set_yanked(&conn, "my-crate", "0.2.0", true).unwrap();
+        let yanked_at = |conn: &Connection| {
+            list_versions(conn)
+                .unwrap()
+                .into_iter()
+                .map(|version| version.yanked_at)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            vec![None, Some("1970-01-01 00:00:00".to_string())],
+            yanked_at(&conn)
+        );
+        set_yanked(&conn, "my-crate", "0.2.0", false).unwrap();
+        assert_eq!(vec![None, None], yanked_at(&conn));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let conn = get_conn();
+        assert_eq!(None, find_metadata(&conn, "my-crate", "0.1.0").unwrap());
+        record_publish(&conn, "my-crate", "0.1.0", None, None).unwrap();
+        assert_eq!(
+            Some(CrateMetadata::default()),
+            find_metadata(&conn, "my-crate", "0.1.0").unwrap()
+        );
+
+        let metadata = CrateMetadata {
+            description: Some("Does things".to_string()),
+            readme: Some("# My Crate".to_string()),
+            license: Some("MIT OR Apache-2.0".to_string()),
+            keywords: vec!["things".to_string(), "stuff".to_string()],
+            categories: vec!["development-tools".to_string()],
+            authors: vec!["Zed".to_string(), "Alice".to_string()],
+            ..Default::default()
+        };
+        record_metadata(&conn, "my-crate", "0.1.0", &metadata).unwrap();
+        assert_eq!(
+            Some(CrateMetadata {
+                keywords: vec!["stuff".to_string(), "things".to_string()],
+                ..metadata.clone()
+            }),
+            find_metadata(&conn, "My-Crate", "0.1.0").unwrap()
+        );
+
+        // Publishing again replaces it all.
+        record_metadata(&conn, "my-crate", "0.1.0", &CrateMetadata::default()).unwrap();
+        assert_eq!(
+            Some(CrateMetadata::default()),
+            find_metadata(&conn, "my-crate", "0.1.0").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let conn = get_conn();
+        let publish = |name: &str, version: &str, metadata: CrateMetadata| {
+            record_publish(&conn, name, version, None, None).unwrap();
+            record_metadata(&conn, name, version, &metadata).unwrap();
+            update_search(&conn, name).unwrap();
+        };
+        publish(
+            "serde_json",
+            "1.0.0",
+            CrateMetadata {
+                description: Some("A JSON serialization file format".to_string()),
+                ..Default::default()
+            },
+        );
+        publish(
+            "json-tools",
+            "0.1.0",
+            CrateMetadata {
+                keywords: vec!["json".to_string()],
+                ..Default::default()
+            },
+        );
+        publish(
+            "yaml",
+            "0.1.0",
+            CrateMetadata {
+                readme: Some("Like json, but with more whitespace.".to_string()),
+                ..Default::default()
+            },
+        );
+        // Only the newest version counts.
+        publish(
+            "yaml",
+            "0.0.1",
+            CrateMetadata {
+                description: Some("Serialization".to_string()),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            vec!["json-tools", "serde_json", "yaml"],
+            search_crates(&conn, "json").unwrap()
+        );
+        // Words match the start of words.
+        assert_eq!(vec!["serde_json"], search_crates(&conn, "serial").unwrap());
+        assert_eq!(vec!["serde_json"], search_crates(&conn, "SERDE").unwrap());
+        // Or anywhere in a name.
+        assert_eq!(vec!["serde_json"], search_crates(&conn, "erde").unwrap());
+        // Search syntax is taken as words.
+        assert_eq!(
+            vec!["yaml"],
+            search_crates(&conn, "\"whitespace*\" NOT").unwrap()
+        );
+        assert!(search_crates(&conn, "-").unwrap().is_empty());
+
+        // Crates from before are found by name.
+        add_to_search(&conn, &["old-json".to_string(), "yaml".to_string()]).unwrap();
+        assert_eq!(4, search_crates(&conn, "json").unwrap().len());
+        assert_eq!(vec!["yaml"], search_crates(&conn, "yaml").unwrap());
+
+        let latest = |name: &str| {
+            find_latest_version(&conn, name)
+                .unwrap()
+                .map(|(name, version)| format!("{} {}", name, version))
+        };
+        assert_eq!(Some("yaml 0.1.0".to_string()), latest("YAML"));
+        set_yanked(&conn, "yaml", "0.1.0", true).unwrap();
+        assert_eq!(Some("yaml 0.0.1".to_string()), latest("yaml"));
+        set_yanked(&conn, "yaml", "0.0.1", true).unwrap();
+        assert_eq!(None, latest("yaml"));
+        assert_eq!(None, latest("old-json"));
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let conn = get_conn();
+        let publish = |name: &str, description: &str| {
+            record_publish(&conn, name, "0.1.0", None, None).unwrap();
+            let metadata = CrateMetadata {
+                description: Some(description.to_string()),
+                ..Default::default()
+            };
+            record_metadata(&conn, name, "0.1.0", &metadata).unwrap();
+            update_search(&conn, name).unwrap();
+        };
+        publish("declientizer", "Removes clients");
+        publish("acme-client", "");
+        publish("http", "An HTTP client");
+        publish("client-utils", "");
+        publish("client", "");
+
+        // The crate named, then names starting with it, then names with it
+        // as a word, then the rest.
+        assert_eq!(
+            vec![
+                "client",
+                "client-utils",
+                "acme-client",
+                "declientizer",
+                "http"
+            ],
+            search_crates(&conn, "client").unwrap()
+        );
+        // `-` and `_` are the same in a crate's name.
+        let words = ["client".to_string(), "utils".to_string()];
+        assert_eq!(
+            NameMatch::Exact,
+            name_match("client-utils", "Client_Utils", &words)
+        );
+        assert_eq!(
+            "client-utils",
+            search_crates(&conn, "client_utils").unwrap()[0]
+        );
+
+        // More downloads (recently) count for more, among otherwise equal
+        // matches.
+        publish("clientele", "");
+        record_downloads(
+            &conn,
+            &[Downloads {
+                crate_name: "client-utils".to_string(),
+                version: "0.1.0".to_string(),
+                date: OffsetDateTime::now_utc().date().to_string(),
+                count: 100,
+            }],
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["client-utils", "clientele"],
+            search_crates(&conn, "client").unwrap()[1..3].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_sort_crates() {
+        let conn = get_conn();
+        for (name, version, created_at) in &[
+            ("alpha", "0.1.0", 100),
+            ("alpha", "0.2.0", 400),
+            ("beta", "0.1.0", 200),
+            ("Gamma", "0.1.0", 300),
+        ] {
+            record_publish(&conn, name, version, None, None).unwrap();
+            conn.execute(
+                "UPDATE crate_versions SET created_at = ?1 WHERE crate_name = ?2 AND version = ?3",
+                params![created_at, name, version],
+            )
+            .unwrap();
+        }
+        record_downloads(
+            &conn,
+            &[Downloads {
+                crate_name: "beta".to_string(),
+                version: "0.1.0".to_string(),
+                date: "2020-01-01".to_string(),
+                count: 5,
+            }],
+        )
+        .unwrap();
+
+        let sorted = |sort| {
+            let mut names: Vec<String> = ["old", "Gamma", "beta", "alpha"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            sort_crates(&conn, &mut names, sort).unwrap();
+            names
+        };
+        assert_eq!(
+            vec!["old", "Gamma", "beta", "alpha"],
+            sorted(SearchSort::Relevance)
+        );
+        assert_eq!(
+            vec!["alpha", "beta", "Gamma", "old"],
+            sorted(SearchSort::Alphabetical)
+        );
+        assert_eq!(
+            vec!["Gamma", "beta", "alpha", "old"],
+            sorted(SearchSort::Newest)
+        );
+        assert_eq!(
+            vec!["alpha", "Gamma", "beta", "old"],
+            sorted(SearchSort::RecentlyUpdated)
+        );
+        // Level crates keep their order.
+        assert_eq!(
+            vec!["beta", "old", "Gamma", "alpha"],
+            sorted(SearchSort::Downloads)
+        );
+    }
+
+    #[test]
+    fn test_list_crates_page() {
+        let conn = get_conn();
+        for (name, version, created_at) in &[
+            ("alpha", "0.1.0", 100),
+            ("alpha", "0.2.0", 400),
+            ("beta", "0.1.0", 200),
+            ("Gamma", "0.1.0", 300),
+        ] {
+            record_publish(&conn, name, version, None, None).unwrap();
+            conn.execute(
+                "UPDATE crate_versions SET created_at = ?1 WHERE crate_name = ?2 AND version = ?3",
+                params![created_at, name, version],
+            )
+            .unwrap();
+        }
+        record_downloads(
+            &conn,
+            &[Downloads {
+                crate_name: "beta".to_string(),
+                version: "0.1.0".to_string(),
+                date: "2020-01-01".to_string(),
+                count: 5,
+            }],
+        )
+        .unwrap();
+        let names: Vec<String> = ["alpha", "beta", "Gamma", "old"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        add_to_search(&conn, &names).unwrap();
+
+        let page = |prefix, sort, offset, limit| {
+            list_crates_page(&conn, prefix, sort, offset, limit).unwrap()
+        };
+        let names = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            (names(&["alpha", "beta", "Gamma", "old"]), 4),
+            page(None, SearchSort::Alphabetical, 0, 10)
+        );
+        assert_eq!(
+            (names(&["beta", "Gamma"]), 4),
+            page(None, SearchSort::Alphabetical, 1, 2)
+        );
+        assert_eq!(
+            (names(&["alpha", "Gamma", "beta", "old"]), 4),
+            page(None, SearchSort::RecentlyUpdated, 0, 10)
+        );
+        assert_eq!(
+            (names(&["Gamma", "beta", "alpha", "old"]), 4),
+            page(None, SearchSort::Newest, 0, 10)
+        );
+        assert_eq!(
+            (names(&["beta", "alpha", "Gamma", "old"]), 4),
+            page(None, SearchSort::Downloads, 0, 10)
+        );
+        assert_eq!(
+            (names(&["Gamma"]), 1),
+            page(Some("G"), SearchSort::Alphabetical, 0, 10)
+        );
+        // Past the end, however far.
+        assert_eq!(
+            (names(&[]), 4),
+            page(None, SearchSort::Alphabetical, usize::MAX, usize::MAX)
+        );
+
+        assert_eq!(
+            names(&["a", "b", "g", "o"]),
+            list_crate_letters(&conn).unwrap()
+        );
+        remove_from_search(&conn, "old").unwrap();
+        assert_eq!(names(&["a", "b", "g"]), list_crate_letters(&conn).unwrap());
+    }
+
+    #[test]
+    fn test_list_tagged_crates() {
+        let conn = get_conn();
+        let publish = |name: &str, version: &str, keywords: &[&str], categories: &[&str]| {
+            record_publish(&conn, name, version, None, None).unwrap();
+            let metadata = CrateMetadata {
+                keywords: keywords.iter().map(|s| s.to_string()).collect(),
+                categories: categories.iter().map(|s| s.to_string()).collect(),
+                ..Default::default()
+            };
+            record_metadata(&conn, name, version, &metadata).unwrap();
+        };
+        publish(
+            "my-macros",
+            "0.1.0",
+            &["proc-macro"],
+            &["development-tools"],
+        );
+        publish("my-macros", "0.2.0", &["proc-macro", "derive"], &[]);
+        publish("sdk", "1.0.0", &["internal-sdk"], &["development-tools"]);
+        publish("sdk-macros", "1.0.0", &["Proc-Macro", "internal-sdk"], &[]);
+
+        let tagged = |keyword, category| list_tagged_crates(&conn, keyword, category).unwrap();
+        assert_eq!(
+            vec!["my-macros", "sdk-macros"],
+            tagged(Some("proc-macro"), None)
+        );
+        assert_eq!(
+            vec!["my-macros", "sdk"],
+            tagged(None, Some("development-tools"))
+        );
+        assert_eq!(
+            vec!["sdk"],
+            tagged(Some("internal-sdk"), Some("development-tools"))
+        );
+        assert!(tagged(Some("nothing"), None).is_empty());
+        assert!(tagged(None, None).is_empty());
+    }
+
+    #[test]
+    fn test_downloads() {
+        let conn = get_conn();
+        let today = OffsetDateTime::now_utc().date().to_string();
+        let downloads = |crate_name: &str, date: &str, count| Downloads {
+            crate_name: crate_name.to_string(),
+            version: "0.1.0".to_string(),
+            date: date.to_string(),
+            count,
+        };
+        record_downloads(
+            &conn,
+            &[
+                downloads("my-crate", "2020-01-01", 10),
+                downloads("my-crate", &today, 2),
+                downloads("other-crate", &today, 5),
+                downloads("third-crate", &today, 5),
+            ],
+        )
+        .unwrap();
+        assert_eq!(12, count_downloads(&conn, "my-crate", None).unwrap());
+        assert_eq!(2, count_recent_downloads(&conn, "my-crate").unwrap());
+        assert_eq!(0, count_recent_downloads(&conn, "no-crate").unwrap());
+        assert_eq!(
+            vec![("my-crate".to_string(), 12), ("other-crate".to_string(), 5)],
+            list_most_downloaded(&conn, 2).unwrap()
+        );
+        assert_eq!(
+            vec![("2020-01-01".to_string(), 10), (today.clone(), 2)],
+            list_daily_downloads(&conn, "My-Crate").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_delete_version() {
+        let conn = get_conn();
+        let metadata = CrateMetadata {
+            keywords: vec!["sdk".to_string()],
+            ..Default::default()
+        };
+        for (name, version, digest) in &[
+            ("my-crate", "0.1.0", "abc"),
+            ("my-crate", "0.2.0", "def"),
+            ("other-crate", "0.1.0", "abc"),
+        ] {
+            record_publish(&conn, name, version, None, None).unwrap();
+            record_metadata(&conn, name, version, &metadata).unwrap();
+            record_crate_file(&conn, name, version, digest, 10).unwrap();
+        }
+
+        // Another version's file is stored under the same digest.
+        assert_eq!(None, delete_version(&conn, "my-crate", "0.1.0").unwrap());
+        assert_eq!(None, find_publish(&conn, "my-crate", "0.1.0").unwrap());
+        assert_eq!(None, find_metadata(&conn, "my-crate", "0.1.0").unwrap());
+        assert_eq!(None, find_crate_file(&conn, "my-crate", "0.1.0").unwrap());
+        assert_eq!(
+            Some("def".to_string()),
+            delete_version(&conn, "My-Crate", "0.2.0").unwrap()
+        );
+        assert_eq!(None, delete_version(&conn, "my-crate", "0.2.0").unwrap());
+        assert_eq!(
+            Some("abc".to_string()),
+            delete_version(&conn, "other-crate", "0.1.0").unwrap()
+        );
+        assert!(list_versions(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tombstones() {
+        let conn = get_conn();
+        assert_eq!(None, find_tombstone(&conn, "my-crate", "0.1.0").unwrap());
+        add_tombstone(&conn, "my-crate", "0.1.0", "abc", None).unwrap();
+        // Deleted again, after an admin's put it back by hand.
+        add_tombstone(&conn, "my-crate", "0.1.0", "def", Some("leaked a key")).unwrap();
+
+        let tombstone = find_tombstone(&conn, "My-Crate", "0.1.0").unwrap().unwrap();
+        assert_eq!("my-crate", tombstone.crate_name);
+        assert_eq!("def", tombstone.cksum);
+        assert_eq!(Some("leaked a key".to_string()), tombstone.reason);
+        assert_eq!(None, find_tombstone(&conn, "my-crate", "0.2.0").unwrap());
+        // Build metadata doesn't make it another version.
+        let tombstone = find_tombstone(&conn, "my-crate", "0.1.0+build")
+            .unwrap()
+            .unwrap();
+        assert_eq!("0.1.0", tombstone.version);
+        add_tombstone(&conn, "my-crate", "0.2.0+linux", "ghi", None).unwrap();
+        let tombstone = find_tombstone(&conn, "my-crate", "0.2.0").unwrap().unwrap();
+        assert_eq!("ghi", tombstone.cksum);
+        assert_eq!(
+            None,
+            find_tombstone(&conn, "my-crate", "0.2.0-rc.1").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_version_stats() {
+        let conn = get_conn();
+        record_publish(&conn, "my-crate", "0.1.0", None, None).unwrap();
+        record_publish(&conn, "my-crate", "0.2.0", None, None).unwrap();
+        record_publish(&conn, "other-crate", "0.1.0", None, None).unwrap();
+        record_crate_file(&conn, "my-crate", "0.2.0", "abc", 10).unwrap();
+        record_downloads(
+            &conn,
+            &[Downloads {
+                crate_name: "my-crate".to_string(),
+                version: "0.2.0".to_string(),
+                date: "2020-01-01".to_string(),
+                count: 3,
+            }],
+        )
+        .unwrap();
+        let stats = list_version_stats(&conn, "my-crate").unwrap();
+        assert_eq!(
+            vec![("0.1.0", None, 0), ("0.2.0", Some(10), 3)],
+            stats
+                .iter()
+                .map(|stats| (stats.version.as_str(), stats.size, stats.downloads))
+                .collect::<Vec<_>>()
+        );
+        assert!(list_version_stats(&conn, "no-crate").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_usage() {
+        let conn = get_conn();
+        assert_eq!(0, crate_usage(&conn, "my-crate").unwrap());
+        assert_eq!(0, registry_usage(&conn).unwrap());
+        record_crate_file(&conn, "my-crate", "0.1.0", "abc", 10).unwrap();
+        record_crate_file(&conn, "my-crate", "0.2.0", "def", 20).unwrap();
+        // The same file as another crate's, so it's only stored the once.
+        record_crate_file(&conn, "other-crate", "0.1.0", "abc", 10).unwrap();
+        assert_eq!(30, crate_usage(&conn, "My-Crate").unwrap());
+        assert_eq!(30, registry_usage(&conn).unwrap());
+        assert_eq!(
+            vec![
+                CrateUsage {
+                    crate_name: "my-crate".to_string(),
+                    versions: 2,
+                    bytes: 30,
+                },
+                CrateUsage {
+                    crate_name: "other-crate".to_string(),
+                    versions: 1,
+                    bytes: 10,
+                },
+            ],
+            list_crate_usage(&conn).unwrap()
+        );
+        assert!(database_size(&conn).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_teams() {
+        let conn = get_conn();
+        let alice = find_or_create_user(&conn, "alice").unwrap();
+        let bob = find_or_create_user(&conn, "bob").unwrap();
+        let backend = create_team(&conn, "backend").unwrap();
+        assert!(is_unique_violation(
+            &create_team(&conn, "Backend").unwrap_err()
+        ));
+        // Postgres doesn't reuse the id the failed insert took.
+        let empty = create_team(&conn, "empty").unwrap();
+        assert_eq!(Some(backend.clone()), find_team(&conn, "backend").unwrap());
+
+        assert!(add_team_member(&conn, backend.id, bob.id).unwrap());
+        assert!(add_team_member(&conn, backend.id, alice.id).unwrap());
+        assert!(!add_team_member(&conn, backend.id, alice.id).unwrap());
+        assert_eq!(
+            vec![
+                (
+                    backend.clone(),
+                    vec!["alice".to_string(), "bob".to_string()]
+                ),
+                (empty, vec![]),
+            ],
+            list_teams(&conn).unwrap()
+        );
+
+        assert!(add_crate_owner_team(&conn, "my-crate", backend.id).unwrap());
+        assert!(!add_crate_owner_team(&conn, "My-Crate", backend.id).unwrap());
+        assert_eq!(
+            vec![backend.clone()],
+            list_crate_owner_teams(&conn, "my-crate").unwrap()
+        );
+        assert_eq!(
+            vec!["my-crate".to_string()],
+            list_owned_crates(&conn, alice.id).unwrap()
+        );
+
+        assert!(remove_team_member(&conn, backend.id, alice.id).unwrap());
+        assert!(list_owned_crates(&conn, alice.id).unwrap().is_empty());
+        assert!(remove_crate_owner_team(&conn, "my-crate", backend.id).unwrap());
+        assert!(!remove_crate_owner_team(&conn, "my-crate", backend.id).unwrap());
+
+        add_crate_owner_team(&conn, "my-crate", backend.id).unwrap();
+        assert!(remove_team(&conn, "backend").unwrap());
+        assert!(!remove_team(&conn, "backend").unwrap());
+        assert!(list_crate_owner_teams(&conn, "my-crate")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_reservations() {
+        let conn = get_conn();
+        let alice = find_or_create_user(&conn, "alice").unwrap();
+        let bob = find_or_create_user(&conn, "bob").unwrap();
+        let carol = find_or_create_user(&conn, "carol").unwrap();
+        let team = create_team(&conn, "backend").unwrap();
+        add_team_member(&conn, team.id, bob.id).unwrap();
+
+        assert!(!is_reserved(&conn, "acme-core", None).unwrap());
+        add_reservation(&conn, "acme-*", &Holder::Team("backend".to_string())).unwrap();
+        let id =
+            add_reservation(&conn, "alices-crate", &Holder::User("alice".to_string())).unwrap();
+        assert_eq!(
+            vec![
+                Reservation {
+                    id: id - 1,
+                    crate_pattern: "acme-*".to_string(),
+                    holder: Holder::Team("backend".to_string()),
+                },
+                Reservation {
+                    id,
+                    crate_pattern: "alices-crate".to_string(),
+                    holder: Holder::User("alice".to_string()),
+                },
+            ],
+            list_reservations(&conn).unwrap()
+        );
+
+        assert!(!is_reserved(&conn, "ACME-core", Some(bob.id)).unwrap());
+        assert!(is_reserved(&conn, "acme-core", Some(alice.id)).unwrap());
+        assert!(is_reserved(&conn, "acme-core", None).unwrap());
+        // `-` and `_` count the same, so there's no squatting on `acme_core`.
+        assert!(is_reserved(&conn, "acme_core", Some(alice.id)).unwrap());
+        assert!(!is_reserved(&conn, "Acme_Core", Some(bob.id)).unwrap());
+        assert!(is_reserved(&conn, "alices_crate", Some(carol.id)).unwrap());
+        assert!(!is_reserved(&conn, "alices-crate", Some(alice.id)).unwrap());
+        assert!(is_reserved(&conn, "alices-crate", Some(carol.id)).unwrap());
+        assert!(!is_reserved(&conn, "unreserved", Some(carol.id)).unwrap());
+        // A pattern that'd never match anything isn't saved.
+        assert!(matches!(
+            add_reservation(&conn, "acme-[", &Holder::User("alice".to_string())),
+            Err(Error::InvalidPattern(..))
+        ));
+        assert!(add_grant(&conn, "acme-[", &Grantee::User("alice".to_string())).is_err());
+
+        assert!(remove_reservation(&conn, id).unwrap());
+        assert!(!remove_reservation(&conn, id).unwrap());
+        remove_team(&conn, "backend").unwrap();
+        assert!(list_reservations(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_holder_parse() {
+        assert_eq!(
+            Holder::Team("backend".to_string()),
+            Holder::parse("@backend")
+        );
+        assert_eq!(Holder::User("alice".to_string()), Holder::parse("alice"));
+        assert_eq!("@backend", Holder::parse("@backend").to_string());
+    }
+
+    #[test]
+    fn test_admins() {
+        let conn = get_conn();
+        let alice = find_or_create_user(&conn, "alice").unwrap();
+        find_or_create_user(&conn, "bob").unwrap();
+        assert!(!is_admin(&conn, alice.id).unwrap());
+
+        assert!(set_admin(&conn, "alice", true).unwrap());
+        assert!(is_admin(&conn, alice.id).unwrap());
+        assert_eq!(vec!["alice".to_string()], list_admins(&conn).unwrap());
+        assert!(!set_admin(&conn, "carol", true).unwrap());
+
+        set_admin(&conn, "alice", false).unwrap();
+        assert!(list_admins(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invitations() {
+        let conn = get_conn();
+        let alice = find_or_create_user(&conn, "alice").unwrap();
+        let bob = find_or_create_user(&conn, "bob").unwrap();
+        assert!(invite_crate_owner(&conn, "my-crate", bob.id, Some(alice.id)).unwrap());
+        assert!(!invite_crate_owner(&conn, "My-Crate", bob.id, None).unwrap());
+        invite_crate_owner(&conn, "other-crate", bob.id, None).unwrap();
+
+        let invitations = list_invitations(&conn, bob.id).unwrap();
+        assert_eq!(2, invitations.len());
+        assert_eq!("my-crate", invitations[0].crate_name);
+        assert_eq!(Some("alice".to_string()), invitations[0].inviter);
+        assert_eq!(None, invitations[1].inviter);
+        assert!(list_invitations(&conn, alice.id).unwrap().is_empty());
+        // Invitations don't confer anything by themselves.
+        assert!(!is_crate_owner(&conn, "my-crate", bob.id).unwrap());
+
+        assert!(answer_invitation(&conn, "my-crate", bob.id, true).unwrap());
+        assert!(is_crate_owner(&conn, "my-crate", bob.id).unwrap());
+        assert!(!answer_invitation(&conn, "my-crate", bob.id, true).unwrap());
+
+        assert!(answer_invitation(&conn, "other-crate", bob.id, false).unwrap());
+        assert!(!is_crate_owner(&conn, "other-crate", bob.id).unwrap());
+        assert!(list_invitations(&conn, bob.id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_is_crate_owner() {
+        let conn = get_conn();
+        let alice = find_or_create_user(&conn, "alice").unwrap();
+        let bob = find_or_create_user(&conn, "bob").unwrap();
+        assert!(!has_owners(&conn, "my-crate").unwrap());
+
+        add_crate_owner(&conn, "my-crate", alice.id).unwrap();
+        assert!(has_owners(&conn, "My-Crate").unwrap());
+        assert!(is_crate_owner(&conn, "my-crate", alice.id).unwrap());
+        assert!(!is_crate_owner(&conn, "my-crate", bob.id).unwrap());
+
+        let team = create_team(&conn, "backend").unwrap();
+        add_crate_owner_team(&conn, "my-crate", team.id).unwrap();
+        add_team_member(&conn, team.id, bob.id).unwrap();
+        assert!(is_crate_owner(&conn, "my-crate", bob.id).unwrap());
+        assert!(!is_crate_owner(&conn, "other-crate", bob.id).unwrap());
+    }
+
+    #[test]
+    fn test_login_states() {
+        let conn = get_conn();
+        create_login_state(&conn, "state", "nonce").unwrap();
+        assert_eq!(None, take_login_state(&conn, "other", 60).unwrap());
+        assert_eq!(
+            Some("nonce".to_string()),
+            take_login_state(&conn, "state", 60).unwrap()
+        );
+        // States can only be used once.
+        assert_eq!(None, take_login_state(&conn, "state", 60).unwrap());
+
+        create_login_state(&conn, "state", "nonce").unwrap();
+        assert_eq!(None, take_login_state(&conn, "state", -1).unwrap());
+    }
+
+    #[test]
+    fn test_init_upgrades_old_tokens() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE tokens (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                token TEXT NOT NULL UNIQUE,
+                created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+            );
+            INSERT INTO tokens (name, token) VALUES ('old', 'abc');",
+        )
+        .unwrap();
+        init(&conn).unwrap();
+
+        let (token, hash) = list_token_hashes(&conn).unwrap().remove(0);
+        assert_eq!(Scope::DEFAULT.to_vec(), token.scopes);
+        assert_eq!(hash_token("abc"), hash);
+    }
+
+    #[test]
+    fn test_plaintext_is_not_stored() {
+        let conn = get_conn();
+        set_token(&conn, "ci", "super-secret", Scope::ALL).unwrap();
+        let stored: String = conn
+            .query_row("SELECT token_hash FROM tokens", params![], |row| row.get(0))
+            .unwrap();
+        assert!(!stored.contains("super-secret"));
+    }
+
+    #[test]
+    fn test_set_token_replaces_by_name() {
+        let conn = get_conn();
+        set_token(&conn, "ci", "abc", Scope::ALL).unwrap();
+        set_token(&conn, "ci", "xyz", Scope::ALL).unwrap();
+        assert_eq!(
+            vec![hash_token("xyz")],
+            list_token_hashes(&conn)
+                .unwrap()
+                .into_iter()
+                .map(|(_, secret)| secret)
+                .collect::<Vec<_>>()
+        );
+    }
+}
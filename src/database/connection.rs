@@ -0,0 +1,1064 @@
+//! Talking to whichever database is configured: a SQLite file, or Postgres.
+//!
+//! Queries are written once, in the SQL the two have in common, with `?1`
+//! style params (which are rewritten to `$1` for Postgres). Values go in and
+//! come out as SQLite has them: nulls, integers, reals, text, and blobs.
+
+use bytes::BytesMut;
+use postgres::error::SqlState;
+use postgres::types::{IsNull, Type};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Postgres(#[from] postgres::Error),
+    #[error("Query returned no rows")]
+    NoRows,
+    /// A column held something other than what it was read as.
+    #[error("Column {0}: {1}")]
+    Conversion(usize, String),
+    /// Something only one of the databases can do.
+    #[error("{0}")]
+    Unsupported(&'static str),
+    /// A crate pattern that isn't a glob, and what's wrong with it.
+    #[error("`{0}` isn't a valid pattern: {1}")]
+    InvalidPattern(String, String),
+    /// Setting up TLS for a Postgres connection failed.
+    #[error("TLS: {0}")]
+    Tls(String),
+}
+
+impl Error {
+    /// Whether this came from trying to reuse a name (or other value) that
+    /// must be unique.
+    pub fn is_unique_violation(&self) -> bool {
+        match self {
+            Error::Sqlite(rusqlite::Error::SqliteFailure(e, _)) => {
+                e.code == rusqlite::ErrorCode::ConstraintViolation
+            }
+            Error::Postgres(e) => e.code() == Some(&SqlState::UNIQUE_VIOLATION),
+            _ => false,
+        }
+    }
+}
+
+/// Turns "no rows" into `None`, as rusqlite's extension of the same name does.
+pub trait OptionalExtension<T> {
+    fn optional(self) -> Result<Option<T>>;
+}
+
+impl<T> OptionalExtension<T> for Result<T> {
+    fn optional(self) -> Result<Option<T>> {
+        match self {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::NoRows) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Which database to use.
+#[derive(Clone, PartialEq)]
+pub enum DatabaseUrl {
+    /// A SQLite file, ex: `sqlite:///var/lib/estuary/estuary.db`.
+    Sqlite(PathBuf),
+    /// A Postgres connection string, ex: `postgres://estuary@db/estuary`.
+    Postgres(String),
+}
+
+impl FromStr for DatabaseUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.starts_with("postgres://") || s.starts_with("postgresql://") {
+            return Ok(DatabaseUrl::Postgres(s.to_string()));
+        }
+        match s
+            .strip_prefix("sqlite://")
+            .or_else(|| s.strip_prefix("sqlite:"))
+        {
+            Some(path) if !path.is_empty() => Ok(DatabaseUrl::Sqlite(path.into())),
+            _ => Err(format!(
+                "`{}` isn't a database url, ex: `postgres://estuary@db/estuary` or \
+                `sqlite:///var/lib/estuary/estuary.db`",
+                s
+            )),
+        }
+    }
+}
+
+/// How to secure a connection to Postgres, from its url's `sslmode` (and
+/// `sslrootcert`), which mean what they do to libpq: the server's certificate
+/// is only checked for `verify-ca` and `verify-full`, or when there's a root
+/// certificate to check it against.
+///
+/// <https://www.postgresql.org/docs/current/libpq-ssl.html#LIBPQ-SSL-PROTECTION>
+#[derive(Debug, PartialEq)]
+struct PostgresTls {
+    /// The url, with `sslmode` as the client understands it, ex: `require`
+    /// for `verify-full`.
+    url: String,
+    verify_certificate: bool,
+    verify_hostname: bool,
+    root_certificate: Option<PathBuf>,
+}
+
+impl PostgresTls {
+    fn from_url(url: &str) -> Result<PostgresTls> {
+        let (base, query) = url.split_once('?').unwrap_or((url, ""));
+        let (mut mode, mut root_certificate, mut params) = ("prefer", None, vec![]);
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            match param.split_once('=') {
+                Some(("sslmode", value)) => mode = value,
+                Some(("sslrootcert", value)) => root_certificate = Some(PathBuf::from(value)),
+                _ => params.push(param.to_string()),
+            }
+        }
+        let client_mode = match mode {
+            "disable" => "disable",
+            "allow" | "prefer" => "prefer",
+            "require" | "verify-ca" | "verify-full" => "require",
+            _ => return Err(Error::Tls(format!("`{}` isn't an sslmode", mode))),
+        };
+        params.push(format!("sslmode={}", client_mode));
+        Ok(PostgresTls {
+            url: format!("{}?{}", base, params.join("&")),
+            verify_certificate: root_certificate.is_some()
+                || mode == "verify-ca"
+                || mode == "verify-full",
+            verify_hostname: mode == "verify-full",
+            root_certificate,
+        })
+    }
+
+    fn connect(&self) -> Result<postgres::Client> {
+        let mut builder = native_tls::TlsConnector::builder();
+        builder
+            .danger_accept_invalid_certs(!self.verify_certificate)
+            .danger_accept_invalid_hostnames(!self.verify_hostname);
+        if let Some(ref path) = self.root_certificate {
+            let pem = std::fs::read(path)
+                .map_err(|e| Error::Tls(format!("can't read `{}`: {}", path.display(), e)))?;
+            let certificate = native_tls::Certificate::from_pem(&pem)
+                .map_err(|e| Error::Tls(format!("`{}`: {}", path.display(), e)))?;
+            builder.add_root_certificate(certificate);
+        }
+        let connector = builder.build().map_err(|e| Error::Tls(e.to_string()))?;
+        let tls = postgres_native_tls::MakeTlsConnector::new(connector);
+        Ok(postgres::Client::connect(&self.url, tls)?)
+    }
+}
+
+// Keeps any password out of the logs.
+impl fmt::Display for DatabaseUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseUrl::Sqlite(path) => write!(f, "{}", path.display()),
+            DatabaseUrl::Postgres(url) => {
+                let (url, query) = url.split_once('?').unwrap_or((url, ""));
+                let (scheme, rest) = url.split_at(url.find("://").map_or(0, |i| i + 3));
+                let authority = rest.split('/').next().unwrap_or(rest);
+                match authority
+                    .rfind('@')
+                    .and_then(|at| authority[..at].find(':').map(|colon| (colon, &rest[at..])))
+                {
+                    Some((colon, host)) => write!(f, "{}{}:***{}", scheme, &rest[..colon], host)?,
+                    None => write!(f, "{}", url)?,
+                }
+                for (i, param) in query.split('&').filter(|p| !p.is_empty()).enumerate() {
+                    let param = match param.split_once('=') {
+                        Some(("password", _)) => "password=***",
+                        _ => param,
+                    };
+                    write!(f, "{}{}", if i == 0 { '?' } else { '&' }, param)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DatabaseUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+/// A value, as it's sent to or read from the database.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// Something that can be sent as a query param (see [params]).
+pub trait ToSql {
+    fn to_value(&self) -> Value;
+}
+
+/// Something a column can be read as (see [Row::get]).
+pub trait FromSql: Sized {
+    fn from_value(value: Value) -> std::result::Result<Self, String>;
+}
+
+impl<T: ToSql + ?Sized> ToSql for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+}
+
+impl<T: ToSql> ToSql for Option<T> {
+    fn to_value(&self) -> Value {
+        match self {
+            Some(value) => value.to_value(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl ToSql for str {
+    fn to_value(&self) -> Value {
+        Value::Text(self.to_string())
+    }
+}
+
+impl ToSql for String {
+    fn to_value(&self) -> Value {
+        Value::Text(self.clone())
+    }
+}
+
+impl ToSql for bool {
+    fn to_value(&self) -> Value {
+        Value::Integer(*self as i64)
+    }
+}
+
+macro_rules! integer_to_sql {
+    ($($ty:ty),*) => {
+        $(impl ToSql for $ty {
+            fn to_value(&self) -> Value {
+                Value::Integer(*self as i64)
+            }
+        })*
+    };
+}
+
+integer_to_sql!(i32, i64, u32, u64, usize);
+
+impl<T: FromSql> FromSql for Option<T> {
+    fn from_value(value: Value) -> std::result::Result<Self, String> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl FromSql for String {
+    fn from_value(value: Value) -> std::result::Result<Self, String> {
+        match value {
+            Value::Text(s) => Ok(s),
+            value => Err(format!("expected text, got {:?}", value)),
+        }
+    }
+}
+
+impl FromSql for bool {
+    fn from_value(value: Value) -> std::result::Result<Self, String> {
+        i64::from_value(value).map(|i| i != 0)
+    }
+}
+
+impl FromSql for i64 {
+    fn from_value(value: Value) -> std::result::Result<Self, String> {
+        match value {
+            Value::Integer(i) => Ok(i),
+            value => Err(format!("expected an integer, got {:?}", value)),
+        }
+    }
+}
+
+macro_rules! integer_from_sql {
+    ($($ty:ty),*) => {
+        $(impl FromSql for $ty {
+            fn from_value(value: Value) -> std::result::Result<Self, String> {
+                let i = i64::from_value(value)?;
+                <$ty>::try_from(i).map_err(|_| format!("{} is out of range", i))
+            }
+        })*
+    };
+}
+
+integer_from_sql!(i32, u32, u64, usize);
+
+/// Query params, ex: `params![crate_name, version]`.
+macro_rules! params {
+    () => {
+        &[] as &[&dyn $crate::database::ToSql]
+    };
+    ($($param:expr),+ $(,)?) => {
+        &[$(&$param as &dyn $crate::database::ToSql),+] as &[&dyn $crate::database::ToSql]
+    };
+}
+
+pub(crate) use params;
+
+/// A row of results.
+pub struct Row {
+    values: Vec<Value>,
+}
+
+impl Row {
+    /// The column at `idx` (from 0).
+    pub fn get<T: FromSql>(&self, idx: usize) -> Result<T> {
+        let value = self
+            .values
+            .get(idx)
+            .cloned()
+            .ok_or_else(|| Error::Conversion(idx, "no such column".to_string()))?;
+        T::from_value(value).map_err(|e| Error::Conversion(idx, e))
+    }
+}
+
+/// A connection to the database, and how many transactions it has open.
+pub struct Connection(Inner, Cell<usize>);
+
+enum Inner {
+    Sqlite(rusqlite::Connection),
+    // Queries don't need the connection to be `mut` for SQLite, so nor do
+    // they here.
+    Postgres(RefCell<postgres::Client>),
+}
+
+impl Connection {
+    pub fn open(url: &DatabaseUrl) -> Result<Connection> {
+        Ok(Connection(
+            match url {
+                DatabaseUrl::Sqlite(path) => Inner::Sqlite(rusqlite::Connection::open(path)?),
+                DatabaseUrl::Postgres(url) => {
+                    Inner::Postgres(RefCell::new(PostgresTls::from_url(url)?.connect()?))
+                }
+            },
+            Cell::new(0),
+        ))
+    }
+
+    /// A connection that can only read, for looking at a database without
+    /// changing it. A SQLite file that isn't there isn't created.
+    pub fn open_read_only(url: &DatabaseUrl) -> Result<Connection> {
+        let inner = match url {
+            DatabaseUrl::Sqlite(path) => Inner::Sqlite(rusqlite::Connection::open_with_flags(
+                path,
+                rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY
+                    | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+            )?),
+            DatabaseUrl::Postgres(url) => {
+                let mut client = PostgresTls::from_url(url)?.connect()?;
+                client.batch_execute("SET SESSION CHARACTERISTICS AS TRANSACTION READ ONLY")?;
+                Inner::Postgres(RefCell::new(client))
+            }
+        };
+        Ok(Connection(inner, Cell::new(0)))
+    }
+
+    /// A SQLite database that only lasts as long as the connection.
+    pub fn open_in_memory() -> Result<Connection> {
+        Ok(Connection(
+            Inner::Sqlite(rusqlite::Connection::open_in_memory()?),
+            Cell::new(0),
+        ))
+    }
+
+    pub fn is_postgres(&self) -> bool {
+        matches!(self.0, Inner::Postgres(_))
+    }
+
+    /// Run one or more statements (separated by `;`), without params.
+    pub fn execute_batch(&self, sql: &str) -> Result<()> {
+        match self.0 {
+            Inner::Sqlite(ref conn) => conn.execute_batch(sql)?,
+            Inner::Postgres(ref client) => client.borrow_mut().batch_execute(sql)?,
+        }
+        Ok(())
+    }
+
+    /// Run a statement, giving how many rows it changed.
+    pub fn execute(&self, sql: &str, params: &[&dyn ToSql]) -> Result<usize> {
+        match self.0 {
+            Inner::Sqlite(ref conn) => Ok(conn
+                .prepare_cached(sql)?
+                .execute(rusqlite::params_from_iter(sqlite_params(params)))?),
+            Inner::Postgres(ref client) => {
+                let params = postgres_params(params);
+                Ok(client
+                    .borrow_mut()
+                    .execute(postgres_sql(sql).as_ref(), &postgres_param_refs(&params))?
+                    as usize)
+            }
+        }
+    }
+
+    /// Run a query, passing each row of results through `f`.
+    pub fn query_map<T, F>(&self, sql: &str, params: &[&dyn ToSql], f: F) -> Result<Vec<T>>
+    where
+        F: FnMut(&Row) -> Result<T>,
+    {
+        self.rows(sql, params)?.iter().map(f).collect()
+    }
+
+    /// Run a query, passing the first row of results through `f`.
+    ///
+    /// Fails with [Error::NoRows] when there aren't any.
+    pub fn query_row<T, F>(&self, sql: &str, params: &[&dyn ToSql], f: F) -> Result<T>
+    where
+        F: FnOnce(&Row) -> Result<T>,
+    {
+        match self.rows(sql, params)?.first() {
+            Some(row) => f(row),
+            None => Err(Error::NoRows),
+        }
+    }
+
+    fn rows(&self, sql: &str, params: &[&dyn ToSql]) -> Result<Vec<Row>> {
+        match self.0 {
+            Inner::Sqlite(ref conn) => {
+                let mut stmt = conn.prepare_cached(sql)?;
+                let columns = stmt.column_count();
+                let mut rows = stmt.query(rusqlite::params_from_iter(sqlite_params(params)))?;
+                let mut results = vec![];
+                while let Some(row) = rows.next()? {
+                    results.push(sqlite_row(row, columns)?);
+                }
+                Ok(results)
+            }
+            Inner::Postgres(ref client) => {
+                let params = postgres_params(params);
+                client
+                    .borrow_mut()
+                    .query(postgres_sql(sql).as_ref(), &postgres_param_refs(&params))?
+                    .iter()
+                    .map(postgres_row)
+                    .collect()
+            }
+        }
+    }
+
+    /// Start a transaction, which is rolled back unless it's committed.
+    ///
+    /// Inside another transaction, this is a savepoint instead: committing it
+    /// leaves its changes to the outer transaction, and rolling it back only
+    /// undoes its own.
+    pub fn transaction(&self) -> Result<Transaction<'_>> {
+        self.begin("BEGIN")
+    }
+
+    fn begin(&self, sql: &str) -> Result<Transaction<'_>> {
+        let depth = self.1.get();
+        if depth == 0 {
+            self.execute_batch(sql)?;
+        } else {
+            self.execute_batch(&format!("SAVEPOINT sp{}", depth))?;
+        }
+        self.1.set(depth + 1);
+        Ok(Transaction {
+            conn: self,
+            depth,
+            finished: false,
+        })
+    }
+
+    /// Start a transaction that first waits for any other connection's
+    /// exclusive transaction to finish, and keeps others waiting on it.
+    pub fn exclusive_transaction(&self) -> Result<Transaction<'_>> {
+        match self.0 {
+            Inner::Sqlite(_) => self.begin("BEGIN IMMEDIATE"),
+            Inner::Postgres(_) => {
+                let tx = self.transaction()?;
+                // Any number will do, so long as it's always the same one.
+                tx.execute_batch("SELECT pg_advisory_xact_lock(7135278571)")?;
+                Ok(tx)
+            }
+        }
+    }
+
+    /// Copy the database to a new SQLite file at `dest`, using SQLite's online
+    /// backup API so the copy is consistent without stopping anyone else
+    /// writing to it.
+    ///
+    /// It's copied a few pages at a time, starting over if another connection
+    /// writes in between.
+    pub fn backup(&self, dest: &Path) -> Result<()> {
+        match self.0 {
+            Inner::Sqlite(ref conn) => {
+                let mut dest = rusqlite::Connection::open(dest)?;
+                let backup = rusqlite::backup::Backup::new(conn, &mut dest)?;
+                backup.run_to_completion(BACKUP_PAGES, BACKUP_PAUSE, None)?;
+                Ok(())
+            }
+            Inner::Postgres(_) => Err(Error::Unsupported(
+                "Postgres databases are backed up with `pg_dump`, not Estuary.",
+            )),
+        }
+    }
+}
+
+/// How many pages to copy at a time while backing up.
+const BACKUP_PAGES: std::os::raw::c_int = 1024;
+
+/// How long to leave the database to others between each lot of pages.
+const BACKUP_PAUSE: Duration = Duration::from_millis(10);
+
+/// A transaction on a [Connection], which it derefs to.
+pub struct Transaction<'a> {
+    conn: &'a Connection,
+    /// How many transactions were already open, so zero unless this is a
+    /// savepoint.
+    depth: usize,
+    finished: bool,
+}
+
+impl Transaction<'_> {
+    pub fn commit(mut self) -> Result<()> {
+        self.finished = true;
+        self.conn.1.set(self.depth);
+        match self.depth {
+            0 => self.conn.execute_batch("COMMIT"),
+            depth => self.conn.execute_batch(&format!("RELEASE sp{}", depth)),
+        }
+    }
+}
+
+impl Deref for Transaction<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.conn.1.set(self.depth);
+            let sql = match self.depth {
+                0 => "ROLLBACK".to_string(),
+                depth => format!("ROLLBACK TO sp{0}; RELEASE sp{0}", depth),
+            };
+            if let Err(e) = self.conn.execute_batch(&sql) {
+                log::error!("Couldn't roll back a transaction: {}", e);
+            }
+        }
+    }
+}
+
+/// How SQLite keeps its journal (`PRAGMA journal_mode`).
+///
+/// <https://www.sqlite.org/pragma.html#pragma_journal_mode>
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JournalMode {
+    /// A rollback journal, deleted after each transaction (SQLite's default).
+    /// Readers wait on writers.
+    Delete,
+    Truncate,
+    Persist,
+    /// A write-ahead log, so reads carry on while something's being written.
+    /// The database can't be on a network filesystem.
+    Wal,
+}
+
+impl JournalMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Delete => "DELETE",
+            Self::Truncate => "TRUNCATE",
+            Self::Persist => "PERSIST",
+            Self::Wal => "WAL",
+        }
+    }
+}
+
+impl FromStr for JournalMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "delete" => Ok(Self::Delete),
+            "truncate" => Ok(Self::Truncate),
+            "persist" => Ok(Self::Persist),
+            "wal" => Ok(Self::Wal),
+            _ => Err(format!("unknown journal mode `{}`", s)),
+        }
+    }
+}
+
+/// How often SQLite waits for writes to reach the disk (`PRAGMA synchronous`).
+///
+/// <https://www.sqlite.org/pragma.html#pragma_synchronous>
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Synchronous {
+    Off,
+    /// Enough not to be corrupted by a crash, in WAL mode, though the last
+    /// few commits might be lost to a power cut.
+    Normal,
+    /// On every commit (SQLite's default).
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Off => "OFF",
+            Self::Normal => "NORMAL",
+            Self::Full => "FULL",
+            Self::Extra => "EXTRA",
+        }
+    }
+}
+
+impl FromStr for Synchronous {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "normal" => Ok(Self::Normal),
+            "full" => Ok(Self::Full),
+            "extra" => Ok(Self::Extra),
+            _ => Err(format!("unknown synchronous setting `{}`", s)),
+        }
+    }
+}
+
+/// SQLite settings for each connection a [Manager] opens. Postgres has no use
+/// for them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pragmas {
+    pub journal_mode: JournalMode,
+    pub synchronous: Synchronous,
+}
+
+/// What suits a registry with several publishes going at once.
+impl Default for Pragmas {
+    fn default() -> Pragmas {
+        Pragmas {
+            journal_mode: JournalMode::Wal,
+            synchronous: Synchronous::Normal,
+        }
+    }
+}
+
+/// Opens [Connection]s for a pool.
+#[derive(Debug)]
+pub struct Manager {
+    pub url: DatabaseUrl,
+    /// How long to wait on another connection's lock: SQLite's busy timeout,
+    /// or Postgres' `lock_timeout`.
+    pub busy_timeout: Duration,
+    pub pragmas: Pragmas,
+}
+
+impl r2d2::ManageConnection for Manager {
+    type Connection = Connection;
+    type Error = Error;
+
+    fn connect(&self) -> Result<Connection> {
+        let conn = Connection::open(&self.url)?;
+        match conn.0 {
+            Inner::Sqlite(ref sqlite) => {
+                sqlite.busy_timeout(self.busy_timeout)?;
+                // The journal mode sticks to the file, but switching to it
+                // again is a no-op. It gives back the mode, so can't be run
+                // as a batch.
+                let journal_mode = format!(
+                    "PRAGMA journal_mode = {}",
+                    self.pragmas.journal_mode.as_str()
+                );
+                sqlite.query_row(&journal_mode, [], |_| Ok(()))?;
+                sqlite.execute_batch(&format!(
+                    "PRAGMA synchronous = {}",
+                    self.pragmas.synchronous.as_str()
+                ))?;
+            }
+            Inner::Postgres(_) => conn.execute_batch(&format!(
+                "SET lock_timeout = {}",
+                self.busy_timeout.as_millis()
+            ))?,
+        }
+        Ok(conn)
+    }
+
+    fn is_valid(&self, conn: &mut Connection) -> Result<()> {
+        conn.execute_batch("SELECT 1")
+    }
+
+    fn has_broken(&self, conn: &mut Connection) -> bool {
+        match conn.0 {
+            Inner::Sqlite(_) => false,
+            Inner::Postgres(ref client) => client.borrow().is_closed(),
+        }
+    }
+}
+
+fn sqlite_params(params: &[&dyn ToSql]) -> Vec<rusqlite::types::Value> {
+    use rusqlite::types::Value as V;
+    params
+        .iter()
+        .map(|param| match param.to_value() {
+            Value::Null => V::Null,
+            Value::Integer(i) => V::Integer(i),
+            Value::Real(f) => V::Real(f),
+            Value::Text(s) => V::Text(s),
+            Value::Blob(b) => V::Blob(b),
+        })
+        .collect()
+}
+
+fn sqlite_row(row: &rusqlite::Row, columns: usize) -> Result<Row> {
+    use rusqlite::types::ValueRef;
+    let values = (0..columns)
+        .map(|i| {
+            Ok(match row.get_ref(i)? {
+                ValueRef::Null => Value::Null,
+                ValueRef::Integer(i) => Value::Integer(i),
+                ValueRef::Real(f) => Value::Real(f),
+                ValueRef::Text(s) => Value::Text(String::from_utf8_lossy(s).into_owned()),
+                ValueRef::Blob(b) => Value::Blob(b.to_vec()),
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Row { values })
+}
+
+/// A param, converted to whichever type Postgres decided it should be.
+#[derive(Debug)]
+struct PostgresParam(Value);
+
+type BoxError = Box<dyn std::error::Error + Sync + Send>;
+
+impl postgres::types::ToSql for PostgresParam {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> std::result::Result<IsNull, BoxError> {
+        match (&self.0, ty) {
+            (Value::Null, _) => Ok(IsNull::Yes),
+            (Value::Integer(i), &Type::BOOL) => (*i != 0).to_sql(ty, out),
+            (Value::Integer(i), &Type::INT2) => i16::try_from(*i)?.to_sql(ty, out),
+            (Value::Integer(i), &Type::INT4) => i32::try_from(*i)?.to_sql(ty, out),
+            (Value::Integer(i), &Type::INT8) => i.to_sql(ty, out),
+            (Value::Integer(i), _) => i.to_string().as_str().to_sql(ty, out),
+            (Value::Real(f), &Type::FLOAT4) => (*f as f32).to_sql(ty, out),
+            (Value::Real(f), _) => f.to_sql(ty, out),
+            (Value::Text(s), _) => s.as_str().to_sql(ty, out),
+            (Value::Blob(b), _) => b.as_slice().to_sql(ty, out),
+        }
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+
+    postgres::types::to_sql_checked!();
+}
+
+fn postgres_params(params: &[&dyn ToSql]) -> Vec<PostgresParam> {
+    params
+        .iter()
+        .map(|param| PostgresParam(param.to_value()))
+        .collect()
+}
+
+fn postgres_param_refs(params: &[PostgresParam]) -> Vec<&(dyn postgres::types::ToSql + Sync)> {
+    params
+        .iter()
+        .map(|param| param as &(dyn postgres::types::ToSql + Sync))
+        .collect()
+}
+
+fn postgres_row(row: &postgres::Row) -> Result<Row> {
+    let values = row
+        .columns()
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let value = match *column.type_() {
+                Type::BOOL => row.try_get::<_, Option<bool>>(i)?.map(Value::from),
+                Type::INT2 => row.try_get::<_, Option<i16>>(i)?.map(Value::from),
+                Type::INT4 => row.try_get::<_, Option<i32>>(i)?.map(Value::from),
+                Type::INT8 => row.try_get::<_, Option<i64>>(i)?.map(Value::Integer),
+                Type::FLOAT4 => row.try_get::<_, Option<f32>>(i)?.map(Value::from),
+                Type::FLOAT8 => row.try_get::<_, Option<f64>>(i)?.map(Value::Real),
+                Type::BYTEA => row.try_get::<_, Option<Vec<u8>>>(i)?.map(Value::Blob),
+                _ => row.try_get::<_, Option<String>>(i)?.map(Value::Text),
+            };
+            Ok(value.unwrap_or(Value::Null))
+        })
+        .collect::<Result<_>>()?;
+    Ok(Row { values })
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Integer(b as i64)
+    }
+}
+
+impl From<i16> for Value {
+    fn from(i: i16) -> Self {
+        Value::Integer(i.into())
+    }
+}
+
+impl From<i32> for Value {
+    fn from(i: i32) -> Self {
+        Value::Integer(i.into())
+    }
+}
+
+impl From<f32> for Value {
+    fn from(f: f32) -> Self {
+        Value::Real(f.into())
+    }
+}
+
+/// `sql` with its `?1` params written as `$1`, the way Postgres has them.
+fn postgres_sql(sql: &str) -> Cow<'_, str> {
+    if !sql.contains('?') {
+        return Cow::Borrowed(sql);
+    }
+    let mut out = String::with_capacity(sql.len());
+    let mut quoted = false;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => quoted = !quoted,
+            '?' if !quoted && chars.peek().is_some_and(char::is_ascii_digit) => {
+                out.push('$');
+                continue;
+            }
+            _ => {}
+        }
+        out.push(c);
+    }
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_postgres_sql() {
+        assert_eq!(
+            "SELECT id FROM users WHERE login = $1 AND id <> $12",
+            postgres_sql("SELECT id FROM users WHERE login = ?1 AND id <> ?12")
+        );
+        assert_eq!("SELECT '?1' WHERE $1", postgres_sql("SELECT '?1' WHERE ?1"));
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let data_root = crate::test_helpers::get_data_root();
+        let url = DatabaseUrl::Sqlite(data_root.path().join("estuary.db"));
+        // A file that isn't there isn't created.
+        assert!(Connection::open_read_only(&url).is_err());
+        assert!(!data_root.path().join("estuary.db").exists());
+
+        Connection::open(&url)
+            .unwrap()
+            .execute_batch("CREATE TABLE things (id INTEGER)")
+            .unwrap();
+        let conn = Connection::open_read_only(&url).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM things", &[], |row| row.get(0))
+            .unwrap();
+        assert_eq!(0, count);
+        assert!(conn
+            .execute("INSERT INTO things (id) VALUES (1)", &[])
+            .is_err());
+    }
+
+    #[test]
+    fn test_database_url() {
+        assert_eq!(
+            Ok(DatabaseUrl::Sqlite("/var/lib/estuary/estuary.db".into())),
+            "sqlite:///var/lib/estuary/estuary.db".parse()
+        );
+        assert_eq!(
+            Ok(DatabaseUrl::Sqlite("estuary.db".into())),
+            "sqlite:estuary.db".parse()
+        );
+        let url: DatabaseUrl = "postgres://estuary:hunter2@db:5432/estuary"
+            .parse()
+            .unwrap();
+        assert_eq!("postgres://estuary:***@db:5432/estuary", url.to_string());
+        let url: DatabaseUrl = "postgresql://db/estuary?user=estuary".parse().unwrap();
+        assert_eq!("postgresql://db/estuary?user=estuary", url.to_string());
+        let url: DatabaseUrl =
+            "postgres://db/estuary?user=estuary&password=hunter2&sslmode=require"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            "postgres://db/estuary?user=estuary&password=***&sslmode=require",
+            url.to_string()
+        );
+        assert!("/var/lib/estuary/estuary.db"
+            .parse::<DatabaseUrl>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_postgres_tls() {
+        let tls = PostgresTls::from_url("postgres://estuary@db/estuary").unwrap();
+        assert_eq!(
+            PostgresTls {
+                url: "postgres://estuary@db/estuary?sslmode=prefer".to_string(),
+                verify_certificate: false,
+                verify_hostname: false,
+                root_certificate: None,
+            },
+            tls
+        );
+
+        let tls = PostgresTls::from_url(
+            "postgres://db/estuary?user=estuary&sslmode=verify-full&sslrootcert=/etc/ca.pem",
+        )
+        .unwrap();
+        assert_eq!(
+            PostgresTls {
+                url: "postgres://db/estuary?user=estuary&sslmode=require".to_string(),
+                verify_certificate: true,
+                verify_hostname: true,
+                root_certificate: Some("/etc/ca.pem".into()),
+            },
+            tls
+        );
+
+        let tls = PostgresTls::from_url("postgres://db/estuary?sslmode=verify-ca").unwrap();
+        assert!(tls.verify_certificate && !tls.verify_hostname);
+        let tls = PostgresTls::from_url("postgres://db/estuary?sslmode=disable").unwrap();
+        assert_eq!("postgres://db/estuary?sslmode=disable", tls.url);
+        assert!(PostgresTls::from_url("postgres://db/estuary?sslmode=always").is_err());
+    }
+
+    #[test]
+    fn test_backup() {
+        let data_root = crate::test_helpers::get_data_root();
+        let conn =
+            Connection::open(&DatabaseUrl::Sqlite(data_root.path().join("estuary.db"))).unwrap();
+        conn.execute_batch("CREATE TABLE t (x TEXT); INSERT INTO t VALUES ('abc');")
+            .unwrap();
+        let dest = data_root.path().join("backup.db");
+        conn.backup(&dest).unwrap();
+
+        let copy = Connection::open(&DatabaseUrl::Sqlite(dest)).unwrap();
+        let x: String = copy
+            .query_row("SELECT x FROM t", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!("abc", x);
+    }
+
+    #[test]
+    fn test_values() {
+        let conn = Connection::open_in_memory().unwrap();
+        let row = conn
+            .query_row(
+                "SELECT ?1, ?2, ?3, ?4",
+                params!["abc", 5u64, true, None::<String>],
+                |row| {
+                    Ok((
+                        row.get::<String>(0)?,
+                        row.get::<u64>(1)?,
+                        row.get::<bool>(2)?,
+                        row.get::<Option<String>>(3)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(("abc".to_string(), 5, true, None), row);
+        assert!(conn
+            .query_row("SELECT -1", params![], |row| row.get::<u64>(0))
+            .is_err());
+        assert!(matches!(
+            conn.query_row("SELECT 1 WHERE 0", params![], |row| row.get::<i64>(0)),
+            Err(Error::NoRows)
+        ));
+    }
+
+    #[test]
+    fn test_transaction_rolls_back() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (x INTEGER)").unwrap();
+        {
+            let tx = conn.transaction().unwrap();
+            tx.execute("INSERT INTO t (x) VALUES (?1)", params![1])
+                .unwrap();
+        }
+        let tx = conn.transaction().unwrap();
+        tx.execute("INSERT INTO t (x) VALUES (?1)", params![2])
+            .unwrap();
+        tx.commit().unwrap();
+        assert_eq!(
+            vec![2],
+            conn.query_map("SELECT x FROM t", params![], |row| row.get::<i64>(0))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_nested_transactions() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (x INTEGER)").unwrap();
+        let tx = conn.transaction().unwrap();
+        tx.execute("INSERT INTO t (x) VALUES (?1)", params![1])
+            .unwrap();
+        {
+            // Only what the inner one did is undone.
+            let inner = tx.transaction().unwrap();
+            inner
+                .execute("INSERT INTO t (x) VALUES (?1)", params![2])
+                .unwrap();
+        }
+        let inner = tx.transaction().unwrap();
+        inner
+            .execute("INSERT INTO t (x) VALUES (?1)", params![3])
+            .unwrap();
+        inner.commit().unwrap();
+        drop(tx);
+        // Nor is anything kept unless the outer one commits.
+        assert!(conn
+            .query_map("SELECT x FROM t", params![], |row| row.get::<i64>(0))
+            .unwrap()
+            .is_empty());
+
+        let tx = conn.transaction().unwrap();
+        tx.execute("INSERT INTO t (x) VALUES (?1)", params![1])
+            .unwrap();
+        let inner = tx.transaction().unwrap();
+        inner
+            .execute("INSERT INTO t (x) VALUES (?1)", params![3])
+            .unwrap();
+        inner.commit().unwrap();
+        tx.commit().unwrap();
+        assert_eq!(
+            vec![1, 3],
+            conn.query_map("SELECT x FROM t ORDER BY x", params![], |row| row
+                .get::<i64>(0))
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,67 @@
+//! Deleting a version outright, for when yanking it isn't enough (a yanked
+//! version's crate file can still be downloaded).
+//!
+//! The version is taken out of the index and the database first, and then its
+//! crate file is deleted from the store, unless another version's file is
+//! stored under the same digest.
+//!
+//! A tombstone is left in the database, so the same version can't be
+//! published again: builds that had the old one would get something else
+//! under the same number.
+
+use crate::database::{self, Connection};
+use crate::errors::EstuaryError;
+use crate::package_index::{Author, PackageIndex, PackageVersion};
+use crate::storage::{self, CrateStore};
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+/// A version taken out of the index and the database.
+#[derive(Debug, PartialEq)]
+pub struct Removed {
+    /// What the index had for it.
+    pub pkg: PackageVersion,
+    /// Where in the store its crate file may be, that nothing else needs.
+    pub keys: Vec<String>,
+}
+
+/// Take a version out of the index (crediting the commit to `author`) and out
+/// of the database, leaving a tombstone saying why (the `reason`, if there's
+/// one) and its crate file for [delete_files].
+///
+/// Gives `None` when the index doesn't have the version.
+pub fn remove_version(
+    index: &PackageIndex,
+    conn: &Connection,
+    crate_name: &str,
+    version: &semver::Version,
+    reason: Option<&str>,
+    author: &Author,
+) -> Result<Option<Removed>> {
+    let pkg = match index.delete_version(crate_name, version, author)? {
+        Some(pkg) => pkg,
+        None => return Ok(None),
+    };
+    database::add_tombstone(conn, &pkg.name, &version.to_string(), &pkg.cksum, reason)?;
+    let digest = database::delete_version(conn, &pkg.name, &version.to_string())?;
+    // The search has the newest version's metadata, or nothing once it's gone.
+    if index.find_crate_name(&pkg.name)?.is_none() {
+        database::remove_from_search(conn, &pkg.name)?;
+    } else {
+        database::update_search(conn, &pkg.name)?;
+    }
+    // Files stored before they were kept by digest are only under the old key.
+    let mut keys = vec![storage::get_crate_file_key(&pkg.name, version)];
+    if let Some(digest) = digest {
+        keys.push(storage::get_blob_key(&digest));
+    }
+    Ok(Some(Removed { pkg, keys }))
+}
+
+/// Delete the crate file of a version that's been removed.
+pub async fn delete_files(store: &dyn CrateStore, removed: &Removed) -> Result<()> {
+    for key in &removed.keys {
+        store.delete(key).await?;
+    }
+    Ok(())
+}
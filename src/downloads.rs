@@ -0,0 +1,95 @@
+//! Counting crate downloads, by version and day.
+//!
+//! Downloads are tallied in memory and written to the database in batches
+//! (see [DownloadCounter::flush]), so serving a crate file doesn't mean
+//! waiting on a write.
+
+use crate::database::{self, Connection};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+/// How often the server writes out what it's counted.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The downloads counted since the last flush.
+#[derive(Debug, Default)]
+pub struct DownloadCounter {
+    /// Keyed by crate name, version, and day (as `YYYY-MM-DD`, in UTC).
+    pending: Mutex<HashMap<(String, String, String), u64>>,
+}
+
+impl DownloadCounter {
+    /// Count a download of `version` of `crate_name`, today.
+    pub fn count(&self, crate_name: &str, version: &str) {
+        let date = OffsetDateTime::now_utc().date().to_string();
+        *self
+            .pending
+            .lock()
+            .unwrap()
+            .entry((crate_name.to_string(), version.to_string(), date))
+            .or_default() += 1;
+    }
+
+    /// Add what's been counted to the database.
+    ///
+    /// If that fails, the counts are kept for the next try.
+    pub fn flush(&self, conn: &Connection) -> Result<(), database::Error> {
+        let batch = std::mem::take(&mut *self.pending.lock().unwrap());
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let downloads = batch
+            .iter()
+            .map(|((crate_name, version, date), count)| database::Downloads {
+                crate_name: crate_name.clone(),
+                version: version.clone(),
+                date: date.clone(),
+                count: *count,
+            })
+            .collect::<Vec<_>>();
+        database::record_downloads(conn, &downloads).inspect_err(|_| {
+            let mut pending = self.pending.lock().unwrap();
+            for (key, count) in batch {
+                *pending.entry(key).or_default() += count;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flush() {
+        let conn = Connection::open_in_memory().unwrap();
+        let counter = DownloadCounter::default();
+        counter.count("my-crate", "0.1.0");
+        counter.count("my-crate", "0.1.0");
+        counter.count("my-crate", "0.2.0");
+
+        // Nowhere to write them yet, so they're kept.
+        assert!(counter.flush(&conn).is_err());
+        database::init(&conn).unwrap();
+        counter.flush(&conn).unwrap();
+        assert_eq!(
+            3,
+            database::count_downloads(&conn, "my-crate", None).unwrap()
+        );
+        assert_eq!(
+            2,
+            database::count_downloads(&conn, "my-crate", Some("0.1.0")).unwrap()
+        );
+
+        // Flushing again doesn't count them twice.
+        counter.flush(&conn).unwrap();
+        counter.count("my-crate", "0.1.0");
+        counter.flush(&conn).unwrap();
+        assert_eq!(
+            4,
+            database::count_downloads(&conn, "my-crate", None).unwrap()
+        );
+    }
+}
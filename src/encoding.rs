@@ -0,0 +1,137 @@
+//! Compression for the larger, highly cacheable responses (index files and the
+//! git ref advertisement).
+//!
+//! The body is encoded according to the client's `Accept-Encoding` header,
+//! preferring zstd over gzip. Clients that don't advertise support for either
+//! get the body as-is.
+
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+/// Encodings we're able to produce, in order of preference.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Zstd,
+    Gzip,
+    Identity,
+}
+
+impl Encoding {
+    fn as_header_value(&self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+            Self::Identity => "identity",
+        }
+    }
+}
+
+/// Pick the best encoding the client says it will accept.
+///
+/// Entries with a quality of zero (ex: `gzip;q=0`) count as *not* accepted.
+pub fn negotiate(accept_encoding: &str) -> Encoding {
+    let accepted: Vec<&str> = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let name = parts.next()?.trim();
+            let rejected = parts.any(|param| {
+                let param = param.trim();
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .map(|q| q <= 0.0)
+                    .unwrap_or(false)
+            });
+            if rejected {
+                None
+            } else {
+                Some(name)
+            }
+        })
+        .collect();
+
+    if accepted.iter().any(|&n| n.eq_ignore_ascii_case("zstd")) {
+        Encoding::Zstd
+    } else if accepted
+        .iter()
+        .any(|&n| n.eq_ignore_ascii_case("gzip") || n == "*")
+    {
+        Encoding::Gzip
+    } else {
+        Encoding::Identity
+    }
+}
+
+fn encode(encoding: Encoding, body: &[u8]) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Zstd => zstd::encode_all(body, 0),
+        Encoding::Gzip => {
+            let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+            enc.write_all(body)?;
+            enc.finish()
+        }
+        Encoding::Identity => Ok(body.to_vec()),
+    }
+}
+
+/// Finish a response, compressing the body if the client allows it.
+pub fn respond(
+    request: &HttpRequest,
+    mut builder: HttpResponseBuilder,
+    body: Vec<u8>,
+) -> std::io::Result<HttpResponse> {
+    let encoding = request
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(negotiate)
+        .unwrap_or(Encoding::Identity);
+
+    builder.header(header::VARY, "Accept-Encoding");
+
+    if encoding == Encoding::Identity {
+        return Ok(builder.body(body));
+    }
+
+    let encoded = encode(encoding, &body)?;
+    Ok(builder
+        .header(header::CONTENT_ENCODING, encoding.as_header_value())
+        .body(encoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Encoding::Identity, negotiate(""));
+        assert_eq!(Encoding::Identity, negotiate("br, deflate"));
+        assert_eq!(Encoding::Gzip, negotiate("deflate, gzip"));
+        assert_eq!(Encoding::Gzip, negotiate("*"));
+        assert_eq!(Encoding::Zstd, negotiate("gzip, zstd"));
+        assert_eq!(Encoding::Gzip, negotiate("gzip, zstd;q=0"));
+        assert_eq!(Encoding::Zstd, negotiate("ZSTD;q=0.5"));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let body = b"{\"name\":\"foo\"}\n".repeat(100);
+
+        let zstd_body = encode(Encoding::Zstd, &body).unwrap();
+        assert_eq!(body, zstd::decode_all(zstd_body.as_slice()).unwrap());
+
+        let gzip_body = encode(Encoding::Gzip, &body).unwrap();
+        let mut decoded = vec![];
+        flate2::read::GzDecoder::new(gzip_body.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(body, decoded);
+    }
+}
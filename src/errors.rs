@@ -1,7 +1,8 @@
 #![cfg(not(tarpaulin_include))]
+#![allow(clippy::upper_case_acronyms)]
 
 use actix_web::dev::HttpResponseBuilder;
-use actix_web::error::{BlockingError, ResponseError};
+use actix_web::error::{BlockingError, PayloadError, ResponseError};
 use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use serde_json::json;
@@ -16,6 +17,16 @@ pub enum ApiError {
     JSON(#[from] serde_json::Error),
     #[error("Package Index failure: `{0}`")]
     PackageIndex(#[from] PackageIndexError),
+    #[error("{0}")]
+    Estuary(#[from] EstuaryError),
+    #[error("Database error: `{0}`")]
+    Database(#[from] crate::database::Error),
+    #[error("Reading the request failed: `{0}`")]
+    Payload(#[from] PayloadError),
+    /// The request was understood, but can't be done, ex: adding an owner who
+    /// doesn't exist.
+    #[error("{0}")]
+    Rejected(String),
 }
 
 /// For the Api Errors, cargo wants them converted to a 200 OK response with a
@@ -42,12 +53,23 @@ pub enum PackageIndexError {
     JSON(#[from] serde_json::Error),
     #[error("Publish failed: `{0}`")]
     Publish(String),
-    #[error("Invalid package name: `{0}`")]
-    InvalidPackageName(String),
+    /// The name, and what's wrong with it.
+    #[error("Invalid package name `{0}`: {1}")]
+    InvalidPackageName(String, String),
     #[error("Glob failed: `{0}`")]
     Glob(#[from] glob::GlobError),
     #[error("Glob pattern failed: `{0}`")]
     GlobPattern(#[from] glob::PatternError),
+    /// A git client sent something that isn't part of the protocol (so far as
+    /// it's been implemented).
+    #[error("Git protocol error: `{0}`")]
+    Protocol(String),
+    /// A key to sign commits with couldn't be read, or couldn't sign.
+    #[error("Signing failed: `{0}`")]
+    Signing(String),
+    /// Another process (the server, say) is changing the index at this path.
+    #[error("`{0}` is in use by another Estuary process (is the server running?)")]
+    Locked(String),
 }
 
 #[derive(Debug, Error)]
@@ -64,6 +86,36 @@ pub enum EstuaryError {
     NotFound,
     #[error("Invalid Version: `{0}`")]
     InvalidVersion(#[from] semver::SemVerError),
+    #[error("Database error: `{0}`")]
+    Database(#[from] crate::database::Error),
+    #[error("Couldn't get a database connection: `{0}`")]
+    Pool(#[from] r2d2::Error),
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    #[error("TLS error: {0}")]
+    TLS(String),
+    #[error("LDAP error: {0}")]
+    Ldap(#[from] ldap3::LdapError),
+    #[error("Single sign-on failed: {0}")]
+    Oidc(#[from] OidcError),
+    #[error("Storage request failed: {0}")]
+    Storage(String),
+    #[error("`{0}` doesn't match its checksum")]
+    Corrupt(String),
+}
+
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error("Identity provider request failed: {0}")]
+    Provider(String),
+    #[error("Invalid ID token: {0}")]
+    InvalidToken(String),
+    #[error("The login expired (or was already used). Please try again.")]
+    UnknownState,
+    #[error("The login `{0}` is already taken by another user.")]
+    LoginTaken(String),
+    #[error("`{0}` isn't allowed to log in to this registry.")]
+    NotAllowed(String),
 }
 
 impl<T> From<BlockingError<T>> for EstuaryError
@@ -82,6 +134,13 @@ impl ResponseError for EstuaryError {
     fn status_code(&self) -> StatusCode {
         match self {
             EstuaryError::NotFound => StatusCode::NOT_FOUND,
+            EstuaryError::Oidc(OidcError::Provider(_)) => StatusCode::BAD_GATEWAY,
+            EstuaryError::Oidc(OidcError::NotAllowed(_)) => StatusCode::FORBIDDEN,
+            EstuaryError::Oidc(_) => StatusCode::BAD_REQUEST,
+            EstuaryError::Storage(_) => StatusCode::BAD_GATEWAY,
+            EstuaryError::Database(crate::database::Error::InvalidPattern(..)) => {
+                StatusCode::BAD_REQUEST
+            }
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
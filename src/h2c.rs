@@ -0,0 +1,148 @@
+//! HTTP/2 without TLS ("h2c"), for running behind a proxy that talks HTTP/2
+//! to its upstreams.
+//!
+//! When serving https, HTTP/2 is negotiated via ALPN and needs nothing extra.
+//! Plain http connections are normally always treated as HTTP/1.1, so here we
+//! peek at the first bytes of each connection to spot the HTTP/2 connection
+//! preface (sent by clients with "prior knowledge" that we speak HTTP/2) and
+//! hand the connection to the appropriate protocol handler.
+//!
+//! This matters for cargo's sparse index in particular, where resolving a
+//! large dependency graph means many small requests that can be multiplexed
+//! over a single HTTP/2 connection.
+
+use actix_http::error::DispatchError;
+use actix_http::{Error, HttpService, Protocol, Request, Response};
+use actix_server::Server;
+use actix_service::{map_config, pipeline_factory, IntoServiceFactory, Service, ServiceFactory};
+use actix_web::dev::{AppConfig, MessageBody};
+use actix_web::rt::net::TcpStream;
+use std::fmt;
+use std::time::Duration;
+
+/// The opening bytes of the HTTP/2 connection preface.
+///
+/// HTTP/1.x requests start with a method name, and `PRI` isn't one, so these
+/// few bytes are enough to tell the protocols apart.
+const H2_PREFACE_START: &[u8] = b"PRI ";
+
+/// How long a new connection has to send its first bytes before it's
+/// dropped, the same as actix's own default for an HTTP/1 request's head.
+const SNIFF_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Decide which protocol a connection is using based on its first bytes.
+///
+/// Gives `None` when not enough bytes have arrived to tell yet.
+fn detect_protocol(buf: &[u8]) -> Option<Protocol> {
+    let len = buf.len().min(H2_PREFACE_START.len());
+    if buf[..len] != H2_PREFACE_START[..len] {
+        Some(Protocol::Http1)
+    } else if len == H2_PREFACE_START.len() {
+        Some(Protocol::Http2)
+    } else {
+        None
+    }
+}
+
+/// Wait (up to `timeout`) for enough of the connection's first bytes to pick
+/// a protocol, so an idle connection doesn't hold its slot forever.
+async fn sniff(io: &mut TcpStream, timeout: Duration) -> std::io::Result<Protocol> {
+    actix_web::rt::time::timeout(timeout, sniff_forever(io))
+        .await
+        .map_err(|_| {
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "the connection sent nothing to tell its protocol by",
+            )
+        })?
+}
+
+async fn sniff_forever(io: &mut TcpStream) -> std::io::Result<Protocol> {
+    let mut buf = [0u8; 4];
+    loop {
+        let n = io.peek(&mut buf).await?;
+        if n == 0 {
+            // The client hung up. Let the HTTP/1 dispatcher deal with it.
+            return Ok(Protocol::Http1);
+        }
+        if let Some(proto) = detect_protocol(&buf[..n]) {
+            return Ok(proto);
+        }
+        // Peeking doesn't consume anything, so back off a moment to let the
+        // rest of the bytes arrive instead of spinning.
+        actix_web::rt::time::delay_for(Duration::from_millis(1)).await;
+    }
+}
+
+/// Bind a server that accepts both HTTP/1.1 and cleartext HTTP/2.
+///
+/// `factory` is the same app factory that would be given to
+/// `actix_web::HttpServer::new`.
+#[cfg(not(tarpaulin_include))]
+pub fn bind<F, I, S, B>(factory: F, addr: &str) -> std::io::Result<Server>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S>,
+    S: ServiceFactory<Config = AppConfig, Request = Request> + 'static,
+    S::Error: Into<Error> + 'static,
+    S::InitError: fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service>::Future: 'static,
+    B: MessageBody + 'static,
+{
+    Ok(Server::build()
+        .bind("estuary-h2c", addr, move || {
+            let app = map_config(factory().into_factory(), |_| AppConfig::default());
+            pipeline_factory(|mut io: TcpStream| async move {
+                let proto = sniff(&mut io, SNIFF_TIMEOUT).await?;
+                let peer_addr = io.peer_addr().ok();
+                Ok::<_, DispatchError>((io, proto, peer_addr))
+            })
+            .and_then(HttpService::build().finish(app))
+        })?
+        .run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_protocol() {
+        assert_eq!(
+            Some(Protocol::Http2),
+            detect_protocol(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+        );
+        assert_eq!(Some(Protocol::Http2), detect_protocol(b"PRI "));
+        assert_eq!(
+            Some(Protocol::Http1),
+            detect_protocol(b"GET / HTTP/1.1\r\n")
+        );
+        assert_eq!(Some(Protocol::Http1), detect_protocol(b"PUT"));
+        assert_eq!(None, detect_protocol(b"PR"));
+    }
+
+    #[test]
+    fn test_sniff_timeout() {
+        use actix_web::rt::net::TcpListener;
+        use std::io::Write;
+
+        // The server's own runtime, rather than the one `actix_rt::test` gives.
+        actix_web::rt::System::new("test_sniff_timeout").block_on(async {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let mut listener = TcpListener::from_std(listener).unwrap();
+            let (mut io, _) = listener.accept().await.unwrap();
+
+            // Nothing sent yet.
+            let err = sniff(&mut io, Duration::from_millis(50)).await.unwrap_err();
+            assert_eq!(std::io::ErrorKind::TimedOut, err.kind());
+
+            client.write_all(b"PRI * HTTP/2.0\r\n").unwrap();
+            assert_eq!(
+                Protocol::Http2,
+                sniff(&mut io, Duration::from_secs(5)).await.unwrap()
+            );
+        });
+    }
+}
@@ -1,28 +1,98 @@
+use crate::Settings;
 use actix_web::web;
+pub mod backup;
+pub mod changes;
+pub mod feed;
 pub mod frontend;
 pub mod git;
+pub mod oidc;
 pub mod registry;
+pub mod reservations;
+pub mod sparse;
+pub mod tokens;
+pub mod usage;
+pub mod verify;
 
-pub fn configure_routes(cfg: &mut web::ServiceConfig) {
+/// The most a request may send that's read in whole (a git fetch's
+/// negotiation, say). Publishes are read as they arrive, and held to
+/// `--max-crate-size` instead.
+const MAX_BODY_LEN: usize = 16 * 1024 * 1024;
+
+pub fn configure_routes(cfg: &mut web::ServiceConfig, settings: &Settings) {
+    cfg.app_data(web::PayloadConfig::new(MAX_BODY_LEN));
+    // Registered ahead of the sparse index scope so it isn't mistaken for a
+    // package file.
+    cfg.route("/index/changes", web::get().to(changes::get_changes));
+    cfg.service(feed::get_feed);
+    if settings.index_protocol.git_enabled() {
+        cfg.service(
+            web::scope("/git/index")
+                .service(git::get_info_refs)
+                .service(git::upload_pack),
+        );
+    }
+    if settings.index_protocol.sparse_enabled() {
+        cfg.service(
+            web::scope("/index")
+                .service(sparse::get_config)
+                .service(sparse::get_package_file),
+        );
+    }
     cfg.service(
-        web::scope("/git/index")
-            .service(git::get_info_refs)
-            .service(git::upload_pack),
-    )
-    .service(
         web::scope("/api/v1/crates")
             .service(registry::publish)
             .service(registry::yank)
             .service(registry::unyank)
             .service(registry::download)
+            .service(registry::list_owners)
+            .service(registry::add_owners)
+            .service(registry::remove_owners)
             .service(registry::search),
     )
+    .service(
+        web::scope("/api/v1/me")
+            .service(registry::list_invitations)
+            .service(registry::answer_invitation),
+    )
+    .service(web::scope("/api/v1/tokens").service(tokens::revoke))
+    .service(
+        web::scope("/api/v1/reservations")
+            .service(reservations::list)
+            .service(reservations::add)
+            .service(reservations::remove),
+    )
+    .service(web::scope("/api/v1/usage").service(usage::get_usage))
+    .service(
+        web::scope("/api/v1/verify")
+            .service(verify::get_verify)
+            .service(verify::start_verify),
+    )
+    .service(web::scope("/api/v1/backup").service(backup::get_backup))
     .service(frontend::styles)
     .service(frontend::login)
+    .service(frontend::create_token)
+    .service(frontend::log_in)
+    .service(frontend::logout)
+    .service(frontend::answer_invitation)
+    .service(frontend::sign_up)
+    .service(frontend::user_profile)
+    .service(oidc::login)
+    .service(oidc::callback)
     .service(frontend::landing)
+    .service(frontend::search)
+    .service(frontend::admin)
+    .service(frontend::admin_action)
     .service(
         web::scope("/crates/{crate_name}")
             .route("/versions", web::get().to(frontend::version_list))
+            .route("/stats", web::get().to(frontend::crate_stats))
+            .route("/owners", web::get().to(frontend::crate_owners))
+            .route("/owners", web::post().to(frontend::update_crate_owners))
+            .route("/{version}/files", web::get().to(frontend::crate_files))
+            .route(
+                "/{version}/files/{path:.*}",
+                web::get().to(frontend::crate_files),
+            )
             .route("/{version}", web::get().to(frontend::crate_detail))
             .route("", web::get().to(frontend::crate_detail)),
     );
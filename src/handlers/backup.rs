@@ -0,0 +1,87 @@
+//! A consistent copy of the database, for admins to back up without stopping
+//! the server (as `estuary backup-db` does, but over http).
+//!
+//! Only for SQLite: Postgres has `pg_dump`.
+
+use crate::auth::{self, Scope};
+use crate::database::DatabaseUrl;
+use crate::errors::EstuaryError;
+use crate::storage::{FileBody, TempFile};
+use crate::Settings;
+use actix_http::body::Body;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[get("")]
+pub async fn get_backup(
+    request: HttpRequest,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_token(&request, &settings, Scope::Admin).await {
+        return Ok(resp);
+    }
+    if let DatabaseUrl::Postgres(_) = settings.database_url {
+        return Ok(HttpResponse::NotFound()
+            .body("Postgres databases are backed up with `pg_dump`, not Estuary."));
+    }
+    // The copy is made next to the crate files, then sent from there.
+    let copy = TempFile::create(&settings.crate_dir.join("backups"))?;
+    let path = copy.path().to_path_buf();
+    settings.with_db(move |conn| conn.backup(&path)).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/vnd.sqlite3")
+        .header("content-disposition", "attachment; filename=\"estuary.db\"")
+        .body(Body::from_message(FileBody::temp(copy)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Scope;
+    use crate::database::{self, Connection, DatabaseUrl};
+    use crate::test_helpers;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+
+    #[actix_rt::test]
+    async fn test_backup() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        database::set_token(&conn, "admin", "admin-secret", &[Scope::Admin]).unwrap();
+        database::set_token(&conn, "ci", "ci-secret", &[Scope::Publish]).unwrap();
+        drop(conn);
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let backup = |token: &str| {
+            test::TestRequest::get()
+                .uri("/api/v1/backup")
+                .header("authorization", token)
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, backup("ci-secret")).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let resp = test::call_service(&mut app, backup("admin-secret")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = test::read_body(resp).await;
+        let path = data_root.path().join("backup.db");
+        std::fs::write(&path, &body).unwrap();
+        let copy = Connection::open(&DatabaseUrl::Sqlite(path)).unwrap();
+        assert_eq!(2, database::count_tokens(&copy).unwrap());
+        // The copy sent isn't left behind.
+        assert_eq!(
+            0,
+            std::fs::read_dir(settings.crate_dir.join("backups"))
+                .unwrap()
+                .count()
+        );
+    }
+}
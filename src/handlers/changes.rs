@@ -0,0 +1,118 @@
+//! A feed of changes to the index, for downstream mirrors and caches.
+//!
+//! Rather than re-cloning the index or re-crawling every package file, a
+//! mirror can poll this endpoint with the last commit it saw and only apply
+//! what changed since.
+//!
+//! The response is JSON lines, one object per change, oldest first:
+//!
+//! ```text
+//! {"crate":"foo","version":"0.1.0","op":"publish","timestamp":1608854400,"commit":"d049f6c2..."}
+//! ```
+
+use crate::auth;
+use crate::errors::{EstuaryError, PackageIndexError};
+use crate::package_index::PackageIndex;
+use crate::Settings;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use std::sync::Mutex;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[derive(Deserialize, Debug)]
+pub struct ChangesQuery {
+    /// Only list changes made after this commit.
+    since: Option<String>,
+    /// The maximum number of changes to return.
+    limit: Option<usize>,
+}
+
+pub async fn get_changes(
+    query: web::Query<ChangesQuery>,
+    request: HttpRequest,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_read(&request, &settings).await {
+        return Ok(resp);
+    }
+
+    let index = index.lock().unwrap();
+    let changes = index
+        .get_changes(query.since.as_deref(), query.limit)
+        .map_err(|e| match e {
+            // Either the commit id is garbage, or it's not in our history.
+            PackageIndexError::Git2(_) if query.since.is_some() => EstuaryError::NotFound,
+            _ => e.into(),
+        })?;
+
+    let mut body = String::new();
+    for change in changes {
+        body.push_str(&serde_json::to_string(&change)?);
+        body.push('\n');
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .body(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers;
+    use crate::test_helpers::MY_CRATE_0_1_0;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+
+    #[actix_rt::test]
+    async fn test_changes_after_publish() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::get().uri("/index/changes").to_request();
+        let body = test::read_response(&mut app, req).await;
+        let lines: Vec<serde_json::Value> = std::str::from_utf8(&body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(1, lines.len());
+        assert_eq!("my-crate", lines[0]["crate"]);
+        assert_eq!("publish", lines[0]["op"]);
+    }
+
+    #[actix_rt::test]
+    async fn test_changes_unknown_since_is_not_found() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/index/changes?since=nonsense")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+}
@@ -0,0 +1,170 @@
+//! An Atom feed of the latest publishes, yanks, unyanks, and deletions, for
+//! feed readers (and chat integrations that take feeds).
+//!
+//! Like the changes endpoint, this is built from the index history, so it
+//! covers changes made before the database kept a record of them.
+
+use crate::auth;
+use crate::database;
+use crate::errors::EstuaryError;
+use crate::package_index::{ChangeOp, PackageIndex};
+use crate::Settings;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use askama::Template;
+use std::sync::Mutex;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// How many changes the feed lists.
+const FEED_SIZE: usize = 50;
+
+#[derive(Template)]
+#[template(path = "feed.xml")]
+pub struct FeedTemplate {
+    /// The web UI's title, to tell this registry's feed from others.
+    title: String,
+    base_url: String,
+    /// When the newest entry was made, or the epoch when there are none.
+    updated: String,
+    entries: Vec<FeedEntry>,
+}
+
+pub struct FeedEntry {
+    title: String,
+    /// The crate's page for the version.
+    link: String,
+    commit: String,
+    updated: String,
+    description: Option<String>,
+}
+
+/// An Atom date, ex: `2020-12-25T00:00:00Z`.
+fn rfc3339(timestamp: i64) -> String {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
+
+#[get("/feed.xml")]
+pub async fn get_feed(
+    request: HttpRequest,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> actix_web::Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_frontend(&request, &settings).await {
+        return Ok(resp);
+    }
+
+    let changes = index
+        .lock()
+        .unwrap()
+        .get_recent_changes(FEED_SIZE)
+        .map_err(EstuaryError::from)?;
+    let versions: Vec<_> = changes
+        .iter()
+        .map(|change| (change.name.clone(), change.version.to_string()))
+        .collect();
+    let descriptions = settings
+        .with_db(move |conn| -> Result<Vec<_>, EstuaryError> {
+            let mut descriptions = vec![];
+            for (name, version) in &versions {
+                let metadata = database::find_metadata(conn, name, version)?;
+                descriptions.push(metadata.and_then(|metadata| metadata.description));
+            }
+            Ok(descriptions)
+        })
+        .await?;
+
+    let base_url = settings.base_url.trim_end_matches('/').to_string();
+    let entries = changes
+        .into_iter()
+        .zip(descriptions)
+        .map(|(change, description)| {
+            let done = match change.op {
+                ChangeOp::Publish => "published",
+                ChangeOp::Yank => "yanked",
+                ChangeOp::Unyank => "unyanked",
+                ChangeOp::Delete => "deleted",
+            };
+            FeedEntry {
+                title: format!("{} {} {}", change.name, change.version, done),
+                link: format!("{}/crates/{}/{}", base_url, change.name, change.version),
+                commit: change.commit,
+                updated: rfc3339(change.timestamp),
+                description,
+            }
+        })
+        .collect::<Vec<_>>();
+    let feed = FeedTemplate {
+        title: settings.branding.title.clone(),
+        updated: match entries.first() {
+            Some(entry) => entry.updated.clone(),
+            None => rfc3339(0),
+        },
+        base_url,
+        entries,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/atom+xml")
+        .body(
+            feed.render()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_helpers;
+    use crate::test_helpers::MY_CRATE_0_1_0;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+
+    #[actix_rt::test]
+    async fn test_feed() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/feed.xml").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = test::read_body(resp).await;
+        assert!(!std::str::from_utf8(&body).unwrap().contains("<entry>"));
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+        let req = test::TestRequest::delete()
+            .uri("/api/v1/crates/my-crate/0.1.0/yank")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let req = test::TestRequest::get().uri("/feed.xml").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(
+            "application/atom+xml",
+            resp.headers().get("content-type").unwrap()
+        );
+        let body = test::read_body(resp).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("<title>Estuary</title>"), "{}", body);
+        let yanked = body.find("<title>my-crate 0.1.0 yanked</title>").unwrap();
+        let published = body
+            .find("<title>my-crate 0.1.0 published</title>")
+            .unwrap();
+        assert!(yanked < published, "{}", body);
+        assert!(body.contains(r#"href="http://localhost:7878/crates/my-crate/0.1.0""#));
+    }
+}
@@ -1,10 +1,19 @@
-use crate::errors::{EstuaryError, PackageIndexError};
-use crate::package_index::{Dependency, DependencyKind, PackageIndex, PackageVersion};
-use actix_web::{get, web, HttpRequest, HttpResponse};
+use crate::auth::{self, Scope};
+use crate::database;
+use crate::deletion;
+use crate::errors::{ApiError, EstuaryError, PackageIndexError};
+use crate::handlers::registry;
+use crate::package_index::{
+    normalize_name, Dependency, DependencyKind, PackageIndex, PackageVersion,
+};
+use crate::tarball;
+use crate::Settings;
+use actix_web::http::header;
+use actix_web::{get, post, web, Either, HttpMessage, HttpRequest, HttpResponse};
 use askama::Template;
-use serde::Deserialize;
-use std::sync::Mutex;
 use log::info;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
 
 type Result<T> = std::result::Result<T, EstuaryError>;
 
@@ -19,57 +28,769 @@ pub async fn styles(_req: HttpRequest) -> HttpResponse {
         )))
 }
 
+/// What's shown on every page of the web UI to tell which registry it is.
+#[derive(Clone, Debug)]
+pub struct Branding {
+    /// Shown at the top of every page, and after each page's title.
+    pub title: String,
+    /// The url of an image to show beside the title.
+    pub logo_url: Option<String>,
+    /// Shown in the footer, ahead of Estuary's version.
+    pub footer: Option<String>,
+    /// Shown above everything else, for news like planned downtime.
+    pub banner: Option<String>,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Branding {
+            title: "Estuary".to_string(),
+            logo_url: None,
+            footer: None,
+            banner: None,
+        }
+    }
+}
+
+/// How many crates the landing page lists at a time.
+const LANDING_PAGE_SIZE: usize = 100;
+
+/// How many of the latest publishes the landing page shows.
+const RECENT_PUBLISHES: usize = 10;
+
+/// How many of the most downloaded crates the landing page shows.
+const MOST_DOWNLOADED: usize = 10;
+
 #[derive(Template)]
 #[template(path = "landing.html")]
 pub struct LandingTemplate<'a> {
+    branding: Branding,
     title: &'a str,
     packages: Vec<String>,
+    /// The first letters of the crates' names, each with a link to the
+    /// crates starting with it, and whether it's the one picked.
+    letters: Vec<(String, String, bool)>,
+    /// Each way the list can be sorted, the same way.
+    sorts: Vec<(&'static str, String, bool)>,
+    page: usize,
+    pages: usize,
+    prev: Option<String>,
+    next: Option<String>,
+    /// With the login of whoever published each, if anyone.
+    recent: Vec<(database::Publish, Option<String>)>,
+    /// Crate names, with their downloads of all time.
+    most_downloaded: Vec<(String, u64)>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct LandingQuery {
+    #[serde(default = "default_page")]
+    page: usize,
+    /// Only crates whose names start with this.
+    letter: Option<String>,
+    /// By name, unless asked for otherwise.
+    #[serde(default = "default_landing_sort")]
+    sort: database::SearchSort,
+}
+
+fn default_page() -> usize {
+    1
+}
+
+fn default_landing_sort() -> database::SearchSort {
+    database::SearchSort::Alphabetical
+}
+
+impl LandingQuery {
+    fn url(&self) -> String {
+        // Only fails for things that can't be represented as pairs.
+        format!("/?{}", serde_urlencoded::to_string(self).unwrap())
+    }
 }
 
 #[derive(Template)]
 #[template(path = "login.html")]
 pub struct LoginTemplate<'a> {
+    branding: Branding,
     title: &'a str,
-    token: &'a str,
+    index_url: String,
+    registry_name: String,
+    /// Whether visitors may issue themselves a token from this page.
+    web_tokens: bool,
+    /// Whether there's an identity provider to log in with.
+    oidc: bool,
+    /// Whether there's anyone who could log in with a password.
+    password_login: bool,
+    /// Whether visitors may sign up for an account.
+    signup: bool,
+    /// Whether the user logged in may use the admin page.
+    is_admin: bool,
+    /// Who's logged in, if anyone.
+    user: Option<String>,
+    /// The crates they've been invited to own.
+    invitations: Vec<database::Invitation>,
+    /// A freshly issued token. This is the only time it's ever shown.
+    token: Option<String>,
+    error: Option<String>,
 }
 
 #[derive(Template)]
 #[template(path = "crate_detail.html")]
 pub struct CrateDetailTemplate {
+    branding: Branding,
     title: String,
     pkg: PackageVersion,
-    dev_deps: Vec<Dependency>,
-    non_dev_deps: Vec<Dependency>,
+    dev_deps: Vec<DependencyRow>,
+    non_dev_deps: Vec<DependencyRow>,
     releases: Vec<PackageVersion>,
+    /// Of every version.
+    downloads: u64,
+    /// Of every version, in the last [database::RECENT_DOWNLOAD_DAYS].
+    recent_downloads: u64,
+    recent_download_days: i64,
+    version_downloads: u64,
+    /// What was sent along with the version when it was published (empty for
+    /// those published before the database kept it).
+    metadata: database::CrateMetadata,
+    /// The homepage, repository, and documentation, for those that are web
+    /// pages.
+    links: Vec<(&'static str, String)>,
+    published_at: Option<String>,
+    /// The line to add to `[dependencies]` for this version.
+    dependency: String,
+    /// `default` first, then the rest by name.
+    features: Vec<FeatureRow>,
+}
+
+pub struct FeatureRow {
+    name: String,
+    /// The features and dependencies it turns on.
+    enables: Vec<String>,
+    /// Whether it's turned on by `default`.
+    default: bool,
+}
+
+/// The rows of the features table for `pkg`.
+fn feature_rows(pkg: &PackageVersion) -> Vec<FeatureRow> {
+    let defaults = pkg.features.get("default");
+    let mut rows: Vec<_> = pkg
+        .features
+        .iter()
+        .map(|(name, enables)| FeatureRow {
+            name: name.clone(),
+            enables: enables.clone(),
+            default: defaults.is_some_and(|defaults| defaults.contains(name)),
+        })
+        .collect();
+    rows.sort_by(|a, b| (a.name != "default", &a.name).cmp(&(b.name != "default", &b.name)));
+    rows
+}
+
+/// The ways crates.io's index is named in `registry` (less any trailing `/`).
+const CRATES_IO_INDEXES: &[&str] = &[
+    "https://github.com/rust-lang/crates.io-index",
+    "sparse+https://index.crates.io",
+];
+
+pub struct DependencyRow {
+    dep: Dependency,
+    /// The page of the crate it's on, for those from this registry or
+    /// crates.io.
+    page: Option<String>,
+    /// Its documentation, for those from crates.io.
+    docs: Option<String>,
+}
+
+impl From<Dependency> for DependencyRow {
+    fn from(dep: Dependency) -> Self {
+        // The name may be a rename.
+        let name = dep.package.as_ref().unwrap_or(&dep.name);
+        let (page, docs) = match dep.registry.as_deref() {
+            None => (Some(format!("/crates/{}", name)), None),
+            Some(registry) if CRATES_IO_INDEXES.contains(&registry.trim_end_matches('/')) => (
+                Some(format!("https://crates.io/crates/{}", name)),
+                Some(format!("https://docs.rs/{}", name)),
+            ),
+            Some(_) => (None, None),
+        };
+        DependencyRow { dep, page, docs }
+    }
+}
+
+/// Whether `url` is safe to link to, rather than (say) `javascript:`.
+fn is_web_url(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
 }
 
 #[get("/")]
-pub async fn landing(index: web::Data<Mutex<PackageIndex>>) -> Result<LandingTemplate<'static>> {
-    let index = index.lock().unwrap();
-    let mut names = index.list_crates()?;
-    names.sort();
+pub async fn landing(
+    request: HttpRequest,
+    query: web::Query<LandingQuery>,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, LandingTemplate<'static>>> {
+    if let Err(resp) = auth::authorize_frontend(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+
+    let mut query = query.into_inner();
+    query.page = query.page.max(1);
+    let prefix = query.letter.clone();
+    let sort = query.sort;
+    let offset = (query.page - 1).saturating_mul(LANDING_PAGE_SIZE);
+    let ((packages, total), letters, recent, most_downloaded) = settings
+        .with_db(move |conn| {
+            Ok::<_, database::Error>((
+                database::list_crates_page(
+                    conn,
+                    prefix.as_deref(),
+                    sort,
+                    offset,
+                    LANDING_PAGE_SIZE,
+                )?,
+                database::list_crate_letters(conn)?,
+                database::list_recent_publishes(conn, RECENT_PUBLISHES)?,
+                database::list_most_downloaded(conn, MOST_DOWNLOADED)?,
+            ))
+        })
+        .await?;
 
-    Ok(LandingTemplate {
+    let pages = total.div_ceil(LANDING_PAGE_SIZE);
+    let page_url = |page: usize| {
+        LandingQuery {
+            page,
+            ..query.clone()
+        }
+        .url()
+    };
+    Ok(Either::B(LandingTemplate {
+        branding: settings.branding.clone(),
         title: "Crate List",
-        packages: names,
-    })
+        packages,
+        letters: letters
+            .into_iter()
+            .map(|letter| {
+                let url = LandingQuery {
+                    page: 1,
+                    letter: Some(letter.clone()),
+                    ..query.clone()
+                }
+                .url();
+                let picked = query
+                    .letter
+                    .as_ref()
+                    .is_some_and(|picked| picked.to_lowercase() == letter);
+                (letter, url, picked)
+            })
+            .collect(),
+        sorts: [
+            (database::SearchSort::Alphabetical, "Name"),
+            (database::SearchSort::RecentlyUpdated, "Recently published"),
+        ]
+        .iter()
+        .map(|(sort, label)| {
+            let url = LandingQuery {
+                page: 1,
+                sort: *sort,
+                ..query.clone()
+            }
+            .url();
+            (*label, url, *sort == query.sort)
+        })
+        .collect(),
+        page: query.page,
+        pages,
+        prev: match query.page {
+            1 => None,
+            page => Some(page_url(page - 1)),
+        },
+        next: match query.page {
+            page if page < pages => Some(page_url(page + 1)),
+            _ => None,
+        },
+        recent,
+        most_downloaded,
+    }))
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+pub struct SearchTemplate {
+    branding: Branding,
+    title: String,
+    q: String,
+    /// The value, label, and whether it's the one picked, for each way the
+    /// results can be sorted.
+    sorts: Vec<(&'static str, &'static str, bool)>,
+    results: Vec<registry::SearchResult>,
+    total: usize,
+    page: usize,
+    pages: usize,
+    prev: Option<String>,
+    next: Option<String>,
+}
+
+/// Search, the same as `cargo search` does, a page at a time.
+#[get("/search")]
+pub async fn search(
+    request: HttpRequest,
+    query: web::Query<registry::SearchQuery>,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> std::result::Result<Either<HttpResponse, SearchTemplate>, actix_web::Error> {
+    if let Err(resp) = auth::authorize_frontend(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+
+    let mut query = query.into_inner();
+    query.page = query.page.max(1);
+    query.per_page = query.per_page.clamp(1, registry::MAX_PER_PAGE);
+    let (total, results) = {
+        let (query, index, cache) = (query.clone(), index.clone(), settings.search_cache.clone());
+        registry::with_db(&settings, move |conn| {
+            registry::search_results(conn, &index, &cache, &query)
+        })
+        .await?
+    };
+    let pages = total.div_ceil(query.per_page);
+    let page_url = |page: usize| {
+        let query = registry::SearchQuery {
+            page,
+            ..query.clone()
+        };
+        // Only fails for things that can't be represented as pairs.
+        format!("/search?{}", serde_urlencoded::to_string(&query).unwrap())
+    };
+    Ok(Either::B(SearchTemplate {
+        branding: settings.branding.clone(),
+        title: match query.q.as_str() {
+            "" => "Search".to_string(),
+            q => format!("{} :: Search", q),
+        },
+        sorts: database::SearchSort::ALL
+            .iter()
+            .map(|sort| (sort.as_str(), sort_label(*sort), *sort == query.sort))
+            .collect(),
+        results,
+        total,
+        page: query.page,
+        pages,
+        prev: match query.page {
+            1 => None,
+            page => Some(page_url(page - 1)),
+        },
+        next: match query.page {
+            page if page < pages => Some(page_url(page + 1)),
+            _ => None,
+        },
+        q: query.q,
+    }))
+}
+
+fn sort_label(sort: database::SearchSort) -> &'static str {
+    match sort {
+        database::SearchSort::Relevance => "Relevance",
+        database::SearchSort::Newest => "Newest",
+        database::SearchSort::RecentlyUpdated => "Recently updated",
+        database::SearchSort::Downloads => "Downloads",
+        database::SearchSort::Alphabetical => "Alphabetical",
+    }
 }
 
 #[get("/me")]
-pub async fn login(req: HttpRequest) -> Result<LoginTemplate<'static>> {
+pub async fn login(
+    req: HttpRequest,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, LoginTemplate<'static>>> {
     info!("{:?}", req);
+    if let Err(resp) = auth::authorize_login_page(&req, &settings).await {
+        return Ok(Either::A(resp));
+    }
+
+    let user = auth::session_user(&req, &settings).await;
+    Ok(Either::B(login_page(&settings, user.as_ref()).await?))
+}
 
+async fn login_page(
+    settings: &Settings,
+    user: Option<&database::User>,
+) -> Result<LoginTemplate<'static>> {
+    let ldap = settings.ldap.is_some();
+    let user_id = user.map(|user| user.id);
+    let (password_login, invitations, is_admin) = settings
+        .with_db(move |conn| -> Result<_> {
+            let password_login =
+                ldap || (user_id.is_none() && !database::list_users(conn)?.is_empty());
+            let (invitations, is_admin) = match user_id {
+                Some(user_id) => (
+                    database::list_invitations(conn, user_id)?,
+                    database::is_admin(conn, user_id)?,
+                ),
+                None => (vec![], false),
+            };
+            Ok((password_login, invitations, is_admin))
+        })
+        .await?;
     Ok(LoginTemplate {
+        branding: settings.branding.clone(),
         title: "Login",
-        token: "0000", // TODO: implement proper auth
+        index_url: settings.index_url.clone(),
+        registry_name: settings.registry_name.clone(),
+        web_tokens: anyone_can_issue_tokens(settings) || user.is_some(),
+        oidc: settings.oidc.is_some(),
+        password_login,
+        signup: settings.signup && user.is_none(),
+        is_admin,
+        user: user.map(|user| user.login.clone()),
+        invitations,
+        token: None,
+        error: None,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    login: String,
+    password: String,
+}
+
+/// Log in with a password, checked against the users table or the directory
+/// server (see [`PasswordBackend`](auth::backend::PasswordBackend)).
+#[post("/me/login")]
+pub async fn log_in(
+    form: web::Form<LoginForm>,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, LoginTemplate<'static>>> {
+    let form = form.into_inner();
+    let backend = settings.password_backend();
+    let kind = if settings.ldap.is_some() {
+        database::Backend::Ldap
+    } else {
+        database::Backend::Local
+    };
+    let (name, ok) = web::block(move || -> Result<_> {
+        let ok = backend.verify(&form.login, &form.password)?;
+        Ok((form.login, ok))
     })
+    .await?;
+    if !ok {
+        let mut page = login_page(&settings, None).await?;
+        page.error = Some("Wrong login or password.".to_string());
+        return Ok(Either::B(page));
+    }
+
+    let session = auth::generate_secret();
+    let user = {
+        let session = session.clone();
+        settings
+            .with_db(move |conn| -> Result<_> {
+                let user = database::find_or_create_password_user(conn, kind, &name)?;
+                if let Some(ref user) = user {
+                    database::create_session(conn, &session, user.id)?;
+                }
+                Ok(user)
+            })
+            .await?
+    };
+    let user = match user {
+        Some(user) => user,
+        None => {
+            // Someone else's account (say, from single sign-on) has this
+            // login.
+            let mut page = login_page(&settings, None).await?;
+            page.error = Some("Wrong login or password.".to_string());
+            return Ok(Either::B(page));
+        }
+    };
+    log::info!("`{}` logged in", user.login);
+    Ok(Either::A(
+        HttpResponse::SeeOther()
+            .header(header::LOCATION, "/me")
+            .cookie(auth::session_cookie(&settings, session))
+            .finish(),
+    ))
+}
+
+/// Why `name` can't be used as the login for a new account, if it can't.
+///
+/// Logins end up in token names and urls, so they're kept to a safe set of
+/// characters.
+fn invalid_login(name: &str) -> Option<&'static str> {
+    if name.is_empty() || name.len() > 39 {
+        Some("Logins need to be between 1 and 39 characters long.")
+    } else if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Some("Logins may only contain letters, numbers, `-`, and `_`.")
+    } else {
+        None
+    }
+}
+
+/// The shortest password accepted when signing up.
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// Sign up for an account (with `--signup`), logging in straight away.
+#[post("/me/signup")]
+pub async fn sign_up(
+    request: HttpRequest,
+    form: web::Form<LoginForm>,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, LoginTemplate<'static>>> {
+    if !settings.signup {
+        return Err(EstuaryError::NotFound);
+    }
+    if let Err(resp) = auth::authorize_login_page(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+
+    let form = form.into_inner();
+    let name = form.login.trim().to_string();
+    let error = match invalid_login(&name) {
+        Some(error) => Some(error.to_string()),
+        None if form.password.chars().count() < MIN_PASSWORD_LEN => Some(format!(
+            "Passwords need to be at least {} characters long.",
+            MIN_PASSWORD_LEN
+        )),
+        None => None,
+    };
+    let mut page = login_page(&settings, None).await?;
+    if error.is_some() {
+        page.error = error;
+        return Ok(Either::B(page));
+    }
+
+    // Hashing is slow on purpose, so it's kept off the event loop.
+    let password = form.password;
+    let hash =
+        web::block(move || auth::hash_password(&password).map_err(EstuaryError::Config)).await?;
+    let session = auth::generate_secret();
+    let created = {
+        let (name, session) = (name.clone(), session.clone());
+        settings
+            .with_db(move |conn| -> Result<_> {
+                match database::create_user(conn, &name, &hash) {
+                    Ok(user) => {
+                        database::create_session(conn, &session, user.id)?;
+                        Ok(Some(user))
+                    }
+                    Err(e) if database::is_unique_violation(&e) => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            })
+            .await?
+    };
+    let user = match created {
+        Some(user) => user,
+        None => {
+            page.error = Some(format!("The login `{}` is already taken.", name));
+            return Ok(Either::B(page));
+        }
+    };
+    log::info!("`{}` signed up", user.login);
+    Ok(Either::A(
+        HttpResponse::SeeOther()
+            .header(header::LOCATION, "/me")
+            .cookie(auth::session_cookie(&settings, session))
+            .finish(),
+    ))
+}
+
+#[derive(Template)]
+#[template(path = "user.html")]
+pub struct UserTemplate {
+    branding: Branding,
+    title: String,
+    login: String,
+    /// The crates they own.
+    crates: Vec<String>,
+    /// What they've published, newest first.
+    publishes: Vec<database::Publish>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserPath {
+    login: String,
+}
+
+/// A user's profile: what they own and what they've published.
+#[get("/users/{login}")]
+pub async fn user_profile(
+    request: HttpRequest,
+    path: web::Path<UserPath>,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, UserTemplate>> {
+    if let Err(resp) = auth::authorize_frontend(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+
+    let name = path.into_inner().login;
+    let branding = settings.branding.clone();
+    let page = settings
+        .with_db(move |conn| -> Result<_> {
+            let user = database::find_user(conn, &name)?.ok_or(EstuaryError::NotFound)?;
+            Ok(UserTemplate {
+                branding,
+                title: user.login.clone(),
+                crates: database::list_owned_crates(conn, user.id)?,
+                publishes: database::list_user_publishes(conn, user.id)?,
+                login: user.login,
+            })
+        })
+        .await?;
+    Ok(Either::B(page))
+}
+
+#[derive(Deserialize)]
+pub struct NewTokenForm {
+    name: String,
+}
+
+/// Whether visitors to `/me` can issue themselves a token without logging
+/// in. Private registries only allow it behind `--basic-auth=web`, since
+/// otherwise the token would let anyone in.
+fn anyone_can_issue_tokens(settings: &Settings) -> bool {
+    settings.web_tokens
+        && (!settings.private || settings.basic_auth.contains(&auth::BasicAuthArea::Web))
+}
+
+/// Scopes for tokens issued from the web. Changing owners is left to tokens
+/// issued by an operator.
+const WEB_TOKEN_SCOPES: &[Scope] = &[Scope::Read, Scope::Publish, Scope::Yank];
+
+#[post("/me")]
+pub async fn create_token(
+    request: HttpRequest,
+    form: web::Form<NewTokenForm>,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, LoginTemplate<'static>>> {
+    let user = auth::session_user(&request, &settings).await;
+    // Without web tokens enabled, only people who've logged in may post here.
+    if !anyone_can_issue_tokens(&settings) && user.is_none() {
+        if settings.oidc.is_some() {
+            return Ok(Either::A(
+                HttpResponse::SeeOther()
+                    .header(header::LOCATION, "/me/oidc/login")
+                    .finish(),
+            ));
+        }
+        return Err(EstuaryError::NotFound);
+    }
+    if let Err(resp) = auth::authorize_login_page(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+
+    let mut page = login_page(&settings, user.as_ref()).await?;
+
+    let name = form.name.trim();
+    if name.is_empty() {
+        page.error = Some("Please give the token a name.".to_string());
+    } else {
+        // Tokens issued to a user are named after them, so they don't clash
+        // with anyone else's.
+        let name = match user {
+            Some(ref user) => format!("{}/{}", user.login, name),
+            None => name.to_string(),
+        };
+        let token = auth::generate_token();
+        let user_id = user.as_ref().map(|user| user.id);
+        let created = {
+            let (name, token) = (name.clone(), token.clone());
+            settings
+                .with_db(move |conn| -> Result<_> {
+                    // Users can't give their tokens more than they've been
+                    // given themselves.
+                    let scopes = match user_id {
+                        Some(user_id) => {
+                            let allowed = database::find_user_scopes(conn, user_id)?;
+                            WEB_TOKEN_SCOPES
+                                .iter()
+                                .copied()
+                                .filter(|scope| allowed.contains(scope))
+                                .collect()
+                        }
+                        None => WEB_TOKEN_SCOPES.to_vec(),
+                    };
+                    Ok(database::create_token(
+                        conn, &name, &token, &scopes, user_id,
+                    ))
+                })
+                .await?
+        };
+        match created {
+            Ok(()) => page.token = Some(token),
+            Err(e) if database::is_unique_violation(&e) => {
+                page.error = Some(format!("A token named `{}` already exists.", name));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(Either::B(page))
+}
+
+#[derive(Deserialize)]
+pub struct InvitationPath {
+    crate_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct InvitationForm {
+    accepted: bool,
+}
+
+/// Accept or decline an invitation to own a crate, from the `/me` page.
+#[post("/me/invitations/{crate_name}")]
+pub async fn answer_invitation(
+    request: HttpRequest,
+    path: web::Path<InvitationPath>,
+    form: web::Form<InvitationForm>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_login_page(&request, &settings).await {
+        return Ok(resp);
+    }
+    let user = auth::session_user(&request, &settings)
+        .await
+        .ok_or(EstuaryError::NotFound)?;
+    let (crate_name, accepted) = (path.into_inner().crate_name, form.accepted);
+    if !settings
+        .with_db(move |conn| database::answer_invitation(conn, &crate_name, user.id, accepted))
+        .await?
+    {
+        return Err(EstuaryError::NotFound);
+    }
+    Ok(HttpResponse::SeeOther()
+        .header(header::LOCATION, "/me")
+        .finish())
+}
+
+/// End the visitor's web UI session.
+#[post("/me/logout")]
+pub async fn logout(request: HttpRequest, settings: web::Data<Settings>) -> Result<HttpResponse> {
+    if let Some(session) = request.cookie(auth::SESSION_COOKIE) {
+        let session = session.value().to_string();
+        settings
+            .with_db(move |conn| database::delete_session(conn, &session))
+            .await?;
+    }
+    let mut resp = HttpResponse::SeeOther()
+        .header(header::LOCATION, "/me")
+        .finish();
+    resp.del_cookie(auth::SESSION_COOKIE);
+    Ok(resp)
 }
 
 #[derive(Template)]
 #[template(path = "crate_version_list.html")]
 pub struct CrateVersionListTemplate {
+    branding: Branding,
     crate_name: String,
     releases: Vec<PackageVersion>,
+    hide_yanked: bool,
+    /// How many versions are yanked, whether they're shown or not.
+    yanked: usize,
 }
 
 #[derive(Deserialize, Debug)]
@@ -77,13 +798,32 @@ pub struct CrateVersionListPath {
     crate_name: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct CrateVersionListQuery {
+    #[serde(default)]
+    hide_yanked: bool,
+}
+
 pub async fn version_list(
+    request: HttpRequest,
     path: web::Path<CrateVersionListPath>,
+    query: web::Query<CrateVersionListQuery>,
     index: web::Data<Mutex<PackageIndex>>,
-) -> Result<CrateVersionListTemplate> {
-    let index = index.lock().unwrap();
-    let releases = index
-        .get_package_versions(&path.crate_name)
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, CrateVersionListTemplate>> {
+    if let Err(resp) = auth::authorize_frontend(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+
+    let crate_name = require_crate(&index, &path.crate_name)?;
+    if crate_name != path.crate_name {
+        return Ok(Either::A(redirect_to_crate(&request, &crate_name)));
+    }
+
+    let mut releases = index
+        .lock()
+        .unwrap()
+        .get_package_versions(&crate_name)
         .map_err(|e| match e {
             PackageIndexError::IO(e @ std::io::Error { .. })
                 if e.kind() == std::io::ErrorKind::NotFound =>
@@ -92,13 +832,200 @@ pub async fn version_list(
             }
             _ => e.into(),
         })?;
+    let yanked = releases.iter().filter(|pkg| pkg.yanked).count();
+    if query.hide_yanked {
+        releases.retain(|pkg| !pkg.yanked);
+    }
 
-    Ok(CrateVersionListTemplate {
-        crate_name: path.crate_name.clone(),
+    Ok(Either::B(CrateVersionListTemplate {
+        branding: settings.branding.clone(),
+        crate_name,
         releases,
+        hide_yanked: query.hide_yanked,
+        yanked,
+    }))
+}
+
+#[derive(Template)]
+#[template(path = "crate_owners.html")]
+pub struct CrateOwnersTemplate {
+    branding: Branding,
+    title: String,
+    crate_name: String,
+    /// Users first, then teams.
+    owners: Vec<OwnerRow>,
+    /// Whether the visitor may add and remove owners.
+    can_edit: bool,
+    msg: Option<String>,
+    error: Option<String>,
+}
+
+pub struct OwnerRow {
+    /// A user's login, or a team as `@name`.
+    login: String,
+    is_team: bool,
+}
+
+#[derive(Deserialize)]
+pub struct CrateOwnersPath {
+    crate_name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OwnerAction {
+    Add,
+    Remove,
+}
+
+#[derive(Deserialize)]
+pub struct OwnerForm {
+    action: OwnerAction,
+    login: String,
+}
+
+/// The owners page for `crate_name`, as seen by `user`. Only owners and admins
+/// get to change the owners.
+fn owners_page(
+    conn: &database::Connection,
+    crate_name: &str,
+    user: Option<&database::User>,
+    branding: &Branding,
+) -> Result<CrateOwnersTemplate> {
+    let users = database::list_crate_owners(conn, crate_name)?;
+    let teams = database::list_crate_owner_teams(conn, crate_name)?;
+    let can_edit = match user {
+        Some(user) => {
+            database::is_admin(conn, user.id)?
+                || database::is_crate_owner(conn, crate_name, user.id)?
+        }
+        None => false,
+    };
+    let owners = users
+        .into_iter()
+        .map(|user| OwnerRow {
+            login: user.login,
+            is_team: false,
+        })
+        .chain(teams.into_iter().map(|team| OwnerRow {
+            login: format!("@{}", team.name),
+            is_team: true,
+        }))
+        .collect();
+    Ok(CrateOwnersTemplate {
+        branding: branding.clone(),
+        title: format!("{} :: Owners", crate_name),
+        crate_name: crate_name.to_string(),
+        owners,
+        can_edit,
+        msg: None,
+        error: None,
     })
 }
 
+/// 404 if `crate_name` isn't in the index, otherwise give back the name it was
+/// published as, which may differ in case or `-` vs `_`.
+fn require_crate(index: &Mutex<PackageIndex>, crate_name: &str) -> Result<String> {
+    index
+        .lock()
+        .unwrap()
+        .find_crate_name(crate_name)?
+        .ok_or(EstuaryError::NotFound)
+}
+
+/// Send the browser to the same page for the crate under the name it was
+/// published as, for a request made using one that only collides with it
+/// (`my_crate` for `my-crate`, say).
+fn redirect_to_crate(request: &HttpRequest, crate_name: &str) -> HttpResponse {
+    let mut location = format!("/crates/{}", crate_name);
+    // The path is `/crates/{crate_name}` with maybe more after it.
+    if let Some(rest) = request.path().splitn(4, '/').nth(3) {
+        location.push('/');
+        location.push_str(rest);
+    }
+    if !request.query_string().is_empty() {
+        location.push('?');
+        location.push_str(request.query_string());
+    }
+    HttpResponse::Found()
+        .header(header::LOCATION, location)
+        .finish()
+}
+
+pub async fn crate_owners(
+    request: HttpRequest,
+    path: web::Path<CrateOwnersPath>,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, CrateOwnersTemplate>> {
+    if let Err(resp) = auth::authorize_frontend(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+    let crate_name = require_crate(&index, &path.crate_name)?;
+    if crate_name != path.crate_name {
+        return Ok(Either::A(redirect_to_crate(&request, &crate_name)));
+    }
+
+    let user = auth::session_user(&request, &settings).await;
+    let branding = settings.branding.clone();
+    let page = settings
+        .with_db(move |conn| owners_page(conn, &crate_name, user.as_ref(), &branding))
+        .await?;
+    Ok(Either::B(page))
+}
+
+/// Add or remove an owner from the owners page, the same way the owners
+/// endpoints do for `cargo owner`.
+pub async fn update_crate_owners(
+    request: HttpRequest,
+    path: web::Path<CrateOwnersPath>,
+    form: web::Form<OwnerForm>,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> std::result::Result<Either<HttpResponse, CrateOwnersTemplate>, actix_web::Error> {
+    if let Err(resp) = auth::authorize_frontend(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+    let crate_name = require_crate(&index, &path.crate_name)?;
+    let user = auth::session_user(&request, &settings)
+        .await
+        .ok_or(EstuaryError::NotFound)?;
+    let OwnerForm {
+        action,
+        login: owner,
+    } = form.into_inner();
+    let branding = settings.branding.clone();
+    let updated = settings
+        .with_db(move |conn| -> Result<_> {
+            if !owners_page(conn, &crate_name, Some(&user), &branding)?.can_edit {
+                return Ok(None);
+            }
+            let logins = [owner.trim().to_string()];
+            let done = match action {
+                OwnerAction::Add => {
+                    registry::add_crate_owners(conn, &crate_name, &logins, Some(user.id))
+                }
+                OwnerAction::Remove => registry::remove_crate_owners(conn, &crate_name, &logins)
+                    .map(|()| format!("{} has been removed as an owner", logins[0])),
+            };
+            Ok(Some((
+                done,
+                owners_page(conn, &crate_name, Some(&user), &branding)?,
+            )))
+        })
+        .await?;
+    let (done, mut page) = match updated {
+        Some(updated) => updated,
+        None => return Ok(Either::A(HttpResponse::Forbidden().finish())),
+    };
+    match done {
+        Ok(msg) => page.msg = Some(msg),
+        Err(ApiError::Rejected(reason)) => page.error = Some(reason),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(Either::B(page))
+}
+
 #[derive(Deserialize, Debug)]
 pub struct CrateDetailPath {
     crate_name: String,
@@ -107,18 +1034,29 @@ pub struct CrateDetailPath {
 }
 
 pub async fn crate_detail(
+    request: HttpRequest,
     path: web::Path<CrateDetailPath>,
     index: web::Data<Mutex<PackageIndex>>,
-) -> Result<CrateDetailTemplate> {
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, CrateDetailTemplate>> {
+    if let Err(resp) = auth::authorize_frontend(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+
     // 404 if:
     // - the crate isn't in the index
     // - the crate version doesn't exist
     // - the requested version isn't a valid version string
 
-    let index = index.lock().unwrap();
+    let crate_name = require_crate(&index, &path.crate_name)?;
+    if crate_name != path.crate_name {
+        return Ok(Either::A(redirect_to_crate(&request, &crate_name)));
+    }
 
     let all_releases = index
-        .get_package_versions(&path.crate_name)
+        .lock()
+        .unwrap()
+        .get_package_versions(&crate_name)
         .map_err(|e| match e {
             PackageIndexError::IO(e @ std::io::Error { .. })
                 if e.kind() == std::io::ErrorKind::NotFound =>
@@ -136,42 +1074,477 @@ pub async fn crate_detail(
 
     match pkg {
         Some(pkg) => {
-            let (dev_deps, non_dev_deps) = pkg
+            let (dev_deps, non_dev_deps): (Vec<DependencyRow>, _) = pkg
                 .deps
                 .iter()
                 .cloned()
-                .partition(|dep| dep.kind == DependencyKind::Dev);
+                .map(DependencyRow::from)
+                .partition(|row| row.dep.kind == DependencyKind::Dev);
+            let (name, vers) = (pkg.name.clone(), pkg.vers.to_string());
+            let (downloads, recent_downloads, version_downloads, metadata, publish) = settings
+                .with_db(move |conn| -> Result<_> {
+                    Ok((
+                        database::count_downloads(conn, &name, None)?,
+                        database::count_recent_downloads(conn, &name)?,
+                        database::count_downloads(conn, &name, Some(&vers))?,
+                        database::find_metadata(conn, &name, &vers)?.unwrap_or_default(),
+                        database::find_publish(conn, &name, &vers)?,
+                    ))
+                })
+                .await?;
+            let links = [
+                ("Homepage", &metadata.homepage),
+                ("Repository", &metadata.repository),
+                ("Documentation", &metadata.documentation),
+            ]
+            .iter()
+            .filter_map(|(label, url)| match url {
+                Some(url) if is_web_url(url) => Some((*label, url.clone())),
+                _ => None,
+            })
+            .collect();
 
-            Ok(CrateDetailTemplate {
+            let dependency = format!(
+                "{} = {{ version = \"{}\", registry = \"{}\" }}",
+                pkg.name, pkg.vers, settings.registry_name
+            );
+
+            Ok(Either::B(CrateDetailTemplate {
+                branding: settings.branding.clone(),
                 title: format!("{} v{}", pkg.name, pkg.vers),
+                features: feature_rows(&pkg),
                 pkg,
                 dev_deps,
                 non_dev_deps,
                 // Think about showing the highest N instead of all
                 releases: all_releases,
-            })
+                downloads,
+                recent_downloads,
+                recent_download_days: database::RECENT_DOWNLOAD_DAYS,
+                version_downloads,
+                metadata,
+                links,
+                published_at: publish.map(|publish| publish.published_at),
+                dependency,
+            }))
+        }
+        None => Err(EstuaryError::NotFound),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "crate_stats.html")]
+pub struct CrateStatsTemplate {
+    branding: Branding,
+    title: String,
+    crate_name: String,
+    /// Those the database has a record of, oldest first.
+    versions: Vec<database::VersionStats>,
+    /// Of the crate files with known sizes.
+    total_size: u64,
+    downloads: u64,
+    recent_downloads: u64,
+    recent_download_days: i64,
+    /// Versions published each month (as `YYYY-MM`), oldest first.
+    publishes_by_month: Vec<(String, u64)>,
+    /// Downloads each month (as `YYYY-MM`), oldest first.
+    downloads_by_month: Vec<(String, u64)>,
+}
+
+/// Total up `counts` by the month (the `YYYY-MM`) their dates start with,
+/// keeping them in order.
+fn by_month<'a>(counts: impl Iterator<Item = (&'a str, u64)>) -> Vec<(String, u64)> {
+    let mut months: Vec<(String, u64)> = vec![];
+    for (date, count) in counts {
+        let month = date.get(..7).unwrap_or(date);
+        match months.last_mut() {
+            Some((last, total)) if last == month => *total += count,
+            _ => months.push((month.to_string(), count)),
+        }
+    }
+    months
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CrateStatsPath {
+    crate_name: String,
+}
+
+/// How a crate has been published and downloaded over time.
+pub async fn crate_stats(
+    request: HttpRequest,
+    path: web::Path<CrateStatsPath>,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, CrateStatsTemplate>> {
+    if let Err(resp) = auth::authorize_frontend(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+
+    let crate_name = require_crate(&index, &path.crate_name)?;
+    if crate_name != path.crate_name {
+        return Ok(Either::A(redirect_to_crate(&request, &crate_name)));
+    }
+
+    let name = crate_name.clone();
+    let (versions, daily_downloads, recent_downloads) = settings
+        .with_db(move |conn| -> Result<_> {
+            Ok((
+                database::list_version_stats(conn, &name)?,
+                database::list_daily_downloads(conn, &name)?,
+                database::count_recent_downloads(conn, &name)?,
+            ))
+        })
+        .await?;
+
+    Ok(Either::B(CrateStatsTemplate {
+        branding: settings.branding.clone(),
+        title: format!("{} :: Stats", crate_name),
+        crate_name,
+        total_size: versions.iter().filter_map(|version| version.size).sum(),
+        downloads: daily_downloads.iter().map(|(_, count)| count).sum(),
+        recent_downloads,
+        recent_download_days: database::RECENT_DOWNLOAD_DAYS,
+        publishes_by_month: by_month(
+            versions
+                .iter()
+                .map(|version| (version.published_at.as_str(), 1)),
+        ),
+        downloads_by_month: by_month(
+            daily_downloads
+                .iter()
+                .map(|(date, count)| (date.as_str(), *count)),
+        ),
+        versions,
+    }))
+}
+
+/// Files bigger than this aren't shown on the files page.
+const MAX_VIEWED_FILE_SIZE: u64 = 512 * 1024;
+
+#[derive(Template)]
+#[template(path = "crate_files.html")]
+pub struct CrateFilesTemplate {
+    branding: Branding,
+    title: String,
+    crate_name: String,
+    vers: String,
+    files: Vec<tarball::Entry>,
+    /// The file being looked at, if any.
+    file: Option<FileView>,
+}
+
+pub struct FileView {
+    path: String,
+    size: u64,
+    /// `None` for files that are binary, or too big to show.
+    text: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CrateFilesPath {
+    crate_name: String,
+    version: semver::Version,
+    /// Relative to the crate's root, ex: `src/lib.rs`.
+    path: Option<String>,
+}
+
+/// The files in a version's crate file, and the contents of one of them.
+pub async fn crate_files(
+    request: HttpRequest,
+    path: web::Path<CrateFilesPath>,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, CrateFilesTemplate>> {
+    if let Err(resp) = auth::authorize_frontend(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+
+    let crate_name = require_crate(&index, &path.crate_name)?;
+    if crate_name != path.crate_name {
+        return Ok(Either::A(redirect_to_crate(&request, &crate_name)));
+    }
+    let CrateFilesPath { version, path, .. } = path.into_inner();
+    let published = index
+        .lock()
+        .unwrap()
+        .get_package_versions(&crate_name)?
+        .iter()
+        .any(|pkg| pkg.vers == version);
+    if !published {
+        return Err(EstuaryError::NotFound);
+    }
+
+    // Found the same way downloads find it.
+    let (name, vers) = (crate_name.clone(), version.to_string());
+    let digest = settings
+        .with_db(move |conn| database::find_crate_file(conn, &name, &vers))
+        .await?;
+    let key = match digest {
+        Some(ref digest) => crate::storage::get_blob_key(digest),
+        None => crate::storage::get_crate_file_key(&crate_name, &version),
+    };
+    let crate_file = settings
+        .crate_store
+        .get(&key)
+        .await?
+        .ok_or(EstuaryError::NotFound)?;
+
+    let (files, file) = web::block(move || -> Result<_> {
+        let files = tarball::list(&crate_file)?;
+        let file = match path {
+            None => None,
+            Some(path) => {
+                let size = files
+                    .iter()
+                    .find(|entry| entry.path == path)
+                    .ok_or(EstuaryError::NotFound)?
+                    .size;
+                let text = match size {
+                    size if size > MAX_VIEWED_FILE_SIZE => None,
+                    _ => tarball::read(&crate_file[..], &path, MAX_VIEWED_FILE_SIZE)?
+                        .and_then(|contents| String::from_utf8(contents).ok())
+                        .filter(|text| !text.contains('\0')),
+                };
+                Some(FileView { path, size, text })
+            }
+        };
+        Ok((files, file))
+    })
+    .await?;
+
+    Ok(Either::B(CrateFilesTemplate {
+        branding: settings.branding.clone(),
+        title: format!("{} v{} :: Files", crate_name, version),
+        crate_name,
+        vers: version.to_string(),
+        files,
+        file,
+    }))
+}
+
+/// The most crates the admin page lists for a search.
+const ADMIN_RESULTS: usize = 20;
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+pub struct AdminTemplate {
+    branding: Branding,
+    title: &'static str,
+    q: String,
+    /// The crates matching `q`, with all their versions.
+    crates: Vec<(String, Vec<PackageVersion>)>,
+    msg: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AdminQuery {
+    #[serde(default)]
+    q: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminAction {
+    Yank,
+    Unyank,
+    Delete,
+}
+
+#[derive(Deserialize)]
+pub struct AdminForm {
+    action: AdminAction,
+    crate_name: String,
+    version: String,
+    /// Why a version's being deleted.
+    #[serde(default)]
+    reason: String,
+    /// The search to show again afterwards.
+    #[serde(default)]
+    q: String,
+}
+
+/// The user logged in, if they're an admin.
+async fn require_admin(
+    request: &HttpRequest,
+    settings: &Settings,
+) -> std::result::Result<database::User, HttpResponse> {
+    auth::authorize_frontend(request, settings).await?;
+    let user = auth::session_user(request, settings)
+        .await
+        .ok_or_else(|| HttpResponse::Forbidden().finish())?;
+    let user_id = user.id;
+    match settings
+        .with_db(move |conn| database::is_admin(conn, user_id))
+        .await
+    {
+        Ok(true) => Ok(user),
+        Ok(false) => Err(HttpResponse::Forbidden().finish()),
+        Err(e) => Err(HttpResponse::from_error(e.into())),
+    }
+}
+
+/// The admin page, with the crates whose names contain `q`.
+fn admin_page(index: &PackageIndex, q: &str, branding: &Branding) -> Result<AdminTemplate> {
+    let mut crates = vec![];
+    let wanted = normalize_name(q);
+    if !wanted.is_empty() {
+        let mut names = index.list_crates()?;
+        names.retain(|name| normalize_name(name).contains(&wanted));
+        names.sort_by_key(|name| name.to_lowercase());
+        for name in names.into_iter().take(ADMIN_RESULTS) {
+            let versions = index.get_package_versions(&name)?;
+            crates.push((name, versions));
         }
-        None => return Err(EstuaryError::NotFound),
     }
+    Ok(AdminTemplate {
+        branding: branding.clone(),
+        title: "Admin",
+        q: q.to_string(),
+        crates,
+        msg: None,
+        error: None,
+    })
+}
+
+/// Where admins can yank, unyank, and delete any crate's versions.
+#[get("/admin")]
+pub async fn admin(
+    request: HttpRequest,
+    query: web::Query<AdminQuery>,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, AdminTemplate>> {
+    if let Err(resp) = require_admin(&request, &settings).await {
+        return Ok(Either::A(resp));
+    }
+    Ok(Either::B(admin_page(
+        &index.lock().unwrap(),
+        &query.q,
+        &settings.branding,
+    )?))
+}
+
+#[post("/admin")]
+pub async fn admin_action(
+    request: HttpRequest,
+    form: web::Form<AdminForm>,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> Result<Either<HttpResponse, AdminTemplate>> {
+    let user = match require_admin(&request, &settings).await {
+        Ok(user) => user,
+        Err(resp) => return Ok(Either::A(resp)),
+    };
+    let AdminForm {
+        action,
+        crate_name,
+        version,
+        reason,
+        q,
+    } = form.into_inner();
+
+    let published = match version.parse::<semver::Version>() {
+        Ok(vers) => {
+            let found = index.lock().unwrap().find_crate_name(&crate_name)?;
+            match found {
+                Some(name) => {
+                    let versions = index.lock().unwrap().get_package_versions(&name)?;
+                    Some((name, vers))
+                        .filter(|(_, vers)| versions.iter().any(|pkg| &pkg.vers == vers))
+                }
+                None => None,
+            }
+        }
+        Err(_) => None,
+    };
+    let (crate_name, vers) = match published {
+        Some(published) => published,
+        None => {
+            let mut page = admin_page(&index.lock().unwrap(), &q, &settings.branding)?;
+            page.error = Some(format!("`{}` has no version {}", crate_name, version));
+            return Ok(Either::B(page));
+        }
+    };
+
+    let author = registry::named_author(&settings, user.login.clone());
+    let done = match action {
+        AdminAction::Yank | AdminAction::Unyank => {
+            let yanked = matches!(action, AdminAction::Yank);
+            index
+                .lock()
+                .unwrap()
+                .set_yanked(&crate_name, &vers, yanked, &author)?;
+            let (name, version) = (crate_name.clone(), vers.to_string());
+            settings
+                .with_db(move |conn| database::set_yanked(conn, &name, &version, yanked))
+                .await?;
+            if yanked {
+                "Yanked"
+            } else {
+                "Unyanked"
+            }
+        }
+        AdminAction::Delete => {
+            let (name, version, index) = (crate_name.clone(), vers.clone(), index.clone());
+            let removed = settings
+                .with_db(move |conn| {
+                    let reason = Some(reason.trim()).filter(|reason| !reason.is_empty());
+                    deletion::remove_version(
+                        &index.lock().unwrap(),
+                        conn,
+                        &name,
+                        &version,
+                        reason,
+                        &author,
+                    )
+                })
+                .await?;
+            if let Some(removed) = removed {
+                deletion::delete_files(settings.crate_store.as_ref(), &removed).await?;
+            }
+            "Deleted"
+        }
+    };
+    settings.search_cache.invalidate(&crate_name);
+    settings.refs_cache.invalidate();
+    info!(
+        "{} {} {} v{}",
+        user.login,
+        done.to_lowercase(),
+        crate_name,
+        vers
+    );
+
+    let mut page = admin_page(&index.lock().unwrap(), &q, &settings.branding)?;
+    page.msg = Some(format!("{} {} {}", done, crate_name, vers));
+    Ok(Either::B(page))
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::auth::oidc::OidcConfig;
+    use crate::auth::BasicAuthArea;
+    use crate::database;
+    use crate::package_index::{Author, Dependency, DependencyKind, PackageVersion};
     use crate::test_helpers;
     use crate::test_helpers::MY_CRATE_0_1_0;
+    use crate::Settings;
+    use actix_web::cookie::Cookie;
     use actix_web::http::StatusCode;
-    use actix_web::{test, App};
+    use actix_web::{test, web, App};
 
     #[actix_rt::test]
     async fn test_landing_ok_empty() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
         let mut app = test::init_service(
             App::new()
                 .app_data(package_index.clone())
                 .app_data(settings.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
         let req = test::TestRequest::get().uri("/").to_request();
@@ -180,35 +1553,591 @@ mod tests {
     }
 
     #[actix_rt::test]
-    async fn test_login() {
+    async fn test_landing_pages() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
-
+        let conn = settings.get_db().unwrap();
+        let mut names: Vec<String> = (0..super::LANDING_PAGE_SIZE)
+            .map(|i| format!("crate-{:03}", i))
+            .collect();
+        names.push("zebra".to_string());
+        for name in &names {
+            let pkg = PackageVersion {
+                name: name.to_string(),
+                vers: "0.1.0".parse().unwrap(),
+                deps: vec![],
+                cksum: "".to_string(),
+                features: Default::default(),
+                yanked: false,
+                links: None,
+            };
+            package_index
+                .lock()
+                .unwrap()
+                .publish(&pkg, &Author::default())
+                .unwrap();
+            database::record_publish(&conn, name, "0.1.0", None, None).unwrap();
+            database::update_search(&conn, name).unwrap();
+        }
+        conn.execute(
+            "UPDATE crate_versions SET created_at = created_at + 60 WHERE crate_name = 'crate-050'",
+            &[],
+        )
+        .unwrap();
+        database::record_downloads(
+            &conn,
+            &[database::Downloads {
+                crate_name: "crate-010".to_string(),
+                version: "0.1.0".to_string(),
+                date: "2020-01-01".to_string(),
+                count: 7,
+            }],
+        )
+        .unwrap();
         let mut app = test::init_service(
             App::new()
                 .app_data(package_index.clone())
                 .app_data(settings.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        // The list, without the latest publishes beside it.
+        let list = |body: &[u8]| {
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            body.split("Recently Published").next().unwrap().to_string()
+        };
 
-        let req = test::TestRequest::get().uri("/me").to_request();
+        let body = test::read_response(&mut app, get("/")).await;
+        let body = list(&body);
+        assert!(body.contains(r#"href="/crates/crate-099""#), "{}", body);
+        assert!(!body.contains(r#"href="/crates/zebra""#), "{}", body);
+        assert!(body.contains("Page 1 of 2"), "{}", body);
+        assert!(
+            body.contains(r#"href="/?page=1&amp;letter=z&amp;sort=alphabetical""#),
+            "{}",
+            body
+        );
+
+        let body = test::read_response(&mut app, get("/?page=2")).await;
+        let body = list(&body);
+        assert!(body.contains(r#"href="/crates/zebra""#), "{}", body);
+        assert!(!body.contains(r#"href="/crates/crate-099""#), "{}", body);
+
+        // Pages out of range are the first one, or past the end.
+        let body = test::read_response(&mut app, get("/?page=0")).await;
+        let body = list(&body);
+        assert!(body.contains(r#"href="/crates/crate-000""#), "{}", body);
+        let req = get(&format!("/?page={}", usize::MAX));
         let resp = test::call_service(&mut app, req).await;
         assert_eq!(StatusCode::OK, resp.status());
+
+        let body = test::read_response(&mut app, get("/?letter=Z")).await;
+        let body = list(&body);
+        assert!(body.contains(r#"href="/crates/zebra""#), "{}", body);
+        assert!(!body.contains(r#"href="/crates/crate-000""#), "{}", body);
+        assert!(!body.contains("Page 1"), "{}", body);
+
+        let body = test::read_response(&mut app, get("/?sort=recently-updated")).await;
+        let body = list(&body);
+        let first = body.find("/crates/").unwrap();
+        assert!(body[first..].starts_with("/crates/crate-050"), "{}", body);
+
+        let body = test::read_response(&mut app, get("/")).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let recent = &body[body.find("Recently Published").unwrap()..];
+        assert!(recent.contains("crate-050 v0.1.0"), "{}", recent);
+        assert!(recent.contains("zebra v0.1.0"), "{}", recent);
+        assert!(!recent.contains("crate-000 v0.1.0"), "{}", recent);
+        let most_downloaded = &body[body.find("Most Downloaded").unwrap()..];
+        assert!(
+            most_downloaded.contains(r#"href="/crates/crate-010""#),
+            "{}",
+            most_downloaded
+        );
+        assert!(
+            most_downloaded.contains("7 downloads"),
+            "{}",
+            most_downloaded
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_login() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/me").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = test::read_body(resp).await;
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains(r#"index = "http://localhost:7878/git/index""#));
+    }
+
+    #[actix_rt::test]
+    async fn test_branding() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            branding: super::Branding {
+                title: "Acme Crates".to_string(),
+                logo_url: Some("https://acme.example.com/logo.png".to_string()),
+                footer: Some("Ask #rust for help".to_string()),
+                banner: Some("Down for upgrades <tonight>".to_string()),
+            },
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        for uri in &["/", "/me", "/search?q=foo"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let body = test::read_response(&mut app, req).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            assert!(body.contains(":: Acme Crates</title>"), "{}", body);
+            assert!(!body.contains(":: Estuary</title>"), "{}", body);
+            assert!(
+                body.contains(r#"<img src="https://acme.example.com/logo.png""#),
+                "{}",
+                body
+            );
+            assert!(body.contains("Ask #rust for help"), "{}", body);
+            assert!(
+                body.contains("Down for upgrades &lt;tonight&gt;"),
+                "{}",
+                body
+            );
+        }
+
+        // Without any, there's just the title.
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/").to_request();
+        let body = test::read_response(&mut app, req).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(":: Estuary</title>"), "{}", body);
+        assert!(!body.contains("<img"), "{}", body);
+        assert!(!body.contains(r#"id="banner""#), "{}", body);
+    }
+
+    #[actix_rt::test]
+    async fn test_landing_private_needs_token() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            private: true,
+            auth_required: true,
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        test_helpers::add_token(&settings, "ci", "secret");
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/crates/my-crate")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .header(
+                "authorization",
+                format!("Basic {}", base64::encode("me:secret")),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        // The login page stays reachable, so people can find out how to get in.
+        let req = test::TestRequest::get().uri("/me").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_basic_auth_web_needs_login() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            basic_auth: vec![BasicAuthArea::Web],
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        crate::database::set_user_password(
+            &settings.get_db().unwrap(),
+            "alice",
+            &crate::auth::hash_password("hunter2").unwrap(),
+        )
+        .unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        for uri in &["/", "/me"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(
+                    "authorization",
+                    format!("Basic {}", base64::encode("alice:wrong")),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+            let req = test::TestRequest::get()
+                .uri(uri)
+                .header(
+                    "authorization",
+                    format!("Basic {}", base64::encode("alice:hunter2")),
+                )
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::OK, resp.status());
+        }
+
+        // Cargo's endpoints are left alone.
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates?q=my-crate&per_page=10")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_create_token_disabled_is_not_found() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/me")
+            .set_form(&[("name", "alice")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_create_token() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            web_tokens: true,
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/me")
+            .set_form(&[("name", "alice")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+
+        // The token on the page is the one that got stored.
+        let (token, hash) = crate::database::list_token_hashes(&settings.get_db().unwrap())
+            .unwrap()
+            .remove(0);
+        assert_eq!("alice", token.name);
+        assert!(!token.scopes.contains(&crate::auth::Scope::Owners));
+        let shown = body
+            .split("<pre>")
+            .nth(1)
+            .and_then(|rest| rest.split("</pre>").next())
+            .unwrap();
+        assert_eq!(crate::database::hash_token(shown), hash);
+
+        // Names can't be reused.
+        let req = test::TestRequest::post()
+            .uri("/me")
+            .set_form(&[("name", "alice")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("already exists"));
+    }
+
+    #[actix_rt::test]
+    async fn test_create_token_private() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            web_tokens: true,
+            private: true,
+            auth_required: true,
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        test_helpers::add_token(&settings, "ci", "secret");
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        // Reaching `/me` isn't enough to be let in.
+        let req = test::TestRequest::post()
+            .uri("/me")
+            .set_form(&[("name", "mallory")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        assert_eq!(
+            1,
+            crate::database::count_tokens(&settings.get_db().unwrap()).unwrap()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_oidc_session_issues_user_tokens() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            oidc: Some(OidcConfig {
+                issuer: "https://id.example.com".to_string(),
+                client_id: "estuary".to_string(),
+                client_secret: "shh".to_string(),
+                allowed: vec![],
+            }),
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/me").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains(r#"href="/me/oidc/login""#));
+        assert!(!body.contains(r#"action="/me""#));
+
+        // Posting without logging in first gets sent off to log in.
+        let req = test::TestRequest::post()
+            .uri("/me")
+            .set_form(&[("name", "laptop")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+
+        let session = Cookie::new(
+            crate::auth::SESSION_COOKIE,
+            test_helpers::add_session(&settings, "alice"),
+        );
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .cookie(session.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("<strong>alice</strong>"));
+        assert!(body.contains(r#"action="/me""#));
+
+        let req = test::TestRequest::post()
+            .uri("/me")
+            .cookie(session.clone())
+            .set_form(&[("name", "laptop")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let token = crate::database::list_tokens(&settings.get_db().unwrap())
+            .unwrap()
+            .remove(0);
+        assert_eq!("alice/laptop", token.name);
+        assert!(token.user_id.is_some());
+
+        let req = test::TestRequest::post()
+            .uri("/me/logout")
+            .cookie(session.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .cookie(session)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(!body.contains("<strong>alice</strong>"));
+    }
+
+    #[actix_rt::test]
+    async fn test_password_login() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        // Nobody could log in yet, so there's no form.
+        let req = test::TestRequest::get().uri("/me").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(!body.contains(r#"action="/me/login""#));
+
+        crate::database::set_user_password(
+            &settings.get_db().unwrap(),
+            "alice",
+            &crate::auth::hash_password("hunter2").unwrap(),
+        )
+        .unwrap();
+        let req = test::TestRequest::get().uri("/me").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains(r#"action="/me/login""#));
+
+        let req = test::TestRequest::post()
+            .uri("/me/login")
+            .set_form(&[("login", "alice"), ("password", "wrong")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.response().cookies().next().is_none());
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("Wrong login or password."));
+
+        let req = test::TestRequest::post()
+            .uri("/me/login")
+            .set_form(&[("login", "alice"), ("password", "hunter2")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        let session = resp.response().cookies().next().unwrap().into_owned();
+        assert_eq!(crate::auth::SESSION_COOKIE, session.name());
+
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .cookie(session.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("<strong>alice</strong>"));
+        assert!(body.contains(r#"action="/me""#));
+
+        // Their tokens can't do more than they can.
+        let req = test::TestRequest::post()
+            .uri("/me")
+            .cookie(session)
+            .set_form(&[("name", "laptop")])
+            .to_request();
+        assert_eq!(
+            StatusCode::OK,
+            test::call_service(&mut app, req).await.status()
+        );
+        let token = crate::database::list_tokens(&settings.get_db().unwrap())
+            .unwrap()
+            .remove(0);
+        assert_eq!(vec![crate::auth::Scope::Read], token.scopes);
+
+        // Nor can they log in as someone with the same login from elsewhere.
+        crate::database::find_or_create_oidc_user(&settings.get_db().unwrap(), "sub-1", "bob")
+            .unwrap();
+        crate::database::set_user_password(
+            &settings.get_db().unwrap(),
+            "bob",
+            &crate::auth::hash_password("hunter2").unwrap(),
+        )
+        .unwrap();
+        let req = test::TestRequest::post()
+            .uri("/me/login")
+            .set_form(&[("login", "bob"), ("password", "hunter2")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert!(resp.response().cookies().next().is_none());
     }
 
     #[actix_rt::test]
     async fn test_detail_existing_crate_no_version_is_ok() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
 
@@ -225,19 +2154,127 @@ mod tests {
 
         let resp = test::call_service(&mut app, req).await;
         assert_eq!(StatusCode::OK, resp.status());
+
+        // Along with what was sent when it was published.
+        let metadata = database::CrateMetadata {
+            description: Some("Does things".to_string()),
+            license: Some("MIT OR Apache-2.0".to_string()),
+            repository: Some("https://example.com/my-crate.git".to_string()),
+            homepage: Some("javascript:alert(1)".to_string()),
+            keywords: vec!["internal sdk".to_string()],
+            authors: vec!["Owen Nelson <onelson@gmail.com>".to_string()],
+            ..Default::default()
+        };
+        database::record_metadata(&settings.get_db().unwrap(), "my-crate", "0.1.0", &metadata)
+            .unwrap();
+        let req = test::TestRequest::get()
+            .uri("/crates/my-crate/0.1.0")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        for expected in &[
+            "Does things",
+            "MIT OR Apache-2.0",
+            r#"href="https://example.com/my-crate.git""#,
+            "Owen Nelson &lt;onelson@gmail.com&gt;",
+            r#"href="/search?keyword=internal%20sdk""#,
+            "Published",
+            "0 in the last 90 days",
+            "my-crate = { version = &quot;0.1.0&quot;, registry = &quot;estuary&quot; }",
+            r#"href="https://crates.io/crates/serde""#,
+            r#"href="https://docs.rs/serde""#,
+        ] {
+            assert!(body.contains(expected), "{}\n{}", expected, body);
+        }
+        assert!(!body.contains("javascript:"), "{}", body);
+    }
+
+    #[test]
+    fn test_dependency_links() {
+        let dep = |name: &str, package: Option<&str>, registry: Option<&str>| Dependency {
+            name: name.to_string(),
+            req: "^1".to_string(),
+            features: vec![],
+            optional: false,
+            default_features: true,
+            target: None,
+            kind: DependencyKind::Normal,
+            registry: registry.map(str::to_string),
+            package: package.map(str::to_string),
+        };
+        let links = |dep| {
+            let row = super::DependencyRow::from(dep);
+            (row.page, row.docs)
+        };
+        assert_eq!(
+            (Some("/crates/other-crate".to_string()), None),
+            links(dep("other-crate", None, None))
+        );
+        assert_eq!(
+            (
+                Some("https://crates.io/crates/serde".to_string()),
+                Some("https://docs.rs/serde".to_string())
+            ),
+            links(dep(
+                "serde1",
+                Some("serde"),
+                Some("sparse+https://index.crates.io/")
+            ))
+        );
+        assert_eq!(
+            (None, None),
+            links(dep("elsewhere", None, Some("https://example.com/index")))
+        );
+    }
+
+    #[test]
+    fn test_feature_rows() {
+        let pkg = PackageVersion {
+            name: "my-crate".to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: vec![],
+            cksum: String::new(),
+            features: vec![
+                ("std", vec![]),
+                ("default", vec!["std", "serde"]),
+                ("serde", vec!["dep:serde", "chrono?/serde"]),
+                ("nightly", vec![]),
+            ]
+            .into_iter()
+            .map(|(name, enables)| {
+                let enables = enables.into_iter().map(str::to_string).collect();
+                (name.to_string(), enables)
+            })
+            .collect(),
+            yanked: false,
+            links: None,
+        };
+        let rows = super::feature_rows(&pkg);
+        assert_eq!(
+            vec![
+                ("default", false),
+                ("nightly", false),
+                ("serde", true),
+                ("std", true)
+            ],
+            rows.iter()
+                .map(|row| (row.name.as_str(), row.default))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["dep:serde", "chrono?/serde"], rows[2].enables);
     }
 
     #[actix_rt::test]
     async fn test_detail_nonexistent_crate_is_not_found() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
 
@@ -252,14 +2289,14 @@ mod tests {
     #[actix_rt::test]
     async fn test_detail_nonexistent_crate_no_version_is_not_found() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
 
@@ -274,14 +2311,14 @@ mod tests {
     #[actix_rt::test]
     async fn test_version_list_existing_crate_is_ok() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
 
@@ -298,19 +2335,267 @@ mod tests {
 
         let resp = test::call_service(&mut app, req).await;
         assert_eq!(StatusCode::OK, resp.status());
+
+        // Yanked versions are badged, and can be hidden.
+        let mut pkg = package_index
+            .lock()
+            .unwrap()
+            .get_package_versions("my-crate")
+            .unwrap()
+            .remove(0);
+        pkg.vers = "0.2.0".parse().unwrap();
+        pkg.yanked = true;
+        package_index
+            .lock()
+            .unwrap()
+            .publish(&pkg, &Author::default())
+            .unwrap();
+        for (uri, hidden, toggle) in &[
+            ("/crates/my-crate/versions", false, "?hide_yanked=true"),
+            ("/crates/my-crate/versions?hide_yanked=true", true, ""),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let body = test::read_response(&mut app, req).await;
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            assert!(
+                body.contains(r#"href="/crates/my-crate/0.1.0""#),
+                "{}",
+                body
+            );
+            assert_eq!(
+                !hidden,
+                body.contains(r#"href="/crates/my-crate/0.2.0""#),
+                "{}",
+                body
+            );
+            assert_eq!(!hidden, body.contains("Yanked"), "{}", body);
+            let toggle = format!(r#"href="/crates/my-crate/versions{}""#, toggle);
+            assert!(body.contains(&toggle), "{}", body);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_search() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+        let conn = settings.get_db().unwrap();
+        for name in &["crate-utils", "crates-extra"] {
+            database::record_publish(&conn, name, "1.0.0", None, None).unwrap();
+            database::update_search(&conn, name).unwrap();
+        }
+
+        let search = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(
+            &mut app,
+            search("/search?q=crate&sort=alphabetical&per_page=2"),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains(r#"href="/crates/crate-utils""#), "{}", body);
+        assert!(body.contains(r#"href="/crates/crates-extra""#), "{}", body);
+        assert!(!body.contains(r#"href="/crates/my-crate""#), "{}", body);
+        assert!(body.contains("Page 1 of 2"), "{}", body);
+        assert!(body.contains("page=2"), "{}", body);
+
+        let resp = test::call_service(
+            &mut app,
+            search("/search?q=crate&sort=alphabetical&per_page=2&page=2"),
+        )
+        .await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains(r#"href="/crates/my-crate""#), "{}", body);
+        assert!(body.contains("v0.1.0"), "{}", body);
+        assert!(body.contains("Previous"), "{}", body);
+
+        let resp = test::call_service(&mut app, search("/search?q=nothing")).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("Nothing found."), "{}", body);
+    }
+
+    #[actix_rt::test]
+    async fn test_colliding_name_redirects() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        for (uri, location) in &[
+            ("/crates/my_crate", "/crates/my-crate"),
+            ("/crates/My_Crate/0.1.0", "/crates/my-crate/0.1.0"),
+            ("/crates/my_crate/versions", "/crates/my-crate/versions"),
+            ("/crates/my_crate/owners?x=1", "/crates/my-crate/owners?x=1"),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::FOUND, resp.status(), "{}", uri);
+            assert_eq!(
+                location,
+                &resp.headers().get("location").unwrap(),
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_crate_stats() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+        let downloads = |date: &str, count| database::Downloads {
+            crate_name: "my-crate".to_string(),
+            version: "0.1.0".to_string(),
+            date: date.to_string(),
+            count,
+        };
+        database::record_downloads(
+            &settings.get_db().unwrap(),
+            &[
+                downloads("2020-01-01", 3),
+                downloads("2020-01-31", 4),
+                downloads("2020-02-01", 5),
+            ],
+        )
+        .unwrap();
+
+        let req = test::TestRequest::get()
+            .uri("/crates/my-crate/stats")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        let size = test_helpers::crate_file(MY_CRATE_0_1_0).len();
+        for expected in &[
+            format!("{} bytes", size),
+            "12 all time".to_string(),
+            // Totalled up by month.
+            ">2020-01</td>".to_string(),
+            ">7</td>".to_string(),
+        ] {
+            assert!(body.contains(expected), "{}\n{}", expected, body);
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/crates/no-crate/stats")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_crate_files() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::get()
+            .uri("/crates/my-crate/0.1.0/files")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains(r#"href="/crates/my-crate/0.1.0/files/src/lib.rs""#));
+        assert!(body.contains("Cargo.toml.orig"));
+
+        let req = test::TestRequest::get()
+            .uri("/crates/my-crate/0.1.0/files/src/lib.rs")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("<pre"));
+        assert!(body.contains("(95 bytes)"));
+
+        for uri in &[
+            "/crates/my-crate/0.1.0/files/src/main.rs",
+            "/crates/my-crate/0.2.0/files",
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::NOT_FOUND, resp.status(), "{}", uri);
+        }
+
+        // The crate's detail page links to them.
+        let req = test::TestRequest::get()
+            .uri("/crates/my-crate/0.1.0")
+            .to_request();
+        let body = test::read_body(test::call_service(&mut app, req).await).await;
+        assert!(String::from_utf8(body.to_vec())
+            .unwrap()
+            .contains(r#"href="/crates/my-crate/0.1.0/files""#));
     }
 
     #[actix_rt::test]
     async fn test_version_list_nonexistent_crate_is_not_found() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
 
@@ -321,4 +2606,350 @@ mod tests {
         let resp = test::call_service(&mut app, req).await;
         assert_eq!(StatusCode::NOT_FOUND, resp.status());
     }
+
+    #[actix_rt::test]
+    async fn test_signup() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            signup: true,
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/me").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains(r#"action="/me/signup""#));
+
+        let sign_up = |login: &str, password: &str| {
+            test::TestRequest::post()
+                .uri("/me/signup")
+                .set_form(&[("login", login), ("password", password)])
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, sign_up("alice/admin", "hunter22")).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("Logins may only contain"));
+        let resp = test::call_service(&mut app, sign_up("alice", "short")).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("Passwords need to be at least"));
+
+        let resp = test::call_service(&mut app, sign_up("alice", "hunter22")).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        let session = resp.response().cookies().next().unwrap().into_owned();
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .cookie(session)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("<strong>alice</strong>"));
+
+        let resp = test::call_service(&mut app, sign_up("alice", "hunter22")).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("already taken"));
+    }
+
+    #[actix_rt::test]
+    async fn test_signup_disabled() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/me/signup")
+            .set_form(&[("login", "alice"), ("password", "hunter22")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_answer_invitation() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let session = Cookie::new(
+            crate::auth::SESSION_COOKIE,
+            test_helpers::add_session(&settings, "alice"),
+        );
+        let conn = settings.get_db().unwrap();
+        let alice = crate::database::find_user(&conn, "alice").unwrap().unwrap();
+        crate::database::invite_crate_owner(&conn, "my-crate", alice.id, None).unwrap();
+        crate::database::invite_crate_owner(&conn, "other-crate", alice.id, None).unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .cookie(session.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains(r#"action="/me/invitations/my-crate""#));
+
+        let answer = |crate_name: &str, accepted: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/me/invitations/{}", crate_name))
+                .cookie(session.clone())
+                .set_form(&[("accepted", accepted)])
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, answer("my-crate", "true")).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        let resp = test::call_service(&mut app, answer("other-crate", "false")).await;
+        assert_eq!(StatusCode::SEE_OTHER, resp.status());
+        assert!(crate::database::is_crate_owner(&conn, "my-crate", alice.id).unwrap());
+        assert!(!crate::database::is_crate_owner(&conn, "other-crate", alice.id).unwrap());
+
+        let resp = test::call_service(&mut app, answer("my-crate", "true")).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_admin() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let alice_session = Cookie::new(
+            crate::auth::SESSION_COOKIE,
+            test_helpers::add_session(&settings, "alice"),
+        );
+        let bob_session = Cookie::new(
+            crate::auth::SESSION_COOKIE,
+            test_helpers::add_session(&settings, "bob"),
+        );
+        let conn = settings.get_db().unwrap();
+        database::set_admin(&conn, "alice", true).unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        // Only for admins.
+        let req = test::TestRequest::get().uri("/admin?q=my").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        let req = test::TestRequest::get()
+            .uri("/admin?q=my")
+            .cookie(bob_session.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .cookie(alice_session.clone())
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains(r#"href="/admin""#));
+
+        let req = test::TestRequest::get()
+            .uri("/admin?q=MY_C")
+            .cookie(alice_session.clone())
+            .to_request();
+        let body = String::from_utf8(test::read_response(&mut app, req).await.to_vec()).unwrap();
+        assert!(body.contains(r#"href="/crates/my-crate""#), "{}", body);
+        assert!(body.contains(r#"value="yank""#), "{}", body);
+
+        let act = |session: &Cookie, action: &str, version: &str| {
+            test::TestRequest::post()
+                .uri("/admin")
+                .cookie(session.clone())
+                .set_form(&[
+                    ("action", action),
+                    ("crate_name", "my-crate"),
+                    ("version", version),
+                    ("q", "my"),
+                ])
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, act(&bob_session, "delete", "0.1.0")).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let yanked = || {
+            package_index
+                .lock()
+                .unwrap()
+                .get_package_versions("my-crate")
+                .unwrap()[0]
+                .yanked
+        };
+        let body = test::read_response(&mut app, act(&alice_session, "yank", "0.1.0")).await;
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains("Yanked my-crate 0.1.0"));
+        assert!(yanked());
+        test::read_response(&mut app, act(&alice_session, "unyank", "0.1.0")).await;
+        assert!(!yanked());
+        let body = test::read_response(&mut app, act(&alice_session, "yank", "0.9.0")).await;
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains("`my-crate` has no version 0.9.0"));
+
+        let key = crate::storage::get_blob_key(&crate::storage::sha256(&test_helpers::crate_file(
+            MY_CRATE_0_1_0,
+        )));
+        assert!(settings.crate_store.exists(&key).await.unwrap());
+        let body = test::read_response(&mut app, act(&alice_session, "delete", "0.1.0")).await;
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("Deleted my-crate 0.1.0"), "{}", body);
+        assert!(body.contains("No crates match."), "{}", body);
+        assert!(package_index
+            .lock()
+            .unwrap()
+            .list_crates()
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            None,
+            database::find_publish(&conn, "my-crate", "0.1.0").unwrap()
+        );
+        assert!(!settings.crate_store.exists(&key).await.unwrap());
+    }
+
+    #[actix_rt::test]
+    async fn test_crate_owners() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let alice_session = Cookie::new(
+            crate::auth::SESSION_COOKIE,
+            test_helpers::add_session(&settings, "alice"),
+        );
+        let bob_session = Cookie::new(
+            crate::auth::SESSION_COOKIE,
+            test_helpers::add_session(&settings, "bob"),
+        );
+        let conn = settings.get_db().unwrap();
+        let alice = crate::database::find_user(&conn, "alice").unwrap().unwrap();
+        crate::database::add_crate_owner(&conn, "my-crate", alice.id).unwrap();
+        crate::database::create_team(&conn, "backend").unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        // Anyone can look, but only owners can change anything.
+        let req = test::TestRequest::get()
+            .uri("/crates/my-crate/owners")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains(r#"href="/users/alice""#));
+        assert!(!body.contains("Remove"));
+
+        let update = |session: &Cookie<'static>, action: &str, login: &str| {
+            test::TestRequest::post()
+                .uri("/crates/my-crate/owners")
+                .cookie(session.clone())
+                .set_form(&[("action", action), ("login", login)])
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, update(&bob_session, "add", "bob")).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let resp = test::call_service(&mut app, update(&alice_session, "add", "bob")).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("user bob has been invited"));
+
+        let resp = test::call_service(&mut app, update(&alice_session, "add", "@backend")).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("team @backend has been added"));
+        let resp = test::call_service(&mut app, update(&alice_session, "remove", "@backend")).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(!body.contains(r#"value="@backend""#));
+
+        let resp = test::call_service(&mut app, update(&alice_session, "remove", "alice")).await;
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains("cannot remove all owners of a crate"));
+    }
+
+    #[actix_rt::test]
+    async fn test_user_profile() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        let alice = crate::database::find_or_create_user(&conn, "alice").unwrap();
+        crate::database::create_token(
+            &conn,
+            "alice/laptop",
+            "secret",
+            crate::auth::Scope::ALL,
+            Some(alice.id),
+        )
+        .unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", "secret")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let req = test::TestRequest::get().uri("/users/alice").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(body.contains(r#"href="/crates/my-crate/0.1.0""#));
+
+        let req = test::TestRequest::get().uri("/users/bob").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
 }
@@ -6,12 +6,17 @@
 //! The endpoints here aim to support whatever is necessary for "git fetch" to
 //! work so cargo can do what it needs.
 
+use crate::auth;
+use crate::encoding;
 use crate::errors::EstuaryError;
+use crate::package_index::PackageIndex;
+use crate::storage::{FileBody, TempFile};
 use crate::Settings;
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::body::Body;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
 use serde::Deserialize;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
 
 type Result<T> = std::result::Result<T, EstuaryError>;
 
@@ -48,26 +53,23 @@ pub struct Query {
 
 #[get("/info/refs")]
 pub async fn get_info_refs(
+    request: HttpRequest,
     settings: web::Data<Settings>,
     query: web::Query<Query>,
 ) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_git(&request, &settings).await {
+        return Ok(resp);
+    }
+
     let service_name = query.service.as_service_name().to_string();
-    let svc = service_name.clone();
-    let output = web::block(move || {
-        let service_name = svc;
-        Command::new(&settings.git_binary)
-            .args(&[
-                &service_name,
-                "--stateless-rpc",
-                "--advertise-refs",
-                &settings.index_dir.display().to_string(),
-            ])
-            .output()
+    let refs = web::block(move || {
+        let index = PackageIndex::open(&settings.index_dir)?;
+        settings
+            .refs_cache
+            .get_or_insert_with(index.head_id()?, || index.advertise_refs())
     })
     .await?;
 
-    log::trace!("git says: {:?}", &output);
-
     let mut body = vec![];
 
     write!(
@@ -77,52 +79,118 @@ pub async fn get_info_refs(
     )?;
 
     write!(body, "0000")?;
-    body.extend(output.stdout);
+    body.extend(refs);
 
-    Ok(HttpResponse::Ok()
-        .content_type(format!("application/x-git-{}-advertisement", &service_name))
-        .body(body))
+    let mut builder = HttpResponse::Ok();
+    builder.content_type(format!("application/x-git-{}-advertisement", &service_name));
+    Ok(encoding::respond(&request, builder, body)?)
 }
 
+/// git gzips the bodies of bigger requests (with `Content-Encoding: gzip`),
+/// which actix decompresses before `payload` gets here.
 #[post("/git-upload-pack")]
 pub async fn upload_pack(
+    request: HttpRequest,
     settings: web::Data<Settings>,
     payload: web::Bytes,
 ) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_git(&request, &settings).await {
+        return Ok(resp);
+    }
+
     let service_name = Service::UploadPack.as_service_name();
 
-    let output = web::block(move || {
-        let mut cmd = Command::new(&settings.git_binary)
-            .args(&[
-                service_name,
-                "--stateless-rpc",
-                &settings.index_dir.display().to_string(),
-            ])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()?;
-
-        cmd.stdin.as_mut().unwrap().write_all(&payload)?;
-        cmd.wait_with_output()
+    let index_dir = settings.index_dir.clone();
+    let upload = match web::block(move || PackageIndex::open(&index_dir)?.negotiate(&payload)).await
+    {
+        Ok(upload) => upload,
+        Err(e) => {
+            log::error!("git upload-pack failed with: `{}`", e);
+            return Ok(HttpResponse::InternalServerError().finish());
+        }
+    };
+
+    // The pack is written out on the blocking pool, and only then sent, so a
+    // slow client doesn't keep a thread from the pool waiting on it. Big packs
+    // (a fresh clone of a big index, say) go to disk rather than memory.
+    let spool_dir = settings.crate_dir.join("packs");
+    let written = web::block(move || {
+        let mut out = BufWriter::with_capacity(PACK_CHUNK_SIZE, Spool::new(spool_dir));
+        out.write_all(&upload.reply)?;
+        PackageIndex::open(&settings.index_dir)?.write_pack(&upload, &mut out)?;
+        Ok::<_, EstuaryError>(out.into_inner().map_err(|e| e.into_error())?)
     })
-    .await?;
+    .await;
+    let spool = match written {
+        Ok(spool) => spool,
+        Err(e) => {
+            log::error!("git upload-pack failed with: `{}`", e);
+            return Ok(HttpResponse::InternalServerError().finish());
+        }
+    };
+
+    let mut builder = HttpResponse::Ok();
+    builder.content_type(format!("application/x-git-{}-result", service_name));
+    Ok(match spool {
+        Spool::Memory(buf, _) => builder.body(buf),
+        Spool::File(file) => builder.body(Body::from_message(FileBody::temp(file)?)),
+    })
+}
+
+/// How much of a pack to write at a time.
+const PACK_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The most of a pack to hold in memory, before the rest of it goes to disk.
+const PACK_SPOOL_LIMIT: usize = 1024 * 1024;
+
+/// Where a pack is written to, before it's sent: memory, until it's too big
+/// for that, and then a file in the directory kept alongside it.
+enum Spool {
+    Memory(Vec<u8>, PathBuf),
+    File(TempFile),
+}
+
+impl Spool {
+    fn new(dir: PathBuf) -> Spool {
+        Spool::Memory(vec![], dir)
+    }
+}
 
-    if output.status.success() {
-        Ok(HttpResponse::Ok()
-            .content_type(format!("application/x-git-{}-result", service_name))
-            .body(output.stdout))
-    } else {
-        log::error!("git upload-pack failed with: `{:?}`", &output);
-        Ok(HttpResponse::InternalServerError().finish())
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Spool::Memory(held, dir) = self {
+            if held.len() + buf.len() > PACK_SPOOL_LIMIT {
+                let mut file = TempFile::create(dir)?;
+                file.write_all(held)?;
+                *self = Spool::File(file);
+            }
+        }
+        match self {
+            Spool::Memory(held, _) => held.write(buf),
+            Spool::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Spool::Memory(..) => Ok(()),
+            Spool::File(file) => file.flush(),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::handlers::git::pkt_line;
+    use crate::auth::BasicAuthArea;
+    use crate::handlers::git::{pkt_line, Spool, PACK_SPOOL_LIMIT};
+    use crate::package_index::{Author, PackageVersion};
     use crate::test_helpers;
-    use actix_web::http::StatusCode;
-    use actix_web::{test, App};
+    use crate::Settings;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::{test, web, App};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
 
     #[test]
     fn test_pkt_line_from_example() {
@@ -138,16 +206,100 @@ mod tests {
         assert_eq!(expected, pkt_line(input));
     }
 
+    /// git itself (libgit2, here) can clone the index through the server, and
+    /// fetch what's changed since.
+    #[test]
+    fn test_clone_and_fetch() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        // The server has a thread of its own, and the test server's client
+        // (which goes unused) a runtime to start up in.
+        let srv = {
+            let (package_index, settings) = (package_index.clone(), settings.clone());
+            actix_web::rt::System::new("test_clone_and_fetch").block_on(async move {
+                test::start(move || {
+                    App::new()
+                        .app_data(package_index.clone())
+                        .app_data(settings.clone())
+                        .configure(|cfg| crate::handlers::configure_routes(cfg, &settings))
+                })
+            })
+        };
+        let head = |repo: &git2::Repository, name: &str| {
+            repo.find_reference(name)
+                .unwrap()
+                .peel_to_commit()
+                .unwrap()
+                .id()
+        };
+        let index_repo = git2::Repository::open(&settings.index_dir).unwrap();
+
+        let clone_dir = data_root.path().join("clone");
+        let clone = git2::Repository::clone(&srv.url("/git/index"), &clone_dir).unwrap();
+        assert_eq!(head(&index_repo, "HEAD"), head(&clone, "HEAD"));
+        assert!(clone_dir.join("config.json").exists());
+
+        let pkg = PackageVersion {
+            name: "my-crate".to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+        package_index
+            .lock()
+            .unwrap()
+            .publish(&pkg, &Author::default())
+            .unwrap();
+        clone
+            .find_remote("origin")
+            .unwrap()
+            .fetch(&[] as &[&str], None, None)
+            .unwrap();
+        let branch = index_repo.head().unwrap().shorthand().unwrap().to_string();
+        assert_eq!(
+            head(&index_repo, "HEAD"),
+            head(&clone, &format!("refs/remotes/origin/{}", branch))
+        );
+    }
+
+    #[test]
+    fn test_spool() {
+        let data_root = test_helpers::get_data_root();
+        let dir = data_root.path().join("packs");
+        let mut spool = Spool::new(dir.clone());
+        spool.write_all(b"small").unwrap();
+        assert!(matches!(spool, Spool::Memory(ref held, _) if held == b"small"));
+        assert!(!dir.exists());
+
+        // Until it's too big to hold.
+        spool.write_all(&vec![0; PACK_SPOOL_LIMIT]).unwrap();
+        spool.flush().unwrap();
+        match spool {
+            Spool::File(ref file) => {
+                let written = std::fs::read(file.path()).unwrap();
+                assert_eq!(PACK_SPOOL_LIMIT + 5, written.len());
+                assert!(written.starts_with(b"small"));
+            }
+            Spool::Memory(..) => panic!("expected the spool to be on disk"),
+        }
+        drop(spool);
+        assert_eq!(0, std::fs::read_dir(&dir).unwrap().count());
+    }
+
     #[actix_rt::test]
     async fn test_get_info_refs_no_service_query() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
         let mut app = test::init_service(
             App::new()
                 .app_data(package_index.clone())
                 .app_data(settings.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
         let req = test::TestRequest::get()
@@ -160,13 +312,13 @@ mod tests {
     #[actix_rt::test]
     async fn test_get_info_refs_invalid_service_query() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
         let mut app = test::init_service(
             App::new()
                 .app_data(package_index.clone())
                 .app_data(settings.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
         let req = test::TestRequest::get()
@@ -179,17 +331,155 @@ mod tests {
     #[actix_rt::test]
     async fn test_get_info_refs_valid_service_query() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/git/index/info/refs?service=git-upload-pack")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_get_info_refs_follows_head() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let head = || {
+            git2::Repository::open(&settings.index_dir)
+                .unwrap()
+                .head()
+                .unwrap()
+                .peel_to_commit()
+                .unwrap()
+                .id()
+                .to_string()
+        };
+        let req = test::TestRequest::get()
+            .uri("/git/index/info/refs?service=git-upload-pack")
+            .to_request();
+        let before = head();
+        assert!(
+            String::from_utf8_lossy(&test::read_response(&mut app, req).await).contains(&before)
+        );
+
+        // A commit made behind the server's back (by another server sharing
+        // the index, say) is seen all the same.
+        let repo = git2::Repository::open(&settings.index_dir).unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        let sig = git2::Signature::now("someone else", "someone@example.com").unwrap();
+        repo.commit(
+            Some("HEAD"),
+            &sig,
+            &sig,
+            "elsewhere",
+            &parent.tree().unwrap(),
+            &[&parent],
+        )
+        .unwrap();
+        let after = head();
+        assert_ne!(before, after);
+        let req = test::TestRequest::get()
+            .uri("/git/index/info/refs?service=git-upload-pack")
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains(&after));
+        assert!(!body.contains(&before));
+    }
+
+    #[actix_rt::test]
+    async fn test_get_info_refs_private_offers_basic_auth() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            private: true,
+            auth_required: true,
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        test_helpers::add_token(&settings, "ci", "secret");
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/git/index/info/refs?service=git-upload-pack")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        assert!(resp
+            .headers()
+            .get_all(header::WWW_AUTHENTICATE)
+            .any(|v| v.to_str().unwrap().starts_with("Basic ")));
+
+        // This is what git sends after asking its credential helper.
+        let req = test::TestRequest::get()
+            .uri("/git/index/info/refs?service=git-upload-pack")
+            .header(
+                "authorization",
+                format!("Basic {}", base64::encode("git:secret")),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_get_info_refs_basic_auth_login() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            basic_auth: vec![BasicAuthArea::Git],
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        crate::database::set_user_password(
+            &settings.get_db().unwrap(),
+            "alice",
+            &crate::auth::hash_password("hunter2").unwrap(),
+        )
+        .unwrap();
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
         let mut app = test::init_service(
             App::new()
                 .app_data(package_index.clone())
                 .app_data(settings.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
+
+        let req = test::TestRequest::get()
+            .uri("/git/index/info/refs?service=git-upload-pack")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
         let req = test::TestRequest::get()
             .uri("/git/index/info/refs?service=git-upload-pack")
+            .header(
+                "authorization",
+                format!("Basic {}", base64::encode("alice:hunter2")),
+            )
             .to_request();
         let resp = test::call_service(&mut app, req).await;
         assert_eq!(StatusCode::OK, resp.status());
@@ -198,14 +488,14 @@ mod tests {
     #[actix_rt::test]
     async fn test_upload_pack_no_body() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(package_index.clone())
                 .app_data(settings.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
         let req = test::TestRequest::post()
@@ -220,13 +510,13 @@ mod tests {
     #[actix_rt::test]
     async fn test_upload_pack_initial_fetch() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
         let mut app = test::init_service(
             App::new()
                 .app_data(package_index.clone())
                 .app_data(settings.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
         let req = test::TestRequest::post()
@@ -238,4 +528,84 @@ mod tests {
         let resp = test::call_service(&mut app, req).await;
         assert_eq!(StatusCode::OK, resp.status());
     }
+
+    #[actix_rt::test]
+    async fn test_upload_pack_streams_pack() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let head = git2::Repository::open(&settings.index_dir)
+            .unwrap()
+            .head()
+            .unwrap()
+            .peel_to_commit()
+            .unwrap()
+            .id();
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let want = pkt_line(&format!("want {} side-band-64k ofs-delta\n", head));
+        let req = test::TestRequest::post()
+            .uri("/git/index/git-upload-pack")
+            .header("content-type", "application/x-git-upload-pack-request")
+            .set_payload(format!("{}0000{}", want, pkt_line("done\n")))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body = test::read_body(resp).await;
+        assert!(body.starts_with(pkt_line("NAK\n").as_bytes()));
+        assert_eq!(b"\x01PACK", &body[12..17]);
+        assert!(body.ends_with(b"0000"));
+    }
+
+    #[actix_rt::test]
+    async fn test_upload_pack_gzipped_request() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let head = git2::Repository::open(&settings.index_dir)
+            .unwrap()
+            .head()
+            .unwrap()
+            .peel_to_commit()
+            .unwrap()
+            .id();
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let payload = format!(
+            "{}0000{}",
+            pkt_line(&format!("want {} ofs-delta\n", head)),
+            pkt_line("done\n")
+        );
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        gz.write_all(payload.as_bytes()).unwrap();
+
+        let req = test::TestRequest::post()
+            .uri("/git/index/git-upload-pack")
+            .header("content-type", "application/x-git-upload-pack-request")
+            .header("content-encoding", "gzip")
+            .set_payload(gz.finish().unwrap())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let gzipped = test::read_body(resp).await;
+
+        let req = test::TestRequest::post()
+            .uri("/git/index/git-upload-pack")
+            .header("content-type", "application/x-git-upload-pack-request")
+            .set_payload(payload)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(gzipped, test::read_body(resp).await);
+        assert!(gzipped.starts_with(pkt_line("NAK\n").as_bytes()));
+    }
 }
@@ -0,0 +1,240 @@
+//! Single sign-on for the web UI (see [`auth::oidc`]).
+
+use crate::auth::{self, oidc, SecureEq};
+use crate::database;
+use crate::errors::{EstuaryError, OidcError};
+use crate::Settings;
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header;
+use actix_web::{get, web, HttpMessage, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+/// Send the visitor off to the identity provider to log in.
+#[get("/me/oidc/login")]
+pub async fn login(settings: web::Data<Settings>) -> Result<HttpResponse> {
+    let config = settings.oidc.as_ref().ok_or(EstuaryError::NotFound)?;
+    let discovery = oidc::discover(&config.issuer).await?;
+
+    let state = auth::generate_secret();
+    let nonce = auth::generate_secret();
+    {
+        let (state, nonce) = (state.clone(), nonce.clone());
+        settings
+            .with_db(move |conn| database::create_login_state(conn, &state, &nonce))
+            .await?;
+    }
+
+    let url = oidc::authorization_url(
+        &discovery,
+        config,
+        &oidc::redirect_uri(&settings),
+        &state,
+        &nonce,
+    );
+    Ok(HttpResponse::SeeOther()
+        .header(header::LOCATION, url)
+        .cookie(state_cookie(&settings, state))
+        .finish())
+}
+
+/// The cookie tying a login to the browser that started it.
+///
+/// `SameSite=Lax` still has it sent along when the provider redirects back.
+fn state_cookie(settings: &Settings, state: String) -> Cookie<'static> {
+    Cookie::build(oidc::STATE_COOKIE, state)
+        .path(oidc::CALLBACK_PATH)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(settings.base_url.starts_with("https://"))
+        .finish()
+}
+
+#[derive(Deserialize)]
+pub struct CallbackQuery {
+    state: String,
+    code: Option<String>,
+    /// Set instead of `code` when the provider turned the login down.
+    error: Option<String>,
+}
+
+/// Where the identity provider sends people back to, having logged in.
+///
+/// The login has to have been started in the same browser (going by its
+/// state cookie), so nobody can finish their own login in someone else's
+/// browser. A new user is added the first time someone logs in, and they're
+/// given a session cookie before being sent on to the `/me` page.
+#[get("/me/oidc/callback")]
+pub async fn callback(
+    request: HttpRequest,
+    query: web::Query<CallbackQuery>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    let config = settings.oidc.as_ref().ok_or(EstuaryError::NotFound)?;
+    // Checked first, so that callbacks we didn't ask for don't reach out to
+    // the provider.
+    match request.cookie(oidc::STATE_COOKIE) {
+        Some(cookie) if cookie.value().secure_eq(&query.state.as_str()) => {}
+        _ => return Err(OidcError::UnknownState.into()),
+    }
+    let state = query.state.clone();
+    let nonce = settings
+        .with_db(move |conn| database::take_login_state(conn, &state, oidc::LOGIN_MAX_AGE))
+        .await?
+        .ok_or(OidcError::UnknownState)?;
+    let code = match (&query.code, &query.error) {
+        (Some(code), _) => code,
+        (None, error) => {
+            return Err(OidcError::Provider(format!(
+                "the login was refused (`{}`)",
+                error.as_deref().unwrap_or("no code")
+            ))
+            .into())
+        }
+    };
+
+    let discovery = oidc::discover(&config.issuer).await?;
+    let id_token =
+        oidc::exchange_code(&discovery, config, &oidc::redirect_uri(&settings), code).await?;
+    let jwks = oidc::fetch_jwks(&discovery).await?;
+    let claims = oidc::verify_id_token(
+        &id_token,
+        &jwks,
+        &discovery.issuer,
+        &config.client_id,
+        &nonce,
+    )?;
+    if !claims.is_allowed(&config.allowed) {
+        log::info!("`{}` isn't allowed to log in", claims.login());
+        return Err(OidcError::NotAllowed(claims.login().to_string()).into());
+    }
+
+    let session = auth::generate_secret();
+    let user = {
+        let session = session.clone();
+        settings
+            .with_db(move |conn| -> Result<_> {
+                let user = database::find_or_create_oidc_user(conn, &claims.sub, claims.login())
+                    .map_err(|e| {
+                        if database::is_unique_violation(&e) {
+                            OidcError::LoginTaken(claims.login().to_string()).into()
+                        } else {
+                            EstuaryError::from(e)
+                        }
+                    })?;
+                database::create_session(conn, &session, user.id)?;
+                Ok(user)
+            })
+            .await?
+    };
+    log::info!("`{}` logged in", user.login);
+
+    Ok(HttpResponse::SeeOther()
+        .header(header::LOCATION, "/me")
+        .cookie(auth::session_cookie(&settings, session))
+        .del_cookie(&state_cookie(&settings, String::new()))
+        .finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::oidc::{self, OidcConfig};
+    use crate::database;
+    use crate::test_helpers;
+    use crate::Settings;
+    use actix_web::cookie::Cookie;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+
+    #[actix_rt::test]
+    async fn test_callback_unknown_state_is_bad_request() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            oidc: Some(OidcConfig {
+                // Nothing should be fetched from here.
+                issuer: "http://127.0.0.1:1".to_string(),
+                client_id: "estuary".to_string(),
+                client_secret: "shh".to_string(),
+                allowed: vec![],
+            }),
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/me/oidc/callback?state=made-up&code=abc")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_callback_needs_state_cookie() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            oidc: Some(OidcConfig {
+                // Nothing should be fetched from here.
+                issuer: "http://127.0.0.1:1".to_string(),
+                client_id: "estuary".to_string(),
+                client_secret: "shh".to_string(),
+                allowed: vec![],
+            }),
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        let conn = settings.get_db().unwrap();
+        database::create_login_state(&conn, "started", "nonce").unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        // A login started somewhere else, finished in this browser.
+        for cookie in &[None, Some("other")] {
+            let mut req = test::TestRequest::get().uri("/me/oidc/callback?state=started&code=abc");
+            if let Some(cookie) = cookie {
+                req = req.cookie(Cookie::new(oidc::STATE_COOKIE, *cookie));
+            }
+            let resp = test::call_service(&mut app, req.to_request()).await;
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        }
+        // And the login itself is left alone.
+        assert_eq!(
+            Some("nonce".to_string()),
+            database::take_login_state(&conn, "started", oidc::LOGIN_MAX_AGE).unwrap()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_disabled_is_not_found() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/me/oidc/login").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+}
@@ -3,36 +3,68 @@
 //! Publish, yank, unyank, and download are the bare essentials needed for
 //! adding new crates to the registry and using the registry to install crates.
 //!
-//! For now, the endpoints related to "owners" are on the back burner.
-//! For the small-scale use case estuary targets, we may not need them at all.
-//!
-//! The search endpoint is still pending, but it's on the more near term list.
+//! Owners are users (see `estuary user`), and need to exist before they can
+//! be added to a crate. Adding someone else only invites them, and they
+//! become an owner once they accept.
 //!
 //! - [x] Publish `PUT /api/v1/crates/new`.
 //! - [x] Download `GET /api/v1/crates/{crate_name}/{version}/download`.
 //! - [x] Yank `DELETE /api/v1/crates/{crate_name}/{version}/yank`.
 //! - [x] Unyank `PUT /api/v1/crates/{crate_name}/{version}/unyank`.
-//! - [ ] Owners List `GET /api/v1/crates/{crate_name}/owners`.
-//! - [ ] Owners Add `PUT /api/v1/crates/{crate_name}/owners`.
-//! - [ ] Owners Remove `DELETE /api/v1/crates/{crate_name}/owners`.
-//! - [ ] Search `GET /api/v1/crates` query params: `q` (search terms), `per_page`
-//!   (result limit - default 10, max 100).
+//! - [x] Owners List `GET /api/v1/crates/{crate_name}/owners`.
+//! - [x] Owners Add `PUT /api/v1/crates/{crate_name}/owners`.
+//! - [x] Owners Remove `DELETE /api/v1/crates/{crate_name}/owners`.
+//! - [x] Owner Invitations `GET /api/v1/me/crate_owner_invitations`.
+//! - [x] Accept/Decline Invitations `PUT /api/v1/me/crate_owner_invitations/{crate_name}`.
+//! - [x] Search `GET /api/v1/crates` query params: `q` (search terms), `per_page`
+//!   (result limit - default 10, max 100). Crates are ranked on their names,
+//!   keywords, descriptions, and readmes (see [`database::search_crates`]),
+//!   and their newest versions and descriptions come from the database too.
+//!   `keyword` and `category` narrow the results to crates whose newest
+//!   versions have them, and list every such crate when `q` is left out.
+//!   Crates whose every version is yanked are left out, unless
+//!   `include_yanked=true` (when they're marked `yanked`).
 //! - [x] Login `/me` (this one lives in the frontend module).
 
-use crate::errors::ApiError;
-use crate::package_index::{Dependency, PackageIndex, PackageVersion};
+use crate::auth::{self, Scope};
+use crate::database::{self, Token};
+use crate::errors::{ApiError, EstuaryError};
+use crate::manifest;
+use crate::package_index::{
+    validate_package_name, Author, Dependency, PackageIndex, PackageVersion,
+};
+use crate::search_cache::{Latest, SearchCache};
+use crate::storage::TempFile;
 use crate::Settings;
-use actix_files as fs;
-use actix_web::{delete, get, put, web, HttpResponse, http::{StatusCode, header}};
+use actix_files::NamedFile;
+use actix_web::error::PayloadError;
+use actix_web::http::header;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{delete, get, put, web, HttpRequest, HttpResponse};
 use byteorder::{LittleEndian, ReadBytesExt};
+use futures_util::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::fs::File;
+use std::io;
 use std::sync::Mutex;
 
 pub type ApiResponse = Result<HttpResponse, ApiError>;
 
+/// Run `f` with [`Settings::with_db`], keeping the errors it gives back (like
+/// a rejected owners request) for cargo to show.
+pub async fn with_db<T, F>(settings: &Settings, f: F) -> Result<T, ApiError>
+where
+    F: FnOnce(&database::Connection) -> Result<T, ApiError> + Send + 'static,
+    T: Send + 'static,
+{
+    settings
+        .with_db(move |conn| Ok::<_, EstuaryError>(f(conn)))
+        .await?
+}
+
 #[derive(Deserialize)]
 pub struct Crate {
     crate_name: String,
@@ -41,168 +73,918 @@ pub struct Crate {
 
 /// Data supplied by `cargo` during the publishing of a crate.
 ///
-/// The actual json payload has extra fields (which we're currently dropping)
-/// but in the future we might want to record the data instead.
+/// What goes in the index is kept there, and the rest of what we keep goes in
+/// the database (see [database::CrateMetadata]). The `badges` are only
+/// checked, for the [warnings](crate::publish_policy::Warnings).
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PartialPackageVersion {
     name: String,
     vers: semver::Version,
-    deps: Vec<Dependency>,
+    deps: Vec<PublishedDependency>,
     features: HashMap<String, Vec<String>>,
     links: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    description: Option<String>,
+    documentation: Option<String>,
+    homepage: Option<String>,
+    readme: Option<String>,
+    readme_file: Option<String>,
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    categories: Vec<String>,
+    license: Option<String>,
+    license_file: Option<String>,
+    repository: Option<String>,
+    #[serde(default)]
+    badges: HashMap<String, serde_json::Value>,
 }
 
-/**
- * Compare two objects for equality without revealing information about the objects (other than size) through timing sidechannels.
- */
-trait SecureEq {
-    /**
-     * Compare 2 objects for equality.
-     */
-    fn secure_eq(&self, other: &Self) -> bool;
+/// A dependency as cargo sends it during a publish: under the name of the
+/// package it is, with the name it's used by alongside when it's renamed.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PublishedDependency {
+    #[serde(flatten)]
+    dep: Dependency,
+    explicit_name_in_toml: Option<String>,
 }
 
-impl SecureEq for &str {
-    fn secure_eq(&self, other: &Self) -> bool {
-        // Revealing length is okay.
-        if self.len() != other.len() {
-            return false;
+impl From<PublishedDependency> for Dependency {
+    /// The index has renamed dependencies the other way around.
+    fn from(published: PublishedDependency) -> Dependency {
+        let PublishedDependency {
+            dep,
+            explicit_name_in_toml,
+        } = published;
+        match explicit_name_in_toml {
+            Some(name) => Dependency {
+                package: Some(dep.name.clone()),
+                name,
+                ..dep
+            },
+            None => dep,
         }
+    }
+}
 
-        self
-            .bytes()
-            .zip(other.bytes())
-            .fold(true, |x, (a, b)| { x && a == b })
+/// Who to count publishes and yanks against.
+fn rate_limit_key(token: Option<&Token>) -> String {
+    match token {
+        Some(token) => token.name.clone(),
+        // Until there are tokens, everyone shares a limit.
+        None => "anonymous".to_string(),
     }
 }
 
-fn is_authorized(request: &web::HttpRequest, settings: &Settings) -> Result<(),StatusCode> {
-    let publish_key = request.headers().get(header::AUTHORIZATION);
+/// Who to credit in the index for changes made with `token`: the user it was
+/// issued to, or else whoever it was issued to from the shell. Changes made
+/// without one go to the `fallback_author`.
+///
+/// Nobody's email address is known, so they're given one at the registry's
+/// host.
+async fn index_author(settings: &Settings, token: Option<&Token>) -> Result<Author, ApiError> {
+    let token = match token {
+        Some(token) => token,
+        None => return Ok(settings.fallback_author.clone()),
+    };
+    let name = match token.user_id {
+        Some(user_id) => settings
+            .with_db(move |conn| database::find_user_by_id(conn, user_id))
+            .await?
+            .map_or_else(|| token.name.clone(), |user| user.login),
+        None => token.name.clone(),
+    };
+    Ok(named_author(settings, name))
+}
 
-    if let Some(ref key) = settings.publish_key {
-        match publish_key {
-            Some(k) => {
-                let k = match k.to_str() {
-                    Ok(v) => v,
-                    Err(_) => {
-                        return Err(StatusCode::BAD_REQUEST);
-                    }
-                };
+/// Credit an index commit to `name`, with an email address at the
+/// registry's host.
+pub fn named_author(settings: &Settings, name: String) -> Author {
+    let host = settings
+        .base_url
+        .split("://")
+        .last()
+        .and_then(|rest| rest.split(&['/', ':'][..]).next())
+        .filter(|host| !host.is_empty())
+        .unwrap_or("localhost");
+    Author {
+        email: format!("{}@{}", name.replace(char::is_whitespace, "-"), host),
+        name,
+    }
+}
 
-                if !key.as_str().secure_eq(&k) {
-                    return Err(StatusCode::FORBIDDEN);
-                }
-            },
+/// Refuse a crate file of `len` bytes if it's over the `max_crate_size`.
+fn check_crate_size(settings: &Settings, len: u64) -> Result<(), ApiError> {
+    match settings.max_crate_size {
+        Some(max) if len > max => Err(ApiError::Rejected(format!(
+            "the crate file is {} bytes, over the registry's {} byte limit",
+            len, max
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Refuse a crate file of `len` bytes if it'd take its crate over
+/// `crate_quota`, or the whole registry over `registry_quota`.
+fn check_quotas(
+    conn: &database::Connection,
+    crate_name: &str,
+    len: u64,
+    crate_quota: Option<u64>,
+    registry_quota: Option<u64>,
+) -> Result<(), ApiError> {
+    if let Some(quota) = crate_quota {
+        let usage = database::crate_usage(conn, crate_name)?;
+        if usage + len > quota {
+            return Err(ApiError::Rejected(format!(
+                "`{}` already takes up {} of its {} byte quota, with no room for {} more",
+                crate_name, usage, quota, len
+            )));
+        }
+    }
+    if let Some(quota) = registry_quota {
+        let usage = database::registry_usage(conn)?;
+        if usage + len > quota {
+            return Err(ApiError::Rejected(format!(
+                "the registry is full: {} of its {} byte quota is used, with no room for {} more",
+                usage, quota, len
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Refuse a version that's been deleted before, since whoever built against
+/// it (and anything they locked) would get something else under the same
+/// number.
+async fn check_tombstone(
+    settings: &Settings,
+    crate_name: &str,
+    vers: &semver::Version,
+) -> Result<(), ApiError> {
+    let (name, version) = (crate_name.to_string(), vers.to_string());
+    let tombstone = settings
+        .with_db(move |conn| database::find_tombstone(conn, &name, &version))
+        .await?;
+    match tombstone {
+        Some(tombstone) => Err(ApiError::Rejected(format!(
+            "`{}` {} was deleted on {}{} and can't be published again; publish a new version \
+            instead",
+            crate_name,
+            vers,
+            tombstone.deleted_at,
+            tombstone
+                .reason
+                .map(|reason| format!(" ({})", reason))
+                .unwrap_or_default()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// The most metadata (which includes the readme) we'll hold in memory for a
+/// publish.
+const MAX_METADATA_LEN: usize = 16 * 1024 * 1024;
+
+/// Reads a publish request's body a part at a time, as it arrives.
+///
+/// The body is the metadata's length (as a little endian `u32`) and json,
+/// then the crate file's length and bytes.
+struct PayloadReader<S> {
+    stream: S,
+    buf: BytesMut,
+}
+
+impl<S: Stream<Item = Result<Bytes, PayloadError>> + Unpin> PayloadReader<S> {
+    fn new(stream: S) -> PayloadReader<S> {
+        PayloadReader {
+            stream,
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// Read the next chunk of the body into the buffer.
+    async fn fill(&mut self) -> Result<(), ApiError> {
+        match self.stream.next().await {
+            Some(chunk) => {
+                self.buf.extend_from_slice(&chunk?);
+                Ok(())
+            }
             None => {
-                return Err(StatusCode::UNAUTHORIZED);
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the request ended early").into())
             }
         }
     }
 
-    Ok(())
+    async fn read_exact(&mut self, len: usize) -> Result<Bytes, ApiError> {
+        while self.buf.len() < len {
+            self.fill().await?;
+        }
+        Ok(self.buf.split_to(len).freeze())
+    }
+
+    async fn read_u32(&mut self) -> Result<u32, ApiError> {
+        Ok(self
+            .read_exact(4)
+            .await?
+            .as_ref()
+            .read_u32::<LittleEndian>()?)
+    }
+
+    /// Copy the next `len` bytes into `out` (a chunk at a time), handing back
+    /// their SHA-256.
+    async fn copy_to(&mut self, len: usize, out: &TempFile) -> Result<String, ApiError> {
+        let mut hasher = Sha256::new();
+        let mut remaining = len;
+        while remaining > 0 {
+            if self.buf.is_empty() {
+                self.fill().await?;
+            }
+            let chunk = self.buf.split_to(remaining.min(self.buf.len())).freeze();
+            hasher.update(&chunk);
+            remaining -= chunk.len();
+            out.write_chunk(chunk).await?;
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+}
+
+/// Refuse to publish `name` when the crate already in the index under what
+/// cargo would count as the same name is spelled differently.
+fn check_name_clash(name: &str, existing: Option<&str>) -> Result<(), ApiError> {
+    match existing {
+        Some(existing) if existing != name => Err(ApiError::Rejected(format!(
+            "`{}` is too close to the name of the existing crate `{}`; \
+             crate names can't differ only by case or `-`/`_`",
+            name, existing
+        ))),
+        _ => Ok(()),
+    }
 }
 
 #[put("/new")]
 pub async fn publish(
-    mut payload: web::Bytes,
-    request: web::HttpRequest,
+    payload: web::Payload,
+    request: HttpRequest,
     package_index: web::Data<Mutex<PackageIndex>>,
     settings: web::Data<Settings>,
 ) -> ApiResponse {
-    match is_authorized(&request, &settings) {
-        Ok(_) => {},
-        Err(s) => { return Ok(HttpResponse::new(s)) }
+    // Bodies too big for any crate file that'd be allowed are refused before
+    // reading any of them.
+    let body_len = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<u64>().ok());
+    if let (Some(body_len), Some(max)) = (body_len, settings.max_crate_size) {
+        if body_len > max + MAX_METADATA_LEN as u64 + 8 {
+            return Err(ApiError::Rejected(format!(
+                "the request is {} bytes, too big for a crate file under the registry's {} \
+                 byte limit",
+                body_len, max
+            )));
+        }
     }
+    // Anyone who couldn't publish any crate is turned away before the body is
+    // read. Trusted publishers don't have a token.
+    let trusted = auth::trusted::is_id_token(&request);
+    let id_token = if trusted {
+        match auth::trusted::authenticate(&request, &settings).await {
+            Ok(id_token) => Some(id_token),
+            Err(resp) => return Ok(resp),
+        }
+    } else {
+        if let Err(resp) = auth::authorize(&request, &settings, Scope::Publish).await {
+            return Ok(resp);
+        }
+        None
+    };
 
-    log::trace!("total len: {}", payload.len());
+    let mut payload = PayloadReader::new(payload);
 
-    let metadata_len = { payload.split_to(4).as_ref().read_u32::<LittleEndian>()? } as usize;
+    let metadata_len = payload.read_u32().await? as usize;
     log::trace!("metadata len: {}", metadata_len);
+    if metadata_len > MAX_METADATA_LEN {
+        return Err(ApiError::Rejected(format!(
+            "the metadata is over the {} byte limit",
+            MAX_METADATA_LEN
+        )));
+    }
 
     let metadata: PartialPackageVersion =
-        serde_json::from_slice(payload.split_to(metadata_len).as_ref())?;
-
-    let crate_file_len = { payload.split_to(4).as_ref().read_u32::<LittleEndian>()? } as usize;
-    log::trace!("crate file len: {}", crate_file_len);
+        serde_json::from_slice(payload.read_exact(metadata_len).await?.as_ref())?;
+    validate_package_name(&metadata.name).map_err(|e| ApiError::Rejected(e.to_string()))?;
+    let known = find_crate_name(&package_index, &metadata.name)
+        .await?
+        .is_some();
+    if !known {
+        settings
+            .publish_policy
+            .check_name(&metadata.name)
+            .map_err(ApiError::Rejected)?;
+    }
 
-    let crate_file_bytes = payload.split_to(crate_file_len);
-    let cksum = format!("{:x}", Sha256::digest(crate_file_bytes.as_ref()));
+    // Who may publish depends on which crate is being published.
+    let token = match id_token {
+        Some(id_token) => auth::trusted::authorize(&settings, id_token, &metadata.name)
+            .await
+            .map(|()| None),
+        None => auth::authorize_publish(&request, &settings, &metadata.name, &metadata.vers).await,
+    };
+    let token = match token {
+        Ok(token) => token,
+        Err(resp) => return Ok(resp),
+    };
+    let rate_limit_key = if trusted {
+        format!("trusted publishers of `{}`", metadata.name)
+    } else {
+        rate_limit_key(token.as_ref())
+    };
+    if let Err(resp) = settings.rate_limiter.limit(&rate_limit_key) {
+        return Ok(resp);
+    }
+    check_tombstone(&settings, &metadata.name, &metadata.vers).await?;
 
+    let crate_file_len = payload.read_u32().await? as usize;
+    log::trace!("crate file len: {}", crate_file_len);
+    check_crate_size(&settings, crate_file_len as u64)?;
+    // Checked before the upload, so there's no waiting on one that'd be
+    // refused, and again once it's done.
+    let (crate_quota, registry_quota) = (settings.crate_quota, settings.registry_quota);
+    let name = metadata.name.clone();
+    with_db(&settings, move |conn| {
+        check_quotas(
+            conn,
+            &name,
+            crate_file_len as u64,
+            crate_quota,
+            registry_quota,
+        )
+    })
+    .await?;
+
+    // The crate file goes to disk as it arrives, rather than being held in
+    // memory.
+    let crate_file = TempFile::create(&settings.crate_dir.join("uploads"))?;
+    let cksum = payload.copy_to(crate_file_len, &crate_file).await?;
+
+    let warnings = settings
+        .publish_policy
+        .warnings(&metadata.categories, &metadata.badges);
+    let categories = settings.publish_policy.categories(&metadata.categories);
+    let crate_metadata = database::CrateMetadata {
+        description: metadata.description,
+        documentation: metadata.documentation,
+        homepage: metadata.homepage,
+        readme: metadata.readme,
+        readme_file: metadata.readme_file,
+        license: metadata.license,
+        license_file: metadata.license_file,
+        repository: metadata.repository,
+        keywords: metadata.keywords,
+        categories,
+        authors: metadata.authors,
+    };
     let pkg_version = PackageVersion {
         name: metadata.name,
         vers: metadata.vers,
-        deps: metadata.deps,
+        deps: metadata.deps.into_iter().map(Dependency::from).collect(),
         cksum,
         features: metadata.features,
         yanked: false,
         links: metadata.links,
     };
+    // The index is written from what cargo sent, so that has to be what's in
+    // the crate file. It's read off the async worker, as that means unzipping
+    // it.
+    let (path, index_urls) = (crate_file.path().to_path_buf(), settings.index_urls());
+    let manifest = web::block(move || {
+        File::open(path).map(|crate_file| manifest::read_from(crate_file, &index_urls))
+    })
+    .await
+    .map_err(EstuaryError::from)?
+    .map_err(|e| ApiError::Rejected(format!("the crate file can't be read: {}", e)))?;
+    let differences = manifest.differences(&pkg_version);
+    if !differences.is_empty() {
+        return Err(ApiError::Rejected(format!(
+            "the crate file doesn't match what was sent with it: it differs in {}",
+            differences.join(", ")
+        )));
+    }
+    settings
+        .publish_policy
+        .check(&pkg_version)
+        .map_err(ApiError::Rejected)?;
+
+    let author = index_author(&settings, token.as_ref()).await?;
+    let existing = find_crate_name(&package_index, &pkg_version.name).await?;
+    check_name_clash(&pkg_version.name, existing.as_deref())?;
+
+    // The crate file is stored before the index has the version, so cargo is
+    // never told about a version it can't download. The index is let go of
+    // meanwhile, since this may mean waiting on a bucket.
+    let key = crate::storage::get_blob_key(&pkg_version.cksum);
+    if !settings.crate_store.exists(&key).await? {
+        settings.crate_store.put(&key, crate_file.path()).await?;
+    }
 
-    let package_index = package_index.lock().unwrap();
-    package_index.publish(&pkg_version)?;
-
-    crate::storage::store_crate_file(
-        &settings.crate_dir,
-        &pkg_version.name,
-        &pkg_version.vers,
-        crate_file_bytes.as_ref(),
-    )?;
-    Ok(HttpResponse::Ok().json(json!({
-        // Optional object of warnings to display to the user.
-        "warnings": {
-            // Array of strings of categories that are invalid and ignored.
-            "invalid_categories": [],
-            // Array of strings of badge names that are invalid and ignored.
-            "invalid_badges": [],
-            // Array of strings of arbitrary warnings to display to the user.
-            "other": []
+    // The version's rows are written in a transaction that's only committed
+    // once the index has the version too, so a failure in either leaves
+    // neither with it.
+    let user_id = token.as_ref().and_then(|token| token.user_id);
+    let token_name = token.map(|token| token.name);
+    let crate_name = pkg_version.name.clone();
+    let package_index = package_index.into_inner();
+    with_db(&settings, move |conn| {
+        let (name, vers) = (&pkg_version.name, pkg_version.vers.to_string());
+        let tx = conn.transaction()?;
+        // Publishes take turns from here, so whatever's checked again now
+        // can't have changed by the time it's committed.
+        let package_index = package_index.lock().unwrap();
+        // Another publish may have used up the room since the upload began.
+        check_quotas(
+            &tx,
+            name,
+            crate_file_len as u64,
+            crate_quota,
+            registry_quota,
+        )?;
+        database::record_crate_file(&tx, name, &vers, &pkg_version.cksum, crate_file_len as u64)?;
+        database::record_publish(&tx, name, &vers, user_id, token_name.as_deref())?;
+        database::record_metadata(&tx, name, &vers, &crate_metadata)?;
+        database::update_search(&tx, name)?;
+
+        // In case another publish got there first.
+        let existing = package_index.find_crate_name(name)?;
+        check_name_clash(name, existing.as_deref())?;
+        let is_new = existing.is_none();
+        // Whoever publishes a crate first owns it, so there's no setting owners
+        // up by hand in the common case.
+        if let Some(user_id) = user_id {
+            if is_new && !database::has_owners(&tx, name)? {
+                database::add_crate_owner(&tx, name, user_id)?;
+            }
         }
-    })))
+        package_index.publish(&pkg_version, &author)?;
+        tx.commit()?;
+        Ok(())
+    })
+    .await?;
+    // So search doesn't keep showing the version before this one.
+    settings.search_cache.invalidate(&crate_name);
+    settings.refs_cache.invalidate();
+    Ok(HttpResponse::Ok().json(json!({ "warnings": warnings })))
 }
 
 #[delete("/{crate_name}/{version}/yank")]
 pub async fn yank(
     path: web::Path<Crate>,
-    request: web::HttpRequest,
+    request: HttpRequest,
     package_index: web::Data<Mutex<PackageIndex>>,
     settings: web::Data<Settings>,
 ) -> ApiResponse {
-    match is_authorized(&request, &settings) {
-        Ok(_) => {},
-        Err(s) => { return Ok(HttpResponse::new(s)) }
-    }
-
-    let package_index = package_index.lock().unwrap();
-    package_index.set_yanked(&path.crate_name, &path.version, true)?;
+    let crate_name = match find_crate_name(&package_index, &path.crate_name).await? {
+        Some(name) => name,
+        None => return Ok(crate_not_found(&path.crate_name)),
+    };
+    let token = match auth::authorize_crate(&request, &settings, Scope::Yank, &crate_name)
+        .await
+        .and_then(|token| {
+            settings
+                .rate_limiter
+                .limit(&rate_limit_key(token.as_ref()))?;
+            Ok(token)
+        }) {
+        Ok(token) => token,
+        Err(resp) => return Ok(resp),
+    };
+    let author = index_author(&settings, token.as_ref()).await?;
+
+    package_index
+        .lock()
+        .unwrap()
+        .set_yanked(&crate_name, &path.version, true, &author)?;
+    let (name, version) = (crate_name.clone(), path.version.to_string());
+    settings
+        .with_db(move |conn| database::set_yanked(conn, &name, &version, true))
+        .await?;
+    settings.search_cache.invalidate(&crate_name);
+    settings.refs_cache.invalidate();
     Ok(HttpResponse::Ok().json(json!({ "ok": true })))
 }
 
 #[put("/{crate_name}/{version}/unyank")]
 pub async fn unyank(
     path: web::Path<Crate>,
-    request: web::HttpRequest,
+    request: HttpRequest,
+    package_index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> ApiResponse {
+    let crate_name = match find_crate_name(&package_index, &path.crate_name).await? {
+        Some(name) => name,
+        None => return Ok(crate_not_found(&path.crate_name)),
+    };
+    let token = match auth::authorize_crate(&request, &settings, Scope::Yank, &crate_name)
+        .await
+        .and_then(|token| {
+            settings
+                .rate_limiter
+                .limit(&rate_limit_key(token.as_ref()))?;
+            Ok(token)
+        }) {
+        Ok(token) => token,
+        Err(resp) => return Ok(resp),
+    };
+    let author = index_author(&settings, token.as_ref()).await?;
+
+    package_index
+        .lock()
+        .unwrap()
+        .set_yanked(&crate_name, &path.version, false, &author)?;
+    let (name, version) = (crate_name.clone(), path.version.to_string());
+    settings
+        .with_db(move |conn| database::set_yanked(conn, &name, &version, false))
+        .await?;
+    settings.search_cache.invalidate(&crate_name);
+    settings.refs_cache.invalidate();
+    Ok(HttpResponse::Ok().json(json!({ "ok": true })))
+}
+
+#[derive(Deserialize)]
+pub struct CrateName {
+    crate_name: String,
+}
+
+/// The users being added or removed as owners, with teams written as
+/// `@name`.
+#[derive(Deserialize)]
+pub struct OwnersRequest {
+    users: Vec<String>,
+}
+
+/// The name `crate_name` was published under, which may differ in case or
+/// `-` vs `_`, if it's in the index.
+///
+/// Owners are recorded under the published name, so crates are authorized
+/// (and changed) by it rather than by whatever spelling the request used.
+async fn find_crate_name(
+    package_index: &web::Data<Mutex<PackageIndex>>,
+    crate_name: &str,
+) -> Result<Option<String>, ApiError> {
+    let (package_index, crate_name) = (package_index.clone(), crate_name.to_string());
+    // Looking through the index's tree is blocking work.
+    Ok(
+        web::block(move || package_index.lock().unwrap().find_crate_name(&crate_name))
+            .await
+            .map_err(EstuaryError::from)?,
+    )
+}
+
+/// A 404, in cargo's error format, for a crate that isn't in the index.
+fn crate_not_found(crate_name: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(json!({
+        "errors": [{ "detail": format!("crate `{}` does not exist", crate_name) }]
+    }))
+}
+
+/// Someone named in an owners request.
+enum Owner {
+    User(database::User),
+    Team(database::Team),
+}
+
+fn user_json(user: &database::User) -> serde_json::Value {
+    json!({ "id": user.id, "login": user.login, "kind": "user", "name": null })
+}
+
+fn team_json(team: &database::Team) -> serde_json::Value {
+    json!({ "id": team.id, "login": format!("@{}", team.name), "kind": "team", "name": null })
+}
+
+/// The users owning `crate_name` followed by the teams, as cargo lists them.
+fn list_owner_json(
+    conn: &database::Connection,
+    crate_name: &str,
+) -> Result<Vec<serde_json::Value>, ApiError> {
+    let users = database::list_crate_owners(conn, crate_name)?;
+    let teams = database::list_crate_owner_teams(conn, crate_name)?;
+    Ok(users
+        .iter()
+        .map(user_json)
+        .chain(teams.iter().map(team_json))
+        .collect())
+}
+
+#[get("/{crate_name}/owners")]
+pub async fn list_owners(
+    path: web::Path<CrateName>,
+    request: HttpRequest,
     package_index: web::Data<Mutex<PackageIndex>>,
     settings: web::Data<Settings>,
 ) -> ApiResponse {
-    match is_authorized(&request, &settings) {
-        Ok(_) => {},
-        Err(s) => { return Ok(HttpResponse::new(s)) }
+    if let Err(resp) = auth::authorize_read(&request, &settings).await {
+        return Ok(resp);
     }
+    let crate_name = match find_crate_name(&package_index, &path.crate_name).await? {
+        Some(name) => name,
+        None => return Ok(crate_not_found(&path.crate_name)),
+    };
+    let owners = with_db(&settings, move |conn| list_owner_json(conn, &crate_name)).await?;
+    Ok(HttpResponse::Ok().json(json!({ "users": owners })))
+}
 
-    let index = package_index.lock().unwrap();
-    index.set_yanked(&path.crate_name, &path.version, false)?;
-    Ok(HttpResponse::Ok().json(json!({ "ok": true })))
+#[put("/{crate_name}/owners")]
+pub async fn add_owners(
+    path: web::Path<CrateName>,
+    body: web::Json<OwnersRequest>,
+    request: HttpRequest,
+    package_index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> ApiResponse {
+    let crate_name = match find_crate_name(&package_index, &path.crate_name).await? {
+        Some(name) => name,
+        None => return Ok(crate_not_found(&path.crate_name)),
+    };
+    let token = match auth::authorize_crate(&request, &settings, Scope::Owners, &crate_name).await {
+        Ok(token) => token,
+        Err(resp) => return Ok(resp),
+    };
+
+    let inviter_id = token.and_then(|token| token.user_id);
+    let users = body.into_inner().users;
+    let msg = with_db(&settings, move |conn| {
+        add_crate_owners(conn, &crate_name, &users, inviter_id)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({ "ok": true, "msg": msg })))
+}
+
+#[delete("/{crate_name}/owners")]
+pub async fn remove_owners(
+    path: web::Path<CrateName>,
+    body: web::Json<OwnersRequest>,
+    request: HttpRequest,
+    package_index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> ApiResponse {
+    let crate_name = match find_crate_name(&package_index, &path.crate_name).await? {
+        Some(name) => name,
+        None => return Ok(crate_not_found(&path.crate_name)),
+    };
+    if let Err(resp) = auth::authorize_crate(&request, &settings, Scope::Owners, &crate_name).await
+    {
+        return Ok(resp);
+    }
+
+    let users = body.into_inner().users;
+    with_db(&settings, move |conn| {
+        remove_crate_owners(conn, &crate_name, &users)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "ok": true,
+        "msg": "owners successfully removed",
+    })))
+}
+
+/// Add the users and teams named by `logins` as owners of `crate_name`, on
+/// behalf of `inviter_id`, giving a message saying what was done.
+///
+/// Shared by the owners endpoint and the web UI's owners page.
+pub fn add_crate_owners(
+    conn: &database::Connection,
+    crate_name: &str,
+    logins: &[String],
+    inviter_id: Option<i64>,
+) -> Result<String, ApiError> {
+    let current = database::list_crate_owners(conn, crate_name)?;
+    let mut msgs = vec![];
+    for owner in find_owners(conn, logins)? {
+        match owner {
+            // Nobody needs asking whether they want to add themselves.
+            Owner::User(user) if Some(user.id) == inviter_id => {
+                database::add_crate_owner(conn, crate_name, user.id)?;
+                msgs.push(format!("user {} has been added as an owner", user.login));
+            }
+            Owner::User(user) if current.contains(&user) => {
+                msgs.push(format!("user {} is already an owner", user.login));
+            }
+            Owner::User(user) => {
+                database::invite_crate_owner(conn, crate_name, user.id, inviter_id)?;
+                msgs.push(format!(
+                    "user {} has been invited to be an owner of crate {}",
+                    user.login, crate_name
+                ));
+            }
+            // Teams are made up by the operator, so there's nobody to ask.
+            Owner::Team(team) => {
+                database::add_crate_owner_team(conn, crate_name, team.id)?;
+                msgs.push(format!("team @{} has been added as an owner", team.name));
+            }
+        }
+    }
+    Ok(msgs.join(", "))
+}
+
+/// Remove the users and teams named by `logins` from the owners of
+/// `crate_name`, as long as that leaves it with some.
+pub fn remove_crate_owners(
+    conn: &database::Connection,
+    crate_name: &str,
+    logins: &[String],
+) -> Result<(), ApiError> {
+    let owners = find_owners(conn, logins)?;
+    let tx = conn.transaction()?;
+    let had_owners = database::has_owners(&tx, crate_name)?;
+    for owner in owners {
+        match owner {
+            Owner::User(user) => {
+                database::remove_crate_owner(&tx, crate_name, user.id)?;
+                // Or an invitation that hasn't been answered yet.
+                database::withdraw_invitation(&tx, crate_name, user.id)?;
+            }
+            Owner::Team(team) => {
+                database::remove_crate_owner_team(&tx, crate_name, team.id)?;
+            }
+        };
+    }
+    // Like crates.io, a crate that has owners can't be left without any.
+    if had_owners && !database::has_owners(&tx, crate_name)? {
+        return Err(ApiError::Rejected(
+            "cannot remove all owners of a crate".to_string(),
+        ));
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Look up the users and teams named in an owners request, failing on the
+/// first that doesn't exist.
+fn find_owners(conn: &database::Connection, logins: &[String]) -> Result<Vec<Owner>, ApiError> {
+    logins
+        .iter()
+        .map(|login| match login.strip_prefix('@') {
+            Some(name) => database::find_team(conn, name)?
+                .map(Owner::Team)
+                .ok_or_else(|| ApiError::Rejected(format!("could not find team `{}`", login))),
+            None => database::find_user(conn, login)?
+                .map(Owner::User)
+                .ok_or_else(|| {
+                    ApiError::Rejected(format!("could not find user with login `{}`", login))
+                }),
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct InvitationResponse {
+    crate_owner_invite: InvitationAnswer,
+}
+
+#[derive(Deserialize)]
+pub struct InvitationAnswer {
+    accepted: bool,
+}
+
+/// The user a token was issued to, who invitations are for.
+fn invitee(token: Option<Token>) -> Result<i64, ApiError> {
+    token.and_then(|token| token.user_id).ok_or_else(|| {
+        ApiError::Rejected("this token wasn't issued to a user, so has no invitations".to_string())
+    })
+}
+
+#[get("/crate_owner_invitations")]
+pub async fn list_invitations(request: HttpRequest, settings: web::Data<Settings>) -> ApiResponse {
+    let token = match auth::authorize_token(&request, &settings, Scope::Owners).await {
+        Ok(token) => token,
+        Err(resp) => return Ok(resp),
+    };
+    let user_id = invitee(token)?;
+    let invitations = settings
+        .with_db(move |conn| database::list_invitations(conn, user_id))
+        .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "crate_owner_invitations": invitations
+            .iter()
+            .map(|invitation| json!({
+                "crate_name": invitation.crate_name,
+                "invitee_id": invitation.invitee_id,
+                "inviter_username": invitation.inviter,
+                "created_at": invitation.created_at,
+            }))
+            .collect::<Vec<_>>()
+    })))
+}
+
+#[put("/crate_owner_invitations/{crate_name}")]
+pub async fn answer_invitation(
+    path: web::Path<CrateName>,
+    body: web::Json<InvitationResponse>,
+    request: HttpRequest,
+    settings: web::Data<Settings>,
+) -> ApiResponse {
+    let token = match auth::authorize_token(&request, &settings, Scope::Owners).await {
+        Ok(token) => token,
+        Err(resp) => return Ok(resp),
+    };
+    let user_id = invitee(token)?;
+    let accepted = body.crate_owner_invite.accepted;
+    let crate_name = path.crate_name.clone();
+    if !settings
+        .with_db(move |conn| database::answer_invitation(conn, &crate_name, user_id, accepted))
+        .await?
+    {
+        return Err(ApiError::Rejected(format!(
+            "no invitation to own `{}` was found",
+            path.crate_name
+        )));
+    }
+    Ok(HttpResponse::Ok().json(json!({
+        "crate_owner_invitation": { "crate_name": path.crate_name, "accepted": accepted }
+    })))
 }
 
 #[get("/{crate_name}/{version}/download")]
 pub async fn download(
     path: web::Path<Crate>,
+    request: HttpRequest,
+    package_index: web::Data<Mutex<PackageIndex>>,
     settings: web::Data<Settings>,
-) -> actix_web::Result<fs::NamedFile> {
-    let crate_file =
-        crate::storage::get_crate_file_path(&settings.crate_dir, &path.crate_name, &path.version);
-    log::debug!("serving `{}`", crate_file.display());
-    Ok(fs::NamedFile::open(crate_file)?)
+) -> actix_web::Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_read(&request, &settings).await {
+        return Ok(resp);
+    }
+
+    let (crate_name, version) = (path.crate_name.clone(), path.version.to_string());
+    let digest = settings
+        .with_db(move |conn| database::find_crate_file(conn, &crate_name, &version))
+        .await?;
+    let key = match digest {
+        Some(ref digest) => crate::storage::get_blob_key(digest),
+        None => crate::storage::get_crate_file_key(&path.crate_name, &path.version),
+    };
+    log::debug!("serving `{}`", key);
+
+    // What the file has to hash to, when downloads are verified. The digest
+    // recorded at publish is the index's `cksum`, so the index only needs
+    // reading for files from before digests were recorded.
+    let expected = match digest {
+        _ if !settings.verify_downloads => None,
+        Some(digest) => Some(Some(digest)),
+        None => Some(
+            package_index
+                .lock()
+                .unwrap()
+                .get_package_versions(&path.crate_name)
+                .map_err(EstuaryError::from)?
+                .into_iter()
+                .find(|pkg| pkg.vers == path.version)
+                .map(|pkg| pkg.cksum),
+        ),
+    };
+    let refuse = |key: String| {
+        log::error!(
+            "refusing to serve `{}`, which doesn't match its checksum",
+            key
+        );
+        Err(EstuaryError::Corrupt(key).into())
+    };
+
+    // Files on local disk are sent straight from the file, which also answers
+    // range requests.
+    if let Some(file) = settings.crate_store.local_path(&key) {
+        if let Some(expected) = expected {
+            let hashed = file.clone();
+            let actual = web::block(move || crate::storage::sha256_file(&hashed)).await?;
+            if expected != Some(actual) {
+                return refuse(key);
+            }
+        }
+        settings
+            .downloads
+            .count(&path.crate_name, &path.version.to_string());
+        return NamedFile::open(file)?
+            .set_content_type(header::ContentType::octet_stream().0)
+            .disable_content_disposition()
+            .into_response(&request);
+    }
+
+    // Anywhere else, the file is passed on as it arrives. It can only be
+    // checked once it's all been seen, so a corrupt one is cut off before its
+    // end instead.
+    let mut body = match settings.crate_store.get_stream(&key).await? {
+        Some(body) => body,
+        None => return Err(EstuaryError::NotFound.into()),
+    };
+    if let Some(expected) = expected {
+        body = crate::storage::verified(body, key, expected);
+    }
+    settings
+        .downloads
+        .count(&path.crate_name, &path.version.to_string());
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .streaming(body))
 }
 
 /// Query string params for the search endpoint.
@@ -212,78 +994,185 @@ pub async fn download(
 /// page number.
 ///
 /// <https://doc.rust-lang.org/nightly/cargo/reference/registries.html#search>
-#[derive(Deserialize, Debug)]
+#[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct SearchQuery {
     /// The search terms to match on.
-    q: String,
-    /// default=10, max=100.
+    ///
+    /// May be left out when filtering by `keyword` or `category`, to list
+    /// every crate with them.
+    #[serde(default)]
+    pub q: String,
+    /// default=10, max=100 ([MAX_PER_PAGE]).
     ///
     /// Note that `cargo` itself will clamp the value at 100 if the `--limit`
     /// flag is set to a higher number.
-    per_page: usize,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+    /// Which page of results to give, starting from 1 (the default).
+    ///
+    /// `cargo` doesn't ask for anything but the first, but the web UI does.
+    #[serde(default = "default_page")]
+    pub page: usize,
+    /// Only crates whose newest version has this keyword.
+    pub keyword: Option<String>,
+    /// Only crates whose newest version is in this category.
+    pub category: Option<String>,
+    /// Include crates whose every version is yanked, with their newest.
+    #[serde(default)]
+    pub include_yanked: bool,
+    /// `relevance` (the default), `newest`, `recently-updated`, `downloads`,
+    /// or `alphabetical`.
+    #[serde(default)]
+    pub sort: database::SearchSort,
+}
+
+fn default_per_page() -> usize {
+    10
+}
+
+/// The most results a page of search results has, however many are asked
+/// for (as many as cargo asks for).
+pub const MAX_PER_PAGE: usize = 100;
+
+fn default_page() -> usize {
+    1
 }
 
 #[derive(Serialize, Debug)]
 pub struct SearchResult {
-    name: String,
-    max_version: semver::Version,
-    description: String,
+    pub name: String,
+    pub max_version: semver::Version,
+    pub description: String,
+    /// When the newest version was published, if the database knows.
+    pub updated_at: Option<String>,
+    /// Whether every version is yanked (only when `include_yanked` is set).
+    pub yanked: bool,
+}
+
+/// What search shows for `crate_name`: its newest version that isn't yanked
+/// (or the newest of all, if they all are), and what's in its metadata.
+///
+/// The index is only read for crates whose versions were all published (or
+/// yanked) before the database kept track.
+fn find_latest(
+    conn: &database::Connection,
+    index: &Mutex<PackageIndex>,
+    crate_name: &str,
+) -> Result<Option<Latest>, ApiError> {
+    let latest = match database::find_latest_version(conn, crate_name)? {
+        Some((name, version)) => Some((name, version, false)),
+        None => index
+            .lock()
+            .unwrap()
+            .get_package_versions(crate_name)
+            .ok()
+            .and_then(|versions| {
+                let all_yanked = versions.iter().all(|pkg| pkg.yanked);
+                versions
+                    .into_iter()
+                    .filter(|pkg| all_yanked || !pkg.yanked)
+                    .max_by(|a, b| a.vers.cmp(&b.vers))
+                    .map(|pkg| (pkg.name, pkg.vers, all_yanked))
+            }),
+    };
+    let (name, version, yanked) = match latest {
+        Some(latest) => latest,
+        None => return Ok(None),
+    };
+    let metadata = database::find_metadata(conn, &name, &version.to_string())?.unwrap_or_default();
+    Ok(Some(Latest {
+        updated_at: database::find_updated_at(conn, &name)?,
+        name,
+        version,
+        yanked,
+        description: metadata.description,
+        keywords: metadata.keywords,
+        categories: metadata.categories,
+    }))
+}
+
+/// The page of crates matching `query` (in the order asked for), along with
+/// how many there are in all, for the search endpoint and the web UI.
+pub fn search_results(
+    conn: &database::Connection,
+    index: &Mutex<PackageIndex>,
+    cache: &SearchCache,
+    query: &SearchQuery,
+) -> Result<(usize, Vec<SearchResult>), ApiError> {
+    let filtered = query.keyword.is_some() || query.category.is_some();
+    let mut matches = if query.q.trim().is_empty() && filtered {
+        database::list_tagged_crates(conn, query.keyword.as_deref(), query.category.as_deref())?
+    } else {
+        database::search_crates(conn, &query.q)?
+    };
+    database::sort_crates(conn, &mut matches, query.sort)?;
+    // Filtered matches are only counted once they're known to pass.
+    let mut total = if filtered { 0 } else { matches.len() };
+    let per_page = query.per_page.min(MAX_PER_PAGE);
+    // Pages past the last are empty, however far past it they are.
+    let offset = query.page.saturating_sub(1).saturating_mul(per_page);
+    let end = offset.saturating_add(per_page);
+    let mut shown = 0;
+    let mut crates = vec![];
+    for name in &matches {
+        if shown == end && !filtered {
+            break;
+        }
+        let latest = cache.get_or_insert_with(name, || find_latest(conn, index, name))?;
+        // Those that are all yanked are left out, unless they're asked for.
+        let latest = match latest {
+            Some(latest) if !latest.yanked || query.include_yanked => latest,
+            _ => continue,
+        };
+        if filtered {
+            let tagged = |tags: &[String], tag: &Option<String>| match tag {
+                Some(tag) => tags.iter().any(|t| t.eq_ignore_ascii_case(tag)),
+                None => true,
+            };
+            if !tagged(&latest.keywords, &query.keyword)
+                || !tagged(&latest.categories, &query.category)
+            {
+                continue;
+            }
+            total += 1;
+        }
+        if shown >= offset && crates.len() < per_page {
+            crates.push(SearchResult {
+                name: latest.name,
+                max_version: latest.version,
+                description: latest.description.unwrap_or_default(),
+                updated_at: latest.updated_at,
+                yanked: latest.yanked,
+            });
+        }
+        shown += 1;
+    }
+    Ok((total, crates))
 }
 
 #[get("")]
 pub async fn search(
     query: web::Query<SearchQuery>,
+    request: HttpRequest,
     index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
 ) -> ApiResponse {
-    let index = index.lock().unwrap();
-    let names = index.list_crates()?;
-    let terms: Vec<&str> = query.q.split(&['-', '_', ' ', '\t'][..]).collect();
-    let mut matches: Vec<(&str, usize)> = names
-        .iter()
-        .filter_map(|name| {
-            let mut score = terms.iter().filter(|&&term| name.contains(term)).count();
-            if name == &query.q {
-                score += 100; // idk, if the search is an exact match, boost it.
-            }
-            if score > 0 {
-                Some((name.as_str(), score))
-            } else {
-                None
-            }
-        })
-        .collect();
-
-    let total_match_count = matches.len();
-    matches.sort_by_key(|(_, score)| 0_isize - *score as isize);
+    if let Err(resp) = auth::authorize_read(&request, &settings).await {
+        return Ok(resp);
+    }
 
-    let crates: Result<Vec<SearchResult>, _> = matches
-        .into_iter()
-        .map(|(name, _)| {
-            index.get_package_versions(name).map(|pkgs| {
-                pkgs.into_iter()
-                    .filter(|pkg| !pkg.yanked)
-                    .max_by(|a, b| a.vers.cmp(&b.vers))
-                    .map(|pkg| SearchResult {
-                        name: pkg.name,
-                        max_version: pkg.vers,
-                        // FIXME: need a db to hold on to this info
-                        description: String::new(),
-                    })
-            })
-        })
-        .filter_map(|res: Result<Option<_>, _>| match res {
-            // Errors should be propagated so we can deal with them in the
-            // handler body.
-            Err(e) => Some(Err(e)),
-            Ok(Some(pkg)) => Some(Ok(pkg)),
-            // filter out crates that don't have any unyanked versions.
-            Ok(None) => None,
-        })
-        .take(query.per_page)
-        .collect();
+    let (query, index, cache) = (
+        query.into_inner(),
+        index.clone(),
+        settings.search_cache.clone(),
+    );
+    let (total_match_count, crates) = with_db(&settings, move |conn| {
+        search_results(conn, &index, &cache, &query)
+    })
+    .await?;
 
     Ok(HttpResponse::Ok().json(json!({
-    "crates": crates?,
+    "crates": crates,
     "meta": {
         "total": total_match_count
     }
@@ -292,22 +1181,34 @@ pub async fn search(
 
 #[cfg(test)]
 mod tests {
+    use super::PayloadReader;
+    use crate::auth::Scope;
+    use crate::database;
+    use crate::deletion;
+    use crate::manifest::CRATES_IO_INDEX;
+    use crate::package_index::Author;
+    use crate::publish_policy::{name_pattern, PublishPolicy};
     use crate::test_helpers;
     use crate::test_helpers::MY_CRATE_0_1_0;
-    use actix_web::http::StatusCode;
-    use actix_web::{test, App};
+    use crate::Settings;
+    use actix_web::error::PayloadError;
+    use actix_web::http::{header, Method, StatusCode};
+    use actix_web::web::Bytes;
+    use actix_web::{test, web, App};
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
 
     #[actix_rt::test]
     async fn test_publish() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
 
@@ -318,19 +1219,26 @@ mod tests {
 
         let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
         assert!(!resp.as_object().unwrap().contains_key("errors"));
+
+        // What isn't in the index is kept in the database.
+        let metadata = database::find_metadata(&settings.get_db().unwrap(), "my-crate", "0.1.0")
+            .unwrap()
+            .unwrap();
+        assert_eq!(vec!["Owen Nelson <onelson@gmail.com>"], metadata.authors);
+        assert_eq!(None, metadata.description);
     }
 
     #[actix_rt::test]
     async fn test_publish_twice_is_error() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
 
@@ -356,121 +1264,1492 @@ mod tests {
     }
 
     #[actix_rt::test]
-    async fn test_yank() {
+    async fn test_publish_deleted_version() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
 
-        // Publish (so we can yank)
         let req = test::TestRequest::put()
             .uri("/api/v1/crates/new")
             .set_payload(MY_CRATE_0_1_0)
             .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(!resp.as_object().unwrap().contains_key("errors"));
 
-        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+        deletion::remove_version(
+            &package_index.lock().unwrap(),
+            &settings.get_db().unwrap(),
+            "my-crate",
+            &"0.1.0".parse().unwrap(),
+            Some("published by mistake"),
+            &Author::default(),
+        )
+        .unwrap();
 
-        let req = test::TestRequest::delete()
-            .uri("/api/v1/crates/my-crate/0.1.0/yank")
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
             .to_request();
-
         let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
-        assert!(resp["ok"].as_bool().unwrap());
+        let detail = resp["errors"][0]["detail"].as_str().unwrap();
+        assert!(
+            detail.starts_with("`my-crate` 0.1.0 was deleted on "),
+            "{}",
+            detail
+        );
+        assert!(
+            detail.ends_with(
+                " (published by mistake) and can't be published again; publish a new version \
+                instead"
+            ),
+            "{}",
+            detail
+        );
+        assert!(package_index
+            .lock()
+            .unwrap()
+            .get_package_versions("my-crate")
+            .map_or(true, |versions| versions.is_empty()));
     }
 
     #[actix_rt::test]
-    async fn test_unyank() {
+    async fn test_publish_checks_name() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
+        let publish = |name: &str| {
+            let body = test_helpers::publish_body(
+                json!({"name": name, "vers": "0.1.0", "deps": [], "features": {}}),
+                &format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\n", name),
+            );
+            test::TestRequest::put()
+                .uri("/api/v1/crates/new")
+                .set_payload(body)
+                .to_request()
+        };
+
+        let body: serde_json::Value = test::read_response_json(&mut app, publish("my crate")).await;
+        assert!(body["errors"][0]["detail"]
+            .as_str()
+            .unwrap()
+            .contains("` ` isn't allowed"));
+
+        let body: serde_json::Value = test::read_response_json(&mut app, publish("my-crate")).await;
+        assert!(body.get("errors").is_none());
+        // Cargo would see these as the same crate.
+        for name in &["My-Crate", "my_crate"] {
+            let body: serde_json::Value = test::read_response_json(&mut app, publish(name)).await;
+            assert!(
+                body["errors"][0]["detail"]
+                    .as_str()
+                    .unwrap()
+                    .contains("the existing crate `my-crate`"),
+                "{}",
+                name
+            );
+        }
+        assert!(package_index
+            .lock()
+            .unwrap()
+            .list_crates()
+            .unwrap()
+            .eq(&["my-crate"]));
+        // What was written to the database for them went with them.
+        let recorded = database::list_versions(&settings.get_db().unwrap()).unwrap();
+        assert_eq!(1, recorded.len());
+        assert_eq!("my-crate", recorded[0].crate_name);
+    }
 
-        // Publish (so we can yank)
-        let req = test::TestRequest::put()
-            .uri("/api/v1/crates/new")
-            .set_payload(MY_CRATE_0_1_0)
-            .to_request();
-
-        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
-
-        let req = test::TestRequest::delete()
-            .uri("/api/v1/crates/my-crate/0.1.0/yank")
-            .to_request();
-
-        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
-
-        let req = test::TestRequest::put()
-            .uri("/api/v1/crates/my-crate/0.1.0/unyank")
-            .to_request();
+    #[actix_rt::test]
+    async fn test_publish_checks_crate_file() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
-        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
-        assert!(resp["ok"].as_bool().unwrap());
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let publish = |body: Vec<u8>| {
+            test::TestRequest::put()
+                .uri("/api/v1/crates/new")
+                .set_payload(body)
+                .to_request()
+        };
+        let error =
+            |body: serde_json::Value| body["errors"][0]["detail"].as_str().unwrap().to_string();
+
+        let body = test_helpers::with_metadata(MY_CRATE_0_1_0, |m| m["vers"] = json!("0.2.0"));
+        let body: serde_json::Value = test::read_response_json(&mut app, publish(body)).await;
+        assert!(error(body).ends_with("it differs in the version (`0.1.0` in `Cargo.toml`)"));
+
+        let body = test_helpers::with_metadata(MY_CRATE_0_1_0, |m| {
+            m["deps"].as_array_mut().unwrap().pop();
+            m["features"] = json!({"extra": ["chrono"]});
+        });
+        let body: serde_json::Value = test::read_response_json(&mut app, publish(body)).await;
+        assert!(error(body).ends_with("the dependencies on `uuid`, the features"));
+
+        // Past the gzip header.
+        let mut body = MY_CRATE_0_1_0.to_vec();
+        let start = body.len() - test_helpers::crate_file(MY_CRATE_0_1_0).len() + 10;
+        body[start..start + 100].iter_mut().for_each(|b| *b = 0xff);
+        let body: serde_json::Value = test::read_response_json(&mut app, publish(body)).await;
+        assert!(error(body).starts_with("the crate file can't be read"));
+        assert!(package_index
+            .lock()
+            .unwrap()
+            .list_crates()
+            .unwrap()
+            .is_empty());
+
+        // Cargo sends renamed dependencies under the package's name.
+        let body = test_helpers::publish_body(
+            json!({
+                "name": "renames",
+                "vers": "1.0.0",
+                "deps": [{
+                    "name": "serde",
+                    "explicit_name_in_toml": "serde1",
+                    "version_req": "^1",
+                    "features": [],
+                    "optional": false,
+                    "default_features": true,
+                    "target": null,
+                    "kind": "normal",
+                    "registry": "https://github.com/rust-lang/crates.io-index"
+                }],
+                "features": {}
+            }),
+            "[package]\nname = \"renames\"\nversion = \"1.0.0\"\n\n\
+             [dependencies.serde1]\nversion = \"1\"\npackage = \"serde\"\n",
+        );
+        let body: serde_json::Value = test::read_response_json(&mut app, publish(body)).await;
+        assert!(body.get("errors").is_none(), "{}", body);
+        let deps = package_index
+            .lock()
+            .unwrap()
+            .get_package_versions("renames")
+            .unwrap()
+            .remove(0)
+            .deps;
+        assert_eq!("serde1", deps[0].name);
+        assert_eq!(Some("serde".to_string()), deps[0].package);
+
+        // The same requirement and target, written differently.
+        let body = test_helpers::publish_body(
+            json!({
+                "name": "spelled",
+                "vers": "1.0.0",
+                "deps": [{
+                    "name": "libc",
+                    "version_req": "0.*",
+                    "features": [],
+                    "optional": false,
+                    "default_features": true,
+                    "target": "cfg(target_os = \"linux\")",
+                    "kind": "normal",
+                    "registry": "https://github.com/rust-lang/crates.io-index"
+                }],
+                "features": {}
+            }),
+            "[package]\nname = \"spelled\"\nversion = \"1.0.0\"\n\n\
+             [target.'cfg(target_os=\"linux\")'.dependencies]\nlibc = \"0.x\"\n",
+        );
+        let body: serde_json::Value = test::read_response_json(&mut app, publish(body)).await;
+        assert!(body.get("errors").is_none(), "{}", body);
     }
 
     #[actix_rt::test]
-    async fn test_download_existing_crate_is_ok() {
+    async fn test_publish_policy() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = web::Data::new(Settings {
+            publish_policy: PublishPolicy {
+                deny_wildcard_deps: true,
+                dep_registries: vec![CRATES_IO_INDEX.to_string()],
+                denied_names: vec![name_pattern("other-*").unwrap()],
+                ..Default::default()
+            },
+            ..(**test_helpers::get_test_settings(data_root.path())).clone()
+        });
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
-
-        // Publish (so we can download)
+        let publish = |req: &str, registry: &str| {
+            let body = test_helpers::publish_body(
+                json!({
+                    "name": "my-crate",
+                    "vers": "0.1.0",
+                    "deps": [{
+                        "name": "serde",
+                        "version_req": req,
+                        "features": [],
+                        "optional": false,
+                        "default_features": true,
+                        "target": null,
+                        "kind": "normal",
+                        "registry": registry
+                    }],
+                    "features": {}
+                }),
+                &format!(
+                    "[package]\nname = \"my-crate\"\nversion = \"0.1.0\"\n\n\
+                     [dependencies.serde]\nversion = \"{}\"\nregistry-index = \"{}\"\n",
+                    req, registry
+                ),
+            );
+            test::TestRequest::put()
+                .uri("/api/v1/crates/new")
+                .set_payload(body)
+                .to_request()
+        };
+
+        let body =
+            test_helpers::with_metadata(MY_CRATE_0_1_0, |m| m["name"] = json!("other_crate"));
         let req = test::TestRequest::put()
             .uri("/api/v1/crates/new")
-            .set_payload(MY_CRATE_0_1_0)
+            .set_payload(body)
             .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            "the name `other_crate` is reserved",
+            body["errors"][0]["detail"]
+        );
+
+        let body: serde_json::Value =
+            test::read_response_json(&mut app, publish("*", CRATES_IO_INDEX)).await;
+        assert!(body["errors"][0]["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("wildcard dependencies aren't allowed"));
+        let body: serde_json::Value =
+            test::read_response_json(&mut app, publish("^1", "https://example.com/index")).await;
+        assert!(body["errors"][0]["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("dependencies can't come from `https://example.com/index`"));
+        let body: serde_json::Value =
+            test::read_response_json(&mut app, publish("^1", CRATES_IO_INDEX)).await;
+        assert!(body.get("errors").is_none(), "{}", body);
+
+        // Crates that were in the index before their name was denied can
+        // still get new versions.
+        let old = crate::package_index::PackageVersion {
+            name: "other-crate".to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+        package_index
+            .lock()
+            .unwrap()
+            .publish(&old, &Author::default())
+            .unwrap();
+        let body = test_helpers::publish_body(
+            json!({"name": "other-crate", "vers": "0.2.0", "deps": [], "features": {}}),
+            "[package]\nname = \"other-crate\"\nversion = \"0.2.0\"\n",
+        );
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(body)
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(body.get("errors").is_none(), "{}", body);
+    }
+
+    #[actix_rt::test]
+    async fn test_publish_warnings() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            publish_policy: PublishPolicy {
+                categories: Some(vec!["development-tools".to_string()].into_iter().collect()),
+                ..Default::default()
+            },
+            ..(**test_helpers::get_test_settings(data_root.path())).clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let body = test_helpers::with_metadata(MY_CRATE_0_1_0, |m| {
+            m["categories"] = json!(["Development-Tools", "made-up"]);
+            m["badges"] = json!({
+                "maintenance": {"status": "experimental"},
+                "travis-ci": {},
+                "bitbucket-pipelines": {"repository": "acme/my-crate", "branch": "main"}
+            });
+        });
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(body)
+            .to_request();
+
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            json!({
+                "invalid_categories": ["made-up"],
+                "invalid_badges": ["travis-ci"],
+                "other": ["the `travis-ci` badge needs a `repository`"]
+            }),
+            resp["warnings"]
+        );
+        // The invalid ones are left out, and the others go by the list's slug.
+        let metadata = database::find_metadata(&settings.get_db().unwrap(), "my-crate", "0.1.0")
+            .unwrap()
+            .unwrap();
+        assert_eq!(vec!["development-tools"], metadata.categories);
+    }
+
+    #[actix_rt::test]
+    async fn test_yank() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        // Publish (so we can yank)
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::delete()
+            .uri("/api/v1/crates/my-crate/0.1.0/yank")
+            .to_request();
+
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(resp["ok"].as_bool().unwrap());
+        let versions = database::list_versions(&settings.get_db().unwrap()).unwrap();
+        assert!(versions[0].yanked_at.is_some());
+    }
+
+    #[actix_rt::test]
+    async fn test_unyank() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        // Publish (so we can yank)
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::delete()
+            .uri("/api/v1/crates/my-crate/0.1.0/yank")
+            .to_request();
+
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/my-crate/0.1.0/unyank")
+            .to_request();
+
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(resp["ok"].as_bool().unwrap());
+        let versions = database::list_versions(&settings.get_db().unwrap()).unwrap();
+        assert_eq!(None, versions[0].yanked_at);
+    }
+
+    #[actix_rt::test]
+    async fn test_yank_updates_search() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            search_cache: Default::default(),
+            ..(**test_helpers::get_test_settings(data_root.path())).clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+        let search = || {
+            test::TestRequest::get()
+                .uri("/api/v1/crates?q=my-crate&per_page=10")
+                .to_request()
+        };
+        let resp: serde_json::Value = test::read_response_json(&mut app, search()).await;
+        assert_eq!(1, resp["crates"].as_array().unwrap().len());
+
+        // Under a name cargo counts as the same, the published crate's entry
+        // is the one dropped.
+        for (method, uri, total) in &[
+            (Method::DELETE, "/api/v1/crates/My_Crate/0.1.0/yank", 0),
+            (Method::PUT, "/api/v1/crates/my_crate/0.1.0/unyank", 1),
+        ] {
+            let req = test::TestRequest::with_uri(uri)
+                .method(method.clone())
+                .to_request();
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert!(resp["ok"].as_bool().unwrap());
+            let resp: serde_json::Value = test::read_response_json(&mut app, search()).await;
+            assert_eq!(*total, resp["crates"].as_array().unwrap().len(), "{}", uri);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_search() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates?q=crate&per_page=10")
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, resp["meta"]["total"]);
+        assert_eq!("my-crate", resp["crates"][0]["name"]);
+        assert_eq!("0.1.0", resp["crates"][0]["max_version"]);
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates?q=nothing&per_page=10")
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(0, resp["meta"]["total"]);
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates?q=crate&per_page=10&sort=recently-updated")
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!("my-crate", resp["crates"][0]["name"]);
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates?q=crate&per_page=1&page=2")
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, resp["meta"]["total"]);
+        assert!(resp["crates"].as_array().unwrap().is_empty());
+        // However big the numbers asked for.
+        let req = test::TestRequest::get()
+            .uri(&format!(
+                "/api/v1/crates?q=crate&per_page={}&page={}",
+                usize::MAX,
+                usize::MAX
+            ))
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, resp["meta"]["total"]);
+        assert!(resp["crates"].as_array().unwrap().is_empty());
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/v1/crates?q=crate&per_page={}", usize::MAX))
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!("my-crate", resp["crates"][0]["name"]);
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates?q=crate&per_page=10&sort=random")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        // Filtered by keyword or category, with or without search terms.
+        let metadata = database::CrateMetadata {
+            keywords: vec!["internal-sdk".to_string()],
+            categories: vec!["development-tools".to_string()],
+            ..Default::default()
+        };
+        database::record_metadata(&settings.get_db().unwrap(), "my-crate", "0.1.0", &metadata)
+            .unwrap();
+        for (uri, total) in &[
+            ("/api/v1/crates?keyword=internal-sdk&per_page=10", 1),
+            ("/api/v1/crates?q=my&keyword=Internal-SDK&per_page=10", 1),
+            ("/api/v1/crates?category=development-tools&per_page=10", 1),
+            ("/api/v1/crates?q=my&keyword=proc-macro&per_page=10", 0),
+            (
+                "/api/v1/crates?keyword=internal-sdk&category=no-std&per_page=10",
+                0,
+            ),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!(*total, resp["meta"]["total"], "{}", uri);
+            assert_eq!(*total, resp["crates"].as_array().unwrap().len(), "{}", uri);
+        }
+
+        // Crates from before the database kept their versions are read from
+        // the index.
+        settings
+            .get_db()
+            .unwrap()
+            .execute("DELETE FROM crate_versions", &[])
+            .unwrap();
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates?q=my-crate&per_page=10")
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!("0.1.0", resp["crates"][0]["max_version"]);
+
+        // Crates with nothing left to install aren't shown.
+        let req = test::TestRequest::delete()
+            .uri("/api/v1/crates/my-crate/0.1.0/yank")
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates?q=my-crate&per_page=10")
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(resp["crates"].as_array().unwrap().is_empty());
+        // Unless they're asked for.
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates?q=my-crate&per_page=10&include_yanked=true")
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!("0.1.0", resp["crates"][0]["max_version"]);
+        assert_eq!(true, resp["crates"][0]["yanked"]);
+    }
+
+    #[actix_rt::test]
+    async fn test_download_existing_crate_is_ok() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        // Publish (so we can download)
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates/my-crate/0.1.0/download")
+            .to_request();
+
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        // Counted, once the counts are written out.
+        let conn = settings.get_db().unwrap();
+        assert_eq!(
+            0,
+            database::count_downloads(&conn, "my-crate", None).unwrap()
+        );
+        settings.downloads.flush(&conn).unwrap();
+        assert_eq!(
+            1,
+            database::count_downloads(&conn, "my-crate", None).unwrap()
+        );
+
+        // Interrupted downloads can pick up where they left off.
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates/my-crate/0.1.0/download")
+            .header("range", "bytes=0-9")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::PARTIAL_CONTENT, resp.status());
+        assert_eq!(10, test::read_body(resp).await.len());
+    }
+
+    #[actix_rt::test]
+    async fn test_payload_reader() {
+        // However the body happens to be split up.
+        let chunks: Vec<Result<Bytes, PayloadError>> = vec![
+            Ok(Bytes::from_static(&[3, 0])),
+            Ok(Bytes::from_static(&[0, 0, b'a', b'b'])),
+            Ok(Bytes::from_static(b"cdef")),
+        ];
+        let mut payload = PayloadReader::new(futures_util::stream::iter(chunks));
+        assert_eq!(3, payload.read_u32().await.unwrap());
+        let data_root = test_helpers::get_data_root();
+        let out = crate::storage::TempFile::create(data_root.path()).unwrap();
+        let digest = payload.copy_to(3, &out).await.unwrap();
+        assert_eq!(b"abc".to_vec(), std::fs::read(out.path()).unwrap());
+        assert_eq!(format!("{:x}", Sha256::digest(b"abc")), digest);
+        assert_eq!(
+            Bytes::from_static(b"de"),
+            payload.read_exact(2).await.unwrap()
+        );
+        // There's only the one byte left.
+        assert!(payload.read_exact(2).await.is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_crate_files_are_kept_by_digest() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let digest = database::find_crate_file(&settings.get_db().unwrap(), "my-crate", "0.1.0")
+            .unwrap()
+            .unwrap();
+        let keys = settings.crate_store.list("").await.unwrap();
+        assert_eq!(vec![crate::storage::get_blob_key(&digest)], keys);
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates/my-crate/0.1.0/download")
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        assert_eq!(digest, format!("{:x}", Sha256::digest(&body)));
+
+        // Files from before there were digests are still where they were.
+        std::fs::create_dir_all(settings.crate_dir.join("old-crate")).unwrap();
+        std::fs::write(
+            settings.crate_dir.join("old-crate/old-crate-1.0.0.crate"),
+            b"old",
+        )
+        .unwrap();
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates/old-crate/1.0.0/download")
+            .to_request();
+        assert_eq!(&b"old"[..], test::read_response(&mut app, req).await);
+    }
+
+    #[actix_rt::test]
+    async fn test_publish_quotas() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            crate_quota: Some(64 * 1024),
+            registry_quota: Some(128 * 1024),
+            ..(**test_helpers::get_test_settings(data_root.path())).clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let publish = || {
+            test::TestRequest::put()
+                .uri("/api/v1/crates/new")
+                .set_payload(MY_CRATE_0_1_0)
+                .to_request()
+        };
+
+        let conn = settings.get_db().unwrap();
+        database::record_crate_file(&conn, "my-crate", "0.0.1", "abc", 64 * 1024).unwrap();
+        let body: serde_json::Value = test::read_response_json(&mut app, publish()).await;
+        assert!(body["errors"][0]["detail"]
+            .as_str()
+            .unwrap()
+            .contains("of its 65536 byte quota"));
+
+        database::record_crate_file(&conn, "my-crate", "0.0.1", "abc", 0).unwrap();
+        database::record_crate_file(&conn, "other-crate", "1.0.0", "def", 128 * 1024).unwrap();
+        let body: serde_json::Value = test::read_response_json(&mut app, publish()).await;
+        assert!(body["errors"][0]["detail"]
+            .as_str()
+            .unwrap()
+            .contains("the registry is full"));
+
+        database::record_crate_file(&conn, "other-crate", "1.0.0", "def", 0).unwrap();
+        let body: serde_json::Value = test::read_response_json(&mut app, publish()).await;
+        assert!(body.get("errors").is_none());
+        assert!(database::crate_usage(&conn, "my-crate").unwrap() > 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_publish_max_crate_size() {
+        let data_root = test_helpers::get_data_root();
+        let crate_len = test_helpers::crate_file(MY_CRATE_0_1_0).len() as u64;
+        let settings = web::Data::new(Settings {
+            max_crate_size: Some(crate_len - 1),
+            ..(**test_helpers::get_test_settings(data_root.path())).clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            format!(
+                "the crate file is {} bytes, over the registry's {} byte limit",
+                crate_len,
+                crate_len - 1
+            ),
+            body["errors"][0]["detail"]
+        );
+        assert!(package_index
+            .lock()
+            .unwrap()
+            .list_crates()
+            .unwrap()
+            .is_empty());
+        assert!(std::fs::read_dir(settings.crate_dir.join("uploads"))
+            .map_or(true, |mut files| files.next().is_none()));
+
+        // Going by the length of the request alone.
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header(header::CONTENT_LENGTH, "1000000000")
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(body["errors"][0]["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("the request is 1000000000 bytes"));
+    }
+
+    #[actix_rt::test]
+    async fn test_download_verifies_checksum() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            verify_downloads: true,
+            ..(**test_helpers::get_test_settings(data_root.path())).clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+        let download = || {
+            test::TestRequest::get()
+                .uri("/api/v1/crates/my-crate/0.1.0/download")
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, download()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        // Bit-rot.
+        let key = settings.crate_store.list("").await.unwrap().pop().unwrap();
+        std::fs::write(settings.crate_dir.join(key), b"rotten").unwrap();
+        let resp = test::call_service(&mut app, download()).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_download_nonexistent_crate_is_not_found() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
-        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
 
+        // No crates have been published
         let req = test::TestRequest::get()
             .uri("/api/v1/crates/my-crate/0.1.0/download")
             .to_request();
 
         let resp = test::call_service(&mut app, req).await;
-        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
     }
 
     #[actix_rt::test]
-    async fn test_download_nonexistent_crate_is_not_found() {
+    async fn test_publish_without_token_is_challenged() {
         let data_root = test_helpers::get_data_root();
-        let settings = test_helpers::get_test_settings(&data_root.path());
+        let settings = test_helpers::get_test_settings(data_root.path());
+        test_helpers::add_token(&settings, "ci", "secret");
         let package_index = test_helpers::get_test_package_index(&settings.index_dir);
 
         let mut app = test::init_service(
             App::new()
                 .app_data(settings.clone())
                 .app_data(package_index.clone())
-                .configure(crate::handlers::configure_routes),
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
         )
         .await;
 
-        // No crates have been published
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+        assert!(resp
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("Cargo login_url="));
+    }
+
+    #[actix_rt::test]
+    async fn test_publish_id_token_needs_trusted_publisher() {
+        use crate::auth::trusted::Provider;
+
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        crate::database::add_trusted_publisher(
+            &settings.get_db().unwrap(),
+            &crate::database::TrustedPublisher {
+                id: 0,
+                crate_name: "other-crate".to_string(),
+                provider: Provider::GitHub,
+                issuer: Provider::GitHub.default_issuer().to_string(),
+                repository: "example/other-crate".to_string(),
+                workflow: None,
+                branch: None,
+            },
+        )
+        .unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let provider = crate::auth::oidc::tests::Provider::new();
+        settings
+            .jwks_cache
+            .insert(Provider::GitHub.default_issuer(), provider.jwks.clone());
+        let mut claims = crate::auth::oidc::tests::Provider::claims("");
+        claims["iss"] = json!(Provider::GitHub.default_issuer());
+        claims["aud"] = json!(settings.base_url);
+        claims["repository"] = json!("example/other-crate");
+
+        // Issuers no crate trusts are turned away before the body is read.
+        let mut untrusted = claims.clone();
+        untrusted["iss"] = json!("https://gitlab.example.com");
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", provider.sign(untrusted))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["errors"][0]["detail"]
+            .as_str()
+            .unwrap()
+            .contains("No crate has trusted publishers from"));
+
+        let id_token = provider.sign(claims);
+
+        // Trusted publishers for one crate can't publish another.
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", id_token)
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["errors"][0]["detail"]
+            .as_str()
+            .unwrap()
+            .contains("`my-crate` has no trusted publishers"));
+    }
+
+    #[actix_rt::test]
+    async fn test_download_auth_required() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            auth_required: true,
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        test_helpers::add_token(&settings, "ci", "secret");
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", "secret")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/crates/my-crate/0.1.0/download")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
         let req = test::TestRequest::get()
             .uri("/api/v1/crates/my-crate/0.1.0/download")
+            .header("authorization", "secret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_publish_only_token_cannot_yank() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        crate::database::set_token(
+            &settings.get_db().unwrap(),
+            "ci",
+            "secret",
+            &[Scope::Publish],
+        )
+        .unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", "secret")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let req = test::TestRequest::delete()
+            .uri("/api/v1/crates/my-crate/0.1.0/yank")
+            .header("authorization", "secret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_publish_needs_grant() {
+        use crate::database::Grantee;
+
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        test_helpers::add_token(&settings, "team-a", "secret-a");
+        test_helpers::add_token(&settings, "team-b", "secret-b");
+        crate::database::add_grant(
+            &settings.get_db().unwrap(),
+            "my-*",
+            &Grantee::Token("team-a".to_string()),
+        )
+        .unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", "secret-b")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", "secret-a")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let req = test::TestRequest::delete()
+            .uri("/api/v1/crates/my-crate/0.1.0/yank")
+            .header("authorization", "secret-b")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let req = test::TestRequest::delete()
+            .uri("/api/v1/crates/my-crate/0.1.0/yank")
+            .header("authorization", "secret-a")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_publish_needs_ownership() {
+        use crate::auth::Scope;
+        use crate::database;
+
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        let publisher_scopes = [Scope::Publish, Scope::Yank, Scope::Owners];
+        let alice = database::find_or_create_user(&conn, "alice").unwrap();
+        let bob = database::find_or_create_user(&conn, "bob").unwrap();
+        database::create_token(
+            &conn,
+            "alice",
+            "alice-secret",
+            &publisher_scopes,
+            Some(alice.id),
+        )
+        .unwrap();
+        database::create_token(&conn, "bob", "bob-secret", &publisher_scopes, Some(bob.id))
+            .unwrap();
+        database::create_token(&conn, "bob-admin", "admin-secret", Scope::ALL, Some(bob.id))
+            .unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        // Nobody owns it yet, so alice gets to, by publishing it first.
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", "alice-secret")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert!(database::is_crate_owner(&conn, "my-crate", alice.id).unwrap());
+        let yank = |token: &str| {
+            test::TestRequest::delete()
+                .uri("/api/v1/crates/my-crate/0.1.0/yank")
+                .header("authorization", token)
+                .to_request()
+        };
+
+        let resp = test::call_service(&mut app, yank("bob-secret")).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            "crate `my-crate` exists, but you don't seem to be an owner.",
+            body["errors"][0]["detail"]
+        );
+
+        // However the name's spelled, it's the published crate's owners who
+        // get a say.
+        for name in &["My_Crate", "my_crate"] {
+            let req = test::TestRequest::delete()
+                .uri(&format!("/api/v1/crates/{}/0.1.0/yank", name))
+                .header("authorization", "bob-secret")
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        }
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/My_Crate/owners")
+            .header("authorization", "bob-secret")
+            .set_json(&json!({ "users": ["bob"] }))
             .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        assert!(!database::is_crate_owner(&conn, "my-crate", bob.id).unwrap());
+        assert!(!database::is_crate_owner(&conn, "My_Crate", bob.id).unwrap());
+        let versions = package_index
+            .lock()
+            .unwrap()
+            .get_package_versions("my-crate")
+            .unwrap();
+        assert!(!versions[0].yanked);
 
+        let req = test::TestRequest::delete()
+            .uri("/api/v1/crates/no-such-crate/0.1.0/yank")
+            .header("authorization", "bob-secret")
+            .to_request();
         let resp = test::call_service(&mut app, req).await;
         assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        let resp = test::call_service(&mut app, yank("alice-secret")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let resp = test::call_service(&mut app, yank("admin-secret")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        // Admins' tokens can act on any crate, with the scopes they were issued.
+        database::set_admin(&conn, "bob", true).unwrap();
+        let resp = test::call_service(&mut app, yank("bob-secret")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_publish_owned_crate() {
+        use crate::auth::Scope;
+        use crate::database;
+
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        let alice = database::find_or_create_user(&conn, "alice").unwrap();
+        let bob = database::find_or_create_user(&conn, "bob").unwrap();
+        database::create_token(
+            &conn,
+            "alice",
+            "alice-secret",
+            &[Scope::Publish],
+            Some(alice.id),
+        )
+        .unwrap();
+        database::create_token(&conn, "bob", "bob-secret", &[Scope::Publish], Some(bob.id))
+            .unwrap();
+        database::add_crate_owner(&conn, "my-crate", alice.id).unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let publish = |token: &str| {
+            test::TestRequest::put()
+                .uri("/api/v1/crates/new")
+                .header("authorization", token)
+                .set_payload(MY_CRATE_0_1_0)
+                .to_request()
+        };
+        // Owners would be pointless if anyone could publish over them.
+        let resp = test::call_service(&mut app, publish("bob-secret")).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        let resp = test::call_service(&mut app, publish("alice-secret")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_rate_limit() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            rate_limiter: std::sync::Arc::new(crate::rate_limit::RateLimiter::new(Some(1))),
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        test_helpers::add_token(&settings, "ci", "secret");
+        test_helpers::add_token(&settings, "alice", "alice-secret");
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", "secret")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let yank = |token: &str| {
+            test::TestRequest::delete()
+                .uri("/api/v1/crates/my-crate/0.1.0/yank")
+                .header("authorization", token)
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, yank("secret")).await;
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, resp.status());
+        assert!(resp.headers().contains_key(header::RETRY_AFTER));
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["errors"][0]["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("Too many requests"));
+
+        // Other tokens aren't held up.
+        let resp = test::call_service(&mut app, yank("alice-secret")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_owners() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        let alice = crate::database::find_or_create_user(&conn, "alice").unwrap();
+        let bob = crate::database::find_or_create_user(&conn, "bob").unwrap();
+        crate::database::create_team(&conn, "backend").unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let owners = |method: Method, users: serde_json::Value| {
+            test::TestRequest::with_uri("/api/v1/crates/my-crate/owners")
+                .method(method)
+                .set_json(&json!({ "users": users }))
+                .to_request()
+        };
+        let list = || {
+            test::TestRequest::get()
+                .uri("/api/v1/crates/my-crate/owners")
+                .to_request()
+        };
+
+        let resp = test::call_service(&mut app, owners(Method::PUT, json!(["alice", "bob"]))).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(json!(true), body["ok"]);
+        assert_eq!(
+            "user alice has been invited to be an owner of crate my-crate, \
+            user bob has been invited to be an owner of crate my-crate",
+            body["msg"]
+        );
+
+        // They're only owners once they've accepted.
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&mut app, list()).await).await;
+        assert_eq!(json!([]), body["users"]);
+        for user in &[&alice, &bob] {
+            crate::database::answer_invitation(&conn, "my-crate", user.id, true).unwrap();
+        }
+
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&mut app, list()).await).await;
+        let logins: Vec<_> = body["users"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|user| user["login"].as_str().unwrap())
+            .collect();
+        assert_eq!(vec!["alice", "bob"], logins);
+
+        let resp = test::call_service(&mut app, owners(Method::PUT, json!(["@backend"]))).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(json!(true), body["ok"]);
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&mut app, list()).await).await;
+        assert_eq!(json!("@backend"), body["users"][2]["login"]);
+        assert_eq!(json!("team"), body["users"][2]["kind"]);
+        let resp = test::call_service(&mut app, owners(Method::PUT, json!(["@frontend"]))).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            "could not find team `@frontend`",
+            body["errors"][0]["detail"]
+        );
+        let resp = test::call_service(&mut app, owners(Method::DELETE, json!(["@backend"]))).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(json!(true), body["ok"]);
+
+        // Unknown users are reported the way cargo expects.
+        let resp = test::call_service(&mut app, owners(Method::PUT, json!(["carol"]))).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            "could not find user with login `carol`",
+            body["errors"][0]["detail"]
+        );
+
+        let resp = test::call_service(&mut app, owners(Method::DELETE, json!(["bob"]))).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(json!(true), body["ok"]);
+
+        // The last owner can't be removed.
+        let resp = test::call_service(&mut app, owners(Method::DELETE, json!(["alice"]))).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["errors"][0]["detail"].is_string());
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&mut app, list()).await).await;
+        assert_eq!(1, body["users"].as_array().unwrap().len());
+    }
+
+    #[actix_rt::test]
+    async fn test_invitations() {
+        use crate::auth::Scope;
+        use crate::database;
+
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        let alice = database::find_or_create_user(&conn, "alice").unwrap();
+        let bob = database::find_or_create_user(&conn, "bob").unwrap();
+        database::create_token(&conn, "alice", "alice-secret", Scope::ALL, Some(alice.id)).unwrap();
+        database::create_token(&conn, "bob", "bob-secret", Scope::ALL, Some(bob.id)).unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", "alice-secret")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        // Adding yourself needs no invitation.
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/my-crate/owners")
+            .header("authorization", "alice-secret")
+            .set_json(&json!({ "users": ["alice", "bob"] }))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(json!(true), body["ok"]);
+        assert!(database::is_crate_owner(&conn, "my-crate", alice.id).unwrap());
+        assert!(!database::is_crate_owner(&conn, "my-crate", bob.id).unwrap());
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/me/crate_owner_invitations")
+            .header("authorization", "bob-secret")
+            .to_request();
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&mut app, req).await).await;
+        let invitation = &body["crate_owner_invitations"][0];
+        assert_eq!("my-crate", invitation["crate_name"]);
+        assert_eq!("alice", invitation["inviter_username"]);
+
+        let answer = || {
+            test::TestRequest::put()
+                .uri("/api/v1/me/crate_owner_invitations/my-crate")
+                .header("authorization", "bob-secret")
+                .set_json(&json!({ "crate_owner_invite": { "accepted": true } }))
+                .to_request()
+        };
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&mut app, answer()).await).await;
+        assert_eq!(json!(true), body["crate_owner_invitation"]["accepted"]);
+        assert!(database::is_crate_owner(&conn, "my-crate", bob.id).unwrap());
+
+        // It's been used up.
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&mut app, answer()).await).await;
+        assert_eq!(
+            "no invitation to own `my-crate` was found",
+            body["errors"][0]["detail"]
+        );
     }
 }
@@ -0,0 +1,160 @@
+//! Reserving crate names over http, for admins who'd rather not need a shell
+//! on the registry's host (see `estuary reserve` for the same from there).
+
+use crate::auth::{self, Scope};
+use crate::database::{self, Holder};
+use crate::errors::EstuaryError;
+use crate::Settings;
+use actix_web::{delete, get, put, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[derive(Deserialize)]
+pub struct NewReservation {
+    /// A crate name, or a pattern like `acme-*`.
+    crate_pattern: String,
+    /// A user's login, or a team as `@name`.
+    holder: String,
+}
+
+#[get("")]
+pub async fn list(request: HttpRequest, settings: web::Data<Settings>) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_token(&request, &settings, Scope::Admin).await {
+        return Ok(resp);
+    }
+    let reservations = settings.with_db(database::list_reservations).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "reservations": reservations
+            .iter()
+            .map(|reservation| json!({
+                "id": reservation.id,
+                "crate_pattern": reservation.crate_pattern,
+                "holder": reservation.holder.to_string(),
+            }))
+            .collect::<Vec<_>>()
+    })))
+}
+
+#[put("")]
+pub async fn add(
+    body: web::Json<NewReservation>,
+    request: HttpRequest,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_token(&request, &settings, Scope::Admin).await {
+        return Ok(resp);
+    }
+    let NewReservation {
+        crate_pattern,
+        holder,
+    } = body.into_inner();
+    let holder = Holder::parse(&holder);
+    let id = {
+        let (crate_pattern, holder) = (crate_pattern.clone(), holder.clone());
+        settings
+            .with_db(move |conn| database::add_reservation(conn, &crate_pattern, &holder))
+            .await?
+    };
+    log::info!("Reserved `{}` for `{}`", crate_pattern, holder);
+    Ok(HttpResponse::Ok().json(json!({ "ok": true, "id": id })))
+}
+
+#[delete("/{id}")]
+pub async fn remove(
+    id: web::Path<i64>,
+    request: HttpRequest,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_token(&request, &settings, Scope::Admin).await {
+        return Ok(resp);
+    }
+    let id = id.into_inner();
+    if !settings
+        .with_db(move |conn| database::remove_reservation(conn, id))
+        .await?
+    {
+        return Err(EstuaryError::NotFound);
+    }
+    Ok(HttpResponse::Ok().json(json!({ "ok": true })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Scope;
+    use crate::database;
+    use crate::test_helpers;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use serde_json::json;
+
+    #[actix_rt::test]
+    async fn test_reservations() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        database::set_token(&conn, "admin", "admin-secret", &[Scope::Admin]).unwrap();
+        let alice = database::find_or_create_user(&conn, "alice").unwrap();
+        database::create_token(
+            &conn,
+            "alice",
+            "alice-secret",
+            &[Scope::Read, Scope::Publish],
+            Some(alice.id),
+        )
+        .unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let reserve = |token: &str| {
+            test::TestRequest::put()
+                .uri("/api/v1/reservations")
+                .header("authorization", token)
+                .set_json(&json!({ "crate_pattern": "my-*", "holder": "@backend" }))
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, reserve("alice-secret")).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        let resp = test::call_service(&mut app, reserve("admin-secret")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/reservations")
+            .header("authorization", "admin-secret")
+            .to_request();
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&mut app, req).await).await;
+        assert_eq!("@backend", body["reservations"][0]["holder"]);
+
+        // It isn't alice's to publish.
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", "alice-secret")
+            .set_payload(test_helpers::MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["errors"][0]["detail"]
+            .as_str()
+            .unwrap()
+            .contains("is reserved"));
+
+        let id = database::list_reservations(&conn).unwrap()[0].id;
+        let req = test::TestRequest::delete()
+            .uri(&format!("/api/v1/reservations/{}", id))
+            .header("authorization", "admin-secret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert!(database::list_reservations(&conn).unwrap().is_empty());
+    }
+}
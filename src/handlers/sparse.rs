@@ -0,0 +1,220 @@
+//! Cargo can also read index information using plain http requests.
+//!
+//! With a `sparse+http` index url, cargo fetches `config.json` and then only
+//! the individual package files it needs, using the same directory layout as
+//! the git repo.
+//!
+//! <https://doc.rust-lang.org/cargo/reference/registry-index.html#sparse-protocol>
+
+use crate::auth;
+use crate::encoding;
+use crate::errors::{EstuaryError, PackageIndexError};
+use crate::package_index::{get_package_file_dir, PackageIndex};
+use crate::Settings;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[get("/config.json")]
+pub async fn get_config(
+    request: HttpRequest,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    // Cargo fetches this without credentials first, and retries with its
+    // token if it sees the `401` challenge.
+    if let Err(resp) = auth::authorize_read(&request, &settings).await {
+        return Ok(resp);
+    }
+
+    let index = index.lock().unwrap();
+    let config = index.read_config()?;
+    Ok(HttpResponse::Ok().json(config))
+}
+
+#[get("/{path:.+}")]
+pub async fn get_package_file(
+    path: web::Path<String>,
+    request: HttpRequest,
+    index: web::Data<Mutex<PackageIndex>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_read(&request, &settings).await {
+        return Ok(resp);
+    }
+
+    let requested = PathBuf::from(path.as_str());
+    let name = requested
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or(EstuaryError::NotFound)?;
+
+    // Only serve paths that line up with the index layout for the given name.
+    // Anything else (including attempts to wander outside the index) is a 404.
+    let expected = get_package_file_dir(name)
+        .map_err(|_| EstuaryError::NotFound)?
+        .join(name);
+    if expected != requested {
+        return Err(EstuaryError::NotFound);
+    }
+
+    let index = index.lock().unwrap();
+    let contents = index.read_package_file(name).map_err(|e| match e {
+        PackageIndexError::IO(e @ std::io::Error { .. })
+            if e.kind() == std::io::ErrorKind::NotFound =>
+        {
+            EstuaryError::NotFound
+        }
+        _ => e.into(),
+    })?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.content_type("text/plain; charset=utf-8");
+    Ok(encoding::respond(&request, builder, contents.into_bytes())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::package_index::IndexProtocol;
+    use crate::test_helpers;
+    use crate::test_helpers::MY_CRATE_0_1_0;
+    use crate::Settings;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+
+    #[actix_rt::test]
+    async fn test_get_config() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/index/config.json")
+            .to_request();
+        let resp: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(resp.as_object().unwrap().contains_key("dl"));
+        assert!(resp.as_object().unwrap().contains_key("api"));
+    }
+
+    #[actix_rt::test]
+    async fn test_get_package_file() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::get()
+            .uri("/index/my/-c/my-crate")
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        let line: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!("my-crate", line["name"]);
+
+        // `_` for `-` finds the same crate.
+        let req = test::TestRequest::get()
+            .uri("/index/my/_c/my_crate")
+            .to_request();
+        assert_eq!(body, test::read_response(&mut app, req).await);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_package_file_zstd() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::get()
+            .uri("/index/my/-c/my-crate")
+            .header("accept-encoding", "gzip, zstd")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!("zstd", resp.headers().get("content-encoding").unwrap());
+        let body = test::read_body(resp).await;
+        let line: serde_json::Value =
+            serde_json::from_slice(&zstd::decode_all(body.as_ref()).unwrap()).unwrap();
+        assert_eq!("my-crate", line["name"]);
+    }
+
+    #[actix_rt::test]
+    async fn test_get_package_file_wrong_dir_is_not_found() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .set_payload(MY_CRATE_0_1_0)
+            .to_request();
+        let _: serde_json::Value = test::read_response_json(&mut app, req).await;
+
+        let req = test::TestRequest::get()
+            .uri("/index/xx/yy/my-crate")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_git_only_does_not_serve_sparse() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            index_protocol: IndexProtocol::Git,
+            ..test_helpers::get_test_settings(data_root.path())
+                .get_ref()
+                .clone()
+        });
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let mut app = test::init_service(
+            App::new()
+                .app_data(package_index.clone())
+                .app_data(settings.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/index/config.json")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+}
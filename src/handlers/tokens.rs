@@ -0,0 +1,199 @@
+//! Managing tokens over http, for when one needs revoking and there's no
+//! shell on the registry's host to hand.
+
+use crate::auth::{self, Scope};
+use crate::database;
+use crate::errors::EstuaryError;
+use crate::Settings;
+use actix_web::{delete, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[derive(Deserialize)]
+pub struct RevokeQuery {
+    reason: Option<String>,
+}
+
+/// Revoke a token, by the id shown by `estuary token list` (which is the
+/// PASERK id, for public keys).
+///
+/// Tokens are looked up afresh for every request, so it stops working
+/// straight away.
+#[delete("/{id}")]
+pub async fn revoke(
+    id: web::Path<String>,
+    query: web::Query<RevokeQuery>,
+    request: HttpRequest,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    let admin = match auth::authorize_token(&request, &settings, Scope::Admin).await {
+        Ok(token) => token,
+        Err(resp) => return Ok(resp),
+    };
+    let revoked_by = admin.map_or_else(|| "anonymous".to_string(), |token| token.name);
+    let (id, reason) = (id.into_inner(), query.into_inner().reason);
+    let by = revoked_by.clone();
+    let name = settings
+        .with_db(move |conn| -> Result<_> {
+            match id.parse::<i64>() {
+                Ok(id) => {
+                    let name =
+                        database::find_token_name(conn, id)?.ok_or(EstuaryError::NotFound)?;
+                    database::revoke_token(conn, &name, &by, reason.as_deref())?;
+                    Ok(name)
+                }
+                Err(_) => database::remove_public_key(conn, &id, &by, reason.as_deref())?
+                    .ok_or(EstuaryError::NotFound),
+            }
+        })
+        .await?;
+    log::info!("Token `{}` revoked by `{}`", name, revoked_by);
+    Ok(HttpResponse::Ok().json(json!({ "ok": true })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Scope;
+    use crate::database;
+    use crate::test_helpers;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+
+    #[actix_rt::test]
+    async fn test_revoke() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        database::set_token(&conn, "admin", "admin-secret", &[Scope::Admin]).unwrap();
+        database::set_token(&conn, "ci", "ci-secret", &[Scope::Publish]).unwrap();
+        database::add_public_key(
+            &conn,
+            "ci",
+            "k3.public.abc",
+            "k3.pid.abc",
+            &[Scope::Publish],
+        )
+        .unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+        let ci = database::list_tokens(&conn)
+            .unwrap()
+            .into_iter()
+            .find(|token| token.name == "ci")
+            .unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let uri = format!("/api/v1/tokens/{}?reason=leaked", ci.id);
+        // Only for admins.
+        let req = test::TestRequest::delete()
+            .uri(&uri)
+            .header("authorization", "ci-secret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let req = test::TestRequest::delete()
+            .uri(&uri)
+            .header("authorization", "admin-secret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        // The token stops working right away.
+        let req = test::TestRequest::put()
+            .uri("/api/v1/crates/new")
+            .header("authorization", "ci-secret")
+            .set_payload(test_helpers::MY_CRATE_0_1_0)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let revocations = database::list_revocations(&conn).unwrap();
+        assert_eq!("ci", revocations[0].token_name);
+        assert_eq!("admin", revocations[0].revoked_by);
+        assert_eq!(Some("leaked".to_string()), revocations[0].reason);
+
+        let req = test::TestRequest::delete()
+            .uri(&uri)
+            .header("authorization", "admin-secret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        // Keys are revoked by their PASERK id, not along with tokens that
+        // share their name.
+        assert!(database::find_public_key(&conn, "k3.pid.abc")
+            .unwrap()
+            .is_some());
+        let req = test::TestRequest::delete()
+            .uri("/api/v1/tokens/k3.pid.abc")
+            .header("authorization", "admin-secret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            None,
+            database::find_public_key(&conn, "k3.pid.abc").unwrap()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_revoke_as_admin_user() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        let alice = database::find_or_create_user(&conn, "alice").unwrap();
+        database::create_token(
+            &conn,
+            "alice/laptop",
+            "alice-secret",
+            &[Scope::Read],
+            Some(alice.id),
+        )
+        .unwrap();
+        database::set_token(&conn, "ci", "ci-secret", &[Scope::Publish]).unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+
+        let revoke = |token: &str| {
+            test::TestRequest::delete()
+                .uri("/api/v1/tokens/2")
+                .header("authorization", token)
+                .to_request()
+        };
+        // Being an admin doesn't add to what a token was issued for.
+        database::set_admin(&conn, "alice", true).unwrap();
+        let resp = test::call_service(&mut app, revoke("alice-secret")).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        database::create_token(
+            &conn,
+            "alice/admin",
+            "alice-admin-secret",
+            &[Scope::Admin],
+            Some(alice.id),
+        )
+        .unwrap();
+        let resp = test::call_service(&mut app, revoke("alice-admin-secret")).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "alice/admin",
+            database::list_revocations(&conn).unwrap()[0].revoked_by
+        );
+    }
+}
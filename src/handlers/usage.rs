@@ -0,0 +1,92 @@
+//! How much storage the registry's crate files take up, against the quotas
+//! (see `--crate-quota` and `--registry-quota`), for admins.
+//!
+//! Files stored before their sizes were recorded aren't counted.
+
+use crate::auth::{self, Scope};
+use crate::database;
+use crate::errors::EstuaryError;
+use crate::Settings;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use serde_json::json;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[get("")]
+pub async fn get_usage(
+    request: HttpRequest,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_token(&request, &settings, Scope::Admin).await {
+        return Ok(resp);
+    }
+    let (crates, bytes) = settings
+        .with_db(|conn| -> Result<_> {
+            Ok((
+                database::list_crate_usage(conn)?,
+                database::registry_usage(conn)?,
+            ))
+        })
+        .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "bytes": bytes,
+        "quota": settings.registry_quota,
+        "crate_quota": settings.crate_quota,
+        "crates": crates
+            .iter()
+            .map(|usage| json!({
+                "name": usage.crate_name,
+                "versions": usage.versions,
+                "bytes": usage.bytes,
+            }))
+            .collect::<Vec<_>>()
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Scope;
+    use crate::database;
+    use crate::test_helpers;
+    use crate::Settings;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+
+    #[actix_rt::test]
+    async fn test_usage() {
+        let data_root = test_helpers::get_data_root();
+        let settings = web::Data::new(Settings {
+            registry_quota: Some(1024),
+            ..(**test_helpers::get_test_settings(data_root.path())).clone()
+        });
+        let conn = settings.get_db().unwrap();
+        database::set_token(&conn, "admin", "admin-secret", &[Scope::Admin]).unwrap();
+        database::set_token(&conn, "ci", "ci-secret", &[Scope::Publish]).unwrap();
+        database::record_crate_file(&conn, "my-crate", "0.1.0", "abc", 100).unwrap();
+        let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(settings.clone())
+                .app_data(package_index.clone())
+                .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+        )
+        .await;
+        let usage = |token: &str| {
+            test::TestRequest::get()
+                .uri("/api/v1/usage")
+                .header("authorization", token)
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, usage("ci-secret")).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let body: serde_json::Value =
+            test::read_body_json(test::call_service(&mut app, usage("admin-secret")).await).await;
+        assert_eq!(100, body["bytes"]);
+        assert_eq!(1024, body["quota"]);
+        assert!(body["crate_quota"].is_null());
+        assert_eq!("my-crate", body["crates"][0]["name"]);
+        assert_eq!(1, body["crates"][0]["versions"]);
+    }
+}
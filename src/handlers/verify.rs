@@ -0,0 +1,160 @@
+//! Checking the index, the database, and the crate files against each other
+//! (as `estuary verify` does), for admins.
+//!
+//! Every crate file is read, so this can take a while on a big registry. It
+//! runs in the background once it's asked for with a `POST`, and a `GET`
+//! says how it's going, with the report once it's done.
+
+use crate::auth::{self, Scope};
+use crate::database;
+use crate::errors::EstuaryError;
+use crate::package_index::PackageIndex;
+use crate::verify;
+use crate::Settings;
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use serde_json::json;
+use std::sync::Mutex;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+#[get("")]
+pub async fn get_verify(
+    request: HttpRequest,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_token(&request, &settings, Scope::Admin).await {
+        return Ok(resp);
+    }
+    Ok(HttpResponse::Ok().json(status(&settings.verify_scan.lock().unwrap())))
+}
+
+/// Start a check, unless one is already underway.
+#[post("")]
+pub async fn start_verify(
+    request: HttpRequest,
+    settings: web::Data<Settings>,
+    package_index: web::Data<Mutex<PackageIndex>>,
+) -> Result<HttpResponse> {
+    if let Err(resp) = auth::authorize_token(&request, &settings, Scope::Admin).await {
+        return Ok(resp);
+    }
+    let mut scan = settings.verify_scan.lock().unwrap();
+    if !scan.running {
+        scan.running = true;
+        let settings = settings.clone();
+        actix_web::rt::spawn(async move {
+            let last = check(&settings, &package_index).await;
+            if let Err(ref e) = last {
+                log::error!("Verifying the registry failed: {}", e);
+            }
+            let mut scan = settings.verify_scan.lock().unwrap();
+            scan.running = false;
+            scan.last = Some(last.map_err(|e| e.to_string()));
+        });
+    }
+    Ok(HttpResponse::Accepted().json(status(&scan)))
+}
+
+async fn check(settings: &Settings, package_index: &Mutex<PackageIndex>) -> Result<verify::Report> {
+    let index = verify::index_versions(&package_index.lock().unwrap())?;
+    let recorded = settings.with_db(database::list_versions).await?;
+    Ok(verify::check(&index, &recorded, settings.crate_store.as_ref()).await)
+}
+
+fn status(scan: &verify::Scan) -> serde_json::Value {
+    let (report, error) = match scan.last {
+        None => (None, None),
+        Some(Ok(ref report)) => (Some(report), None),
+        Some(Err(ref e)) => (None, Some(e)),
+    };
+    json!({
+        "running": scan.running,
+        "error": error,
+        "report": report.map(|report| json!({
+            "versions": report.versions,
+            "problems": report
+                .problems
+                .iter()
+                .map(|problem| json!({
+                    "name": problem.crate_name,
+                    "version": problem.version,
+                    "kind": problem.kind.as_str(),
+                    "detail": problem.kind.to_string(),
+                }))
+                .collect::<Vec<_>>()
+        })),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::Scope;
+    use crate::database;
+    use crate::test_helpers;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use std::time::Duration;
+
+    #[test]
+    fn test_verify() {
+        // The check is spawned on the server's runtime.
+        actix_web::rt::System::new("test_verify").block_on(async move {
+            let data_root = test_helpers::get_data_root();
+            let settings = test_helpers::get_test_settings(data_root.path());
+            let conn = settings.get_db().unwrap();
+            database::set_token(&conn, "admin", "admin-secret", &[Scope::Admin]).unwrap();
+            database::set_token(&conn, "ci", "ci-secret", &[Scope::Publish]).unwrap();
+            database::record_publish(&conn, "my-crate", "0.1.0", None, None).unwrap();
+            let package_index = test_helpers::get_test_package_index(&settings.index_dir);
+
+            let mut app = test::init_service(
+                App::new()
+                    .app_data(settings.clone())
+                    .app_data(package_index.clone())
+                    .configure(|cfg| crate::handlers::configure_routes(cfg, &settings)),
+            )
+            .await;
+            let verify = |request: test::TestRequest, token: &str| {
+                request
+                    .uri("/api/v1/verify")
+                    .header("authorization", token)
+                    .to_request()
+            };
+            let resp =
+                test::call_service(&mut app, verify(test::TestRequest::post(), "ci-secret")).await;
+            assert_eq!(StatusCode::FORBIDDEN, resp.status());
+            let resp =
+                test::call_service(&mut app, verify(test::TestRequest::get(), "ci-secret")).await;
+            assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+            let body: serde_json::Value = test::read_body_json(
+                test::call_service(&mut app, verify(test::TestRequest::get(), "admin-secret"))
+                    .await,
+            )
+            .await;
+            assert_eq!(false, body["running"]);
+            assert!(body["report"].is_null());
+
+            let resp =
+                test::call_service(&mut app, verify(test::TestRequest::post(), "admin-secret"))
+                    .await;
+            assert_eq!(StatusCode::ACCEPTED, resp.status());
+            let body = loop {
+                let body: serde_json::Value = test::read_body_json(
+                    test::call_service(&mut app, verify(test::TestRequest::get(), "admin-secret"))
+                        .await,
+                )
+                .await;
+                if body["running"] == false {
+                    break body;
+                }
+                actix_web::rt::time::delay_for(Duration::from_millis(10)).await;
+            };
+            let report = &body["report"];
+            assert_eq!(1, report["versions"]);
+            assert_eq!("my-crate", report["problems"][0]["name"]);
+            assert_eq!("0.1.0", report["problems"][0]["version"]);
+            assert_eq!("not_in_index", report["problems"][0]["kind"]);
+        });
+    }
+}
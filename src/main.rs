@@ -1,32 +1,184 @@
 use crate::errors::EstuaryError;
 use actix_web::{middleware, web, App, HttpServer};
-use package_index::{Config, PackageIndex};
+use package_index::{IndexProtocol, PackageIndex};
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, PoisonError};
 
+mod auth;
 mod cli;
+mod commands;
+mod database;
+mod deletion;
+mod downloads;
+mod encoding;
 mod errors;
+mod h2c;
 mod handlers;
+mod manifest;
 mod package_index;
+mod publish_policy;
+mod rate_limit;
+mod refs_cache;
+mod search_cache;
 mod storage;
+mod tarball;
+mod tls;
+mod verify;
 
 /// Common configuration details to share with handlers.
 #[derive(Clone, Debug)]
 pub struct Settings {
+    /// The public url for the service.
+    pub base_url: String,
+    /// The url cargo should use to fetch the package index.
+    ///
+    /// This may point somewhere other than `base_url` when the index is
+    /// fronted by a different hostname.
+    pub index_url: String,
+    /// What users call the registry in their cargo config, for the snippets
+    /// in the web UI.
+    pub registry_name: String,
+    /// How the web UI tells people which registry they're looking at.
+    pub branding: handlers::frontend::Branding,
     /// Root path for storing `.crate` files when they are published.
     pub crate_dir: PathBuf,
+    /// Where `.crate` files are actually kept, which is the `crate_dir` unless
+    /// an object store is configured.
+    ///
+    /// The database still lives under the `crate_dir` by default.
+    pub crate_store: Arc<dyn storage::CrateStore>,
+
+    /// Check crate files against their checksums before serving them.
+    pub verify_downloads: bool,
+    /// The biggest crate file (in bytes) that can be published.
+    /// `None` for no limit.
+    pub max_crate_size: Option<u64>,
+    /// What else crates must do to be published.
+    pub publish_policy: publish_policy::PublishPolicy,
+    /// The most bytes of crate files any one crate may have, over all its
+    /// versions.
+    pub crate_quota: Option<u64>,
+    /// The most bytes of crate files the registry may have, all told.
+    pub registry_quota: Option<u64>,
     /// Location for the git repo that tracks changes to the package index.
     ///
     /// Note that this should be the path to the working tree, not the `.git`
     /// directory inside it.
     pub index_dir: PathBuf,
-    /// Optionally specify a path to `git`.
+
+    /// The database holding API tokens, and what else isn't in the index.
+    pub database_url: database::DatabaseUrl,
+    /// Connections to the database, shared between clones (and so workers).
+    pub db: database::Pool,
+
+    /// When set, a token must also be presented to read from the index, search,
+    /// or download crates.
+    ///
+    /// This mirrors the `auth-required` field in the index `config.json`.
+    pub auth_required: bool,
+
+    /// Private mode: a token is needed for everything (the index, downloads,
+    /// search, and the web UI) except the `/me` page.
+    ///
+    /// Implies `auth_required`.
+    pub private: bool,
+
+    /// Allow anyone who can reach the `/me` page to issue themselves a token.
+    pub web_tokens: bool,
+
+    /// Allow anyone who can reach the `/me` page to sign up for an account.
+    pub signup: bool,
+
+    /// Parts of the site that need a user's login (or a token) via HTTP Basic
+    /// auth.
+    pub basic_auth: Vec<auth::BasicAuthArea>,
+
+    /// An identity provider to log in to the web UI with.
+    pub oidc: Option<auth::oidc::OidcConfig>,
+
+    /// A directory server to check passwords against, in place of the users
+    /// table.
+    pub ldap: Option<auth::backend::LdapConfig>,
+
+    /// Controls which endpoints are available for cargo to read the index.
+    pub index_protocol: IndexProtocol,
+
+    /// How often each token may publish and yank.
+    ///
+    /// Shared between clones, so the count is kept across all the workers.
+    pub rate_limiter: Arc<rate_limit::RateLimiter>,
+
+    /// Downloads counted, but not yet written to the database.
+    ///
+    /// Shared between clones, like the `rate_limiter`.
+    pub downloads: Arc<downloads::DownloadCounter>,
+
+    /// What search shows for each crate, kept between searches.
+    ///
+    /// Shared between clones, like the `rate_limiter`.
+    pub search_cache: Arc<search_cache::SearchCache>,
+
+    /// What `info/refs` advertises, kept between fetches.
+    ///
+    /// Shared between clones, like the `rate_limiter`.
+    pub refs_cache: Arc<refs_cache::RefsCache>,
+
+    /// The check admins last ran over http, or are running.
     ///
-    /// Defaults to just "git", expecting it to be in your `PATH`.
-    pub git_binary: PathBuf,
+    /// Shared between clones, like the `rate_limiter`.
+    pub verify_scan: Arc<Mutex<verify::Scan>>,
 
-    /// The key that must be presented in order to publish a crate.
-    pub publish_key: Option<String>,
+    /// The keys trusted publishers' ID tokens are checked with, kept between
+    /// publishes.
+    ///
+    /// Shared between clones, like the `rate_limiter`.
+    pub jwks_cache: Arc<auth::oidc::JwksCache>,
+
+    /// Who's credited with index changes made without a token.
+    pub fallback_author: package_index::Author,
+}
+
+impl Settings {
+    /// Borrow a connection to the database, waiting for one to be free if
+    /// they're all in use.
+    ///
+    /// This blocks, so handlers should go through [`with_db`](Self::with_db)
+    /// instead.
+    pub fn get_db(&self) -> Result<database::PooledConnection, EstuaryError> {
+        Ok(self.db.get()?)
+    }
+
+    /// Run `f` with a connection to the database on the blocking thread pool,
+    /// so neither waiting for a connection nor the queries themselves hold up
+    /// the worker's other requests.
+    pub async fn with_db<F, T, E>(&self, f: F) -> Result<T, EstuaryError>
+    where
+        F: FnOnce(&database::Connection) -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Into<EstuaryError>,
+    {
+        let db = self.db.clone();
+        Ok(
+            web::block(move || -> Result<T, EstuaryError> { f(&*db.get()?).map_err(Into::into) })
+                .await?,
+        )
+    }
+
+    /// The urls cargo might know the index by: the configured `index_url`, as
+    /// well as the default url for each protocol being served.
+    pub fn index_urls(&self) -> Vec<String> {
+        package_index::index_urls(&self.index_url, &self.base_url, self.index_protocol)
+    }
+
+    /// Where to check users' passwords.
+    pub fn password_backend(&self) -> Box<dyn auth::backend::PasswordBackend> {
+        match self.ldap {
+            Some(ref ldap) => Box::new(ldap.clone()),
+            None => Box::new(auth::backend::LocalUsers {
+                db: self.db.clone(),
+            }),
+        }
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -37,43 +189,385 @@ async fn main() -> Result<(), EstuaryError> {
 
     env_logger::init();
 
-    let args = cli::parse_args();
+    match cli::parse_args() {
+        cli::Command::Run(opt) if opt.dev => {
+            // Deleted once the server stops.
+            let dir = tempfile::Builder::new().prefix("estuary-dev").tempdir()?;
+            let args = commands::dev::setup(opt.opt, dir.path()).await?;
+            run_server(args).await
+        }
+        cli::Command::Run(opt) => run_server(opt.opt).await,
+        cli::Command::Token(opt) => commands::token::run(opt),
+        cli::Command::User(opt) => commands::user::run(opt),
+        cli::Command::Trust(opt) => commands::trust::run(opt),
+        cli::Command::Grant(opt) => commands::grant::run(opt),
+        cli::Command::Team(opt) => commands::team::run(opt),
+        cli::Command::Reserve(opt) => commands::reserve::run(opt),
+        cli::Command::Storage(opt) => commands::storage::run(opt),
+        cli::Command::Verify(args) => commands::verify::run(args).await,
+        cli::Command::BackupDb(opt) => commands::backup_db::run(opt),
+        cli::Command::Stats(opt) => commands::stats::run(opt),
+        cli::Command::Admin(cmd) => commands::admin::run(cmd).await,
+        cli::Command::Fsck(opt) => commands::fsck::run(opt),
+        cli::Command::SquashIndex(args) => commands::squash_index::run(args),
+        cli::Command::Doctor(args) => commands::doctor::run(args).await,
+        cli::Command::Export(opt) => commands::archive::run_export(opt).await,
+        cli::Command::Import(opt) => commands::archive::run_import(opt).await,
+        cli::Command::RebuildIndex(args) => commands::rebuild_index::run(args).await,
+        cli::Command::ImportDir(opt) => commands::import_dir::run(opt).await,
+        cli::Command::Completions(opt) => commands::completions::run(opt),
+    }
+}
 
+/// Set up the package index and storage, then serve http (or https) until
+/// we're told to stop.
+#[cfg(not(tarpaulin_include))]
+async fn run_server(args: cli::Opt) -> Result<(), EstuaryError> {
     let bind_addr = format!("{}:{}", args.http_host, args.http_port);
-    let config = Config {
-        dl: args.download_url(),
-        api: args.base_url().to_string(),
-    };
+    let config = args.index_config();
+    let mirror = args.index_mirror();
+    let committer = args.committer()?;
+    let publish_policy = args.publish_policy()?;
+    // The pool would panic over this, rather than complain.
+    if args.db_pool_size == 0 {
+        return Err(EstuaryError::Config(
+            "`--db-pool-size` needs to allow at least one connection.".to_string(),
+        ));
+    }
+    let crate_store = args.crate_store()?;
     let settings = Settings {
+        base_url: args.base_url().to_string(),
+        index_url: args.index_url(),
+        registry_name: args.registry_name.clone(),
+        branding: args.branding(),
+        db: database::pool(
+            &args.database_url(),
+            args.db_pool_size,
+            std::time::Duration::from_millis(args.db_busy_timeout),
+            database::Pragmas {
+                journal_mode: args.db_journal_mode,
+                synchronous: args.db_synchronous,
+            },
+        )?,
+        database_url: args.database_url(),
+        oidc: args.oidc(),
+        ldap: args.ldap(),
+        crate_store,
+        verify_downloads: args.verify_downloads,
+        max_crate_size: args.max_crate_size()?,
+        publish_policy,
+        crate_quota: args.crate_quota,
+        registry_quota: args.registry_quota,
         crate_dir: args.crate_dir,
         index_dir: args.index_dir,
-        git_binary: args.git_bin,
-        publish_key: args.publish_key,
+        auth_required: args.auth_required || args.private,
+        private: args.private,
+        web_tokens: args.web_tokens,
+        signup: args.signup,
+        basic_auth: args.basic_auth,
+        index_protocol: args.index_protocol,
+        rate_limiter: Arc::new(rate_limit::RateLimiter::new(args.rate_limit)),
+        downloads: Default::default(),
+        search_cache: Default::default(),
+        refs_cache: Default::default(),
+        verify_scan: Default::default(),
+        jwks_cache: Default::default(),
+        fallback_author: args
+            .fallback_author
+            .unwrap_or_else(|| committer.identity.clone()),
     };
 
     std::fs::create_dir_all(&settings.index_dir)?;
     std::fs::create_dir_all(&settings.crate_dir)?;
 
+    let conn = settings.get_db()?;
+    database::init(&conn)?;
+    if let Some(ref key) = args.publish_key {
+        database::set_token(
+            &conn,
+            cli::PUBLISH_KEY_TOKEN_NAME,
+            key,
+            auth::Scope::DEFAULT,
+        )?;
+    }
+    if settings.auth_required && database::count_tokens(&conn)? == 0 {
+        return Err(EstuaryError::Config(
+            "`--auth-required` (and `--private`) need at least one token to check requests against."
+                .to_string(),
+        ));
+    }
+
+    if settings.private
+        && settings.web_tokens
+        && !settings.basic_auth.contains(&auth::BasicAuthArea::Web)
+    {
+        return Err(EstuaryError::Config(
+            "`--web-tokens` would let anyone who can reach `/me` issue themselves a token for \
+            the `--private` registry. Put `/me` behind `--basic-auth=web` too, or have people \
+            log in (ex: with `--oidc-issuer`) to issue their tokens."
+                .to_string(),
+        ));
+    }
+    if settings.private
+        && settings
+            .oidc
+            .as_ref()
+            .is_some_and(|oidc| oidc.allowed.is_empty())
+    {
+        return Err(EstuaryError::Config(
+            "`--oidc-issuer` would let anyone the provider knows into the `--private` registry. \
+            Say who may log in with `--oidc-allow`."
+                .to_string(),
+        ));
+    }
+    if settings.signup && settings.ldap.is_some() {
+        return Err(EstuaryError::Config(
+            "`--signup` can't be used with `--ldap-url`, since accounts live in the directory."
+                .to_string(),
+        ));
+    }
+    if settings.signup && settings.private {
+        return Err(EstuaryError::Config(
+            "`--signup` can't be used with `--private`, since anyone could sign up to see it."
+                .to_string(),
+        ));
+    }
+    if args.scan_interval == Some(0) {
+        return Err(EstuaryError::Config(
+            "`--scan-interval` needs to be at least an hour.".to_string(),
+        ));
+    }
+    if args.squash_index_interval == Some(0) {
+        return Err(EstuaryError::Config(
+            "`--squash-index-interval` needs to be at least a day.".to_string(),
+        ));
+    }
+    if args.rate_limit == Some(0) {
+        return Err(EstuaryError::Config(
+            "`--rate-limit` needs to allow at least one request a minute.".to_string(),
+        ));
+    }
+    if let Some(ref ldap) = settings.ldap {
+        if ldap.user_dn.is_none() && ldap.base_dn.is_none() {
+            return Err(EstuaryError::Config(
+                "`--ldap-url` needs either `--ldap-user-dn` or `--ldap-base-dn`.".to_string(),
+            ));
+        }
+    }
+    if !settings.basic_auth.is_empty()
+        && settings.ldap.is_none()
+        && database::list_users(&conn)?.is_empty()
+        && database::count_tokens(&conn)? == 0
+    {
+        return Err(EstuaryError::Config(
+            "`--basic-auth` needs at least one user (see `estuary user add`) or token.".to_string(),
+        ));
+    }
+
+    if args.git_bin.is_some() {
+        log::warn!("`--git-bin` is no longer used, since the index is served without `git`.");
+    }
+
     log::info!("Server starting on `{}`", bind_addr);
     log::info!("\tIndex Dir: `{}`", settings.index_dir.display());
     log::info!("\tCrate Dir: `{}`", settings.crate_dir.display());
+    log::info!("\tCrate Store: `{:?}`", settings.crate_store);
+    log::info!(
+        "\tDatabase: `{}` (up to {} connections)",
+        settings.database_url,
+        args.db_pool_size
+    );
+    log::info!("\tIndex Url: `{}`", settings.index_url);
     log::info!("\tPackage Index Config: `{:?}`", config);
+    log::info!("\tIndex Protocol: `{:?}`", settings.index_protocol);
+    if !settings.basic_auth.is_empty() {
+        log::info!("\tBasic Auth: `{:?}`", settings.basic_auth);
+    }
+    if let Some(ref oidc) = settings.oidc {
+        log::info!("\tOIDC Issuer: `{}`", oidc.issuer);
+        if !oidc.allowed.is_empty() {
+            log::info!("\tOIDC Allowed: `{}`", oidc.allowed.join(", "));
+        }
+    }
+    if let Some(ref ldap) = settings.ldap {
+        log::info!("\tLDAP: `{}`", ldap.url);
+    }
+    if let Some(limit) = args.rate_limit {
+        log::info!("\tRate Limit: {} per minute", limit);
+    }
+    if settings.verify_downloads {
+        log::info!("\tVerifying Downloads");
+    }
+    if let Some(size) = settings.max_crate_size {
+        log::info!("\tMax Crate Size: {} bytes", size);
+    }
+    if settings.publish_policy.deny_wildcard_deps {
+        log::info!("\tDenying Wildcard Dependencies");
+    }
+    for registry in &settings.publish_policy.dep_registries {
+        log::info!("\tAllowing Dependencies From: `{}`", registry);
+    }
+    for pattern in &settings.publish_policy.allowed_names {
+        log::info!("\tAllowing Names: `{}`", pattern);
+    }
+    if !settings.publish_policy.denied_names.is_empty() {
+        log::info!(
+            "\tDenied Names: {}",
+            settings.publish_policy.denied_names.len()
+        );
+    }
+    if let Some(ref categories) = settings.publish_policy.categories {
+        log::info!("\tCategories: {}", categories.len());
+    }
+    if let Some(quota) = settings.crate_quota {
+        log::info!("\tCrate Quota: {} bytes", quota);
+    }
+    if let Some(quota) = settings.registry_quota {
+        log::info!("\tRegistry Quota: {} bytes", quota);
+    }
 
-    let package_index = web::Data::new(Mutex::new(PackageIndex::init(
-        &settings.index_dir,
-        &config,
-    )?));
+    if let Some(hours) = args.scan_interval {
+        log::info!("\tScanning Crate Files: every {} hours", hours);
+        let settings = settings.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval =
+                actix_web::rt::time::interval(std::time::Duration::from_secs(hours * 60 * 60));
+            loop {
+                interval.tick().await;
+                storage::scan_and_report(&settings).await;
+            }
+        });
+    }
 
-    Ok(HttpServer::new(move || {
+    {
+        let settings = settings.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(downloads::FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let downloads = settings.downloads.clone();
+                if let Err(e) = settings.with_db(move |conn| downloads.flush(conn)).await {
+                    log::error!("Couldn't record downloads: {}", e);
+                }
+            }
+        });
+    }
+    let final_flush = settings.clone();
+
+    if let Some(ref mirror) = mirror {
+        log::info!("\tIndex Mirror: `{}`", mirror.redacted_url());
+    }
+    let mut package_index =
+        PackageIndex::init_as(&settings.index_dir, &config, &committer)?.with_mirror(mirror);
+    // Held until the server stops, so commands that change the index refuse to
+    // meanwhile.
+    let _index_lock = package_index::lock::acquire(&settings.index_dir)?;
+    // So the mirror has anything committed while the server was stopped.
+    package_index.push_mirror();
+    let backfilled = verify::backfill_yanks(&package_index, &*settings.get_db()?)?;
+    if backfilled > 0 {
+        log::info!(
+            "Recorded {} versions yanked before the database kept track of yanks.",
+            backfilled
+        );
+    }
+    // Changes a server stopped before committing left staged.
+    if package_index.commit_staged()? {
+        log::info!("Committed the index changes that were left staged.");
+    }
+    if args.index_commit_window.is_some() {
+        package_index = package_index.coalesce_commits();
+    }
+    let package_index = web::Data::new(Mutex::new(package_index));
+    if let Some(millis) = args.index_commit_window {
+        log::info!("\tCoalescing Index Commits: every {}ms", millis);
+        let package_index = package_index.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval =
+                actix_web::rt::time::interval(std::time::Duration::from_millis(millis.max(1)));
+            loop {
+                interval.tick().await;
+                let package_index = package_index.clone();
+                // Committing writes to disk, so it's done off the workers. A
+                // publish that panicked holding the index leaves its staged
+                // changes as they were, which are still to be committed.
+                let committed = web::block(move || {
+                    package_index
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .commit_pending()
+                })
+                .await;
+                if let Err(e) = committed {
+                    log::error!("Couldn't commit the index changes: {}", e);
+                }
+            }
+        });
+    }
+    let final_commit = package_index.clone();
+    if let Some(days) = args.squash_index_interval {
+        log::info!("\tSquashing Index History: every {} days", days);
+        let package_index = package_index.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval =
+                actix_web::rt::time::interval(std::time::Duration::from_secs(days * 24 * 60 * 60));
+            // The first tick is straight away, rather than a whole interval in.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let package_index = package_index.clone();
+                match web::block(move || package_index.lock().unwrap().squash()).await {
+                    Ok(Some(id)) => log::info!("Squashed the index history into `{}`", id),
+                    Ok(None) => (),
+                    Err(e) => log::error!("Couldn't squash the index history: {}", e),
+                }
+            }
+        });
+    }
+    // So crates from before search kept its own table can be found, by name
+    // at least.
+    database::add_to_search(
+        &*settings.get_db()?,
+        &package_index.lock().unwrap().list_crates()?,
+    )?;
+
+    let app_factory = move || {
         App::new()
             .wrap(middleware::Logger::default())
             .app_data(package_index.clone())
             .data(settings.clone())
-            .configure(handlers::configure_routes)
-    })
-    .bind(bind_addr)?
-    .run()
-    .await?)
+            .configure(|cfg| handlers::configure_routes(cfg, &settings))
+    };
+
+    let served = if args.h2c && args.tls_cert.is_none() {
+        log::info!("\tAccepting cleartext HTTP/2");
+        h2c::bind(app_factory, &bind_addr)?.await
+    } else {
+        let server = HttpServer::new(app_factory);
+        let server = match (args.tls_cert, args.tls_key) {
+            (Some(cert_path), Some(key_path)) => {
+                log::info!("\tTLS Cert: `{}`", cert_path.display());
+                let cert = Arc::new(tls::ReloadableCert::load(&cert_path, &key_path)?);
+                tls::reload_on_sighup(cert.clone())?;
+                server.bind_rustls(bind_addr, tls::server_config(cert))?
+            }
+            _ => server.bind(bind_addr)?,
+        };
+        server.run().await
+    };
+    // Whatever was downloaded since the last flush.
+    flush_downloads(&final_flush)?;
+    // And whatever's been changed in the index since the last commit.
+    final_commit
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .commit_pending()?;
+    Ok(served?)
+}
+
+#[cfg(not(tarpaulin_include))]
+fn flush_downloads(settings: &Settings) -> Result<(), EstuaryError> {
+    Ok(settings.downloads.flush(&*settings.get_db()?)?)
 }
 
 #[cfg(test)]
@@ -0,0 +1,428 @@
+//! Reading what the index needs to know about a version from the
+//! `Cargo.toml` in its crate file, for when there's no publish request to get
+//! it from.
+//!
+//! Cargo normalizes the manifest as it packages a crate, so there's only one
+//! form of each thing to expect: dependencies each in a table of their own,
+//! with `version` set, and `registry-index` set for those from registries
+//! other than crates.io.
+
+use crate::database::CrateMetadata;
+use crate::package_index::{Dependency, DependencyKind, PackageVersion};
+use crate::tarball;
+use cargo_platform::Platform;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read};
+
+/// Bigger manifests than this aren't read.
+const MAX_MANIFEST_SIZE: u64 = 1024 * 1024;
+
+/// Bigger readmes than this aren't kept.
+const MAX_README_SIZE: u64 = 512 * 1024;
+
+/// The index url dependencies without a `registry-index` are from.
+pub const CRATES_IO_INDEX: &str = "https://github.com/rust-lang/crates.io-index";
+
+/// A version, as its crate file describes it.
+#[derive(Debug, PartialEq)]
+pub struct Manifest {
+    pub name: String,
+    pub vers: semver::Version,
+    pub deps: Vec<Dependency>,
+    pub features: HashMap<String, Vec<String>>,
+    pub links: Option<String>,
+    pub metadata: CrateMetadata,
+}
+
+impl Manifest {
+    /// The line for the index, for a crate file with the SHA-256 `cksum`.
+    pub fn package_version(&self, cksum: &str) -> PackageVersion {
+        PackageVersion {
+            name: self.name.clone(),
+            vers: self.vers.clone(),
+            deps: self.deps.clone(),
+            cksum: cksum.to_string(),
+            features: self.features.clone(),
+            yanked: false,
+            links: self.links.clone(),
+        }
+    }
+
+    /// Where `published` (what cargo sent about the version as it published
+    /// it) disagrees with the manifest, ex: "the version (`1.0.0` in
+    /// `Cargo.toml`)". Nothing, when it's the same version.
+    pub fn differences(&self, published: &PackageVersion) -> Vec<String> {
+        let mut found = vec![];
+        if self.name != published.name {
+            found.push(format!("the name (`{}` in `Cargo.toml`)", self.name));
+        }
+        if self.vers != published.vers {
+            found.push(format!("the version (`{}` in `Cargo.toml`)", self.vers));
+        }
+
+        let (ours, theirs) = (comparable(&self.deps), comparable(&published.deps));
+        let mut deps = ours
+            .iter()
+            .filter(|dep| !theirs.contains(dep))
+            .chain(theirs.iter().filter(|dep| !ours.contains(dep)))
+            .map(|dep| format!("`{}`", dep.name))
+            .collect::<Vec<_>>();
+        deps.sort();
+        deps.dedup();
+        if !deps.is_empty() {
+            found.push(format!("the dependencies on {}", deps.join(", ")));
+        }
+
+        let features = |features: &HashMap<String, Vec<String>>| {
+            features
+                .iter()
+                .map(|(name, enables)| {
+                    let mut enables = enables.clone();
+                    enables.sort();
+                    (name.clone(), enables)
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+        if features(&self.features) != features(&published.features) {
+            found.push("the features".to_string());
+        }
+        if self.links != published.links {
+            found.push("`links`".to_string());
+        }
+        found
+    }
+}
+
+/// `deps`, with their requirements and targets all written one way: cargo
+/// counts `0.x` the same as `0.*` and `cfg(unix )` the same as `cfg(unix)`,
+/// but may send either.
+fn comparable(deps: &[Dependency]) -> Vec<Dependency> {
+    deps.iter()
+        .map(|dep| Dependency {
+            req: canonical_req(&dep.req),
+            target: dep.target.as_deref().map(canonical_target),
+            ..dep.clone()
+        })
+        .collect()
+}
+
+/// `req` as the range it matches.
+fn canonical_req(req: &str) -> String {
+    let req = cargo_req(req);
+    semver::VersionReq::parse(&req).map_or(req, |parsed| parsed.to_string())
+}
+
+/// `req` written the way cargo sends it when publishing, ex: `^1` for `1`.
+fn cargo_req(req: &str) -> String {
+    req.split(',')
+        .map(|part| match part.trim() {
+            part if part.starts_with(|c: char| c.is_ascii_digit()) => format!("^{}", part),
+            part => part.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `target` written the way cargo writes it when publishing.
+fn canonical_target(target: &str) -> String {
+    target
+        .parse::<Platform>()
+        .map_or_else(|_| target.to_string(), |platform| platform.to_string())
+}
+
+#[derive(Deserialize)]
+struct TomlManifest {
+    package: TomlPackage,
+    #[serde(default)]
+    dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default, alias = "build_dependencies", rename = "build-dependencies")]
+    build_dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default, alias = "dev_dependencies", rename = "dev-dependencies")]
+    dev_dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default)]
+    target: BTreeMap<String, TomlPlatform>,
+    #[serde(default)]
+    features: HashMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct TomlPackage {
+    name: String,
+    version: semver::Version,
+    links: Option<String>,
+    #[serde(default)]
+    authors: Vec<String>,
+    description: Option<String>,
+    documentation: Option<String>,
+    homepage: Option<String>,
+    /// A path, or (in manifests that weren't normalized) `false`.
+    readme: Option<toml::Value>,
+    #[serde(default)]
+    keywords: Vec<String>,
+    #[serde(default)]
+    categories: Vec<String>,
+    license: Option<String>,
+    license_file: Option<String>,
+    repository: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct TomlPlatform {
+    #[serde(default)]
+    dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default, alias = "build_dependencies", rename = "build-dependencies")]
+    build_dependencies: BTreeMap<String, TomlDependency>,
+    #[serde(default, alias = "dev_dependencies", rename = "dev-dependencies")]
+    dev_dependencies: BTreeMap<String, TomlDependency>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TomlDependency {
+    /// Just a version requirement, as older versions of cargo wrote them.
+    Simple(String),
+    Detailed(DetailedDependency),
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct DetailedDependency {
+    version: Option<String>,
+    #[serde(default)]
+    features: Vec<String>,
+    #[serde(default)]
+    optional: bool,
+    #[serde(alias = "default_features")]
+    default_features: Option<bool>,
+    package: Option<String>,
+    registry_index: Option<String>,
+}
+
+/// Read the manifest in `crate_file`, along with its readme.
+///
+/// Dependencies from any of `own_index_urls` (this registry's) are from the
+/// same registry as the crate, as far as the index is concerned.
+pub fn read(crate_file: &[u8], own_index_urls: &[String]) -> io::Result<Manifest> {
+    let mut manifest = read_from(crate_file, own_index_urls)?;
+    if let Some(ref path) = manifest.metadata.readme_file {
+        manifest.metadata.readme = tarball::read(crate_file, path, MAX_README_SIZE)
+            .ok()
+            .flatten()
+            .map(|readme| String::from_utf8_lossy(&readme).into_owned());
+    }
+    Ok(manifest)
+}
+
+/// Like [read], but leaving out the readme, so `crate_file` can be read
+/// straight from wherever it's kept (it's only read through once).
+pub fn read_from(crate_file: impl Read, own_index_urls: &[String]) -> io::Result<Manifest> {
+    let contents = tarball::read(crate_file, "Cargo.toml", MAX_MANIFEST_SIZE)?
+        .ok_or_else(|| invalid("there's no `Cargo.toml` in the crate file".to_string()))?;
+    let contents = String::from_utf8(contents).map_err(|e| invalid(e.to_string()))?;
+    let manifest: TomlManifest =
+        toml::from_str(&contents).map_err(|e| invalid(format!("`Cargo.toml`: {}", e)))?;
+
+    let mut tables = vec![
+        (None, &manifest.dependencies, DependencyKind::Normal),
+        (None, &manifest.build_dependencies, DependencyKind::Build),
+        (None, &manifest.dev_dependencies, DependencyKind::Dev),
+    ];
+    for (target, platform) in &manifest.target {
+        tables.push((Some(target), &platform.dependencies, DependencyKind::Normal));
+        tables.push((
+            Some(target),
+            &platform.build_dependencies,
+            DependencyKind::Build,
+        ));
+        tables.push((
+            Some(target),
+            &platform.dev_dependencies,
+            DependencyKind::Dev,
+        ));
+    }
+    let mut deps = vec![];
+    for (target, table, kind) in tables {
+        for (name, dep) in table {
+            deps.push(dependency(name, dep, target, kind.clone(), own_index_urls)?);
+        }
+    }
+
+    let package = manifest.package;
+    let readme_file = package
+        .readme
+        .as_ref()
+        .and_then(toml::Value::as_str)
+        .map(str::to_string);
+    Ok(Manifest {
+        name: package.name,
+        vers: package.version,
+        deps,
+        features: manifest.features,
+        links: package.links,
+        metadata: CrateMetadata {
+            description: package.description,
+            documentation: package.documentation,
+            homepage: package.homepage,
+            readme: None,
+            readme_file,
+            license: package.license,
+            license_file: package.license_file,
+            repository: package.repository,
+            keywords: package.keywords,
+            categories: package.categories,
+            authors: package.authors,
+        },
+    })
+}
+
+/// A dependency as the index has it: under the name it's used by, with the
+/// package it is when it's been renamed.
+fn dependency(
+    name: &str,
+    dep: &TomlDependency,
+    target: Option<&String>,
+    kind: DependencyKind,
+    own_index_urls: &[String],
+) -> io::Result<Dependency> {
+    let detailed = match dep {
+        TomlDependency::Simple(version) => DetailedDependency {
+            version: Some(version.clone()),
+            ..Default::default()
+        },
+        TomlDependency::Detailed(detailed) => detailed.clone(),
+    };
+    let req = detailed.version.as_deref().unwrap_or("*");
+    semver::VersionReq::parse(req)
+        .map_err(|e| invalid(format!("`{}` has the requirement `{}`: {}", name, req, e)))?;
+    let req = cargo_req(req);
+    let target = target
+        .map(|target| {
+            target
+                .parse::<Platform>()
+                .map(|platform| platform.to_string())
+        })
+        .transpose()
+        .map_err(|e| invalid(format!("`{}` has a target that isn't valid: {}", name, e)))?;
+    let registry = match detailed.registry_index {
+        Some(ref url) if own_index_urls.contains(url) => None,
+        Some(url) => Some(url),
+        None => Some(CRATES_IO_INDEX.to_string()),
+    };
+    Ok(Dependency {
+        name: name.to_string(),
+        req,
+        features: detailed.features,
+        optional: detailed.optional,
+        default_features: detailed.default_features.unwrap_or(true),
+        target,
+        kind,
+        registry,
+        package: detailed.package,
+    })
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_read() {
+        let crate_file = test_helpers::crate_file(test_helpers::MY_CRATE_0_1_0);
+        let manifest = read(&crate_file, &[]).unwrap();
+        assert_eq!("my-crate", manifest.name);
+        assert_eq!("0.1.0", manifest.vers.to_string());
+        assert_eq!(
+            vec!["Owen Nelson <onelson@gmail.com>".to_string()],
+            manifest.metadata.authors
+        );
+        assert_eq!(None, manifest.metadata.readme);
+
+        // The same as cargo sent when it was published.
+        let body = test_helpers::MY_CRATE_0_1_0;
+        let len = u32::from_le_bytes([body[0], body[1], body[2], body[3]]) as usize;
+        let published: serde_json::Value = serde_json::from_slice(&body[4..4 + len]).unwrap();
+        assert_eq!(
+            serde_json::from_value::<Vec<Dependency>>(published["deps"].clone()).unwrap(),
+            manifest.deps
+        );
+        assert!(manifest.features.is_empty());
+        assert!(manifest
+            .differences(&manifest.package_version("abc"))
+            .is_empty());
+        assert_eq!("abc", manifest.package_version("abc").cksum);
+    }
+
+    #[test]
+    fn test_dependency() {
+        let manifest: TomlManifest = toml::from_str(
+            r#"
+            [package]
+            name = "my-crate"
+            version = "0.1.0"
+
+            [dependencies]
+            simple = "1.2"
+
+            [dependencies.renamed]
+            version = "0.3"
+            package = "real-name"
+            default-features = false
+            registry-index = "sparse+http://localhost:7878/index/"
+
+            [target."cfg(unix)".dev-dependencies.other]
+            version = "=2.0.0"
+            registry-index = "https://example.com/git/index"
+            "#,
+        )
+        .unwrap();
+        let own = vec!["sparse+http://localhost:7878/index/".to_string()];
+        let renamed = dependency(
+            "renamed",
+            &manifest.dependencies["renamed"],
+            None,
+            DependencyKind::Normal,
+            &own,
+        )
+        .unwrap();
+        assert_eq!("renamed", renamed.name);
+        assert_eq!(Some("real-name".to_string()), renamed.package);
+        assert_eq!("^0.3", renamed.req);
+        assert!(!renamed.default_features);
+        assert_eq!(None, renamed.registry);
+
+        let simple = dependency(
+            "simple",
+            &manifest.dependencies["simple"],
+            None,
+            DependencyKind::Normal,
+            &own,
+        )
+        .unwrap();
+        assert!(simple.default_features);
+        assert_eq!(Some(CRATES_IO_INDEX.to_string()), simple.registry);
+
+        let target = "cfg(unix)".to_string();
+        let other = dependency(
+            "other",
+            &manifest.target[&target].dev_dependencies["other"],
+            Some(&target),
+            DependencyKind::Dev,
+            &own,
+        )
+        .unwrap();
+        assert_eq!(Some(target), other.target);
+        assert_eq!(DependencyKind::Dev, other.kind);
+        assert_eq!("=2.0.0", other.req);
+        assert_eq!(
+            Some("https://example.com/git/index".to_string()),
+            other.registry
+        );
+    }
+}
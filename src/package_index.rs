@@ -19,23 +19,100 @@
 //!
 //! Currently none of these restrictions are being performed. This may come in
 //! the future.
+
+pub mod fsck;
+pub mod lock;
+pub mod mirror;
+pub mod signing;
+pub mod upload_pack;
+
 use crate::errors::PackageIndexError;
-#[cfg(test)]
-use git2::Oid;
-use git2::{Repository, RepositoryInitOptions, Signature};
+use git2::{Oid, Repository, RepositoryInitOptions, Signature, Sort};
 use serde::{Deserialize, Serialize};
+use signing::SigningKey;
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::{BufReader, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 type Result<T> = std::result::Result<T, PackageIndexError>;
 
+/// The protocols cargo can use to read the index.
+///
+/// The index itself is always managed as a git repo, but the files in the
+/// working tree can also be served directly over http for clients using a
+/// `sparse+http` index url.
+///
+/// The `config.json` is the same regardless of the protocol used to fetch it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndexProtocol {
+    /// Only expose the index via git's smart http transport.
+    Git,
+    /// Only expose the index via cargo's sparse http protocol.
+    Sparse,
+    /// Expose the index via both git and sparse http.
+    Both,
+}
+
+impl IndexProtocol {
+    /// Should the git transport endpoints be registered?
+    pub fn git_enabled(&self) -> bool {
+        matches!(self, Self::Git | Self::Both)
+    }
+
+    /// Should the sparse index endpoints be registered?
+    pub fn sparse_enabled(&self) -> bool {
+        matches!(self, Self::Sparse | Self::Both)
+    }
+}
+
+/// Every url cargo may know a registry's index by: the `index_url` it's told
+/// to use, and the default url (under `base_url`) of each `protocol` served.
+pub fn index_urls(index_url: &str, base_url: &str, protocol: IndexProtocol) -> Vec<String> {
+    let mut urls = vec![index_url.to_string()];
+    let git = format!("{}/git/index", base_url);
+    let sparse = format!("sparse+{}/index/", base_url);
+    for (url, enabled) in [
+        (git, protocol.git_enabled()),
+        (sparse, protocol.sparse_enabled()),
+    ] {
+        if enabled && !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+impl std::str::FromStr for IndexProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "git" => Ok(Self::Git),
+            "sparse" => Ok(Self::Sparse),
+            "both" => Ok(Self::Both),
+            _ => Err(format!("Unknown index protocol: `{}`", s)),
+        }
+    }
+}
+
 /// The config data for the registry.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Config {
     pub dl: String,
     pub api: String,
+    /// Tells cargo it must send its token when reading the index and
+    /// downloading crates, not just for publishing.
+    ///
+    /// Left out of the file entirely when `false`.
+    #[serde(
+        rename = "auth-required",
+        default,
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub auth_required: bool,
 }
 
 /// These records appear, one per line per version, in each crate file.
@@ -121,8 +198,69 @@ pub enum DependencyKind {
     Normal,
 }
 
+/// The kinds of change to a package version recorded in the index history.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeOp {
+    Publish,
+    Yank,
+    Unyank,
+    /// Removed from the index altogether.
+    Delete,
+}
+
+/// A single change to the index, as recovered from the git history.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct IndexChange {
+    #[serde(rename = "crate")]
+    pub name: String,
+    pub version: semver::Version,
+    pub op: ChangeOp,
+    /// Unix timestamp (seconds) of the commit that made the change.
+    pub timestamp: i64,
+    /// The id of the commit that made the change.
+    pub commit: String,
+}
+
+/// Where the history from before each [squash](PackageIndex::squash) is kept.
+const SNAPSHOTS: &str = "refs/snapshots/";
+
+/// The commit the history before a squash ends at, from the squash's commit
+/// message.
+fn parse_snapshot(msg: &str) -> Option<Oid> {
+    let id = msg.split(SNAPSHOTS).nth(1)?.split('`').next()?;
+    Oid::from_str(id).ok()
+}
+
+/// Recover the operation, crate name, and version from an index commit
+/// message, ex: ``publish crate: `foo v0.1.0` ``.
+///
+/// Commits that aren't about a specific package version (like config
+/// updates) give `None`.
+fn parse_commit_message(msg: &str) -> Option<(ChangeOp, String, semver::Version)> {
+    let (verb, rest) = msg.split_once(" crate: ")?;
+    let op = match verb {
+        "publish" => ChangeOp::Publish,
+        "yank" => ChangeOp::Yank,
+        "unyank" => ChangeOp::Unyank,
+        "delete" => ChangeOp::Delete,
+        _ => return None,
+    };
+    let middle = rest.split('`').nth(1)?;
+    let mut parts = middle.split_whitespace();
+    let name = parts.next()?.to_string();
+    let vers = parts.next()?.trim_start_matches('v').parse().ok()?;
+    Some((op, name, vers))
+}
+
 pub struct PackageIndex {
     repo: Repository,
+    mirror: Option<mirror::Pusher>,
+    /// The changes staged but not yet committed, when commits are being
+    /// [coalesced](Self::coalesce_commits), with their messages and authors.
+    pending: Option<RefCell<Vec<(String, Author)>>>,
+    /// Who commits are made by, and credited to when nobody else is.
+    committer: Committer,
 }
 
 impl PackageIndex {
@@ -136,12 +274,25 @@ impl PackageIndex {
     /// An attempt to update the config (if necessary) using the supplied values
     /// will be made.
     pub fn init<P>(path: P, config: &Config) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Self::init_as(path, config, &Committer::default())
+    }
+
+    /// [Initialize](Self::init) an index, with its commits made by
+    /// `committer` (see [with_committer](Self::with_committer)) from the
+    /// first.
+    pub fn init_as<P>(path: P, config: &Config, committer: &Committer) -> Result<Self>
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
         let pkg_index = Self {
-            repo: get_or_create_repo(path)?,
+            repo: get_or_create_repo(path, committer)?,
+            mirror: None,
+            pending: None,
+            committer: committer.clone(),
         };
         let current_config: Option<Config> = pkg_index.read_config().ok();
 
@@ -149,38 +300,162 @@ impl PackageIndex {
             // XXX: might need to think about reverting if something fails part way
             // through the operation.
             pkg_index.write_config(config)?;
-            pkg_index.add_and_commit_file("config.json", "update registry config")?;
+            pkg_index.add_and_commit_file(
+                "config.json",
+                "update registry config",
+                &pkg_index.committer.identity,
+            )?;
         }
         Ok(pkg_index)
     }
 
+    /// Open an index that's already there, leaving its config as it is.
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self {
+            repo: Repository::open(path)?,
+            mirror: None,
+            pending: None,
+            committer: Committer::default(),
+        })
+    }
+
+    /// Make commits as `committer`, rather than as estuary's own
+    /// [default](Author::default) (unsigned). Changes are still credited (as
+    /// their author) to whoever made them.
+    pub fn with_committer(self, committer: Committer) -> Self {
+        Self { committer, ..self }
+    }
+
+    /// Who commits are made by (see [with_committer](Self::with_committer)).
+    pub fn identity(&self) -> &Author {
+        &self.committer.identity
+    }
+
+    /// Stage each change rather than committing it straight away, until
+    /// [commit_pending](Self::commit_pending) commits them all together, so a
+    /// burst of publishes makes a single commit.
+    ///
+    /// Staged changes are in the working tree (so publishes see them, and
+    /// the sparse index serves them) but the git transport only sees them
+    /// once they're committed.
+    pub fn coalesce_commits(self) -> Self {
+        Self {
+            pending: Some(Default::default()),
+            ..self
+        }
+    }
+
+    /// Commit whatever was left staged but not committed (by a server that
+    /// stopped before committing its [coalesced](Self::coalesce_commits)
+    /// changes, say), handing back whether there was anything.
+    ///
+    /// Otherwise the next commit would take those changes in, under a message
+    /// that doesn't mention them.
+    pub fn commit_staged(&self) -> Result<bool> {
+        let tree_id = self.repo.index()?.write_tree()?;
+        if self.repo.head()?.peel_to_tree()?.id() == tree_id {
+            return Ok(false);
+        }
+        self.commit_tree(
+            tree_id,
+            "commit changes left staged",
+            &self.committer.identity,
+        )?;
+        Ok(true)
+    }
+
+    /// Commit whatever's been staged since the last commit, as one commit
+    /// listing each change (or as the change's own commit, if there's only
+    /// the one).
+    pub fn commit_pending(&self) -> Result<()> {
+        let pending = match &self.pending {
+            Some(pending) if !pending.borrow().is_empty() => pending,
+            _ => return Ok(()),
+        };
+        let changes = pending.borrow().clone();
+        let msg = match changes.as_slice() {
+            [(msg, _)] => msg.clone(),
+            _ => {
+                let msgs = changes.iter().map(|(msg, _)| msg.as_str());
+                format!(
+                    "update index: {} changes\n\n{}\n",
+                    changes.len(),
+                    msgs.collect::<Vec<_>>().join("\n")
+                )
+            }
+        };
+        // Credited to whoever made the changes, when there's only the one.
+        let author = match changes.first() {
+            Some((_, author)) if changes.iter().all(|(_, other)| other == author) => author.clone(),
+            _ => self.committer.identity.clone(),
+        };
+        let tree_id = self.repo.index()?.write_tree()?;
+        self.commit_tree(tree_id, &msg, &author)?;
+        pending.borrow_mut().clear();
+        Ok(())
+    }
+
     /// Add a file, then commit it to the git repo.
     ///
     /// Roughly equivalent to:
     ///
     /// ```text
-    /// git add <path> && git commit -m <msg>
+    /// git add <path> && git commit -m <msg> --author <author>
     /// ```
-    fn add_and_commit_file<P>(&self, path: P, msg: &str) -> Result<()>
+    /// Commit the file at `path` as it is in the working tree (or its removal,
+    /// once it's gone from there).
+    fn add_and_commit_file<P>(&self, path: P, msg: &str, author: &Author) -> Result<()>
     where
         P: AsRef<Path>,
     {
-        let head = self.repo.head()?;
-        let parent = head.peel_to_commit()?;
+        self.add_and_commit_files(&[path.as_ref()], msg, author)
+    }
+
+    /// Commit each of `paths` the same way, all together.
+    fn add_and_commit_files(&self, paths: &[&Path], msg: &str, author: &Author) -> Result<()> {
         let mut index = self.repo.index()?;
-        index.add_path(path.as_ref())?;
+        for path in paths {
+            if self.repo.workdir().unwrap().join(path).exists() {
+                index.add_path(path)?;
+            } else {
+                index.remove_path(path)?;
+            }
+        }
         index.write()?;
+        if let Some(pending) = &self.pending {
+            pending.borrow_mut().push((msg.to_string(), author.clone()));
+            return Ok(());
+        }
         let tree_id = index.write_tree()?;
+        self.commit_tree(tree_id, msg, author)
+    }
+
+    /// Commit `tree_id` on top of HEAD.
+    fn commit_tree(&self, tree_id: Oid, msg: &str, author: &Author) -> Result<()> {
+        let parent = self.repo.head()?.peel_to_commit()?;
         let tree = self.repo.find_tree(tree_id)?;
-        let sig = get_sig()?;
-        self.repo
-            .commit(Some("HEAD"), &sig, &sig, msg, &tree, &[&parent])?;
+        let author = get_sig(author)?;
+        let committer = get_sig(&self.committer.identity)?;
+        make_commit(
+            &self.repo,
+            &self.committer,
+            true,
+            &author,
+            &committer,
+            msg,
+            &tree,
+            &[&parent],
+        )?;
         git_update_server_info(&self.repo)?;
+        self.push_mirror();
         Ok(())
     }
 
     /// Read and parse the config file from the registry root directory.
-    fn read_config(&self) -> Result<Config> {
+    pub fn read_config(&self) -> Result<Config> {
         let fh = std::fs::File::open(self.repo.workdir().unwrap().join("config.json"))?;
         Ok(serde_json::from_reader(fh)?)
     }
@@ -206,7 +481,10 @@ impl PackageIndex {
     ///
     /// If the version already exists in the package file, this function will
     /// return an `Err`.
-    pub fn publish(&self, pkg: &PackageVersion) -> Result<()> {
+    ///
+    /// The commit is credited to `author`, so the index history records who
+    /// published what.
+    pub fn publish(&self, pkg: &PackageVersion, author: &Author) -> Result<()> {
         let root = self.repo.workdir().unwrap();
         let dir = get_package_file_dir(&pkg.name)?;
         std::fs::create_dir_all(root.join(&dir))?;
@@ -215,6 +493,7 @@ impl PackageIndex {
         // "touch" the file to make sure it's available for reading.
         OpenOptions::new()
             .create(true)
+            .truncate(false)
             .write(true)
             .open(root.join(&pkg_file))?;
 
@@ -245,22 +524,32 @@ impl PackageIndex {
         self.add_and_commit_file(
             pkg_file,
             &format!("publish crate: `{} v{}`", pkg.name, pkg.vers),
+            author,
         )?;
         Ok(())
     }
 
     /// Get the contents of a package file.
-    fn read_package_file(&self, name: &str) -> Result<String> {
+    ///
+    /// When there's no file for `name` exactly, that of the crate it
+    /// collides with (see [`normalize_name`]) is read instead.
+    pub fn read_package_file(&self, name: &str) -> Result<String> {
         let root = self.repo.workdir().unwrap();
         let dir = get_package_file_dir(name)?;
-        std::fs::create_dir_all(root.join(&dir))?;
-        let pkg_file = dir.join(name);
-        let mut fh = BufReader::new(
-            OpenOptions::new()
-                .create(false)
-                .read(true)
-                .open(root.join(&pkg_file))?,
-        );
+        let fh = match OpenOptions::new()
+            .create(false)
+            .read(true)
+            .open(root.join(dir.join(name)))
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                match self.find_crate_name(name)? {
+                    Some(found) if found != name => return self.read_package_file(&found),
+                    _ => return Err(e.into()),
+                }
+            }
+            fh => fh?,
+        };
+        let mut fh = BufReader::new(fh);
 
         let mut buf = String::new();
         fh.read_to_string(&mut buf)?;
@@ -287,8 +576,15 @@ impl PackageIndex {
         Ok(())
     }
 
-    /// Updates the `yanked` field of a given package version.
-    pub fn set_yanked(&self, name: &str, version: &semver::Version, yanked: bool) -> Result<()> {
+    /// Updates the `yanked` field of a given package version, crediting the
+    /// commit to `author`.
+    pub fn set_yanked(
+        &self,
+        name: &str,
+        version: &semver::Version,
+        yanked: bool,
+        author: &Author,
+    ) -> Result<()> {
         // This is the most naive impl I can think of for this, but it should get
         // things rolling.
         // Read the whole package file, json parse all lines, modify the struct that
@@ -297,6 +593,8 @@ impl PackageIndex {
         // A better version of this would modify the specific line in the file, I
         // guess.
 
+        let found = self.find_crate_name(name)?;
+        let name = found.as_deref().unwrap_or(name);
         let mut pkg_versions = self
             .read_package_file(name)?
             .lines()
@@ -324,11 +622,48 @@ impl PackageIndex {
         self.add_and_commit_file(
             dir.join(name),
             &format!("{} crate: `{} v{}`", verb, name, version),
+            author,
         )?;
 
         Ok(())
     }
 
+    /// Remove a version from the index altogether (and the package file with
+    /// its last version), crediting the commit to `author`.
+    ///
+    /// Gives back what the index had for the version, or `None` when it had
+    /// nothing.
+    pub fn delete_version(
+        &self,
+        name: &str,
+        version: &semver::Version,
+        author: &Author,
+    ) -> Result<Option<PackageVersion>> {
+        let name = match self.find_crate_name(name)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let mut pkg_versions = self.get_package_versions(&name)?;
+        let pkg = match pkg_versions.iter().position(|pkg| &pkg.vers == version) {
+            Some(i) => pkg_versions.remove(i),
+            None => return Ok(None),
+        };
+
+        let dir = get_package_file_dir(&name)?;
+        if pkg_versions.is_empty() {
+            std::fs::remove_file(self.repo.workdir().unwrap().join(&dir).join(&name))?;
+        } else {
+            self.rewrite_package_file(&name, &pkg_versions)?;
+        }
+        self.add_and_commit_file(
+            dir.join(&name),
+            &format!("delete crate: `{} v{}`", name, version),
+            author,
+        )?;
+
+        Ok(Some(pkg))
+    }
+
     // XXX: we might want this irl for debug pages or whatever.
     #[cfg(test)]
     fn get_repo_log(&self) -> Result<Vec<(Oid, Option<String>)>> {
@@ -346,7 +681,7 @@ impl PackageIndex {
         let it = reflog.iter().filter_map(|entry| {
             let msg = entry.message().unwrap_or("");
             if msg.contains("publish crate") {
-                let middle = msg.splitn(3, '`').nth(1).unwrap();
+                let middle = msg.split('`').nth(1).unwrap();
                 let mut parts = middle.split_whitespace();
                 let (pkg, vers) = (
                     parts.next().unwrap().to_string(),
@@ -365,6 +700,124 @@ impl PackageIndex {
         }
     }
 
+    /// List the publish, yank, unyank, and delete operations in the index
+    /// history, oldest first.
+    ///
+    /// When `since` is given, only changes made *after* that commit are
+    /// included, which lets a mirror poll for incremental updates by passing
+    /// the last commit it saw.
+    pub fn get_changes(
+        &self,
+        since: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<Vec<IndexChange>> {
+        let since = match since {
+            Some(id) => Some(self.repo.find_commit(Oid::from_str(id)?)?.id()),
+            None => None,
+        };
+
+        let mut changes = self.walk_changes(since, None)?;
+        changes.reverse();
+
+        if let Some(limit) = limit {
+            changes.truncate(limit);
+        }
+        Ok(changes)
+    }
+
+    /// The `limit` most recent publish, yank, unyank, and delete operations in the
+    /// index history, newest first.
+    pub fn get_recent_changes(&self, limit: usize) -> Result<Vec<IndexChange>> {
+        self.walk_changes(None, Some(limit))
+    }
+
+    /// The changes made after `since` (or ever), newest first, stopping at
+    /// `limit` of them.
+    ///
+    /// The history from before a [squash](Self::squash) is walked too, after
+    /// the history since.
+    fn walk_changes(&self, since: Option<Oid>, limit: Option<usize>) -> Result<Vec<IndexChange>> {
+        let mut changes = vec![];
+        let mut tip = Some(self.repo.refname_to_id("HEAD")?);
+        while let Some(id) = tip.take() {
+            let mut walk = self.repo.revwalk()?;
+            walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+            walk.push(id)?;
+            for oid in walk {
+                let oid = oid?;
+                if Some(oid) == since || Some(changes.len()) == limit {
+                    return Ok(changes);
+                }
+                let commit = self.repo.find_commit(oid)?;
+                // Coalesced commits list their changes a line each, oldest
+                // first.
+                let msg = commit.message().unwrap_or("");
+                for (op, name, version) in msg.lines().rev().filter_map(parse_commit_message) {
+                    if Some(changes.len()) == limit {
+                        return Ok(changes);
+                    }
+                    changes.push(IndexChange {
+                        name,
+                        version,
+                        op,
+                        timestamp: commit.time().seconds(),
+                        commit: oid.to_string(),
+                    });
+                }
+                if commit.parent_count() == 0 {
+                    tip = commit.message().and_then(parse_snapshot);
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Replace the history with a single commit of the index as it is, so
+    /// fresh clones don't have to fetch every change ever made, handing back
+    /// the new commit's id (or `None` when there's only the one commit
+    /// already).
+    ///
+    /// The history is kept under `refs/snapshots/`, so clones that are behind
+    /// still have it in common with the index when they fetch, and the
+    /// changes feed still goes back past the squash.
+    pub fn squash(&self) -> Result<Option<String>> {
+        self.commit_pending()?;
+        let head = self.repo.head()?;
+        let commit = head.peel_to_commit()?;
+        if commit.parent_count() == 0 {
+            return Ok(None);
+        }
+        let snapshot = format!("{}{}", SNAPSHOTS, commit.id());
+        self.repo
+            .reference(&snapshot, commit.id(), false, "snapshot before squashing")?;
+
+        let sig = get_sig(&self.committer.identity)?;
+        let msg = format!(
+            "squash index\n\nThe history before this is kept as `{}`.\n",
+            snapshot
+        );
+        let id = make_commit(
+            &self.repo,
+            &self.committer,
+            false,
+            &sig,
+            &sig,
+            &msg,
+            &commit.tree()?,
+            &[],
+        )?;
+        // The branch is moved rather than HEAD, so it isn't left detached.
+        match head.name().filter(|_| head.is_branch()) {
+            Some(name) => {
+                self.repo.reference(name, id, true, "squash index")?;
+            }
+            None => self.repo.set_head_detached(id)?,
+        }
+        git_update_server_info(&self.repo)?;
+        self.push_mirror();
+        Ok(Some(id.to_string()))
+    }
+
     /// Get the [`PackageVersion`] given a crate name and (optional) version.
     /// When `vers` is not specified, the latest available version will be
     /// returned.
@@ -373,10 +826,54 @@ impl PackageIndex {
     /// version was not found.
     pub fn get_package_versions(&self, name: &str) -> Result<Vec<PackageVersion>> {
         let contents = self.read_package_file(name)?;
-        Ok(contents
+        contents
             .lines()
             .map(|s| serde_json::from_str(s).map_err(PackageIndexError::from))
-            .collect::<Result<Vec<PackageVersion>>>()?)
+            .collect::<Result<Vec<PackageVersion>>>()
+    }
+
+    /// Find the name a crate was published under, given any name that
+    /// collides with it (see [`normalize_name`]).
+    pub fn find_crate_name(&self, name: &str) -> Result<Option<String>> {
+        let root = self.repo.workdir().unwrap();
+        if root.join(get_package_file_dir(name)?).join(name).is_file() {
+            return Ok(Some(name.to_string()));
+        }
+        let wanted = normalize_name(name);
+        // The directory depends on the first few characters of the name, so
+        // look in every one a separator in those could have put the crate in.
+        let prefix_len = match wanted.len() {
+            0..=2 => 0,
+            3 => 1,
+            _ => 4,
+        };
+        let mut prefixes = vec![String::new()];
+        for c in wanted.chars().take(prefix_len) {
+            let options: &[char] = if c == '-' { &['-', '_'] } else { &[c] };
+            prefixes = prefixes
+                .iter()
+                .flat_map(|prefix| options.iter().map(move |c| format!("{}{}", prefix, c)))
+                .collect();
+        }
+        for prefix in prefixes {
+            let rest = wanted.chars().skip(prefix_len).collect::<String>();
+            let candidate = format!("{}{}", prefix, rest);
+            let dir = root.join(get_package_file_dir(&candidate)?);
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for entry in entries {
+                let file_name = entry?.file_name();
+                if let Some(file_name) = file_name.to_str() {
+                    if normalize_name(file_name) == wanted {
+                        return Ok(Some(file_name.to_string()));
+                    }
+                }
+            }
+        }
+        Ok(None)
     }
 
     /// Get a list of crates published to the index.
@@ -386,20 +883,14 @@ impl PackageIndex {
 
         // TODO: maybe rewrite with a recursive fn and fs::read_dir().
         //  Probably it'd be more efficient to do it without globs.
-        for entry in glob::glob(&format!("{}/[1,2]/*", root.display()))? {
-            if let Ok(path) = entry {
-                acc.push(path.file_name().unwrap().to_str().unwrap().to_string());
-            }
+        for path in glob::glob(&format!("{}/[1,2]/*", root.display()))?.flatten() {
+            acc.push(path.file_name().unwrap().to_str().unwrap().to_string());
         }
-        for entry in glob::glob(&format!("{}/3/?/*", root.display()))? {
-            if let Ok(path) = entry {
-                acc.push(path.file_name().unwrap().to_str().unwrap().to_string());
-            }
+        for path in glob::glob(&format!("{}/3/?/*", root.display()))?.flatten() {
+            acc.push(path.file_name().unwrap().to_str().unwrap().to_string());
         }
-        for entry in glob::glob(&format!("{}/??/??/*", root.display()))? {
-            if let Ok(path) = entry {
-                acc.push(path.file_name().unwrap().to_str().unwrap().to_string());
-            }
+        for path in glob::glob(&format!("{}/??/??/*", root.display()))?.flatten() {
+            acc.push(path.file_name().unwrap().to_str().unwrap().to_string());
         }
         Ok(acc)
     }
@@ -421,10 +912,13 @@ impl PackageIndex {
 ///   characters of the package name, and the next subdirectory is the third and
 ///   fourth characters of the package name. For example, `cargo` would be
 ///   stored in a file named `ca/rg/cargo`.
-fn get_package_file_dir(name: &str) -> Result<PathBuf> {
+pub fn get_package_file_dir(name: &str) -> Result<PathBuf> {
     let name = name.trim().to_lowercase();
     match name.len() {
-        0 => Err(PackageIndexError::InvalidPackageName(name)),
+        0 => Err(PackageIndexError::InvalidPackageName(
+            name,
+            "it's empty".to_string(),
+        )),
         1 => Ok(PathBuf::from("1/")),
         2 => Ok(PathBuf::from("2/")),
         3 => {
@@ -442,16 +936,157 @@ fn get_package_file_dir(name: &str) -> Result<PathBuf> {
     }
 }
 
-/// Get a git signature for "the system".
-fn get_sig() -> Result<Signature<'static>> {
-    Ok(Signature::now("estuary", "admin@localhost")?)
+/// The form of a crate name that cargo compares to tell whether two crates
+/// collide: lowercased, with `_` counted the same as `-`.
+pub fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase().replace('_', "-")
+}
+
+/// The longest name a crate can be published under (the same as crates.io).
+pub const MAX_NAME_LEN: usize = 64;
+
+/// Names Windows keeps for devices, which can't be used for files (like the
+/// crate's package file) there.
+const RESERVED_NAMES: &[&str] = &[
+    "aux", "com0", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9", "con",
+    "lpt0", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9", "nul", "prn",
+];
+
+/// Check `name` follows the rules cargo recommends for crate names: ASCII
+/// letters, digits, `-` and `_` only, starting with a letter, no longer than
+/// [MAX_NAME_LEN], and not a name Windows reserves.
+///
+/// Whether it collides with a crate that's already published is up to the
+/// index (see [`PackageIndex::find_crate_name`]).
+pub fn validate_package_name(name: &str) -> Result<()> {
+    let invalid = |reason: String| {
+        Err(PackageIndexError::InvalidPackageName(
+            name.to_string(),
+            reason,
+        ))
+    };
+    let first = match name.chars().next() {
+        Some(first) => first,
+        None => return invalid("it's empty".to_string()),
+    };
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_'))
+    {
+        return invalid(format!(
+            "`{}` isn't allowed, only ASCII letters, numbers, `-` and `_` are",
+            c
+        ));
+    }
+    if !first.is_ascii_alphabetic() {
+        return invalid("it needs to start with a letter".to_string());
+    }
+    if name.len() > MAX_NAME_LEN {
+        return invalid(format!(
+            "it's {} characters long, the most allowed is {}",
+            name.len(),
+            MAX_NAME_LEN
+        ));
+    }
+    if RESERVED_NAMES.contains(&name.to_lowercase().as_str()) {
+        return invalid("it's reserved by Windows".to_string());
+    }
+    Ok(())
+}
+
+/// Who a change to the index is credited to, as the author of its commit.
+///
+/// The commits themselves are always made by "the system".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Author {
+    pub name: String,
+    pub email: String,
+}
+
+impl Default for Author {
+    /// "The system", for changes nobody in particular asked for.
+    fn default() -> Self {
+        Self {
+            name: "estuary".to_string(),
+            email: "admin@localhost".to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for Author {
+    type Err = String;
+
+    /// Read an identity the way git writes one, ex: `Jane Doe <jane@example.com>`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("Expected `Name <email>`, not `{}`", s);
+        let (name, rest) = s.split_once('<').ok_or_else(invalid)?;
+        let email = rest.strip_suffix('>').ok_or_else(invalid)?.trim();
+        let name = name.trim();
+        if name.is_empty() || email.is_empty() || email.contains(&['<', '>'][..]) {
+            return Err(invalid());
+        }
+        Ok(Self {
+            name: name.to_string(),
+            email: email.to_string(),
+        })
+    }
+}
+
+/// Who makes the index's commits, and the key they're signed with (if they
+/// are).
+#[derive(Clone, Debug, Default)]
+pub struct Committer {
+    pub identity: Author,
+    pub signing_key: Option<Arc<SigningKey>>,
+}
+
+/// Make a commit, signed when the `committer` has a key, moving `HEAD` (or
+/// the branch it's on) to it when `move_head` is set.
+#[allow(clippy::too_many_arguments)]
+fn make_commit(
+    repo: &Repository,
+    committer: &Committer,
+    move_head: bool,
+    author: &Signature,
+    committer_sig: &Signature,
+    msg: &str,
+    tree: &git2::Tree,
+    parents: &[&git2::Commit],
+) -> Result<Oid> {
+    let key = match &committer.signing_key {
+        Some(key) => key,
+        None => {
+            let update_ref = Some("HEAD").filter(|_| move_head);
+            return Ok(repo.commit(update_ref, author, committer_sig, msg, tree, parents)?);
+        }
+    };
+    let buf = repo.commit_create_buffer(author, committer_sig, msg, tree, parents)?;
+    let buf = buf
+        .as_str()
+        .ok_or_else(|| PackageIndexError::Signing("the commit isn't utf-8".to_string()))?;
+    let id = repo.commit_signed(buf, &key.sign(buf.as_bytes())?, Some("gpgsig"))?;
+    if move_head {
+        let reflog = format!("commit: {}", msg.lines().next().unwrap_or(""));
+        match repo.find_reference("HEAD")?.symbolic_target() {
+            Some(branch) => {
+                repo.reference(branch, id, true, &reflog)?;
+            }
+            None => repo.set_head_detached(id)?,
+        }
+    }
+    Ok(id)
+}
+
+/// Get a git signature for `author`.
+fn get_sig(author: &Author) -> Result<Signature<'static>> {
+    Ok(Signature::now(&author.name, &author.email)?)
 }
 
-fn get_or_create_repo<P>(root: P) -> Result<Repository>
+fn get_or_create_repo<P>(root: P, committer: &Committer) -> Result<Repository>
 where
     P: AsRef<Path>,
 {
-    let sig = get_sig()?;
+    let sig = get_sig(&committer.identity)?;
     let root = root.as_ref();
 
     let is_empty = match std::fs::read_dir(root) {
@@ -461,10 +1096,9 @@ where
 
     if is_empty {
         log::debug!("Creating a fresh index.");
-        let repo =
-            Repository::init_opts(root, RepositoryInitOptions::new().mkdir(true)).map_err(|e| {
+        let repo = Repository::init_opts(root, RepositoryInitOptions::new().mkdir(true))
+            .inspect_err(|_| {
                 log::error!("Failed to init git repo");
-                e
             })?;
 
         {
@@ -473,19 +1107,61 @@ where
                 index.write_tree()?
             };
             let tree = repo.find_tree(tree_id)?;
-            repo.commit(Some("HEAD"), &sig, &sig, "init empty repo", &tree, &[])?;
+            make_commit(
+                &repo,
+                committer,
+                true,
+                &sig,
+                &sig,
+                "init empty repo",
+                &tree,
+                &[],
+            )?;
             git_update_server_info(&repo)?;
         }
         Ok(repo)
     } else {
         log::debug!("Using pre-existing index.");
-        Ok(Repository::open(root).map_err(|e| {
+        Ok(Repository::open(root).inspect_err(|_| {
             log::error!("Failed to open git repo");
-            e
         })?)
     }
 }
 
+/// A ref, as git advertises it to clients.
+#[derive(Debug, PartialEq)]
+pub struct AdvertisedRef {
+    pub name: String,
+    pub oid: Oid,
+    /// What it points at, when it's an annotated tag.
+    pub peeled: Option<Oid>,
+}
+
+/// Every ref in `repo` that points at something (`HEAD` aside), by name.
+fn list_refs(repo: &Repository) -> Result<Vec<AdvertisedRef>> {
+    let mut refs = vec![];
+    for reference in repo.references()? {
+        let reference = reference?;
+        let name = match reference.name() {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        // A symbolic ref is advertised as whatever it points at.
+        let target = match reference.resolve() {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        let oid = match target.target() {
+            Some(oid) => oid,
+            None => continue,
+        };
+        let peeled = Some(target.peel(git2::ObjectType::Any)?.id()).filter(|&id| id != oid);
+        refs.push(AdvertisedRef { name, oid, peeled });
+    }
+    refs.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(refs)
+}
+
 /// The post-update hook is normally used to generate `info/refs` for
 /// each branch when you push to a git server. This is achieved by
 /// running `git update-server-info`.
@@ -494,28 +1170,52 @@ where
 /// See: https://git-scm.com/book/en/v2/Git-on-the-Server-The-Protocols
 ///
 /// Our "git server" is closer to a plain working tree (like a clone) so
-/// we'd never push to this. In order to expose our git repo to `cargo` we can
-/// basically run this command after each commit.
+/// we'd never push to this. In order to expose our git repo to `cargo` we
+/// write the same files `git update-server-info` would after each commit.
 fn git_update_server_info(repo: &Repository) -> Result<()> {
-    // FIXME: see if we can do this without shelling out.
-    Ok(std::process::Command::new("git")
-        .current_dir(repo.workdir().unwrap())
-        .arg("update-server-info")
-        .output()
-        .map(|x| {
-            if x.status.success() {
-                log::trace!("git says: {:?}", x);
-            } else {
-                log::error!("git says: {:?}", x);
+    let mut refs = String::new();
+    for r in list_refs(repo)? {
+        refs.push_str(&format!("{}\t{}\n", r.oid, r.name));
+        if let Some(peeled) = r.peeled {
+            refs.push_str(&format!("{}\t{}^{{}}\n", peeled, r.name));
+        }
+    }
+    write_atomically(&repo.path().join("info/refs"), refs.as_bytes())?;
+
+    let mut packs = vec![];
+    if let Ok(entries) = std::fs::read_dir(repo.path().join("objects/pack")) {
+        for entry in entries {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".pack") {
+                packs.push(name);
             }
-        })?)
+        }
+    }
+    packs.sort();
+    let packs: String = packs
+        .iter()
+        .map(|name| format!("P {}\n", name))
+        .chain(std::iter::once("\n".to_string()))
+        .collect();
+    write_atomically(&repo.path().join("objects/info/packs"), packs.as_bytes())?;
+    Ok(())
+}
+
+/// Write `path` by way of a temporary file beside it, so it's never seen
+/// half-written.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use serde_json::json;
-    use tempdir::TempDir;
+    use tempfile::Builder;
 
     /// Parse the sample object from the cargo docs to verify our structs
     /// capture all the keys they're supposed to.
@@ -552,8 +1252,8 @@ mod tests {
         assert_eq!("rand", pkg.deps[0].name);
         assert_eq!("^0.6", pkg.deps[0].req);
         assert_eq!(vec!["i128_support"], pkg.deps[0].features);
-        assert_eq!(false, pkg.deps[0].optional);
-        assert_eq!(true, pkg.deps[0].default_features);
+        assert!(!pkg.deps[0].optional);
+        assert!(pkg.deps[0].default_features);
         assert_eq!(None, pkg.deps[0].target);
         assert_eq!(DependencyKind::Normal, pkg.deps[0].kind);
         assert_eq!(None, pkg.deps[0].registry);
@@ -566,19 +1266,20 @@ mod tests {
             &vec!["rand/simd_support"],
             pkg.features.get("extras").unwrap()
         );
-        assert_eq!(false, pkg.yanked);
+        assert!(!pkg.yanked);
         assert_eq!(None, pkg.links);
     }
 
     #[test]
     fn test_init_empty_dir() {
-        let root = TempDir::new("test_empty").unwrap();
+        let root = Builder::new().prefix("test_empty").tempdir().unwrap();
 
         let idx = PackageIndex::init(
             &root,
             &Config {
                 dl: String::from("http://localhost/dl"),
                 api: String::from("http://localhost/api"),
+                auth_required: false,
             },
         )
         .unwrap();
@@ -600,13 +1301,17 @@ mod tests {
 
     #[test]
     fn test_config_change_updates_repo() {
-        let root = TempDir::new("test_config_change_updates").unwrap();
+        let root = Builder::new()
+            .prefix("test_config_change_updates")
+            .tempdir()
+            .unwrap();
 
         let _idx = PackageIndex::init(
             &root,
             &Config {
                 dl: String::from("http://localhost/dl"),
                 api: String::from("http://localhost/api"),
+                auth_required: false,
             },
         )
         .unwrap();
@@ -616,6 +1321,7 @@ mod tests {
             &Config {
                 dl: String::from("http://example.com/dl"),
                 api: String::from("http://example.com/api"),
+                auth_required: false,
             },
         )
         .unwrap();
@@ -636,13 +1342,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_config_auth_required_codec() {
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+        let value = serde_json::to_value(&config).unwrap();
+        assert!(!value.as_object().unwrap().contains_key("auth-required"));
+
+        let config = Config {
+            auth_required: true,
+            ..config
+        };
+        let value = serde_json::to_value(&config).unwrap();
+        assert_eq!(json!(true), value["auth-required"]);
+        assert_eq!(config, serde_json::from_value(value).unwrap());
+    }
+
     #[test]
     fn test_unchanged_config_does_not_update_repo() {
-        let root = TempDir::new("test_unchanged_config").unwrap();
+        let root = Builder::new()
+            .prefix("test_unchanged_config")
+            .tempdir()
+            .unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let _idx = PackageIndex::init(&root, &config).unwrap();
@@ -719,6 +1448,109 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_validate_package_name() {
+        for name in &[
+            "a",
+            "my-crate",
+            "My_Crate2",
+            "con-fig",
+            &"a".repeat(MAX_NAME_LEN),
+        ] {
+            assert!(validate_package_name(name).is_ok(), "{}", name);
+        }
+        for (name, reason) in &[
+            ("", "empty"),
+            ("my crate", "` ` isn't allowed"),
+            ("crâte", "`â` isn't allowed"),
+            ("my.crate", "`.` isn't allowed"),
+            ("1password", "start with a letter"),
+            ("_private", "start with a letter"),
+            (&"a".repeat(MAX_NAME_LEN + 1), "the most allowed is 64"),
+            ("nul", "reserved"),
+            ("COM1", "reserved"),
+            ("com0", "reserved"),
+            ("Lpt0", "reserved"),
+        ] {
+            let err = validate_package_name(name).unwrap_err().to_string();
+            assert!(err.contains(reason), "{}: {}", name, err);
+        }
+    }
+
+    #[test]
+    fn test_index_protocol_from_str() {
+        assert_eq!(IndexProtocol::Git, "git".parse().unwrap());
+        assert_eq!(IndexProtocol::Sparse, "sparse".parse().unwrap());
+        assert_eq!(IndexProtocol::Both, "both".parse().unwrap());
+        assert!("svn".parse::<IndexProtocol>().is_err());
+    }
+
+    #[test]
+    fn test_publish_credits_author() {
+        let pkg = PackageVersion {
+            name: "foo".to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+
+        let root = Builder::new()
+            .prefix("test_publish_credits_author")
+            .tempdir()
+            .unwrap();
+
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+
+        let idx = PackageIndex::init(&root, &config).unwrap();
+        let author = Author {
+            name: "alice".to_string(),
+            email: "alice@crates.example.com".to_string(),
+        };
+        idx.publish(&pkg, &author).unwrap();
+
+        let head = idx.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(Some("alice"), head.author().name());
+        assert_eq!(Some("alice@crates.example.com"), head.author().email());
+        assert_eq!(Some("estuary"), head.committer().name());
+    }
+
+    #[test]
+    fn test_init_as() {
+        let root = Builder::new().prefix("test_init_as").tempdir().unwrap();
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+        let identity = Author {
+            name: "Acme Registry".to_string(),
+            email: "registry@acme.example.com".to_string(),
+        };
+
+        let committer = Committer {
+            identity: identity.clone(),
+            signing_key: None,
+        };
+        let idx = PackageIndex::init_as(&root, &config, &committer).unwrap();
+        let head = idx.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(Some("registry@acme.example.com"), head.author().email());
+        assert_eq!(Some("registry@acme.example.com"), head.committer().email());
+        let first = head.parent(0).unwrap();
+        assert_eq!(Some("Acme Registry"), first.committer().name());
+
+        let idx = PackageIndex::open(&root).unwrap().with_committer(committer);
+        idx.squash().unwrap().unwrap();
+        let head = idx.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(Some("registry@acme.example.com"), head.committer().email());
+    }
+
     #[test]
     fn test_publish_create_happy() {
         let pkg = PackageVersion {
@@ -731,16 +1563,20 @@ mod tests {
             links: None,
         };
 
-        let root = TempDir::new("test_publish_create_happy").unwrap();
+        let root = Builder::new()
+            .prefix("test_publish_create_happy")
+            .tempdir()
+            .unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let idx = PackageIndex::init(&root, &config).unwrap();
 
-        idx.publish(&pkg).unwrap();
+        idx.publish(&pkg, &Author::default()).unwrap();
 
         let entries = idx.get_repo_log().unwrap();
 
@@ -769,17 +1605,21 @@ mod tests {
             links: None,
         };
 
-        let root = TempDir::new("test_publish_same_vers_twice_is_err").unwrap();
+        let root = Builder::new()
+            .prefix("test_publish_same_vers_twice_is_err")
+            .tempdir()
+            .unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let idx = PackageIndex::init(&root, &config).unwrap();
 
-        idx.publish(&pkg).unwrap();
-        assert!(idx.publish(&pkg).is_err());
+        idx.publish(&pkg, &Author::default()).unwrap();
+        assert!(idx.publish(&pkg, &Author::default()).is_err());
 
         let entries = idx.get_repo_log().unwrap();
 
@@ -807,17 +1647,19 @@ mod tests {
             links: None,
         };
 
-        let root = TempDir::new("test_yank").unwrap();
+        let root = Builder::new().prefix("test_yank").tempdir().unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let idx = PackageIndex::init(&root, &config).unwrap();
 
-        idx.publish(&pkg).unwrap();
-        idx.set_yanked(&pkg.name, &pkg.vers, true).unwrap();
+        idx.publish(&pkg, &Author::default()).unwrap();
+        idx.set_yanked(&pkg.name, &pkg.vers, true, &Author::default())
+            .unwrap();
 
         let entries = idx.get_repo_log().unwrap();
 
@@ -846,19 +1688,22 @@ mod tests {
             links: None,
         };
 
-        let root = TempDir::new("test_unyank").unwrap();
+        let root = Builder::new().prefix("test_unyank").tempdir().unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let idx = PackageIndex::init(&root, &config).unwrap();
 
-        idx.publish(&pkg).unwrap();
+        idx.publish(&pkg, &Author::default()).unwrap();
 
-        idx.set_yanked(&pkg.name, &pkg.vers, true).unwrap();
-        idx.set_yanked(&pkg.name, &pkg.vers, false).unwrap();
+        idx.set_yanked(&pkg.name, &pkg.vers, true, &Author::default())
+            .unwrap();
+        idx.set_yanked(&pkg.name, &pkg.vers, false, &Author::default())
+            .unwrap();
 
         let entries = idx.get_repo_log().unwrap();
 
@@ -896,19 +1741,22 @@ mod tests {
             links: None,
         };
 
-        let root = TempDir::new("test_double_yank").unwrap();
+        let root = Builder::new().prefix("test_double_yank").tempdir().unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let idx = PackageIndex::init(&root, &config).unwrap();
 
-        idx.publish(&pkg).unwrap();
+        idx.publish(&pkg, &Author::default()).unwrap();
 
-        idx.set_yanked(&pkg.name, &pkg.vers, true).unwrap();
-        idx.set_yanked(&pkg.name, &pkg.vers, true).unwrap();
+        idx.set_yanked(&pkg.name, &pkg.vers, true, &Author::default())
+            .unwrap();
+        idx.set_yanked(&pkg.name, &pkg.vers, true, &Author::default())
+            .unwrap();
 
         let entries = idx.get_repo_log().unwrap();
         assert_eq!(entries.len(), 4);
@@ -934,19 +1782,22 @@ mod tests {
             links: None,
         };
 
-        let root = TempDir::new("test_double_yank").unwrap();
+        let root = Builder::new().prefix("test_double_yank").tempdir().unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let idx = PackageIndex::init(&root, &config).unwrap();
 
-        idx.publish(&pkg).unwrap();
+        idx.publish(&pkg, &Author::default()).unwrap();
 
-        idx.set_yanked(&pkg.name, &pkg.vers, false).unwrap();
-        idx.set_yanked(&pkg.name, &pkg.vers, false).unwrap();
+        idx.set_yanked(&pkg.name, &pkg.vers, false, &Author::default())
+            .unwrap();
+        idx.set_yanked(&pkg.name, &pkg.vers, false, &Author::default())
+            .unwrap();
 
         let entries = idx.get_repo_log().unwrap();
 
@@ -1000,13 +1851,312 @@ mod tests {
         assert_eq!(&from_cargo, &from_index);
     }
 
+    #[test]
+    fn test_parse_commit_message() {
+        assert_eq!(
+            Some((
+                ChangeOp::Publish,
+                "foo".to_string(),
+                "0.1.0".parse().unwrap()
+            )),
+            parse_commit_message("publish crate: `foo v0.1.0`")
+        );
+        assert_eq!(
+            Some((
+                ChangeOp::Unyank,
+                "foo".to_string(),
+                "1.2.3".parse().unwrap()
+            )),
+            parse_commit_message("unyank crate: `foo v1.2.3`")
+        );
+        assert_eq!(None, parse_commit_message("update registry config"));
+    }
+
+    #[test]
+    fn test_get_changes() {
+        let pkg = PackageVersion {
+            name: "foo".to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+
+        let root = Builder::new().prefix("test_get_changes").tempdir().unwrap();
+
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+
+        let idx = PackageIndex::init(&root, &config).unwrap();
+
+        idx.publish(&pkg, &Author::default()).unwrap();
+        idx.set_yanked(&pkg.name, &pkg.vers, true, &Author::default())
+            .unwrap();
+
+        let changes = idx.get_changes(None, None).unwrap();
+        assert_eq!(
+            vec![ChangeOp::Publish, ChangeOp::Yank],
+            changes.iter().map(|c| c.op).collect::<Vec<_>>()
+        );
+        assert_eq!("foo", changes[0].name);
+
+        let changes = idx.get_changes(Some(&changes[0].commit), None).unwrap();
+        assert_eq!(1, changes.len());
+        assert_eq!(ChangeOp::Yank, changes[0].op);
+
+        assert!(idx
+            .get_changes(Some("0000000000000000000000000000000000000001"), None)
+            .is_err());
+
+        // Newest first, the other way around.
+        let recent = idx.get_recent_changes(1).unwrap();
+        assert_eq!(changes, recent);
+        assert_eq!(2, idx.get_recent_changes(10).unwrap().len());
+    }
+
+    #[test]
+    fn test_author_from_str() {
+        assert_eq!(
+            Ok(Author {
+                name: "Registry Admins".to_string(),
+                email: "registry@example.com".to_string(),
+            }),
+            " Registry Admins <registry@example.com> ".trim().parse()
+        );
+        assert!("registry@example.com".parse::<Author>().is_err());
+        assert!("<registry@example.com>".parse::<Author>().is_err());
+        assert!("Registry Admins <>".parse::<Author>().is_err());
+        assert!("Registry <Admins <registry@example.com>"
+            .parse::<Author>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_coalesce_commits() {
+        let pkg = |vers: &str| PackageVersion {
+            name: "foo".to_string(),
+            vers: vers.parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+        let root = Builder::new()
+            .prefix("test_coalesce_commits")
+            .tempdir()
+            .unwrap();
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+        let idx = PackageIndex::init(&root, &config)
+            .unwrap()
+            .coalesce_commits();
+        let before = idx.repo.head().unwrap().peel_to_commit().unwrap().id();
+        let author = Author {
+            name: "alice".to_string(),
+            email: "alice@example.com".to_string(),
+        };
+
+        idx.publish(&pkg("0.1.0"), &author).unwrap();
+        idx.publish(&pkg("0.2.0"), &author).unwrap();
+        idx.set_yanked("foo", &"0.1.0".parse().unwrap(), true, &author)
+            .unwrap();
+        // Nothing's committed yet, but the changes are there to build on.
+        assert_eq!(
+            before,
+            idx.repo.head().unwrap().peel_to_commit().unwrap().id()
+        );
+        assert_eq!(2, idx.get_package_versions("foo").unwrap().len());
+        assert!(idx.publish(&pkg("0.2.0"), &author).is_err());
+
+        idx.commit_pending().unwrap();
+        let commit = idx.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(before, commit.parent_id(0).unwrap());
+        assert_eq!(Some("alice"), commit.author().name());
+        assert!(commit
+            .tree()
+            .unwrap()
+            .get_path(Path::new("3/f/foo"))
+            .is_ok());
+        let changes = idx.get_changes(None, None).unwrap();
+        assert_eq!(
+            vec![ChangeOp::Publish, ChangeOp::Publish, ChangeOp::Yank],
+            changes.iter().map(|c| c.op).collect::<Vec<_>>()
+        );
+        assert_eq!("0.2.0", changes[1].version.to_string());
+        assert_eq!(1, idx.get_recent_changes(1).unwrap().len());
+
+        // Nothing left to commit.
+        idx.commit_pending().unwrap();
+        assert_eq!(
+            commit.id(),
+            idx.repo.head().unwrap().peel_to_commit().unwrap().id()
+        );
+
+        // A single change is committed as it would be otherwise.
+        idx.publish(&pkg("0.3.0"), &Author::default()).unwrap();
+        idx.commit_pending().unwrap();
+        let commit = idx.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(Some("publish crate: `foo v0.3.0`"), commit.summary());
+        assert!(!idx.commit_staged().unwrap());
+
+        // Changes still staged when the server stopped are committed when it
+        // starts again.
+        idx.publish(&pkg("0.4.0"), &Author::default()).unwrap();
+        let restarted = PackageIndex::open(&root).unwrap();
+        assert!(restarted.commit_staged().unwrap());
+        let commit = restarted.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(Some("commit changes left staged"), commit.summary());
+        assert!(!restarted.commit_staged().unwrap());
+    }
+
+    #[test]
+    fn test_squash() {
+        let pkg = |vers: &str| PackageVersion {
+            name: "foo".to_string(),
+            vers: vers.parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+        let root = Builder::new().prefix("test_squash").tempdir().unwrap();
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+        let idx = PackageIndex::init(&root, &config).unwrap();
+        idx.publish(&pkg("0.1.0"), &Author::default()).unwrap();
+        idx.publish(&pkg("0.2.0"), &Author::default()).unwrap();
+        let before = idx.repo.head().unwrap().peel_to_commit().unwrap();
+        let id = idx.squash().unwrap().unwrap();
+        // There's nothing more to squash.
+        assert_eq!(None, idx.squash().unwrap());
+
+        let head = idx.repo.head().unwrap();
+        assert!(head.is_branch());
+        let commit = head.peel_to_commit().unwrap();
+        assert_eq!(id, commit.id().to_string());
+        assert_eq!(0, commit.parent_count());
+        assert_eq!(before.tree_id(), commit.tree_id());
+        assert_eq!(Some(before.id()), parse_snapshot(commit.message().unwrap()));
+        let snapshot = format!("refs/snapshots/{}", before.id());
+        assert_eq!(before.id(), idx.repo.refname_to_id(&snapshot).unwrap());
+        assert_eq!(
+            vec![pkg("0.1.0"), pkg("0.2.0")],
+            idx.get_package_versions("foo").unwrap()
+        );
+        assert!(idx.fsck(&config).unwrap().problems.is_empty());
+
+        // The changes from before are still there, after those since.
+        idx.publish(&pkg("0.3.0"), &Author::default()).unwrap();
+        let changes = idx.get_changes(None, None).unwrap();
+        assert_eq!(
+            vec!["0.1.0", "0.2.0", "0.3.0"],
+            changes
+                .iter()
+                .map(|c| c.version.to_string())
+                .collect::<Vec<_>>()
+        );
+        let since = idx.get_changes(Some(&changes[0].commit), None).unwrap();
+        assert_eq!(changes[1..], since[..]);
+        assert_eq!(changes[2..], idx.get_changes(Some(&id), None).unwrap()[..]);
+        assert_eq!(2, idx.get_recent_changes(2).unwrap().len());
+    }
+
+    #[test]
+    fn test_delete_version() {
+        let pkg = |vers: &str| PackageVersion {
+            name: "foo".to_string(),
+            vers: vers.parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+
+        let root = Builder::new()
+            .prefix("test_delete_version")
+            .tempdir()
+            .unwrap();
+
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+
+        let idx = PackageIndex::init(&root, &config).unwrap();
+        idx.publish(&pkg("0.1.0"), &Author::default()).unwrap();
+        idx.publish(&pkg("0.2.0"), &Author::default()).unwrap();
+
+        let vers = "0.1.0".parse().unwrap();
+        assert_eq!(
+            Some(pkg("0.1.0")),
+            idx.delete_version("foo", &vers, &Author::default())
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            idx.delete_version("foo", &vers, &Author::default())
+                .unwrap()
+        );
+        assert_eq!(vec![pkg("0.2.0")], idx.get_package_versions("foo").unwrap());
+
+        // The package file goes with the last version.
+        let vers = "0.2.0".parse().unwrap();
+        idx.delete_version("foo", &vers, &Author::default())
+            .unwrap()
+            .unwrap();
+        assert!(idx.list_crates().unwrap().is_empty());
+        assert!(!root.path().join("3/f/foo").exists());
+
+        let changes = idx.get_recent_changes(10).unwrap();
+        assert_eq!(
+            vec![
+                ChangeOp::Delete,
+                ChangeOp::Delete,
+                ChangeOp::Publish,
+                ChangeOp::Publish
+            ],
+            changes.iter().map(|c| c.op).collect::<Vec<_>>()
+        );
+        let mut head_files = vec![];
+        let tree = idx.repo.head().unwrap().peel_to_tree().unwrap();
+        tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+            head_files.push(format!("{}{}", dir, entry.name().unwrap()));
+            git2::TreeWalkResult::Ok
+        })
+        .unwrap();
+        assert!(
+            !head_files.contains(&"3/f/foo".to_string()),
+            "{:?}",
+            head_files
+        );
+    }
+
     #[test]
     fn test_list_crates_empty() {
-        let root = TempDir::new("test_list_crates_empty").unwrap();
+        let root = Builder::new()
+            .prefix("test_list_crates_empty")
+            .tempdir()
+            .unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let idx = PackageIndex::init(&root, &config).unwrap();
@@ -1026,15 +2176,19 @@ mod tests {
             links: None,
         };
 
-        let root = TempDir::new("test_list_crates_one").unwrap();
+        let root = Builder::new()
+            .prefix("test_list_crates_one")
+            .tempdir()
+            .unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let idx = PackageIndex::init(&root, &config).unwrap();
-        idx.publish(&pkg).unwrap();
+        idx.publish(&pkg, &Author::default()).unwrap();
         assert_eq!(vec!["foo"], idx.list_crates().unwrap());
     }
 
@@ -1060,17 +2214,21 @@ mod tests {
             links: None,
         };
 
-        let root = TempDir::new("test_list_crates_two").unwrap();
+        let root = Builder::new()
+            .prefix("test_list_crates_two")
+            .tempdir()
+            .unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let idx = PackageIndex::init(&root, &config).unwrap();
 
-        idx.publish(&pkg1).unwrap();
-        idx.publish(&pkg2).unwrap();
+        idx.publish(&pkg1, &Author::default()).unwrap();
+        idx.publish(&pkg2, &Author::default()).unwrap();
         let mut crates = idx.list_crates().unwrap();
         crates.sort();
         assert_eq!(vec!["bar", "foo"], crates);
@@ -1080,25 +2238,32 @@ mod tests {
     fn test_list_crates_all_lengths() {
         let names = ["a", "bb", "ccc", "dddd", "eeeee"];
 
-        let root = TempDir::new("test_list_crates_two").unwrap();
+        let root = Builder::new()
+            .prefix("test_list_crates_two")
+            .tempdir()
+            .unwrap();
 
         let config = Config {
             dl: String::from("http://localhost/dl"),
             api: String::from("http://localhost/api"),
+            auth_required: false,
         };
 
         let idx = PackageIndex::init(&root, &config).unwrap();
 
         for name in &names {
-            idx.publish(&PackageVersion {
-                name: name.to_string(),
-                vers: "0.1.0".parse().unwrap(),
-                deps: vec![],
-                cksum: "".to_string(),
-                features: Default::default(),
-                yanked: false,
-                links: None,
-            })
+            idx.publish(
+                &PackageVersion {
+                    name: name.to_string(),
+                    vers: "0.1.0".parse().unwrap(),
+                    deps: vec![],
+                    cksum: "".to_string(),
+                    features: Default::default(),
+                    yanked: false,
+                    links: None,
+                },
+                &Author::default(),
+            )
             .unwrap();
         }
 
@@ -1106,4 +2271,66 @@ mod tests {
         crates.sort();
         assert_eq!(names.to_vec(), crates);
     }
+
+    #[test]
+    fn test_find_crate_name() {
+        let root = Builder::new()
+            .prefix("test_find_crate_name")
+            .tempdir()
+            .unwrap();
+
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+
+        let idx = PackageIndex::init(&root, &config).unwrap();
+
+        for name in &["a-b", "My_Crate", "serde-json"] {
+            idx.publish(
+                &PackageVersion {
+                    name: name.to_string(),
+                    vers: "0.1.0".parse().unwrap(),
+                    deps: vec![],
+                    cksum: "".to_string(),
+                    features: Default::default(),
+                    yanked: false,
+                    links: None,
+                },
+                &Author::default(),
+            )
+            .unwrap();
+        }
+
+        for (name, found) in &[
+            ("a-b", Some("a-b")),
+            ("a_b", Some("a-b")),
+            ("my-crate", Some("My_Crate")),
+            ("MY_CRATE", Some("My_Crate")),
+            ("serde_json", Some("serde-json")),
+            ("serde", None),
+            ("a", None),
+        ] {
+            assert_eq!(
+                found.map(str::to_string),
+                idx.find_crate_name(name).unwrap(),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            idx.read_package_file("serde-json").unwrap(),
+            idx.read_package_file("serde_json").unwrap()
+        );
+
+        idx.set_yanked(
+            "my-crate",
+            &"0.1.0".parse().unwrap(),
+            true,
+            &Author::default(),
+        )
+        .unwrap();
+        assert!(idx.get_package_versions("My_Crate").unwrap()[0].yanked);
+    }
 }
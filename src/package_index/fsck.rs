@@ -0,0 +1,366 @@
+//! Checking the index repo for itself, for `estuary fsck`.
+//!
+//! Every package file should be where its name puts it, with a version of
+//! that crate on each line, and `config.json` should match the settings.
+//! Everything in the working tree (which the sparse index serves) should be
+//! committed, and `info/refs` (which git's dumb protocol starts from) should
+//! have HEAD's commit.
+//!
+//! Some of these can be put right with a commit (see [PackageIndex::repair]).
+//! Lines that don't parse are left for a person to look at.
+
+use super::{
+    get_package_file_dir, git_update_server_info, Config, PackageIndex, PackageVersion, Result,
+};
+use git2::StatusOptions;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// HEAD doesn't point at a commit.
+    BrokenHead { reason: String },
+    /// A file in the working tree that's different from HEAD.
+    Uncommitted { path: String },
+    /// `info/refs` doesn't have HEAD's commit, so git clients using the dumb
+    /// protocol are given an older index.
+    StaleServerInfo,
+    /// `config.json` is missing (or unreadable, as `None`), or doesn't match
+    /// the settings.
+    ConfigMismatch { found: Option<Config> },
+    /// A package file that isn't where its name says it should be.
+    Misplaced { path: String, expected: String },
+    /// A line of a package file that isn't a version of its crate.
+    BadLine {
+        path: String,
+        line: usize,
+        reason: String,
+    },
+}
+
+impl Problem {
+    /// Whether [PackageIndex::repair] will put it right.
+    ///
+    /// Misplaced files are only moved, and changes only committed, when every
+    /// line of the files involved is fine.
+    fn is_repairable(&self, bad_paths: &HashSet<&str>) -> bool {
+        match self {
+            Problem::BrokenHead { .. } | Problem::BadLine { .. } => false,
+            Problem::StaleServerInfo | Problem::ConfigMismatch { .. } => true,
+            Problem::Uncommitted { path } => !bad_paths.contains(path.as_str()),
+            Problem::Misplaced { path, .. } => !bad_paths.contains(path.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::BrokenHead { reason } => {
+                write!(f, "HEAD doesn't point at a commit: {}", reason)
+            }
+            Problem::Uncommitted { path } => {
+                write!(f, "`{}` has changes that aren't committed", path)
+            }
+            Problem::StaleServerInfo => write!(f, "`.git/info/refs` doesn't have HEAD's commit"),
+            Problem::ConfigMismatch { found: None } => {
+                write!(f, "`config.json` is missing or unreadable")
+            }
+            Problem::ConfigMismatch { found: Some(_) } => {
+                write!(f, "`config.json` doesn't match the settings")
+            }
+            Problem::Misplaced { path, expected } => {
+                write!(f, "`{}` should be at `{}`", path, expected)
+            }
+            Problem::BadLine { path, line, reason } => {
+                write!(f, "`{}` line {}: {}", path, line, reason)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    /// How many package files were checked.
+    pub files: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl PackageIndex {
+    /// Check the repo over, expecting `config.json` to have `config`.
+    pub fn fsck(&self, config: &Config) -> Result<Report> {
+        let mut report = Report::default();
+
+        match self.repo.head().and_then(|head| head.peel_to_commit()) {
+            Err(e) => report.problems.push(Problem::BrokenHead {
+                reason: e.message().to_string(),
+            }),
+            Ok(commit) => {
+                let head = self.repo.head()?;
+                // Detached heads aren't listed, so there's nothing to look for.
+                if let Some(name) = head.name().filter(|_| head.is_branch()) {
+                    let wanted = format!("{}\t{}", commit.id(), name);
+                    let refs = std::fs::read_to_string(self.repo.path().join("info/refs"))
+                        .unwrap_or_default();
+                    if !refs.lines().any(|line| line == wanted) {
+                        report.problems.push(Problem::StaleServerInfo);
+                    }
+                }
+                let mut opts = StatusOptions::new();
+                opts.include_untracked(true).recurse_untracked_dirs(true);
+                let mut paths = self
+                    .repo
+                    .statuses(Some(&mut opts))?
+                    .iter()
+                    .filter_map(|entry| entry.path().map(str::to_string))
+                    .collect::<Vec<_>>();
+                paths.sort();
+                report
+                    .problems
+                    .extend(paths.into_iter().map(|path| Problem::Uncommitted { path }));
+            }
+        }
+
+        match self.read_config() {
+            Ok(ref found) if found == config => (),
+            found => report
+                .problems
+                .push(Problem::ConfigMismatch { found: found.ok() }),
+        }
+
+        for path in self.package_files()? {
+            report.files += 1;
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            let display = path.to_string_lossy().replace('\\', "/");
+            let expected = get_package_file_dir(&name)?.join(&name);
+            if expected != path {
+                report.problems.push(Problem::Misplaced {
+                    path: display.clone(),
+                    expected: expected.to_string_lossy().replace('\\', "/"),
+                });
+            }
+            let contents = std::fs::read(self.repo.workdir().unwrap().join(&path))?;
+            for (i, line) in String::from_utf8_lossy(&contents).lines().enumerate() {
+                let reason = match serde_json::from_str::<PackageVersion>(line) {
+                    Err(e) => e.to_string(),
+                    Ok(pkg) if pkg.name != name => format!("is a version of `{}`", pkg.name),
+                    Ok(_) => continue,
+                };
+                report.problems.push(Problem::BadLine {
+                    path: display.clone(),
+                    line: i + 1,
+                    reason,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// Put right what can be of the `problems` [fsck](Self::fsck) found, with
+    /// a commit, handing back how many were.
+    pub fn repair(&self, problems: &[Problem], config: &Config) -> Result<usize> {
+        let bad_paths = problems
+            .iter()
+            .filter_map(|problem| match problem {
+                Problem::BadLine { path, .. } => Some(path.as_str()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        if problems
+            .iter()
+            .any(|problem| matches!(problem, Problem::BrokenHead { .. }))
+        {
+            return Ok(0);
+        }
+
+        let root = self.repo.workdir().unwrap();
+        let mut paths = vec![];
+        let mut repaired = 0;
+        for problem in problems {
+            if !problem.is_repairable(&bad_paths) {
+                continue;
+            }
+            match problem {
+                Problem::Uncommitted { path } => paths.push(PathBuf::from(path)),
+                Problem::ConfigMismatch { .. } => {
+                    self.write_config(config)?;
+                    paths.push(PathBuf::from("config.json"));
+                }
+                Problem::Misplaced { path, expected } => {
+                    // Another file being there already isn't trivial.
+                    if root.join(expected).exists() {
+                        continue;
+                    }
+                    std::fs::create_dir_all(root.join(expected).parent().unwrap())?;
+                    std::fs::rename(root.join(path), root.join(expected))?;
+                    paths.push(PathBuf::from(path));
+                    paths.push(PathBuf::from(expected));
+                }
+                _ => (),
+            }
+            repaired += 1;
+        }
+
+        paths.sort();
+        paths.dedup();
+        if paths.is_empty() {
+            git_update_server_info(&self.repo)?;
+        } else {
+            let paths = paths.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+            self.add_and_commit_files(&paths, "repair index", self.identity())?;
+        }
+        Ok(repaired)
+    }
+
+    /// Every file in the working tree but `config.json`, relative to it.
+    fn package_files(&self) -> Result<Vec<PathBuf>> {
+        fn walk(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+            for entry in std::fs::read_dir(root.join(dir))? {
+                let entry = entry?;
+                let path = dir.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    walk(root, &path, files)?;
+                } else {
+                    files.push(path);
+                }
+            }
+            Ok(())
+        }
+
+        let root = self.repo.workdir().unwrap();
+        let mut files = vec![];
+        for entry in std::fs::read_dir(root)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if name == ".git" || name == "config.json" {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                walk(root, Path::new(&name), &mut files)?;
+            } else {
+                files.push(PathBuf::from(name));
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_index::Author;
+    use tempfile::Builder;
+
+    fn pkg(name: &str) -> PackageVersion {
+        PackageVersion {
+            name: name.to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        }
+    }
+
+    #[test]
+    fn test_fsck() {
+        let root = Builder::new().prefix("test_fsck").tempdir().unwrap();
+        let config = Config {
+            dl: String::from("http://localhost/dl"),
+            api: String::from("http://localhost/api"),
+            auth_required: false,
+        };
+        let idx = PackageIndex::init(&root, &config).unwrap();
+        idx.publish(&pkg("foo"), &Author::default()).unwrap();
+        idx.publish(&pkg("serde"), &Author::default()).unwrap();
+        assert_eq!(
+            Report {
+                files: 2,
+                problems: vec![],
+            },
+            idx.fsck(&config).unwrap()
+        );
+
+        // Moved by hand, with a bad line added to another, and the config and
+        // `info/refs` out of date.
+        let path = root.path();
+        std::fs::create_dir_all(path.join("fo")).unwrap();
+        std::fs::rename(path.join("3/f/foo"), path.join("fo/foo")).unwrap();
+        let line = serde_json::to_string(&pkg("bar")).unwrap();
+        let serde = std::fs::read_to_string(path.join("se/rd/serde")).unwrap();
+        std::fs::write(path.join("se/rd/serde"), format!("{}{}\n{{\n", serde, line)).unwrap();
+        std::fs::remove_file(path.join(".git/info/refs")).unwrap();
+        let new_config = Config {
+            auth_required: true,
+            ..config.clone()
+        };
+
+        let report = idx.fsck(&new_config).unwrap();
+        assert_eq!(2, report.files);
+        assert_eq!(
+            vec![
+                Problem::StaleServerInfo,
+                Problem::Uncommitted {
+                    path: "3/f/foo".to_string()
+                },
+                Problem::Uncommitted {
+                    path: "fo/foo".to_string()
+                },
+                Problem::Uncommitted {
+                    path: "se/rd/serde".to_string()
+                },
+                Problem::ConfigMismatch {
+                    found: Some(config.clone())
+                },
+                Problem::Misplaced {
+                    path: "fo/foo".to_string(),
+                    expected: "3/f/foo".to_string(),
+                },
+                Problem::BadLine {
+                    path: "se/rd/serde".to_string(),
+                    line: 2,
+                    reason: "is a version of `bar`".to_string(),
+                },
+                Problem::BadLine {
+                    path: "se/rd/serde".to_string(),
+                    line: 3,
+                    reason: "EOF while parsing an object at line 1 column 1".to_string(),
+                },
+            ],
+            report.problems
+        );
+        assert_eq!(
+            "`fo/foo` should be at `3/f/foo`",
+            report.problems[5].to_string()
+        );
+
+        // The bad lines (and so the changes to their file) are left alone.
+        assert_eq!(5, idx.repair(&report.problems, &new_config).unwrap());
+        let report = idx.fsck(&new_config).unwrap();
+        assert_eq!(
+            vec![
+                Problem::Uncommitted {
+                    path: "se/rd/serde".to_string()
+                },
+                Problem::BadLine {
+                    path: "se/rd/serde".to_string(),
+                    line: 2,
+                    reason: "is a version of `bar`".to_string(),
+                },
+                Problem::BadLine {
+                    path: "se/rd/serde".to_string(),
+                    line: 3,
+                    reason: "EOF while parsing an object at line 1 column 1".to_string(),
+                },
+            ],
+            report.problems
+        );
+        assert_eq!(new_config, idx.read_config().unwrap());
+        assert_eq!(vec![pkg("foo")], idx.get_package_versions("foo").unwrap());
+        let head = idx.repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(Some("repair index"), head.message());
+    }
+}
@@ -0,0 +1,60 @@
+//! Keeping `estuary run` and the commands that change the index underneath it
+//! from doing so at the same time.
+//!
+//! Whichever starts first holds a lock on a file in the index's `.git`
+//! directory until it's done, and the others refuse to start while it's
+//! held. The lock is advisory, and let go of by the OS if the process dies.
+
+use super::Result;
+use crate::errors::PackageIndexError;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::Path;
+
+/// The lock file, in the index's `.git` directory.
+pub const LOCK_FILE: &str = "estuary.lock";
+
+/// The hold on an index, until it's dropped.
+#[derive(Debug)]
+pub struct IndexLock {
+    _file: File,
+}
+
+/// Take the lock on the index at `index_dir`, or refuse if another process
+/// (the server, say) has it.
+pub fn acquire(index_dir: &Path) -> Result<IndexLock> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(index_dir.join(".git").join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => Ok(IndexLock { _file: file }),
+        Err(TryLockError::WouldBlock) => {
+            Err(PackageIndexError::Locked(index_dir.display().to_string()))
+        }
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_acquire() {
+        let data_root = test_helpers::get_data_root();
+        test_helpers::get_test_package_index(data_root.path());
+
+        let lock = acquire(data_root.path()).unwrap();
+        match acquire(data_root.path()) {
+            Err(PackageIndexError::Locked(path)) => {
+                assert_eq!(data_root.path().display().to_string(), path)
+            }
+            other => panic!("expected the index to be locked, got {:?}", other),
+        }
+        // Until it's let go of.
+        drop(lock);
+        acquire(data_root.path()).unwrap();
+    }
+}
@@ -0,0 +1,496 @@
+//! The server side of git's "smart" protocol, for fetching the index over
+//! http without running `git upload-pack`.
+//!
+//! This is version 0 of the protocol, as git speaks it with `--stateless-rpc`:
+//! each request carries all the client's `want`s and `have`s so far, to be
+//! answered with an `ACK` or `NAK` and (once the client says it's `done`) a
+//! pack. Only what fetching the index needs is offered, so there's no
+//! `multi_ack` (the client stops at the first commit in common), and no
+//! shallow clones or filters.
+//!
+//! It's written here, on top of git2, rather than with gitoxide: gitoxide's
+//! protocol crates (`gix-protocol`, `gix-transport`) are only the client's
+//! side, and serving fetches isn't something it does yet. All it'd bring is
+//! writing packs, which git2 (already used for everything else in the index)
+//! does too, with its `PackBuilder`. What's left is reading `want`s and
+//! `have`s and writing pkt-lines, which version 0 keeps small.
+//!
+//! Little's lost by the rest being left out. Clients asking for version 2
+//! (with `Git-Protocol: version=2`) fall back to version 0 when the server
+//! answers with it, as git does for any server that's never heard of
+//! version 2. Without `multi_ack`, the pack is made from the first commit in
+//! common, so a client may be sent a little more than it needs, and the
+//! index's history is kept short by squashing anyway. Cargo only fetches
+//! the index shallowly behind an unstable flag (`-Zgit=shallow-index`).
+//!
+//! See: https://git-scm.com/docs/pack-protocol
+
+use super::{list_refs, PackageIndex, Result};
+use crate::errors::PackageIndexError;
+use git2::Oid;
+use std::collections::HashSet;
+use std::io::{self, Write};
+
+/// The most a pkt-line can hold, its 4 bytes of length included.
+const MAX_PKT_LEN: usize = 65520;
+
+/// The most a pkt-line can hold with `side-band` (rather than `side-band-64k`).
+const MAX_SIDE_BAND_PKT_LEN: usize = 1000;
+
+/// The side-band channel pack data is sent on.
+const PACK_DATA_BAND: u8 = 1;
+
+impl PackageIndex {
+    /// The commit HEAD points to, which [advertise_refs](Self::advertise_refs)
+    /// only changes along with.
+    pub fn head_id(&self) -> Result<Oid> {
+        Ok(self.repo.head()?.peel_to_commit()?.id())
+    }
+
+    /// The refs, and what the server can do, as git sends them in reply to
+    /// `info/refs?service=git-upload-pack` (after the `# service` line).
+    pub fn advertise_refs(&self) -> Result<Vec<u8>> {
+        // libgit2 only ever writes `ofs-delta`s to packs, so clients have to
+        // be able to read them.
+        let mut capabilities = format!(
+            "side-band-64k side-band ofs-delta agent=estuary/{}",
+            env!("CARGO_PKG_VERSION")
+        );
+        let head = self.repo.find_reference("HEAD")?;
+        if let Some(branch) = head.symbolic_target() {
+            capabilities = format!("symref=HEAD:{} {}", branch, capabilities);
+        }
+
+        let mut lines = vec![];
+        if let Ok(oid) = head.peel_to_commit().map(|commit| commit.id()) {
+            lines.push(format!("{} HEAD", oid));
+        }
+        for r in list_refs(&self.repo)? {
+            lines.push(format!("{} {}", r.oid, r.name));
+            if let Some(peeled) = r.peeled {
+                lines.push(format!("{} {}^{{}}", peeled, r.name));
+            }
+        }
+        if lines.is_empty() {
+            lines.push(format!("{} capabilities^{{}}", Oid::zero()));
+        }
+
+        let mut out = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            if i == 0 {
+                write_pkt(&mut out, format!("{}\0{}\n", line, capabilities).as_bytes());
+            } else {
+                write_pkt(&mut out, format!("{}\n", line).as_bytes());
+            }
+        }
+        out.extend(b"0000");
+        Ok(out)
+    }
+
+    /// Work out the reply to a `git-upload-pack` request: the `ACK`s and
+    /// `NAK`s, and whether there's a pack to follow them.
+    pub fn negotiate(&self, mut request: &[u8]) -> Result<UploadPack> {
+        let tips = list_refs(&self.repo)?
+            .iter()
+            .flat_map(|r| std::iter::once(r.oid).chain(r.peeled))
+            .collect::<HashSet<_>>();
+
+        let mut wants = vec![];
+        let mut capabilities = HashSet::new();
+        while let Some(line) = read_pkt(&mut request)? {
+            let mut words = line.split(' ');
+            match (words.next(), words.next()) {
+                (Some("want"), Some(oid)) => {
+                    let oid = parse_oid(oid)?;
+                    if !tips.contains(&oid) {
+                        return Err(protocol_error(format!("not our ref: {}", oid)));
+                    }
+                    wants.push(oid);
+                    // The first `want` says which capabilities are in use.
+                    if wants.len() == 1 {
+                        capabilities.extend(words.map(str::to_string));
+                    }
+                }
+                _ => return Err(protocol_error(format!("unexpected `{}`", line))),
+            }
+        }
+        let mut upload = UploadPack {
+            reply: vec![],
+            pack: None,
+        };
+        // Nothing to send, as git does when the client has everything.
+        if wants.is_empty() {
+            return Ok(upload);
+        }
+
+        let mut common = vec![];
+        let done = loop {
+            let line = match read_pkt(&mut request)? {
+                Some(line) => line,
+                None => break false,
+            };
+            match line.split_once(' ') {
+                _ if line == "done" => break true,
+                Some(("have", oid)) => {
+                    let oid = parse_oid(oid)?;
+                    if self.repo.find_commit(oid).is_ok() {
+                        common.push(oid);
+                        if common.len() == 1 {
+                            write_pkt(&mut upload.reply, format!("ACK {}\n", oid).as_bytes());
+                        }
+                    }
+                }
+                _ => return Err(protocol_error(format!("unexpected `{}`", line))),
+            }
+        };
+        if common.is_empty() {
+            write_pkt(&mut upload.reply, b"NAK\n");
+        }
+        // Otherwise, the client will be back with more `have`s.
+        if done {
+            let max_pkt_len = if capabilities.contains("side-band-64k") {
+                Some(MAX_PKT_LEN)
+            } else if capabilities.contains("side-band") {
+                Some(MAX_SIDE_BAND_PKT_LEN)
+            } else {
+                None
+            };
+            upload.pack = Some(Pack {
+                wants,
+                common,
+                max_pkt_len,
+            });
+        }
+        Ok(upload)
+    }
+
+    /// Write the pack `upload` calls for (if it calls for one) to `out`, as
+    /// it's made: everything in the client's `want`s that isn't in (or
+    /// behind) the commits it has.
+    pub fn write_pack(&self, upload: &UploadPack, out: &mut dyn Write) -> Result<()> {
+        let pack = match upload.pack {
+            Some(ref pack) => pack,
+            None => return Ok(()),
+        };
+        let mut builder = self.repo.packbuilder()?;
+        let mut walk = self.repo.revwalk()?;
+        for &oid in &pack.wants {
+            if self.repo.find_commit(oid).is_ok() {
+                walk.push(oid)?;
+            } else {
+                builder.insert_recursive(oid, None)?;
+            }
+        }
+        for &oid in &pack.common {
+            walk.hide(oid)?;
+        }
+        builder.insert_walk(&mut walk)?;
+
+        let mut out = match pack.max_pkt_len {
+            Some(max_pkt_len) => Output::SideBand(SideBand {
+                out,
+                buf: Vec::with_capacity(max_pkt_len),
+                max_pkt_len,
+            }),
+            None => Output::Plain(out),
+        };
+        let mut failed = None;
+        let written = builder.foreach(|chunk| match out.write_all(chunk) {
+            Ok(()) => true,
+            Err(e) => {
+                failed = Some(e);
+                false
+            }
+        });
+        if let Some(e) = failed {
+            return Err(e.into());
+        }
+        written?;
+        if let Output::SideBand(side_band) = out {
+            side_band.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// The reply to a `git-upload-pack` request, from [PackageIndex::negotiate].
+pub struct UploadPack {
+    /// What to send ahead of the pack.
+    pub reply: Vec<u8>,
+    pack: Option<Pack>,
+}
+
+/// What to put in a pack, and how to send it.
+struct Pack {
+    wants: Vec<Oid>,
+    common: Vec<Oid>,
+    /// How big the side-band pkt-lines to send it in can be, if it's sent in
+    /// them.
+    max_pkt_len: Option<usize>,
+}
+
+enum Output<'a> {
+    Plain(&'a mut dyn Write),
+    SideBand(SideBand<'a>),
+}
+
+impl Write for Output<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(out) => out.write(buf),
+            Output::SideBand(side_band) => side_band.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(out) => out.flush(),
+            Output::SideBand(side_band) => side_band.flush(),
+        }
+    }
+}
+
+/// Writes pack data in side-band pkt-lines, each as full as it can be.
+struct SideBand<'a> {
+    out: &'a mut dyn Write,
+    buf: Vec<u8>,
+    max_pkt_len: usize,
+}
+
+impl SideBand<'_> {
+    /// Send what's left, and the flush-pkt that ends the response.
+    fn finish(mut self) -> io::Result<()> {
+        self.flush()?;
+        self.out.write_all(b"0000")
+    }
+}
+
+impl Write for SideBand<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Its length and band take 5 bytes of each line.
+        let room = self.max_pkt_len - 5 - self.buf.len();
+        let len = buf.len().min(room);
+        self.buf.extend(&buf[..len]);
+        if self.buf.len() == self.max_pkt_len - 5 {
+            self.flush()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            let mut line = Vec::with_capacity(self.buf.len() + 5);
+            write_pkt(&mut line, &[&[PACK_DATA_BAND], &self.buf[..]].concat());
+            self.out.write_all(&line)?;
+            self.buf.clear();
+        }
+        self.out.flush()
+    }
+}
+
+/// Append `data` to `out` as a pkt-line.
+fn write_pkt(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend(format!("{:04x}", data.len() + 4).as_bytes());
+    out.extend(data);
+}
+
+/// Take the next pkt-line from `input`, without its trailing newline, or
+/// `None` for a flush-pkt.
+fn read_pkt<'a>(input: &mut &'a [u8]) -> Result<Option<&'a str>> {
+    let len = input
+        .get(..4)
+        .and_then(|len| std::str::from_utf8(len).ok())
+        .and_then(|len| usize::from_str_radix(len, 16).ok())
+        .ok_or_else(|| protocol_error("expected a pkt-line".to_string()))?;
+    if len == 0 {
+        *input = &input[4..];
+        return Ok(None);
+    }
+    if len < 4 || len > input.len() {
+        return Err(protocol_error(format!("bad pkt-line length: {}", len)));
+    }
+    let line = std::str::from_utf8(&input[4..len])
+        .map_err(|_| protocol_error("pkt-line isn't utf-8".to_string()))?;
+    *input = &input[len..];
+    Ok(Some(line.trim_end_matches('\n')))
+}
+
+fn parse_oid(oid: &str) -> Result<Oid> {
+    Oid::from_str(oid).map_err(|_| protocol_error(format!("bad object id: `{}`", oid)))
+}
+
+fn protocol_error(msg: String) -> PackageIndexError {
+    PackageIndexError::Protocol(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_index::{Author, Config, PackageVersion};
+    use tempfile::{Builder, TempDir};
+
+    fn pkt(line: &str) -> String {
+        format!("{:04x}{}", line.len() + 4, line)
+    }
+
+    fn get_index(dir: &TempDir) -> PackageIndex {
+        let config = Config {
+            dl: "http://localhost/dl".to_string(),
+            api: "http://localhost/api".to_string(),
+            auth_required: false,
+        };
+        PackageIndex::init(dir.path(), &config).unwrap()
+    }
+
+    fn publish(index: &PackageIndex, vers: &str) {
+        let pkg = PackageVersion {
+            name: "my-crate".to_string(),
+            vers: vers.parse().unwrap(),
+            deps: vec![],
+            cksum: "".to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        };
+        index.publish(&pkg, &Author::default()).unwrap();
+    }
+
+    /// The whole reply to `request`.
+    fn upload_pack(index: &PackageIndex, request: &[u8]) -> Result<Vec<u8>> {
+        let upload = index.negotiate(request)?;
+        let mut out = upload.reply.clone();
+        index.write_pack(&upload, &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_read_pkt() {
+        let mut input = &b"000ahello\n0000000bworld!"[..];
+        assert_eq!(Some("hello"), read_pkt(&mut input).unwrap());
+        assert_eq!(None, read_pkt(&mut input).unwrap());
+        // Longer than what's left.
+        assert!(read_pkt(&mut input).is_err());
+        assert!(read_pkt(&mut &b""[..]).is_err());
+        assert!(read_pkt(&mut &b"zzzz"[..]).is_err());
+    }
+
+    #[test]
+    fn test_advertise_refs() {
+        let dir = Builder::new().prefix("upload-pack").tempdir().unwrap();
+        let index = get_index(&dir);
+        let head = index.repo.head().unwrap().peel_to_commit().unwrap().id();
+        let branch = index.repo.head().unwrap().name().unwrap().to_string();
+        let out = String::from_utf8(index.advertise_refs().unwrap()).unwrap();
+        let first = format!(
+            "{} HEAD\0symref=HEAD:{} side-band-64k side-band ofs-delta agent=estuary/{}\n",
+            head,
+            branch,
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(
+            format!(
+                "{}{}0000",
+                pkt(&first),
+                pkt(&format!("{} {}\n", head, branch))
+            ),
+            out
+        );
+    }
+
+    #[test]
+    fn test_upload_pack() {
+        let dir = Builder::new().prefix("upload-pack").tempdir().unwrap();
+        let index = get_index(&dir);
+        let old = index.repo.head().unwrap().peel_to_commit().unwrap().id();
+        publish(&index, "0.1.0");
+        let new = index.repo.head().unwrap().peel_to_commit().unwrap().id();
+        let want = pkt(&format!("want {} side-band-64k ofs-delta\n", new));
+
+        // Nothing wanted, nothing sent.
+        assert!(upload_pack(&index, b"0000").unwrap().is_empty());
+        // Not without a ref pointing at it.
+        let other = pkt(&format!("want {}\n", Oid::zero()));
+        assert!(upload_pack(&index, format!("{}0000", other).as_bytes()).is_err());
+        assert!(upload_pack(&index, b"").is_err());
+
+        // A round of negotiation, before the client's `done`.
+        let unknown = "1111111111111111111111111111111111111111";
+        let request = format!(
+            "{}0000{}{}0000",
+            want,
+            pkt(&format!("have {}\n", unknown)),
+            pkt(&format!("have {}\n", old))
+        );
+        assert_eq!(
+            pkt(&format!("ACK {}\n", old)).into_bytes(),
+            upload_pack(&index, request.as_bytes()).unwrap()
+        );
+
+        // All of it, for a fresh clone.
+        let request = format!("{}0000{}", want, pkt("done\n"));
+        let out = upload_pack(&index, request.as_bytes()).unwrap();
+        assert!(out.starts_with(pkt("NAK\n").as_bytes()));
+        let mut rest = &out[8..];
+        let first = read_pkt_bytes(&mut rest);
+        assert_eq!(PACK_DATA_BAND, first[0]);
+        assert_eq!(b"PACK", &first[1..5]);
+        assert_eq!(b"0000", &out[out.len() - 4..]);
+
+        // Only what's new, for a fetch.
+        let request = format!(
+            "{}0000{}{}",
+            want,
+            pkt(&format!("have {}\n", old)),
+            pkt("done\n")
+        );
+        let out = upload_pack(&index, request.as_bytes()).unwrap();
+        let ack = pkt(&format!("ACK {}\n", old));
+        assert!(out.starts_with(ack.as_bytes()));
+        let mut rest = &out[ack.len()..];
+        let pack = read_pkt_bytes(&mut rest);
+        // The commit, its tree, the package file, and the dirs it's in.
+        assert_eq!(
+            5,
+            u32::from_be_bytes([pack[9], pack[10], pack[11], pack[12]])
+        );
+    }
+
+    #[test]
+    fn test_side_band() {
+        let dir = Builder::new().prefix("upload-pack").tempdir().unwrap();
+        let index = get_index(&dir);
+        for vers in &["0.1.0", "0.2.0", "0.3.0"] {
+            publish(&index, vers);
+        }
+        let head = index.repo.head().unwrap().peel_to_commit().unwrap().id();
+        let request = |capabilities: &str| {
+            let want = pkt(&format!("want {}{}\n", head, capabilities));
+            format!("{}0000{}", want, pkt("done\n"))
+        };
+
+        let plain = upload_pack(&index, request("").as_bytes()).unwrap();
+        let pack = &plain[8..];
+        assert_eq!(b"PACK", &pack[..4]);
+
+        // The same pack, in lines no longer than `side-band` allows.
+        let out = upload_pack(&index, request(" side-band").as_bytes()).unwrap();
+        let mut rest = &out[8..];
+        let mut data = Vec::<u8>::new();
+        while rest != b"0000" {
+            let line = read_pkt_bytes(&mut rest);
+            assert!(line.len() + 4 <= MAX_SIDE_BAND_PKT_LEN);
+            assert_eq!(PACK_DATA_BAND, line[0]);
+            data.extend(&line[1..]);
+        }
+        assert!(out.len() > MAX_SIDE_BAND_PKT_LEN);
+        assert_eq!(pack, &data[..]);
+    }
+
+    /// The next pkt-line in `input`, as bytes.
+    fn read_pkt_bytes<'a>(input: &mut &'a [u8]) -> &'a [u8] {
+        let len = usize::from_str_radix(std::str::from_utf8(&input[..4]).unwrap(), 16).unwrap();
+        let line = &input[4..len];
+        *input = &input[len..];
+        line
+    }
+}
@@ -0,0 +1,500 @@
+//! Rules a registry can hold its crates to, on top of what makes a crate
+//! valid, ex: no `*` dependencies (as on crates.io).
+//!
+//! They're only checked as crates are published, so versions already in the
+//! index aren't affected by a change of rules.
+//!
+//! Some things aren't worth refusing a publish over, like a category that's
+//! misspelled. Those are left out, and cargo is told (see [Warnings]).
+
+use crate::package_index::{normalize_name, Dependency, PackageVersion};
+use glob::Pattern;
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// The badges cargo has known, and the attributes each needs, all strings.
+const BADGES: &[(&str, &[&str])] = &[
+    ("appveyor", &["repository"]),
+    ("azure-devops", &["project", "pipeline"]),
+    ("bitbucket-pipelines", &["repository", "branch"]),
+    ("circle-ci", &["repository"]),
+    ("cirrus-ci", &["repository"]),
+    ("codecov", &["repository"]),
+    ("coveralls", &["repository"]),
+    ("gitlab", &["repository"]),
+    ("is-it-maintained-issue-resolution", &["repository"]),
+    ("is-it-maintained-open-issues", &["repository"]),
+    ("maintenance", &["status"]),
+    ("travis-ci", &["repository"]),
+];
+
+/// What a `maintenance` badge's `status` can be.
+const MAINTENANCE_STATUSES: &[&str] = &[
+    "actively-developed",
+    "passively-maintained",
+    "as-is",
+    "experimental",
+    "looking-for-maintainer",
+    "deprecated",
+    "none",
+];
+
+/// What publishes are checked against. Anything goes by default.
+#[derive(Clone, Debug, Default)]
+pub struct PublishPolicy {
+    /// Refuse dependencies with no upper bound on their version, like `*` or
+    /// `>= 1`, which let any future release in.
+    pub deny_wildcard_deps: bool,
+    /// The index urls of the registries dependencies may come from, besides
+    /// this one. Any will do when there are none.
+    pub dep_registries: Vec<String>,
+    /// The categories crates may be in, by slug (ex:
+    /// `development-tools::testing`). Any will do when there's no list.
+    pub categories: Option<BTreeSet<String>>,
+    /// New crates need a name matching one of these, when there are any.
+    pub allowed_names: Vec<NamePattern>,
+    /// The names new crates can't be published under, even if they're
+    /// allowed.
+    pub denied_names: Vec<NamePattern>,
+}
+
+/// The warnings cargo shows after a publish, for what was published but left
+/// out.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Warnings {
+    /// Categories that aren't in the registry's list.
+    pub invalid_categories: Vec<String>,
+    /// Badges cargo doesn't know, or that are missing what they need.
+    pub invalid_badges: Vec<String>,
+    /// Anything else, ex: why a badge is invalid.
+    pub other: Vec<String>,
+}
+
+/// A pattern for crate names, matched against them the way cargo compares
+/// names: ignoring case, with `_` the same as `-`.
+#[derive(Clone, Debug)]
+pub enum NamePattern {
+    /// Ex: `acme-*`.
+    Glob(Pattern),
+    /// Ex: `/^acme-[a-z]+$/`, which (like any regex) matches anywhere in the
+    /// name unless it's anchored.
+    Regex(Regex),
+}
+
+impl NamePattern {
+    /// Whether `name`, as [normalize_name] leaves it, matches.
+    fn matches(&self, normalized: &str) -> bool {
+        match self {
+            NamePattern::Glob(pattern) => pattern.matches(normalized),
+            NamePattern::Regex(regex) => regex.is_match(normalized),
+        }
+    }
+}
+
+impl fmt::Display for NamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NamePattern::Glob(pattern) => write!(f, "{}", pattern),
+            NamePattern::Regex(regex) => write!(f, "/{}/", regex),
+        }
+    }
+}
+
+/// A pattern for crate names: a regex when it's between slashes (ex:
+/// `/^acme-[a-z]+$/`), or else a glob (ex: `acme-*`).
+pub fn name_pattern(pattern: &str) -> Result<NamePattern, String> {
+    match pattern
+        .strip_prefix('/')
+        .and_then(|pattern| pattern.strip_suffix('/'))
+    {
+        Some(regex) => RegexBuilder::new(&regex.replace('_', "-"))
+            .case_insensitive(true)
+            .build()
+            .map(NamePattern::Regex)
+            .map_err(|e| e.to_string()),
+        None => Pattern::new(&normalize_name(pattern))
+            .map(NamePattern::Glob)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Read the lines in the file at `path`, lowercased (ex: categories, a slug
+/// per line). Blank lines, and those starting with `#`, are skipped.
+pub fn read_list(path: &Path) -> io::Result<BTreeSet<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect())
+}
+
+impl PublishPolicy {
+    /// Check a new crate may be published as `name`, which can be done
+    /// before any more of it is read.
+    ///
+    /// Crates already in the index aren't held to it, so they can still get
+    /// new versions when the rules change.
+    pub fn check_name(&self, name: &str) -> Result<(), String> {
+        let normalized = normalize_name(name);
+        let named = |patterns: &[NamePattern]| patterns.iter().any(|p| p.matches(&normalized));
+        if !self.allowed_names.is_empty() && !named(&self.allowed_names) {
+            return Err(format!(
+                "crate names need to match {}",
+                self.allowed_names
+                    .iter()
+                    .map(|pattern| format!("`{}`", pattern))
+                    .collect::<Vec<_>>()
+                    .join(" or ")
+            ));
+        }
+        if named(&self.denied_names) {
+            return Err(format!("the name `{}` is reserved", name));
+        }
+        Ok(())
+    }
+
+    /// Check `pkg` against the rules for what's in it (see [Self::check_name]
+    /// for its name), giving the ones it breaks if it does.
+    pub fn check(&self, pkg: &PackageVersion) -> Result<(), String> {
+        let mut broken = vec![];
+        if self.deny_wildcard_deps {
+            let unbounded = pkg
+                .deps
+                .iter()
+                .filter(|dep| is_unbounded(dep))
+                .map(|dep| format!("`{} = \"{}\"`", dep.name, dep.req))
+                .collect::<Vec<_>>();
+            if !unbounded.is_empty() {
+                broken.push(format!(
+                    "wildcard dependencies aren't allowed, give {} an upper bound",
+                    unbounded.join(", ")
+                ));
+            }
+        }
+        if !self.dep_registries.is_empty() {
+            let mut elsewhere = pkg
+                .deps
+                .iter()
+                .filter_map(|dep| dep.registry.as_deref())
+                .filter(|registry| !self.allows_registry(registry))
+                .map(|registry| format!("`{}`", registry))
+                .collect::<Vec<_>>();
+            elsewhere.sort();
+            elsewhere.dedup();
+            if !elsewhere.is_empty() {
+                broken.push(format!(
+                    "dependencies can't come from {}, only this registry or {}",
+                    elsewhere.join(", "),
+                    self.dep_registries
+                        .iter()
+                        .map(|registry| format!("`{}`", registry))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+        match broken.is_empty() {
+            true => Ok(()),
+            false => Err(broken.join("; ")),
+        }
+    }
+
+    /// What to warn about in a publish in `categories`, with `badges` (the
+    /// attributes of each, by name).
+    pub fn warnings(
+        &self,
+        categories: &[String],
+        badges: &HashMap<String, serde_json::Value>,
+    ) -> Warnings {
+        let mut warnings = Warnings::default();
+        if let Some(ref known) = self.categories {
+            warnings.invalid_categories = categories
+                .iter()
+                .filter(|category| !known.contains(&category.to_lowercase()))
+                .cloned()
+                .collect();
+        }
+        let mut badges = badges.iter().collect::<Vec<_>>();
+        badges.sort_by_key(|(name, _)| name.as_str());
+        for (name, attributes) in badges {
+            if let Err(reason) = check_badge(name, attributes) {
+                warnings.invalid_badges.push(name.clone());
+                warnings.other.push(reason);
+            }
+        }
+        warnings
+    }
+
+    /// What to record of `categories`: the slug the list has for each that's
+    /// in it (`Development-Tools` is `development-tools`), or all of them as
+    /// they are when there's no list.
+    pub fn categories(&self, categories: &[String]) -> Vec<String> {
+        let known = match self.categories {
+            Some(ref known) => known,
+            None => return categories.to_vec(),
+        };
+        let mut slugs: Vec<String> = vec![];
+        for category in categories {
+            if let Some(slug) = known.get(&category.to_lowercase()) {
+                if !slugs.contains(slug) {
+                    slugs.push(slug.clone());
+                }
+            }
+        }
+        slugs
+    }
+
+    /// Whether dependencies may come from the registry with the index at
+    /// `url`, a trailing `/` or not.
+    fn allows_registry(&self, url: &str) -> bool {
+        let url = url.trim_end_matches('/');
+        self.dep_registries
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/') == url)
+    }
+}
+
+/// Check the badge `name` is one cargo knows, with the `attributes` it needs.
+fn check_badge(name: &str, attributes: &serde_json::Value) -> Result<(), String> {
+    let needed = BADGES
+        .iter()
+        .find(|(badge, _)| *badge == name)
+        .map(|(_, needed)| *needed)
+        .ok_or_else(|| format!("`{}` isn't a badge cargo knows", name))?;
+    for attribute in needed {
+        if !attributes[attribute].is_string() {
+            return Err(format!("the `{}` badge needs a `{}`", name, attribute));
+        }
+    }
+    if name == "maintenance" {
+        let status = attributes["status"].as_str().unwrap_or_default();
+        if !MAINTENANCE_STATUSES.contains(&status) {
+            return Err(format!(
+                "`{}` isn't a maintenance status, which is one of {}",
+                status,
+                MAINTENANCE_STATUSES.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Whether any version of `dep` at all, however far in the future, would do:
+/// none of its requirement's parts set a limit. That takes in more than the
+/// `*` crates.io refuses, like `>= 1`.
+fn is_unbounded(dep: &Dependency) -> bool {
+    dep.req.split(',').map(str::trim).all(|part| {
+        // `1.*` and the like are bounded by the versions they spell out.
+        part == "*" || part.starts_with('>')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_index::DependencyKind;
+    use serde_json::json;
+
+    fn pkg(reqs: &[&str]) -> PackageVersion {
+        pkg_from(reqs.iter().map(|req| (*req, None)))
+    }
+
+    /// A version with dependencies on `deps`: requirements, and the
+    /// registries they're from.
+    fn pkg_from<'a>(deps: impl Iterator<Item = (&'a str, Option<&'a str>)>) -> PackageVersion {
+        PackageVersion {
+            name: "my-crate".to_string(),
+            vers: "0.1.0".parse().unwrap(),
+            deps: deps
+                .enumerate()
+                .map(|(i, (req, registry))| Dependency {
+                    name: format!("dep{}", i),
+                    req: req.to_string(),
+                    features: vec![],
+                    optional: false,
+                    default_features: true,
+                    target: None,
+                    kind: DependencyKind::Normal,
+                    registry: registry.map(str::to_string),
+                    package: None,
+                })
+                .collect(),
+            cksum: String::new(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        }
+    }
+
+    #[test]
+    fn test_deny_wildcard_deps() {
+        let bounded = pkg(&["^1", "~0.3", "=2.0.0", "1.*", "< 2", ">= 1.2, < 1.5"]);
+        let wildcards = pkg(&["*", "^1", ">= 1", "> 1, >= 2"]);
+        assert!(PublishPolicy::default().check(&wildcards).is_ok());
+
+        let policy = PublishPolicy {
+            deny_wildcard_deps: true,
+            ..Default::default()
+        };
+        assert!(policy.check(&bounded).is_ok());
+        assert_eq!(
+            Err(
+                "wildcard dependencies aren't allowed, give `dep0 = \"*\"`, \
+                `dep2 = \">= 1\"`, `dep3 = \"> 1, >= 2\"` an upper bound"
+                    .to_string()
+            ),
+            policy.check(&wildcards)
+        );
+    }
+
+    #[test]
+    fn test_dep_registries() {
+        let crates_io = "https://github.com/rust-lang/crates.io-index";
+        let pkg = pkg_from(
+            vec![
+                ("^1", None),
+                ("^1", Some(crates_io)),
+                ("^1", Some("https://example.com/index/")),
+                ("^2", Some("https://example.com/index/")),
+            ]
+            .into_iter(),
+        );
+        assert!(PublishPolicy::default().check(&pkg).is_ok());
+
+        let policy = PublishPolicy {
+            dep_registries: vec![crates_io.to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            Err(format!(
+                "dependencies can't come from `https://example.com/index/`, only this registry \
+                or `{}`",
+                crates_io
+            )),
+            policy.check(&pkg)
+        );
+
+        let policy = PublishPolicy {
+            dep_registries: vec![
+                crates_io.to_string(),
+                "https://example.com/index".to_string(),
+            ],
+            ..Default::default()
+        };
+        assert!(policy.check(&pkg).is_ok());
+    }
+
+    #[test]
+    fn test_warnings() {
+        let categories = vec!["Command-Line-Utilities".to_string(), "tools".to_string()];
+        let badges = serde_json::from_value(json!({
+            "travis-ci": {"repository": "acme/widgets", "branch": "main"},
+            "maintenance": {"status": "abandoned"},
+            "gitlab": {},
+            "shields": {"repository": "acme/widgets"},
+        }))
+        .unwrap();
+        assert_eq!(
+            Warnings {
+                invalid_categories: vec![],
+                invalid_badges: vec![
+                    "gitlab".to_string(),
+                    "maintenance".to_string(),
+                    "shields".to_string()
+                ],
+                other: vec![
+                    "the `gitlab` badge needs a `repository`".to_string(),
+                    "`abandoned` isn't a maintenance status, which is one of actively-developed, \
+                    passively-maintained, as-is, experimental, looking-for-maintainer, \
+                    deprecated, none"
+                        .to_string(),
+                    "`shields` isn't a badge cargo knows".to_string(),
+                ],
+            },
+            PublishPolicy::default().warnings(&categories, &badges)
+        );
+
+        let dir = tempfile::Builder::new()
+            .prefix("test_warnings")
+            .tempdir()
+            .unwrap();
+        let path = dir.path().join("categories");
+        std::fs::write(
+            &path,
+            "# Ours.\ncommand-line-utilities\n\ndevelopment-tools\n",
+        )
+        .unwrap();
+        let policy = PublishPolicy {
+            categories: Some(read_list(&path).unwrap()),
+            ..Default::default()
+        };
+        let warnings = policy.warnings(&categories, &HashMap::new());
+        assert_eq!(vec!["tools".to_string()], warnings.invalid_categories);
+        assert!(warnings.invalid_badges.is_empty());
+        // What's kept goes by the list's slug.
+        assert_eq!(
+            vec!["command-line-utilities".to_string()],
+            policy.categories(&categories)
+        );
+        assert_eq!(categories, PublishPolicy::default().categories(&categories));
+    }
+
+    #[test]
+    fn test_names() {
+        assert!(PublishPolicy::default().check_name("serde").is_ok());
+
+        let policy = PublishPolicy {
+            allowed_names: vec![
+                name_pattern("acme-*").unwrap(),
+                name_pattern("internal_*").unwrap(),
+            ],
+            denied_names: vec![name_pattern("acme-serde").unwrap()],
+            ..Default::default()
+        };
+        for name in &[
+            "acme-widgets",
+            "Acme_Widgets",
+            "internal-tools",
+            "internal_tools",
+        ] {
+            assert!(policy.check_name(name).is_ok(), "{}", name);
+        }
+        assert_eq!(
+            Err("crate names need to match `acme-*` or `internal-*`".to_string()),
+            policy.check_name("serde")
+        );
+        assert_eq!(
+            Err("the name `acme_serde` is reserved".to_string()),
+            policy.check_name("acme_serde")
+        );
+
+        // Denied names are denied whether there are allowed ones or not.
+        let policy = PublishPolicy {
+            denied_names: vec![name_pattern("serde*").unwrap()],
+            ..Default::default()
+        };
+        assert!(policy.check_name("serde-json").is_err());
+        assert!(policy.check_name("tokio").is_ok());
+
+        // Regexes too, between slashes.
+        let policy = PublishPolicy {
+            allowed_names: vec![name_pattern("/^acme_[a-z]+$/").unwrap()],
+            denied_names: vec![name_pattern("/secret/").unwrap()],
+            ..Default::default()
+        };
+        for name in &["acme-widgets", "Acme_Widgets"] {
+            assert!(policy.check_name(name).is_ok(), "{}", name);
+        }
+        assert_eq!(
+            Err("crate names need to match `/^acme-[a-z]+$/`".to_string()),
+            policy.check_name("acme-widgets2")
+        );
+        assert!(policy.check_name("acme-secrets").is_err());
+        assert!(name_pattern("/acme-(/").is_err());
+    }
+}
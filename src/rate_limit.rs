@@ -0,0 +1,115 @@
+//! Limits on how often each token can publish and yank, so a CI job stuck
+//! retrying in a loop can't flood the index with commits.
+
+use actix_web::http::{header, StatusCode};
+use actix_web::HttpResponse;
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The window the limit is counted over.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Counts recent requests by whoever made them, allowing `limit` of them in
+/// any minute.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    /// `None` for no limit.
+    limit: Option<usize>,
+    /// When each key's requests within the window were made, oldest first.
+    hits: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            hits: Default::default(),
+        }
+    }
+
+    /// Count a request by `key`, giving how long until it may try again if
+    /// it's over the limit.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let mut hits = self.hits.lock().unwrap();
+        // Forget about anyone who's been quiet for a while, so the map
+        // doesn't grow forever.
+        hits.retain(|_, times| {
+            while times
+                .front()
+                .is_some_and(|t| now.duration_since(*t) >= WINDOW)
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+        let times = hits.entry(key.to_string()).or_default();
+        if times.len() >= limit {
+            let oldest = times.front().copied().unwrap_or(now);
+            return Err(WINDOW - now.duration_since(oldest));
+        }
+        times.push_back(now);
+        Ok(())
+    }
+
+    /// Guard for the rate limited endpoints, with `key` naming who's asking.
+    pub fn limit(&self, key: &str) -> Result<(), HttpResponse> {
+        self.check(key).map_err(|retry_after| {
+            log::warn!("Rate limited `{}`", key);
+            too_many_requests(retry_after)
+        })
+    }
+}
+
+/// The `429` sent when over the limit, with the error where cargo will show
+/// it.
+pub fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    // Rounded up, so retrying right on time works.
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    HttpResponse::build(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::RETRY_AFTER, seconds.to_string())
+        .json(json!({"errors": [{
+            "detail": format!("Too many requests with this token. Try again in {} seconds.", seconds)
+        }]}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let limiter = RateLimiter::new(Some(2));
+        let start = Instant::now();
+        assert_eq!(Ok(()), limiter.check_at("ci", start));
+        assert_eq!(
+            Ok(()),
+            limiter.check_at("ci", start + Duration::from_secs(10))
+        );
+        assert_eq!(
+            Err(Duration::from_secs(40)),
+            limiter.check_at("ci", start + Duration::from_secs(20))
+        );
+        // Others have their own count.
+        assert_eq!(Ok(()), limiter.check_at("alice", start));
+        // The first request has aged out.
+        assert_eq!(Ok(()), limiter.check_at("ci", start + WINDOW));
+    }
+
+    #[test]
+    fn test_no_limit() {
+        let limiter = RateLimiter::new(None);
+        for _ in 0..100 {
+            assert_eq!(Ok(()), limiter.check("ci"));
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Remembering the refs `info/refs` advertises, since cargo asks for them
+//! before every fetch but they only change when the index's HEAD moves.
+//!
+//! The advertisement is kept along with the HEAD it was made from, and made
+//! again once HEAD has moved on, so commits from other servers (or `estuary`
+//! commands) sharing the index are noticed. Publishes and yanks drop it
+//! straight away as well (see [RefsCache::invalidate]).
+
+use git2::Oid;
+use std::sync::Mutex;
+
+/// The last advertisement made, and the HEAD it was made from.
+#[derive(Debug, Default)]
+pub struct RefsCache {
+    entry: Mutex<Option<(Oid, Vec<u8>)>>,
+}
+
+impl RefsCache {
+    /// The advertisement for `head`, made with `f` if the one kept is for
+    /// some other HEAD (or there isn't one).
+    ///
+    /// The cache isn't locked while `f` runs, so a slow advertisement doesn't
+    /// hold up other fetches.
+    pub fn get_or_insert_with<F, E>(&self, head: Oid, f: F) -> Result<Vec<u8>, E>
+    where
+        F: FnOnce() -> Result<Vec<u8>, E>,
+    {
+        if let Some((at, refs)) = &*self.entry.lock().unwrap() {
+            if *at == head {
+                return Ok(refs.clone());
+            }
+        }
+        let refs = f()?;
+        *self.entry.lock().unwrap() = Some((head, refs.clone()));
+        Ok(refs)
+    }
+
+    /// Forget the advertisement, after the index has changed.
+    pub fn invalidate(&self) {
+        *self.entry.lock().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refs_cache() {
+        let cache = RefsCache::default();
+        let (one, two) = (Oid::from_bytes(&[1; 20]).unwrap(), Oid::zero());
+        let advertise = |refs: &'static str| move || Ok::<_, ()>(refs.as_bytes().to_vec());
+
+        assert_eq!(
+            Ok(b"a".to_vec()),
+            cache.get_or_insert_with(one, advertise("a"))
+        );
+        // Until HEAD moves, the first advertisement is kept.
+        assert_eq!(
+            Ok(b"a".to_vec()),
+            cache.get_or_insert_with(one, advertise("b"))
+        );
+        assert_eq!(
+            Ok(b"c".to_vec()),
+            cache.get_or_insert_with(two, advertise("c"))
+        );
+        cache.invalidate();
+        assert_eq!(
+            Ok(b"d".to_vec()),
+            cache.get_or_insert_with(two, advertise("d"))
+        );
+
+        // Failed advertisements aren't kept.
+        assert_eq!(Err(()), cache.get_or_insert_with(one, || Err(())));
+        assert_eq!(
+            Ok(b"e".to_vec()),
+            cache.get_or_insert_with(one, advertise("e"))
+        );
+    }
+}
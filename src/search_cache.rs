@@ -0,0 +1,174 @@
+//! Remembering what search shows for each crate, so searching doesn't mean
+//! looking up the newest version (and its metadata) of every match, every
+//! time.
+//!
+//! Entries are dropped when the crate is published to or yanked from (see
+//! [SearchCache::invalidate]). Servers sharing a database don't hear about
+//! each other's publishes (and a search racing one may keep what it saw just
+//! before), so entries also only last for [MAX_AGE].
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long an entry is trusted for, at most.
+pub const MAX_AGE: Duration = Duration::from_secs(60);
+
+/// What search needs to know about a crate's newest version.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Latest {
+    /// The crate's name, as published.
+    pub name: String,
+    /// The highest version not yanked, or the highest of all when every
+    /// version is.
+    pub version: semver::Version,
+    /// Whether every version is yanked.
+    pub yanked: bool,
+    /// When the crate was last published to.
+    pub updated_at: Option<String>,
+    pub description: Option<String>,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+}
+
+/// Each crate's [Latest], keyed by lowercased name.
+///
+/// `None` is remembered too, for crates with nothing to show.
+#[derive(Debug)]
+pub struct SearchCache {
+    entries: Mutex<HashMap<String, (Instant, Option<Latest>)>>,
+    max_age: Duration,
+}
+
+impl Default for SearchCache {
+    fn default() -> Self {
+        SearchCache::with_max_age(MAX_AGE)
+    }
+}
+
+impl SearchCache {
+    /// A cache whose entries last for `max_age`, rather than [MAX_AGE]. With
+    /// none at all, nothing's kept.
+    pub fn with_max_age(max_age: Duration) -> Self {
+        SearchCache {
+            entries: Default::default(),
+            max_age,
+        }
+    }
+
+    /// The entry for `crate_name`, looked up with `f` if there isn't one (or it
+    /// has expired).
+    ///
+    /// The cache isn't locked while `f` runs, so a slow lookup doesn't hold up
+    /// other searches.
+    pub fn get_or_insert_with<F, E>(&self, crate_name: &str, f: F) -> Result<Option<Latest>, E>
+    where
+        F: FnOnce() -> Result<Option<Latest>, E>,
+    {
+        self.get_or_insert_at(crate_name, Instant::now(), f)
+    }
+
+    fn get_or_insert_at<F, E>(
+        &self,
+        crate_name: &str,
+        now: Instant,
+        f: F,
+    ) -> Result<Option<Latest>, E>
+    where
+        F: FnOnce() -> Result<Option<Latest>, E>,
+    {
+        let key = crate_name.to_lowercase();
+        if let Some((at, latest)) = self.entries.lock().unwrap().get(&key) {
+            if now.duration_since(*at) < self.max_age {
+                return Ok(latest.clone());
+            }
+        }
+        let latest = f()?;
+        let mut entries = self.entries.lock().unwrap();
+        // Expired entries are cleared out now and then, so crates that are
+        // never searched for again don't stay around forever.
+        if entries.len().is_multiple_of(1024) {
+            let max_age = self.max_age;
+            entries.retain(|_, (at, _)| now.duration_since(*at) < max_age);
+        }
+        entries.insert(key, (now, latest.clone()));
+        Ok(latest)
+    }
+
+    /// Forget about `crate_name`, after it's changed.
+    pub fn invalidate(&self, crate_name: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .remove(&crate_name.to_lowercase());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latest(version: &str) -> Option<Latest> {
+        Some(Latest {
+            name: "my-crate".to_string(),
+            version: version.parse().unwrap(),
+            yanked: false,
+            updated_at: None,
+            description: None,
+            keywords: vec![],
+            categories: vec![],
+        })
+    }
+
+    #[test]
+    fn test_search_cache() {
+        let cache = SearchCache::default();
+        let now = Instant::now();
+        let lookup = |version: &'static str| move || Ok::<_, ()>(latest(version));
+
+        assert_eq!(
+            Ok(latest("0.1.0")),
+            cache.get_or_insert_at("my-crate", now, lookup("0.1.0"))
+        );
+        // Until it expires or changes, the first lookup is kept.
+        assert_eq!(
+            Ok(latest("0.1.0")),
+            cache.get_or_insert_at("My-Crate", now, lookup("0.2.0"))
+        );
+        let later = now + MAX_AGE;
+        assert_eq!(
+            Ok(latest("0.2.0")),
+            cache.get_or_insert_at("my-crate", later, lookup("0.2.0"))
+        );
+        cache.invalidate("MY-CRATE");
+        assert_eq!(
+            Ok(latest("0.3.0")),
+            cache.get_or_insert_at("my-crate", later, lookup("0.3.0"))
+        );
+
+        // Failed lookups aren't kept.
+        assert_eq!(Err(()), cache.get_or_insert_at("other", now, || Err(())));
+        assert_eq!(
+            None,
+            cache
+                .get_or_insert_at("other", now, || Ok::<_, ()>(None))
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            cache
+                .get_or_insert_at("other", now, lookup("0.1.0"))
+                .unwrap()
+        );
+
+        // Or kept at all, without an age to keep them for.
+        let cache = SearchCache::with_max_age(Duration::ZERO);
+        cache
+            .get_or_insert_at("my-crate", now, lookup("0.1.0"))
+            .unwrap();
+        assert_eq!(
+            Ok(latest("0.2.0")),
+            cache.get_or_insert_at("my-crate", now, lookup("0.2.0"))
+        );
+    }
+}
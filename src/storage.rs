@@ -1,27 +1,860 @@
-use std::fs::{self, OpenOptions};
-use std::io::Write;
+//! Where `.crate` files are kept.
+//!
+//! That's the crate dir by default, or an object store when one is configured
+//! ([s3], [gcs], or [azure]). Whichever it is gets picked at startup (and
+//! wrapped in [encrypted] when there's a key), and the handlers only see a
+//! [CrateStore].
+
+pub mod azure;
+pub mod encrypted;
+pub mod gcs;
+pub mod s3;
+
+use crate::database;
+use crate::errors::EstuaryError;
+use crate::package_index;
+use crate::Settings;
+use actix_http::body::{BodySize, MessageBody};
+use actix_web::error::{BlockingError, PayloadError};
+use actix_web::web::{self, Bytes};
+use async_trait::async_trait;
+use futures_util::{ready, Stream, StreamExt};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{self, File};
+use std::future::Future;
+use std::io::{self, ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+/// How long to wait on an object store before giving up.
+///
+/// Generous, since crate files can take a while to upload.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The most we'll read back from an object store into memory in one
+/// response. Downloads are streamed instead, so aren't held to it, but
+/// `--max-crate-size` can't be set past it.
+///
+/// Well past crates.io's 10MB limit for crate files, but a bound all the same.
+pub const MAX_OBJECT_SIZE: usize = 64 * 1024 * 1024;
+
+/// How much of a file to read at a time when sending it somewhere.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A stored file's contents, a chunk at a time as they arrive.
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes>>>>;
+
+/// Somewhere crate files can be put, and fetched back from.
+///
+/// Files are named by keys like `sha256/ab/ab12...cd.crate` (see
+/// [get_blob_key]), which each store maps onto its own layout.
+#[async_trait(?Send)]
+pub trait CrateStore: fmt::Debug + Send + Sync {
+    /// Store the contents of `file` as `key`, replacing whatever was there.
+    ///
+    /// Files are streamed from disk, so large crates don't need to fit in
+    /// memory.
+    async fn put(&self, key: &str, file: &Path) -> Result<()>;
+    /// The contents of `key`, or `None` when there's nothing stored there.
+    async fn get(&self, key: &str) -> Result<Option<Bytes>>;
+    /// The contents of `key` as they arrive, so they can be passed on without
+    /// being held in memory. Stores that can't do better read them in first.
+    async fn get_stream(&self, key: &str) -> Result<Option<ByteStream>> {
+        Ok(self
+            .get(key)
+            .await?
+            .map(|body| Box::pin(futures_util::stream::once(async { Ok(body) })) as ByteStream))
+    }
+    async fn exists(&self, key: &str) -> Result<bool>;
+    /// Remove `key`, if it's there.
+    async fn delete(&self, key: &str) -> Result<()>;
+    /// Every key starting with `prefix`, in no particular order.
+    async fn list(&self, prefix: &str) -> Result<Vec<String>>;
+    /// Where `key` is on local disk, as is, for stores that keep files there,
+    /// so downloads can be served from the file rather than read in first.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Where a crate file lives in a [CrateStore], by its SHA-256.
+///
+/// Keeping files by their content means identical ones (the same crate
+/// mirrored under another name, say) are only stored once, and checking a
+/// file is just a matter of hashing it again. The database has which version
+/// is which digest.
+pub fn get_blob_key(digest: &str) -> String {
+    format!("sha256/{}/{}.crate", &digest[..digest.len().min(2)], digest)
+}
+
+/// Where a crate file was kept before files were kept by digest.
+pub fn get_crate_file_key(name: &str, vers: &semver::Version) -> String {
+    format!("{}/{}-{}.crate", name, name, vers)
+}
+
+/// A file being received (a crate file being published, say), which is
+/// removed once it's dropped.
+pub struct TempFile {
+    path: PathBuf,
+    file: Arc<File>,
+}
+
+impl TempFile {
+    /// A new, empty file in `dir`.
+    pub fn create(dir: &Path) -> io::Result<TempFile> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{:016x}.part", rand::thread_rng().gen::<u64>()));
+        let file = Arc::new(File::create(&path)?);
+        Ok(TempFile { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `chunk`, from the blocking pool rather than the async worker.
+    pub async fn write_chunk(&self, chunk: Bytes) -> io::Result<()> {
+        let file = self.file.clone();
+        web::block(move || (&*file).write_all(&chunk))
+            .await
+            .map_err(|e| match e {
+                BlockingError::Error(e) => e,
+                BlockingError::Canceled => io::Error::other("write canceled"),
+            })
+    }
+}
+
+impl Write for TempFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self.file).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self.file).flush()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("couldn't remove `{}`: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// A chunk read from a [FileBody]'s file, along with the file to read the
+/// next one from.
+type PendingRead =
+    Pin<Box<dyn Future<Output = std::result::Result<(File, Vec<u8>), BlockingError<io::Error>>>>>;
+
+/// A file sent as a request (or response) body, a chunk at a time.
+///
+/// The reads happen on the blocking pool, so a slow disk doesn't hold up the
+/// async worker.
+pub struct FileBody {
+    /// Taken away for the length of each read.
+    file: Option<File>,
+    pending: Option<PendingRead>,
+    remaining: u64,
+    /// Removed once it's been sent, or sending it is given up on.
+    _temp: Option<TempFile>,
+}
+
+impl FileBody {
+    fn open(path: &Path) -> io::Result<FileBody> {
+        let file = File::open(path)?;
+        let remaining = file.metadata()?.len();
+        Ok(FileBody {
+            file: Some(file),
+            pending: None,
+            remaining,
+            _temp: None,
+        })
+    }
+
+    /// Send a temporary file, which is removed once the body is dropped.
+    pub fn temp(temp: TempFile) -> io::Result<FileBody> {
+        let mut body = FileBody::open(&temp.path)?;
+        body._temp = Some(temp);
+        Ok(body)
+    }
+}
+
+impl MessageBody for FileBody {
+    fn size(&self) -> BodySize {
+        BodySize::Sized(self.remaining)
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<std::result::Result<Bytes, actix_web::Error>>> {
+        if self.pending.is_none() {
+            if self.remaining == 0 {
+                return Poll::Ready(None);
+            }
+            let mut file = self.file.take().expect("there's no read pending");
+            let len = CHUNK_SIZE.min(self.remaining as usize);
+            self.pending = Some(Box::pin(web::block(move || {
+                let mut chunk = vec![0; len];
+                let n = file.read(&mut chunk)?;
+                chunk.truncate(n);
+                Ok((file, chunk))
+            })));
+        }
+        let read = ready!(self.pending.as_mut().unwrap().as_mut().poll(cx));
+        self.pending = None;
+        Poll::Ready(Some(match read {
+            Ok((_, chunk)) if chunk.is_empty() => {
+                self.remaining = 0;
+                Err(io::Error::from(ErrorKind::UnexpectedEof).into())
+            }
+            Ok((file, chunk)) => {
+                self.file = Some(file);
+                self.remaining -= chunk.len() as u64;
+                Ok(chunk.into())
+            }
+            // There's no file left to carry on with.
+            Err(e) => {
+                self.remaining = 0;
+                Err(e.into())
+            }
+        }))
+    }
+}
+
+/// `stream`, checked against the SHA-256 `expected` as it goes: when it
+/// doesn't match, the last chunk is held back and it ends in an error
+/// instead, so whoever's downloading it never gets the whole thing.
+pub fn verified(stream: ByteStream, key: String, expected: Option<String>) -> ByteStream {
+    struct Verified {
+        stream: ByteStream,
+        key: String,
+        expected: Option<String>,
+        hasher: Sha256,
+        // Each chunk is passed on once the next one's arrived.
+        held: Option<Bytes>,
+        finished: bool,
+    }
+
+    impl Stream for Verified {
+        type Item = Result<Bytes>;
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+            loop {
+                match ready!(self.stream.poll_next_unpin(cx)) {
+                    Some(Ok(chunk)) => {
+                        self.hasher.update(&chunk);
+                        if let Some(held) = self.held.replace(chunk) {
+                            return Poll::Ready(Some(Ok(held)));
+                        }
+                    }
+                    Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                    None if self.finished => return Poll::Ready(None),
+                    None => {
+                        self.finished = true;
+                        let actual = format!("{:x}", self.hasher.finalize_reset());
+                        if self.expected.as_deref() != Some(actual.as_str()) {
+                            log::error!(
+                                "refusing to serve `{}`, which doesn't match its checksum",
+                                self.key
+                            );
+                            let key = self.key.clone();
+                            return Poll::Ready(Some(Err(EstuaryError::Corrupt(key))));
+                        }
+                        return Poll::Ready(self.held.take().map(Ok));
+                    }
+                }
+            }
+        }
+    }
+
+    Box::pin(Verified {
+        stream,
+        key,
+        expected,
+        hasher: Sha256::new(),
+        held: None,
+        finished: false,
+    })
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// The SHA-256 of what's stored as `key`, hashed as it arrives rather than
+/// read in whole, or `None` if there's nothing there.
+pub async fn sha256_stored(store: &dyn CrateStore, key: &str) -> Result<Option<String>> {
+    if let Some(path) = store.local_path(key) {
+        return Ok(Some(web::block(move || sha256_file(&path)).await?));
+    }
+    let mut body = match store.get_stream(key).await? {
+        Some(body) => body,
+        None => return Ok(None),
+    };
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = body.next().await {
+        hasher.update(&chunk?);
+    }
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+/// A stored crate file that isn't what it should be.
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    Missing { key: String },
+    Mismatch { key: String, actual: String },
+    Unreadable { key: String, error: String },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Missing { key } => write!(f, "`{}` is missing", key),
+            Problem::Mismatch { key, actual } => {
+                write!(f, "`{}` has the SHA-256 `{}` instead", key, actual)
+            }
+            Problem::Unreadable { key, error } => {
+                write!(f, "`{}` couldn't be read: {}", key, error)
+            }
+        }
+    }
+}
+
+/// Check that the files stored under each of `digests` are there, and still
+/// hash to their digest. A file the store can't read is one more problem,
+/// rather than the end of the scan.
+pub async fn scan(store: &dyn CrateStore, digests: &[String]) -> Vec<Problem> {
+    let mut problems = vec![];
+    for digest in digests {
+        let key = get_blob_key(digest);
+        match sha256_stored(store, &key).await {
+            Ok(None) => problems.push(Problem::Missing { key }),
+            Ok(Some(actual)) => {
+                if &actual != digest {
+                    problems.push(Problem::Mismatch { key, actual });
+                }
+            }
+            Err(e) => problems.push(Problem::Unreadable {
+                key,
+                error: e.to_string(),
+            }),
+        }
+    }
+    problems
+}
+
+/// Scan every file the database knows of, logging whatever's wrong.
+///
+/// Files stored before they were kept by digest aren't checked, since there's
+/// nothing recorded to check them against.
+pub async fn scan_and_report(settings: &Settings) {
+    let digests = match settings.with_db(database::list_crate_file_digests).await {
+        Ok(digests) => digests,
+        Err(e) => {
+            log::error!("Couldn't list crate files to scan: {}", e);
+            return;
+        }
+    };
+    log::info!("Scanning {} crate files", digests.len());
+    let problems = scan(settings.crate_store.as_ref(), &digests).await;
+    if problems.is_empty() {
+        log::info!("All crate files are intact");
+        return;
+    }
+    for problem in &problems {
+        log::error!("Bad crate file: {}", problem);
+    }
+    log::error!(
+        "{} of {} crate files are missing, corrupt, or unreadable",
+        problems.len(),
+        digests.len()
+    );
+}
+
+/// The SHA-256 of a file, read a chunk at a time.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        match file.read(&mut chunk)? {
+            0 => return Ok(format!("{:x}", hasher.finalize())),
+            n => hasher.update(&chunk[..n]),
+        }
+    }
+}
+
+/// How crate files are arranged in the crate dir.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// Each key is a path under the crate dir, as is.
+    Flat,
+    /// Keys get another level of directories, so no one directory ends up
+    /// with more entries than is comfortable to list: `sha256/ab/cd/abcd...`
+    /// for files kept by digest, and `cr/at/crate-name/...` (the index's own
+    /// scheme) for older ones.
+    Sharded,
+}
+
+impl std::str::FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(Self::Flat),
+            "sharded" => Ok(Self::Sharded),
+            _ => Err(format!("Unknown crate dir layout: `{}`", s)),
+        }
+    }
+}
+
+impl Layout {
+    /// Where `key` is kept, relative to the crate dir.
+    fn path(self, key: &str) -> PathBuf {
+        let parts = key.split('/').collect::<Vec<_>>();
+        match (self, parts.as_slice()) {
+            (Layout::Flat, _) => PathBuf::from(key),
+            (Layout::Sharded, ["sha256", first, file]) => {
+                let second = file.get(2..4).unwrap_or_default();
+                ["sha256", first, second, file].iter().collect()
+            }
+            (Layout::Sharded, [name, file]) => match package_index::get_package_file_dir(name) {
+                Ok(dir) => dir.join(name).join(file),
+                Err(_) => PathBuf::from(key),
+            },
+            (Layout::Sharded, _) => PathBuf::from(key),
+        }
+    }
+}
+
+/// The key for a crate file found at `path` (relative to the crate dir), in
+/// either layout.
+fn key_for_path(path: &Path) -> Option<String> {
+    let file = path.file_name()?.to_str()?;
+    if !file.ends_with(".crate") {
+        return None;
+    }
+    // A crate that's actually called `sha256` is only two deep, when flat.
+    if path.starts_with("sha256") && path.components().count() > 2 {
+        Some(get_blob_key(file.trim_end_matches(".crate")))
+    } else {
+        let name = path.parent()?.file_name()?.to_str()?;
+        Some(format!("{}/{}", name, file))
+    }
+}
+
+/// Crate files kept on disk, under the crate dir.
+#[derive(Debug)]
+pub struct LocalStore {
+    pub root: PathBuf,
+    pub layout: Layout,
+}
+
+impl LocalStore {
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(self.layout.path(key))
+    }
+
+    /// Where `key` is, for reading: its place in the store's layout, or (for
+    /// files that haven't been moved yet) the other one.
+    fn find(&self, key: &str) -> PathBuf {
+        let path = self.path(key);
+        if path.is_file() {
+            return path;
+        }
+        [Layout::Flat, Layout::Sharded]
+            .iter()
+            .map(|layout| self.root.join(layout.path(key)))
+            .find(|other| other.is_file())
+            .unwrap_or(path)
+    }
+
+    /// Move every crate file into this store's layout, from wherever it was,
+    /// handing back how many were moved.
+    pub fn relocate(&self) -> Result<usize> {
+        let mut moved = 0;
+        for key in self.keys("")? {
+            let to = self.path(&key);
+            for layout in &[Layout::Flat, Layout::Sharded] {
+                let from = self.root.join(layout.path(&key));
+                if from == to || !from.is_file() {
+                    continue;
+                }
+                fs::create_dir_all(to.parent().unwrap())?;
+                fs::rename(&from, &to)?;
+                // Tidy up the directories that are now empty (and only those).
+                let mut dir = from.parent();
+                while let Some(d) = dir.filter(|d| *d != self.root) {
+                    if fs::remove_dir(d).is_err() {
+                        break;
+                    }
+                    dir = d.parent();
+                }
+                moved += 1;
+            }
+        }
+        Ok(moved)
+    }
+
+    /// Only `.crate` files are listed, since the crate dir is also the
+    /// database's default home. Files are found in either layout.
+    fn keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                match key_for_path(path.strip_prefix(&self.root).unwrap()) {
+                    Some(key) if key.starts_with(prefix) => keys.push(key),
+                    _ => {}
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
+
+#[async_trait(?Send)]
+impl CrateStore for LocalStore {
+    async fn put(&self, key: &str, file: &Path) -> Result<()> {
+        let (from, to) = (file.to_path_buf(), self.path(key));
+        actix_web::web::block(move || {
+            fs::create_dir_all(to.parent().unwrap())?;
+            fs::copy(from, to)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        let path = self.find(key);
+        match actix_web::web::block(move || fs::read(path)).await {
+            Ok(content) => Ok(Some(content.into())),
+            Err(BlockingError::Error(e)) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(self.find(key).is_file())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match fs::remove_file(self.find(key)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        self.keys(prefix)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.find(key)).filter(|path| path.is_file())
+    }
+}
+
+/// An object store's answer to a request for `key` being some `status` we
+/// didn't expect.
+fn unexpected(method: &str, key: &str, status: awc::http::StatusCode) -> EstuaryError {
+    EstuaryError::Storage(format!("{} {}: {}", method, key, status))
+}
+
+/// The body of an object store's answer to a request, still to be read.
+trait ResponseBody: Stream<Item = std::result::Result<Bytes, PayloadError>> + Unpin + 'static {}
+
+impl<S: Stream<Item = std::result::Result<Bytes, PayloadError>> + Unpin + 'static> ResponseBody
+    for S
+{
+}
+
+/// The body of an object store's answer to a `GET` for `key`, as it arrives.
+fn body_stream(key: &str, resp: awc::ClientResponse<impl ResponseBody>) -> ByteStream {
+    let key = key.to_string();
+    Box::pin(
+        resp.map(move |chunk| {
+            chunk.map_err(|e| EstuaryError::Storage(format!("GET {}: {}", key, e)))
+        }),
+    )
+}
+
+/// The text of each `<tag>` in an XML document, for the object stores that
+/// answer listings in XML.
+///
+/// This is no XML parser, but the listings are simple (and regular) enough.
+fn xml_values(xml: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut values = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let end = match rest.find(&close) {
+            Some(end) => end,
+            None => break,
+        };
+        values.push(
+            rest[..end]
+                .replace("&lt;", "<")
+                .replace("&gt;", ">")
+                .replace("&quot;", "\"")
+                .replace("&apos;", "'")
+                .replace("&amp;", "&"),
+        );
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+/// Percent-encode everything but the unreserved characters, and `/` unless
+/// `encode_slash` is set.
+fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    #[actix_rt::test]
+    async fn test_local_store() {
+        let data_root = test_helpers::get_data_root();
+        let store = LocalStore {
+            root: data_root.path().join("crates"),
+            layout: Layout::Flat,
+        };
+        assert!(store.list("").await.unwrap().is_empty());
+
+        let mut hello = TempFile::create(&data_root.path().join("tmp")).unwrap();
+        hello.write_all(b"hello").unwrap();
+        let empty = TempFile::create(&data_root.path().join("tmp")).unwrap();
+
+        let key = "my-crate/my-crate-0.1.0.crate";
+        assert_eq!(None, store.get(key).await.unwrap());
+        store.put(key, hello.path()).await.unwrap();
+        assert!(store.exists(key).await.unwrap());
+        assert_eq!(
+            Some(Bytes::from_static(b"hello")),
+            store.get(key).await.unwrap()
+        );
+        store
+            .put("other/other-1.0.0.crate", empty.path())
+            .await
+            .unwrap();
+
+        // The database (when it's kept here) isn't a crate file.
+        fs::write(data_root.path().join("crates/estuary.db"), b"").unwrap();
+        let mut keys = store.list("").await.unwrap();
+        keys.sort();
+        assert_eq!(
+            vec!["my-crate/my-crate-0.1.0.crate", "other/other-1.0.0.crate"],
+            keys
+        );
+        assert_eq!(vec![key], store.list("my-").await.unwrap());
+
+        store.delete(key).await.unwrap();
+        assert!(!store.exists(key).await.unwrap());
+        // Deleting twice is fine.
+        store.delete(key).await.unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_scan() {
+        let data_root = test_helpers::get_data_root();
+        let store = LocalStore {
+            root: data_root.path().join("crates"),
+            layout: Layout::Flat,
+        };
+        let mut file = TempFile::create(data_root.path()).unwrap();
+        file.write_all(b"hello").unwrap();
+        let good = sha256(b"hello");
+        store.put(&get_blob_key(&good), file.path()).await.unwrap();
+        // Someone's been at this one.
+        let bad = sha256(b"goodbye");
+        store.put(&get_blob_key(&bad), file.path()).await.unwrap();
+        let missing = sha256(b"");
+        // And the store can't read this one, which leaves the rest checked.
+        let unreadable = sha256(b"unreadable");
+        fs::create_dir_all(store.root.join(get_blob_key(&unreadable))).unwrap();
+
+        let mut problems = scan(
+            &store,
+            &[unreadable.clone(), good, bad.clone(), missing.clone()],
+        )
+        .await;
+        match problems.remove(0) {
+            Problem::Unreadable { key, .. } => assert_eq!(get_blob_key(&unreadable), key),
+            other => panic!("expected an unreadable file, got {:?}", other),
+        }
+        assert_eq!(
+            vec![
+                Problem::Mismatch {
+                    key: get_blob_key(&bad),
+                    actual: sha256(b"hello"),
+                },
+                Problem::Missing {
+                    key: get_blob_key(&missing),
+                },
+            ],
+            problems
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_verified() {
+        let chunks = || {
+            let chunks: Vec<Result<Bytes>> = vec![Ok("hel".into()), Ok("lo".into())];
+            Box::pin(futures_util::stream::iter(chunks)) as ByteStream
+        };
+        let key = "my-crate/my-crate-0.1.0.crate".to_string();
+
+        let good = verified(chunks(), key.clone(), Some(sha256(b"hello")));
+        let good = good.collect::<Vec<_>>().await;
+        assert_eq!(
+            vec![Bytes::from("hel"), Bytes::from("lo")],
+            good.into_iter()
+                .map(|chunk| chunk.unwrap())
+                .collect::<Vec<_>>()
+        );
+
+        // The last chunk never goes out.
+        let mut bad = verified(chunks(), key.clone(), Some(sha256(b"goodbye")));
+        assert_eq!(Bytes::from("hel"), bad.next().await.unwrap().unwrap());
+        assert!(matches!(
+            bad.next().await,
+            Some(Err(EstuaryError::Corrupt(corrupt))) if corrupt == key
+        ));
+        assert!(bad.next().await.is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_file_body() {
+        let data_root = test_helpers::get_data_root();
+        let mut file = TempFile::create(data_root.path()).unwrap();
+        let content = (0..CHUNK_SIZE + 5).map(|i| i as u8).collect::<Vec<_>>();
+        file.write_all(&content).unwrap();
+
+        let mut body = FileBody::temp(file).unwrap();
+        assert_eq!(BodySize::Sized(content.len() as u64), body.size());
+        let mut sent = vec![];
+        while let Some(chunk) =
+            futures_util::future::poll_fn(|cx| Pin::new(&mut body).poll_next(cx)).await
+        {
+            sent.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(content, sent);
+    }
+
+    #[test]
+    fn test_layout_paths() {
+        let digest = sha256(b"hello");
+        assert_eq!(
+            PathBuf::from(format!("sha256/2c/f2/{}.crate", digest)),
+            Layout::Sharded.path(&get_blob_key(&digest))
+        );
+        assert_eq!(
+            PathBuf::from("cr/at/crate-name/crate-name-0.1.0.crate"),
+            Layout::Sharded.path("crate-name/crate-name-0.1.0.crate")
+        );
+        assert_eq!(
+            PathBuf::from("3/a/abc/abc-0.1.0.crate"),
+            Layout::Sharded.path("abc/abc-0.1.0.crate")
+        );
+        assert_eq!(
+            PathBuf::from("abc/abc-0.1.0.crate"),
+            Layout::Flat.path("abc/abc-0.1.0.crate")
+        );
+        for layout in &[Layout::Flat, Layout::Sharded] {
+            for key in &[get_blob_key(&digest), "abc/abc-0.1.0.crate".to_string()] {
+                assert_eq!(Some(key.clone()), key_for_path(&layout.path(key)));
+            }
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_relocate() {
+        let data_root = test_helpers::get_data_root();
+        let root = data_root.path().join("crates");
+        let flat = LocalStore {
+            root: root.clone(),
+            layout: Layout::Flat,
+        };
+        let mut file = TempFile::create(data_root.path()).unwrap();
+        file.write_all(b"hello").unwrap();
+        let blob = get_blob_key(&sha256(b"hello"));
+        let legacy = "my-crate/my-crate-0.1.0.crate";
+        flat.put(&blob, file.path()).await.unwrap();
+        flat.put(legacy, file.path()).await.unwrap();
+
+        // The files are still found before they're moved...
+        let sharded = LocalStore {
+            root: root.clone(),
+            layout: Layout::Sharded,
+        };
+        assert!(sharded.exists(legacy).await.unwrap());
+        assert_eq!(2, sharded.relocate().unwrap());
+        assert!(root.join("my/-c/my-crate/my-crate-0.1.0.crate").is_file());
+        assert!(!root.join("my-crate").exists());
+        // ...and after.
+        assert!(sharded.exists(&blob).await.unwrap());
+        assert!(sharded.exists(legacy).await.unwrap());
+        assert_eq!(0, sharded.relocate().unwrap());
+
+        // Moving back is the same.
+        assert_eq!(2, flat.relocate().unwrap());
+        assert!(root.join(legacy).is_file());
+        assert!(!root.join("my").exists());
+    }
+
+    #[test]
+    fn test_temp_file() {
+        let data_root = test_helpers::get_data_root();
+        let mut file = TempFile::create(data_root.path()).unwrap();
+        file.write_all(b"hello").unwrap();
+        let path = file.path().to_path_buf();
+        assert_eq!(
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+            sha256_file(&path).unwrap()
+        );
+        drop(file);
+        assert!(!path.exists());
+    }
 
-pub fn get_crate_file_path<P: AsRef<Path>>(root: P, name: &str, vers: &semver::Version) -> PathBuf {
-    let dir = root.as_ref().join(name);
-    dir.join(&format!("{}-{}.crate", name, vers))
-}
-
-/// Write bytes to crate storage.
-pub fn store_crate_file<P: AsRef<Path>>(
-    root: P,
-    name: &str,
-    vers: &semver::Version,
-    content: &[u8],
-) -> std::io::Result<()> {
-    let fp = get_crate_file_path(root.as_ref(), name, vers);
-    fs::create_dir_all(fp.parent().unwrap())?;
-
-    let mut fh = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .truncate(true)
-        .open(fp)?;
-    fh.write_all(content)?;
-    Ok(())
+    #[test]
+    fn test_xml_values() {
+        let xml = "<ListBucketResult><Contents><Key>a/a-1.0.0.crate</Key></Contents>\
+            <Contents><Key>b&amp;c</Key></Contents></ListBucketResult>";
+        assert_eq!(vec!["a/a-1.0.0.crate", "b&c"], xml_values(xml, "Key"));
+        assert!(xml_values(xml, "NextContinuationToken").is_empty());
+    }
 }
@@ -0,0 +1,367 @@
+//! Keeping `.crate` files in an Azure Blob Storage container.
+//!
+//! Requests are signed with the storage account's access key ("Shared Key"
+//! authorization).
+//!
+//! <https://learn.microsoft.com/en-us/rest/api/storageservices/authorize-with-shared-key>
+
+use super::{
+    body_stream, unexpected, uri_encode, xml_values, ByteStream, CrateStore, FileBody,
+    ResponseBody, MAX_OBJECT_SIZE, TIMEOUT,
+};
+use crate::errors::EstuaryError;
+use actix_http::body::Body;
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use awc::http::{Method, StatusCode};
+use awc::ClientResponse;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::path::Path;
+use time::OffsetDateTime;
+
+/// The version of the Blob service API we speak.
+const API_VERSION: &str = "2021-08-06";
+
+/// Which container, in which storage account, and how to sign requests to it.
+#[derive(Clone)]
+pub struct AzureConfig {
+    pub account: String,
+    /// The account's access key, base64 encoded as the portal shows it.
+    pub access_key: String,
+    pub container: String,
+    /// ex: `http://127.0.0.1:10000/devstoreaccount1` for Azurite. Defaults to
+    /// `https://<account>.blob.core.windows.net`.
+    pub endpoint: Option<String>,
+}
+
+// Keeps the key out of the logs.
+impl fmt::Debug for AzureConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AzureConfig")
+            .field("account", &self.account)
+            .field("container", &self.container)
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+impl AzureConfig {
+    fn endpoint(&self) -> String {
+        match self.endpoint {
+            Some(ref endpoint) => endpoint.trim_end_matches('/').to_string(),
+            None => format!("https://{}.blob.core.windows.net", self.account),
+        }
+    }
+
+    /// The path to `key` (or the container itself, when there's no key),
+    /// under the endpoint.
+    fn path(&self, key: Option<&str>) -> String {
+        match key {
+            Some(key) => format!("/{}/{}", self.container, uri_encode(key, false)),
+            None => format!("/{}", self.container),
+        }
+    }
+
+    fn url(&self, key: Option<&str>, query: &[(&str, &str)]) -> String {
+        let mut url = format!("{}{}", self.endpoint(), self.path(key));
+        for (i, (name, value)) in query.iter().enumerate() {
+            url.push(if i == 0 { '?' } else { '&' });
+            url.push_str(&format!("{}={}", name, uri_encode(value, true)));
+        }
+        url
+    }
+
+    /// What's signed as the resource being requested: the account, then
+    /// the whole path (including any the endpoint has, as with Azurite).
+    fn resource(&self, key: Option<&str>) -> String {
+        let endpoint = self.endpoint();
+        let endpoint_path = endpoint
+            .split("://")
+            .nth(1)
+            .and_then(|rest| rest.find('/').map(|i| &rest[i..]))
+            .unwrap_or("");
+        format!("/{}{}{}", self.account, endpoint_path, self.path(key))
+    }
+
+    /// Send a signed request (with the contents of `file`, if any), handing
+    /// back the status and body of the response, whatever the status.
+    async fn send(
+        &self,
+        method: Method,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        file: Option<&Path>,
+    ) -> Result<(StatusCode, Bytes), EstuaryError> {
+        let mut resp = self.request(method.clone(), key, query, file).await?;
+        let body = resp.body().limit(MAX_OBJECT_SIZE).await.map_err(|e| {
+            EstuaryError::Storage(format!("{} {}: {}", method, key.unwrap_or_default(), e))
+        })?;
+        Ok((resp.status(), body))
+    }
+
+    /// [send](Self::send), but with the body of the response left to be read.
+    async fn request(
+        &self,
+        method: Method,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        file: Option<&Path>,
+    ) -> Result<ClientResponse<impl ResponseBody>, EstuaryError> {
+        let (content_length, body) = match file {
+            Some(file) => {
+                let body = FileBody::open(file)?;
+                (body.remaining, Body::from_message(body))
+            }
+            None => (0, Body::Empty),
+        };
+        let mut headers = vec![
+            ("x-ms-date", http_date(OffsetDateTime::now_utc())),
+            ("x-ms-version", API_VERSION.to_string()),
+        ];
+        if method == Method::PUT {
+            headers.insert(0, ("x-ms-blob-type", "BlockBlob".to_string()));
+        }
+        let content_type = if content_length == 0 {
+            ""
+        } else {
+            "application/octet-stream"
+        };
+        let string_to_sign = string_to_sign(
+            method.as_str(),
+            content_length,
+            content_type,
+            &headers,
+            &self.resource(key),
+            query,
+        );
+        let signature = sign(&self.access_key, &string_to_sign)?;
+
+        let error = |e: &dyn fmt::Display| {
+            EstuaryError::Storage(format!("{} {}: {}", method, key.unwrap_or_default(), e))
+        };
+        let mut req = awc::Client::default()
+            .request(method.clone(), self.url(key, query))
+            .timeout(TIMEOUT)
+            .header(
+                "authorization",
+                format!("SharedKey {}:{}", self.account, signature),
+            );
+        if !content_type.is_empty() {
+            req = req.content_type(content_type);
+        }
+        for (name, value) in headers {
+            req = req.header(name, value);
+        }
+        req.send_body(body).await.map_err(|e| error(&e))
+    }
+}
+
+/// A date as `x-ms-date` wants it, ex: `Sun, 11 Oct 2009 21:49:13 GMT`.
+fn http_date(now: OffsetDateTime) -> String {
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        &now.weekday().to_string()[..3],
+        now.day(),
+        &now.month().to_string()[..3],
+        now.year(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    )
+}
+
+/// What gets signed for a request.
+///
+/// `headers` are the `x-ms-*` ones, lowercase and sorted by name, and
+/// `resource` is `/<account><path>`.
+fn string_to_sign(
+    method: &str,
+    content_length: u64,
+    content_type: &str,
+    headers: &[(&str, String)],
+    resource: &str,
+    query: &[(&str, &str)],
+) -> String {
+    let content_length = match content_length {
+        0 => String::new(),
+        len => len.to_string(),
+    };
+    let mut canonical = format!(
+        // Content-Encoding, Content-Language, Content-Length, Content-MD5,
+        // Content-Type, Date, If-Modified-Since, If-Match, If-None-Match,
+        // If-Unmodified-Since, and Range.
+        "{}\n\n\n{}\n\n{}\n\n\n\n\n\n\n",
+        method, content_length, content_type
+    );
+    for (name, value) in headers {
+        canonical.push_str(&format!("{}:{}\n", name, value));
+    }
+    canonical.push_str(resource);
+    let mut query = query.to_vec();
+    query.sort();
+    for (name, value) in query {
+        canonical.push_str(&format!("\n{}:{}", name, value));
+    }
+    canonical
+}
+
+fn sign(access_key: &str, string_to_sign: &str) -> Result<String, EstuaryError> {
+    let key = base64::decode(access_key)
+        .map_err(|e| EstuaryError::Config(format!("The Azure access key isn't base64: {}", e)))?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("hmac takes keys of any size");
+    mac.update(string_to_sign.as_bytes());
+    Ok(base64::encode(mac.finalize().into_bytes()))
+}
+
+#[async_trait(?Send)]
+impl CrateStore for AzureConfig {
+    async fn put(&self, key: &str, file: &Path) -> Result<(), EstuaryError> {
+        match self.send(Method::PUT, Some(key), &[], Some(file)).await? {
+            (status, _) if status.is_success() => Ok(()),
+            (status, _) => Err(unexpected("PUT", key, status)),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, EstuaryError> {
+        match self.send(Method::GET, Some(key), &[], None).await? {
+            (status, body) if status.is_success() => Ok(Some(body)),
+            (StatusCode::NOT_FOUND, _) => Ok(None),
+            (status, _) => Err(unexpected("GET", key, status)),
+        }
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<Option<ByteStream>, EstuaryError> {
+        match self.request(Method::GET, Some(key), &[], None).await? {
+            resp if resp.status().is_success() => Ok(Some(body_stream(key, resp))),
+            resp if resp.status() == StatusCode::NOT_FOUND => Ok(None),
+            resp => Err(unexpected("GET", key, resp.status())),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, EstuaryError> {
+        match self.send(Method::HEAD, Some(key), &[], None).await? {
+            (status, _) if status.is_success() => Ok(true),
+            (StatusCode::NOT_FOUND, _) => Ok(false),
+            (status, _) => Err(unexpected("HEAD", key, status)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), EstuaryError> {
+        match self.send(Method::DELETE, Some(key), &[], None).await? {
+            (status, _) if status.is_success() || status == StatusCode::NOT_FOUND => Ok(()),
+            (status, _) => Err(unexpected("DELETE", key, status)),
+        }
+    }
+
+    /// Pages through `List Blobs`.
+    ///
+    /// <https://learn.microsoft.com/en-us/rest/api/storageservices/list-blobs>
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, EstuaryError> {
+        let mut keys = vec![];
+        let mut marker: Option<String> = None;
+        loop {
+            let mut query = vec![
+                ("comp", "list"),
+                ("prefix", prefix),
+                ("restype", "container"),
+            ];
+            if let Some(ref marker) = marker {
+                query.insert(1, ("marker", marker));
+            }
+            let body = match self.send(Method::GET, None, &query, None).await? {
+                (status, body) if status.is_success() => body,
+                (status, _) => return Err(unexpected("GET", prefix, status)),
+            };
+            let body = String::from_utf8_lossy(&body);
+            keys.extend(xml_values(&body, "Name"));
+            marker = xml_values(&body, "NextMarker")
+                .pop()
+                .filter(|marker| !marker.is_empty());
+            if marker.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn azurite() -> AzureConfig {
+        AzureConfig {
+            account: "devstoreaccount1".to_string(),
+            access_key: base64::encode("shh"),
+            container: "crates".to_string(),
+            endpoint: Some("http://127.0.0.1:10000/devstoreaccount1/".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_urls() {
+        let config = azurite();
+        assert_eq!(
+            "/devstoreaccount1/devstoreaccount1/crates/my-crate/my-crate-0.1.0.crate",
+            config.resource(Some("my-crate/my-crate-0.1.0.crate"))
+        );
+        assert_eq!(
+            "http://127.0.0.1:10000/devstoreaccount1/crates?restype=container&prefix=my%2F",
+            config.url(None, &[("restype", "container"), ("prefix", "my/")])
+        );
+
+        let config = AzureConfig {
+            endpoint: None,
+            ..azurite()
+        };
+        assert_eq!(
+            "/devstoreaccount1/crates/a/a-1.0.0.crate",
+            config.resource(Some("a/a-1.0.0.crate"))
+        );
+        assert_eq!(
+            "https://devstoreaccount1.blob.core.windows.net/crates/a/a-1.0.0.crate",
+            config.url(Some("a/a-1.0.0.crate"), &[])
+        );
+    }
+
+    #[test]
+    fn test_string_to_sign() {
+        let headers = [
+            ("x-ms-blob-type", "BlockBlob".to_string()),
+            ("x-ms-date", "Sun, 11 Oct 2009 21:49:13 GMT".to_string()),
+            ("x-ms-version", API_VERSION.to_string()),
+        ];
+        assert_eq!(
+            "PUT\n\n\n5\n\napplication/octet-stream\n\n\n\n\n\n\n\
+            x-ms-blob-type:BlockBlob\n\
+            x-ms-date:Sun, 11 Oct 2009 21:49:13 GMT\n\
+            x-ms-version:2021-08-06\n\
+            /myaccount/crates/a/a-1.0.0.crate",
+            string_to_sign(
+                "PUT",
+                5,
+                "application/octet-stream",
+                &headers,
+                "/myaccount/crates/a/a-1.0.0.crate",
+                &[],
+            )
+        );
+        // Query params go at the end, sorted.
+        assert!(string_to_sign(
+            "GET",
+            0,
+            "",
+            &headers[1..],
+            "/myaccount/crates",
+            &[("restype", "container"), ("comp", "list"),]
+        )
+        .ends_with("/myaccount/crates\ncomp:list\nrestype:container"));
+    }
+
+    #[test]
+    fn test_http_date() {
+        let date = OffsetDateTime::from_unix_timestamp(1255297753).unwrap();
+        assert_eq!("Sun, 11 Oct 2009 21:49:13 GMT", http_date(date));
+    }
+}
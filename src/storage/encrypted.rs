@@ -0,0 +1,332 @@
+//! Encrypting crate files before they're stored, for when whatever keeps them
+//! (a shared disk, a bucket) shouldn't be able to read them.
+//!
+//! Files are encrypted with AES-256-GCM a chunk at a time (the "STREAM"
+//! construction), so storing one doesn't mean holding it in memory. Each
+//! chunk's nonce is a random prefix (one per file), the chunk's number, and
+//! whether it's the last one, so chunks can't be reordered, dropped, or cut
+//! off the end without it being noticed.
+//!
+//! <https://eprint.iacr.org/2015/189.pdf>
+
+use super::{ByteStream, CrateStore, TempFile, CHUNK_SIZE};
+use crate::errors::EstuaryError;
+use actix_web::web::{Bytes, BytesMut};
+use async_trait::async_trait;
+use futures_util::{ready, Stream, StreamExt};
+use rand::Rng;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM};
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+/// What encrypted files start with, so those stored before there was a key
+/// can still be told apart (and served as they are).
+const MAGIC: &[u8] = b"estuary\x01";
+
+const PREFIX_LEN: usize = 7;
+
+const TAG_LEN: usize = 16;
+
+/// Another store, with everything in it encrypted.
+pub struct EncryptedStore {
+    inner: Arc<dyn CrateStore>,
+    key: Arc<LessSafeKey>,
+}
+
+// Keeps the key out of the logs.
+impl fmt::Debug for EncryptedStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedStore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl EncryptedStore {
+    /// Wrap `inner`, encrypting with `key` (32 bytes, base64 encoded).
+    pub fn new(inner: Arc<dyn CrateStore>, key: &str) -> Result<EncryptedStore> {
+        let invalid = || {
+            EstuaryError::Config(
+                "The encryption key needs to be 32 bytes, base64 encoded.".to_string(),
+            )
+        };
+        let key = base64::decode(key.trim()).map_err(|_| invalid())?;
+        let key = UnboundKey::new(&AES_256_GCM, &key).map_err(|_| invalid())?;
+        Ok(EncryptedStore {
+            inner,
+            key: Arc::new(LessSafeKey::new(key)),
+        })
+    }
+}
+
+fn nonce(prefix: &[u8], counter: u32, last: bool) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[..PREFIX_LEN].copy_from_slice(prefix);
+    nonce[PREFIX_LEN..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Encrypt the `plain` file into `out`.
+fn encrypt(key: &LessSafeKey, plain: &Path, out: &mut impl Write) -> Result<()> {
+    let mut file = File::open(plain)?;
+    let len = file.metadata()?.len() as usize;
+    let chunks = len.div_ceil(CHUNK_SIZE).max(1);
+    let prefix = rand::thread_rng().gen::<[u8; PREFIX_LEN]>();
+    out.write_all(MAGIC)?;
+    out.write_all(&prefix)?;
+    for i in 0..chunks {
+        let mut chunk = vec![0; CHUNK_SIZE.min(len - i * CHUNK_SIZE)];
+        file.read_exact(&mut chunk)?;
+        let nonce = nonce(&prefix, i as u32, i == chunks - 1);
+        key.seal_in_place_append_tag(nonce, Aad::empty(), &mut chunk)
+            .map_err(|_| EstuaryError::Storage(format!("encrypting `{}`", plain.display())))?;
+        out.write_all(&chunk)?;
+    }
+    Ok(())
+}
+
+/// Decrypt what's stored under `name`, or hand it back as it is when it was
+/// stored before there was a key.
+fn decrypt(key: &LessSafeKey, name: &str, stored: Bytes) -> Result<Bytes> {
+    if !stored.starts_with(MAGIC) {
+        return Ok(stored);
+    }
+    let stored = &stored[MAGIC.len()..];
+    if stored.len() < PREFIX_LEN + TAG_LEN {
+        return Err(failed(name));
+    }
+    let (prefix, body) = stored.split_at(PREFIX_LEN);
+    let chunks = body.chunks(CHUNK_SIZE + TAG_LEN).collect::<Vec<_>>();
+    let mut plain = Vec::with_capacity(body.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+        let last = i == chunks.len() - 1;
+        plain.extend_from_slice(&open(key, name, prefix, i as u32, last, chunk)?);
+    }
+    Ok(plain.into())
+}
+
+/// Decrypt the `counter`th chunk of `name`.
+fn open(
+    key: &LessSafeKey,
+    name: &str,
+    prefix: &[u8],
+    counter: u32,
+    last: bool,
+    chunk: &[u8],
+) -> Result<Bytes> {
+    let mut chunk = chunk.to_vec();
+    let opened = key
+        .open_in_place(nonce(prefix, counter, last), Aad::empty(), &mut chunk)
+        .map_err(|_| failed(name))?;
+    Ok(Bytes::copy_from_slice(opened))
+}
+
+fn failed(name: &str) -> EstuaryError {
+    EstuaryError::Storage(format!("`{}` couldn't be decrypted", name))
+}
+
+/// What's stored under `name`, decrypted a chunk at a time as it arrives.
+///
+/// A chunk is only known to be the last once the stream's ended, so one is
+/// always held back until there's more after it.
+struct Decrypting {
+    stored: ByteStream,
+    key: Arc<LessSafeKey>,
+    name: String,
+    buf: BytesMut,
+    /// Once it's been read, and `None` in the meantime.
+    prefix: Option<Vec<u8>>,
+    /// When the file was stored before there was a key.
+    plain: bool,
+    counter: u32,
+    ended: bool,
+}
+
+impl Stream for Decrypting {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        loop {
+            if this.ended {
+                return Poll::Ready(None);
+            }
+            if this.plain && !this.buf.is_empty() {
+                return Poll::Ready(Some(Ok(this.buf.split().freeze())));
+            }
+            if let Some(ref prefix) = this.prefix {
+                if this.buf.len() > CHUNK_SIZE + TAG_LEN {
+                    let chunk = this.buf.split_to(CHUNK_SIZE + TAG_LEN);
+                    let opened = open(&this.key, &this.name, prefix, this.counter, false, &chunk);
+                    this.counter += 1;
+                    return Poll::Ready(Some(opened));
+                }
+            }
+            match ready!(this.stored.poll_next_unpin(cx)) {
+                Some(Ok(bytes)) => {
+                    this.buf.extend_from_slice(&bytes);
+                    if this.prefix.is_none() && !this.plain && this.buf.len() >= MAGIC.len() {
+                        if !this.buf.starts_with(MAGIC) {
+                            this.plain = true;
+                        } else if this.buf.len() >= MAGIC.len() + PREFIX_LEN {
+                            let header = this.buf.split_to(MAGIC.len() + PREFIX_LEN);
+                            this.prefix = Some(header[MAGIC.len()..].to_vec());
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    this.ended = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                None => {
+                    this.ended = true;
+                    let last = this.buf.split();
+                    return Poll::Ready(match this.prefix {
+                        Some(ref prefix) if last.len() >= TAG_LEN => Some(open(
+                            &this.key,
+                            &this.name,
+                            prefix,
+                            this.counter,
+                            true,
+                            &last,
+                        )),
+                        Some(_) => Some(Err(failed(&this.name))),
+                        None if last.starts_with(MAGIC) => Some(Err(failed(&this.name))),
+                        None if last.is_empty() => None,
+                        None => Some(Ok(last.freeze())),
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl CrateStore for EncryptedStore {
+    /// The encrypted copy is written next to `file`, then stored.
+    async fn put(&self, key: &str, file: &Path) -> Result<()> {
+        let mut encrypted = TempFile::create(file.parent().unwrap_or_else(|| Path::new(".")))?;
+        encrypt(&self.key, file, &mut encrypted)?;
+        encrypted.flush()?;
+        self.inner.put(key, encrypted.path()).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        match self.inner.get(key).await? {
+            Some(stored) => Ok(Some(decrypt(&self.key, key, stored)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<Option<ByteStream>> {
+        Ok(self.inner.get_stream(key).await?.map(|stored| {
+            Box::pin(Decrypting {
+                stored,
+                key: self.key.clone(),
+                name: key.to_string(),
+                buf: BytesMut::new(),
+                prefix: None,
+                plain: false,
+                counter: 0,
+                ended: false,
+            }) as ByteStream
+        }))
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.inner.delete(key).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        self.inner.list(prefix).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{Layout, LocalStore};
+    use crate::test_helpers;
+
+    #[actix_rt::test]
+    async fn test_round_trip() {
+        let data_root = test_helpers::get_data_root();
+        let local = Arc::new(LocalStore {
+            root: data_root.path().join("crates"),
+            layout: Layout::Flat,
+        });
+        let key = base64::encode([7; 32]);
+        let store = EncryptedStore::new(local.clone(), &key).unwrap();
+
+        for len in &[0, 5, CHUNK_SIZE, 2 * CHUNK_SIZE + 5] {
+            let content = (0..*len).map(|i| i as u8).collect::<Vec<_>>();
+            let mut file = TempFile::create(data_root.path()).unwrap();
+            file.write_all(&content).unwrap();
+            let name = format!("a/a-{}.0.0.crate", len);
+            store.put(&name, file.path()).await.unwrap();
+
+            let stored = local.get(&name).await.unwrap().unwrap();
+            assert!(stored.starts_with(MAGIC));
+            assert_ne!(content[..], stored[..]);
+            assert_eq!(
+                Some(Bytes::from(content.clone())),
+                store.get(&name).await.unwrap(),
+                "{} bytes",
+                len
+            );
+            let streamed = store.get_stream(&name).await.unwrap().unwrap();
+            let streamed = streamed.collect::<Vec<_>>().await;
+            assert_eq!(
+                content,
+                streamed
+                    .into_iter()
+                    .flat_map(|chunk| chunk.unwrap().to_vec())
+                    .collect::<Vec<_>>(),
+                "{} bytes, streamed",
+                len
+            );
+        }
+
+        // Someone else's key can't read them.
+        let other = EncryptedStore::new(local.clone(), &base64::encode([8; 32])).unwrap();
+        let err = other.get("a/a-5.0.0.crate").await.unwrap_err();
+        assert!(err.to_string().contains("couldn't be decrypted"));
+
+        // Nor can a file with its last chunk cut off.
+        let name = format!("a/a-{}.0.0.crate", 2 * CHUNK_SIZE + 5);
+        let stored = local.get(&name).await.unwrap().unwrap();
+        let truncated = stored.slice(..MAGIC.len() + PREFIX_LEN + 2 * (CHUNK_SIZE + TAG_LEN));
+        assert!(decrypt(&store.key, &name, truncated).is_err());
+
+        // Files from before there was a key are served as they are.
+        let mut file = TempFile::create(data_root.path()).unwrap();
+        file.write_all(b"plain").unwrap();
+        local.put("b/b-1.0.0.crate", file.path()).await.unwrap();
+        assert_eq!(
+            Some(Bytes::from_static(b"plain")),
+            store.get("b/b-1.0.0.crate").await.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bad_key() {
+        let local = Arc::new(LocalStore {
+            root: "crates".into(),
+            layout: Layout::Flat,
+        });
+        assert!(EncryptedStore::new(local.clone(), "not base64!").is_err());
+        assert!(EncryptedStore::new(local, &base64::encode([7; 16])).is_err());
+    }
+}
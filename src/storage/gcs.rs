@@ -0,0 +1,317 @@
+//! Keeping `.crate` files in a Google Cloud Storage bucket, through its JSON
+//! API.
+//!
+//! Requests are made as a service account, whose key file is traded for
+//! short-lived access tokens (the same file `GOOGLE_APPLICATION_CREDENTIALS`
+//! usually points at).
+//!
+//! <https://cloud.google.com/storage/docs/json_api>
+//! <https://developers.google.com/identity/protocols/oauth2/service-account#httprest>
+
+use super::{
+    body_stream, unexpected, uri_encode, ByteStream, CrateStore, FileBody, ResponseBody,
+    MAX_OBJECT_SIZE, TIMEOUT,
+};
+use crate::errors::EstuaryError;
+use actix_http::body::Body;
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use awc::http::{Method, StatusCode};
+use awc::ClientResponse;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use time::OffsetDateTime;
+
+const ENDPOINT: &str = "https://storage.googleapis.com";
+
+/// What the access tokens are good for.
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+/// How long (in seconds) before an access token expires to stop using it.
+const EXPIRY_MARGIN: i64 = 60;
+
+/// Which bucket, and which service account to use it as.
+#[derive(Clone, Debug)]
+pub struct GcsConfig {
+    pub bucket: String,
+    /// The service account's JSON key file.
+    pub credentials: PathBuf,
+}
+
+/// The parts of a service account key file we need.
+#[derive(Deserialize)]
+struct ServiceAccount {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: i64,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct AccessToken {
+    access_token: String,
+    expires_in: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectList {
+    #[serde(default)]
+    items: Vec<Object>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Object {
+    name: String,
+}
+
+pub struct GcsStore {
+    bucket: String,
+    client_email: String,
+    token_uri: String,
+    key: EncodingKey,
+    /// The current access token, and when it expires.
+    token: Mutex<Option<(String, i64)>>,
+}
+
+// Keeps the key out of the logs.
+impl fmt::Debug for GcsStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GcsStore")
+            .field("bucket", &self.bucket)
+            .field("client_email", &self.client_email)
+            .finish()
+    }
+}
+
+impl GcsStore {
+    /// Read the service account's key file.
+    pub fn new(config: GcsConfig) -> Result<GcsStore, EstuaryError> {
+        let invalid = |e: &dyn fmt::Display| {
+            EstuaryError::Config(format!(
+                "`{}` isn't a service account key: {}",
+                config.credentials.display(),
+                e
+            ))
+        };
+        let account: ServiceAccount = serde_json::from_slice(&std::fs::read(&config.credentials)?)
+            .map_err(|e| invalid(&e))?;
+        let key =
+            EncodingKey::from_rsa_pem(account.private_key.as_bytes()).map_err(|e| invalid(&e))?;
+        Ok(GcsStore {
+            bucket: config.bucket,
+            client_email: account.client_email,
+            token_uri: account.token_uri,
+            key,
+            token: Mutex::new(None),
+        })
+    }
+
+    fn object_url(&self, key: &str) -> String {
+        format!(
+            "{}/storage/v1/b/{}/o/{}",
+            ENDPOINT,
+            self.bucket,
+            uri_encode(key, true)
+        )
+    }
+
+    /// An access token, from the last one fetched if it's still good.
+    async fn access_token(&self) -> Result<String, EstuaryError> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        if let Some((ref token, expires_at)) = *self.token.lock().unwrap() {
+            if now < expires_at - EXPIRY_MARGIN {
+                return Ok(token.clone());
+            }
+        }
+
+        let error =
+            |e: &dyn fmt::Display| EstuaryError::Storage(format!("getting an access token: {}", e));
+        let claims = Claims {
+            iss: &self.client_email,
+            scope: SCOPE,
+            aud: &self.token_uri,
+            iat: now,
+            exp: now + 60 * 60,
+        };
+        let assertion = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.key)
+            .map_err(|e| error(&e))?;
+        let mut resp = awc::Client::default()
+            .post(&self.token_uri)
+            .timeout(TIMEOUT)
+            .send_form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", &assertion),
+            ])
+            .await
+            .map_err(|e| error(&e))?;
+        if !resp.status().is_success() {
+            return Err(error(&resp.status()));
+        }
+        let token: AccessToken = resp.json().await.map_err(|e| error(&e))?;
+        *self.token.lock().unwrap() = Some((token.access_token.clone(), now + token.expires_in));
+        Ok(token.access_token)
+    }
+
+    /// Send an authorized request (with the contents of `file`, if any),
+    /// handing back the status and body of the response, whatever the status.
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        file: Option<&Path>,
+    ) -> Result<(StatusCode, Bytes), EstuaryError> {
+        let mut resp = self.request(method.clone(), url, file).await?;
+        let body = resp
+            .body()
+            .limit(MAX_OBJECT_SIZE)
+            .await
+            .map_err(|e| EstuaryError::Storage(format!("{} {}: {}", method, url, e)))?;
+        Ok((resp.status(), body))
+    }
+
+    /// [send](Self::send), but with the body of the response left to be read.
+    async fn request(
+        &self,
+        method: Method,
+        url: &str,
+        file: Option<&Path>,
+    ) -> Result<ClientResponse<impl ResponseBody>, EstuaryError> {
+        let body = match file {
+            Some(file) => Body::from_message(FileBody::open(file)?),
+            None => Body::Empty,
+        };
+        let error =
+            |e: &dyn fmt::Display| EstuaryError::Storage(format!("{} {}: {}", method, url, e));
+        let token = self.access_token().await?;
+        awc::Client::default()
+            .request(method.clone(), url)
+            .timeout(TIMEOUT)
+            .bearer_auth(token)
+            .content_type("application/octet-stream")
+            .send_body(body)
+            .await
+            .map_err(|e| error(&e))
+    }
+}
+
+#[async_trait(?Send)]
+impl CrateStore for GcsStore {
+    async fn put(&self, key: &str, file: &Path) -> Result<(), EstuaryError> {
+        let url = format!(
+            "{}/upload/storage/v1/b/{}/o?uploadType=media&name={}",
+            ENDPOINT,
+            self.bucket,
+            uri_encode(key, true)
+        );
+        match self.send(Method::POST, &url, Some(file)).await? {
+            (status, _) if status.is_success() => Ok(()),
+            (status, _) => Err(unexpected("POST", key, status)),
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>, EstuaryError> {
+        let url = format!("{}?alt=media", self.object_url(key));
+        match self.send(Method::GET, &url, None).await? {
+            (status, body) if status.is_success() => Ok(Some(body)),
+            (StatusCode::NOT_FOUND, _) => Ok(None),
+            (status, _) => Err(unexpected("GET", key, status)),
+        }
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<Option<ByteStream>, EstuaryError> {
+        let url = format!("{}?alt=media", self.object_url(key));
+        match self.request(Method::GET, &url, None).await? {
+            resp if resp.status().is_success() => Ok(Some(body_stream(key, resp))),
+            resp if resp.status() == StatusCode::NOT_FOUND => Ok(None),
+            resp => Err(unexpected("GET", key, resp.status())),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, EstuaryError> {
+        match self.send(Method::GET, &self.object_url(key), None).await? {
+            (status, _) if status.is_success() => Ok(true),
+            (StatusCode::NOT_FOUND, _) => Ok(false),
+            (status, _) => Err(unexpected("GET", key, status)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), EstuaryError> {
+        match self
+            .send(Method::DELETE, &self.object_url(key), None)
+            .await?
+        {
+            (status, _) if status.is_success() || status == StatusCode::NOT_FOUND => Ok(()),
+            (status, _) => Err(unexpected("DELETE", key, status)),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<String>, EstuaryError> {
+        let mut keys = vec![];
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut url = format!(
+                "{}/storage/v1/b/{}/o?fields=items(name),nextPageToken&prefix={}",
+                ENDPOINT,
+                self.bucket,
+                uri_encode(prefix, true)
+            );
+            if let Some(ref token) = page_token {
+                url.push_str(&format!("&pageToken={}", uri_encode(token, true)));
+            }
+            let body = match self.send(Method::GET, &url, None).await? {
+                (status, body) if status.is_success() => body,
+                (status, _) => return Err(unexpected("GET", prefix, status)),
+            };
+            let page: ObjectList = serde_json::from_slice(&body)?;
+            keys.extend(page.items.into_iter().map(|object| object.name));
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bad_credentials() {
+        let data_root = crate::test_helpers::get_data_root();
+        let credentials = data_root.path().join("key.json");
+        std::fs::write(&credentials, r#"{"type": "authorized_user"}"#).unwrap();
+        let err = GcsStore::new(GcsConfig {
+            bucket: "crates".to_string(),
+            credentials,
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("isn't a service account key"));
+    }
+
+    #[test]
+    fn test_object_list() {
+        let page: ObjectList = serde_json::from_str(
+            r#"{"items": [{"name": "my-crate/my-crate-0.1.0.crate"}], "nextPageToken": "abc"}"#,
+        )
+        .unwrap();
+        assert_eq!("my-crate/my-crate-0.1.0.crate", page.items[0].name);
+        assert_eq!(Some("abc"), page.next_page_token.as_deref());
+        // An empty bucket has no items at all.
+        let page: ObjectList = serde_json::from_str("{}").unwrap();
+        assert!(page.items.is_empty());
+    }
+}
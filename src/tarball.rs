@@ -0,0 +1,183 @@
+//! Reading the files in a crate file (a gzipped tarball), so they can be
+//! looked through in the web UI, and reading and writing the archives
+//! `estuary export` makes, both with the `tar` crate.
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Read, Write};
+
+/// A file in a crate file.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    /// Relative to the crate's root, ex: `src/lib.rs`.
+    pub path: String,
+    pub size: u64,
+}
+
+/// Every file in `crate_file`, in the order they were packaged.
+pub fn list(crate_file: &[u8]) -> io::Result<Vec<Entry>> {
+    let mut entries = vec![];
+    each_file(crate_file, |path, size, _| {
+        entries.push(Entry {
+            path: in_crate(path),
+            size,
+        });
+        Ok(true)
+    })?;
+    Ok(entries)
+}
+
+/// The contents of the file at `path` in `crate_file`, if there is one, as
+/// long as it's no bigger than `limit`.
+///
+/// Bigger files give an `Err`, rather than being read into memory.
+pub fn read(crate_file: impl Read, path: &str, limit: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut found = None;
+    each_file(crate_file, |entry_path, size, contents| {
+        if in_crate(entry_path) != path {
+            return Ok(true);
+        }
+        if size > limit {
+            return Err(io::Error::other(format!(
+                "`{}` is {} bytes, which is more than {}",
+                path, size, limit
+            )));
+        }
+        let mut buf = Vec::with_capacity(size as usize);
+        contents.read_to_end(&mut buf)?;
+        found = Some(buf);
+        Ok(false)
+    })?;
+    Ok(found)
+}
+
+/// A path in a crate file, relative to the crate's root: everything's under
+/// `{name}-{version}/`.
+fn in_crate(path: String) -> String {
+    match path.split_once('/') {
+        Some((_, path)) => path.to_string(),
+        None => path,
+    }
+}
+
+/// Call `f` with the path, size, and contents of each file in `tarball`,
+/// until it hands back `false`.
+///
+/// Directories, links, and the like are skipped.
+pub fn each_file<R, F>(tarball: R, mut f: F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(String, u64, &mut dyn Read) -> io::Result<bool>,
+{
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        // Long paths come from GNU or pax headers, when there are any.
+        let path = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        let size = entry.size();
+        if !f(path, size, &mut entry)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// A gzipped tarball being written, a file at a time.
+pub type Builder<W> = tar::Builder<GzEncoder<W>>;
+
+/// Start writing a gzipped tarball to `out`.
+pub fn builder<W: Write>(out: W) -> Builder<W> {
+    tar::Builder::new(GzEncoder::new(out, Compression::default()))
+}
+
+/// Add a file at `path`, with `contents`.
+pub fn append<W: Write>(tarball: &mut Builder<W>, path: &str, contents: &[u8]) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    tarball.append_data(&mut header, path, contents)
+}
+
+/// Write the end of `tarball`, handing back what it was written to.
+pub fn finish<W: Write>(tarball: Builder<W>) -> io::Result<W> {
+    tarball.into_inner()?.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_list_and_read() {
+        let crate_file = test_helpers::crate_file(test_helpers::MY_CRATE_0_1_0);
+        assert_eq!(
+            vec!["Cargo.toml", "Cargo.toml.orig", "src/lib.rs"],
+            list(&crate_file)
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect::<Vec<_>>()
+        );
+        let lib = read(&crate_file[..], "src/lib.rs", 1024).unwrap().unwrap();
+        assert_eq!(95, lib.len());
+        assert!(String::from_utf8(lib).unwrap().contains("fn "));
+        assert_eq!(None, read(&crate_file[..], "src/main.rs", 1024).unwrap());
+        assert!(read(&crate_file[..], "src/lib.rs", 10).is_err());
+    }
+
+    #[test]
+    fn test_builder() {
+        let long = format!("crates/{}/file.crate", "a".repeat(100));
+        let mut tarball = builder(vec![]);
+        append(&mut tarball, "index/config.json", b"{}").unwrap();
+        append(&mut tarball, &long, b"abc").unwrap();
+        let tarball = finish(tarball).unwrap();
+        let empty = finish(builder(vec![])).unwrap();
+
+        let mut files = vec![];
+        each_file(&tarball[..], |path, size, contents| {
+            let mut buf = vec![];
+            contents.read_to_end(&mut buf)?;
+            files.push((path, size, buf));
+            Ok(true)
+        })
+        .unwrap();
+        assert_eq!(
+            vec![
+                ("index/config.json".to_string(), 2, b"{}".to_vec()),
+                (long, 3, b"abc".to_vec()),
+            ],
+            files
+        );
+        each_file(&empty[..], |_, _, _| panic!("expected no files")).unwrap();
+    }
+
+    #[test]
+    fn test_pax_path() {
+        let long = format!("my-crate-0.1.0/{}/lib.rs", "a".repeat(100));
+        let mut tarball = builder(vec![]);
+        tarball
+            .append_pax_extensions([("path", long.as_bytes())])
+            .unwrap();
+        let mut header = tar::Header::new_ustar();
+        header.set_size(3);
+        header.set_mode(0o644);
+        tarball
+            .append_data(&mut header, "my-crate-0.1.0/short.rs", &b"abc"[..])
+            .unwrap();
+        let tarball = finish(tarball).unwrap();
+
+        let mut paths = vec![];
+        each_file(&tarball[..], |path, size, _| {
+            paths.push((path, size));
+            Ok(true)
+        })
+        .unwrap();
+        assert_eq!(vec![(long, 3)], paths);
+    }
+}
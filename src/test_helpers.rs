@@ -1,9 +1,14 @@
-use crate::package_index::{Config, PackageIndex};
-use crate::Settings;
+use crate::auth::Scope;
+use crate::package_index::{Config, IndexProtocol, PackageIndex};
+use crate::search_cache::SearchCache;
+use crate::storage::{Layout, LocalStore};
+use crate::{database, tarball, Settings};
 use actix_web::web;
-use std::path::{Path, PathBuf};
-use std::sync::Mutex;
-use tempdir::TempDir;
+use std::convert::TryInto;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::{Builder, TempDir};
 
 /// This is the request body sent to the publish endpoint from an empty bin crate.
 pub const MY_CRATE_0_1_0: &[u8] = include_bytes!(concat!(
@@ -11,24 +16,116 @@ pub const MY_CRATE_0_1_0: &[u8] = include_bytes!(concat!(
     "/test_data/publish-my-crate-body"
 ));
 
+/// The crate file sent in a publish request `body`, after the metadata.
+pub fn crate_file(body: &[u8]) -> Vec<u8> {
+    let len = |at: usize| u32::from_le_bytes(body[at..at + 4].try_into().unwrap()) as usize;
+    let at = 4 + len(0);
+    body[at + 4..at + 4 + len(at)].to_vec()
+}
+
+/// A publish request `body` with its metadata changed by `f`.
+pub fn with_metadata(body: &[u8], f: impl FnOnce(&mut serde_json::Value)) -> Vec<u8> {
+    let len = u32::from_le_bytes(body[..4].try_into().unwrap()) as usize;
+    let mut metadata = serde_json::from_slice(&body[4..4 + len]).unwrap();
+    f(&mut metadata);
+    let metadata = serde_json::to_vec(&metadata).unwrap();
+    let mut out = (metadata.len() as u32).to_le_bytes().to_vec();
+    out.extend(metadata);
+    out.extend(&body[4 + len..]);
+    out
+}
+
+/// A publish request body with `metadata`, and a crate file with `manifest`
+/// as its `Cargo.toml`.
+pub fn publish_body(metadata: serde_json::Value, manifest: &str) -> Vec<u8> {
+    let mut tarball = tarball::builder(vec![]);
+    tarball::append(
+        &mut tarball,
+        &format!("{}-{}/Cargo.toml", metadata["name"], metadata["vers"]).replace('"', ""),
+        manifest.as_bytes(),
+    )
+    .unwrap();
+    let crate_file = tarball::finish(tarball).unwrap();
+    let metadata = serde_json::to_vec(&metadata).unwrap();
+    let mut out = (metadata.len() as u32).to_le_bytes().to_vec();
+    out.extend(metadata);
+    out.extend(&(crate_file.len() as u32).to_le_bytes());
+    out.extend(crate_file);
+    out
+}
+
 pub fn get_data_root() -> TempDir {
-    TempDir::new("estuary_test").unwrap()
+    Builder::new().prefix("estuary_test").tempdir().unwrap()
 }
 
 pub fn get_test_package_index(data_dir: &Path) -> web::Data<Mutex<PackageIndex>> {
     let config = Config {
         api: String::new(),
         dl: String::new(),
+        auth_required: false,
     };
     web::Data::new(Mutex::new(PackageIndex::init(data_dir, &config).unwrap()))
 }
 
 pub fn get_test_settings(data_dir: &Path) -> web::Data<Settings> {
+    let crate_dir = data_dir.join("crates");
+    let database_url = database::DatabaseUrl::Sqlite(data_dir.join("estuary.db"));
     let settings = Settings {
-        crate_dir: data_dir.join("crates").to_path_buf(),
+        base_url: String::from("http://localhost:7878"),
+        index_url: String::from("http://localhost:7878/git/index"),
+        registry_name: String::from("estuary"),
+        branding: Default::default(),
+        crate_store: Arc::new(LocalStore {
+            root: crate_dir.clone(),
+            layout: Layout::Flat,
+        }),
+        crate_dir,
+        verify_downloads: false,
+        max_crate_size: None,
+        publish_policy: Default::default(),
+        crate_quota: None,
+        registry_quota: None,
         index_dir: data_dir.join("index").to_path_buf(),
-        git_binary: PathBuf::from("git"),
-        publish_key: None,
+        db: database::pool(
+            &database_url,
+            4,
+            Duration::from_secs(5),
+            database::Pragmas::default(),
+        )
+        .unwrap(),
+        database_url,
+        auth_required: false,
+        private: false,
+        web_tokens: false,
+        signup: false,
+        basic_auth: vec![],
+        oidc: None,
+        ldap: None,
+        index_protocol: IndexProtocol::Both,
+        rate_limiter: Default::default(),
+        downloads: Default::default(),
+        // Tests change the database underneath search, so nothing's kept.
+        search_cache: Arc::new(SearchCache::with_max_age(Duration::ZERO)),
+        refs_cache: Default::default(),
+        verify_scan: Default::default(),
+        jwks_cache: Default::default(),
+        fallback_author: Default::default(),
     };
+    database::init(&settings.get_db().unwrap()).unwrap();
     web::Data::new(settings)
 }
+
+/// Issue a token (with every scope) directly in the database.
+pub fn add_token(settings: &Settings, name: &str, token: &str) {
+    database::set_token(&settings.get_db().unwrap(), name, token, Scope::ALL).unwrap();
+}
+
+/// Log in `login` (as if through single sign-on), giving the session id to
+/// send back in the session cookie.
+pub fn add_session(settings: &Settings, login: &str) -> String {
+    let conn = settings.get_db().unwrap();
+    let user = database::find_or_create_oidc_user(&conn, &format!("sub-{}", login), login).unwrap();
+    let session = crate::auth::generate_secret();
+    database::create_session(&conn, &session, user.id).unwrap();
+    session
+}
@@ -0,0 +1,124 @@
+//! Serving https directly, without a reverse proxy in front of us.
+//!
+//! The certificate and key are read from PEM files at startup, and read again
+//! whenever the process receives a `SIGHUP`. This means certificates can be
+//! renewed (ex: by certbot) without restarting the server.
+
+use crate::errors::EstuaryError;
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+/// Read a certificate chain and private key from a pair of PEM files.
+///
+/// Both PKCS#8 and (legacy) RSA private keys are accepted.
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey> {
+    let tls_err =
+        |msg: &str, path: &Path| EstuaryError::TLS(format!("{} `{}`", msg, path.display()));
+
+    let certs = pemfile::certs(&mut BufReader::new(File::open(cert_path)?))
+        .map_err(|_| tls_err("Failed to parse certificates in", cert_path))?;
+    if certs.is_empty() {
+        return Err(tls_err("No certificates found in", cert_path));
+    }
+
+    let mut keys = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))
+        .map_err(|_| tls_err("Failed to parse private key in", key_path))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut BufReader::new(File::open(key_path)?))
+            .map_err(|_| tls_err("Failed to parse private key in", key_path))?;
+    }
+    let key = keys
+        .first()
+        .ok_or_else(|| tls_err("No private key found in", key_path))?;
+    let signer = sign::any_supported_type(key)
+        .map_err(|_| tls_err("Unsupported private key type in", key_path))?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(signer)))
+}
+
+/// Hands rustls whichever certificate was most recently loaded from disk.
+pub struct ReloadableCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<CertifiedKey>,
+}
+
+impl ReloadableCert {
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: RwLock::new(load_certified_key(cert_path, key_path)?),
+        })
+    }
+
+    /// Re-read the certificate and key from disk.
+    ///
+    /// If anything goes wrong, the previously loaded certificate stays in use.
+    pub fn reload(&self) -> Result<()> {
+        let fresh = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap() = fresh;
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+/// Build the rustls config for the server.
+pub fn server_config(cert: Arc<ReloadableCert>) -> ServerConfig {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = cert;
+    config
+}
+
+/// Reload the certificate each time we get a `SIGHUP`.
+#[cfg(not(tarpaulin_include))]
+pub fn reload_on_sighup(cert: Arc<ReloadableCert>) -> std::io::Result<()> {
+    let mut signals = signal_hook::iterator::Signals::new([signal_hook::consts::SIGHUP])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            match cert.reload() {
+                Ok(_) => log::info!("Reloaded TLS certificate."),
+                Err(e) => log::error!("Failed to reload TLS certificate: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_data(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("test_data")
+            .join(name)
+    }
+
+    #[test]
+    fn test_load_and_reload() {
+        let cert =
+            ReloadableCert::load(&test_data("test-cert.pem"), &test_data("test-key.pem")).unwrap();
+        assert_eq!(1, cert.current.read().unwrap().cert.len());
+        cert.reload().unwrap();
+    }
+
+    #[test]
+    fn test_load_key_as_cert_is_err() {
+        assert!(
+            ReloadableCert::load(&test_data("test-key.pem"), &test_data("test-key.pem")).is_err()
+        );
+    }
+}
@@ -0,0 +1,468 @@
+//! Checking the index, the database, and the stored crate files against each
+//! other, for `estuary verify` (and admins, over http).
+//!
+//! Every version in the index should be recorded in the database, with the
+//! same checksum and yank, and have its crate file stored intact. Versions
+//! published (or yanked) before the database kept a record of them show up as
+//! missing from it.
+
+use crate::database::{self, Connection, RecordedVersion};
+use crate::errors::EstuaryError;
+use crate::package_index::{PackageIndex, PackageVersion};
+use crate::storage::{self, CrateStore};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+type Result<T> = std::result::Result<T, EstuaryError>;
+
+/// What's wrong with a version.
+#[derive(Debug, PartialEq)]
+pub enum Kind {
+    /// It's in the index, but the database has no record of it.
+    NotInDatabase,
+    /// The database has a record of it, but it isn't in the index.
+    NotInIndex,
+    /// The database has its crate file stored under another checksum.
+    ChecksumMismatch { index: String, database: String },
+    /// There's no crate file stored for it.
+    MissingFile { key: String },
+    /// Its crate file doesn't match the index's checksum.
+    CorruptFile { key: String, actual: String },
+    /// Its crate file couldn't be read from the store.
+    UnreadableFile { key: String, error: String },
+    /// It's yanked in the index but not the database, or the other way
+    /// around.
+    YankMismatch { index: bool },
+}
+
+impl Kind {
+    /// A name for the kind of problem, for machines.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::NotInDatabase => "not_in_database",
+            Kind::NotInIndex => "not_in_index",
+            Kind::ChecksumMismatch { .. } => "checksum_mismatch",
+            Kind::MissingFile { .. } => "missing_file",
+            Kind::CorruptFile { .. } => "corrupt_file",
+            Kind::UnreadableFile { .. } => "unreadable_file",
+            Kind::YankMismatch { .. } => "yank_mismatch",
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::NotInDatabase => write!(f, "is in the index, but not the database"),
+            Kind::NotInIndex => write!(f, "is in the database, but not the index"),
+            Kind::ChecksumMismatch { index, database } => write!(
+                f,
+                "has the checksum `{}` in the index, but `{}` in the database",
+                index, database
+            ),
+            Kind::MissingFile { key } => write!(f, "has no crate file (`{}`)", key),
+            Kind::CorruptFile { key, actual } => {
+                write!(
+                    f,
+                    "has a crate file (`{}`) with the SHA-256 `{}`",
+                    key, actual
+                )
+            }
+            Kind::UnreadableFile { key, error } => {
+                write!(
+                    f,
+                    "has a crate file (`{}`) that couldn't be read: {}",
+                    key, error
+                )
+            }
+            Kind::YankMismatch { index: true } => {
+                write!(f, "is yanked in the index, but not the database")
+            }
+            Kind::YankMismatch { index: false } => {
+                write!(f, "is yanked in the database, but not the index")
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub crate_name: String,
+    pub version: String,
+    pub kind: Kind,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` {} {}", self.crate_name, self.version, self.kind)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Report {
+    /// How many versions were checked, from wherever they were found.
+    pub versions: usize,
+    pub problems: Vec<Problem>,
+}
+
+/// The check admins run over http. It takes too long to answer the request
+/// that asks for it, so it runs in the background, and its report is kept
+/// for them to fetch.
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Whether one is underway.
+    pub running: bool,
+    /// How the last one to finish went.
+    pub last: Option<std::result::Result<Report, String>>,
+}
+
+/// Every version of every crate in the index, by crate.
+pub fn index_versions(index: &PackageIndex) -> Result<Vec<PackageVersion>> {
+    let mut names = index.list_crates()?;
+    names.sort();
+    let mut versions = vec![];
+    for name in names {
+        versions.extend(index.get_package_versions(&name)?);
+    }
+    Ok(versions)
+}
+
+/// Copy the yanks in the index the database has no record of into it, if
+/// that has yet to be done, handing back how many there were.
+///
+/// Versions yanked before the database kept track of them are only marked
+/// as yanked in the index. Until they're backfilled, the database would
+/// count them as available (and an index rebuilt from it would unyank them).
+pub fn backfill_yanks(index: &PackageIndex, conn: &Connection) -> Result<usize> {
+    if !database::has_backfill(conn, database::BACKFILL_YANKS)? {
+        return Ok(0);
+    }
+    let recorded = database::list_versions(conn)?
+        .into_iter()
+        .filter(|version| version.yanked_at.is_none())
+        .map(|version| (version.crate_name.to_lowercase(), version.version))
+        .collect::<HashSet<_>>();
+    let mut backfilled = 0;
+    for pkg in index_versions(index)? {
+        let vers = pkg.vers.to_string();
+        if pkg.yanked && recorded.contains(&(pkg.name.to_lowercase(), vers.clone())) {
+            database::set_yanked(conn, &pkg.name, &vers, true)?;
+            backfilled += 1;
+        }
+    }
+    database::finish_backfill(conn, database::BACKFILL_YANKS)?;
+    Ok(backfilled)
+}
+
+/// Check the versions in the index against those `recorded` in the database,
+/// and each one's crate file in the `store`.
+///
+/// Files are looked for where downloads would find them, so this reads every
+/// one of them (a chunk at a time). One the store can't read is reported
+/// along with the rest.
+pub async fn check(
+    index: &[PackageVersion],
+    recorded: &[RecordedVersion],
+    store: &dyn CrateStore,
+) -> Report {
+    let by_version = recorded
+        .iter()
+        .map(|version| {
+            (
+                (version.crate_name.to_lowercase(), version.version.clone()),
+                version,
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    let mut report = Report::default();
+    let mut seen = HashSet::new();
+
+    for pkg in index {
+        let version = pkg.vers.to_string();
+        let problem = |kind| Problem {
+            crate_name: pkg.name.clone(),
+            version: version.clone(),
+            kind,
+        };
+        let id = (pkg.name.to_lowercase(), version.clone());
+        let digest = match by_version.get(&id) {
+            None => {
+                report.problems.push(problem(Kind::NotInDatabase));
+                None
+            }
+            Some(recorded) => {
+                if pkg.yanked != recorded.yanked_at.is_some() {
+                    report
+                        .problems
+                        .push(problem(Kind::YankMismatch { index: pkg.yanked }));
+                }
+                recorded.digest.clone()
+            }
+        };
+        seen.insert(id);
+
+        let mismatched = match digest {
+            Some(ref digest) if digest != &pkg.cksum => {
+                report.problems.push(problem(Kind::ChecksumMismatch {
+                    index: pkg.cksum.clone(),
+                    database: digest.clone(),
+                }));
+                true
+            }
+            _ => false,
+        };
+        let key = match digest {
+            Some(ref digest) => storage::get_blob_key(digest),
+            None => storage::get_crate_file_key(&pkg.name, &pkg.vers),
+        };
+        match storage::sha256_stored(store, &key).await {
+            Ok(None) => report.problems.push(problem(Kind::MissingFile { key })),
+            Ok(Some(actual)) => {
+                // A file stored under another checksum isn't corrupt for
+                // not matching this one.
+                if actual != pkg.cksum && !mismatched {
+                    report
+                        .problems
+                        .push(problem(Kind::CorruptFile { key, actual }));
+                }
+            }
+            Err(e) => report.problems.push(problem(Kind::UnreadableFile {
+                key,
+                error: e.to_string(),
+            })),
+        }
+    }
+    report.versions = index.len();
+
+    for version in recorded {
+        if !seen.contains(&(version.crate_name.to_lowercase(), version.version.clone())) {
+            report.versions += 1;
+            report.problems.push(Problem {
+                crate_name: version.crate_name.clone(),
+                version: version.version.clone(),
+                kind: Kind::NotInIndex,
+            });
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_index::Author;
+    use crate::storage::{Layout, LocalStore, TempFile};
+    use crate::test_helpers;
+    use std::io::Write;
+
+    fn pkg(name: &str, vers: &str, cksum: &str) -> PackageVersion {
+        PackageVersion {
+            name: name.to_string(),
+            vers: vers.parse().unwrap(),
+            deps: vec![],
+            cksum: cksum.to_string(),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+        }
+    }
+
+    fn recorded(name: &str, version: &str, digest: Option<&str>) -> RecordedVersion {
+        RecordedVersion {
+            crate_name: name.to_string(),
+            version: version.to_string(),
+            digest: digest.map(str::to_string),
+            yanked_at: None,
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_check() {
+        let data_root = test_helpers::get_data_root();
+        let store = LocalStore {
+            root: data_root.path().join("crates"),
+            layout: Layout::Flat,
+        };
+        let good = storage::sha256(b"good");
+        let old = storage::sha256(b"old");
+        let gone = storage::sha256(b"gone");
+        for (key, content) in &[
+            (storage::get_blob_key(&good), "good"),
+            ("old-crate/old-crate-0.1.0.crate".to_string(), "old"),
+            ("bad-crate/bad-crate-0.1.0.crate".to_string(), "tampered"),
+        ] {
+            let mut file = TempFile::create(data_root.path()).unwrap();
+            file.write_all(content.as_bytes()).unwrap();
+            store.put(key, file.path()).await.unwrap();
+        }
+
+        let mut index = vec![
+            pkg("My-Crate", "0.1.0", &good),
+            pkg("my-crate", "0.2.0", &gone),
+            pkg("other-crate", "0.1.0", &good),
+            pkg("old-crate", "0.1.0", &old),
+            pkg("bad-crate", "0.1.0", &good),
+        ];
+        index[0].yanked = true;
+        let recorded = vec![
+            recorded("my-crate", "0.1.0", Some(&good)),
+            recorded("my-crate", "0.2.0", Some(&gone)),
+            recorded("my-crate", "0.3.0", Some(&good)),
+            recorded("other-crate", "0.1.0", Some(&old)),
+        ];
+        let report = check(&index, &recorded, &store).await;
+        let problem = |name: &str, version: &str, kind| Problem {
+            crate_name: name.to_string(),
+            version: version.to_string(),
+            kind,
+        };
+        assert_eq!(6, report.versions);
+        assert_eq!(
+            vec![
+                problem("My-Crate", "0.1.0", Kind::YankMismatch { index: true }),
+                problem(
+                    "my-crate",
+                    "0.2.0",
+                    Kind::MissingFile {
+                        key: storage::get_blob_key(&gone)
+                    }
+                ),
+                problem(
+                    "other-crate",
+                    "0.1.0",
+                    Kind::ChecksumMismatch {
+                        index: good.clone(),
+                        database: old.clone(),
+                    }
+                ),
+                problem(
+                    "other-crate",
+                    "0.1.0",
+                    Kind::MissingFile {
+                        key: storage::get_blob_key(&old)
+                    }
+                ),
+                problem("old-crate", "0.1.0", Kind::NotInDatabase),
+                problem("bad-crate", "0.1.0", Kind::NotInDatabase),
+                problem(
+                    "bad-crate",
+                    "0.1.0",
+                    Kind::CorruptFile {
+                        key: "bad-crate/bad-crate-0.1.0.crate".to_string(),
+                        actual: storage::sha256(b"tampered"),
+                    }
+                ),
+                problem("my-crate", "0.3.0", Kind::NotInIndex),
+            ],
+            report.problems
+        );
+        assert_eq!(
+            "`my-crate` 0.3.0 is in the database, but not the index",
+            report.problems[7].to_string()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_check_unreadable() {
+        let data_root = test_helpers::get_data_root();
+        let store = LocalStore {
+            root: data_root.path().join("crates"),
+            layout: Layout::Flat,
+        };
+        let good = storage::sha256(b"good");
+        let mut file = TempFile::create(data_root.path()).unwrap();
+        file.write_all(b"good").unwrap();
+        store
+            .put(&storage::get_blob_key(&good), file.path())
+            .await
+            .unwrap();
+        // The store can't read this one, which is one more problem rather
+        // than the end of the check.
+        let key = "stuck-crate/stuck-crate-0.1.0.crate";
+        std::fs::create_dir_all(store.root.join(key)).unwrap();
+
+        let index = vec![
+            pkg("stuck-crate", "0.1.0", &good),
+            pkg("other-crate", "0.1.0", &good),
+        ];
+        let recorded = vec![
+            recorded("stuck-crate", "0.1.0", None),
+            recorded("other-crate", "0.1.0", Some(&good)),
+        ];
+        let report = check(&index, &recorded, &store).await;
+        assert_eq!(2, report.versions);
+        match &report.problems[..] {
+            [Problem {
+                crate_name,
+                kind:
+                    Kind::UnreadableFile {
+                        key: unreadable, ..
+                    },
+                ..
+            }] => {
+                assert_eq!("stuck-crate", crate_name);
+                assert_eq!(key, unreadable);
+            }
+            other => panic!("expected an unreadable file, got {:?}", other),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_backfill_yanks() {
+        let data_root = test_helpers::get_data_root();
+        let settings = test_helpers::get_test_settings(data_root.path());
+        let conn = settings.get_db().unwrap();
+        test_helpers::get_test_package_index(&settings.index_dir);
+        let index = PackageIndex::open(&settings.index_dir).unwrap();
+        for (name, vers) in &[
+            ("My-Crate", "0.1.0"),
+            ("My-Crate", "0.2.0"),
+            ("other", "0.1.0"),
+        ] {
+            index
+                .publish(&pkg(name, vers, "abc"), &Author::default())
+                .unwrap();
+            database::record_publish(&conn, name, vers, None, None).unwrap();
+        }
+        for (name, vers) in &[("my-crate", "0.1.0"), ("other", "0.1.0")] {
+            index
+                .set_yanked(name, &vers.parse().unwrap(), true, &Author::default())
+                .unwrap();
+        }
+        // Only the first was yanked after the database kept track of yanks.
+        database::set_yanked(&conn, "my-crate", "0.1.0", true).unwrap();
+        let yanked_at = |conn: &Connection| {
+            database::list_versions(conn)
+                .unwrap()
+                .into_iter()
+                .map(|version| version.yanked_at.is_some())
+                .collect::<Vec<_>>()
+        };
+
+        // Databases that started out keeping track of yanks have nothing to
+        // backfill.
+        assert_eq!(0, backfill_yanks(&index, &conn).unwrap());
+        assert_eq!(vec![true, false, false], yanked_at(&conn));
+
+        conn.execute(
+            "INSERT INTO backfills (name) VALUES (?1)",
+            database::params![database::BACKFILL_YANKS],
+        )
+        .unwrap();
+        assert_eq!(1, backfill_yanks(&index, &conn).unwrap());
+        assert_eq!(vec![true, false, true], yanked_at(&conn));
+        let report = check(
+            &index_versions(&index).unwrap(),
+            &database::list_versions(&conn).unwrap(),
+            settings.crate_store.as_ref(),
+        )
+        .await;
+        assert!(report
+            .problems
+            .iter()
+            .all(|problem| problem.kind != Kind::YankMismatch { index: true }));
+        // Which is only done the once.
+        assert!(!database::has_backfill(&conn, database::BACKFILL_YANKS).unwrap());
+        assert_eq!(0, backfill_yanks(&index, &conn).unwrap());
+    }
+}